@@ -0,0 +1,164 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+#[allow(dead_code)]
+mod performance_utils {
+    include!("../src/performance_utils.rs");
+}
+
+#[allow(dead_code)]
+mod processing {
+    include!("../src/processing.rs");
+}
+
+#[allow(dead_code)]
+mod generator {
+    include!("../src/generator.rs");
+}
+
+#[allow(dead_code)]
+mod generator_noise {
+    include!("../src/generator_noise.rs");
+}
+
+#[allow(dead_code)]
+mod generator_output {
+    include!("../src/generator_output.rs");
+}
+
+#[allow(dead_code)]
+mod generator_schema {
+    include!("../src/generator_schema.rs");
+}
+
+#[allow(dead_code)]
+mod aggregators {
+    include!("../src/aggregators.rs");
+}
+
+#[allow(dead_code)]
+mod column_stats {
+    include!("../src/column_stats.rs");
+}
+
+#[cfg(feature = "columnar")]
+#[allow(dead_code)]
+mod columnar {
+    include!("../src/columnar.rs");
+}
+
+#[allow(dead_code)]
+mod runtimes {
+    include!("../src/runtimes.rs");
+}
+
+use generator::GenerateOptions;
+use generator_schema::Schema;
+use runtimes::AsyncRuntime;
+
+/// Rows in the benchmark input. Large enough for the parallel strategy to
+/// split work across cores, small enough to keep `cargo bench` quick.
+const BENCH_ROWS: u32 = 20_000;
+
+/// Groups in the top-K benchmark, as many as a file of unique customers.
+const TOP_GROUPS: usize = 200_000;
+
+/// Generates the input with a fixed seed so every run measures the same bytes.
+fn sample_content() -> String {
+    let path = std::env::temp_dir().join(format!("csv_processing_bench_{}.csv", std::process::id()));
+    let filename = path.to_str().expect("temp dir path is UTF-8");
+    let options = GenerateOptions {
+        seed: Some(42),
+        ..GenerateOptions::new(BENCH_ROWS)
+    };
+    generator::generate(filename, &Schema::sales(), &options, &mut ()).expect("generating benchmark input");
+    let content = std::fs::read_to_string(&path).expect("reading benchmark input");
+    let _ = std::fs::remove_file(&path);
+    content
+}
+
+fn processing_strategies(c: &mut Criterion) {
+    let content = sample_content();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let mut group = c.benchmark_group("csv_processing");
+    group.throughput(Throughput::Bytes(content.len() as u64));
+
+    group.bench_function("sync", |b| b.iter(|| processing::parse_sync(black_box(&content)).unwrap()));
+    group.bench_function("async", |b| {
+        b.to_async(&runtime).iter(|| async { processing::parse_async(black_box(&content)).await.unwrap() })
+    });
+    group.bench_function("chunked", |b| {
+        b.to_async(&runtime).iter(|| async { processing::parse_chunked(black_box(&content), 1000).await.unwrap() })
+    });
+    group.bench_function("byte_record", |b| b.iter(|| processing::parse_byte_records(black_box(&content)).unwrap()));
+    group.bench_function("manual", |b| b.iter(|| processing::parse_manual(black_box(&content)).unwrap()));
+    group.bench_function("parallel", |b| b.iter(|| processing::parse_parallel(black_box(&content))));
+    group.bench_function("streaming", |b| {
+        b.to_async(&runtime).iter(|| async {
+            processing::parse_streaming(black_box(content.as_bytes()), processing::STREAMING_BUFFER_SIZE).await.unwrap()
+        })
+    });
+    group.bench_function("csv_async", |b| {
+        b.to_async(&runtime).iter(|| async { processing::parse_csv_async(black_box(content.as_bytes())).await.unwrap() })
+    });
+
+    group.finish();
+}
+
+/// The async strategy on each runtime built in, one fresh runtime per
+/// iteration batch so tokio is measured the same way as the others.
+fn async_runtimes(c: &mut Criterion) {
+    let content = sample_content();
+
+    let mut group = c.benchmark_group("async_runtimes");
+    group.throughput(Throughput::Bytes(content.len() as u64));
+
+    bench_runtime::<runtimes::Tokio>(&mut group, &content);
+    #[cfg(feature = "async-std")]
+    bench_runtime::<runtimes::AsyncStd>(&mut group, &content);
+    #[cfg(feature = "smol")]
+    bench_runtime::<runtimes::Smol>(&mut group, &content);
+
+    group.finish();
+}
+
+fn bench_runtime<R: AsyncRuntime>(group: &mut criterion::BenchmarkGroup<criterion::measurement::WallTime>, content: &str) {
+    group.bench_function(R::NAME, |b| {
+        b.iter_custom(|iterations| {
+            R::block_on(async {
+                let start = std::time::Instant::now();
+                for _ in 0..iterations {
+                    runtimes::parse_async::<R>(black_box(content)).await.unwrap();
+                }
+                start.elapsed()
+            })
+        })
+    });
+}
+
+/// Top 10 of a high-cardinality grouping, one total per customer name as
+/// /analyze keeps per product, by sorting every group and by
+/// [`aggregators::top_k`].
+fn top_groups(c: &mut Criterion) {
+    let totals: Vec<(String, f64)> = (0..TOP_GROUPS)
+        .map(|index| (format!("Customer {}", index), (index.wrapping_mul(2_654_435_761) % 1_000_003) as f64 / 7.0))
+        .collect();
+
+    let mut group = c.benchmark_group("top_groups");
+    group.throughput(Throughput::Elements(TOP_GROUPS as u64));
+    group.bench_function("sort_truncate", |b| {
+        b.iter(|| {
+            let mut all: Vec<(&str, f64)> = black_box(&totals).iter().map(|(name, total)| (name.as_str(), *total)).collect();
+            all.sort_by(|a, b| b.1.total_cmp(&a.1));
+            all.truncate(10);
+            all
+        })
+    });
+    group.bench_function("top_k", |b| {
+        b.iter(|| aggregators::top_k(black_box(&totals).iter().map(|(name, total)| (name.as_str(), *total)), 10, |(_, total)| *total))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, processing_strategies, async_runtimes, top_groups);
+criterion_main!(benches);
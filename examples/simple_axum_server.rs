@@ -1,12 +1,13 @@
 use axum::{
-    extract::Path,
-    response::Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
     routing::get,
     Router,
 };
 use csv::ReaderBuilder;
-use serde_json;
 use std::time::Instant;
+use tokio_util::sync::CancellationToken;
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 struct SalesRecord {
@@ -24,12 +25,16 @@ async fn main() {
     println!("🌐 Simple Axum CSV Server (Fixed)");
     println!("=================================");
     
+    // Cancelled on Ctrl+C so in-flight parsing stops promptly during shutdown
+    let shutdown = CancellationToken::new();
+    
     let app = Router::new()
         .route("/", get(root_handler))
         .route("/process", get(process_default_csv))
         .route("/process/:filename", get(process_specific_csv))  // ← New dynamic route!
         .route("/health", get(health_check))
-        .route("/files", get(list_files));  // ← List available files
+        .route("/files", get(list_files))  // ← List available files
+        .with_state(shutdown.clone());
     
     let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
         .await
@@ -47,7 +52,16 @@ async fn main() {
     println!("  curl http://127.0.0.1:3000/process/small_data.csv");
     println!("  curl http://127.0.0.1:3000/process/medium_data.csv");
     
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal(shutdown))
+        .await
+        .unwrap();
+}
+
+async fn shutdown_signal(shutdown: CancellationToken) {
+    let _ = tokio::signal::ctrl_c().await;
+    println!("\n🛑 Shutting down, cancelling in-flight processing...");
+    shutdown.cancel();
 }
 
 async fn root_handler() -> Json<serde_json::Value> {
@@ -67,19 +81,22 @@ async fn root_handler() -> Json<serde_json::Value> {
     }))
 }
 
-async fn process_default_csv() -> Json<serde_json::Value> {
-    process_csv_file("small_data.csv").await
+async fn process_default_csv(State(shutdown): State<CancellationToken>) -> Response {
+    process_csv_file("small_data.csv", &shutdown).await
 }
 
-async fn process_specific_csv(Path(filename): Path<String>) -> Json<serde_json::Value> {
+async fn process_specific_csv(
+    Path(filename): Path<String>,
+    State(shutdown): State<CancellationToken>,
+) -> Response {
     // Remove .csv extension if provided, then add it back
     let clean_filename = filename.strip_suffix(".csv").unwrap_or(&filename);
     let csv_filename = format!("{}.csv", clean_filename);
     
-    process_csv_file(&csv_filename).await
+    process_csv_file(&csv_filename, &shutdown).await
 }
 
-async fn process_csv_file(filename: &str) -> Json<serde_json::Value> {
+async fn process_csv_file(filename: &str, shutdown: &CancellationToken) -> Response {
     let start = Instant::now();
     let file_path = format!("sample_data/{}", filename);
     
@@ -91,6 +108,19 @@ async fn process_csv_file(filename: &str) -> Json<serde_json::Value> {
             let mut records = Vec::new();
             
             for result in reader.deserialize() {
+                if shutdown.is_cancelled() {
+                    let duration = start.elapsed();
+                    return Json(serde_json::json!({
+                        "status": "cancelled",
+                        "message": "Server is shutting down",
+                        "file": filename,
+                        "records_processed": records.len(),
+                        "bytes_processed": content.len(),
+                        "duration_ms": duration.as_millis()
+                    }))
+                    .into_response();
+                }
+                
                 match result {
                     Ok(record) => {
                         let record: SalesRecord = record;
@@ -101,13 +131,30 @@ async fn process_csv_file(filename: &str) -> Json<serde_json::Value> {
                             "status": "error",
                             "message": format!("CSV parsing error: {}", e),
                             "file": filename
-                        }));
+                        }))
+                        .into_response();
                     }
                 }
             }
             
+            // Nothing to measure: say so instead of reporting zero throughput
+            if records.is_empty() {
+                return (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    Json(serde_json::json!({
+                        "status": "error",
+                        "message": format!("{} has no data rows", filename),
+                        "file": filename,
+                        "records_processed": 0,
+                        "bytes_processed": content.len()
+                    })),
+                )
+                    .into_response();
+            }
+            
             let duration = start.elapsed();
             let rps = records.len() as f64 / duration.as_secs_f64();
+            let mbps = content.len() as f64 / 1_000_000.0 / duration.as_secs_f64();
             
             Json(serde_json::json!({
                 "status": "success",
@@ -116,8 +163,11 @@ async fn process_csv_file(filename: &str) -> Json<serde_json::Value> {
                 "records_processed": records.len(),
                 "duration_ms": duration.as_millis(),
                 "records_per_second": rps as u64,
+                "bytes_processed": content.len(),
+                "mb_per_second": mbps,
                 "sample_record": records.first()
             }))
+            .into_response()
         }
         Err(_) => {
             Json(serde_json::json!({
@@ -126,6 +176,7 @@ async fn process_csv_file(filename: &str) -> Json<serde_json::Value> {
                 "available_files": "Try GET /files to see available files",
                 "suggestion": "Run 'cargo run --bin generate_data' to create sample files"
             }))
+            .into_response()
         }
     }
 }
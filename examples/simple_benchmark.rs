@@ -1,45 +1,217 @@
+use clap::{Arg, Command};
 use csv::ReaderBuilder;
-use std::time::Instant;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+#[allow(dead_code)]
+mod performance_utils {
+    include!("../src/performance_utils.rs");
+}
+
+#[allow(dead_code)]
+mod processing {
+    include!("../src/processing.rs");
+}
+
+#[allow(dead_code)]
+mod locale {
+    include!("../src/locale.rs");
+}
+
+#[allow(dead_code)]
+mod report {
+    include!("../src/report.rs");
+}
+
+#[allow(dead_code)]
+mod runtimes {
+    include!("../src/runtimes.rs");
+}
+
+#[allow(dead_code)]
+mod metrics_push {
+    include!("../src/metrics_push.rs");
+}
+
+use performance_utils::{apply_results_args, check_results_args, results_args, runtime_args, BenchmarkRunner, PerformanceMetrics, RuntimeOptions, SystemInfo};
+use runtimes::AsyncRuntime;
+
+/// Names accepted by `--strategy`.
+const STRATEGIES: [&str; 3] = ["sync", "async", "mmap"];
 
 // We only need to count records, not deserialize them
 // So let's use a simpler approach
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let command = Command::new("Simple Sync vs Async Benchmark")
+        .about("Times record counting with sync and async file reads")
+        .arg(
+            Arg::new("file")
+                .short('f')
+                .long("file")
+                .value_name("PATH")
+                .help("CSV file to benchmark; repeat for several (default: the sample files)")
+                .action(clap::ArgAction::Append)
+        )
+        .arg(
+            Arg::new("strategy")
+                .short('s')
+                .long("strategy")
+                .value_name("NAME")
+                .help("Strategy to run; repeat for several (default: all)")
+                .value_parser(STRATEGIES)
+                .action(clap::ArgAction::Append)
+        )
+        .arg(
+            Arg::new("iterations")
+                .short('i')
+                .long("iterations")
+                .value_name("N")
+                .help("Runs of each strategy per file; results report the mean")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1")
+        )
+        .arg(
+            Arg::new("runtime")
+                .short('r')
+                .long("runtime")
+                .value_name("NAME")
+                .help("Runtime for the async strategy; repeat for several (default: every runtime built in, see the async-std and smol features)")
+                .value_parser(runtimes::available())
+                .action(clap::ArgAction::Append)
+        );
+    let mut command = runtime_args(report::report_arg(results_args(command)));
+    let matches = command.get_matches_mut();
+    if let Err(message) = check_results_args(&matches) {
+        command.error(clap::error::ErrorKind::ValueValidation, message).exit();
+    }
+    let runtime = RuntimeOptions::from_matches(&matches);
+    runtime.build()?.block_on(run(matches, runtime))
+}
+
+async fn run(matches: clap::ArgMatches, runtime: RuntimeOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let runner = BenchmarkRunner::new(0, *matches.get_one::<usize>("iterations").unwrap());
+    let strategies: Vec<&str> = match matches.get_many::<String>("strategy") {
+        Some(names) => names.map(String::as_str).collect(),
+        None => STRATEGIES.to_vec(),
+    };
+    let runtime_names: Vec<&str> = match matches.get_many::<String>("runtime") {
+        Some(names) => names.map(String::as_str).collect(),
+        None => runtimes::available(),
+    };
+    
     println!("🏆 Simple Sync vs Async Benchmark");
     println!("=================================");
+    runtime.display();
+    SystemInfo::collect().display();
+    
+    // Test with medium and large data if available
+    let test_files: Vec<(String, String)> = match matches.get_many::<String>("file") {
+        Some(paths) => paths.map(|path| (path.clone(), file_description(path))).collect(),
+        None => {
+            if !std::path::Path::new("sample_data/small_data.csv").exists() {
+                println!("❌ Sample data not found. Run: cargo run --bin generate_data");
+                return Ok(());
+            }
+            [
+                ("sample_data/small_data.csv", "Small Dataset"),
+                ("sample_data/medium_data.csv", "Medium Dataset"),
+                ("sample_data/large_data.csv", "Large Dataset"),
+            ]
+            .iter()
+            .map(|(path, description)| (path.to_string(), description.to_string()))
+            .collect()
+        }
+    };
+    
+    // Ctrl+C stops the running benchmark early and prints the partial count
+    let token = CancellationToken::new();
+    let ctrl_c_token = token.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            ctrl_c_token.cancel();
+        }
+    });
     
-    let file_path = "sample_data/small_data.csv";
+    let mut results = Vec::new();
+    let mut runs = Vec::new();
+    for (file_path, description) in &test_files {
+        let (file_path, description) = (file_path.as_str(), description.as_str());
+        if token.is_cancelled() {
+            break;
+        }
+        
+        if !std::path::Path::new(file_path).exists() {
+            if matches.contains_id("file") {
+                println!("⚠️  {} not found, skipping...", file_path);
+            }
+            continue;
+        }
+        
+        println!("\n🔍 Testing: {}", description);
+        let mut file_results = Vec::new();
+        
+        // Benchmark 1: Synchronous processing
+        if strategies.contains(&"sync") {
+            file_results.push(runner.run(|| sync_benchmark(file_path, description, &token))?);
+        }
+        
+        // Benchmark 2: Asynchronous processing, once per runtime
+        if strategies.contains(&"async") {
+            for runtime in &runtime_names {
+                file_results.push(match *runtime {
+                    // Already inside tokio, so await rather than block on a second runtime
+                    runtimes::Tokio::NAME => runner.run_async(|| async_benchmark::<runtimes::Tokio>(file_path, description, &token)).await?,
+                    #[cfg(feature = "async-std")]
+                    runtimes::AsyncStd::NAME => runner.run(|| on_runtime::<runtimes::AsyncStd>(file_path, description, &token))?,
+                    #[cfg(feature = "smol")]
+                    runtimes::Smol::NAME => runner.run(|| on_runtime::<runtimes::Smol>(file_path, description, &token))?,
+                    other => unreachable!("--runtime accepted unknown runtime {}", other),
+                });
+            }
+        }
+        
+        // Benchmark 3: Memory-mapped file
+        if strategies.contains(&"mmap") {
+            file_results.push(runner.run(|| mmap_benchmark(file_path, description, &token))?);
+        }
+        
+        for stats in file_results.iter().filter(|stats| !stats.cancelled) {
+            if runner.iterations > 1 {
+                stats.display();
+            }
+            results.push(stats.result(stats.operation.clone()));
+        }
+        runs.extend(file_results.into_iter().flat_map(|stats| stats.runs));
+        println!("{}", "-".repeat(30));
+    }
     
-    if !std::path::Path::new(file_path).exists() {
-        println!("❌ Sample data not found. Run: cargo run --bin generate_data");
-        return Ok(());
+    // Pushed even when cancelled, so a container stopped mid-run still reports
+    if let Some(pusher) = metrics_push::MetricsPusher::from_env("simple_benchmark") {
+        let mut samples = metrics_push::Samples::new();
+        samples.add_metrics(&runs);
+        samples.add_results(&results);
+        pusher.push(&samples).await;
     }
     
-    // Test with medium data if available
-    let test_files = [
-        ("sample_data/small_data.csv", "Small Dataset"),
-        ("sample_data/medium_data.csv", "Medium Dataset"),
-    ];
-    
-    for (file_path, description) in test_files {
-        if std::path::Path::new(file_path).exists() {
-            println!("\n🔍 Testing: {}", description);
-            
-            // Benchmark 1: Synchronous processing
-            sync_benchmark(file_path)?;
-            
-            // Benchmark 2: Asynchronous processing  
-            async_benchmark(file_path).await?;
-            
-            println!("{}", "-".repeat(30));
+    if !token.is_cancelled() {
+        report::apply_report_arg(&matches, "simple_benchmark", "Sync vs Async CSV Reading", &results)?;
+        if !apply_results_args(&matches, "benchmark", &results)? {
+            std::process::exit(1);
         }
     }
     
     Ok(())
 }
 
-fn sync_benchmark(file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+/// Names a `--file` in output after its file name.
+fn file_description(path: &str) -> String {
+    std::path::Path::new(path)
+        .file_name()
+        .map_or_else(|| path.to_string(), |name| name.to_string_lossy().into_owned())
+}
+
+fn sync_benchmark(file_path: &str, description: &str, token: &CancellationToken) -> Result<PerformanceMetrics, Box<dyn std::error::Error>> {
     let start = Instant::now();
     println!("🔄 Synchronous Processing:");
     
@@ -49,39 +221,84 @@ fn sync_benchmark(file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
     
     // Just count records without deserializing to avoid unused field warnings
     for result in reader.records() {
+        if token.is_cancelled() {
+            break;
+        }
         let _record = result?;
         count += 1;
     }
     
-    let duration = start.elapsed();
-    let rps = count as f64 / duration.as_secs_f64();
-    
-    println!("   ✅ {} records in {:?} ({:.0} records/sec)", count, duration, rps);
-    Ok(())
+    Ok(run_metrics(format!("Synchronous — {}", description), count, start.elapsed(), content.len(), token.is_cancelled()))
 }
 
-async fn async_benchmark(file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+async fn async_benchmark<R: AsyncRuntime>(file_path: &str, description: &str, token: &CancellationToken) -> Result<PerformanceMetrics, Box<dyn std::error::Error>> {
     let start = Instant::now();
-    println!("⚡ Asynchronous Processing:");
+    // Tokio keeps the plain name so results stay comparable with earlier runs
+    let operation = match R::NAME {
+        runtimes::Tokio::NAME => "Asynchronous".to_string(),
+        name => format!("Asynchronous ({})", name),
+    };
+    println!("⚡ {} Processing:", operation);
     
-    let content = tokio::fs::read_to_string(file_path).await?;
+    let content = R::read_to_string(std::path::Path::new(file_path)).await?;
     let mut reader = ReaderBuilder::new().from_reader(content.as_bytes());
     let mut count = 0;
     
     // Just count records without deserializing
     for result in reader.records() {
+        if token.is_cancelled() {
+            break;
+        }
         let _record = result?;
         count += 1;
         
         // Yield every 100 records to demonstrate async behavior
         if count % 100 == 0 {
-            tokio::task::yield_now().await;
+            R::yield_now().await;
+        }
+    }
+    
+    Ok(run_metrics(format!("{} — {}", operation, description), count, start.elapsed(), content.len(), token.is_cancelled()))
+}
+
+/// Runs [`async_benchmark`] on a fresh runtime of kind `R`, outside tokio.
+#[cfg(any(feature = "async-std", feature = "smol"))]
+fn on_runtime<R: AsyncRuntime>(file_path: &str, description: &str, token: &CancellationToken) -> Result<PerformanceMetrics, Box<dyn std::error::Error>> {
+    R::block_on(async_benchmark::<R>(file_path, description, token))
+}
+
+fn mmap_benchmark(file_path: &str, description: &str, token: &CancellationToken) -> Result<PerformanceMetrics, Box<dyn std::error::Error>> {
+    let start = Instant::now();
+    println!("🗺️  Memory-Mapped Processing:");
+    
+    // No read into a buffer; pages are faulted in as the parser reaches them
+    let mapping = processing::map_file(file_path)?;
+    let mut reader = ReaderBuilder::new().from_reader(&mapping[..]);
+    let mut count = 0;
+    
+    for result in reader.records() {
+        if token.is_cancelled() {
+            break;
         }
+        let _record = result?;
+        count += 1;
     }
     
-    let duration = start.elapsed();
-    let rps = count as f64 / duration.as_secs_f64();
+    Ok(run_metrics(format!("Memory-Mapped — {}", description), count, start.elapsed(), mapping.len(), token.is_cancelled()))
+}
+
+/// Prints how one run went and returns it as metrics for the runner.
+fn run_metrics(operation: String, count: usize, duration: Duration, bytes: usize, cancelled: bool) -> PerformanceMetrics {
+    let mut metrics = PerformanceMetrics::new(operation, count, duration);
+    let mbps = bytes as f64 / 1_000_000.0 / duration.as_secs_f64();
+    metrics.bytes_processed = Some(bytes as u64);
+    metrics.megabytes_per_second = Some(mbps);
+    metrics.cancelled = cancelled;
     
-    println!("   ✅ {} records in {:?} ({:.0} records/sec)", count, duration, rps);
-    Ok(())
-}
\ No newline at end of file
+    if cancelled {
+        println!("   ⛔ Cancelled after {} records in {:?} ({:.0} records/sec, {:.2} MB/s)", count, duration, metrics.records_per_second, mbps);
+    } else {
+        println!("   ✅ {} records in {:?} ({:.0} records/sec, {:.2} MB/s)", count, duration, metrics.records_per_second, mbps);
+    }
+    metrics
+}
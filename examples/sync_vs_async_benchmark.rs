@@ -1,26 +1,192 @@
+use clap::{Arg, Command};
 use csv::ReaderBuilder;
 use std::fs;
-use std::time::Instant;
 use rayon::prelude::*;
+use tokio_util::sync::CancellationToken;
 
+#[allow(dead_code)]
 mod performance_utils {
     include!("../src/performance_utils.rs");
 }
 
-use performance_utils::{PerformanceTimer, SalesRecord};
+#[allow(dead_code)]
+mod metrics_push {
+    include!("../src/metrics_push.rs");
+}
+
+#[allow(dead_code)]
+mod processing {
+    include!("../src/processing.rs");
+}
+
+#[allow(dead_code)]
+mod locale {
+    include!("../src/locale.rs");
+}
+
+#[allow(dead_code)]
+mod report {
+    include!("../src/report.rs");
+}
+
+use performance_utils::{apply_results_args, check_results_args, results_args, runtime_args, console_output, set_console_output, BenchmarkRunner, BenchmarkStats, LatencyRecorder, PerformanceMetrics, PerformanceTimer, SalesRecord, StrategyResult, RuntimeOptions, SystemInfo};
+
+/// Lines per chunk tried by `--sweep` unless `--chunk-size` is given.
+const SWEEP_CHUNK_SIZES: [usize; 5] = [1_000, 5_000, 10_000, 50_000, 100_000];
+
+/// Names accepted by `--strategy`, in the order the strategies run.
+const STRATEGIES: [&str; 11] = [
+    "sync",
+    "async",
+    "spawn-blocking",
+    "parallel",
+    "async-parallel",
+    "streaming",
+    "csv-async",
+    "mmap",
+    "mmap-reused",
+    "byte-record",
+    "manual",
+];
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// Files compared when no `--file` is given.
+const DEFAULT_FILES: [(&str, &str); 3] = [
+    ("sample_data/small_data.csv", "Small Dataset (1K records)"),
+    ("sample_data/medium_data.csv", "Medium Dataset (100K records)"),
+    ("sample_data/large_data.csv", "Large Dataset (1M records)"),
+];
+
+/// `--chunk-size` and `--concurrency` for the parallel strategies; `None`
+/// keeps each strategy's own default.
+#[derive(Debug, Clone, Copy, Default)]
+struct Tuning {
+    chunk_size: Option<usize>,
+    concurrency: Option<usize>,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let command = Command::new("Sync vs Async Benchmark")
+        .about("Compares CSV processing strategies over repeated runs")
+        .arg(
+            Arg::new("warmup")
+                .long("warmup")
+                .value_name("N")
+                .help("Unmeasured runs of each strategy before timing starts")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1")
+        )
+        .arg(
+            Arg::new("iterations")
+                .short('i')
+                .long("iterations")
+                .value_name("N")
+                .help("Measured runs of each strategy per file")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("5")
+        )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .help("Only print the per-strategy statistics, not every run")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("sweep")
+                .long("sweep")
+                .help("Instead of comparing strategies, sweep chunk size × concurrency for the chunked and async+parallel strategies")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("file")
+                .short('f')
+                .long("file")
+                .value_name("PATH")
+                .help("CSV file to benchmark; repeat for several (default: the small, medium and large sample files)")
+                .action(clap::ArgAction::Append)
+        )
+        .arg(
+            Arg::new("strategy")
+                .short('s')
+                .long("strategy")
+                .value_name("NAME")
+                .help("Strategy to run; repeat for several (default: all)")
+                .value_parser(STRATEGIES)
+                .action(clap::ArgAction::Append)
+        )
+        .arg(
+            Arg::new("chunk-size")
+                .long("chunk-size")
+                .value_name("LINES")
+                .help("Lines per chunk for the parallel strategies; repeat to give --sweep its sizes")
+                .value_parser(clap::value_parser!(usize))
+                .action(clap::ArgAction::Append)
+        )
+        .arg(
+            Arg::new("concurrency")
+                .long("concurrency")
+                .value_name("N")
+                .help("Threads or tasks for the parallel strategies (default: one per CPU); repeat to give --sweep its levels")
+                .value_parser(clap::value_parser!(usize))
+                .action(clap::ArgAction::Append)
+        );
+    let mut command = runtime_args(report::report_arg(results_args(command)));
+    let matches = command.get_matches_mut();
+    if let Err(message) = check_results_args(&matches) {
+        command.error(clap::error::ErrorKind::ValueValidation, message).exit();
+    }
+    let runtime = RuntimeOptions::from_matches(&matches);
+    runtime.build()?.block_on(run(matches, runtime))
+}
+
+async fn run(matches: clap::ArgMatches, runtime: RuntimeOptions) -> Result<(), Box<dyn std::error::Error>> {
+    set_console_output(!matches.get_flag("quiet"));
+    let strategies: Vec<&str> = match matches.get_many::<String>("strategy") {
+        Some(names) => names.map(String::as_str).collect(),
+        None => STRATEGIES.to_vec(),
+    };
+    let test_files: Vec<(String, String)> = match matches.get_many::<String>("file") {
+        Some(paths) => paths.map(|path| (path.clone(), file_description(path))).collect(),
+        None => DEFAULT_FILES.iter().map(|(path, description)| (path.to_string(), description.to_string())).collect(),
+    };
+    let chunk_sizes: Vec<usize> = matches.get_many::<usize>("chunk-size").map_or(SWEEP_CHUNK_SIZES.to_vec(), |sizes| sizes.copied().collect());
+    let concurrency: Vec<usize> = matches.get_many::<usize>("concurrency").map_or_else(concurrency_levels, |levels| levels.copied().collect());
+    let tuning = Tuning {
+        chunk_size: matches.get_one::<usize>("chunk-size").copied(),
+        concurrency: matches.get_one::<usize>("concurrency").copied(),
+    };
+    // Built once, so the timed runs do not pay for starting threads
+    let pool = tuning
+        .concurrency
+        .map(|threads| rayon::ThreadPoolBuilder::new().num_threads(threads.max(1)).build())
+        .transpose()?;
+    let runner = BenchmarkRunner::new(
+        *matches.get_one::<usize>("warmup").unwrap(),
+        *matches.get_one::<usize>("iterations").unwrap(),
+    );
+    
     println!("🏆 Comprehensive CSV Processing Benchmark");
     println!("========================================");
+    println!("🔁 {} warmup + {} measured iterations per strategy", runner.warmup, runner.iterations);
+    runtime.display();
+    SystemInfo::collect().display();
     
-    let test_files = [
-        ("sample_data/small_data.csv", "Small Dataset (1K records)"),
-        ("sample_data/medium_data.csv", "Medium Dataset (100K records)"),
-        ("sample_data/large_data.csv", "Large Dataset (1M records)"),
-    ];
+    // Ctrl+C stops the running benchmark early and prints partial metrics
+    let token = CancellationToken::new();
+    tokio::spawn(cancel_on_ctrl_c(token.clone()));
+    let mut latencies = LatencyRecorder::new();
+    let mut results = Vec::new();
+    let mut serde_overheads = Vec::new();
+    let mut blocking_speedups = Vec::new();
+    let mut mmap_comparisons = Vec::new();
     
-    for (file_path, description) in test_files {
+    for (file_path, description) in &test_files {
+        let (file_path, description) = (file_path.as_str(), description.as_str());
+        if token.is_cancelled() {
+            println!("⛔ Cancelled, skipping remaining files");
+            break;
+        }
+        
         if !std::path::Path::new(file_path).exists() {
             println!("⚠️  {} not found, skipping...", file_path);
             continue;
@@ -29,53 +195,283 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         println!("\n🔍 Testing: {}", description);
         println!("{}", "=".repeat(50));
         
-        // Run all benchmarks for this file
-        benchmark_sync_processing(file_path)?;
-        benchmark_async_processing(file_path).await?;
-        benchmark_parallel_processing(file_path)?;
-        benchmark_async_parallel_processing(file_path).await?;
+        if matches.get_flag("sweep") {
+            let sweep = Sweep {
+                chunk_sizes: &chunk_sizes,
+                concurrency: &concurrency,
+                strategies: &strategies,
+            };
+            results.extend(run_sweep(file_path, description, &sweep, &runner, &token).await?);
+            continue;
+        }
+        
+        // Run the selected benchmarks for this file
+        let selected = |name: &str| strategies.contains(&name);
+        let mut file_results: Vec<(&str, BenchmarkStats)> = Vec::new();
+        if selected("sync") {
+            file_results.push(("sync", runner.run(|| benchmark_sync_processing(file_path, &token))?));
+        }
+        if selected("async") {
+            file_results.push(("async", runner.run_async(|| benchmark_async_processing(file_path, &token)).await?));
+        }
+        if selected("spawn-blocking") {
+            file_results.push(("spawn-blocking", runner.run_async(|| benchmark_spawn_blocking_processing(file_path, &token)).await?));
+        }
+        if selected("parallel") {
+            file_results.push(("parallel", runner.run(|| benchmark_parallel_processing(file_path, tuning, pool.as_ref(), &token))?));
+        }
+        if selected("async-parallel") {
+            file_results.push(("async-parallel", runner.run_async(|| benchmark_async_parallel_processing(file_path, tuning, &token)).await?));
+        }
+        if selected("streaming") {
+            file_results.push(("streaming", runner.run_async(|| benchmark_streaming_processing(file_path, &token)).await?));
+        }
+        if selected("csv-async") {
+            file_results.push(("csv-async", runner.run_async(|| benchmark_csv_async_processing(file_path, &token)).await?));
+        }
+        if selected("mmap") {
+            file_results.push(("mmap", runner.run(|| benchmark_mmap_processing(file_path, None, &token))?));
+        }
+        if selected("mmap-reused") {
+            // One mapping serves every iteration, so only the warmup touches the pages
+            let mapping = processing::map_file(file_path)?;
+            file_results.push(("mmap-reused", runner.run(|| benchmark_mmap_processing(file_path, Some(&mapping), &token))?));
+        }
+        if selected("byte-record") {
+            file_results.push(("byte-record", runner.run(|| benchmark_byte_record_processing(file_path, &token, false))?));
+        }
+        if selected("manual") {
+            file_results.push(("manual", runner.run(|| benchmark_byte_record_processing(file_path, &token, true))?));
+        }
         
         println!("{}", "=".repeat(50));
+        let completed = |name: &str| file_results.iter().find(|(n, _)| *n == name).map(|(_, stats)| stats).filter(|stats| !stats.cancelled);
+        if let (Some(yielding_stats), Some(blocking_stats)) = (completed("async"), completed("spawn-blocking")) {
+            blocking_speedups.push((description, yielding_stats.mean.as_secs_f64() / blocking_stats.mean.as_secs_f64()));
+        }
+        if let (Some(buffered_stats), Some(fresh_map_stats), Some(reused_map_stats)) =
+            (completed("sync"), completed("mmap"), completed("mmap-reused"))
+        {
+            mmap_comparisons.push((
+                description,
+                buffered_stats.mean.as_secs_f64() / fresh_map_stats.mean.as_secs_f64(),
+                page_faults_per_run(fresh_map_stats),
+                buffered_stats.mean.as_secs_f64() / reused_map_stats.mean.as_secs_f64(),
+                page_faults_per_run(reused_map_stats),
+            ));
+        }
+        if let Some(overhead) = completed("byte-record").zip(completed("manual")).and_then(|(serde, manual)| serde_overhead(serde, manual)) {
+            serde_overheads.push((description, overhead));
+        }
+        for (_, stats) in &file_results {
+            stats.display();
+            if !stats.cancelled {
+                // Without the emoji, so exported files stay easy to plot
+                let strategy = stats.operation.trim_start_matches(|c: char| !c.is_ascii_alphanumeric());
+                results.push(stats.result(format!("{} — {}", strategy, description)));
+            }
+            for run in &stats.runs {
+                latencies.record(&format!("{} — {}", run.operation, description), run.duration);
+            }
+        }
     }
     
     println!("\n📊 Benchmark Summary:");
+    latencies.display();
     println!("• Sync: Traditional single-threaded processing");
     println!("• Async: Tokio async/await with yielding");
+    println!("• spawn_blocking: Async read, then parse on tokio's blocking pool");
     println!("• Parallel: Multi-threaded with Rayon");
     println!("• Async+Parallel: Combine async I/O with parallel processing");
+    println!("• Streaming: Parse while reading, in constant memory");
+    println!("• csv-async: The csv-async crate's AsyncDeserializer reading the file directly");
+    println!("• Memory-Mapped: Parse straight from an mmap of the file, mapped per run or once");
+    println!("• ByteRecord: One reused record, deserialized with serde or built by hand");
+    for (description, speedup) in &blocking_speedups {
+        println!("🧵 spawn_blocking vs yielding every 1000 rows on {}: {:.2}×", description, speedup);
+    }
+    for (description, fresh, fresh_faults, reused, reused_faults) in &mmap_comparisons {
+        println!(
+            "🗺️  mmap vs buffered read on {}: fresh mapping {:.2}× ({:.0} page faults/run), reused mapping {:.2}× ({:.0} page faults/run)",
+            description, fresh, fresh_faults, reused, reused_faults
+        );
+    }
+    for (description, (per_row, percent)) in &serde_overheads {
+        println!("🧮 Serde overhead on {}: {:.0} ns/row ({:+.1}% vs manual)", description, per_row, percent);
+    }
     println!("\n💡 Key Takeaways:");
     println!("• Async shines for I/O-bound operations");
+    println!("• In services, parse in spawn_blocking rather than yielding: workers stay free at no extra cost");
     println!("• Parallel processing helps with CPU-bound work");
     println!("• Combined approach best for large datasets");
     
+    // Pushed even when cancelled, so a container stopped mid-run still reports
+    if let Some(pusher) = metrics_push::MetricsPusher::from_env("sync_vs_async_benchmark") {
+        let mut samples = metrics_push::Samples::new();
+        samples.add_results(&results);
+        samples.add_latencies(&latencies.summary());
+        pusher.push(&samples).await;
+    }
+    
+    if token.is_cancelled() {
+        println!("\n⛔ Cancelled, baselines not saved or compared");
+    } else {
+        report::apply_report_arg(&matches, "sync_vs_async", "CSV Processing Strategies", &results)?;
+        if !apply_results_args(&matches, "sync_vs_async_benchmark", &results)? {
+            std::process::exit(1);
+        }
+    }
+    
     Ok(())
 }
 
-fn benchmark_sync_processing(file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let timer = PerformanceTimer::new("🔄 Synchronous Processing".to_string());
+/// Names a `--file` in output after its file name.
+fn file_description(path: &str) -> String {
+    std::path::Path::new(path)
+        .file_name()
+        .map_or_else(|| path.to_string(), |name| name.to_string_lossy().into_owned())
+}
+
+/// What `--sweep` tries.
+struct Sweep<'a> {
+    chunk_sizes: &'a [usize],
+    concurrency: &'a [usize],
+    /// The `--strategy` names; the sweep covers "parallel" and "async-parallel".
+    strategies: &'a [&'a str],
+}
+
+/// 1, 2, 4, ... up to twice the number of cores.
+fn concurrency_levels() -> Vec<usize> {
+    let max = 2 * num_cpus::get();
+    let mut levels: Vec<usize> = std::iter::successors(Some(1), |level| Some(level * 2))
+        .take_while(|&level| level < max)
+        .collect();
+    levels.push(max);
+    levels
+}
+
+/// Times the chunked (rayon pool of N threads) and async+parallel (at most
+/// N tokio tasks) strategies for every chunk size and concurrency level,
+/// prints a throughput matrix per strategy and returns every cell. Only
+/// parsing is timed; the file is read once up front.
+async fn run_sweep(
+    file_path: &str,
+    description: &str,
+    sweep: &Sweep<'_>,
+    runner: &BenchmarkRunner,
+    token: &CancellationToken,
+) -> Result<Vec<StrategyResult>, Box<dyn std::error::Error>> {
+    let content = tokio::fs::read_to_string(file_path).await?;
+    let levels = sweep.concurrency;
+    let mut results = Vec::new();
+    
+    // Hundreds of runs; the matrix is the useful output
+    let console = console_output();
+    set_console_output(false);
+    
+    for (name, strategy) in [("parallel", "Chunked"), ("async-parallel", "Async + Parallel")] {
+        if !sweep.strategies.contains(&name) {
+            continue;
+        }
+        println!("\n🧮 {} (K records/sec, chunk size × concurrency)", strategy);
+        print!("{:>10}", "chunk");
+        for level in levels {
+            print!("{:>10}", format!("×{}", level));
+        }
+        println!();
+        
+        let mut best: Option<(usize, usize, f64)> = None;
+        for &chunk_size in sweep.chunk_sizes {
+            print!("{:>10}", chunk_size);
+            for &level in levels {
+                if token.is_cancelled() {
+                    println!("\n⛔ Cancelled, sweep incomplete");
+                    set_console_output(console);
+                    return Ok(results);
+                }
+                
+                let label = format!("{} (chunk {}, ×{})", strategy, chunk_size, level);
+                let content = content.as_str();
+                let stats = if strategy == "Chunked" {
+                    let pool = rayon::ThreadPoolBuilder::new().num_threads(level.max(1)).build()?;
+                    runner.run(|| {
+                        let mut timer = PerformanceTimer::new(label.clone());
+                        timer.add_bytes(content.len());
+                        timer.set_concurrency(level);
+                        let count = pool.install(|| processing::parse_parallel_chunks(content, chunk_size));
+                        Ok::<_, Box<dyn std::error::Error>>(timer.finish(count))
+                    })?
+                } else {
+                    runner
+                        .run_async(|| {
+                            let label = label.clone();
+                            async move {
+                                let mut timer = PerformanceTimer::new(label);
+                                timer.add_bytes(content.len());
+                                timer.set_concurrency(level);
+                                let count = processing::parse_async_parallel(content, chunk_size, level).await?;
+                                Ok::<_, Box<dyn std::error::Error>>(timer.finish(count))
+                            }
+                        })
+                        .await?
+                };
+                
+                print!("{:>10.0}", stats.records_per_second / 1_000.0);
+                if best.is_none_or(|(_, _, rate)| stats.records_per_second > rate) {
+                    best = Some((chunk_size, level, stats.records_per_second));
+                }
+                results.push(stats.result(format!("{} — {}", label, description)));
+            }
+            println!();
+        }
+        if let Some((chunk_size, level, rate)) = best {
+            println!("🏅 Best: chunk {} × {} concurrent → {:.0} records/sec", chunk_size, level, rate);
+        }
+    }
+    
+    set_console_output(console);
+    Ok(results)
+}
+
+async fn cancel_on_ctrl_c(token: CancellationToken) {
+    if tokio::signal::ctrl_c().await.is_ok() {
+        println!("\n⛔ Ctrl+C received, cancelling...");
+        token.cancel();
+    }
+}
+
+fn benchmark_sync_processing(file_path: &str, token: &CancellationToken) -> Result<PerformanceMetrics, Box<dyn std::error::Error>> {
+    let mut timer = PerformanceTimer::new("🔄 Synchronous Processing".to_string());
     
     let content = fs::read_to_string(file_path)?;
+    timer.add_bytes(content.len());
     let mut reader = ReaderBuilder::new().from_reader(content.as_bytes());
     let mut records = Vec::new();
     
     for result in reader.deserialize() {
+        if token.is_cancelled() {
+            return Ok(timer.cancelled(records.len()));
+        }
         let record: SalesRecord = result?;
         records.push(record);
     }
     
-    timer.finish(records.len());
-    Ok(())
+    Ok(timer.finish(records.len()))
 }
 
-async fn benchmark_async_processing(file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let timer = PerformanceTimer::new("⚡ Asynchronous Processing".to_string());
+async fn benchmark_async_processing(file_path: &str, token: &CancellationToken) -> Result<PerformanceMetrics, Box<dyn std::error::Error>> {
+    let mut timer = PerformanceTimer::new("⚡ Asynchronous Processing".to_string());
     
     let content = tokio::fs::read_to_string(file_path).await?;
+    timer.add_bytes(content.len());
     let mut reader = ReaderBuilder::new().from_reader(content.as_bytes());
     let mut records = Vec::new();
     
     let mut count = 0;
     for result in reader.deserialize() {
+        if token.is_cancelled() {
+            return Ok(timer.cancelled(records.len()));
+        }
         let record: SalesRecord = result?;
         records.push(record);
         count += 1;
@@ -86,79 +482,259 @@ async fn benchmark_async_processing(file_path: &str) -> Result<(), Box<dyn std::
         }
     }
     
-    timer.finish(records.len());
-    Ok(())
+    Ok(timer.finish(records.len()))
 }
 
-fn benchmark_parallel_processing(file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let timer = PerformanceTimer::new("🚀 Parallel Processing (Rayon)".to_string());
+/// The pattern services should use: read asynchronously, then move the
+/// CPU-bound parse to the blocking pool so it never stalls a runtime worker.
+async fn benchmark_spawn_blocking_processing(file_path: &str, token: &CancellationToken) -> Result<PerformanceMetrics, Box<dyn std::error::Error>> {
+    let mut timer = PerformanceTimer::new("🧵 Async Read + spawn_blocking Parse".to_string());
+    
+    let content = tokio::fs::read_to_string(file_path).await?;
+    timer.add_bytes(content.len());
+    let token = token.clone();
+    let (records, cancelled) = tokio::task::spawn_blocking(move || -> Result<_, csv::Error> {
+        let mut reader = ReaderBuilder::new().from_reader(content.as_bytes());
+        let mut records = Vec::new();
+        for result in reader.deserialize() {
+            if token.is_cancelled() {
+                return Ok((records, true));
+            }
+            let record: SalesRecord = result?;
+            records.push(record);
+        }
+        Ok((records, false))
+    })
+    .await??;
+    
+    if cancelled {
+        Ok(timer.cancelled(records.len()))
+    } else {
+        Ok(timer.finish(records.len()))
+    }
+}
+
+async fn benchmark_streaming_processing(file_path: &str, token: &CancellationToken) -> Result<PerformanceMetrics, Box<dyn std::error::Error>> {
+    let mut timer = PerformanceTimer::new("🌊 Streaming Processing".to_string());
+    
+    let file = tokio::fs::File::open(file_path).await?;
+    let mut reader = processing::StreamingReader::new(file, processing::STREAMING_BUFFER_SIZE);
+    let mut count = 0;
+    
+    while let Some(records) = reader.next_batch().await? {
+        if token.is_cancelled() {
+            timer.add_bytes(reader.bytes_read());
+            return Ok(timer.cancelled(count));
+        }
+        count += records.len();
+    }
+    
+    timer.add_bytes(reader.bytes_read());
+    Ok(timer.finish(count))
+}
+
+async fn benchmark_csv_async_processing(file_path: &str, token: &CancellationToken) -> Result<PerformanceMetrics, Box<dyn std::error::Error>> {
+    use futures::StreamExt;
+    
+    let mut timer = PerformanceTimer::new("📦 csv-async Deserializer".to_string());
+    
+    let file = tokio::fs::File::open(file_path).await?;
+    timer.add_bytes(file.metadata().await?.len() as usize);
+    let mut deserializer = csv_async::AsyncDeserializer::from_reader(file);
+    let mut records = deserializer.deserialize::<SalesRecord>();
+    let mut count = 0;
+    
+    while let Some(record) = records.next().await {
+        if token.is_cancelled() {
+            return Ok(timer.cancelled(count));
+        }
+        let _record: SalesRecord = record?;
+        count += 1;
+    }
+    
+    Ok(timer.finish(count))
+}
+
+/// Parses straight out of a memory-mapped file. Without `mapping` every run
+/// maps the file afresh and faults in each page on first touch; with one,
+/// runs after the first find the pages already mapped.
+fn benchmark_mmap_processing(
+    file_path: &str,
+    mapping: Option<&memmap2::Mmap>,
+    token: &CancellationToken,
+) -> Result<PerformanceMetrics, Box<dyn std::error::Error>> {
+    let mut timer = PerformanceTimer::new(if mapping.is_some() {
+        "🗺️ Memory-Mapped (reused mapping)".to_string()
+    } else {
+        "🗺️ Memory-Mapped (fresh mapping)".to_string()
+    });
+    
+    let fresh;
+    let bytes: &[u8] = match mapping {
+        Some(mapping) => mapping,
+        None => {
+            fresh = processing::map_file(file_path)?;
+            &fresh
+        }
+    };
+    timer.add_bytes(bytes.len());
+    let mut reader = ReaderBuilder::new().from_reader(bytes);
+    let mut records = Vec::new();
+    
+    for result in reader.deserialize() {
+        if token.is_cancelled() {
+            return Ok(timer.cancelled(records.len()));
+        }
+        let record: SalesRecord = result?;
+        records.push(record);
+    }
+    
+    Ok(timer.finish(records.len()))
+}
+
+/// Mean minor plus major page faults over the measured runs.
+fn page_faults_per_run(stats: &BenchmarkStats) -> f64 {
+    let faults: u64 = stats
+        .runs
+        .iter()
+        .map(|run| run.minor_page_faults.unwrap_or(0) + run.major_page_faults.unwrap_or(0))
+        .sum();
+    faults as f64 / stats.runs.len().max(1) as f64
+}
+
+/// Parses from a reused `ByteRecord`, through serde or by hand, so the two
+/// differ only in how fields become a `SalesRecord`.
+fn benchmark_byte_record_processing(file_path: &str, token: &CancellationToken, manual: bool) -> Result<PerformanceMetrics, Box<dyn std::error::Error>> {
+    let mut timer = PerformanceTimer::new(if manual {
+        "🛠️ Manual ByteRecord Parsing".to_string()
+    } else {
+        "🧬 Serde ByteRecord Parsing".to_string()
+    });
+    
+    let content = fs::read_to_string(file_path)?;
+    timer.add_bytes(content.len());
+    if token.is_cancelled() {
+        return Ok(timer.cancelled(0));
+    }
+    let count = if manual {
+        processing::parse_manual(&content)?
+    } else {
+        processing::parse_byte_records(&content)?
+    };
+    
+    Ok(timer.finish(count))
+}
+
+/// Extra time per row that serde deserialization costs over manual field
+/// extraction, in nanoseconds and as a percentage of the manual time.
+fn serde_overhead(serde: &BenchmarkStats, manual: &BenchmarkStats) -> Option<(f64, f64)> {
+    let records = serde.runs.first()?.records_processed;
+    if serde.cancelled || manual.cancelled || records == 0 {
+        return None;
+    }
+    let per_row = (serde.mean.as_secs_f64() - manual.mean.as_secs_f64()) * 1e9 / records as f64;
+    Some((per_row, (serde.mean.as_secs_f64() / manual.mean.as_secs_f64() - 1.0) * 100.0))
+}
+
+/// Parses chunks on rayon: the `--concurrency` pool when given, otherwise
+/// the global pool with one chunk per core.
+fn benchmark_parallel_processing(
+    file_path: &str,
+    tuning: Tuning,
+    pool: Option<&rayon::ThreadPool>,
+    token: &CancellationToken,
+) -> Result<PerformanceMetrics, Box<dyn std::error::Error>> {
+    let mut timer = PerformanceTimer::new("🚀 Parallel Processing (Rayon)".to_string());
     
     let content = fs::read_to_string(file_path)?;
-    let lines: Vec<&str> = content.lines().collect();
+    timer.add_bytes(content.len());
+    let lines: Vec<&str> = processing::split_rows(&content);
     
     if lines.is_empty() {
-        timer.finish(0);
-        return Ok(());
+        return Ok(timer.finish(0));
     }
     
     let header = lines[0];
     let data_lines = &lines[1..];
     
     // Process chunks in parallel
-    let chunk_size = 10000.max(data_lines.len() / num_cpus::get());
-    let total_records: usize = data_lines
-        .par_chunks(chunk_size)
-        .map(|chunk| {
-            let chunk_content = format!("{}\n{}", header, chunk.join("\n"));
-            let mut reader = ReaderBuilder::new().from_reader(chunk_content.as_bytes());
-            let mut count = 0;
-            
-            for result in reader.deserialize() {
-                if let Ok(_record) = result {
-                    let _record: SalesRecord = _record;
-                    count += 1;
+    let threads = pool.map_or_else(rayon::current_num_threads, rayon::ThreadPool::current_num_threads);
+    let chunk_size = tuning.chunk_size.unwrap_or_else(|| processing::chunk_size_for(data_lines.len(), threads)).max(1);
+    timer.set_concurrency(threads);
+    let parse = || {
+        data_lines
+            .par_chunks(chunk_size)
+            .map(|chunk| {
+                let chunk_content = format!("{}\n{}", header, chunk.join("\n"));
+                let mut reader = ReaderBuilder::new().from_reader(chunk_content.as_bytes());
+                let mut count = 0;
+                
+                for result in reader.deserialize::<SalesRecord>() {
+                    if token.is_cancelled() {
+                        break;
+                    }
+                    if result.is_ok() {
+                        count += 1;
+                    }
                 }
-            }
-            count
-        })
-        .sum();
+                count
+            })
+            .sum::<usize>()
+    };
+    let total_records = match pool {
+        Some(pool) => pool.install(parse),
+        None => parse(),
+    };
     
-    timer.finish(total_records);
-    Ok(())
+    if token.is_cancelled() {
+        Ok(timer.cancelled(total_records))
+    } else {
+        Ok(timer.finish(total_records))
+    }
 }
 
-async fn benchmark_async_parallel_processing(file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let timer = PerformanceTimer::new("🔥 Async + Parallel Processing".to_string());
+/// Parses chunks as tokio tasks, at most `--concurrency` (default one per
+/// CPU) at a time.
+async fn benchmark_async_parallel_processing(
+    file_path: &str,
+    tuning: Tuning,
+    token: &CancellationToken,
+) -> Result<PerformanceMetrics, Box<dyn std::error::Error>> {
+    use futures::StreamExt;
+    
+    let mut timer = PerformanceTimer::new("🔥 Async + Parallel Processing".to_string());
     
     // Async file read
     let content = tokio::fs::read_to_string(file_path).await?;
-    let lines: Vec<&str> = content.lines().collect();
+    timer.add_bytes(content.len());
+    let lines: Vec<&str> = processing::split_rows(&content);
     
     if lines.is_empty() {
-        timer.finish(0);
-        return Ok(());
+        return Ok(timer.finish(0));
     }
     
     let header = lines[0];
     let data_lines = &lines[1..];
     
     // Split into chunks for concurrent processing
-    let chunk_size = 10000.max(data_lines.len() / 8); // 8 concurrent tasks
-    let chunks: Vec<_> = data_lines.chunks(chunk_size).collect();
-    
-    // Process chunks concurrently
-    let mut tasks = Vec::new();
+    let tasks = tuning.concurrency.unwrap_or_else(processing::default_concurrency).max(1);
+    let chunk_size = tuning.chunk_size.unwrap_or_else(|| processing::chunk_size_for(data_lines.len(), tasks)).max(1);
+    timer.set_concurrency(tasks);
     
-    for chunk in chunks {
+    // Spawned as the stream pulls them, so no more than `tasks` run at once
+    let spawned = data_lines.chunks(chunk_size).map(|chunk| {
         let chunk_content = format!("{}\n{}", header, chunk.join("\n"));
+        let token = token.child_token();
         
-        let task = tokio::spawn(async move {
+        tokio::spawn(async move {
             let mut reader = ReaderBuilder::new().from_reader(chunk_content.as_bytes());
             let mut count = 0;
             
-            for result in reader.deserialize() {
-                if let Ok(_record) = result {
-                    let _record: SalesRecord = _record;
+            for result in reader.deserialize::<SalesRecord>() {
+                if token.is_cancelled() {
+                    break;
+                }
+                if result.is_ok() {
                     count += 1;
                 }
                 
@@ -168,17 +744,19 @@ async fn benchmark_async_parallel_processing(file_path: &str) -> Result<(), Box<
                 }
             }
             count
-        });
-        
-        tasks.push(task);
-    }
+        })
+    });
     
     // Collect results
     let mut total_records = 0;
-    for task in tasks {
-        total_records += task.await?;
+    let mut completed = futures::stream::iter(spawned).buffer_unordered(tasks);
+    while let Some(count) = completed.next().await {
+        total_records += count?;
     }
     
-    timer.finish(total_records);
-    Ok(())
+    if token.is_cancelled() {
+        Ok(timer.cancelled(total_records))
+    } else {
+        Ok(timer.finish(total_records))
+    }
 }
\ No newline at end of file
@@ -1,17 +1,77 @@
+use clap::{Arg, Command};
 use csv::ReaderBuilder;
-use std::path::Path;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tokio::fs::File;
 use tokio::io::{AsyncReadExt, BufReader};
+use tokio_util::sync::CancellationToken;
 use anyhow::Result;
 
+#[allow(dead_code)]
 mod performance_utils {
     include!("../src/performance_utils.rs");
 }
 
+#[allow(dead_code)]
+mod processing {
+    include!("../src/processing.rs");
+}
+
 use performance_utils::{PerformanceTimer, SalesRecord};
+use processing::{StreamingReader, STREAMING_BUFFER_SIZE};
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let matches = Command::new("Tokio CSV Demo")
+        .about("Walks through async CSV processing methods on the sample data")
+        .arg(
+            Arg::new("concurrency")
+                .long("concurrency")
+                .value_name("N")
+                .help("Tasks for concurrent chunk processing (default: one per CPU)")
+                .value_parser(clap::value_parser!(usize))
+        )
+        .arg(
+            Arg::new("watch")
+                .long("watch")
+                .value_name("DIR")
+                .help("Process every CSV dropped into DIR until Ctrl+C, instead of the demo")
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .value_name("DIR")
+                .help("Where --watch writes analysis results (default: DIR/output)")
+                .requires("watch")
+        )
+        .arg(
+            Arg::new("interval")
+                .long("interval")
+                .value_name("SECONDS")
+                .help("How often --watch looks for new files")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .default_value("1")
+                .requires("watch")
+        )
+        .get_matches();
+    let concurrency = matches
+        .get_one::<usize>("concurrency")
+        .copied()
+        .unwrap_or_else(processing::default_concurrency)
+        .max(1);
+    
+    // Ctrl+C stops the current method early and prints partial metrics
+    let token = CancellationToken::new();
+    tokio::spawn(cancel_on_ctrl_c(token.clone()));
+    
+    if let Some(dir) = matches.get_one::<String>("watch") {
+        let dir = PathBuf::from(dir);
+        let output = matches.get_one::<String>("output").map_or_else(|| dir.join("output"), PathBuf::from);
+        let interval = Duration::from_secs(*matches.get_one::<u64>("interval").unwrap());
+        return watch_folder(&dir, &output, interval, &token).await;
+    }
+    
     println!("🚀 Tokio CSV Processing Demo");
     println!("===========================");
     
@@ -26,17 +86,25 @@ async fn main() -> Result<()> {
     ];
 
     for (file_path, description) in files {
+        if token.is_cancelled() {
+            println!("⛔ Cancelled, skipping remaining files");
+            break;
+        }
+
         if Path::new(file_path).exists() {
             println!("\n🔍 Processing: {}", description);
             
             // Method 1: Async file reading + sync CSV parsing
-            async_file_sync_csv(file_path).await?;
+            async_file_sync_csv(file_path, &token).await?;
             
-            // Method 2: Streaming async CSV processing
-            streaming_async_csv(file_path).await?;
+            // Method 2: Async read_to_end + CSV parsing with periodic yields
+            read_to_end_async_csv(file_path, &token).await?;
             
             // Method 3: Concurrent chunk processing
-            concurrent_chunk_processing(file_path).await?;
+            concurrent_chunk_processing(file_path, concurrency, &token).await?;
+            
+            // Method 4: True streaming, parsing records as the file is read
+            streaming_async_csv(file_path, &token).await?;
             
             println!("{}", "=".repeat(50));
         } else {
@@ -47,43 +115,219 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-async fn async_file_sync_csv(file_path: &str) -> Result<()> {
-    let timer = PerformanceTimer::new(format!("Async File + Sync CSV: {}", file_path));
+async fn cancel_on_ctrl_c(token: CancellationToken) {
+    if tokio::signal::ctrl_c().await.is_ok() {
+        println!("\n⛔ Ctrl+C received, cancelling...");
+        token.cancel();
+    }
+}
+
+/// Hot-folder mode: every `*.csv` that appears in `dir` is analyzed once its
+/// size stops changing between two polls (so a file still being copied in
+/// is left alone), the result is written to `output` as
+/// `<name>.analysis.json`, its metrics are appended to
+/// `output/metrics.jsonl`, and the file is moved to `dir/processed/`, or to
+/// `dir/failed/` with a `<name>.error.json` when it cannot be parsed.
+async fn watch_folder(dir: &Path, output: &Path, interval: Duration, token: &CancellationToken) -> Result<()> {
+    for subdir in [output.to_path_buf(), dir.join("processed"), dir.join("failed")] {
+        tokio::fs::create_dir_all(subdir).await?;
+    }
+    println!("👀 Watching {} for CSV files, results go to {} (Ctrl+C to stop)", dir.display(), output.display());
+    
+    // Size of each candidate at the previous poll
+    let mut sizes: HashMap<PathBuf, u64> = HashMap::new();
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => break,
+            _ = ticker.tick() => {}
+        }
+        
+        let mut seen = HashMap::new();
+        let mut entries = tokio::fs::read_dir(dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let metadata = entry.metadata().await?;
+            if metadata.is_file() && path.extension().is_some_and(|extension| extension == "csv") {
+                seen.insert(path, metadata.len());
+            }
+        }
+        
+        let mut stable: Vec<PathBuf> = seen
+            .iter()
+            .filter(|(path, size)| sizes.get(*path) == Some(size))
+            .map(|(path, _)| path.clone())
+            .collect();
+        stable.sort();
+        for path in stable {
+            if token.is_cancelled() {
+                break;
+            }
+            seen.remove(&path);
+            process_dropped_file(dir, &path, output, token).await?;
+        }
+        sizes = seen;
+    }
+    
+    println!("👋 Stopped watching {}", dir.display());
+    Ok(())
+}
+
+/// Analyzes one file from the hot folder, streaming it so its size does
+/// not matter, and files it under processed/ or failed/. Only errors in
+/// the watcher's own folders are returned; a bad CSV is not one.
+async fn process_dropped_file(dir: &Path, path: &Path, output: &Path, token: &CancellationToken) -> Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+    println!("\n📥 New file: {}", name);
+    
+    let mut timer = PerformanceTimer::new(format!("Watch: {}", name));
+    let analysis = async {
+        let file = File::open(path).await?;
+        let mut reader = StreamingReader::new(file, STREAMING_BUFFER_SIZE);
+        let mut records = 0;
+        let mut revenue = 0.0;
+        let mut price_sum = 0.0;
+        let mut products: HashMap<String, (f64, u32)> = HashMap::new();
+        while let Some(batch) = reader.next_batch().await? {
+            if token.is_cancelled() {
+                break;
+            }
+            for record in batch {
+                let sales = record.price * record.quantity as f64;
+                revenue += sales;
+                price_sum += record.price;
+                let entry = products.entry(record.product).or_insert((0.0, 0));
+                entry.0 += sales;
+                entry.1 += record.quantity;
+                records += 1;
+            }
+            timer.record_progress(records, reader.bytes_read() as u64);
+        }
+        timer.add_bytes(reader.bytes_read());
+        
+        let mut top_products: Vec<_> = products.into_iter().collect();
+        top_products.sort_by(|a, b| b.1 .0.total_cmp(&a.1 .0));
+        Ok::<_, anyhow::Error>((
+            records,
+            serde_json::json!({
+                "file": name,
+                "total_records": records,
+                "total_revenue": revenue,
+                "average_price": if records > 0 { price_sum / records as f64 } else { 0.0 },
+                "top_products": top_products
+                    .iter()
+                    .take(10)
+                    .map(|(product, (total_sales, quantity_sold))| serde_json::json!({
+                        "product": product,
+                        "total_sales": total_sales,
+                        "quantity_sold": quantity_sold
+                    }))
+                    .collect::<Vec<_>>()
+            }),
+        ))
+    }
+    .await;
+    
+    // Cancelled mid-file: leave it in place to be processed on the next run
+    if token.is_cancelled() {
+        timer.cancelled(0);
+        return Ok(());
+    }
+    
+    let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    match analysis {
+        Ok((records, mut result)) => {
+            let metrics = timer.finish(records);
+            result["metrics"] = serde_json::to_value(&metrics)?;
+            let result_path = output.join(format!("{}.analysis.json", stem));
+            tokio::fs::write(&result_path, serde_json::to_vec_pretty(&result)?).await?;
+            append_metrics(output, &metrics).await?;
+            tokio::fs::rename(path, dir.join("processed").join(format!("{}-{}", stamp, name))).await?;
+            println!("✅ {}: {} records, result in {}", name, records, result_path.display());
+        }
+        Err(error) => {
+            let metrics = timer.cancelled(0);
+            let error_path = output.join(format!("{}.error.json", stem));
+            let report = serde_json::json!({ "file": name, "error": error.to_string() });
+            tokio::fs::write(&error_path, serde_json::to_vec_pretty(&report)?).await?;
+            append_metrics(output, &metrics).await?;
+            tokio::fs::rename(path, dir.join("failed").join(format!("{}-{}", stamp, name))).await?;
+            println!("❌ {}: {}; moved to failed/", name, error);
+        }
+    }
+    Ok(())
+}
+
+/// Appends one line of metrics JSON to `output/metrics.jsonl`.
+async fn append_metrics(output: &Path, metrics: &performance_utils::PerformanceMetrics) -> Result<()> {
+    use tokio::io::AsyncWriteExt;
+    
+    let mut line = serde_json::to_vec(metrics)?;
+    line.push(b'\n');
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(output.join("metrics.jsonl"))
+        .await?;
+    file.write_all(&line).await?;
+    Ok(())
+}
+
+async fn async_file_sync_csv(file_path: &str, token: &CancellationToken) -> Result<()> {
+    let mut timer = PerformanceTimer::new(format!("Async File + Sync CSV: {}", file_path));
     
     // Read entire file asynchronously
     let mut file = File::open(file_path).await?;
     let mut contents = String::new();
     file.read_to_string(&mut contents).await?;
+    timer.add_bytes(contents.len());
     
     // Parse CSV synchronously
     let mut reader = ReaderBuilder::new().from_reader(contents.as_bytes());
     let mut records = Vec::new();
+    let mut cancelled = false;
     
     for result in reader.deserialize() {
+        if token.is_cancelled() {
+            cancelled = true;
+            break;
+        }
         let record: SalesRecord = result?;
         records.push(record);
     }
     
-    timer.finish(records.len());
+    if cancelled {
+        timer.cancelled(records.len());
+    } else {
+        timer.finish(records.len());
+    }
     Ok(())
 }
 
-async fn streaming_async_csv(file_path: &str) -> Result<()> {
-    let timer = PerformanceTimer::new(format!("Streaming Async CSV: {}", file_path));
+async fn read_to_end_async_csv(file_path: &str, token: &CancellationToken) -> Result<()> {
+    let mut timer = PerformanceTimer::new(format!("Read-to-End Async CSV: {}", file_path));
     
     let file = File::open(file_path).await?;
     let reader = BufReader::new(file);
     
-    // Read in chunks to simulate streaming
+    // Buffers the whole file before parsing; see streaming_async_csv for
+    // the incremental version
     let mut buffer = Vec::new();
     let mut buf_reader = reader;
     buf_reader.read_to_end(&mut buffer).await?;
+    timer.add_bytes(buffer.len());
     
     // Process the buffer
     let mut csv_reader = ReaderBuilder::new().from_reader(&buffer[..]);
     let mut record_count = 0;
+    let mut cancelled = false;
     
     for result in csv_reader.deserialize() {
+        if token.is_cancelled() {
+            cancelled = true;
+            break;
+        }
         let _record: SalesRecord = result?;
         record_count += 1;
         
@@ -93,39 +337,80 @@ async fn streaming_async_csv(file_path: &str) -> Result<()> {
         }
     }
     
-    timer.finish(record_count);
+    if cancelled {
+        timer.cancelled(record_count);
+    } else {
+        timer.finish(record_count);
+    }
     Ok(())
 }
 
-async fn concurrent_chunk_processing(file_path: &str) -> Result<()> {
-    let timer = PerformanceTimer::new(format!("Concurrent Chunk Processing: {}", file_path));
+async fn streaming_async_csv(file_path: &str, token: &CancellationToken) -> Result<()> {
+    let mut timer = PerformanceTimer::new(format!("Streaming Async CSV: {}", file_path));
+    
+    // Only one 64 KiB buffer and the records parsed from it are in memory
+    let file = File::open(file_path).await?;
+    let mut reader = StreamingReader::new(file, STREAMING_BUFFER_SIZE);
+    let mut record_count = 0;
+    let mut cancelled = false;
+    
+    while let Some(records) = reader.next_batch().await? {
+        if token.is_cancelled() {
+            cancelled = true;
+            break;
+        }
+        record_count += records.len();
+        timer.record_progress(record_count, reader.bytes_read() as u64);
+    }
+    timer.add_bytes(reader.bytes_read());
+    
+    if cancelled {
+        timer.cancelled(record_count);
+    } else {
+        timer.finish(record_count);
+    }
+    Ok(())
+}
+
+async fn concurrent_chunk_processing(file_path: &str, concurrency: usize, token: &CancellationToken) -> Result<()> {
+    let mut timer = PerformanceTimer::new(format!("Concurrent Chunk Processing: {}", file_path));
+    timer.set_concurrency(concurrency);
     
     // Read file
     let mut file = File::open(file_path).await?;
     let mut contents = String::new();
     file.read_to_string(&mut contents).await?;
+    timer.add_bytes(contents.len());
     
     // Split into chunks for concurrent processing
-    let lines: Vec<&str> = contents.lines().collect();
-    let header = lines[0];
-    let data_lines = &lines[1..];
+    let lines: Vec<&str> = processing::split_rows(&contents);
+    let Some((header, data_lines)) = lines.split_first() else {
+        println!("   Empty file, nothing to process");
+        timer.finish(0);
+        return Ok(());
+    };
     
-    let chunk_size = 10000.max(data_lines.len() / 4); // At least 4 chunks
+    let chunk_size = processing::chunk_size_for(data_lines.len(), concurrency); // One chunk per task
     let chunks: Vec<_> = data_lines.chunks(chunk_size).collect();
     
-    println!("   Processing {} chunks of ~{} records each", chunks.len(), chunk_size);
+    println!("   Processing {} chunks of ~{} records each (concurrency {})", chunks.len(), chunk_size, concurrency);
     
     // Process chunks concurrently
     let mut tasks = Vec::new();
     
     for (i, chunk) in chunks.iter().enumerate() {
         let chunk_data = format!("{}\n{}", header, chunk.join("\n"));
+        let token = token.child_token();
         
         let task = tokio::spawn(async move {
             let mut reader = ReaderBuilder::new().from_reader(chunk_data.as_bytes());
             let mut count = 0;
             
             for result in reader.deserialize() {
+                if token.is_cancelled() {
+                    println!("     Chunk {} cancelled after {} records", i + 1, count);
+                    return count;
+                }
                 let _record: SalesRecord = result.unwrap();
                 count += 1;
             }
@@ -143,7 +428,11 @@ async fn concurrent_chunk_processing(file_path: &str) -> Result<()> {
         total_records += task.await?;
     }
     
-    timer.finish(total_records);
+    if token.is_cancelled() {
+        timer.cancelled(total_records);
+    } else {
+        timer.finish(total_records);
+    }
     Ok(())
 }
 
@@ -155,7 +444,7 @@ async fn generate_sample_data_if_needed() -> Result<()> {
         
         // Generate small dataset for demo
         let output = Command::new("cargo")
-            .args(&["run", "--bin", "generate_data", "--", "--size", "small"])
+            .args(["run", "--bin", "generate_data", "--", "--size", "small"])
             .output()?;
             
         if !output.status.success() {
@@ -0,0 +1,362 @@
+#[cfg(feature = "columnar")]
+use crate::columnar::ColumnarRecords;
+use crate::column_stats::{month_of, ColumnStatistics, GroupSummary};
+use crate::performance_utils::SalesRecord;
+use serde::Serialize;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::sync::Arc;
+
+/// A metric computed in one pass over a file's records. The registry makes
+/// a fresh one with [`Aggregator::init`] for every run, feeds it each record
+/// with [`Aggregator::update`], and returns what [`Aggregator::finalize`]
+/// produces as the metric's JSON value.
+pub trait Aggregator: Send {
+    fn init() -> Self
+    where
+        Self: Sized;
+
+    fn update(&mut self, record: &SalesRecord);
+
+    fn finalize(self: Box<Self>) -> serde_json::Value;
+
+    /// The metric straight from a file's column statistics, for aggregators
+    /// that can compute it from them; `None` makes the registry read the
+    /// records instead.
+    fn answer_from_statistics(&self, _statistics: &ColumnStatistics) -> Option<serde_json::Value> {
+        None
+    }
+
+    /// The metric computed over a file's columnar cache, for aggregators
+    /// that can; `None` makes the registry turn the columns back into
+    /// records instead.
+    #[cfg(feature = "columnar")]
+    fn answer_from_columns(&self, _columns: &ColumnarRecords) -> Option<serde_json::Value> {
+        None
+    }
+}
+
+type Factory = Arc<dyn Fn() -> Box<dyn Aggregator> + Send + Sync>;
+
+#[derive(Clone)]
+struct Registered {
+    description: String,
+    factory: Factory,
+}
+
+/// A registered aggregation, as listed by GET /aggregate.
+#[derive(Debug, Clone, Serialize)]
+pub struct AggregatorInfo {
+    pub name: String,
+    pub description: String,
+}
+
+/// The aggregations the server offers on /aggregate, by name.
+#[derive(Clone, Default)]
+pub struct Registry {
+    aggregators: BTreeMap<String, Registered>,
+}
+
+/// The results of [`Registry::run`], by aggregation name.
+#[derive(Debug, Default, Serialize)]
+pub struct AggregateRun {
+    pub results: BTreeMap<String, serde_json::Value>,
+    /// Records fed to the aggregators before the run stopped.
+    pub records: usize,
+    pub cancelled: bool,
+}
+
+impl Registry {
+    /// A registry with the built-in aggregations.
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::default();
+        registry
+            .register::<RevenueByRegion>("revenue_by_region", "Total revenue (price × quantity) per region")
+            .register::<MedianBasketByRegion>("median_basket_by_region", "Median quantity per order in each region")
+            .register::<OrdersByMonth>("orders_by_month", "Orders and revenue per month of the order date");
+        registry
+    }
+
+    /// Registers `A` under `name`, replacing any aggregation of that name,
+    /// built-ins included.
+    pub fn register<A: Aggregator + 'static>(&mut self, name: &str, description: &str) -> &mut Self {
+        self.register_with(name, description, || Box::new(A::init()))
+    }
+
+    /// Registers an aggregation made by `factory`, for aggregators that
+    /// need configuring, e.g. a percentile other than the median.
+    pub fn register_with(
+        &mut self,
+        name: &str,
+        description: &str,
+        factory: impl Fn() -> Box<dyn Aggregator> + Send + Sync + 'static,
+    ) -> &mut Self {
+        let registered = Registered { description: description.to_string(), factory: Arc::new(factory) };
+        self.aggregators.insert(name.to_string(), registered);
+        self
+    }
+
+    /// Removes the aggregation called `name`, returning whether there was one.
+    pub fn unregister(&mut self, name: &str) -> bool {
+        self.aggregators.remove(name).is_some()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.aggregators.contains_key(name)
+    }
+
+    /// Registered aggregations, by name.
+    pub fn list(&self) -> Vec<AggregatorInfo> {
+        self.aggregators
+            .iter()
+            .map(|(name, registered)| AggregatorInfo { name: name.clone(), description: registered.description.clone() })
+            .collect()
+    }
+
+    /// Runs the aggregations in `names` over `records` in a single pass.
+    /// Names must be registered; check them with [`Registry::contains`].
+    /// Stops early, finalizing what was seen, once `cancelled` returns true.
+    pub fn run(&self, names: &[String], records: &[SalesRecord], cancelled: impl Fn() -> bool) -> AggregateRun {
+        let mut aggregators: Vec<_> = names
+            .iter()
+            .filter_map(|name| Some((name.clone(), (self.aggregators.get(name)?.factory)())))
+            .collect();
+        let mut run = AggregateRun::default();
+        for record in records {
+            if cancelled() {
+                run.cancelled = true;
+                break;
+            }
+            for (_, aggregator) in aggregators.iter_mut() {
+                aggregator.update(record);
+            }
+            run.records += 1;
+        }
+        run.results = aggregators.into_iter().map(|(name, aggregator)| (name, aggregator.finalize())).collect();
+        run
+    }
+
+    /// Runs the aggregations in `names` from column statistics, or returns
+    /// `None` if any of them needs the records.
+    pub fn run_from_statistics(&self, names: &[String], statistics: &ColumnStatistics) -> Option<AggregateRun> {
+        let results = names
+            .iter()
+            .map(|name| Some((name.clone(), (self.aggregators.get(name)?.factory)().answer_from_statistics(statistics)?)))
+            .collect::<Option<_>>()?;
+        Some(AggregateRun { results, records: statistics.rows, cancelled: false })
+    }
+
+    /// Runs the aggregations in `names` over columnar records, or returns
+    /// `None` if any of them needs the records as rows.
+    #[cfg(feature = "columnar")]
+    pub fn run_from_columns(&self, names: &[String], columns: &ColumnarRecords) -> Option<AggregateRun> {
+        let results = names
+            .iter()
+            .map(|name| Some((name.clone(), (self.aggregators.get(name)?.factory)().answer_from_columns(columns)?)))
+            .collect::<Option<_>>()?;
+        Some(AggregateRun { results, records: columns.rows(), cancelled: false })
+    }
+}
+
+/// The `k` items with the largest `key`, largest first, selected through a
+/// heap of at most `k` items rather than by sorting them all: a file with
+/// many thousands of products or customers costs a pass over them, not a
+/// sort. Keys are compared with `total_cmp`, so NaN totals (from NaN
+/// prices) rank above every number instead of panicking. Ties come in no
+/// particular order.
+pub fn top_k<T>(items: impl IntoIterator<Item = T>, k: usize, key: impl Fn(&T) -> f64) -> Vec<T> {
+    let items = items.into_iter();
+    if k == 0 {
+        return Vec::new();
+    }
+    if k >= items.size_hint().0 {
+        let mut all: Vec<T> = items.collect();
+        all.sort_by(|a, b| key(b).total_cmp(&key(a)));
+        all.truncate(k);
+        return all;
+    }
+
+    // A min-heap of the best so far, its weakest on top to be displaced
+    let mut heap = BinaryHeap::with_capacity(k + 1);
+    for item in items {
+        let ranked = Ranked { key: key(&item), item };
+        if heap.len() < k {
+            heap.push(ranked);
+        } else if let Some(mut weakest) = heap.peek_mut() {
+            if ranked.key.total_cmp(&weakest.key).is_gt() {
+                *weakest = ranked;
+            }
+        }
+    }
+    heap.into_sorted_vec().into_iter().map(|ranked| ranked.item).collect()
+}
+
+/// An item of [`top_k`], ordered by its key reversed, so `BinaryHeap`'s
+/// greatest is the smallest key and `into_sorted_vec` runs largest first.
+struct Ranked<T> {
+    key: f64,
+    item: T,
+}
+
+impl<T> PartialEq for Ranked<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl<T> Eq for Ranked<T> {}
+
+impl<T> PartialOrd for Ranked<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Ranked<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        other.key.total_cmp(&self.key)
+    }
+}
+
+struct RevenueByRegion {
+    revenue: HashMap<String, f64>,
+}
+
+impl Aggregator for RevenueByRegion {
+    fn init() -> Self {
+        Self { revenue: HashMap::new() }
+    }
+
+    fn update(&mut self, record: &SalesRecord) {
+        *self.revenue.entry(record.region.clone()).or_default() += record.price * record.quantity as f64;
+    }
+
+    fn finalize(self: Box<Self>) -> serde_json::Value {
+        let revenue: BTreeMap<_, _> = self.revenue.into_iter().collect();
+        serde_json::json!(revenue)
+    }
+
+    fn answer_from_statistics(&self, statistics: &ColumnStatistics) -> Option<serde_json::Value> {
+        Some(Self::from_groups(statistics.groups("region")?))
+    }
+
+    #[cfg(feature = "columnar")]
+    fn answer_from_columns(&self, columns: &ColumnarRecords) -> Option<serde_json::Value> {
+        Some(Self::from_groups(&columns.group_by("region", false)?))
+    }
+}
+
+impl RevenueByRegion {
+    fn from_groups(regions: &BTreeMap<String, GroupSummary>) -> serde_json::Value {
+        let revenue: BTreeMap<_, _> = regions.iter().map(|(region, group)| (region, group.revenue)).collect();
+        serde_json::json!(revenue)
+    }
+}
+
+struct MedianBasketByRegion {
+    quantities: HashMap<String, Vec<u32>>,
+}
+
+impl Aggregator for MedianBasketByRegion {
+    fn init() -> Self {
+        Self { quantities: HashMap::new() }
+    }
+
+    fn update(&mut self, record: &SalesRecord) {
+        self.quantities.entry(record.region.clone()).or_default().push(record.quantity);
+    }
+
+    fn finalize(self: Box<Self>) -> serde_json::Value {
+        let medians: BTreeMap<_, _> = self
+            .quantities
+            .into_iter()
+            .map(|(region, mut quantities)| {
+                quantities.sort_unstable();
+                let middle = quantities.len() / 2;
+                let median = if quantities.len() % 2 == 0 {
+                    (quantities[middle - 1] as f64 + quantities[middle] as f64) / 2.0
+                } else {
+                    quantities[middle] as f64
+                };
+                (region, median)
+            })
+            .collect();
+        serde_json::json!(medians)
+    }
+
+    fn answer_from_statistics(&self, statistics: &ColumnStatistics) -> Option<serde_json::Value> {
+        Self::from_groups(statistics.groups("region")?)
+    }
+
+    #[cfg(feature = "columnar")]
+    fn answer_from_columns(&self, columns: &ColumnarRecords) -> Option<serde_json::Value> {
+        Self::from_groups(&columns.group_by("region", true)?)
+    }
+}
+
+impl MedianBasketByRegion {
+    /// `None` when a region's quantities were not kept.
+    fn from_groups(regions: &BTreeMap<String, GroupSummary>) -> Option<serde_json::Value> {
+        let mut medians = BTreeMap::new();
+        for (region, group) in regions {
+            let quantities = group.quantities.as_ref()?;
+            // The quantity at a sorted position, counting through the histogram
+            let at = |position: u64| {
+                let mut seen = 0;
+                quantities.iter().find_map(|(&quantity, &count)| {
+                    seen += count;
+                    (seen > position).then_some(quantity as f64)
+                })
+            };
+            let middle = group.rows / 2;
+            let median = if group.rows % 2 == 0 { (at(middle - 1)? + at(middle)?) / 2.0 } else { at(middle)? };
+            medians.insert(region, median);
+        }
+        Some(serde_json::json!(medians))
+    }
+}
+
+/// Keyed by the `YYYY-MM` prefix of the date; dates without one count
+/// under `unknown`.
+struct OrdersByMonth {
+    months: BTreeMap<String, (u64, f64)>,
+}
+
+impl Aggregator for OrdersByMonth {
+    fn init() -> Self {
+        Self { months: BTreeMap::new() }
+    }
+
+    fn update(&mut self, record: &SalesRecord) {
+        let entry = self.months.entry(month_of(&record.date).to_string()).or_default();
+        entry.0 += 1;
+        entry.1 += record.price * record.quantity as f64;
+    }
+
+    fn finalize(self: Box<Self>) -> serde_json::Value {
+        let months: BTreeMap<_, _> = self
+            .months
+            .into_iter()
+            .map(|(month, (orders, revenue))| (month, serde_json::json!({ "orders": orders, "revenue": revenue })))
+            .collect();
+        serde_json::json!(months)
+    }
+
+    fn answer_from_statistics(&self, statistics: &ColumnStatistics) -> Option<serde_json::Value> {
+        Some(Self::from_groups(statistics.groups("month")?))
+    }
+
+    #[cfg(feature = "columnar")]
+    fn answer_from_columns(&self, columns: &ColumnarRecords) -> Option<serde_json::Value> {
+        Some(Self::from_groups(&columns.group_by("month", false)?))
+    }
+}
+
+impl OrdersByMonth {
+    fn from_groups(months: &BTreeMap<String, GroupSummary>) -> serde_json::Value {
+        let months: BTreeMap<_, _> = months
+            .iter()
+            .map(|(month, group)| (month, serde_json::json!({ "orders": group.rows, "revenue": group.revenue })))
+            .collect();
+        serde_json::json!(months)
+    }
+}
@@ -0,0 +1,250 @@
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+/// Alerts kept for GET /alerts, oldest dropped first.
+const RECENT_ALERTS: usize = 100;
+
+/// How long a webhook may take to answer.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What a rule watches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    /// Invalid rows as a percentage of all rows of the file.
+    ErrorRate,
+    /// Time to read and parse the file.
+    DurationMs,
+    /// Percentage fall in revenue since the previous parse of the same file.
+    RevenueDrop,
+    /// Any error that stopped the file from being processed.
+    Failure,
+}
+
+/// One rule, written `error_rate>1%`, `duration_ms>500`, `revenue_drop>20%`
+/// or `failure`.
+#[derive(Debug, Clone)]
+pub struct AlertRule {
+    metric: Metric,
+    threshold: f64,
+    rule: String,
+}
+
+impl AlertRule {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        if text == "failure" {
+            return Ok(Self { metric: Metric::Failure, threshold: 0.0, rule: text.to_string() });
+        }
+        let (name, threshold) = text
+            .split_once('>')
+            .ok_or_else(|| format!("'{}' is not of the form metric>threshold, or failure", text))?;
+        let metric = match name.trim() {
+            "error_rate" => Metric::ErrorRate,
+            "duration_ms" => Metric::DurationMs,
+            "revenue_drop" => Metric::RevenueDrop,
+            other => return Err(format!("unknown metric '{}' (error_rate, duration_ms, revenue_drop or failure)", other)),
+        };
+        let threshold = threshold.trim();
+        let threshold = match metric {
+            Metric::ErrorRate | Metric::RevenueDrop => threshold.strip_suffix('%').unwrap_or(threshold),
+            _ => threshold,
+        };
+        let threshold = threshold
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|threshold| threshold.is_finite() && *threshold >= 0.0)
+            .ok_or_else(|| format!("invalid threshold in '{}'", text))?;
+        Ok(Self { metric, threshold, rule: text.to_string() })
+    }
+
+    /// The rule as written.
+    pub fn as_str(&self) -> &str {
+        &self.rule
+    }
+
+    /// Rules separated by commas; an empty list is allowed.
+    pub fn parse_list(text: &str) -> Result<Vec<Self>, String> {
+        text.split(',').filter(|rule| !rule.trim().is_empty()).map(Self::parse).collect()
+    }
+}
+
+/// How a processing job went, for the rules to judge.
+#[derive(Debug, Clone, Default)]
+pub struct JobOutcome {
+    pub file: String,
+    /// Jobs of the same series are compared for `revenue_drop`, e.g. all
+    /// uploads under one name.
+    pub series: String,
+    pub rows: usize,
+    pub invalid_rows: usize,
+    pub duration_ms: f64,
+    /// Total revenue of the file; `None` when it could not be parsed.
+    pub revenue: Option<f64>,
+    pub error: Option<String>,
+}
+
+/// A broken rule.
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub rule: String,
+    pub file: String,
+    /// The measured value, in the rule's unit.
+    pub value: f64,
+    pub threshold: f64,
+    pub message: String,
+    pub triggered_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Alert rules and the state they need between jobs.
+#[derive(Debug, Clone, Default)]
+pub struct AlertMonitor {
+    rules: Vec<AlertRule>,
+    notifier: Notifier,
+    /// Revenue of the last successful parse of each series.
+    previous_revenue: HashMap<String, f64>,
+    recent: VecDeque<Alert>,
+}
+
+impl AlertMonitor {
+    /// Reads the rules from `ALERT_RULES` and the destinations from
+    /// `ALERT_WEBHOOK_URL` and `ALERT_SLACK_WEBHOOK_URL`. Invalid rules are
+    /// reported and leave the server without alerts rather than stopping it.
+    pub fn from_env() -> Self {
+        let rules = match AlertRule::parse_list(&std::env::var("ALERT_RULES").unwrap_or_default()) {
+            Ok(rules) => rules,
+            Err(error) => {
+                println!("⚠️  Ignoring ALERT_RULES: {}", error);
+                Vec::new()
+            }
+        };
+        Self { rules, notifier: Notifier::from_env(), ..Self::default() }
+    }
+
+    pub fn rules(&self) -> &[AlertRule] {
+        &self.rules
+    }
+
+    pub fn notifier(&self) -> &Notifier {
+        &self.notifier
+    }
+
+    /// Alerts raised so far, oldest first.
+    pub fn recent(&self) -> &VecDeque<Alert> {
+        &self.recent
+    }
+
+    /// Checks every rule against `outcome` and returns the broken ones,
+    /// which are also kept for [`AlertMonitor::recent`].
+    pub fn evaluate(&mut self, outcome: &JobOutcome) -> Vec<Alert> {
+        let previous = match outcome.revenue {
+            Some(revenue) => self.previous_revenue.insert(outcome.series.clone(), revenue),
+            None => None,
+        };
+        let mut alerts = Vec::new();
+        for rule in &self.rules {
+            let (value, message) = match rule.metric {
+                Metric::Failure => match &outcome.error {
+                    Some(error) => (1.0, format!("processing {} failed: {}", outcome.file, error)),
+                    None => continue,
+                },
+                Metric::ErrorRate if outcome.rows > 0 => {
+                    let rate = outcome.invalid_rows as f64 / outcome.rows as f64 * 100.0;
+                    let message = format!(
+                        "{:.2}% of the rows of {} are invalid ({} of {})",
+                        rate, outcome.file, outcome.invalid_rows, outcome.rows
+                    );
+                    (rate, message)
+                }
+                Metric::DurationMs if outcome.error.is_none() => {
+                    (outcome.duration_ms, format!("processing {} took {:.1} ms", outcome.file, outcome.duration_ms))
+                }
+                Metric::RevenueDrop => match (previous, outcome.revenue) {
+                    (Some(previous), Some(revenue)) if previous > 0.0 => {
+                        let drop = (previous - revenue) / previous * 100.0;
+                        let message = format!(
+                            "revenue of {} fell {:.2}% since the previous run ({:.2} to {:.2})",
+                            outcome.series, drop, previous, revenue
+                        );
+                        (drop, message)
+                    }
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            if value <= rule.threshold {
+                continue;
+            }
+            alerts.push(Alert {
+                rule: rule.rule.clone(),
+                file: outcome.file.clone(),
+                value,
+                threshold: rule.threshold,
+                message,
+                triggered_at: chrono::Utc::now(),
+            });
+        }
+
+        for alert in &alerts {
+            if self.recent.len() == RECENT_ALERTS {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(alert.clone());
+        }
+        alerts
+    }
+}
+
+/// Where alerts are sent: a generic webhook receiving the [`Alert`] as
+/// JSON, and a Slack incoming webhook receiving its message.
+#[derive(Debug, Clone, Default)]
+pub struct Notifier {
+    client: reqwest::Client,
+    webhook_url: Option<String>,
+    slack_url: Option<String>,
+}
+
+impl Notifier {
+    pub fn from_env() -> Self {
+        let client = reqwest::Client::builder().timeout(WEBHOOK_TIMEOUT).build().unwrap_or_default();
+        Self {
+            client,
+            webhook_url: std::env::var("ALERT_WEBHOOK_URL").ok().filter(|url| !url.is_empty()),
+            slack_url: std::env::var("ALERT_SLACK_WEBHOOK_URL").ok().filter(|url| !url.is_empty()),
+        }
+    }
+
+    /// Names of the configured destinations.
+    pub fn destinations(&self) -> Vec<&'static str> {
+        let mut destinations = Vec::new();
+        if self.webhook_url.is_some() {
+            destinations.push("webhook");
+        }
+        if self.slack_url.is_some() {
+            destinations.push("slack");
+        }
+        destinations
+    }
+
+    /// Sends `alert` to every destination, returning the failures.
+    pub async fn send(&self, alert: &Alert) -> Vec<String> {
+        let mut failures = Vec::new();
+        let slack_message = serde_json::json!({ "text": format!("🚨 {} ({})", alert.message, alert.rule) });
+        let deliveries = [
+            ("webhook", &self.webhook_url, serde_json::json!(alert)),
+            ("slack", &self.slack_url, slack_message),
+        ];
+        // Failures name the destination only; webhook URLs embed secrets
+        for (destination, url, body) in deliveries {
+            let Some(url) = url else {
+                continue;
+            };
+            let result = self.client.post(url).json(&body).send().await.and_then(|response| response.error_for_status());
+            if let Err(error) = result {
+                failures.push(format!("{}: {}", destination, error.without_url()));
+            }
+        }
+        failures
+    }
+}
@@ -0,0 +1,186 @@
+use crate::encryption::{ColumnCipher, DecryptError};
+use csv::{ReaderBuilder, StringRecord, WriterBuilder};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+/// Hex digits of the HMAC kept in a pseudonym: 48 bits, so distinct values
+/// practically never share one.
+const PSEUDONYM_DIGITS: usize = 12;
+
+/// How a column is masked. Empty fields stay empty whatever the strategy.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    /// SHA-256 of the value, in hex. Equal values stay equal, but anyone
+    /// can hash a guess, so it only suits values that are hard to guess.
+    Hash,
+    /// `REDACTED`.
+    Redact,
+    /// `<column>_<hex>` from an HMAC-SHA256 of the value under a secret
+    /// key: equal values stay equal, e.g. for counting orders per customer,
+    /// and without the key nobody can tell which value a pseudonym stands for.
+    Pseudonymize,
+    /// The range of width `n` holding a number, e.g. `500-600` for 523.5
+    /// with `{"bucket": 100}`.
+    Bucket(f64),
+}
+
+/// Why a masking plan does not fit a file.
+#[derive(Debug)]
+pub enum PlanError {
+    UnknownColumn(String),
+    InvalidBucket { column: String, width: f64 },
+    /// Pseudonymizing `column` needs a key, and none was given.
+    MissingKey(String),
+}
+
+impl std::fmt::Display for PlanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlanError::UnknownColumn(column) => write!(f, "the file has no column {}", column),
+            PlanError::InvalidBucket { column, width } => {
+                write!(f, "the bucket width for {} must be a positive number, not {}", column, width)
+            }
+            PlanError::MissingKey(column) => write!(f, "pseudonymizing {} needs a key", column),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AnonymizeError {
+    Plan(PlanError),
+    Csv(csv::Error),
+    /// A value a strategy cannot mask, e.g. text in a bucketed column.
+    Value { line: u64, column: String, value: String },
+    /// A stored field that does not decrypt.
+    Decrypt { line: u64, column: String, error: DecryptError },
+}
+
+impl From<csv::Error> for AnonymizeError {
+    fn from(error: csv::Error) -> Self {
+        AnonymizeError::Csv(error)
+    }
+}
+
+/// The strategies for a file's columns, by column position.
+pub struct Anonymizer {
+    columns: Vec<(usize, String, Strategy)>,
+    key: Option<Hmac<Sha256>>,
+}
+
+impl Anonymizer {
+    /// Matches `strategies` to the columns of `headers`. `key` is the HMAC
+    /// key for [`Strategy::Pseudonymize`].
+    pub fn plan(headers: &StringRecord, strategies: &BTreeMap<String, Strategy>, key: Option<&[u8]>) -> Result<Self, PlanError> {
+        let mut columns = Vec::new();
+        for (column, &strategy) in strategies {
+            let index = headers
+                .iter()
+                .position(|header| header.trim() == column)
+                .ok_or_else(|| PlanError::UnknownColumn(column.clone()))?;
+            match strategy {
+                Strategy::Bucket(width) if !(width.is_finite() && width > 0.0) => {
+                    return Err(PlanError::InvalidBucket { column: column.clone(), width });
+                }
+                Strategy::Pseudonymize if key.is_none() => return Err(PlanError::MissingKey(column.clone())),
+                _ => {}
+            }
+            columns.push((index, column.clone(), strategy));
+        }
+        // HMAC takes keys of any length, so this cannot fail
+        let key = key.map(|key| Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length"));
+        Ok(Self { columns, key })
+    }
+
+    /// `row` with the planned columns masked. On failure returns the column
+    /// and value that could not be masked.
+    pub fn mask(&self, row: &StringRecord) -> Result<StringRecord, (String, String)> {
+        let mut fields: Vec<String> = row.iter().map(String::from).collect();
+        for (index, column, strategy) in &self.columns {
+            let Some(field) = fields.get_mut(*index) else {
+                continue;
+            };
+            if field.trim().is_empty() {
+                continue;
+            }
+            *field = match strategy {
+                Strategy::Hash => hex(&Sha256::digest(field.as_bytes())),
+                Strategy::Redact => "REDACTED".to_string(),
+                Strategy::Pseudonymize => {
+                    let mut mac = self.key.clone().expect("planned with a key");
+                    mac.update(field.as_bytes());
+                    let digest = hex(&mac.finalize().into_bytes());
+                    format!("{}_{}", column, &digest[..PSEUDONYM_DIGITS])
+                }
+                Strategy::Bucket(width) => {
+                    let value: f64 = field.trim().parse().map_err(|_| (column.clone(), field.clone()))?;
+                    let low = (value / width).floor() * width;
+                    format!("{}-{}", low, low + width)
+                }
+            };
+        }
+        Ok(StringRecord::from(fields))
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Copies the CSV in `input` to `output` with the columns in `strategies`
+/// masked and every other column as it is, returning the rows written.
+/// With `cipher`, the columns it encrypts are decrypted first, so equal
+/// values hash alike. Blocking; run it on the parser pool. Stops with
+/// `Ok(None)` once `cancelled` returns true, leaving `output` incomplete.
+pub fn anonymize(
+    input: impl Read,
+    output: impl Write,
+    strategies: &BTreeMap<String, Strategy>,
+    key: Option<&[u8]>,
+    cipher: Option<&ColumnCipher>,
+    cancelled: impl Fn() -> bool,
+) -> Result<Option<u64>, AnonymizeError> {
+    let mut reader = ReaderBuilder::new().from_reader(input);
+    let headers = reader.headers()?.clone();
+    let encrypted: Vec<(usize, &str)> = match cipher {
+        Some(cipher) => headers.iter().enumerate().filter(|(_, header)| cipher.encrypts(header.trim())).collect(),
+        None => Vec::new(),
+    };
+    let anonymizer = Anonymizer::plan(&headers, strategies, key).map_err(AnonymizeError::Plan)?;
+    let mut writer = WriterBuilder::new().from_writer(output);
+    writer.write_record(&headers)?;
+
+    let mut rows = 0;
+    for row in reader.records() {
+        if cancelled() {
+            return Ok(None);
+        }
+        let mut row = row?;
+        if let (Some(cipher), false) = (cipher, encrypted.is_empty()) {
+            let line = row.position().map_or(0, |position| position.line());
+            let mut fields: Vec<String> = row.iter().map(String::from).collect();
+            for &(index, column) in &encrypted {
+                if let Some(field) = fields.get_mut(index) {
+                    *field = cipher
+                        .decrypt(column, field)
+                        .map_err(|error| AnonymizeError::Decrypt { line, column: column.to_string(), error })?;
+                }
+            }
+            let position = row.position().cloned();
+            row = StringRecord::from(fields);
+            row.set_position(position);
+        }
+        let masked = anonymizer.mask(&row).map_err(|(column, value)| AnonymizeError::Value {
+            line: row.position().map_or(0, |position| position.line()),
+            column,
+            value,
+        })?;
+        writer.write_record(&masked)?;
+        rows += 1;
+    }
+    writer.flush().map_err(csv::Error::from)?;
+    Ok(Some(rows))
+}
@@ -0,0 +1,95 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Why a download token was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenError {
+    /// Not a token this server issues, or one whose signature does not
+    /// match: altered, or signed with another key.
+    Invalid,
+    Expired(DateTime<Utc>),
+}
+
+impl std::fmt::Display for TokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenError::Invalid => write!(f, "the download link is not valid"),
+            TokenError::Expired(at) => write!(f, "the download link expired at {}", at.to_rfc3339()),
+        }
+    }
+}
+
+/// Signs and checks download tokens for files in the artifacts directory.
+/// A token is `<deadline>-<signature>-<name>`: the Unix second it expires
+/// at, the hex HMAC-SHA256 of the name and deadline, and the file name. It
+/// grants that one file until the deadline and nothing else, so links can
+/// be handed out without exposing other paths.
+#[derive(Clone)]
+pub struct ArtifactSigner {
+    key: Hmac<Sha256>,
+}
+
+impl ArtifactSigner {
+    pub fn new(key: &[u8]) -> Self {
+        // HMAC takes keys of any length, so this cannot fail
+        Self { key: Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length") }
+    }
+
+    /// Whether `name` can be an artifact: a plain file name, not hidden.
+    pub fn is_valid_name(name: &str) -> bool {
+        !name.is_empty()
+            && !name.starts_with('.')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+    }
+
+    pub fn sign(&self, name: &str, expires_at: DateTime<Utc>) -> String {
+        let deadline = expires_at.timestamp();
+        format!("{}-{}-{}", deadline, hex(&self.mac(name, deadline)), name)
+    }
+
+    /// The artifact name `token` grants as of `now`.
+    pub fn verify(&self, token: &str, now: DateTime<Utc>) -> Result<String, TokenError> {
+        let (deadline, rest) = token.split_once('-').ok_or(TokenError::Invalid)?;
+        let (signature, name) = rest.split_once('-').ok_or(TokenError::Invalid)?;
+        let deadline: i64 = deadline.parse().map_err(|_| TokenError::Invalid)?;
+        let signature = unhex(signature).ok_or(TokenError::Invalid)?;
+        if !Self::is_valid_name(name) {
+            return Err(TokenError::Invalid);
+        }
+        let mut mac = self.key.clone();
+        mac.update(&message(name, deadline));
+        // Compared in constant time
+        mac.verify_slice(&signature).map_err(|_| TokenError::Invalid)?;
+        let expires_at = DateTime::from_timestamp(deadline, 0).ok_or(TokenError::Invalid)?;
+        if now >= expires_at {
+            return Err(TokenError::Expired(expires_at));
+        }
+        Ok(name.to_string())
+    }
+
+    fn mac(&self, name: &str, deadline: i64) -> Vec<u8> {
+        let mut mac = self.key.clone();
+        mac.update(&message(name, deadline));
+        mac.finalize().into_bytes().to_vec()
+    }
+}
+
+/// What is signed: the name and deadline, separated by a byte no name has.
+fn message(name: &str, deadline: i64) -> Vec<u8> {
+    let mut message = name.as_bytes().to_vec();
+    message.push(b'\n');
+    message.extend_from_slice(deadline.to_string().as_bytes());
+    message
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|at| u8::from_str_radix(&text[at..at + 2], 16).ok()).collect()
+}
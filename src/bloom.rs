@@ -0,0 +1,162 @@
+use crate::performance_utils::SalesRecord;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// Chance a filter answers "maybe" for a value the file does not hold,
+/// while it holds no more values than it was sized for.
+pub const FALSE_POSITIVE_RATE: f64 = 0.01;
+
+/// Columns [`KeyFilters`] keep a filter of.
+pub const KEY_COLUMNS: [&str; 2] = ["id", "customer_name"];
+
+/// A Bloom filter: "no" means the value was never inserted, "maybe" means
+/// it was, or that every bit it hashes to was set by others.
+#[derive(Debug, Clone)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+    /// Values the filter was sized for at [`FALSE_POSITIVE_RATE`].
+    capacity: usize,
+    inserted: usize,
+}
+
+/// The size and state of a [`BloomFilter`], for listing.
+#[derive(Debug, Clone, Serialize)]
+pub struct BloomInfo {
+    pub bits: usize,
+    pub hashes: u32,
+    pub capacity: usize,
+    pub inserted: usize,
+    /// The false positive rate to expect with what was inserted.
+    pub false_positive_rate: f64,
+}
+
+impl BloomFilter {
+    /// A filter for `capacity` values with about `false_positive_rate`
+    /// false positives once full: `-n ln p / ln² 2` bits and
+    /// `bits / n · ln 2` hashes.
+    pub fn new(capacity: usize, false_positive_rate: f64) -> Self {
+        let capacity = capacity.max(1);
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-(capacity as f64) * false_positive_rate.ln() / (ln2 * ln2)).ceil().max(64.0) as usize;
+        let hashes = ((bits as f64 / capacity as f64) * ln2).round().clamp(1.0, 16.0) as u32;
+        Self { bits: vec![0; bits.div_ceil(64)], hashes, capacity, inserted: 0 }
+    }
+
+    pub fn insert<T: Hash + ?Sized>(&mut self, value: &T) {
+        for position in self.positions(value) {
+            self.bits[position / 64] |= 1 << (position % 64);
+        }
+        self.inserted += 1;
+    }
+
+    pub fn might_contain<T: Hash + ?Sized>(&self, value: &T) -> bool {
+        self.positions(value).all(|position| self.bits[position / 64] & (1 << (position % 64)) != 0)
+    }
+
+    /// Whether more values went in than the filter was sized for, so it
+    /// answers "maybe" more often than [`FALSE_POSITIVE_RATE`].
+    pub fn saturated(&self) -> bool {
+        self.inserted > self.capacity
+    }
+
+    pub fn info(&self) -> BloomInfo {
+        let bits = self.bits.len() * 64;
+        let set: u32 = self.bits.iter().map(|word| word.count_ones()).sum();
+        BloomInfo {
+            bits,
+            hashes: self.hashes,
+            capacity: self.capacity,
+            inserted: self.inserted,
+            false_positive_rate: (set as f64 / bits as f64).powi(self.hashes as i32),
+        }
+    }
+
+    /// The bits `value` sets, by double hashing (Kirsch and Mitzenmacher,
+    /// "Less Hashing, Same Performance"): `h1 + i·h2` for each hash `i`.
+    fn positions<T: Hash + ?Sized>(&self, value: &T) -> impl Iterator<Item = usize> {
+        let mut halves = [DefaultHasher::new(), DefaultHasher::new()];
+        halves[1].write_u8(0x5a);
+        for half in &mut halves {
+            value.hash(half);
+        }
+        let (first, second) = (halves[0].finish(), halves[1].finish() | 1);
+        let bits = (self.bits.len() * 64) as u64;
+        (0..self.hashes as u64).map(move |index| (first.wrapping_add(index.wrapping_mul(second)) % bits) as usize)
+    }
+}
+
+/// A value of one of the [`KEY_COLUMNS`] to look for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyValue<'a> {
+    Id(u32),
+    /// Compared exactly, case included.
+    CustomerName(&'a str),
+}
+
+impl<'a> KeyValue<'a> {
+    pub fn parse(column: &str, value: &'a str) -> Result<Self, String> {
+        match column {
+            "id" => value.trim().parse().map(Self::Id).map_err(|_| format!("id must be a whole number, not {}", value)),
+            "customer_name" => Ok(Self::CustomerName(value)),
+            _ => Err(format!("unknown key column {}, expected one of {}", column, KEY_COLUMNS.join(", "))),
+        }
+    }
+
+    pub fn matches(&self, record: &SalesRecord) -> bool {
+        match *self {
+            Self::Id(id) => record.id == id,
+            Self::CustomerName(name) => record.customer_name == name,
+        }
+    }
+}
+
+/// Bloom filters over the key columns of one file's records, so a lookup
+/// skips the files that cannot hold a value without reading them.
+#[derive(Debug, Clone)]
+pub struct KeyFilters {
+    id: BloomFilter,
+    customer_name: BloomFilter,
+}
+
+impl KeyFilters {
+    /// Filters of `records`, sized for the file to double by appends
+    /// before they are [`saturated`](Self::saturated).
+    pub fn build(records: &[SalesRecord]) -> Self {
+        let capacity = records.len() * 2;
+        let mut filters = Self {
+            id: BloomFilter::new(capacity, FALSE_POSITIVE_RATE),
+            customer_name: BloomFilter::new(capacity, FALSE_POSITIVE_RATE),
+        };
+        filters.extend(records);
+        filters
+    }
+
+    /// Adds appended `records`.
+    pub fn extend(&mut self, records: &[SalesRecord]) {
+        for record in records {
+            self.id.insert(&record.id);
+            self.customer_name.insert(record.customer_name.as_str());
+        }
+    }
+
+    /// Whether the file may hold a record with `value`: `false` is certain,
+    /// `true` has to be confirmed against the records.
+    pub fn might_contain(&self, value: &KeyValue) -> bool {
+        match *value {
+            KeyValue::Id(id) => self.id.might_contain(&id),
+            KeyValue::CustomerName(name) => self.customer_name.might_contain(name),
+        }
+    }
+
+    /// Whether either filter took more rows than it was sized for; it is
+    /// better rebuilt than grown further.
+    pub fn saturated(&self) -> bool {
+        self.id.saturated() || self.customer_name.saturated()
+    }
+
+    pub fn info(&self) -> BTreeMap<&'static str, BloomInfo> {
+        BTreeMap::from([("id", self.id.info()), ("customer_name", self.customer_name.info())])
+    }
+}
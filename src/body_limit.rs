@@ -0,0 +1,61 @@
+use axum::body::{Body, Bytes};
+use futures::{Stream, StreamExt};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The error a [`limited`] body fails with once more than `limit` bytes
+/// arrived. Extractors report it wrapped in their own errors; find it with
+/// [`exceeded`].
+#[derive(Debug, Clone, Copy)]
+pub struct LimitExceeded {
+    pub limit: u64,
+    pub received: u64,
+}
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the body is larger than the {}-byte limit ({} bytes received)", self.limit, self.received)
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// `body` as a stream that fails as soon as more than `limit` bytes have
+/// come through, calling `on_exceeded` with the count once. Nothing is
+/// buffered: the reader stops at the error and the rest is never read.
+pub fn limited(body: Body, limit: u64, on_exceeded: impl FnOnce(u64) + Send + 'static) -> Body {
+    Body::from_stream(limit_stream(body.into_data_stream(), limit, on_exceeded))
+}
+
+fn limit_stream<E: Into<BoxError>>(
+    stream: impl Stream<Item = Result<Bytes, E>>,
+    limit: u64,
+    on_exceeded: impl FnOnce(u64),
+) -> impl Stream<Item = Result<Bytes, BoxError>> {
+    let mut received = 0;
+    let mut on_exceeded = Some(on_exceeded);
+    stream.map(move |chunk| {
+        let chunk = chunk.map_err(Into::into)?;
+        received += chunk.len() as u64;
+        if received > limit {
+            if let Some(on_exceeded) = on_exceeded.take() {
+                on_exceeded(received);
+            }
+            return Err(LimitExceeded { limit, received }.into());
+        }
+        Ok(chunk)
+    })
+}
+
+/// The [`LimitExceeded`] behind `error`, however deeply an extractor
+/// wrapped it.
+pub fn exceeded(error: &(dyn std::error::Error + 'static)) -> Option<LimitExceeded> {
+    let mut current = Some(error);
+    while let Some(error) = current {
+        if let Some(exceeded) = error.downcast_ref::<LimitExceeded>() {
+            return Some(*exceeded);
+        }
+        current = error.source();
+    }
+    None
+}
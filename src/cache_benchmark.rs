@@ -0,0 +1,255 @@
+use crate::locale::Locale;
+use crate::performance_utils::{LatencyRecorder, LatencySummary, SystemInfo};
+use crate::report::ReportFormat;
+use serde::Serialize;
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Where /analyze gets what it answers from, slowest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheStrategy {
+    /// Read and parse the file on every request.
+    None,
+    /// Keep the parsed records and aggregate them on every request.
+    Records,
+    /// Keep per-column aggregates, computed once from the records.
+    Statistics,
+}
+
+impl CacheStrategy {
+    pub fn label(self) -> &'static str {
+        match self {
+            CacheStrategy::None => "no cache",
+            CacheStrategy::Records => "record cache",
+            CacheStrategy::Statistics => "statistics cache",
+        }
+    }
+}
+
+/// Latencies of one strategy on one file, gathered by the caller.
+pub struct Measurement {
+    pub strategy: CacheStrategy,
+    /// What building the cache from the file takes, paid once by the first
+    /// request: the parse for records, the parse and one pass over the
+    /// records for statistics.
+    pub fill: Duration,
+    /// What the cache holds on to.
+    pub footprint_bytes: u64,
+    pub runs: Vec<Duration>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StrategyLatency {
+    pub strategy: CacheStrategy,
+    pub latency: LatencySummary,
+    pub fill_ms: f64,
+    /// The records' in-memory size, or the statistics' serialized size,
+    /// which is close to theirs. Zero without a cache.
+    pub footprint_bytes: u64,
+    /// Mean latency without a cache over this strategy's.
+    pub speedup: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FileResult {
+    pub file: String,
+    pub size_bytes: u64,
+    pub records: usize,
+    pub strategies: Vec<StrategyLatency>,
+    /// Strategies that cannot serve this file, and why.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unavailable: Vec<(CacheStrategy, String)>,
+}
+
+impl FileResult {
+    pub fn new(file: String, size_bytes: u64, records: usize, measurements: Vec<Measurement>) -> Self {
+        let mut latencies = LatencyRecorder::new();
+        for measurement in &measurements {
+            for run in &measurement.runs {
+                latencies.record(measurement.strategy.label(), *run);
+            }
+        }
+        let summaries = latencies.summary();
+        let latency_of = |strategy: CacheStrategy| summaries.iter().find(|summary| summary.operation == strategy.label()).cloned();
+        let uncached = latency_of(CacheStrategy::None).map(|latency| latency.mean_ms);
+        let strategies = measurements
+            .iter()
+            .filter_map(|measurement| {
+                let latency = latency_of(measurement.strategy)?;
+                Some(StrategyLatency {
+                    strategy: measurement.strategy,
+                    speedup: uncached.map_or(1.0, |uncached| uncached / latency.mean_ms.max(0.001)),
+                    latency,
+                    fill_ms: measurement.fill.as_secs_f64() * 1_000.0,
+                    footprint_bytes: measurement.footprint_bytes,
+                })
+            })
+            .collect();
+        Self { file, size_bytes, records, strategies, unavailable: Vec::new() }
+    }
+
+    fn get(&self, strategy: CacheStrategy) -> Option<&StrategyLatency> {
+        self.strategies.iter().find(|result| result.strategy == strategy)
+    }
+
+    /// What the numbers say about each cache on this file.
+    fn findings(&self) -> Vec<String> {
+        let mut findings = Vec::new();
+        let Some(uncached) = self.get(CacheStrategy::None) else {
+            return findings;
+        };
+        if let Some(records) = self.get(CacheStrategy::Records) {
+            findings.push(format!(
+                "{}: the record cache answers in {:.2} ms instead of {:.2} ms ({:.0}× faster) for {} in memory, {:.1}× the file's size",
+                self.file,
+                records.latency.p50_ms,
+                uncached.latency.p50_ms,
+                records.speedup,
+                bytes(records.footprint_bytes, &Locale::CANONICAL),
+                records.footprint_bytes as f64 / self.size_bytes.max(1) as f64
+            ));
+        }
+        if let (Some(records), Some(statistics)) = (self.get(CacheStrategy::Records), self.get(CacheStrategy::Statistics)) {
+            findings.push(format!(
+                "{}: statistics answer in {:.3} ms, {:.0}× faster than the record cache, from {} ({:.2}% of the records' footprint); computing them from the records takes {:.1} ms, repaid after {} request(s)",
+                self.file,
+                statistics.latency.p50_ms,
+                records.latency.mean_ms / statistics.latency.mean_ms.max(0.001),
+                bytes(statistics.footprint_bytes, &Locale::CANONICAL),
+                statistics.footprint_bytes as f64 / records.footprint_bytes.max(1) as f64 * 100.0,
+                statistics.fill_ms - records.fill_ms,
+                ((statistics.fill_ms - records.fill_ms) / (records.latency.mean_ms - statistics.latency.mean_ms).max(0.001)).ceil()
+            ));
+        }
+        for (strategy, reason) in &self.unavailable {
+            findings.push(format!("{}: no {}, {}", self.file, strategy.label(), reason));
+        }
+        findings
+    }
+}
+
+/// /analyze under each caching strategy, file by file.
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheBenchmark {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub iterations: usize,
+    pub system: SystemInfo,
+    pub files: Vec<FileResult>,
+    pub findings: Vec<String>,
+    pub cancelled: bool,
+}
+
+impl CacheBenchmark {
+    pub fn new(iterations: usize, files: Vec<FileResult>, cancelled: bool) -> Self {
+        let findings = files.iter().flat_map(FileResult::findings).collect();
+        Self { timestamp: chrono::Utc::now(), iterations, system: SystemInfo::collect(), files, findings, cancelled }
+    }
+
+    /// The tables with numbers and dates written for `locale`; findings
+    /// are the sentences of the JSON response.
+    pub fn render(&self, format: ReportFormat, locale: &Locale) -> String {
+        match format {
+            ReportFormat::Markdown => self.render_markdown(locale),
+            ReportFormat::Html => self.render_html(locale),
+        }
+    }
+
+    fn render_markdown(&self, locale: &Locale) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# Caching Strategies for /analyze\n");
+        let _ = writeln!(
+            out,
+            "Generated {} {} · {} runs per strategy · {} ({} cores)\n",
+            locale.date(&self.timestamp),
+            self.timestamp.format("%H:%M UTC"),
+            self.iterations,
+            self.system.cpu_model,
+            self.system.physical_cores
+        );
+        let _ = writeln!(out, "| File | Strategy | p50 ms | p99 ms | Mean ms | Speedup | Fill ms | Footprint |");
+        let _ = writeln!(out, "|---|---|---:|---:|---:|---:|---:|---:|");
+        for file in &self.files {
+            for result in &file.strategies {
+                let _ = writeln!(
+                    out,
+                    "| {} | {} | {} | {} | {} | {}× | {} | {} |",
+                    file.file,
+                    result.strategy.label(),
+                    locale.number(result.latency.p50_ms, 3),
+                    locale.number(result.latency.p99_ms, 3),
+                    locale.number(result.latency.mean_ms, 3),
+                    locale.number(result.speedup, 0),
+                    locale.number(result.fill_ms, 1),
+                    bytes(result.footprint_bytes, locale)
+                );
+            }
+        }
+        let _ = writeln!(out, "\n## Findings\n");
+        for finding in &self.findings {
+            let _ = writeln!(out, "- {}", finding);
+        }
+        out
+    }
+
+    fn render_html(&self, locale: &Locale) -> String {
+        let mut out = String::new();
+        let lang = if locale.is_canonical() { String::new() } else { format!(" lang=\"{}\"", locale.tag) };
+        let _ = writeln!(
+            out,
+            "<!DOCTYPE html>\n<html{}>\n<head>\n<meta charset=\"utf-8\">\n<title>Caching Strategies for /analyze</title>\n\
+             <style>body{{font-family:sans-serif;margin:2em}}table{{border-collapse:collapse}}\
+             td,th{{border:1px solid #ccc;padding:4px 8px;text-align:right}}td:nth-child(-n+2){{text-align:left}}</style>\n</head>\n<body>",
+            lang
+        );
+        let _ = writeln!(out, "<h1>Caching Strategies for /analyze</h1>");
+        let _ = writeln!(
+            out,
+            "<p>Generated {} {} · {} runs per strategy · {} ({} cores)</p>",
+            locale.date(&self.timestamp),
+            self.timestamp.format("%H:%M UTC"),
+            self.iterations,
+            escape(&self.system.cpu_model),
+            self.system.physical_cores
+        );
+        let _ = writeln!(
+            out,
+            "<table>\n<tr><th>File</th><th>Strategy</th><th>p50 ms</th><th>p99 ms</th><th>Mean ms</th><th>Speedup</th><th>Fill ms</th><th>Footprint</th></tr>"
+        );
+        for file in &self.files {
+            for result in &file.strategies {
+                let _ = writeln!(
+                    out,
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}×</td><td>{}</td><td>{}</td></tr>",
+                    escape(&file.file),
+                    result.strategy.label(),
+                    locale.number(result.latency.p50_ms, 3),
+                    locale.number(result.latency.p99_ms, 3),
+                    locale.number(result.latency.mean_ms, 3),
+                    locale.number(result.speedup, 0),
+                    locale.number(result.fill_ms, 1),
+                    bytes(result.footprint_bytes, locale)
+                );
+            }
+        }
+        let _ = writeln!(out, "</table>\n<h2>Findings</h2>\n<ul>");
+        for finding in &self.findings {
+            let _ = writeln!(out, "<li>{}</li>", escape(finding));
+        }
+        let _ = writeln!(out, "</ul>\n</body>\n</html>");
+        out
+    }
+}
+
+fn bytes(count: u64, locale: &Locale) -> String {
+    match count {
+        0 => "–".to_string(),
+        count if count >= 1_000_000 => format!("{} MB", locale.number(count as f64 / 1_000_000.0, 1)),
+        count if count >= 1_000 => format!("{} KB", locale.number(count as f64 / 1_000.0, 1)),
+        count => format!("{} B", count),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
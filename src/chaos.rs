@@ -0,0 +1,248 @@
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+
+/// Which faults to inject and how often, each rate a probability from 0
+/// to 1. All zero injects nothing.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChaosConfig {
+    /// Share of requests delayed by `latency_min_ms` to `latency_max_ms`
+    /// before they are handled.
+    pub latency_rate: f64,
+    pub latency_min_ms: u64,
+    pub latency_max_ms: u64,
+    /// Share of requests answered with 503 without being handled.
+    pub http_error_rate: f64,
+    /// Share of file reads that fail part way with a transient I/O error.
+    pub io_error_rate: f64,
+    /// Share of file reads that end early, as if the file were shorter.
+    pub truncate_rate: f64,
+    /// Seed of the decisions: the same seed and the same sequence of
+    /// requests and reads inject the same faults.
+    pub seed: u64,
+}
+
+impl ChaosConfig {
+    /// Parses comma-separated `fault=rate` pairs, e.g.
+    /// `latency=0.2:50-500,http_error=0.01,io_error=0.05,truncate=0.02,seed=7`,
+    /// where the latency range is in milliseconds (one number for a fixed delay).
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut config = Self::default();
+        for pair in spec.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').ok_or_else(|| format!("'{}' is not fault=rate", pair))?;
+            let (name, value) = (name.trim(), value.trim());
+            match name {
+                "latency" => {
+                    let (rate, range) = value
+                        .split_once(':')
+                        .ok_or_else(|| format!("latency '{}' is not rate:min-max milliseconds", value))?;
+                    config.latency_rate = rate_of(name, rate)?;
+                    let (min, max) = range.trim().trim_end_matches("ms").split_once('-').unwrap_or((range, range));
+                    let milliseconds = |text: &str| {
+                        let text = text.trim().trim_end_matches("ms");
+                        text.parse::<u64>().map_err(|_| format!("latency '{}' is not a whole number of milliseconds", text))
+                    };
+                    (config.latency_min_ms, config.latency_max_ms) = (milliseconds(min)?, milliseconds(max)?);
+                }
+                "http_error" => config.http_error_rate = rate_of(name, value)?,
+                "io_error" => config.io_error_rate = rate_of(name, value)?,
+                "truncate" => config.truncate_rate = rate_of(name, value)?,
+                "seed" => config.seed = value.parse().map_err(|_| format!("seed '{}' is not a whole number", value))?,
+                _ => return Err(format!("unknown fault '{}'; use latency, http_error, io_error, truncate or seed", name)),
+            }
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, rate) in [
+            ("latency_rate", self.latency_rate),
+            ("http_error_rate", self.http_error_rate),
+            ("io_error_rate", self.io_error_rate),
+            ("truncate_rate", self.truncate_rate),
+        ] {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(format!("{} {} is not between 0 and 1", name, rate));
+            }
+        }
+        if self.latency_min_ms > self.latency_max_ms {
+            return Err(format!("latency {}-{} ms runs backwards", self.latency_min_ms, self.latency_max_ms));
+        }
+        Ok(())
+    }
+
+    pub fn is_off(&self) -> bool {
+        self.latency_rate == 0.0 && self.http_error_rate == 0.0 && self.io_error_rate == 0.0 && self.truncate_rate == 0.0
+    }
+}
+
+fn rate_of(name: &str, text: &str) -> Result<f64, String> {
+    text.trim().parse().map_err(|_| format!("{} rate '{}' is not a number", name, text.trim()))
+}
+
+/// How many faults of each kind were injected since the last configuration.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ChaosStats {
+    pub delayed: u64,
+    pub http_errors: u64,
+    pub io_errors: u64,
+    pub truncated: u64,
+}
+
+/// Injects the faults of a [`ChaosConfig`], deciding each one with a
+/// random number generator seeded from it. Shared between requests, and
+/// reconfigured in place.
+#[derive(Debug)]
+pub struct Chaos {
+    config: RwLock<ChaosConfig>,
+    rng: Mutex<ChaCha8Rng>,
+    delayed: AtomicU64,
+    http_errors: AtomicU64,
+    io_errors: AtomicU64,
+    truncated: AtomicU64,
+}
+
+impl Chaos {
+    pub fn new(config: ChaosConfig) -> Self {
+        Self {
+            rng: Mutex::new(ChaCha8Rng::seed_from_u64(config.seed)),
+            config: RwLock::new(config),
+            delayed: AtomicU64::new(0),
+            http_errors: AtomicU64::new(0),
+            io_errors: AtomicU64::new(0),
+            truncated: AtomicU64::new(0),
+        }
+    }
+
+    pub fn config(&self) -> ChaosConfig {
+        self.config.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+
+    /// Replaces the configuration, reseeds the decisions and zeroes the
+    /// counts, so a test starts from a known point.
+    pub fn configure(&self, config: ChaosConfig) {
+        *self.rng.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = ChaCha8Rng::seed_from_u64(config.seed);
+        *self.config.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = config;
+        for count in [&self.delayed, &self.http_errors, &self.io_errors, &self.truncated] {
+            count.store(0, Ordering::Relaxed);
+        }
+    }
+
+    pub fn stats(&self) -> ChaosStats {
+        ChaosStats {
+            delayed: self.delayed.load(Ordering::Relaxed),
+            http_errors: self.http_errors.load(Ordering::Relaxed),
+            io_errors: self.io_errors.load(Ordering::Relaxed),
+            truncated: self.truncated.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Whether an event of probability `rate` happens. A rate of 0 never
+    /// draws, so turning one fault off leaves the others' sequence alone.
+    fn happens(&self, rate: f64) -> bool {
+        rate > 0.0 && self.rng.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).gen_bool(rate.min(1.0))
+    }
+
+    /// The delay to hold a request for, if it is to be delayed.
+    pub fn delay(&self) -> Option<Duration> {
+        let config = self.config();
+        if !self.happens(config.latency_rate) {
+            return None;
+        }
+        self.delayed.fetch_add(1, Ordering::Relaxed);
+        let milliseconds = self
+            .rng
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .gen_range(config.latency_min_ms..=config.latency_max_ms);
+        Some(Duration::from_millis(milliseconds))
+    }
+
+    /// Whether a request is to be answered with an error instead.
+    pub fn http_error(&self) -> bool {
+        let failed = self.happens(self.config().http_error_rate);
+        if failed {
+            self.http_errors.fetch_add(1, Ordering::Relaxed);
+        }
+        failed
+    }
+
+    /// Wraps `inner`, a reader of `length` bytes. Whether the read fails
+    /// or ends early, and after how many bytes, is decided here.
+    pub fn reader<R>(&self, inner: R, length: u64) -> ChaosReader<R> {
+        let config = self.config();
+        let fault = if self.happens(config.io_error_rate) {
+            self.io_errors.fetch_add(1, Ordering::Relaxed);
+            Some(Fault::Error)
+        } else if self.happens(config.truncate_rate) {
+            self.truncated.fetch_add(1, Ordering::Relaxed);
+            Some(Fault::Truncate)
+        } else {
+            None
+        };
+        let fault = fault.map(|fault| {
+            let at = self.rng.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).gen_range(0..length.max(1));
+            (at, fault)
+        });
+        ChaosReader { inner, read: 0, fault }
+    }
+
+    /// Reads the file at `path` into a string through [`Chaos::reader`].
+    pub async fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        let file = tokio::fs::File::open(path).await?;
+        let length = file.metadata().await?.len();
+        let mut content = String::with_capacity(length as usize);
+        self.reader(file, length).read_to_string(&mut content).await?;
+        Ok(content)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Fault {
+    /// Fails with a transient error, one a retry may get past.
+    Error,
+    /// Reports the end of the file.
+    Truncate,
+}
+
+/// A reader that fails, or ends, once a set number of bytes has been read.
+#[derive(Debug)]
+pub struct ChaosReader<R> {
+    inner: R,
+    read: u64,
+    fault: Option<(u64, Fault)>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ChaosReader<R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let Some((at, fault)) = self.fault else {
+            return Pin::new(&mut self.inner).poll_read(cx, buf);
+        };
+        if self.read >= at {
+            return Poll::Ready(match fault {
+                Fault::Error => Err(io::Error::new(io::ErrorKind::TimedOut, format!("chaos: injected I/O error after {} bytes", at))),
+                Fault::Truncate => Ok(()),
+            });
+        }
+        // Reads no further than the fault, so it strikes at its byte
+        let allowed = (at - self.read).min(buf.remaining() as u64) as usize;
+        let mut limited = ReadBuf::new(buf.initialize_unfilled_to(allowed));
+        let polled = Pin::new(&mut self.inner).poll_read(cx, &mut limited);
+        let filled = limited.filled().len();
+        if let Poll::Ready(Ok(())) = polled {
+            buf.advance(filled);
+            self.read += filled as u64;
+        }
+        polled
+    }
+}
@@ -0,0 +1,186 @@
+use crate::performance_utils::SalesRecord;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Distinct values a column may have and still be grouped by. Past this,
+/// e.g. customer names, the column's group map is dropped.
+pub const MAX_GROUP_VALUES: usize = 1_000;
+
+/// Pre-aggregates of a file's records, computed in one pass after the
+/// file is first parsed, from which /analyze and the built-in aggregations
+/// answer without touching the records again.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ColumnStatistics {
+    pub rows: usize,
+    /// `id`, `quantity`, `price` and the derived `revenue` (price × quantity).
+    pub numeric: BTreeMap<String, NumericSummary>,
+    /// Low-cardinality columns, including the derived `month` of the date,
+    /// by value.
+    pub groups: BTreeMap<String, BTreeMap<String, GroupSummary>>,
+    /// Columns with more than [`MAX_GROUP_VALUES`] distinct values.
+    pub high_cardinality: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct NumericSummary {
+    pub count: u64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+/// The rows sharing one value of a grouped column.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GroupSummary {
+    pub rows: u64,
+    pub quantity: u64,
+    pub price_sum: f64,
+    pub revenue: f64,
+    /// Rows per quantity, for medians; `None` past [`MAX_GROUP_VALUES`]
+    /// distinct quantities.
+    pub quantities: Option<BTreeMap<u32, u64>>,
+}
+
+/// The `YYYY-MM` prefix of a date, or `unknown` for dates without one.
+pub fn month_of(date: &str) -> &str {
+    match date.get(..7) {
+        Some(month) if month.as_bytes().get(4) == Some(&b'-') => month,
+        _ => "unknown",
+    }
+}
+
+impl ColumnStatistics {
+    pub fn compute(records: &[SalesRecord]) -> Self {
+        let mut numeric: BTreeMap<&'static str, NumericSummary> = BTreeMap::new();
+        let mut groups: BTreeMap<&'static str, Option<BTreeMap<String, GroupSummary>>> = BTreeMap::new();
+        for record in records {
+            let revenue = record.price * record.quantity as f64;
+            for (column, value) in [
+                ("id", record.id as f64),
+                ("quantity", record.quantity as f64),
+                ("price", record.price),
+                ("revenue", revenue),
+            ] {
+                numeric
+                    .entry(column)
+                    .and_modify(|summary| summary.add(value))
+                    .or_insert(NumericSummary { count: 1, sum: value, min: value, max: value });
+            }
+            for (column, value) in [
+                ("customer_name", record.customer_name.as_str()),
+                ("product", record.product.as_str()),
+                ("date", record.date.as_str()),
+                ("region", record.region.as_str()),
+                ("month", month_of(&record.date)),
+            ] {
+                let Some(values) = groups.entry(column).or_insert_with(|| Some(BTreeMap::new())) else {
+                    continue;
+                };
+                if !values.contains_key(value) && values.len() == MAX_GROUP_VALUES {
+                    groups.insert(column, None);
+                    continue;
+                }
+                values.entry(value.to_string()).or_default().add(record, revenue);
+            }
+        }
+
+        let mut statistics = Self { rows: records.len(), ..Self::default() };
+        statistics.numeric = numeric.into_iter().map(|(column, summary)| (column.to_string(), summary)).collect();
+        for (column, values) in groups {
+            match values {
+                Some(values) => {
+                    statistics.groups.insert(column.to_string(), values);
+                }
+                None => statistics.high_cardinality.push(column.to_string()),
+            }
+        }
+        statistics
+    }
+
+    /// The groups of `column`, unless it has too many values to keep.
+    pub fn groups(&self, column: &str) -> Option<&BTreeMap<String, GroupSummary>> {
+        self.groups.get(column)
+    }
+
+    /// Adds the statistics of more rows, e.g. ones appended to the file,
+    /// as if both had been computed in one pass. A column with too many
+    /// values in either, or in both together, is dropped from the groups.
+    pub fn merge(&mut self, other: ColumnStatistics) {
+        self.rows += other.rows;
+        for (column, summary) in other.numeric {
+            self.numeric
+                .entry(column)
+                .and_modify(|merged| merged.merge(&summary))
+                .or_insert(summary);
+        }
+        for column in other.high_cardinality {
+            self.groups.remove(&column);
+            if !self.high_cardinality.contains(&column) {
+                self.high_cardinality.push(column);
+            }
+        }
+        for (column, values) in other.groups {
+            if self.high_cardinality.contains(&column) {
+                continue;
+            }
+            let merged = self.groups.entry(column.clone()).or_default();
+            for (value, group) in values {
+                merged.entry(value).or_default().merge(group);
+            }
+            if merged.len() > MAX_GROUP_VALUES {
+                self.groups.remove(&column);
+                self.high_cardinality.push(column);
+            }
+        }
+    }
+}
+
+impl NumericSummary {
+    fn add(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    fn merge(&mut self, other: &NumericSummary) {
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+}
+
+impl GroupSummary {
+    fn add(&mut self, record: &SalesRecord, revenue: f64) {
+        self.rows += 1;
+        self.quantity += record.quantity as u64;
+        self.price_sum += record.price;
+        self.revenue += revenue;
+        let quantities = self.quantities.get_or_insert_with(BTreeMap::new);
+        if quantities.contains_key(&record.quantity) || quantities.len() < MAX_GROUP_VALUES {
+            *quantities.entry(record.quantity).or_default() += 1;
+        } else {
+            self.quantities = None;
+        }
+    }
+
+    fn merge(&mut self, other: GroupSummary) {
+        // A group new to the merge has no histogram yet, and takes the other's
+        let fresh = self.rows == 0;
+        self.rows += other.rows;
+        self.quantity += other.quantity;
+        self.price_sum += other.price_sum;
+        self.revenue += other.revenue;
+        self.quantities = match (self.quantities.take(), other.quantities) {
+            (None, quantities) if fresh => quantities,
+            (Some(mut merged), Some(quantities)) => {
+                for (quantity, rows) in quantities {
+                    *merged.entry(quantity).or_default() += rows;
+                }
+                Some(merged).filter(|merged| merged.len() <= MAX_GROUP_VALUES)
+            }
+            _ => None,
+        };
+    }
+}
@@ -0,0 +1,190 @@
+use crate::column_stats::{month_of, GroupSummary};
+use crate::performance_utils::SalesRecord;
+use arrow_array::builder::{Float64Builder, StringBuilder, StringDictionaryBuilder, UInt32Builder};
+use arrow_array::cast::AsArray;
+use arrow_array::types::{Float64Type, Int32Type, UInt32Type};
+use arrow_array::{Array, ArrayRef, RecordBatch};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+/// Rows per record batch. Large enough that per-batch work disappears in
+/// the per-row work, small enough that a batch's columns stay in cache.
+pub const BATCH_ROWS: usize = 64 * 1024;
+
+/// The columns [`ColumnarRecords::group_by`] takes: the dictionary-encoded
+/// ones, and the `month` of the date.
+pub const GROUP_COLUMNS: [&str; 4] = ["product", "date", "region", "month"];
+
+const ID: usize = 0;
+const CUSTOMER_NAME: usize = 1;
+const PRODUCT: usize = 2;
+const QUANTITY: usize = 3;
+const PRICE: usize = 4;
+const DATE: usize = 5;
+const REGION: usize = 6;
+
+/// A file's records as Arrow record batches, column by column. Numbers sit
+/// in contiguous buffers; product, date and region are dictionary-encoded,
+/// so each distinct value is stored once and rows hold a 4-byte key.
+/// Customer names, nearly all distinct, stay plain strings.
+#[derive(Debug, Clone)]
+pub struct ColumnarRecords {
+    batches: Vec<RecordBatch>,
+    rows: usize,
+}
+
+impl ColumnarRecords {
+    pub fn schema() -> SchemaRef {
+        let dictionary = DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8));
+        Arc::new(Schema::new(vec![
+            Field::new("id", DataType::UInt32, false),
+            Field::new("customer_name", DataType::Utf8, false),
+            Field::new("product", dictionary.clone(), false),
+            Field::new("quantity", DataType::UInt32, false),
+            Field::new("price", DataType::Float64, false),
+            Field::new("date", dictionary.clone(), false),
+            Field::new("region", dictionary, false),
+        ]))
+    }
+
+    pub fn from_records(records: &[SalesRecord]) -> Result<Self, ArrowError> {
+        let schema = Self::schema();
+        let batches = records
+            .chunks(BATCH_ROWS)
+            .map(|chunk| {
+                let mut ids = UInt32Builder::with_capacity(chunk.len());
+                let mut customers = StringBuilder::new();
+                let mut products = StringDictionaryBuilder::<Int32Type>::new();
+                let mut quantities = UInt32Builder::with_capacity(chunk.len());
+                let mut prices = Float64Builder::with_capacity(chunk.len());
+                let mut dates = StringDictionaryBuilder::<Int32Type>::new();
+                let mut regions = StringDictionaryBuilder::<Int32Type>::new();
+                for record in chunk {
+                    ids.append_value(record.id);
+                    customers.append_value(&record.customer_name);
+                    products.append_value(&record.product);
+                    quantities.append_value(record.quantity);
+                    prices.append_value(record.price);
+                    dates.append_value(&record.date);
+                    regions.append_value(&record.region);
+                }
+                let columns: Vec<ArrayRef> = vec![
+                    Arc::new(ids.finish()),
+                    Arc::new(customers.finish()),
+                    Arc::new(products.finish()),
+                    Arc::new(quantities.finish()),
+                    Arc::new(prices.finish()),
+                    Arc::new(dates.finish()),
+                    Arc::new(regions.finish()),
+                ];
+                RecordBatch::try_new(schema.clone(), columns)
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { batches, rows: records.len() })
+    }
+
+    /// Adds `records` after the existing rows, in batches of their own.
+    pub fn append(&mut self, records: &[SalesRecord]) -> Result<(), ArrowError> {
+        let appended = Self::from_records(records)?;
+        self.batches.extend(appended.batches);
+        self.rows += appended.rows;
+        Ok(())
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn batches(&self) -> &[RecordBatch] {
+        &self.batches
+    }
+
+    /// Bytes the batches' buffers take, dictionaries included.
+    pub fn memory_bytes(&self) -> usize {
+        self.batches.iter().map(RecordBatch::get_array_memory_size).sum()
+    }
+
+    /// The records back as rows, for the endpoints that work on them.
+    pub fn to_records(&self) -> Vec<SalesRecord> {
+        let mut records = Vec::with_capacity(self.rows);
+        for batch in &self.batches {
+            let ids = batch.column(ID).as_primitive::<UInt32Type>();
+            let customers = batch.column(CUSTOMER_NAME).as_string::<i32>();
+            let quantities = batch.column(QUANTITY).as_primitive::<UInt32Type>();
+            let prices = batch.column(PRICE).as_primitive::<Float64Type>();
+            let [products, dates, regions] = [PRODUCT, DATE, REGION].map(|column| dictionary_values(batch, column));
+            let [product_keys, date_keys, region_keys] = [PRODUCT, DATE, REGION].map(|column| dictionary_keys(batch, column));
+            for row in 0..batch.num_rows() {
+                records.push(SalesRecord {
+                    id: ids.value(row),
+                    customer_name: customers.value(row).to_string(),
+                    product: products[product_keys[row] as usize].to_string(),
+                    quantity: quantities.value(row),
+                    price: prices.value(row),
+                    date: dates[date_keys[row] as usize].to_string(),
+                    region: regions[region_keys[row] as usize].to_string(),
+                });
+            }
+        }
+        records
+    }
+
+    /// Rows, quantity, price and revenue totals per value of `column`, one
+    /// of [`GROUP_COLUMNS`], or `None` for any other. Rows are summed per
+    /// dictionary key and only the keys' totals are merged by value, so no
+    /// string is touched per row. `quantities` adds each group's histogram
+    /// of quantities, for medians.
+    pub fn group_by(&self, column: &str, quantities: bool) -> Option<BTreeMap<String, GroupSummary>> {
+        let (index, label): (usize, fn(&str) -> &str) = match column {
+            "product" => (PRODUCT, |value| value),
+            "date" => (DATE, |value| value),
+            "region" => (REGION, |value| value),
+            "month" => (DATE, month_of),
+            _ => return None,
+        };
+        let mut groups: BTreeMap<String, GroupSummary> = BTreeMap::new();
+        for batch in &self.batches {
+            let values = dictionary_values(batch, index);
+            let keys = dictionary_keys(batch, index);
+            let batch_quantities = batch.column(QUANTITY).as_primitive::<UInt32Type>().values();
+            let prices = batch.column(PRICE).as_primitive::<Float64Type>().values();
+            let mut totals = vec![GroupSummary::default(); values.len()];
+            let mut histograms = vec![HashMap::<u32, u64>::new(); if quantities { values.len() } else { 0 }];
+            for ((&key, &quantity), &price) in keys.iter().zip(batch_quantities.iter()).zip(prices.iter()) {
+                let group = &mut totals[key as usize];
+                group.rows += 1;
+                group.quantity += quantity as u64;
+                group.price_sum += price;
+                group.revenue += price * quantity as f64;
+                if quantities {
+                    *histograms[key as usize].entry(quantity).or_default() += 1;
+                }
+            }
+            for (key, batch_group) in totals.into_iter().enumerate().filter(|(_, group)| group.rows > 0) {
+                let group = groups.entry(label(values[key]).to_string()).or_default();
+                group.rows += batch_group.rows;
+                group.quantity += batch_group.quantity;
+                group.price_sum += batch_group.price_sum;
+                group.revenue += batch_group.revenue;
+                if let Some(histogram) = histograms.get_mut(key) {
+                    let merged = group.quantities.get_or_insert_with(BTreeMap::new);
+                    for (quantity, count) in histogram.drain() {
+                        *merged.entry(quantity).or_default() += count;
+                    }
+                }
+            }
+        }
+        Some(groups)
+    }
+}
+
+/// The distinct values of a dictionary column, by key.
+fn dictionary_values(batch: &RecordBatch, column: usize) -> Vec<&str> {
+    let values = batch.column(column).as_dictionary::<Int32Type>().values().as_string::<i32>();
+    (0..values.len()).map(|index| values.value(index)).collect()
+}
+
+fn dictionary_keys(batch: &RecordBatch, column: usize) -> &[i32] {
+    batch.column(column).as_dictionary::<Int32Type>().keys().values()
+}
@@ -0,0 +1,132 @@
+use clap::{Arg, ArgAction, Command};
+use std::error::Error;
+
+#[allow(dead_code)]
+mod performance_utils {
+    include!("performance_utils.rs");
+}
+
+use performance_utils::{diff_results, ResultDiff, ResultsDocument, Significance};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let matches = Command::new("Compare Benchmark Runs")
+        .about("Shows per-strategy throughput changes between two benchmark results files")
+        .arg(
+            Arg::new("old")
+                .value_name("OLD")
+                .help("Results before the change (JSON from --output or GET /benchmark/results)")
+                .required(true)
+        )
+        .arg(
+            Arg::new("new")
+                .value_name("NEW")
+                .help("Results after the change")
+                .required(true)
+        )
+        .arg(
+            Arg::new("markdown")
+                .long("markdown")
+                .help("Print a markdown table to paste into a pull request")
+                .action(ArgAction::SetTrue)
+        )
+        .get_matches();
+
+    let old_path = matches.get_one::<String>("old").unwrap();
+    let new_path = matches.get_one::<String>("new").unwrap();
+    let old = ResultsDocument::load(old_path)?;
+    let new = ResultsDocument::load(new_path)?;
+    let (diffs, only_old, only_new) = diff_results(&old, &new);
+
+    if matches.get_flag("markdown") {
+        print_markdown(&old, &new, &diffs);
+    } else {
+        print_console(old_path, &old, new_path, &new, &diffs);
+    }
+    for (entries, side) in [(only_old, old_path), (only_new, new_path)] {
+        for entry in entries {
+            println!("➖ Only in {}: {}", side, label(&entry.strategy, entry.file.as_deref()));
+        }
+    }
+    Ok(())
+}
+
+fn print_console(old_path: &str, old: &ResultsDocument, new_path: &str, new: &ResultsDocument, diffs: &[ResultDiff]) {
+    println!("📊 Comparing benchmark runs");
+    for (side, path, document) in [("old", old_path, old), ("new", new_path, new)] {
+        println!(
+            "   {}: {} ({}, {}, {})",
+            side,
+            path,
+            document.generator,
+            document.generated_at.format("%Y-%m-%d %H:%M"),
+            document.environment.cpu_model
+        );
+    }
+    if old.environment.cpu_model != new.environment.cpu_model {
+        println!("⚠️  The runs used different CPUs, so changes may not come from the code");
+    }
+    println!();
+
+    for diff in diffs {
+        println!(
+            "{} {}: {:.0} → {:.0} records/sec ({:+.1}%, {})",
+            icon(diff),
+            label(&diff.strategy, diff.file.as_deref()),
+            diff.old_records_per_second,
+            diff.new_records_per_second,
+            diff.change_percent,
+            hint(diff.significance)
+        );
+    }
+    let significant = diffs.iter().filter(|diff| matches!(diff.significance, Significance::Significant(_))).count();
+    println!("\n📈 {} of {} strategies changed significantly", significant, diffs.len());
+}
+
+fn print_markdown(old: &ResultsDocument, new: &ResultsDocument, diffs: &[ResultDiff]) {
+    println!(
+        "Benchmark: `{}`, old run {} vs new run {} on {}\n",
+        new.generator,
+        old.generated_at.format("%Y-%m-%d %H:%M"),
+        new.generated_at.format("%Y-%m-%d %H:%M"),
+        new.environment.cpu_model
+    );
+    println!("| | Strategy | File | Old rec/s | New rec/s | Change | Significance |");
+    println!("|---|---|---|---:|---:|---:|---|");
+    for diff in diffs {
+        println!(
+            "| {} | {} | {} | {:.0} | {:.0} | {:+.1}% | {} |",
+            icon(diff),
+            diff.strategy,
+            diff.file.as_deref().unwrap_or("–"),
+            diff.old_records_per_second,
+            diff.new_records_per_second,
+            diff.change_percent,
+            hint(diff.significance)
+        );
+    }
+    println!();
+}
+
+fn label(strategy: &str, file: Option<&str>) -> String {
+    match file {
+        Some(file) => format!("{} — {}", strategy, file),
+        None => strategy.to_string(),
+    }
+}
+
+/// 🚀 faster or 🐢 slower when the change is significant, ≈ otherwise.
+fn icon(diff: &ResultDiff) -> &'static str {
+    match diff.significance {
+        Significance::Significant(_) if diff.change_percent > 0.0 => "🚀",
+        Significance::Significant(_) => "🐢",
+        _ => "≈",
+    }
+}
+
+fn hint(significance: Significance) -> String {
+    match significance {
+        Significance::Significant(t) => format!("significant, t={:.1}", t),
+        Significance::Noise(t) => format!("within noise, t={:.1}", t),
+        Significance::Unknown => "single run, rerun with --iterations 5 to judge".to_string(),
+    }
+}
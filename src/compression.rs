@@ -0,0 +1,273 @@
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Extension of the dictionaries [`Dictionary::save`] writes, named by
+/// their id: `<id>.dict`.
+pub const DICTIONARY_EXTENSION: &str = "dict";
+
+/// How a dictionary is trained and files compressed with it.
+#[derive(Debug, Clone, Copy)]
+pub struct TrainingOptions {
+    /// Most rows sampled across all files, spread evenly over them.
+    pub max_rows: usize,
+    /// Rows per training sample. zstd learns what recurs between samples,
+    /// so each is a small CSV of its own: the header and a few rows.
+    pub rows_per_sample: usize,
+    pub max_dictionary_bytes: usize,
+    /// The zstd level files are compressed at, with and without it.
+    pub level: i32,
+}
+
+impl Default for TrainingOptions {
+    fn default() -> Self {
+        // 110 KiB is what the zstd CLI trains by default
+        Self { max_rows: 100_000, rows_per_sample: 32, max_dictionary_bytes: 112_640, level: zstd::DEFAULT_COMPRESSION_LEVEL }
+    }
+}
+
+/// Rows sampled from CSV files to train a dictionary on.
+#[derive(Debug, Clone, Default)]
+pub struct Samples {
+    pub samples: Vec<Vec<u8>>,
+    pub rows: usize,
+    pub bytes: usize,
+    pub files: usize,
+}
+
+/// Takes every n-th row of `paths` so that about `max_rows` are taken in
+/// all, grouped by file into samples of `rows_per_sample` rows, each with
+/// its file's header. Rows are lines, so a quoted field spanning lines is
+/// sampled in pieces. Blocking.
+pub fn sample_rows(paths: &[PathBuf], max_rows: usize, rows_per_sample: usize) -> io::Result<Samples> {
+    let mut total = 0;
+    for path in paths {
+        let counted = crate::row_count::exact(&path.to_string_lossy(), b',', || false)?;
+        total += counted.map_or(0, |count| count.rows as usize);
+    }
+    let stride = total.div_ceil(max_rows.max(1)).max(1);
+    let mut sampled = Samples::default();
+    for path in paths {
+        let mut lines = BufReader::new(File::open(path)?).split(b'\n');
+        let Some(mut header) = lines.next().transpose()? else {
+            continue;
+        };
+        header.push(b'\n');
+        let (mut sample, mut rows) = (header.clone(), 0);
+        for (index, line) in lines.enumerate() {
+            let line = line?;
+            if index % stride != 0 || line.is_empty() {
+                continue;
+            }
+            sample.extend_from_slice(&line);
+            sample.push(b'\n');
+            rows += 1;
+            if rows == rows_per_sample.max(1) {
+                sampled.push(std::mem::replace(&mut sample, header.clone()), rows);
+                rows = 0;
+            }
+        }
+        if rows > 0 {
+            sampled.push(sample, rows);
+        }
+        sampled.files += 1;
+    }
+    Ok(sampled)
+}
+
+impl Samples {
+    fn push(&mut self, sample: Vec<u8>, rows: usize) {
+        self.rows += rows;
+        self.bytes += sample.len();
+        self.samples.push(sample);
+    }
+}
+
+/// A trained zstd dictionary. zstd writes its id into the header of every
+/// frame compressed with it, so a file names the dictionary it needs.
+#[derive(Debug, Clone)]
+pub struct Dictionary {
+    pub id: u32,
+    pub bytes: Vec<u8>,
+}
+
+impl Dictionary {
+    /// Trains a dictionary of at most `max_bytes` on `samples`. zstd refuses
+    /// too few or too small samples to learn from.
+    pub fn train(samples: &Samples, max_bytes: usize) -> io::Result<Self> {
+        Self::from_bytes(zstd::dict::from_samples(&samples.samples, max_bytes)?)
+    }
+
+    /// A dictionary as [`Dictionary::save`] wrote it; raw content without an
+    /// id is refused, as files compressed with it could not name it.
+    pub fn from_bytes(bytes: Vec<u8>) -> io::Result<Self> {
+        let id = zstd::zstd_safe::get_dict_id(&bytes)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a zstd dictionary"))?;
+        Ok(Self { id: id.get(), bytes })
+    }
+
+    pub fn file_name(&self) -> String {
+        format!("{}.{}", self.id, DICTIONARY_EXTENSION)
+    }
+
+    /// Writes the dictionary to `dir` as [`Dictionary::file_name`], through a
+    /// hidden part file, and returns its path.
+    pub fn save(&self, dir: &Path) -> io::Result<PathBuf> {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(self.file_name());
+        let part = dir.join(format!(".{}.part", self.file_name()));
+        let written = (|| {
+            let mut file = File::create(&part)?;
+            file.write_all(&self.bytes)?;
+            file.sync_all()
+        })();
+        if let Err(error) = written.and_then(|()| std::fs::rename(&part, &path)) {
+            let _ = std::fs::remove_file(&part);
+            return Err(error);
+        }
+        Ok(path)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::from_bytes(std::fs::read(path)?)
+    }
+}
+
+/// The dictionary last saved to `dir`, if any; a missing directory has none.
+pub fn latest(dir: &Path) -> io::Result<Option<Dictionary>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error),
+    };
+    let newest = entries
+        .flatten()
+        .filter(|entry| entry.path().extension().is_some_and(|extension| extension == DICTIONARY_EXTENSION))
+        .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+        .max();
+    newest.map(|(_, path)| Dictionary::load(&path)).transpose()
+}
+
+/// A zstd encoder writing to `writer` at `level`, with `dictionary` if
+/// given. Call `finish` on it to complete the frame.
+pub fn encoder<W: Write>(writer: W, level: i32, dictionary: Option<&Dictionary>) -> io::Result<zstd::Encoder<'static, W>> {
+    let mut encoder = match dictionary {
+        Some(dictionary) => zstd::Encoder::with_dictionary(writer, level, &dictionary.bytes)?,
+        None => zstd::Encoder::new(writer, level)?,
+    };
+    // Lets a reader check the whole file, as gzip does
+    encoder.include_checksum(true)?;
+    Ok(encoder)
+}
+
+/// Compresses `source` into `destination` with [`encoder`] and returns the
+/// size written. Blocking.
+pub fn compress_file(source: &Path, destination: &Path, level: i32, dictionary: Option<&Dictionary>) -> io::Result<u64> {
+    let mut encoder = encoder(BufWriter::new(File::create(destination)?), level, dictionary)?;
+    io::copy(&mut File::open(source)?, &mut encoder)?;
+    let file = encoder.finish()?.into_inner().map_err(|error| error.into_error())?;
+    file.sync_all()?;
+    file.metadata().map(|metadata| metadata.len())
+}
+
+/// The id of the dictionary a zstd frame was compressed with, read from the
+/// start of the frame; `None` for frames compressed without one.
+pub fn frame_dictionary_id(frame: &[u8]) -> Option<u32> {
+    zstd::zstd_safe::get_dict_id_from_frame(frame).map(|id| id.get())
+}
+
+/// Decompresses all of `compressed` with `dictionary`, which must be the one
+/// it was compressed with, if any.
+pub fn decompress(compressed: impl Read, dictionary: Option<&Dictionary>) -> io::Result<Vec<u8>> {
+    let mut decoder = match dictionary {
+        Some(dictionary) => zstd::Decoder::with_dictionary(BufReader::new(compressed), &dictionary.bytes)?,
+        None => zstd::Decoder::with_buffer(BufReader::new(compressed))?,
+    };
+    let mut decompressed = Vec::new();
+    decoder.read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+/// How well one file compresses with zstd at the same level, with and
+/// without a dictionary.
+#[derive(Debug, Clone, Serialize)]
+pub struct CompressionRatio {
+    pub file: String,
+    pub bytes: u64,
+    pub zstd_bytes: u64,
+    pub dictionary_bytes: u64,
+    /// `bytes / zstd_bytes`
+    pub ratio: f64,
+    /// `bytes / dictionary_bytes`
+    pub dictionary_ratio: f64,
+    /// How much smaller the file is with the dictionary than without it.
+    pub improvement_percent: f64,
+}
+
+impl CompressionRatio {
+    fn new(file: String, bytes: u64, zstd_bytes: u64, dictionary_bytes: u64) -> Self {
+        let ratio = |compressed: u64| if compressed == 0 { 0.0 } else { bytes as f64 / compressed as f64 };
+        let improvement_percent = match zstd_bytes {
+            0 => 0.0,
+            _ => (zstd_bytes as f64 - dictionary_bytes as f64) / zstd_bytes as f64 * 100.0,
+        };
+        Self { file, bytes, zstd_bytes, dictionary_bytes, ratio: ratio(zstd_bytes), dictionary_ratio: ratio(dictionary_bytes), improvement_percent }
+    }
+
+    /// The ratios of `files` taken together.
+    pub fn total(files: &[CompressionRatio]) -> Self {
+        let sum = |size: fn(&CompressionRatio) -> u64| files.iter().map(size).sum();
+        Self::new("total".to_string(), sum(|file| file.bytes), sum(|file| file.zstd_bytes), sum(|file| file.dictionary_bytes))
+    }
+}
+
+/// Passes writes through to `inner`, counting and hashing them.
+struct Tally<W> {
+    inner: W,
+    bytes: u64,
+    digest: Sha256,
+}
+
+impl<W> Tally<W> {
+    fn new(inner: W) -> Self {
+        Self { inner, bytes: 0, digest: Sha256::new() }
+    }
+}
+
+impl<W: Write> Write for Tally<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.bytes += written as u64;
+        self.digest.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Compresses `path` at `level` with and without `dictionary` and compares
+/// the sizes, streaming so only zstd's buffers are held, however large the
+/// file. The dictionary's output is decompressed again as it is written and
+/// checked against the file, so a ratio is never reported for a file it
+/// would not restore. Blocking.
+pub fn measure(path: &Path, level: i32, dictionary: &Dictionary) -> io::Result<CompressionRatio> {
+    let mut plain = encoder(Tally::new(io::sink()), level, None)?;
+    io::copy(&mut File::open(path)?, &mut plain)?;
+    let plain = plain.finish()?;
+
+    let restored = zstd::stream::write::Decoder::with_dictionary(Tally::new(io::sink()), &dictionary.bytes)?;
+    let mut source = Tally::new(encoder(Tally::new(restored), level, Some(dictionary))?);
+    io::copy(&mut File::open(path)?, &mut source)?;
+    let compressed = source.inner.finish()?;
+    let mut restored = compressed.inner;
+    restored.flush()?;
+    let restored = restored.into_inner();
+    if restored.bytes != source.bytes || restored.digest.finalize() != source.digest.finalize() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "did not decompress to the original"));
+    }
+    Ok(CompressionRatio::new(path.display().to_string(), source.bytes, plain.bytes, compressed.bytes))
+}
@@ -0,0 +1,175 @@
+use crate::performance_utils::SalesRecord;
+use serde::Serialize;
+use std::future::Future;
+
+/// Bytes a record held as a `csv::StringRecord` takes beyond its text:
+/// the record itself and its field bounds, roughly.
+const STRING_RECORD_OVERHEAD_BYTES: u64 = 96;
+
+tokio::task_local! {
+    /// `Some(force)` while a request runs whose reads are still to be
+    /// checked, `None` once its operation was checked as a whole.
+    static PENDING: Option<bool>;
+}
+
+/// Runs `future` as a request with `?force=` set to `force`: each file it
+/// parses is checked against the limits first.
+pub async fn scope<F: Future>(force: bool, future: F) -> F::Output {
+    PENDING.scope(Some(force), future).await
+}
+
+/// Runs `future`, an operation already checked as a whole, without checking
+/// the files it parses again.
+pub async fn checked<F: Future>(future: F) -> F::Output {
+    PENDING.scope(None, future).await
+}
+
+/// Wraps `future`, a job started by the current request, so its parses are
+/// checked as the request's would be.
+pub fn inherit<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    let pending = pending();
+    async move {
+        match pending {
+            Some(force) => scope(force, future).await,
+            None => future.await,
+        }
+    }
+}
+
+/// Whether a parse is to be checked, and if so whether it is forced. Work
+/// the server starts itself is never checked.
+pub fn pending() -> Option<bool> {
+    PENDING.try_with(|pending| *pending).ok().flatten()
+}
+
+/// What is known of one input before it is read.
+#[derive(Debug, Clone, Serialize)]
+pub struct InputSize {
+    pub name: String,
+    pub rows: u64,
+    pub bytes: u64,
+    /// Where `rows` comes from: `cache`, `statistics`, `catalog`,
+    /// `artifact`, `count`, or `estimate` from the lengths of sampled lines.
+    pub source: &'static str,
+    /// Distinct values of the column the operation keys on, when known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_values: Option<u64>,
+}
+
+/// The estimated cost of an operation, from the sizes of its inputs.
+#[derive(Debug, Clone, Serialize)]
+pub struct CostEstimate {
+    pub operation: &'static str,
+    pub inputs: Vec<InputSize>,
+    /// Rows read and parsed; none for a file already in memory.
+    pub rows_read: u64,
+    pub bytes_read: u64,
+    /// The most held in memory at once.
+    pub memory_bytes: u64,
+    pub output_rows: u64,
+    /// Rows handled, comparisons and lookups: a rough measure of CPU time.
+    pub operations: u64,
+}
+
+impl CostEstimate {
+    /// Parses `input` into records and aggregates them, unless `precomputed`:
+    /// its records or statistics are in memory already.
+    pub fn analyze(input: InputSize, precomputed: bool) -> Self {
+        let (rows_read, bytes_read, memory_bytes) = match precomputed {
+            true => (0, 0, 0),
+            false => (input.rows, input.bytes, input.rows * std::mem::size_of::<SalesRecord>() as u64 + input.bytes),
+        };
+        Self { operation: "analyze", rows_read, bytes_read, memory_bytes, output_rows: 0, operations: rows_read, inputs: vec![input] }
+    }
+
+    /// Reads and parses all of `input` into records.
+    pub fn parse(input: InputSize) -> Self {
+        Self { operation: "parse", ..Self::analyze(input, false) }
+    }
+
+    /// Reads all of `input` into memory and sorts it.
+    pub fn sort(input: InputSize) -> Self {
+        let rows = input.rows;
+        Self {
+            operation: "sort",
+            rows_read: rows,
+            bytes_read: input.bytes,
+            memory_bytes: held(&input),
+            output_rows: rows,
+            // n log n comparisons
+            operations: rows.saturating_mul(u64::from(rows.max(2).ilog2())),
+            inputs: vec![input],
+        }
+    }
+
+    /// Holds `right` in memory by key and streams `left` past it. Rows match
+    /// as if keys were spread evenly over the distinct values of the larger
+    /// side; an input whose distinct values are unknown is taken as keyed
+    /// uniquely, which underestimates joins on repeated keys.
+    pub fn join(left: InputSize, right: InputSize, keep_unmatched: bool) -> Self {
+        let key_values = |input: &InputSize| input.key_values.unwrap_or(input.rows).max(1);
+        let distinct = key_values(&left).max(key_values(&right));
+        let matched = left.rows.saturating_mul(right.rows) / distinct;
+        let output_rows = if keep_unmatched { matched.max(left.rows) } else { matched };
+        Self {
+            operation: "join",
+            rows_read: left.rows + right.rows,
+            bytes_read: left.bytes + right.bytes,
+            memory_bytes: held(&right),
+            output_rows,
+            operations: left.rows + right.rows + output_rows,
+            inputs: vec![left, right],
+        }
+    }
+}
+
+/// What an input takes held in memory as CSV records.
+fn held(input: &InputSize) -> u64 {
+    input.bytes + input.rows * STRING_RECORD_OVERHEAD_BYTES
+}
+
+/// Limits an operation's estimate must keep within; `None` is no limit.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CostLimits {
+    pub max_rows_read: Option<u64>,
+    pub max_memory_bytes: Option<u64>,
+    pub max_output_rows: Option<u64>,
+    /// Whether `?force=true` runs an operation over the limits anyway.
+    pub allow_force: bool,
+}
+
+/// A limit an estimate goes over.
+#[derive(Debug, Clone, Serialize)]
+pub struct Exceeded {
+    pub limit: &'static str,
+    pub estimate: u64,
+    pub max: u64,
+}
+
+impl CostLimits {
+    pub fn is_set(&self) -> bool {
+        self.max_rows_read.is_some() || self.max_memory_bytes.is_some() || self.max_output_rows.is_some()
+    }
+
+    /// The limits `estimate` goes over; empty when it is within all of them.
+    pub fn exceeded(&self, estimate: &CostEstimate) -> Vec<Exceeded> {
+        [
+            ("max_rows_read", estimate.rows_read, self.max_rows_read),
+            ("max_memory_bytes", estimate.memory_bytes, self.max_memory_bytes),
+            ("max_output_rows", estimate.output_rows, self.max_output_rows),
+        ]
+        .into_iter()
+        .filter_map(|(limit, estimate, max)| max.filter(|max| estimate > *max).map(|max| Exceeded { limit, estimate, max }))
+        .collect()
+    }
+}
+
+/// The limits in force and what they did, for /metrics.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CostGuard {
+    pub limits: CostLimits,
+    /// Operations refused for going over the limits.
+    pub rejected: u64,
+    /// Operations over the limits run anyway with `?force=true`.
+    pub forced: u64,
+}
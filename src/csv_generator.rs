@@ -1,20 +1,24 @@
-use csv::Writer;
-use rand::Rng;
 use std::error::Error;
-use std::fs::File;
-use clap::{Arg, Command};
-
-#[derive(Debug)]
-struct SalesRecord {
-    id: u32,
-    customer_name: String,
-    product: String,
-    quantity: u32,
-    price: f64,
-    date: String,
-    region: String,
+use std::path::Path;
+use chrono::NaiveDate;
+use clap::{Arg, ArgAction, Command};
+use indicatif::{HumanBytes, HumanCount, ProgressBar, ProgressState, ProgressStyle};
+
+mod generator;
+mod generator_noise;
+mod generator_output;
+mod generator_schema;
+
+#[allow(dead_code)]
+mod performance_utils {
+    include!("performance_utils.rs");
 }
 
+use generator::{GenerateObserver, GenerateOptions, GenerateStats, OutputFile};
+use generator_output::{Compression, OutputFormat};
+use generator_schema::Schema;
+use performance_utils::{parse_size, PerformanceTimer};
+
 fn main() -> Result<(), Box<dyn Error>> {
     let matches = Command::new("CSV Data Generator")
         .about("Generates sample CSV files for performance testing")
@@ -27,69 +31,441 @@ fn main() -> Result<(), Box<dyn Error>> {
                 .value_parser(["small", "medium", "large"])
                 .default_value("medium")
         )
+        .arg(
+            Arg::new("count")
+                .short('n')
+                .long("count")
+                .value_name("N")
+                .help("Exact number of records to generate (overrides --size)")
+                .value_parser(clap::value_parser!(u32))
+        )
+        .arg(
+            Arg::new("target-size")
+                .long("target-size")
+                .value_name("SIZE")
+                .help("Generate rows until the file reaches about this size, e.g. 500MB, 1GB, 2GiB (overrides --size)")
+                .value_parser(parse_size)
+                .conflicts_with("count")
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("PATH")
+                .help("Output file path (defaults to a file under sample_data/)")
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .value_name("SEED")
+                .help("Seed the RNG so the same dataset is generated on every run")
+                .value_parser(clap::value_parser!(u64))
+        )
+        .arg(
+            Arg::new("threads")
+                .short('j')
+                .long("threads")
+                .value_name("N")
+                .help("Worker threads used for generation (defaults to all cores)")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("0")
+        )
+        .arg(
+            Arg::new("fast-rng")
+                .long("fast-rng")
+                .help("Use the faster Xoshiro256++ RNG instead of ChaCha8 (seeded output differs from the default)")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("schema")
+                .long("schema")
+                .value_name("FILE")
+                .help("TOML or JSON schema describing the columns to generate (defaults to the sales schema)")
+        )
+        .arg(
+            Arg::new("realistic")
+                .long("realistic")
+                .help("Use faker-generated names, emails, cities, and products in the sales schema")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("schema")
+        )
+        .arg(
+            Arg::new("skewed")
+                .long("skewed")
+                .help("Zipf product popularity, log-normal prices, and seasonal dates in the sales schema")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("schema")
+        )
+        .arg(
+            Arg::new("products")
+                .long("products")
+                .value_name("FILE")
+                .help("Draw the product column from this file (one value per line, or the first column of a .csv)")
+        )
+        .arg(
+            Arg::new("regions")
+                .long("regions")
+                .value_name("FILE")
+                .help("Draw the region column from this file")
+        )
+        .arg(
+            Arg::new("names")
+                .long("names")
+                .value_name("FILE")
+                .help("Draw the customer_name column from this file of full names")
+        )
+        .arg(
+            Arg::new("with-customers")
+                .long("with-customers")
+                .value_name("N")
+                .help("Also write a customers table of N customers (default 1000) next to the output and reference it by customer_id")
+                .value_parser(clap::value_parser!(u32).range(1..))
+                .num_args(0..=1)
+                .default_missing_value("1000")
+        )
+        .arg(
+            Arg::new("date-from")
+                .long("date-from")
+                .value_name("YYYY-MM-DD")
+                .help("First date generated in every date column")
+                .value_parser(parse_date)
+        )
+        .arg(
+            Arg::new("date-to")
+                .long("date-to")
+                .value_name("YYYY-MM-DD")
+                .help("Last date generated in every date column")
+                .value_parser(parse_date)
+        )
+        .arg(
+            Arg::new("weekday-weights")
+                .long("weekday-weights")
+                .value_name("WEIGHTS")
+                .help("Weekly pattern for date columns: business, retail, or 7 weights Monday first (e.g. 1,1,1,1,1,0.3,0.2)")
+                .value_parser(parse_weekday_weights)
+        )
+        .arg(
+            Arg::new("error-rate")
+                .long("error-rate")
+                .value_name("RATE")
+                .help("Fraction of rows (0.0-1.0) written malformed: missing fields, bad numbers, bad dates, stray quotes")
+                .value_parser(parse_rate)
+                .default_value("0")
+        )
+        .arg(
+            Arg::new("null-rate")
+                .long("null-rate")
+                .value_name("COLUMN=RATE")
+                .help("Leave COLUMN empty in a RATE fraction of rows, e.g. region=0.05 (repeatable)")
+                .value_parser(parse_null_rate)
+                .action(ArgAction::Append)
+        )
+        .arg(
+            Arg::new("duplicate-rate")
+                .long("duplicate-rate")
+                .value_name("RATE")
+                .help("Fraction of rows (0.0-1.0) that repeat a recent row exactly, by id, or by customer+product+date")
+                .value_parser(parse_rate)
+                .default_value("0")
+        )
+        .arg(
+            Arg::new("shards")
+                .long("shards")
+                .value_name("N")
+                .help("Split the records across N files named <output>_partNN.csv")
+                .value_parser(clap::value_parser!(u32).range(1..))
+                .default_value("1")
+        )
+        .arg(
+            Arg::new("format")
+                .short('f')
+                .long("format")
+                .value_name("FORMAT")
+                .help("Output format: csv, tsv, jsonl, or parquet (parquet needs the `parquet` feature)")
+                .value_parser(OutputFormat::parse)
+                .default_value("csv")
+        )
+        .arg(
+            Arg::new("compress")
+                .long("compress")
+                .value_name("ALGO[:LEVEL]")
+                .help("Compress text output with gzip or zstd, e.g. gzip, zstd:19")
+                .value_parser(Compression::parse)
+                .default_value("none")
+        )
+        .arg(
+            Arg::new("append")
+                .long("append")
+                .help("Add rows to an existing csv, tsv, or jsonl file, continuing its id sequence")
+                .action(ArgAction::SetTrue)
+        )
         .get_matches();
 
     let size = matches.get_one::<String>("size").unwrap();
+    let count = matches.get_one::<u32>("count").copied();
+    let seed = matches.get_one::<u64>("seed").copied();
+    let threads = *matches.get_one::<usize>("threads").unwrap();
+    let schema = match matches.get_one::<String>("schema") {
+        Some(path) => Schema::load(path)?,
+        None if matches.get_flag("realistic") => Schema::realistic_sales(),
+        None => Schema::sales(),
+    };
+    let schema = if matches.get_flag("skewed") { schema.skewed() } else { schema };
+    let mut schema = schema;
+    for (arg, column) in [("products", "product"), ("regions", "region"), ("names", "customer_name")] {
+        if let Some(path) = matches.get_one::<String>(arg) {
+            let values = generator_schema::load_values(path)?;
+            println!("  Loaded {} values for {} from {}", values.len(), column, path);
+            schema = schema.with_values(column, values)?;
+        }
+    }
+    let schema = schema.with_date_range(
+        matches.get_one::<NaiveDate>("date-from").copied(),
+        matches.get_one::<NaiveDate>("date-to").copied(),
+    );
+    let schema = match matches.get_one::<Vec<f64>>("weekday-weights") {
+        Some(weights) => schema.with_weekday_weights(weights),
+        None => schema,
+    };
+    let customers = matches.get_one::<u32>("with-customers").copied();
+    let schema = match customers {
+        Some(count) => schema.with_customer_ids(count as u64),
+        None => schema,
+    };
+    let mut schema = schema;
+    for (column, rate) in matches.get_many::<(String, f64)>("null-rate").into_iter().flatten() {
+        schema = schema.with_null_rate(column, *rate)?;
+    }
+    schema.validate()?;
+    
+    let format = *matches.get_one::<OutputFormat>("format").unwrap();
+    let compression = *matches.get_one::<Compression>("compress").unwrap();
+    if format == OutputFormat::Parquet && compression != Compression::None {
+        return Err("--compress applies to text formats; parquet is compressed internally".into());
+    }
     
-    match size.as_str() {
-        "small" => generate_csv("sample_data/small_data.csv", 1_000)?,
-        "medium" => generate_csv("sample_data/medium_data.csv", 100_000)?,
-        "large" => generate_csv("sample_data/large_data.csv", 1_000_000)?,
+    let target_bytes = matches.get_one::<u64>("target-size").copied();
+    let (default_stem, record_count) = match (count, size.as_str()) {
+        _ if target_bytes.is_some() => {
+            let target = matches.get_raw("target-size").unwrap().next().unwrap().to_string_lossy();
+            (format!("sample_data/data_{}", target), u32::MAX)
+        }
+        (Some(n), _) => (format!("sample_data/data_{}", n), n),
+        (None, "small") => ("sample_data/small_data".to_string(), 1_000),
+        (None, "medium") => ("sample_data/medium_data".to_string(), 100_000),
+        (None, "large") => ("sample_data/large_data".to_string(), 1_000_000),
         _ => unreachable!(),
+    };
+    let filename = matches
+        .get_one::<String>("output")
+        .cloned()
+        .unwrap_or_else(|| match compression.extension() {
+            Some(compressed) => format!("{}.{}.{}", default_stem, format.extension(), compressed),
+            None => format!("{}.{}", default_stem, format.extension()),
+        });
+
+    let append = matches.get_flag("append");
+    let shards = *matches.get_one::<u32>("shards").unwrap();
+    if append && (format == OutputFormat::Parquet || compression != Compression::None || shards > 1) {
+        return Err("--append works on a single uncompressed csv, tsv, or jsonl file".into());
+    }
+    if target_bytes.is_some() && (format == OutputFormat::Parquet || compression != Compression::None || shards > 1) {
+        return Err("--target-size works on a single uncompressed csv, tsv, or jsonl file".into());
+    }
+    // Appending to a file that doesn't exist yet just creates it
+    let append = append && Path::new(&filename).exists();
+    let row_offset = if append {
+        generator_output::next_row(&filename, format, &schema)?
+    } else {
+        0
+    };
+
+    let options = GenerateOptions {
+        record_count,
+        seed,
+        threads,
+        fast_rng: matches.get_flag("fast-rng"),
+        error_rate: *matches.get_one::<f64>("error-rate").unwrap(),
+        duplicate_rate: *matches.get_one::<f64>("duplicate-rate").unwrap(),
+        shards,
+        format,
+        compression,
+        target_bytes,
+        append,
+        row_offset,
+    };
+
+    generate_csv(&filename, &schema, &options)?;
+    if let Some(count) = customers {
+        write_customers(&filename, &schema, &options, count)?;
     }
 
-    println!("✅ Generated {} CSV file successfully!", size);
+    if options.shards > 1 {
+        println!("✅ Generated {} shards of {} successfully!", options.shards, filename);
+    } else {
+        println!("✅ Generated {} successfully!", filename);
+    }
     Ok(())
 }
 
-fn generate_csv(filename: &str, record_count: u32) -> Result<(), Box<dyn Error>> {
-    // Create directory if it doesn't exist
-    std::fs::create_dir_all("sample_data")?;
-    
-    let file = File::create(filename)?;
-    let mut writer = Writer::from_writer(file);
-    let mut rng = rand::thread_rng();
-    
-    let products = ["Laptop", "Mouse", "Keyboard", "Monitor", "Headphones", "Tablet", "Phone", "Speaker"];
-    let regions = ["North", "South", "East", "West", "Central"];
-    let first_names = ["John", "Jane", "Bob", "Alice", "Charlie", "Diana", "Eve", "Frank"];
-    let last_names = ["Smith", "Johnson", "Williams", "Brown", "Jones", "Garcia", "Miller", "Davis"];
+/// Writes the customers table for `--with-customers` beside the sales file,
+/// in the same format and compression, with no injected noise.
+fn write_customers(filename: &str, schema: &Schema, options: &GenerateOptions, count: u32) -> Result<(), Box<dyn Error>> {
+    let mut name = format!("customers.{}", options.format.extension());
+    if let Some(compressed) = options.compression.extension() {
+        name = format!("{}.{}", name, compressed);
+    }
+    let path = Path::new(filename).with_file_name(name).to_string_lossy().into_owned();
 
-    // Write header
-    writer.write_record(&["id", "customer_name", "product", "quantity", "price", "date", "region"])?;
+    let orders_from = schema.first_date().unwrap_or_else(|| NaiveDate::from_ymd_opt(2024, 1, 1).unwrap());
+    let customers = Schema::customers(orders_from);
+    let options = GenerateOptions {
+        seed: options.seed,
+        threads: options.threads,
+        fast_rng: options.fast_rng,
+        format: options.format,
+        compression: options.compression,
+        ..GenerateOptions::new(count)
+    };
+    generate_csv(&path, &customers, &options)
+}
 
-    println!("Generating {} records for {}...", record_count, filename);
-    
-    for i in 1..=record_count {
-        let record = SalesRecord {
-            id: i,
-            customer_name: format!("{} {}", 
-                first_names[rng.gen_range(0..first_names.len())],
-                last_names[rng.gen_range(0..last_names.len())]
-            ),
-            product: products[rng.gen_range(0..products.len())].to_string(),
-            quantity: rng.gen_range(1..=10),
-            price: rng.gen_range(10.0..=1000.0),
-            date: format!("2024-{:02}-{:02}", rng.gen_range(1..=12), rng.gen_range(1..=28)),
-            region: regions[rng.gen_range(0..regions.len())].to_string(),
-        };
-
-        writer.write_record(&[
-            &record.id.to_string(),
-            &record.customer_name,
-            &record.product,
-            &record.quantity.to_string(),
-            &format!("{:.2}", record.price),
-            &record.date,
-            &record.region,
-        ])?;
-
-        if i % 100_000 == 0 {
-            println!("  Progress: {} records written", i);
-        }
+fn parse_rate(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("'{}' is not a rate between 0.0 and 1.0", value)),
+    }
+}
+
+fn parse_null_rate(value: &str) -> Result<(String, f64), String> {
+    let (column, rate) = value
+        .split_once('=')
+        .ok_or_else(|| format!("'{}' is not COLUMN=RATE", value))?;
+    Ok((column.to_string(), parse_rate(rate)?))
+}
+
+fn parse_date(value: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| format!("'{}' is not a YYYY-MM-DD date", value))
+}
+
+fn parse_weekday_weights(value: &str) -> Result<Vec<f64>, String> {
+    match value {
+        "business" => return Ok(generator_schema::BUSINESS_WEEKDAY_WEIGHTS.to_vec()),
+        "retail" => return Ok(generator_schema::RETAIL_WEEKDAY_WEIGHTS.to_vec()),
+        _ => {}
+    }
+
+    let weights = value
+        .split(',')
+        .map(|weight| weight.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("'{}' is not business, retail, or a list of numbers", value))?;
+    if weights.len() != 7 {
+        return Err(format!("expected 7 weekday weights, got {}", weights.len()));
+    }
+    Ok(weights)
+}
+
+fn generate_csv(filename: &str, schema: &Schema, options: &GenerateOptions) -> Result<(), Box<dyn Error>> {
+    match options.target_bytes {
+        Some(target) => println!(
+            "Generating {} of records for {} on {} threads...",
+            HumanBytes(target), filename, options.thread_count()
+        ),
+        None => println!(
+            "Generating {} records for {} on {} threads...",
+            options.record_count, filename, options.thread_count()
+        ),
+    }
+    if let Some(seed) = options.seed {
+        println!("  Using seed: {}", seed);
+    }
+    if options.append {
+        println!("  Appending to the existing file from row {}", options.row_offset + 1);
     }
+    
+    let mut timer = PerformanceTimer::new(format!("Generate {}", filename));
+    let mut progress = Progress {
+        bar: progress_bar(options)?,
+        by_size: options.target_bytes.is_some(),
+        sharded: options.shards > 1,
+        rows_written: 0,
+    };
+    let stats = generator::generate(filename, schema, options, &mut progress)?;
+    progress.bar.finish_and_clear();
+    timer.add_bytes(stats.bytes as usize);
 
-    writer.flush()?;
+    let record_count = stats.rows;
+    let metrics = timer.finish(record_count as usize);
+    let on_disk: u64 = generator::output_files(filename, options)
+        .iter()
+        .filter_map(|file| std::fs::metadata(&file.path).ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    println!(
+        "📦 Wrote {} rows, {} ({} on disk) at {:.0} rows/s, {:.2} MB/s",
+        record_count,
+        HumanBytes(stats.bytes),
+        HumanBytes(on_disk),
+        metrics.records_per_second,
+        metrics.megabytes_per_second.unwrap_or(0.0)
+    );
+    let (corrupted, duplicated) = (stats.corrupted, stats.duplicated);
+    if options.error_rate > 0.0 {
+        println!("  Injected {} malformed rows", corrupted);
+    }
+    if options.duplicate_rate > 0.0 {
+        println!("  Injected {} duplicate rows", duplicated);
+    }
     println!("✅ Successfully generated {} with {} records", filename, record_count);
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Drives the progress bar from the generator's chunk callbacks.
+struct Progress {
+    bar: ProgressBar,
+    /// Counting bytes towards `--target-size` rather than rows.
+    by_size: bool,
+    sharded: bool,
+    rows_written: u64,
+}
+
+impl GenerateObserver for Progress {
+    fn chunk_written(&mut self, stats: &GenerateStats, position: u64) {
+        if self.by_size {
+            self.bar.set_position(position);
+            self.bar.set_message(format!("{} rows", HumanCount(stats.rows)));
+        } else {
+            self.bar.inc(stats.rows - self.rows_written);
+            self.bar.set_message(format!("{} written", HumanBytes(stats.bytes)));
+        }
+        self.rows_written = stats.rows;
+    }
+
+    fn file_finished(&mut self, file: &OutputFile) {
+        if self.sharded {
+            self.bar
+                .suspend(|| println!("  Wrote {} ({} records)", file.path, file.end_row - file.first_row));
+        }
+    }
+}
+
+/// Counts rows, or bytes when generating up to `--target-size`.
+fn progress_bar(options: &GenerateOptions) -> Result<ProgressBar, Box<dyn Error>> {
+    let (length, template) = match options.target_bytes {
+        Some(target) => (
+            target,
+            "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {binary_bytes}/{binary_total_bytes} ({binary_bytes_per_sec}, {msg}) ETA {eta}",
+        ),
+        None => (
+            options.record_count as u64,
+            "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {human_pos}/{human_len} rows ({rate}, {msg}) ETA {eta}",
+        ),
+    };
+    let style = ProgressStyle::with_template(template)?
+        .with_key("rate", |state: &ProgressState, w: &mut dyn std::fmt::Write| {
+            let _ = write!(w, "{:.0} rows/s", state.per_sec());
+        })
+        .progress_chars("=> ");
+    Ok(ProgressBar::new(length).with_style(style))
+}
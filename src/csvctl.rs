@@ -0,0 +1,444 @@
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use serde_json::Value;
+use std::error::Error;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+
+/// Bytes read from disk per upload chunk, and per progress bar update.
+const UPLOAD_CHUNK_BYTES: usize = 64 * 1024;
+
+#[tokio::main]
+async fn main() {
+    let matches = Command::new("csvctl")
+        .about("Command-line client for the Axum CSV server")
+        .arg(
+            Arg::new("server")
+                .long("server")
+                .value_name("URL")
+                .help("Server to talk to")
+                .env("CSVCTL_SERVER")
+                .default_value("http://127.0.0.1:3000")
+                .global(true)
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .help("Print the server's JSON response instead of tables")
+                .action(ArgAction::SetTrue)
+                .global(true)
+        )
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("upload")
+                .about("Upload a CSV file, with a progress bar")
+                .arg(Arg::new("file").value_name("FILE").required(true))
+                .arg(
+                    Arg::new("delimiter")
+                        .long("delimiter")
+                        .value_name("CHAR")
+                        .help("Field delimiter of the file, e.g. ';' or tab")
+                )
+                .arg(
+                    Arg::new("no-header")
+                        .long("no-header")
+                        .help("The first line is a record, not column names")
+                        .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("schema")
+                        .long("schema")
+                        .value_name("NAME")
+                        .help("Schema naming the columns: sales, or one in the server's schemas/")
+                )
+        )
+        .subcommand(
+            Command::new("process")
+                .about("Parse a file on the server and show its throughput")
+                .arg(Arg::new("name").value_name("NAME").help("File name or upload id").required(true))
+        )
+        .subcommand(
+            Command::new("analyze")
+                .about("Show revenue and top products of a file")
+                .arg(Arg::new("name").value_name("NAME").help("File name or upload id").required(true))
+                .arg(
+                    Arg::new("limit")
+                        .long("limit")
+                        .value_name("N")
+                        .help("Top products to show")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("10")
+                )
+        )
+        .subcommand(Command::new("benchmark").about("Run the server's benchmark over the sample files"))
+        .subcommand(
+            Command::new("jobs")
+                .about("List background generation jobs, or show one")
+                .arg(Arg::new("id").value_name("ID").value_parser(clap::value_parser!(u64)))
+                .arg(
+                    Arg::new("wait")
+                        .long("wait")
+                        .help("Poll the job until it finishes")
+                        .action(ArgAction::SetTrue)
+                        .requires("id")
+                )
+        )
+        .subcommand(
+            Command::new("metrics")
+                .about("Show throughput per operation, with trends")
+                .arg(
+                    Arg::new("raw")
+                        .long("raw")
+                        .help("Every stored metric from GET /metrics, as JSON")
+                        .action(ArgAction::SetTrue)
+                )
+        )
+        .get_matches();
+
+    let server = Server {
+        http: reqwest::Client::new(),
+        base: matches.get_one::<String>("server").unwrap().trim_end_matches('/').to_string(),
+    };
+    let (name, args) = matches.subcommand().unwrap();
+    let json = args.get_flag("json");
+    let result = match name {
+        "upload" => upload(&server, args, json).await,
+        "process" => process(&server, args, json).await,
+        "analyze" => analyze(&server, args, json).await,
+        "benchmark" => benchmark(&server, json).await,
+        "jobs" => jobs(&server, args, json).await,
+        "metrics" => metrics(&server, args, json).await,
+        _ => unreachable!("clap only accepts the subcommands above"),
+    };
+    if let Err(error) = result {
+        eprintln!("❌ {}", error);
+        std::process::exit(1);
+    }
+}
+
+struct Server {
+    http: reqwest::Client,
+    base: String,
+}
+
+impl Server {
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base, path)
+    }
+
+    /// Sends `request` and returns the JSON body, or the server's error
+    /// code and message as the error.
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<Value, Box<dyn Error>> {
+        let response = request
+            .send()
+            .await
+            .map_err(|error| format!("cannot reach {}: {}", self.base, error))?;
+        let status = response.status();
+        let body: Value = response.json().await.unwrap_or(Value::Null);
+        if status.is_success() {
+            return Ok(body);
+        }
+        // The full server nests {code, message}; the simple one has a bare message
+        let error = &body["error"];
+        let message = error["message"].as_str().or(body["message"].as_str()).unwrap_or("no details");
+        Err(match error["code"].as_str() {
+            Some(code) => format!("{} ({}): {}", status, code, message),
+            None => format!("{}: {}", status, message),
+        }
+        .into())
+    }
+}
+
+async fn upload(server: &Server, args: &ArgMatches, json: bool) -> Result<(), Box<dyn Error>> {
+    let path = args.get_one::<String>("file").unwrap();
+    let file = tokio::fs::File::open(path).await.map_err(|error| format!("cannot open {}: {}", path, error))?;
+    let length = file.metadata().await?.len();
+    let filename = std::path::Path::new(path).file_name().unwrap_or_default().to_string_lossy().to_string();
+
+    let bar = ProgressBar::new(length).with_style(
+        ProgressStyle::with_template(
+            "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {binary_bytes}/{binary_total_bytes} ({binary_bytes_per_sec}) ETA {eta}",
+        )?
+        .progress_chars("=> "),
+    );
+    // Counts bytes as reqwest pulls them from the file, so the bar follows the network
+    let stream = futures::stream::unfold((file, bar.clone()), |(mut file, progress)| async move {
+        let mut buffer = vec![0; UPLOAD_CHUNK_BYTES];
+        match file.read(&mut buffer).await {
+            Ok(0) => None,
+            Ok(read) => {
+                buffer.truncate(read);
+                progress.inc(read as u64);
+                Some((Ok(buffer), (file, progress)))
+            }
+            Err(error) => Some((Err(error), (file, progress))),
+        }
+    });
+    let part = reqwest::multipart::Part::stream_with_length(reqwest::Body::wrap_stream(stream), length)
+        .file_name(filename)
+        .mime_str("text/csv")?;
+    let form = reqwest::multipart::Form::new().part("file", part);
+    // The server keeps these with the upload for every later request
+    let mut hints = Vec::new();
+    if let Some(delimiter) = args.get_one::<String>("delimiter") {
+        hints.push(("delimiter", delimiter.clone()));
+    }
+    if args.get_flag("no-header") {
+        hints.push(("has_header", "false".to_string()));
+    }
+    if let Some(schema) = args.get_one::<String>("schema") {
+        hints.push(("schema", schema.clone()));
+    }
+    let body = server.send(server.http.post(server.url("/upload")).query(&hints).multipart(form)).await;
+    bar.finish_and_clear();
+    let body = body?;
+
+    if json {
+        return print_json(&body);
+    }
+    println!(
+        "✅ Uploaded {} ({}) as {}",
+        body["filename"].as_str().unwrap_or(path),
+        HumanBytes(length),
+        body["id"].as_str().or(body["file_id"].as_str()).unwrap_or("?")
+    );
+    if let Some(id) = body["id"].as_str() {
+        println!("   csvctl process {}", id);
+        println!("   csvctl analyze {}", id);
+    }
+    Ok(())
+}
+
+async fn process(server: &Server, args: &ArgMatches, json: bool) -> Result<(), Box<dyn Error>> {
+    let name = args.get_one::<String>("name").unwrap();
+    let body = server.send(server.http.get(server.url(&format!("/process/{}", name)))).await?;
+    if json {
+        return print_json(&body);
+    }
+
+    println!("📊 {}", body["filename"].as_str().unwrap_or(name));
+    print_table(
+        &["", ""],
+        &[
+            vec!["Records".to_string(), number(&body["records_processed"], 0)],
+            vec!["Time".to_string(), format!("{} ms", number(&body["processing_time_ms"], 0))],
+            vec!["Records/sec".to_string(), number(&body["records_per_second"], 0)],
+            vec!["MB/sec".to_string(), number(&body["mb_per_second"], 2)],
+            vec!["Shared parse".to_string(), body["shared_parse"].to_string()],
+            vec!["Cancelled".to_string(), body["cancelled"].to_string()],
+        ],
+    );
+    if let Some(phases) = body["phases_ms"].as_object().filter(|phases| !phases.is_empty()) {
+        println!();
+        let rows: Vec<Vec<String>> = phases.iter().map(|(phase, ms)| vec![phase.clone(), number(ms, 2)]).collect();
+        print_table(&["Phase", "ms"], &rows);
+    }
+    Ok(())
+}
+
+async fn analyze(server: &Server, args: &ArgMatches, json: bool) -> Result<(), Box<dyn Error>> {
+    let name = args.get_one::<String>("name").unwrap();
+    let limit = args.get_one::<usize>("limit").unwrap();
+    let request = server
+        .http
+        .get(server.url(&format!("/analyze/{}", name)))
+        .query(&[("limit", limit)]);
+    let body = server.send(request).await?;
+    if json {
+        return print_json(&body);
+    }
+
+    println!("📈 {}", name);
+    println!("   Records:       {}", number(&body["total_records"], 0));
+    println!("   Revenue:       ${}", number(&body["total_revenue"], 2));
+    println!("   Average price: ${}", number(&body["average_price"], 2));
+    println!("   Time:          {} ms\n", number(&body["processing_time_ms"], 0));
+    let rows: Vec<Vec<String>> = body["top_products"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|product| {
+            vec![
+                product["product"].as_str().unwrap_or("").to_string(),
+                format!("${}", number(&product["total_sales"], 2)),
+                number(&product["quantity_sold"], 0),
+            ]
+        })
+        .collect();
+    print_table(&["Product", "Sales", "Quantity"], &rows);
+    Ok(())
+}
+
+async fn benchmark(server: &Server, json: bool) -> Result<(), Box<dyn Error>> {
+    let spinner = ProgressBar::new_spinner().with_message("Running the benchmark on the server...");
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    let body = server.send(server.http.post(server.url("/benchmark"))).await;
+    spinner.finish_and_clear();
+    let body = body?;
+    if json {
+        return print_json(&body);
+    }
+
+    let rows: Vec<Vec<String>> = body["results"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|result| {
+            vec![
+                result["file"].as_str().unwrap_or("").to_string(),
+                number(&result["records_count"], 0),
+                HumanBytes(result["file_size_bytes"].as_u64().unwrap_or(0)).to_string(),
+                number(&result["read_performance"]["mb_per_second"], 1),
+                number(&result["parse_performance"]["records_per_second"], 0),
+                number(&result["duration_ms"], 0),
+            ]
+        })
+        .collect();
+    print_table(&["File", "Records", "Size", "Read MB/s", "Parse rec/s", "Total ms"], &rows);
+    for skipped in body["skipped"].as_array().into_iter().flatten() {
+        println!("⚠️  Skipped {}: {}", skipped["file"].as_str().unwrap_or("?"), skipped["reason"].as_str().unwrap_or("?"));
+    }
+    Ok(())
+}
+
+async fn jobs(server: &Server, args: &ArgMatches, json: bool) -> Result<(), Box<dyn Error>> {
+    let Some(id) = args.get_one::<u64>("id") else {
+        let body = server.send(server.http.get(server.url("/generate"))).await?;
+        if json {
+            return print_json(&body);
+        }
+        let rows: Vec<Vec<String>> = body["jobs"].as_array().into_iter().flatten().map(job_row).collect();
+        if rows.is_empty() {
+            println!("No generation jobs yet");
+        } else {
+            print_table(&["ID", "File", "Rows", "Status"], &rows);
+        }
+        return Ok(());
+    };
+
+    let url = server.url(&format!("/generate/{}", id));
+    let mut body = server.send(server.http.get(&url)).await?;
+    if args.get_flag("wait") {
+        let spinner = ProgressBar::new_spinner();
+        spinner.enable_steady_tick(Duration::from_millis(100));
+        while body["status"] == "running" {
+            let rate = body["throughput"].as_array().and_then(|samples| samples.last()).map(|sample| &sample["rows_per_second"]);
+            spinner.set_message(format!("Job {} running, {} rows/s", id, rate.map_or("–".to_string(), |rate| number(rate, 0))));
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            body = server.send(server.http.get(&url)).await?;
+        }
+        spinner.finish_and_clear();
+    }
+    if json {
+        return print_json(&body);
+    }
+    print_table(&["ID", "File", "Rows", "Status"], &[job_row(&body)]);
+    if let Some(error) = body["error"].as_str() {
+        println!("❌ {}", error);
+    }
+    Ok(())
+}
+
+fn job_row(job: &Value) -> Vec<String> {
+    vec![
+        job["id"].to_string(),
+        job["filename"].as_str().unwrap_or("").to_string(),
+        number(&job["rows"], 0),
+        job["status"].as_str().unwrap_or("").to_string(),
+    ]
+}
+
+async fn metrics(server: &Server, args: &ArgMatches, json: bool) -> Result<(), Box<dyn Error>> {
+    if args.get_flag("raw") {
+        return print_json(&server.send(server.http.get(server.url("/metrics"))).await?);
+    }
+    let body = server.send(server.http.get(server.url("/metrics/summary"))).await?;
+    if json {
+        return print_json(&body);
+    }
+
+    let rows: Vec<Vec<String>> = body["operations"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|operation| {
+            vec![
+                operation["operation"].as_str().unwrap_or("").to_string(),
+                number(&operation["runs"], 0),
+                number(&operation["average_records_per_second"], 0),
+                number(&operation["best_records_per_second"], 0),
+                number(&operation["latest_records_per_second"], 0),
+                match operation["trend_percent"].as_f64() {
+                    Some(percent) => format!("{} ({:+.1}%)", operation["trend"].as_str().unwrap_or(""), percent),
+                    None => "–".to_string(),
+                },
+            ]
+        })
+        .collect();
+    if rows.is_empty() {
+        println!("No metrics yet; process or upload a file first");
+    } else {
+        print_table(&["Operation", "Runs", "Avg rec/s", "Best rec/s", "Latest rec/s", "Trend"], &rows);
+    }
+    Ok(())
+}
+
+fn print_json(body: &Value) -> Result<(), Box<dyn Error>> {
+    println!("{}", serde_json::to_string_pretty(body)?);
+    Ok(())
+}
+
+/// A JSON number with thousands separators, or "–" when it is missing.
+fn number(value: &Value, decimals: usize) -> String {
+    let Some(value) = value.as_f64() else {
+        return "–".to_string();
+    };
+    let formatted = format!("{:.*}", decimals, value.abs());
+    let (whole, fraction) = formatted.split_once('.').map_or((formatted.as_str(), None), |(whole, fraction)| (whole, Some(fraction)));
+    let mut grouped = String::new();
+    for (index, digit) in whole.chars().enumerate() {
+        if index > 0 && (whole.len() - index) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    let sign = if value < 0.0 { "-" } else { "" };
+    match fraction {
+        Some(fraction) => format!("{}{}.{}", sign, grouped, fraction),
+        None => format!("{}{}", sign, grouped),
+    }
+}
+
+/// Prints `rows` in columns as wide as their widest cell: the first
+/// column left-aligned, the others (numbers, mostly) right-aligned. An
+/// all-empty header is left out.
+fn print_table(header: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = header.iter().map(|cell| cell.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let line = |cells: Vec<&str>| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .enumerate()
+            .map(|(column, (cell, width))| {
+                if column == 0 {
+                    format!("{:<width$}", cell, width = width)
+                } else {
+                    format!("{:>width$}", cell, width = width)
+                }
+            })
+            .collect();
+        println!("  {}", padded.join("  ").trim_end());
+    };
+    if header.iter().any(|cell| !cell.is_empty()) {
+        line(header.to_vec());
+        line(widths.iter().map(|width| "─".repeat(*width)).collect::<Vec<_>>().iter().map(String::as_str).collect());
+    }
+    for row in rows {
+        line(row.iter().map(String::as_str).collect());
+    }
+}
@@ -0,0 +1,134 @@
+use clap::{Arg, Command};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::error::Error;
+
+#[allow(dead_code)]
+mod query {
+    include!("query.rs");
+}
+
+use query::QueryEngine;
+
+/// Where the prompt keeps its history, in the working directory.
+const HISTORY_FILE: &str = ".csvsql_history";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let matches = Command::new("csvsql")
+        .about("SQL over the CSV files in sample_data/ and uploads/")
+        .arg(
+            Arg::new("command")
+                .short('c')
+                .long("command")
+                .value_name("SQL")
+                .help("Run one query and exit instead of starting the prompt")
+        )
+        .get_matches();
+
+    let engine = QueryEngine::new();
+    let tables = engine.refresh().await?;
+
+    if let Some(sql) = matches.get_one::<String>("command") {
+        if let Err(error) = run_query(&engine, sql).await {
+            eprintln!("❌ {}", error);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    println!("🗄️  csvsql: SQL over sample_data/ and uploads/");
+    println!("   Tables: {}", if tables.is_empty() { "none yet".to_string() } else { tables.join(", ") });
+    println!("   End queries with ';'. Type .help for commands.\n");
+
+    let mut editor = DefaultEditor::new()?;
+    let _ = editor.load_history(HISTORY_FILE);
+    let mut statement = String::new();
+    loop {
+        let prompt = if statement.is_empty() { "csvsql> " } else { "   ...> " };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            // Ctrl+C drops the statement being typed, like psql
+            Err(ReadlineError::Interrupted) => {
+                statement.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(error) => return Err(error.into()),
+        };
+        let trimmed = line.trim();
+        if statement.is_empty() && trimmed.starts_with('.') {
+            let _ = editor.add_history_entry(trimmed);
+            if !run_command(&engine, trimmed).await {
+                break;
+            }
+            continue;
+        }
+        if trimmed.is_empty() {
+            continue;
+        }
+
+        statement.push_str(&line);
+        statement.push('\n');
+        if trimmed.ends_with(';') {
+            let sql = std::mem::take(&mut statement);
+            let _ = editor.add_history_entry(sql.trim());
+            // Files uploaded meanwhile become tables without a restart
+            if let Err(error) = engine.refresh().await {
+                println!("⚠️  Could not refresh tables: {}", error);
+            }
+            if let Err(error) = run_query(&engine, &sql).await {
+                println!("❌ {}", error);
+            }
+        }
+    }
+
+    let _ = editor.save_history(HISTORY_FILE);
+    println!("👋 Bye");
+    Ok(())
+}
+
+async fn run_query(engine: &QueryEngine, sql: &str) -> Result<(), Box<dyn Error>> {
+    let output = engine.run(sql).await?;
+    println!("{}", output.to_table()?);
+    println!(
+        "({} row{} in {:.1} ms)\n",
+        output.rows,
+        if output.rows == 1 { "" } else { "s" },
+        output.elapsed.as_secs_f64() * 1_000.0
+    );
+    Ok(())
+}
+
+/// Runs a dot command. Returns false when the prompt should exit.
+async fn run_command(engine: &QueryEngine, line: &str) -> bool {
+    let mut words = line.split_whitespace();
+    match (words.next().unwrap_or(""), words.next()) {
+        (".quit" | ".exit", _) => return false,
+        (".tables", _) => {
+            for (name, path) in engine.tables().await {
+                println!("  {:<32} {}", name, path.display());
+            }
+        }
+        (".refresh", _) => match engine.refresh().await {
+            Ok(tables) => println!("🔄 {} tables", tables.len()),
+            Err(error) => println!("❌ {}", error),
+        },
+        (".schema", Some(table)) => match engine.schema(table).await {
+            Ok(schema) => {
+                for field in schema.fields() {
+                    println!("  {:<24} {}", field.name(), field.data_type());
+                }
+            }
+            Err(error) => println!("❌ {}", error),
+        },
+        (".help", _) => {
+            println!("  .tables          List tables and their files");
+            println!("  .schema TABLE    Show a table's columns and types");
+            println!("  .refresh         Pick up new and removed files");
+            println!("  .quit            Exit (or Ctrl+D)");
+        }
+        _ => println!("❓ Unknown command {}; try .help", line),
+    }
+    true
+}
@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// How long fetching rates from `CURRENCY_RATES_URL` may take.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Exchange rates from the currency prices are recorded in, `base`, to
+/// others: an amount in base times `rates[code]` is the amount in `code`.
+#[derive(Debug, Clone, Serialize)]
+pub struct CurrencyRates {
+    pub base: String,
+    pub rates: BTreeMap<String, f64>,
+    /// When the rates were read or fetched, or the date the source gave.
+    pub as_of: chrono::DateTime<chrono::Utc>,
+    /// `CURRENCY_RATES`, or the URL they were fetched from.
+    pub source: String,
+}
+
+/// The rate a response was converted with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppliedRate {
+    pub currency: String,
+    pub base: String,
+    pub rate: f64,
+    pub as_of: chrono::DateTime<chrono::Utc>,
+    pub source: String,
+}
+
+/// A rates document as served by e.g. frankfurter.app and most exchange
+/// rate APIs: `{"base": "USD", "date": "2024-05-02", "rates": {"EUR": 0.93}}`.
+#[derive(Deserialize)]
+struct RatesDocument {
+    base: String,
+    date: Option<chrono::NaiveDate>,
+    rates: BTreeMap<String, f64>,
+}
+
+impl CurrencyRates {
+    /// Only the base currency, for servers without configured rates.
+    pub fn base_only(base: &str) -> Self {
+        Self { base: base.to_uppercase(), rates: BTreeMap::new(), as_of: chrono::Utc::now(), source: "none".to_string() }
+    }
+
+    /// Reads `BASE_CURRENCY` (USD by default) and the static rates in
+    /// `CURRENCY_RATES`, e.g. `EUR=0.92,GBP=0.79`.
+    pub fn from_env() -> Result<Self, String> {
+        let base = std::env::var("BASE_CURRENCY").unwrap_or_else(|_| "USD".to_string());
+        match std::env::var("CURRENCY_RATES") {
+            Ok(text) => Self::parse(&base, &text),
+            Err(_) => Ok(Self::base_only(&base)),
+        }
+    }
+
+    /// Parses `CODE=rate` pairs separated by commas.
+    pub fn parse(base: &str, text: &str) -> Result<Self, String> {
+        let mut rates = BTreeMap::new();
+        for pair in text.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (code, rate) = pair.split_once('=').ok_or_else(|| format!("{:?} is not CODE=rate", pair))?;
+            let rate: f64 = rate.trim().parse().map_err(|_| format!("{:?} has no valid rate", pair))?;
+            rates.insert(currency_code(code)?, check_rate(code, rate)?);
+        }
+        Ok(Self { base: base.trim().to_uppercase(), rates, as_of: chrono::Utc::now(), source: "CURRENCY_RATES".to_string() })
+    }
+
+    /// Fetches rates from `url`. The document's base must be `base`, so a
+    /// misconfigured source cannot silently convert from the wrong currency.
+    pub async fn fetch(url: &str, base: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let client = reqwest::Client::builder().timeout(FETCH_TIMEOUT).build()?;
+        let document: RatesDocument = client.get(url).send().await?.error_for_status()?.json().await?;
+        if !document.base.eq_ignore_ascii_case(base) {
+            return Err(format!("{} gives rates from {}, but prices are in {}", url, document.base, base).into());
+        }
+        let mut rates = BTreeMap::new();
+        for (code, rate) in document.rates {
+            rates.insert(currency_code(&code)?, check_rate(&code, rate)?);
+        }
+        let as_of = document
+            .date
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .map_or_else(chrono::Utc::now, |date| date.and_utc());
+        Ok(Self { base: base.to_uppercase(), rates, as_of, source: url.to_string() })
+    }
+
+    /// The rate to `currency`, 1 for the base currency itself.
+    pub fn rate(&self, currency: &str) -> Option<AppliedRate> {
+        let currency = currency.trim().to_uppercase();
+        let rate = if currency == self.base { 1.0 } else { *self.rates.get(&currency)? };
+        Some(AppliedRate { currency, base: self.base.clone(), rate, as_of: self.as_of, source: self.source.clone() })
+    }
+
+    /// Currencies amounts can be converted to, the base first.
+    pub fn currencies(&self) -> Vec<String> {
+        std::iter::once(self.base.clone()).chain(self.rates.keys().cloned()).collect()
+    }
+}
+
+fn currency_code(code: &str) -> Result<String, String> {
+    let code = code.trim();
+    if code.len() != 3 || !code.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(format!("{:?} is not a three-letter currency code", code));
+    }
+    Ok(code.to_uppercase())
+}
+
+fn check_rate(code: &str, rate: f64) -> Result<f64, String> {
+    if rate.is_finite() && rate > 0.0 {
+        Ok(rate)
+    } else {
+        Err(format!("the rate for {} must be a positive number, not {}", code.trim(), rate))
+    }
+}
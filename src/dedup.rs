@@ -0,0 +1,518 @@
+use crate::performance_utils::{SalesRecord, SALES_COLUMNS};
+use serde::Serialize;
+use std::collections::{BinaryHeap, HashSet, VecDeque};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Bytes a key takes in the hash set of recent keys, table overhead and
+/// spare capacity included.
+const SET_ENTRY_BYTES: u64 = 32;
+
+/// Fewest keys the hash set holds before it is frozen, however small the
+/// memory cap.
+const MIN_RECENT_KEYS: usize = 1_024;
+
+/// Spilled runs of sorted keys past which they are merged into one, so a
+/// lookup binary-searches a handful of files rather than every spill.
+const MAX_SPILLED_RUNS: usize = 8;
+
+/// What keys frozen out of the hash set become.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DedupMode {
+    /// Sorted runs of the keys themselves, 16 bytes each. Exact.
+    HashSet,
+    /// Xor filters of 16-bit fingerprints, under 2.5 bytes a key. Each
+    /// filter mistakes about 1 in 65,536 new keys for ones it holds, and
+    /// such a record is dropped as a duplicate.
+    XorFilter,
+}
+
+impl DedupMode {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text.trim().to_ascii_lowercase().as_str() {
+            "hash" | "hashset" | "hash_set" => Some(Self::HashSet),
+            "xor" | "xorfilter" | "xor_filter" => Some(Self::XorFilter),
+            _ => None,
+        }
+    }
+}
+
+/// Which records count as duplicates and how much a [`Deduplicator`] may
+/// keep in memory to find them.
+#[derive(Debug, Clone, Serialize)]
+pub struct DedupConfig {
+    /// Columns whose values together identify a record.
+    pub keys: Vec<String>,
+    pub mode: DedupMode,
+    /// Bound on the keys and filters a deduplicator holds in memory. Past
+    /// it, the oldest frozen keys are spilled to `spill_dir`, or forgotten
+    /// without one, and their duplicates are no longer caught.
+    pub memory_bytes: u64,
+    pub spill_dir: Option<PathBuf>,
+    #[serde(skip)]
+    columns: Vec<KeyColumn>,
+}
+
+impl DedupConfig {
+    /// A configuration keyed on the comma-separated sales `keys`, e.g.
+    /// `id` or `customer_name,product,date`.
+    pub fn new(keys: &str, mode: DedupMode, memory_bytes: u64, spill_dir: Option<PathBuf>) -> Result<Self, String> {
+        let keys: Vec<String> = keys.split(',').map(str::trim).filter(|key| !key.is_empty()).map(String::from).collect();
+        if keys.is_empty() {
+            return Err("name at least one key column".to_string());
+        }
+        let columns = keys
+            .iter()
+            .map(|key| {
+                KeyColumn::parse(key).ok_or_else(|| format!("unknown key column {}, expected one of {}", key, SALES_COLUMNS.join(", ")))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { keys, mode, memory_bytes, spill_dir, columns })
+    }
+
+    /// Keys the hash set takes before it is frozen: half the memory cap,
+    /// leaving the rest to what was frozen.
+    fn recent_keys(&self) -> usize {
+        ((self.memory_bytes / 2 / SET_ENTRY_BYTES) as usize).max(MIN_RECENT_KEYS)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum KeyColumn {
+    Id,
+    CustomerName,
+    Product,
+    Quantity,
+    Price,
+    Date,
+    Region,
+}
+
+impl KeyColumn {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "id" => Self::Id,
+            "customer_name" => Self::CustomerName,
+            "product" => Self::Product,
+            "quantity" => Self::Quantity,
+            "price" => Self::Price,
+            "date" => Self::Date,
+            "region" => Self::Region,
+            _ => return None,
+        })
+    }
+
+    fn hash<H: Hasher>(self, record: &SalesRecord, state: &mut H) {
+        match self {
+            Self::Id => record.id.hash(state),
+            Self::CustomerName => record.customer_name.hash(state),
+            Self::Product => record.product.hash(state),
+            Self::Quantity => record.quantity.hash(state),
+            Self::Price => record.price.to_bits().hash(state),
+            Self::Date => record.date.hash(state),
+            Self::Region => record.region.hash(state),
+        }
+    }
+}
+
+/// What a [`Deduplicator`] has seen, dropped and let go of.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct DedupStats {
+    pub seen: u64,
+    pub dropped: u64,
+    pub keys_in_memory: u64,
+    /// Estimated bytes of the hash set and of what is frozen in memory.
+    pub memory_bytes: u64,
+    pub spilled_keys: u64,
+    pub spilled_bytes: u64,
+    /// Keys let go of under the memory cap, with no spill directory or
+    /// when spilling failed; a later duplicate of one is kept.
+    pub forgotten_keys: u64,
+}
+
+/// A streaming duplicate filter: each record is let through the first
+/// time its key columns are seen and dropped every time after. Recent keys
+/// sit in a hash set; a full set is frozen into sorted keys or an xor
+/// filter, as [`DedupConfig::mode`] says, and the oldest frozen ones leave
+/// memory once the cap is reached.
+///
+/// Keys are 128-bit hashes of the key columns, taken with the standard
+/// library's hasher, which is only stable within a build: spill files are
+/// scratch, removed once the deduplicator is dropped.
+#[derive(Debug)]
+pub struct Deduplicator {
+    config: Arc<DedupConfig>,
+    recent: HashSet<u128>,
+    /// Oldest first.
+    frozen: VecDeque<Frozen>,
+    /// Start of the names of this deduplicator's spill files.
+    spill_prefix: String,
+    next_spill: u64,
+    stats: DedupStats,
+}
+
+impl Deduplicator {
+    /// A deduplicator with nothing seen, whose spill files, if any, are
+    /// named after `name`.
+    pub fn new(config: Arc<DedupConfig>, name: &str) -> Self {
+        let name: String = name.chars().map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_') { c } else { '_' }).collect();
+        Self {
+            config,
+            recent: HashSet::new(),
+            frozen: VecDeque::new(),
+            spill_prefix: format!("{}-{:08x}", name, rand::random::<u32>()),
+            next_spill: 0,
+            stats: DedupStats::default(),
+        }
+    }
+
+    pub fn config(&self) -> &DedupConfig {
+        &self.config
+    }
+
+    pub fn stats(&self) -> DedupStats {
+        DedupStats {
+            keys_in_memory: self.recent.len() as u64 + self.frozen.iter().filter(|frozen| !frozen.spilled()).map(Frozen::keys).sum::<u64>(),
+            memory_bytes: self.memory_bytes(),
+            ..self.stats
+        }
+    }
+
+    /// Whether `record` is the first with its key, remembering the key if
+    /// so.
+    pub fn admit(&mut self, record: &SalesRecord) -> bool {
+        self.stats.seen += 1;
+        let key = self.key(record);
+        if self.recent.contains(&key) || self.frozen.iter().any(|frozen| frozen.contains(key)) {
+            self.stats.dropped += 1;
+            return false;
+        }
+        self.recent.insert(key);
+        if self.recent.len() >= self.config.recent_keys() {
+            self.freeze();
+        }
+        true
+    }
+
+    /// Drops the records of `records` seen before, in this call or an
+    /// earlier one, keeping the order of the rest. Returns how many were
+    /// dropped.
+    pub fn retain(&mut self, records: &mut Vec<SalesRecord>) -> usize {
+        let before = records.len();
+        records.retain(|record| self.admit(record));
+        before - records.len()
+    }
+
+    fn key(&self, record: &SalesRecord) -> u128 {
+        let mut halves = [DefaultHasher::new(), DefaultHasher::new()];
+        halves[1].write_u8(0xa5);
+        for half in &mut halves {
+            for column in &self.config.columns {
+                column.hash(record, half);
+            }
+        }
+        (halves[0].finish() as u128) << 64 | halves[1].finish() as u128
+    }
+
+    fn memory_bytes(&self) -> u64 {
+        // A key and its control byte per slot of the table
+        let recent = self.recent.capacity() as u64 * (std::mem::size_of::<u128>() as u64 + 1);
+        recent + self.frozen.iter().filter(|frozen| !frozen.spilled()).map(Frozen::memory_bytes).sum::<u64>()
+    }
+
+    /// Moves the hash set's keys into a frozen generation, then brings
+    /// memory back under the cap.
+    fn freeze(&mut self) {
+        let mut keys: Vec<u128> = self.recent.drain().collect();
+        keys.sort_unstable();
+        let frozen = match self.config.mode {
+            DedupMode::HashSet => Frozen::Keys(Storage::Memory(keys.iter().flat_map(|key| key.to_le_bytes()).collect())),
+            DedupMode::XorFilter => match Xor16::build(&keys) {
+                Some(filter) => Frozen::Filter(filter),
+                // Keys no seed could place stay exact
+                None => Frozen::Keys(Storage::Memory(keys.iter().flat_map(|key| key.to_le_bytes()).collect())),
+            },
+        };
+        self.frozen.push_back(frozen);
+
+        let budget = self.config.memory_bytes.saturating_sub(self.config.recent_keys() as u64 * SET_ENTRY_BYTES);
+        while self.frozen.iter().filter(|frozen| !frozen.spilled()).map(Frozen::memory_bytes).sum::<u64>() > budget {
+            let Some(oldest) = self.frozen.iter().position(|frozen| !frozen.spilled()) else {
+                break;
+            };
+            let keys = self.frozen[oldest].keys();
+            let spilled = match self.config.spill_dir.clone() {
+                Some(dir) => self.spill(oldest, &dir),
+                None => Err(io::Error::other("no spill directory")),
+            };
+            match spilled {
+                Ok(bytes) => {
+                    self.stats.spilled_keys += keys;
+                    self.stats.spilled_bytes += bytes;
+                }
+                Err(_) => {
+                    self.frozen.remove(oldest);
+                    self.stats.forgotten_keys += keys;
+                }
+            }
+        }
+        if self.frozen.iter().filter(|frozen| matches!(frozen, Frozen::Keys(Storage::Mapped(_)))).count() > MAX_SPILLED_RUNS {
+            if let Some(dir) = self.config.spill_dir.clone() {
+                // Left as they are if the merge fails; lookups just stay slower
+                let _ = self.merge_runs(&dir);
+            }
+        }
+    }
+
+    fn spill_path(&mut self, dir: &Path, extension: &str) -> PathBuf {
+        self.next_spill += 1;
+        dir.join(format!("{}-{}.{}", self.spill_prefix, self.next_spill, extension))
+    }
+
+    /// Writes the frozen generation at `index` to `dir` and maps it back in
+    /// its place. Returns the bytes written.
+    fn spill(&mut self, index: usize, dir: &Path) -> io::Result<u64> {
+        std::fs::create_dir_all(dir)?;
+        let extension = match self.frozen[index] {
+            Frozen::Keys(_) => "keys",
+            Frozen::Filter(_) => "xor16",
+        };
+        let path = self.spill_path(dir, extension);
+        let bytes: &[u8] = match &self.frozen[index] {
+            Frozen::Keys(storage) => storage,
+            Frozen::Filter(filter) => &filter.fingerprints,
+        };
+        std::fs::write(&path, bytes)?;
+        let mapped = Storage::map(path)?;
+        let written = mapped.len() as u64;
+        match &mut self.frozen[index] {
+            Frozen::Keys(storage) => *storage = mapped,
+            Frozen::Filter(filter) => filter.fingerprints = mapped,
+        }
+        Ok(written)
+    }
+
+    /// Merges every spilled run of sorted keys into one.
+    fn merge_runs(&mut self, dir: &Path) -> io::Result<()> {
+        let path = self.spill_path(dir, "keys");
+        let runs: Vec<&Storage> = self
+            .frozen
+            .iter()
+            .filter_map(|frozen| match frozen {
+                Frozen::Keys(storage @ Storage::Mapped(_)) => Some(storage),
+                _ => None,
+            })
+            .collect();
+        let mut output = BufWriter::new(std::fs::File::create(&path)?);
+        let mut heads = BinaryHeap::new();
+        for (run, storage) in runs.iter().enumerate() {
+            if let Some(key) = key_at(storage, 0) {
+                heads.push(std::cmp::Reverse((key, run, 0)));
+            }
+        }
+        while let Some(std::cmp::Reverse((key, run, index))) = heads.pop() {
+            output.write_all(&key.to_le_bytes())?;
+            if let Some(next) = key_at(runs[run], index + 1) {
+                heads.push(std::cmp::Reverse((next, run, index + 1)));
+            }
+        }
+        output.into_inner().map_err(io::IntoInnerError::into_error)?;
+        let merged = Storage::map(path)?;
+        self.frozen.retain(|frozen| !matches!(frozen, Frozen::Keys(Storage::Mapped(_))));
+        // Spilled runs are older than anything still in memory
+        self.frozen.push_front(Frozen::Keys(merged));
+        Ok(())
+    }
+}
+
+/// Keys frozen out of the hash set.
+#[derive(Debug)]
+enum Frozen {
+    /// Sorted 128-bit keys, little-endian.
+    Keys(Storage),
+    Filter(Xor16),
+}
+
+impl Frozen {
+    fn contains(&self, key: u128) -> bool {
+        match self {
+            Self::Keys(storage) => {
+                let (mut low, mut high) = (0, storage.len() / 16);
+                while low < high {
+                    let middle = (low + high) / 2;
+                    match key_at(storage, middle).cmp(&Some(key)) {
+                        std::cmp::Ordering::Equal => return true,
+                        std::cmp::Ordering::Less => low = middle + 1,
+                        std::cmp::Ordering::Greater => high = middle,
+                    }
+                }
+                false
+            }
+            Self::Filter(filter) => filter.contains(key),
+        }
+    }
+
+    fn keys(&self) -> u64 {
+        match self {
+            Self::Keys(storage) => storage.len() as u64 / 16,
+            Self::Filter(filter) => filter.keys,
+        }
+    }
+
+    fn spilled(&self) -> bool {
+        match self {
+            Self::Keys(storage) => matches!(storage, Storage::Mapped(_)),
+            Self::Filter(filter) => matches!(filter.fingerprints, Storage::Mapped(_)),
+        }
+    }
+
+    fn memory_bytes(&self) -> u64 {
+        match self {
+            Self::Keys(storage) => storage.len() as u64,
+            Self::Filter(filter) => filter.fingerprints.len() as u64,
+        }
+    }
+}
+
+fn key_at(storage: &[u8], index: usize) -> Option<u128> {
+    let bytes = storage.get(index * 16..index * 16 + 16)?;
+    Some(u128::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// Bytes held in memory or mapped from a spill file.
+#[derive(Debug)]
+enum Storage {
+    Memory(Vec<u8>),
+    Mapped(SpillFile),
+}
+
+impl Storage {
+    fn map(path: PathBuf) -> io::Result<Self> {
+        let file = std::fs::File::open(&path)?;
+        // SAFETY: spill files are created and named by their deduplicator,
+        // which never writes them again, and removed only once unmapped
+        let map = unsafe { memmap2::Mmap::map(&file) }?;
+        Ok(Self::Mapped(SpillFile { map, path }))
+    }
+}
+
+impl std::ops::Deref for Storage {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Memory(bytes) => bytes,
+            Self::Mapped(file) => &file.map,
+        }
+    }
+}
+
+/// A mapped spill file, removed when dropped.
+#[derive(Debug)]
+struct SpillFile {
+    map: memmap2::Mmap,
+    path: PathBuf,
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// An xor filter with 16-bit fingerprints (Graf and Lemire, "Xor Filters:
+/// Faster and Smaller Than Bloom and Cuckoo Filters"): a key is in it when
+/// its fingerprint equals the xor of the three slots it hashes to.
+#[derive(Debug)]
+struct Xor16 {
+    seed: u64,
+    block_length: usize,
+    keys: u64,
+    /// Native-endian `u16`s.
+    fingerprints: Storage,
+}
+
+impl Xor16 {
+    /// Tries seeds until every key gets a slot of its own, or gives up.
+    fn build(keys: &[u128]) -> Option<Self> {
+        let mut hashes: Vec<u64> = keys.iter().map(|&key| key as u64 ^ (key >> 64) as u64).collect();
+        hashes.sort_unstable();
+        hashes.dedup();
+        let capacity = (32 + (1.23 * hashes.len() as f64).ceil() as usize) / 3 * 3;
+        let block_length = capacity / 3;
+
+        for attempt in 0..64u64 {
+            let seed = mix(0x9e37_79b9_7f4a_7c15 ^ attempt);
+            let slots = |hash: u64| Self::slots(mix(hash.wrapping_add(seed)), block_length);
+            let mut xors = vec![0u64; capacity];
+            let mut counts = vec![0u32; capacity];
+            for &hash in &hashes {
+                for slot in slots(hash) {
+                    xors[slot] ^= hash;
+                    counts[slot] += 1;
+                }
+            }
+            // Peel slots that a single key hashes to, until none are left
+            let mut queue: Vec<usize> = (0..capacity).filter(|&slot| counts[slot] == 1).collect();
+            let mut stack = Vec::with_capacity(hashes.len());
+            while let Some(slot) = queue.pop() {
+                if counts[slot] != 1 {
+                    continue;
+                }
+                let hash = xors[slot];
+                stack.push((hash, slot));
+                for other in slots(hash) {
+                    xors[other] ^= hash;
+                    counts[other] -= 1;
+                    if counts[other] == 1 {
+                        queue.push(other);
+                    }
+                }
+            }
+            if stack.len() < hashes.len() {
+                continue;
+            }
+
+            let mut fingerprints = vec![0u16; capacity];
+            for &(hash, slot) in stack.iter().rev() {
+                let [a, b, c] = slots(hash);
+                fingerprints[slot] = 0;
+                fingerprints[slot] = fingerprint(mix(hash.wrapping_add(seed))) ^ fingerprints[a] ^ fingerprints[b] ^ fingerprints[c];
+            }
+            let bytes = fingerprints.iter().flat_map(|fingerprint| fingerprint.to_ne_bytes()).collect();
+            return Some(Self { seed, block_length, keys: keys.len() as u64, fingerprints: Storage::Memory(bytes) });
+        }
+        None
+    }
+
+    fn slots(hash: u64, block_length: usize) -> [usize; 3] {
+        let reduce = |value: u64| ((value as u32 as u64 * block_length as u64) >> 32) as usize;
+        [reduce(hash), reduce(hash.rotate_left(21)) + block_length, reduce(hash.rotate_left(42)) + 2 * block_length]
+    }
+
+    fn fingerprint_at(&self, slot: usize) -> u16 {
+        u16::from_ne_bytes([self.fingerprints[slot * 2], self.fingerprints[slot * 2 + 1]])
+    }
+
+    fn contains(&self, key: u128) -> bool {
+        let hash = mix((key as u64 ^ (key >> 64) as u64).wrapping_add(self.seed));
+        let [a, b, c] = Self::slots(hash, self.block_length);
+        fingerprint(hash) == self.fingerprint_at(a) ^ self.fingerprint_at(b) ^ self.fingerprint_at(c)
+    }
+}
+
+fn fingerprint(hash: u64) -> u16 {
+    (hash ^ (hash >> 32)) as u16
+}
+
+/// MurmurHash3's 64-bit finalizer.
+fn mix(mut hash: u64) -> u64 {
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
@@ -0,0 +1,133 @@
+use crate::performance_utils::SalesRecord;
+use crate::quantile::StreamingQuantile;
+use serde::Serialize;
+
+/// Numeric columns that can be described, `revenue` being price × quantity.
+pub const NUMERIC_COLUMNS: [&str; 4] = ["id", "quantity", "price", "revenue"];
+
+/// Descriptive statistics of one column.
+#[derive(Debug, Clone, Serialize)]
+pub struct ColumnSummary {
+    pub count: u64,
+    pub sum: f64,
+    pub mean: Option<f64>,
+    /// Sample variance and standard deviation, dividing by count - 1.
+    pub variance: Option<f64>,
+    pub std_dev: Option<f64>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    /// Quartiles, estimated by [`StreamingQuantile`].
+    pub p25: Option<f64>,
+    pub median: Option<f64>,
+    pub p75: Option<f64>,
+}
+
+/// Means, co-moments and quartiles of several columns, updated one record
+/// at a time with Welford's algorithm, so the statistics and the
+/// correlations between every pair of columns come from a single pass
+/// without the rounding trouble of summing squares.
+pub struct Describer {
+    columns: Vec<&'static str>,
+    count: u64,
+    means: Vec<f64>,
+    sums: Vec<f64>,
+    mins: Vec<f64>,
+    maxes: Vec<f64>,
+    /// Sums of products of deviations from the mean; the diagonal holds
+    /// each column's sum of squared deviations.
+    comoments: Vec<Vec<f64>>,
+    quartiles: Vec<[StreamingQuantile; 3]>,
+    /// Deviations of the latest record from the means before it was added.
+    deltas: Vec<f64>,
+}
+
+impl Describer {
+    /// `columns` must be in [`NUMERIC_COLUMNS`]; returns the first that is not.
+    pub fn new(columns: &[String]) -> Result<Self, String> {
+        let columns = columns
+            .iter()
+            .map(|column| NUMERIC_COLUMNS.iter().find(|known| **known == column.trim()).copied().ok_or_else(|| column.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let width = columns.len();
+        Ok(Self {
+            columns,
+            count: 0,
+            means: vec![0.0; width],
+            sums: vec![0.0; width],
+            mins: vec![f64::INFINITY; width],
+            maxes: vec![f64::NEG_INFINITY; width],
+            comoments: vec![vec![0.0; width]; width],
+            quartiles: (0..width)
+                .map(|_| [StreamingQuantile::new(0.25), StreamingQuantile::new(0.5), StreamingQuantile::new(0.75)])
+                .collect(),
+            deltas: vec![0.0; width],
+        })
+    }
+
+    /// Adds a record, with prices multiplied by `price_factor`. Records with
+    /// a NaN in any described column are skipped, so every statistic covers
+    /// the same rows.
+    pub fn add(&mut self, record: &SalesRecord, price_factor: f64) {
+        let value = |column: &str| match column {
+            "id" => record.id as f64,
+            "quantity" => record.quantity as f64,
+            "price" => record.price * price_factor,
+            _ => record.price * price_factor * record.quantity as f64,
+        };
+        let values: Vec<f64> = self.columns.iter().map(|column| value(column)).collect();
+        if values.iter().any(|value| value.is_nan()) {
+            return;
+        }
+
+        self.count += 1;
+        let count = self.count as f64;
+        for (index, &value) in values.iter().enumerate() {
+            self.deltas[index] = value - self.means[index];
+            self.means[index] += self.deltas[index] / count;
+            self.sums[index] += value;
+            self.mins[index] = self.mins[index].min(value);
+            self.maxes[index] = self.maxes[index].max(value);
+            for quartile in &mut self.quartiles[index] {
+                quartile.add(value);
+            }
+        }
+        for (row, delta) in self.comoments.iter_mut().zip(&self.deltas) {
+            for (comoment, (value, mean)) in row.iter_mut().zip(values.iter().zip(&self.means)) {
+                *comoment += delta * (value - mean);
+            }
+        }
+    }
+
+    pub fn columns(&self) -> &[&'static str] {
+        &self.columns
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn summary(&self, index: usize) -> ColumnSummary {
+        let seen = self.count > 0;
+        let variance = (self.count > 1).then(|| self.comoments[index][index] / (self.count - 1) as f64);
+        let [p25, median, p75] = &self.quartiles[index];
+        ColumnSummary {
+            count: self.count,
+            sum: self.sums[index],
+            mean: seen.then_some(self.means[index]),
+            variance,
+            std_dev: variance.map(f64::sqrt),
+            min: seen.then_some(self.mins[index]),
+            max: seen.then_some(self.maxes[index]),
+            p25: p25.value(),
+            median: median.value(),
+            p75: p75.value(),
+        }
+    }
+
+    /// Pearson correlation of two columns; `None` when either is constant
+    /// or fewer than two records were added.
+    pub fn correlation(&self, a: usize, b: usize) -> Option<f64> {
+        let spread = (self.comoments[a][a] * self.comoments[b][b]).sqrt();
+        (self.count > 1 && spread > 0.0).then(|| (self.comoments[a][b] / spread).clamp(-1.0, 1.0))
+    }
+}
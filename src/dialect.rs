@@ -0,0 +1,104 @@
+use csv::{Reader, ReaderBuilder, StringRecord, Writer, WriterBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+/// How a CSV file is written: its delimiter, whether its first line is a
+/// header, and otherwise the column names its fields stand for. Uploads
+/// declare it once and every later read and write of the file uses it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Dialect {
+    pub delimiter: char,
+    pub has_header: bool,
+    /// The schema the columns were named after, if the upload named one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<String>,
+    /// The schema's columns, in order. They name the fields of a file
+    /// without a header; a file with one is read by its own, which must
+    /// include them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub columns: Option<Vec<String>>,
+    /// Header columns read under another name, the one they had in an
+    /// earlier version of the file, keyed by their name in the file.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub renames: BTreeMap<String, String>,
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Self { delimiter: ',', has_header: true, schema: None, columns: None, renames: BTreeMap::new() }
+    }
+}
+
+impl Dialect {
+    /// Parses a delimiter: one ASCII character other than a quote or a
+    /// line break, or `tab`.
+    pub fn parse_delimiter(text: &str) -> Result<char, String> {
+        let delimiter = match text {
+            "tab" | "\\t" => '\t',
+            _ => {
+                let mut chars = text.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(delimiter), None) => delimiter,
+                    _ => return Err(format!("{:?} is not a single character", text)),
+                }
+            }
+        };
+        if !delimiter.is_ascii() || matches!(delimiter, '"' | '\n' | '\r') {
+            return Err(format!("{:?} cannot separate fields; use an ASCII character other than a quote or line break", delimiter));
+        }
+        Ok(delimiter)
+    }
+
+    /// Comma-separated with a header read as it is, however the schema was
+    /// declared.
+    pub fn is_standard(&self) -> bool {
+        self.delimiter == ',' && self.has_header && self.renames.is_empty()
+    }
+
+    /// A reader of `source` in this dialect. Without a header, the first
+    /// line is a record and records deserialize by the dialect's columns;
+    /// with one, renamed columns are read under their earlier names.
+    pub fn reader<R: Read>(&self, flexible: bool, source: R) -> Reader<R> {
+        let mut reader = ReaderBuilder::new().delimiter(self.delimiter as u8).flexible(flexible).from_reader(source);
+        if let Some(columns) = self.columns.as_ref().filter(|_| !self.has_header) {
+            reader.set_headers(StringRecord::from(columns.clone()));
+        } else if !self.renames.is_empty() {
+            // An unreadable header fails the first read instead
+            if let Ok(headers) = reader.headers() {
+                let renamed: StringRecord = headers.iter().map(|column| self.column(column)).collect();
+                reader.set_headers(renamed);
+            }
+        }
+        reader
+    }
+
+    /// The name a header column is read under.
+    pub fn column<'a>(&'a self, header: &'a str) -> &'a str {
+        self.renames.get(header).map_or(header, String::as_str)
+    }
+
+    /// `columns` as [`Dialect::reader`] read them, back as the file names
+    /// them, for writing the header of a rewritten file.
+    pub fn file_header(&self, columns: &StringRecord) -> StringRecord {
+        columns
+            .iter()
+            .map(|column| self.renames.iter().find(|(_, name)| *name == column).map_or(column, |(header, _)| header.as_str()))
+            .collect()
+    }
+
+    /// A writer of records in this dialect. It writes no header; whether
+    /// one belongs at the top is up to the caller.
+    pub fn writer<W: Write>(&self, destination: W) -> Writer<W> {
+        WriterBuilder::new().delimiter(self.delimiter as u8).has_headers(false).from_writer(destination)
+    }
+
+    /// Whether `content` holds a line that is not blank, after the header
+    /// if there is one.
+    pub fn has_data_rows(&self, content: &[u8]) -> bool {
+        content
+            .split(|&byte| byte == b'\n')
+            .skip(self.has_header as usize)
+            .any(|line| !line.trim_ascii().is_empty())
+    }
+}
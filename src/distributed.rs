@@ -0,0 +1,289 @@
+use crate::performance_utils::{ResultStats, ResultsDocument, SalesRecord};
+use csv::ReaderBuilder;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::time::Duration;
+
+// Scale-out of the chunked strategy across machines: a coordinator splits
+// a file into byte ranges, workers running the same server analyze one
+// range each, and the coordinator merges their partial aggregates. Every
+// node must see the file at the same path, e.g. on shared storage.
+
+/// How long a worker may take to answer for one range.
+const WORKER_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Bytes `start..end` of a file. A row belongs to the range its first
+/// byte falls in, so ranges can be cut anywhere and still cover every row
+/// exactly once. Rows with quoted line breaks must not straddle a cut.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+/// Cuts `size` bytes into `count` ranges of about the same length.
+pub fn split_ranges(size: u64, count: usize) -> Vec<ByteRange> {
+    let count = (count.max(1) as u64).min(size.max(1));
+    (0..count)
+        .map(|index| ByteRange { start: size * index / count, end: size * (index + 1) / count })
+        .collect()
+}
+
+/// The /analyze aggregates of one range, before merging.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PartialAnalysis {
+    pub records: usize,
+    pub revenue: f64,
+    pub price_sum: f64,
+    /// Sales and quantity by product.
+    pub products: HashMap<String, (f64, u32)>,
+    /// Bytes of rows read, after aligning the range to row starts.
+    pub bytes: u64,
+    pub duration_ms: f64,
+}
+
+impl PartialAnalysis {
+    fn add(&mut self, record: &SalesRecord) {
+        let sales = record.price * record.quantity as f64;
+        self.records += 1;
+        self.revenue += sales;
+        self.price_sum += record.price;
+        let entry = self.products.entry(record.product.clone()).or_insert((0.0, 0));
+        entry.0 += sales;
+        entry.1 += record.quantity;
+    }
+
+    /// Folds another range's aggregates into these.
+    pub fn merge(&mut self, other: PartialAnalysis) {
+        self.records += other.records;
+        self.revenue += other.revenue;
+        self.price_sum += other.price_sum;
+        for (product, (sales, quantity)) in other.products {
+            let entry = self.products.entry(product).or_insert((0.0, 0));
+            entry.0 += sales;
+            entry.1 += quantity;
+        }
+        self.bytes += other.bytes;
+        self.duration_ms += other.duration_ms;
+    }
+}
+
+/// Why [`analyze_range`] failed.
+#[derive(Debug)]
+pub enum RangeError {
+    Io(std::io::Error),
+    /// A row that does not parse, at `byte` from the start of the file.
+    /// Line numbers would only be known relative to the range.
+    Row { byte: u64, message: String },
+}
+
+impl From<std::io::Error> for RangeError {
+    fn from(error: std::io::Error) -> Self {
+        RangeError::Io(error)
+    }
+}
+
+/// Analyzes the rows of `path` starting in `range`, using the file's header
+/// for every range. Blocking; run it on the parser pool. Stops early, with
+/// what it has, once `cancelled` returns true.
+pub fn analyze_range(path: &str, range: ByteRange, cancelled: impl Fn() -> bool) -> Result<PartialAnalysis, RangeError> {
+    let start = std::time::Instant::now();
+    let mut file = BufReader::new(std::fs::File::open(path)?);
+    let mut header = Vec::new();
+    let header_end = file.read_until(b'\n', &mut header)? as u64;
+    let from = row_start(&mut file, range.start, header_end)?;
+    let to = row_start(&mut file, range.end, header_end)?;
+
+    let mut partial = PartialAnalysis::default();
+    if to > from {
+        file.seek(SeekFrom::Start(from))?;
+        let mut reader = ReaderBuilder::new().from_reader(header.as_slice().chain(file.take(to - from)));
+        for result in reader.deserialize() {
+            if cancelled() {
+                break;
+            }
+            let record: SalesRecord = result.map_err(|error| {
+                // The reader counts from the header it was given, not from `from`
+                let byte = error.position().map_or(from, |position| from + position.byte().saturating_sub(header.len() as u64));
+                let fallback = error.to_string();
+                let message = match error.into_kind() {
+                    csv::ErrorKind::Io(error) => return RangeError::Io(error),
+                    csv::ErrorKind::Deserialize { err, .. } => err.to_string(),
+                    csv::ErrorKind::Utf8 { err, .. } => err.to_string(),
+                    csv::ErrorKind::UnequalLengths { expected_len, len, .. } => {
+                        format!("found a row with {} fields, but the header has {}", len, expected_len)
+                    }
+                    _ => fallback,
+                };
+                RangeError::Row { byte, message }
+            })?;
+            partial.add(&record);
+        }
+        partial.bytes = to - from;
+    }
+    partial.duration_ms = start.elapsed().as_secs_f64() * 1_000.0;
+    Ok(partial)
+}
+
+/// Where the first row starting at or after `offset` begins: past the
+/// header for offsets inside it, otherwise just after the first line break
+/// at or after `offset - 1`. The end of the file for offsets past it.
+fn row_start(file: &mut BufReader<std::fs::File>, offset: u64, header_end: u64) -> std::io::Result<u64> {
+    if offset <= header_end {
+        return Ok(header_end);
+    }
+    let position = file.seek(SeekFrom::Start(offset - 1))?;
+    let mut skipped = Vec::new();
+    Ok(position + file.read_until(b'\n', &mut skipped)? as u64)
+}
+
+/// What a coordinator sends a worker.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RangeRequest {
+    pub filename: String,
+    pub range: ByteRange,
+}
+
+/// A range as reported back by the coordinator.
+#[derive(Debug, Clone, Serialize)]
+pub struct RangeReport {
+    pub worker: String,
+    pub range: ByteRange,
+    pub records: usize,
+    pub bytes: u64,
+    pub duration_ms: f64,
+}
+
+/// The workers a coordinator dispatches ranges to.
+#[derive(Debug, Clone, Default)]
+pub struct Coordinator {
+    client: reqwest::Client,
+    workers: Vec<String>,
+}
+
+/// A request to a worker that failed.
+#[derive(Debug)]
+pub struct WorkerFailure {
+    pub worker: String,
+    /// The worker's HTTP status, when it answered.
+    pub status: Option<u16>,
+    /// The worker's error body, or why it could not be reached.
+    pub error: serde_json::Value,
+}
+
+impl WorkerFailure {
+    /// The message of the worker's error, or why it could not be reached.
+    pub fn message(&self) -> String {
+        let error = self.error.pointer("/error/message").unwrap_or(&self.error);
+        error.as_str().map_or_else(|| error.to_string(), String::from)
+    }
+}
+
+impl Coordinator {
+    /// Reads the worker base URLs, comma-separated, from `WORKER_URLS`.
+    pub fn from_env() -> Self {
+        let client = reqwest::Client::builder().timeout(WORKER_TIMEOUT).build().unwrap_or_default();
+        let workers = std::env::var("WORKER_URLS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|url| url.trim().trim_end_matches('/'))
+            .filter(|url| !url.is_empty())
+            .map(String::from)
+            .collect();
+        Self { client, workers }
+    }
+
+    pub fn workers(&self) -> &[String] {
+        &self.workers
+    }
+
+    /// Sends `range` of `filename` to `worker`.
+    pub async fn dispatch(&self, worker: &str, filename: &str, range: ByteRange) -> Result<PartialAnalysis, WorkerFailure> {
+        let request = RangeRequest { filename: filename.to_string(), range };
+        self.call(worker, self.client.post(format!("{}/worker/analyze", worker)).json(&request)).await
+    }
+
+    /// Runs POST /benchmark on `worker` and fetches the results it keeps,
+    /// as served by its GET /benchmark/results.
+    pub async fn benchmark(&self, worker: &str) -> Result<ResultsDocument, WorkerFailure> {
+        self.call::<serde_json::Value>(worker, self.client.post(format!("{}/benchmark", worker))).await?;
+        self.call(worker, self.client.get(format!("{}/benchmark/results", worker))).await
+    }
+
+    async fn call<T: DeserializeOwned>(&self, worker: &str, request: reqwest::RequestBuilder) -> Result<T, WorkerFailure> {
+        let failure = |status: Option<u16>, error: serde_json::Value| WorkerFailure { worker: worker.to_string(), status, error };
+        let response = request.send().await.map_err(|error| failure(None, error_chain(&error.without_url()).into()))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            let error = serde_json::from_str(&body).unwrap_or(serde_json::Value::String(body));
+            return Err(failure(Some(status.as_u16()), error));
+        }
+        response.json().await.map_err(|error| failure(Some(status.as_u16()), error_chain(&error.without_url()).into()))
+    }
+}
+
+/// One strategy on one file, as measured by each host that ran it.
+#[derive(Debug, Clone, Serialize)]
+pub struct HostComparison {
+    pub strategy: String,
+    pub file: Option<String>,
+    pub hosts: BTreeMap<String, ResultStats>,
+    /// The host with the most records per second.
+    pub fastest: String,
+}
+
+/// Lines up the results of several hosts by strategy and file, in the
+/// order the first host to run each one listed it.
+pub fn compare_hosts(documents: &BTreeMap<String, ResultsDocument>) -> Vec<HostComparison> {
+    let mut comparisons: Vec<HostComparison> = Vec::new();
+    for (host, document) in documents {
+        for entry in &document.results {
+            let position = comparisons
+                .iter()
+                .position(|comparison| comparison.strategy == entry.strategy && comparison.file == entry.file);
+            let comparison = match position {
+                Some(position) => &mut comparisons[position],
+                None => {
+                    comparisons.push(HostComparison {
+                        strategy: entry.strategy.clone(),
+                        file: entry.file.clone(),
+                        hosts: BTreeMap::new(),
+                        fastest: host.clone(),
+                    });
+                    comparisons.last_mut().expect("just pushed")
+                }
+            };
+            comparison.hosts.insert(host.clone(), entry.stats.clone());
+        }
+    }
+    for comparison in &mut comparisons {
+        if let Some((host, _)) = comparison
+            .hosts
+            .iter()
+            .max_by(|a, b| a.1.records_per_second.total_cmp(&b.1.records_per_second))
+        {
+            comparison.fastest = host.clone();
+        }
+    }
+    comparisons
+}
+
+/// A worker URL without its scheme, e.g. `10.0.0.5:3000`, to key results by.
+pub fn host_name(worker: &str) -> &str {
+    worker.split_once("://").map_or(worker, |(_, host)| host)
+}
+
+/// An error and its sources, e.g. `error sending request: connection refused`;
+/// reqwest's own message leaves out the cause.
+fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
@@ -0,0 +1,223 @@
+use crate::performance_utils::SalesRecord;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// Start of an encrypted field, followed by the key id, a colon and the
+/// base64url nonce, ciphertext and tag.
+pub const PREFIX: &str = "enc:";
+
+/// What a caller who may not decrypt sees in place of an encrypted field.
+pub const MASK: &str = "ENCRYPTED";
+
+/// Columns that can be encrypted. The others are grouped, filtered and
+/// partitioned on by code that reads the files as they are stored.
+pub const ENCRYPTABLE_COLUMNS: [&str; 1] = ["customer_name"];
+
+const NONCE_BYTES: usize = 12;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecryptError {
+    /// The field names a key the cipher was not given.
+    UnknownKey(String),
+    /// The field is not base64, or fails authentication: it was changed,
+    /// moved from another column or encrypted under another key.
+    Corrupt,
+}
+
+impl std::fmt::Display for DecryptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownKey(id) => write!(f, "encrypted with key {}, which is not configured", id),
+            Self::Corrupt => write!(f, "the encrypted value is corrupt or was encrypted with another key"),
+        }
+    }
+}
+
+/// Encrypts and decrypts configured columns of sales records with
+/// ChaCha20-Poly1305. Each field gets a random nonce and is bound to its
+/// column, so equal values encrypt differently and a value moved to another
+/// column fails to decrypt. Fields carry the id of their key: the first key
+/// encrypts, and the others still decrypt what they encrypted before a
+/// rotation. Fields without [`PREFIX`] are read as plaintext.
+pub struct ColumnCipher {
+    columns: Vec<String>,
+    active: String,
+    keys: HashMap<String, ChaCha20Poly1305>,
+}
+
+impl std::fmt::Debug for ColumnCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ColumnCipher").field("columns", &self.columns).field("active", &self.active).finish_non_exhaustive()
+    }
+}
+
+impl ColumnCipher {
+    /// A cipher of the comma-separated `columns` with the comma-separated
+    /// `id:key` pairs of `keys`, each key 32 bytes in hex or base64.
+    pub fn new(columns: &str, keys: &str) -> Result<Self, String> {
+        let columns: Vec<String> = columns.split(',').map(str::trim).filter(|column| !column.is_empty()).map(String::from).collect();
+        if columns.is_empty() {
+            return Err("name at least one column to encrypt".to_string());
+        }
+        if let Some(column) = columns.iter().find(|column| !ENCRYPTABLE_COLUMNS.contains(&column.as_str())) {
+            return Err(format!("{} cannot be encrypted, only {}", column, ENCRYPTABLE_COLUMNS.join(", ")));
+        }
+
+        let mut active = None;
+        let mut ciphers = HashMap::new();
+        for entry in keys.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let (id, key) = entry.split_once(':').ok_or_else(|| format!("expected id:key, not {}", entry.split(':').next().unwrap_or("")))?;
+            if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_')) {
+                return Err(format!("key id {:?} must be letters, digits, - or _", id));
+            }
+            let key = decode_key(key).ok_or_else(|| format!("key {} must be 32 bytes, as 64 hex digits or base64", id))?;
+            if ciphers.insert(id.to_string(), ChaCha20Poly1305::new(&key.into())).is_some() {
+                return Err(format!("key {} is given twice", id));
+            }
+            active.get_or_insert_with(|| id.to_string());
+        }
+        let active = active.ok_or("give at least one id:key")?;
+        Ok(Self { columns, active, keys: ciphers })
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// The id of the key new fields are encrypted with.
+    pub fn key_id(&self) -> &str {
+        &self.active
+    }
+
+    pub fn encrypts(&self, column: &str) -> bool {
+        self.columns.iter().any(|encrypted| encrypted == column)
+    }
+
+    /// `plaintext` of `column`, encrypted with the active key.
+    pub fn encrypt(&self, column: &str, plaintext: &str) -> String {
+        let nonce: [u8; NONCE_BYTES] = rand::random();
+        let payload = Payload { msg: plaintext.as_bytes(), aad: column.as_bytes() };
+        // Encrypting only fails for messages past what the cipher's counter covers
+        let ciphertext = self.keys[&self.active].encrypt(Nonce::from_slice(&nonce), payload).expect("a field short enough to encrypt");
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        format!("{}{}:{}", PREFIX, self.active, URL_SAFE_NO_PAD.encode(sealed))
+    }
+
+    /// The plaintext of a `column` field, which is returned as it is when
+    /// it was not encrypted.
+    pub fn decrypt(&self, column: &str, value: &str) -> Result<String, DecryptError> {
+        let Some(sealed) = value.strip_prefix(PREFIX) else {
+            return Ok(value.to_string());
+        };
+        let (id, sealed) = sealed.split_once(':').ok_or(DecryptError::Corrupt)?;
+        let cipher = self.keys.get(id).ok_or_else(|| DecryptError::UnknownKey(id.to_string()))?;
+        let sealed = URL_SAFE_NO_PAD.decode(sealed).map_err(|_| DecryptError::Corrupt)?;
+        if sealed.len() < NONCE_BYTES {
+            return Err(DecryptError::Corrupt);
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_BYTES);
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: column.as_bytes() })
+            .map_err(|_| DecryptError::Corrupt)?;
+        String::from_utf8(plaintext).map_err(|_| DecryptError::Corrupt)
+    }
+
+    /// `record` with its configured columns encrypted, for storing.
+    pub fn encrypt_record(&self, record: &SalesRecord) -> SalesRecord {
+        let mut encrypted = record.clone();
+        for column in &self.columns {
+            if let Some(field) = field_mut(&mut encrypted, column) {
+                *field = self.encrypt(column, field);
+            }
+        }
+        encrypted
+    }
+
+    /// Decrypts the configured columns of a stored `record` in place.
+    /// Returns the column that failed.
+    pub fn decrypt_record(&self, record: &mut SalesRecord) -> Result<(), (String, DecryptError)> {
+        for column in &self.columns {
+            if let Some(field) = field_mut(record, column) {
+                *field = self.decrypt(column, field).map_err(|error| (column.clone(), error))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the configured columns of `record` with [`MASK`].
+    pub fn mask_record(&self, record: &mut SalesRecord) {
+        for column in &self.columns {
+            if let Some(field) = field_mut(record, column) {
+                *field = MASK.to_string();
+            }
+        }
+    }
+}
+
+fn field_mut<'r>(record: &'r mut SalesRecord, column: &str) -> Option<&'r mut String> {
+    match column {
+        "customer_name" => Some(&mut record.customer_name),
+        _ => None,
+    }
+}
+
+fn decode_key(text: &str) -> Option<[u8; 32]> {
+    let text = text.trim();
+    let bytes = match text.len() {
+        64 => (0..64).step_by(2).map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok()).collect::<Option<Vec<u8>>>()?,
+        _ => base64::engine::general_purpose::STANDARD.decode(text).or_else(|_| URL_SAFE_NO_PAD.decode(text)).ok()?,
+    };
+    bytes.try_into().ok()
+}
+
+/// The roles bearer tokens stand for, and which of them may see encrypted
+/// columns decrypted. Tokens are kept as SHA-256 digests and looked up by
+/// digest, so they are never compared byte by byte.
+#[derive(Debug, Default)]
+pub struct RoleTokens {
+    roles: HashMap<[u8; 32], String>,
+    decrypting: Vec<String>,
+}
+
+impl RoleTokens {
+    /// Roles from the comma-separated `role:token` pairs of `tokens`, of
+    /// which those named in the comma-separated `decrypting` may decrypt.
+    pub fn new(tokens: &str, decrypting: &str) -> Result<Self, String> {
+        let mut roles = HashMap::new();
+        for entry in tokens.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let (role, token) = entry.split_once(':').ok_or_else(|| format!("expected role:token, not {}", entry.split(':').next().unwrap_or("")))?;
+            if role.is_empty() || token.is_empty() {
+                return Err("roles and tokens must not be empty".to_string());
+            }
+            roles.insert(digest(token), role.to_string());
+        }
+        let decrypting = decrypting.split(',').map(str::trim).filter(|role| !role.is_empty()).map(String::from).collect();
+        Ok(Self { roles, decrypting })
+    }
+
+    /// The role of a bearer `token`, `None` for one not configured.
+    pub fn role(&self, token: &str) -> Option<&str> {
+        self.roles.get(&digest(token)).map(String::as_str)
+    }
+
+    pub fn may_decrypt(&self, role: &str) -> bool {
+        self.decrypting.iter().any(|decrypting| decrypting == role)
+    }
+
+    pub fn decrypting(&self) -> &[String] {
+        &self.decrypting
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roles.is_empty()
+    }
+}
+
+fn digest(token: &str) -> [u8; 32] {
+    Sha256::digest(token.as_bytes()).into()
+}
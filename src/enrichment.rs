@@ -0,0 +1,270 @@
+use crate::aggregators::Aggregator;
+use crate::column_stats::ColumnStatistics;
+use crate::performance_utils::{SalesRecord, SALES_COLUMNS};
+use csv::ReaderBuilder;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+/// A lookup table joined onto records by one of their fields. Its CSV's
+/// first column is named after that field, e.g. `region`, and every other
+/// column, e.g. `sales_manager`, is added to the records whose field
+/// matches a row's key.
+#[derive(Debug)]
+pub struct LookupTable {
+    pub name: String,
+    pub key: String,
+    pub columns: Vec<String>,
+    rows: HashMap<String, Vec<String>>,
+}
+
+/// A registered table, as listed by GET /enrichment.
+#[derive(Debug, Clone, Serialize)]
+pub struct LookupTableInfo {
+    pub name: String,
+    pub key: String,
+    pub columns: Vec<String>,
+    pub rows: usize,
+}
+
+impl LookupTable {
+    /// Reads a table from CSV. Keys are trimmed; a repeated key keeps its
+    /// last row.
+    pub fn from_csv(name: &str, content: &[u8]) -> Result<Self, String> {
+        let mut reader = ReaderBuilder::new().from_reader(content);
+        let headers = reader.headers().map_err(|error| error.to_string())?.clone();
+        let mut headers = headers.iter().map(|header| header.trim().to_string());
+        let key = headers.next().filter(|key| !key.is_empty()).ok_or("the table has no header row")?;
+        if !SALES_COLUMNS.contains(&key.as_str()) {
+            return Err(format!(
+                "the first column, {}, must be the record field to join on: one of {}",
+                key,
+                SALES_COLUMNS.join(", ")
+            ));
+        }
+        let columns: Vec<String> = headers.collect();
+        if columns.is_empty() {
+            return Err("the table needs at least one column besides the key".to_string());
+        }
+        if let Some(column) = columns.iter().find(|column| SALES_COLUMNS.contains(&column.as_str()) || column.is_empty()) {
+            return Err(format!("column {:?} would hide a record field; rename it", column));
+        }
+
+        let mut rows = HashMap::new();
+        for record in reader.records() {
+            let record = record.map_err(|error| error.to_string())?;
+            let mut fields = record.iter();
+            let key = fields.next().unwrap_or_default().trim().to_string();
+            rows.insert(key, fields.map(String::from).collect());
+        }
+        Ok(Self { name: name.to_string(), key, columns, rows })
+    }
+
+    pub fn info(&self) -> LookupTableInfo {
+        LookupTableInfo { name: self.name.clone(), key: self.key.clone(), columns: self.columns.clone(), rows: self.rows.len() }
+    }
+
+    /// The row joined onto `record`, if its key has one.
+    fn row(&self, record: &SalesRecord) -> Option<&[String]> {
+        let key = field(record, &self.key)?;
+        self.rows.get(key.as_ref()).map(Vec::as_slice)
+    }
+
+    /// `column` of the row with `key`.
+    fn lookup(&self, key: &str, column: usize) -> Option<&str> {
+        self.rows.get(key)?.get(column).map(String::as_str)
+    }
+}
+
+/// A record field by column name, as text.
+pub fn field<'a>(record: &'a SalesRecord, column: &str) -> Option<Cow<'a, str>> {
+    Some(match column {
+        "id" => Cow::Owned(record.id.to_string()),
+        "customer_name" => Cow::Borrowed(record.customer_name.as_str()),
+        "product" => Cow::Borrowed(record.product.as_str()),
+        "quantity" => Cow::Owned(record.quantity.to_string()),
+        "price" => Cow::Owned(record.price.to_string()),
+        "date" => Cow::Borrowed(record.date.as_str()),
+        "region" => Cow::Borrowed(record.region.as_str()),
+        _ => return None,
+    })
+}
+
+/// The registered lookup tables. Records are enriched with every table's
+/// columns; a record without a matching row gets `null` for them.
+#[derive(Debug, Clone, Default)]
+pub struct Enricher {
+    tables: Vec<Arc<LookupTable>>,
+}
+
+impl Enricher {
+    /// Registers `table`, replacing the table of the same name. Its columns
+    /// must not clash with another table's.
+    pub fn register(&mut self, table: LookupTable) -> Result<(), String> {
+        let clash = self
+            .tables
+            .iter()
+            .filter(|other| other.name != table.name)
+            .find_map(|other| Some((other, table.columns.iter().find(|column| other.columns.contains(column))?)));
+        if let Some((other, column)) = clash {
+            return Err(format!("column {} is already added by table {}", column, other.name));
+        }
+        self.remove(&table.name);
+        self.tables.push(Arc::new(table));
+        Ok(())
+    }
+
+    /// Removes the table called `name`, returning it.
+    pub fn remove(&mut self, name: &str) -> Option<Arc<LookupTable>> {
+        let position = self.tables.iter().position(|table| table.name == name)?;
+        Some(self.tables.remove(position))
+    }
+
+    pub fn table(&self, name: &str) -> Option<&Arc<LookupTable>> {
+        self.tables.iter().find(|table| table.name == name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    pub fn list(&self) -> Vec<LookupTableInfo> {
+        self.tables.iter().map(|table| table.info()).collect()
+    }
+
+    /// `column` of `record`, whether a record field or an enriched column.
+    pub fn value<'a>(&'a self, record: &'a SalesRecord, column: &str) -> Option<Cow<'a, str>> {
+        if let Some(value) = field(record, column) {
+            return Some(value);
+        }
+        let (table, index) = self.column(column)?;
+        table.row(record)?.get(index).map(|value| Cow::Borrowed(value.as_str()))
+    }
+
+    /// The table adding `column` and the column's position in its rows.
+    fn column(&self, column: &str) -> Option<(&Arc<LookupTable>, usize)> {
+        self.tables.iter().find_map(|table| Some((table, table.columns.iter().position(|name| name == column)?)))
+    }
+
+    /// Whether `column` is a record field or an enriched column.
+    pub fn has_column(&self, column: &str) -> bool {
+        SALES_COLUMNS.contains(&column) || self.column(column).is_some()
+    }
+
+    /// `record` as a JSON object with the enriched columns after its fields.
+    pub fn enrich(&self, record: &SalesRecord) -> serde_json::Value {
+        let mut value = serde_json::json!(record);
+        if let Some(object) = value.as_object_mut() {
+            for table in &self.tables {
+                let row = table.row(record);
+                for (index, column) in table.columns.iter().enumerate() {
+                    let enriched = row.and_then(|row| row.get(index));
+                    object.insert(column.clone(), serde_json::json!(enriched));
+                }
+            }
+        }
+        value
+    }
+
+    /// Aggregations of revenue and orders per value of each column `table`
+    /// adds, named `revenue_by_<column>`, for the aggregation registry.
+    pub fn aggregations(table: &Arc<LookupTable>) -> Vec<(String, String, RevenueByLookup)> {
+        table
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(index, column)| {
+                let name = aggregation_name(column);
+                let description = format!("Orders and revenue per {} (lookup table {}, joined on {})", column, table.name, table.key);
+                (name, description, RevenueByLookup { table: table.clone(), column: index, groups: BTreeMap::new() })
+            })
+            .collect()
+    }
+}
+
+/// The name [`Enricher::aggregations`] gives the aggregation of `column`.
+pub fn aggregation_name(column: &str) -> String {
+    format!("revenue_by_{}", column)
+}
+
+/// Filters records on equality of columns, enriched ones included, given as
+/// `column:value` pairs separated by commas, e.g. `category:Audio,region:North`.
+/// A record must match every pair.
+#[derive(Debug, Clone, Default)]
+pub struct RecordFilter {
+    conditions: Vec<(String, String)>,
+}
+
+impl RecordFilter {
+    pub fn parse(text: &str, enricher: &Enricher) -> Result<Self, String> {
+        let mut conditions = Vec::new();
+        for condition in text.split(',').map(str::trim).filter(|condition| !condition.is_empty()) {
+            let (column, value) = condition
+                .split_once(':')
+                .ok_or_else(|| format!("{:?} is not column:value", condition))?;
+            let column = column.trim();
+            if !enricher.has_column(column) {
+                return Err(format!("unknown column {}", column));
+            }
+            conditions.push((column.to_string(), value.trim().to_string()));
+        }
+        Ok(Self { conditions })
+    }
+
+    pub fn matches(&self, enricher: &Enricher, record: &SalesRecord) -> bool {
+        self.conditions
+            .iter()
+            .all(|(column, value)| enricher.value(record, column).is_some_and(|found| found == value.as_str()))
+    }
+}
+
+/// Orders and revenue per value of an enriched column. Records without a
+/// matching row count under `unmatched`.
+#[derive(Clone)]
+pub struct RevenueByLookup {
+    table: Arc<LookupTable>,
+    column: usize,
+    groups: BTreeMap<String, (u64, f64)>,
+}
+
+impl RevenueByLookup {
+    fn add(&mut self, key: &str, orders: u64, revenue: f64) {
+        let value = self.table.lookup(key, self.column).unwrap_or("unmatched");
+        let entry = self.groups.entry(value.to_string()).or_default();
+        entry.0 += orders;
+        entry.1 += revenue;
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let groups: BTreeMap<_, _> = self
+            .groups
+            .iter()
+            .map(|(value, (orders, revenue))| (value, serde_json::json!({ "orders": orders, "revenue": revenue })))
+            .collect();
+        serde_json::json!(groups)
+    }
+}
+
+impl Aggregator for RevenueByLookup {
+    fn init() -> Self {
+        unreachable!("registered with a factory, as it needs its table")
+    }
+
+    fn update(&mut self, record: &SalesRecord) {
+        let key = field(record, &self.table.key).unwrap_or_default();
+        self.add(&key, 1, record.price * record.quantity as f64);
+    }
+
+    fn finalize(self: Box<Self>) -> serde_json::Value {
+        self.to_json()
+    }
+
+    fn answer_from_statistics(&self, statistics: &ColumnStatistics) -> Option<serde_json::Value> {
+        let mut aggregation = self.clone();
+        for (key, group) in statistics.groups(&self.table.key)? {
+            aggregation.add(key, group.rows, group.revenue);
+        }
+        Some(aggregation.to_json())
+    }
+}
@@ -0,0 +1,232 @@
+use serde::{Serialize, Serializer};
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Events per segment. Appends fill the open segment; a full one is sealed
+/// behind an `Arc`, so snapshots share it instead of copying it.
+pub const SEGMENT_EVENTS: usize = 256;
+
+/// An append-only log of events that many tasks write at once. It has a
+/// lock of its own, held only to push an event or clone a handful of
+/// `Arc`s, so writers never wait on whoever holds the server state, and
+/// readers take [`Snapshot`]s and serialize them with no lock held. At most
+/// about `memory_events` events stay in memory; older segments are spilled,
+/// oldest first, as JSON lines to `spill_path`, or dropped without one.
+/// Spilled segments are written by a thread of the log's own, so a push
+/// never waits on the disk, and an async task can push without blocking.
+#[derive(Debug)]
+pub struct EventLog<T> {
+    segments: Arc<Mutex<Segments<T>>>,
+    memory_events: usize,
+    spill_path: Option<PathBuf>,
+    /// To the spill writer, started on the first spill. Sent to under the
+    /// segments lock, so segments reach the file in the order they left
+    /// memory.
+    spill: Mutex<Option<Sender<Evicted<T>>>>,
+}
+
+/// Segments taken out of memory together, on their way to the spill file.
+type Evicted<T> = Vec<Arc<Vec<T>>>;
+
+#[derive(Debug)]
+struct Segments<T> {
+    sealed: VecDeque<Arc<Vec<T>>>,
+    open: Vec<T>,
+    in_memory: usize,
+    spilled: u64,
+    dropped: u64,
+}
+
+/// Counts of what an [`EventLog`] holds and has let go of.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct LogStats {
+    pub in_memory: usize,
+    /// Events that left memory for the spill file, including any still
+    /// being written.
+    pub spilled: u64,
+    /// Events that left memory but could not be written to the spill file,
+    /// or had none to go to.
+    pub dropped: u64,
+}
+
+impl<T: Clone + Serialize + Send + Sync + 'static> EventLog<T> {
+    /// A log keeping at least `memory_events` events in memory, spilling
+    /// older ones to `spill_path` when there is one. The file is started
+    /// afresh on the first spill.
+    pub fn new(memory_events: usize, spill_path: Option<PathBuf>) -> Self {
+        Self {
+            segments: Arc::new(Mutex::new(Segments { sealed: VecDeque::new(), open: Vec::new(), in_memory: 0, spilled: 0, dropped: 0 })),
+            memory_events: memory_events.max(SEGMENT_EVENTS),
+            spill_path,
+            spill: Mutex::new(None),
+        }
+    }
+
+    /// Appends `event`. When that fills the open segment and pushes the log
+    /// past its bound, the oldest segments are handed to the spill writer.
+    pub fn push(&self, event: T) {
+        let mut segments = lock(&self.segments);
+        segments.open.push(event);
+        segments.in_memory += 1;
+        if segments.open.len() < SEGMENT_EVENTS {
+            return;
+        }
+        let sealed = std::mem::take(&mut segments.open);
+        segments.sealed.push_back(Arc::new(sealed));
+        self.evict(segments);
+    }
+
+    /// Replaces every event in memory with `events`, as when state is
+    /// restored. What was already spilled stays spilled.
+    pub fn replace(&self, events: Vec<T>) {
+        let mut segments = lock(&self.segments);
+        segments.sealed.clear();
+        segments.open.clear();
+        segments.in_memory = 0;
+        for chunk in events.chunks(SEGMENT_EVENTS) {
+            segments.in_memory += chunk.len();
+            match chunk.len() == SEGMENT_EVENTS {
+                true => segments.sealed.push_back(Arc::new(chunk.to_vec())),
+                false => segments.open = chunk.to_vec(),
+            }
+        }
+        self.evict(segments);
+    }
+
+    /// The events in memory as they are now, oldest first. Later appends
+    /// do not show up in it.
+    pub fn snapshot(&self) -> Snapshot<T> {
+        let segments = lock(&self.segments);
+        let mut shared: Vec<Arc<Vec<T>>> = segments.sealed.iter().cloned().collect();
+        if !segments.open.is_empty() {
+            shared.push(Arc::new(segments.open.clone()));
+        }
+        Snapshot { segments: shared, spilled: segments.spilled }
+    }
+
+    pub fn stats(&self) -> LogStats {
+        let segments = lock(&self.segments);
+        LogStats { in_memory: segments.in_memory, spilled: segments.spilled, dropped: segments.dropped }
+    }
+
+    pub fn spill_path(&self) -> Option<&Path> {
+        self.spill_path.as_deref()
+    }
+
+    /// Takes sealed segments out of memory until the log is within its
+    /// bound, and hands them to the spill writer.
+    fn evict(&self, mut segments: MutexGuard<'_, Segments<T>>) {
+        let mut evicted = Vec::new();
+        while segments.in_memory > self.memory_events {
+            let Some(oldest) = segments.sealed.pop_front() else {
+                break;
+            };
+            segments.in_memory -= oldest.len();
+            evicted.push(oldest);
+        }
+        if evicted.is_empty() {
+            return;
+        }
+        let events: u64 = evicted.iter().map(|segment| segment.len() as u64).sum();
+        let sent = match &self.spill_path {
+            Some(path) => {
+                let mut spill = lock(&self.spill);
+                if spill.is_none() {
+                    *spill = spawn_writer(path.clone(), self.segments.clone()).ok();
+                }
+                spill.as_ref().is_some_and(|writer| writer.send(evicted).is_ok())
+            }
+            None => false,
+        };
+        match sent {
+            true => segments.spilled += events,
+            false => segments.dropped += events,
+        }
+    }
+}
+
+/// Starts the thread that appends spilled segments to `path`, starting the
+/// file afresh, until the log is dropped. Segments it fails to write are
+/// moved from the log's spilled count to its dropped one.
+fn spawn_writer<T: Serialize + Send + Sync + 'static>(
+    path: PathBuf,
+    segments: Arc<Mutex<Segments<T>>>,
+) -> io::Result<Sender<Evicted<T>>> {
+    let (sender, receiver) = mpsc::channel::<Evicted<T>>();
+    std::thread::Builder::new().name("event-log-spill".to_string()).spawn(move || {
+        let mut file = None;
+        for evicted in receiver {
+            if write(&mut file, &path, &evicted).is_err() {
+                let events: u64 = evicted.iter().map(|segment| segment.len() as u64).sum();
+                let mut segments = lock(&segments);
+                segments.spilled -= events;
+                segments.dropped += events;
+            }
+        }
+    })?;
+    Ok(sender)
+}
+
+fn write<T: Serialize>(file: &mut Option<BufWriter<File>>, path: &Path, evicted: &[Arc<Vec<T>>]) -> io::Result<()> {
+    if file.is_none() {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let opened = OpenOptions::new().create(true).write(true).truncate(true).open(path)?;
+        *file = Some(BufWriter::new(opened));
+    }
+    let Some(writer) = file.as_mut() else {
+        return Ok(());
+    };
+    for event in evicted.iter().flat_map(|segment| segment.iter()) {
+        serde_json::to_writer(&mut *writer, event)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()
+}
+
+/// The events an [`EventLog`] held in memory when the snapshot was taken.
+/// Sealed segments are shared with the log, so taking one copies at most
+/// one segment. Serializes as a sequence of the events.
+#[derive(Debug, Clone)]
+pub struct Snapshot<T> {
+    segments: Vec<Arc<Vec<T>>>,
+    /// Events spilled from the log before the snapshot, not in it.
+    pub spilled: u64,
+}
+
+impl<T> Snapshot<T> {
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.segments.iter().flat_map(|segment| segment.iter())
+    }
+
+    pub fn len(&self) -> usize {
+        self.segments.iter().map(|segment| segment.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T: Clone> Snapshot<T> {
+    pub fn to_vec(&self) -> Vec<T> {
+        self.iter().cloned().collect()
+    }
+}
+
+impl<T: Serialize> Serialize for Snapshot<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+/// A poisoned log lock only means a writer panicked mid-push; the events
+/// are still whole, so the log carries on.
+fn lock<U>(mutex: &Mutex<U>) -> MutexGuard<'_, U> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -0,0 +1,73 @@
+use serde_json::{Map, Value};
+
+/// The fields a client asked a response to be cut down to with
+/// `?fields=records_processed,processing_time_ms`. A dotted path, e.g.
+/// `top_products.product`, keeps part of an object, or of every object in
+/// an array.
+#[derive(Debug, Clone)]
+pub struct FieldSet {
+    paths: Vec<Vec<String>>,
+}
+
+impl FieldSet {
+    /// `None` when `text` names no field, i.e. the whole response.
+    pub fn parse(text: &str) -> Result<Option<Self>, String> {
+        let mut paths = Vec::new();
+        for field in text.split(',').map(str::trim).filter(|field| !field.is_empty()) {
+            let path: Vec<String> = field.split('.').map(|part| part.trim().to_string()).collect();
+            if path.iter().any(String::is_empty) {
+                return Err(format!("{:?} is not a field name or a dotted path to one", field));
+            }
+            paths.push(path);
+        }
+        Ok((!paths.is_empty()).then_some(Self { paths }))
+    }
+
+    /// Whether the top-level `field` is wanted, in full or in part. Handlers
+    /// leave out expensive fields that are not before serializing.
+    pub fn includes(&self, field: &str) -> bool {
+        self.paths.iter().any(|path| path[0] == field)
+    }
+
+    /// Keeps only the selected fields of `value`. Returns the top-level
+    /// fields asked for that the response does not have.
+    pub fn select(&self, value: Value) -> Result<Value, Vec<String>> {
+        let Value::Object(object) = value else {
+            return Ok(value);
+        };
+        let unknown: Vec<String> = self
+            .paths
+            .iter()
+            .map(|path| &path[0])
+            .filter(|field| !object.contains_key(field.as_str()))
+            .cloned()
+            .collect();
+        if !unknown.is_empty() {
+            return Err(unknown);
+        }
+        let paths: Vec<&[String]> = self.paths.iter().map(Vec::as_slice).collect();
+        Ok(select_paths(Value::Object(object), &paths))
+    }
+}
+
+/// Keeps the parts of `value` on `paths`. Paths that run into a value that
+/// is neither an object nor an array keep all of it.
+fn select_paths(value: Value, paths: &[&[String]]) -> Value {
+    if paths.iter().any(|path| path.is_empty()) {
+        return value;
+    }
+    match value {
+        Value::Object(object) => {
+            let mut selected = Map::new();
+            for (key, field) in object {
+                let rest: Vec<&[String]> = paths.iter().filter(|path| path[0] == key).map(|path| &path[1..]).collect();
+                if !rest.is_empty() {
+                    selected.insert(key, select_paths(field, &rest));
+                }
+            }
+            Value::Object(selected)
+        }
+        Value::Array(items) => Value::Array(items.into_iter().map(|item| select_paths(item, paths)).collect()),
+        other => other,
+    }
+}
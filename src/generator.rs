@@ -0,0 +1,347 @@
+use crate::generator_noise::{self, Corruption};
+use crate::generator_output::{ChunkEncoder, Compression, EncodedChunk, FileWriter, OutputFormat};
+use crate::generator_schema::Schema;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rand_xoshiro::Xoshiro256PlusPlus;
+use rayon::prelude::*;
+use std::error::Error;
+use std::path::Path;
+
+/// Rows generated per parallel work unit.
+const CHUNK_SIZE: u32 = 50_000;
+
+/// Flag for the ChaCha streams that drive dirty-data injection. Keeping
+/// them separate from the row streams means clean rows are identical for a
+/// given seed whatever the error rate.
+const NOISE_STREAM: u64 = 1 << 63;
+
+/// Flag for the streams that decide `--null-rate` blanks, kept apart from
+/// the row streams for the same reason.
+const NULL_STREAM: u64 = 1 << 62;
+
+/// Number of recent rows a duplicate can be copied from. Duplicates never
+/// cross chunk boundaries, so chunks stay independent.
+const DUPLICATE_WINDOW: usize = 1_000;
+
+/// Everything that shapes a generation run besides the schema.
+#[derive(Clone)]
+pub struct GenerateOptions {
+    pub record_count: u32,
+    pub seed: Option<u64>,
+    pub threads: usize,
+    /// Use Xoshiro256++ instead of ChaCha8. Much cheaper per draw and
+    /// still reproducible, but a seed yields a different dataset.
+    pub fast_rng: bool,
+    pub error_rate: f64,
+    pub duplicate_rate: f64,
+    pub shards: u32,
+    pub format: OutputFormat,
+    pub compression: Compression,
+    /// Stop once the file reaches this many bytes; `record_count` is then
+    /// only an upper bound.
+    pub target_bytes: Option<u64>,
+    /// Add rows to an existing file instead of replacing it.
+    pub append: bool,
+    /// Row number the run starts at, so appended ids carry on from the
+    /// existing file.
+    pub row_offset: u64,
+}
+
+/// Rows `first_row..end_row`, numbered across the whole run, written to
+/// one file. Sharded runs produce one of these per shard.
+pub struct OutputFile {
+    pub path: String,
+    pub shard: u32,
+    pub first_row: u32,
+    pub end_row: u32,
+}
+
+/// Running totals across all output files of a run.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GenerateStats {
+    pub rows: u64,
+    /// Encoded bytes before compression.
+    pub bytes: u64,
+    pub corrupted: usize,
+    pub duplicated: usize,
+}
+
+/// Hooks for following or stopping a run. Every method is called from the
+/// thread that called [`generate`].
+pub trait GenerateObserver {
+    /// After each chunk, with the run's totals and the size of the current
+    /// file so far (only tracked for uncompressed text output).
+    fn chunk_written(&mut self, _stats: &GenerateStats, _position: u64) {}
+
+    fn file_finished(&mut self, _file: &OutputFile) {}
+
+    /// Checked between chunks. Stopping leaves complete files containing
+    /// the rows written so far.
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+}
+
+impl GenerateObserver for () {}
+
+impl GenerateOptions {
+    /// Plain CSV of `record_count` rows on every core, with no noise.
+    pub fn new(record_count: u32) -> Self {
+        Self {
+            record_count,
+            seed: None,
+            threads: 0,
+            fast_rng: false,
+            error_rate: 0.0,
+            duplicate_rate: 0.0,
+            shards: 1,
+            format: OutputFormat::Csv,
+            compression: Compression::None,
+            target_bytes: None,
+            append: false,
+            row_offset: 0,
+        }
+    }
+
+    /// Worker threads a run will use.
+    pub fn thread_count(&self) -> usize {
+        match self.threads {
+            0 => rayon::current_num_threads(),
+            threads => threads,
+        }
+    }
+}
+
+/// Generates `schema` rows into `filename`, or into its shards, and returns
+/// the totals. Chunks are generated on a rayon pool and written in order,
+/// so a seeded run produces the same bytes on any number of threads.
+pub fn generate(
+    filename: &str,
+    schema: &Schema,
+    options: &GenerateOptions,
+    observer: &mut dyn GenerateObserver,
+) -> Result<GenerateStats, Box<dyn Error>> {
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(options.threads).build()?;
+    // Appended rows must not replay the values already in the file when the
+    // same seed is reused
+    let base_seed = options.seed.unwrap_or_else(rand::random).wrapping_add(options.row_offset);
+    let mut stats = GenerateStats::default();
+
+    for file in output_files(filename, options) {
+        write_file(&file, schema, options, &pool, base_seed, observer, &mut stats)?;
+        observer.file_finished(&file);
+        if observer.is_cancelled() {
+            break;
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Splits the run into output files. Shards get (nearly) equal row counts
+/// and ids keep counting up across them.
+pub fn output_files(filename: &str, options: &GenerateOptions) -> Vec<OutputFile> {
+    let record_count = options.record_count;
+    if options.shards <= 1 {
+        return vec![OutputFile {
+            path: filename.to_string(),
+            shard: 0,
+            first_row: 0,
+            end_row: record_count,
+        }];
+    }
+
+    // Keep "data.csv.gz" shards as "data_part01.csv.gz"
+    let compressed = options
+        .compression
+        .extension()
+        .map(|ext| format!(".{}", ext))
+        .filter(|ext| filename.ends_with(ext.as_str()))
+        .unwrap_or_default();
+    let path = Path::new(&filename[..filename.len() - compressed.len()]);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("data");
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or(options.format.extension());
+    let width = options.shards.to_string().len().max(2);
+    let rows_per_shard = record_count.div_ceil(options.shards);
+
+    (0..options.shards)
+        .map(|shard| {
+            let name = format!(
+                "{}_part{:0width$}.{}{}",
+                stem,
+                shard + 1,
+                extension,
+                compressed,
+                width = width
+            );
+            OutputFile {
+                path: path.with_file_name(name).to_string_lossy().into_owned(),
+                shard,
+                first_row: (shard * rows_per_shard).min(record_count),
+                end_row: ((shard + 1) * rows_per_shard).min(record_count),
+            }
+        })
+        .collect()
+}
+
+/// Writes one output file, reporting each chunk to `observer`.
+fn write_file(
+    file: &OutputFile,
+    schema: &Schema,
+    options: &GenerateOptions,
+    pool: &rayon::ThreadPool,
+    base_seed: u64,
+    observer: &mut dyn GenerateObserver,
+    stats: &mut GenerateStats,
+) -> Result<(), Box<dyn Error>> {
+    // Create the output directory if it doesn't exist
+    if let Some(parent) = Path::new(&file.path).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    
+    let mut output = if options.append {
+        FileWriter::append(&file.path)?
+    } else {
+        FileWriter::create(&file.path, options.format, options.compression, schema)?
+    };
+    
+    let chunk_count = (file.end_row - file.first_row).div_ceil(CHUNK_SIZE);
+    // Bound memory by only keeping a couple of chunks per thread in flight
+    let batch_size = (pool.current_num_threads() * 2) as u32;
+    let mut position = output.position()?.unwrap_or(0);
+    
+    'batches: for batch_start in (0..chunk_count).step_by(batch_size as usize) {
+        if observer.is_cancelled() {
+            break;
+        }
+        let batch_end = (batch_start + batch_size).min(chunk_count);
+        let chunks: Vec<GeneratedChunk> = pool.install(|| {
+            (batch_start..batch_end)
+                .into_par_iter()
+                .map(|chunk| generate_chunk(schema, options, file, base_seed, chunk))
+                .collect::<Result<_, csv::Error>>()
+        })?;
+        
+        // Chunks come back in order, so the file is identical to a
+        // single-threaded run with the same seed
+        for mut chunk in chunks {
+            let mut reached_target = false;
+            if let Some(target) = options.target_bytes {
+                if position + chunk.data.len() as u64 >= target {
+                    // A target inside the header still gets one row
+                    let remaining = target.saturating_sub(position) as usize;
+                    chunk.rows = chunk.data.truncate_rows(remaining, options.format) as u32;
+                    reached_target = true;
+                }
+            }
+
+            output.write_chunk(&chunk.data)?;
+            position += chunk.data.len() as u64;
+            stats.rows += chunk.rows as u64;
+            stats.bytes += chunk.data.len() as u64;
+            stats.corrupted += chunk.corrupted;
+            stats.duplicated += chunk.duplicated;
+            observer.chunk_written(stats, position);
+
+            if reached_target {
+                break 'batches;
+            }
+        }
+    }
+
+    output.finish()?;
+    Ok(())
+}
+
+struct GeneratedChunk {
+    data: EncodedChunk,
+    rows: u32,
+    corrupted: usize,
+    duplicated: usize,
+}
+
+/// Generates and encodes the rows of one chunk in memory. Every chunk
+/// draws from its own RNG stream, so chunks can be produced on any thread
+/// in any order.
+fn generate_chunk(
+    schema: &Schema,
+    options: &GenerateOptions,
+    file: &OutputFile,
+    base_seed: u64,
+    chunk: u32,
+) -> Result<GeneratedChunk, csv::Error> {
+    let stream = (file.shard as u64) << 32 | chunk as u64;
+    
+    if options.fast_rng {
+        // Xoshiro has no stream parameter, so the stream is folded into the
+        // seed; seed_from_u64 runs it through SplitMix64, which spreads
+        // neighbouring values far apart
+        let seed = |stream: u64| base_seed ^ stream.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        let rng = Xoshiro256PlusPlus::seed_from_u64(seed(stream));
+        let noise_rng = Xoshiro256PlusPlus::seed_from_u64(seed(NOISE_STREAM | stream));
+        let null_rng = Xoshiro256PlusPlus::seed_from_u64(seed(NULL_STREAM | stream));
+        return generate_rows(schema, options, file, chunk, [rng, noise_rng, null_rng]);
+    }
+    
+    // ChaCha8 output is stable across platforms and rand releases, so a
+    // given seed always produces the same file
+    let mut rng = ChaCha8Rng::seed_from_u64(base_seed);
+    rng.set_stream(stream);
+    let mut noise_rng = ChaCha8Rng::seed_from_u64(base_seed);
+    noise_rng.set_stream(NOISE_STREAM | stream);
+    let mut null_rng = ChaCha8Rng::seed_from_u64(base_seed);
+    null_rng.set_stream(NULL_STREAM | stream);
+    generate_rows(schema, options, file, chunk, [rng, noise_rng, null_rng])
+}
+
+fn generate_rows<R: Rng>(
+    schema: &Schema,
+    options: &GenerateOptions,
+    file: &OutputFile,
+    chunk: u32,
+    [mut rng, mut noise_rng, mut null_rng]: [R; 3],
+) -> Result<GeneratedChunk, csv::Error> {
+    let first_row = file.first_row + chunk * CHUNK_SIZE;
+    let end_row = (first_row + CHUNK_SIZE).min(file.end_row);
+    let mut encoder = ChunkEncoder::new(options.format, schema);
+    let mut fields = Vec::with_capacity(schema.columns.len());
+    let mut corrupted = 0;
+    let mut duplicated = 0;
+    let mut recent: Vec<Vec<String>> = Vec::new();
+    
+    for row in first_row..end_row {
+        schema.generate_row(&mut rng, row as u64 + options.row_offset, &mut fields);
+        if schema.has_nulls() {
+            schema.apply_nulls(&mut null_rng, &mut fields);
+        }
+        
+        if options.duplicate_rate > 0.0 {
+            if !recent.is_empty() && noise_rng.gen_bool(options.duplicate_rate) {
+                let previous = &recent[noise_rng.gen_range(0..recent.len())];
+                generator_noise::duplicate_row(schema, &mut noise_rng, previous, &mut fields);
+                duplicated += 1;
+            }
+            
+            if recent.len() < DUPLICATE_WINDOW {
+                recent.push(fields.clone());
+            } else {
+                recent[row as usize % DUPLICATE_WINDOW].clone_from(&fields);
+            }
+        }
+        
+        if options.error_rate > 0.0 && noise_rng.gen_bool(options.error_rate) {
+            corrupted += 1;
+            if generator_noise::corrupt_row(schema, &mut noise_rng, &mut fields) == Corruption::StrayQuote {
+                // Quoting would turn the damage back into valid CSV
+                encoder.write_raw_row(&fields)?;
+                continue;
+            }
+        }
+        
+        encoder.write_row(&fields)?;
+    }
+
+    let data = encoder.finish()?;
+    Ok(GeneratedChunk { data, rows: end_row - first_row, corrupted, duplicated })
+}
@@ -0,0 +1,105 @@
+use crate::generator_schema::{ColumnKind, Schema};
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+const BAD_NUMBERS: [&str; 6] = ["N/A", "abc", "12,5", "3.14.15", "-", "1e"];
+const BAD_DATE_FORMATS: [&str; 4] = ["%d/%m/%Y", "%m-%d-%y", "%Y%m%d", "%b %e %Y"];
+
+/// The kinds of damage `--error-rate` can do to a row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Corruption {
+    MissingField,
+    BadNumber,
+    BadDate,
+    /// Opens a quoted field that is never closed. The row must be written
+    /// verbatim, since escaping it would produce valid CSV again.
+    StrayQuote,
+}
+
+/// How `--duplicate-rate` repeats an earlier row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Duplicate {
+    /// Every field copied.
+    Exact,
+    /// Fresh values that reuse the earlier row's sequence (id) columns.
+    SameId,
+    /// The earlier row's descriptive fields (customer, product, date, ...)
+    /// with a fresh id and fresh numeric values.
+    SameAttributes,
+}
+
+/// Turns the freshly generated `fields` into a duplicate of `previous`.
+pub fn duplicate_row<R: Rng>(schema: &Schema, rng: &mut R, previous: &[String], fields: &mut [String]) -> Duplicate {
+    let duplicate = match rng.gen_range(0..3) {
+        0 => Duplicate::Exact,
+        1 => Duplicate::SameId,
+        _ => Duplicate::SameAttributes,
+    };
+
+    for (index, column) in schema.columns.iter().enumerate() {
+        let copy = match duplicate {
+            Duplicate::Exact => true,
+            Duplicate::SameId => matches!(column.kind, ColumnKind::Sequence { .. }),
+            Duplicate::SameAttributes => !matches!(
+                column.kind,
+                ColumnKind::Sequence { .. } | ColumnKind::Integer { .. } | ColumnKind::Float { .. }
+            ),
+        };
+        if copy {
+            fields[index].clone_from(&previous[index]);
+        }
+    }
+
+    duplicate
+}
+
+/// Damages one generated row in place and returns what was done to it.
+/// Corruptions that need a column type the schema lacks fall back to
+/// dropping a field.
+pub fn corrupt_row<R: Rng>(schema: &Schema, rng: &mut R, fields: &mut Vec<String>) -> Corruption {
+    let numeric: Vec<usize> = columns_where(schema, |kind| {
+        matches!(kind, ColumnKind::Sequence { .. } | ColumnKind::Integer { .. } | ColumnKind::Float { .. })
+    });
+    let dates: Vec<usize> = columns_where(schema, |kind| matches!(kind, ColumnKind::Date { .. }));
+
+    let corruption = match rng.gen_range(0..4) {
+        1 if !numeric.is_empty() => Corruption::BadNumber,
+        2 if !dates.is_empty() => Corruption::BadDate,
+        3 => Corruption::StrayQuote,
+        _ => Corruption::MissingField,
+    };
+
+    match corruption {
+        Corruption::MissingField => {
+            let index = rng.gen_range(0..fields.len());
+            fields.remove(index);
+        }
+        Corruption::BadNumber => {
+            let index = *numeric.choose(rng).unwrap();
+            fields[index] = BAD_NUMBERS.choose(rng).unwrap().to_string();
+        }
+        Corruption::BadDate => {
+            let index = *dates.choose(rng).unwrap();
+            // Same date in the wrong format, so it looks plausible to a human
+            if let Ok(date) = chrono::NaiveDate::parse_from_str(&fields[index], "%Y-%m-%d") {
+                fields[index] = date.format(BAD_DATE_FORMATS.choose(rng).unwrap()).to_string();
+            }
+        }
+        Corruption::StrayQuote => {
+            let index = rng.gen_range(0..fields.len());
+            fields[index].insert(0, '"');
+        }
+    }
+
+    corruption
+}
+
+fn columns_where(schema: &Schema, predicate: impl Fn(&ColumnKind) -> bool) -> Vec<usize> {
+    schema
+        .columns
+        .iter()
+        .enumerate()
+        .filter(|(_, column)| predicate(&column.kind))
+        .map(|(index, _)| index)
+        .collect()
+}
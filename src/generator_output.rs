@@ -0,0 +1,489 @@
+use crate::generator_schema::{ColumnKind, Schema};
+use csv::{Writer, WriterBuilder};
+use flate2::write::GzEncoder;
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+
+#[cfg(feature = "parquet")]
+use std::sync::Arc;
+
+/// File formats the generator can write. Every format receives the same
+/// generated values, so a seed produces the same dataset in each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Csv,
+    Tsv,
+    Jsonl,
+    Parquet,
+}
+
+impl OutputFormat {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "csv" => Ok(OutputFormat::Csv),
+            "tsv" => Ok(OutputFormat::Tsv),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            "parquet" if cfg!(feature = "parquet") => Ok(OutputFormat::Parquet),
+            "parquet" => Err("parquet output needs the `parquet` feature (cargo run --features parquet ...)".to_string()),
+            _ => Err(format!("unknown format '{}' (expected csv, tsv, jsonl, or parquet)", value)),
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Tsv => "tsv",
+            OutputFormat::Jsonl => "jsonl",
+            OutputFormat::Parquet => "parquet",
+        }
+    }
+
+    fn delimiter(self) -> u8 {
+        match self {
+            OutputFormat::Tsv => b'\t',
+            _ => b',',
+        }
+    }
+}
+
+/// Stream compression for the text formats, chosen with `--compress`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip(u32),
+    Zstd(i32),
+}
+
+impl Compression {
+    /// Parses `gzip`, `zstd`, or either with a level such as `zstd:19`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let (name, level) = match value.split_once(':') {
+            Some((name, level)) => {
+                let level = level
+                    .parse::<i32>()
+                    .map_err(|_| format!("invalid compression level '{}'", level))?;
+                (name, Some(level))
+            }
+            None => (value, None),
+        };
+
+        match (name, level) {
+            ("none", None) => Ok(Compression::None),
+            ("gzip", None) => Ok(Compression::Gzip(6)),
+            ("gzip", Some(level @ 0..=9)) => Ok(Compression::Gzip(level as u32)),
+            ("gzip", Some(_)) => Err("gzip levels range from 0 to 9".to_string()),
+            ("zstd", None) => Ok(Compression::Zstd(zstd::DEFAULT_COMPRESSION_LEVEL)),
+            ("zstd", Some(level @ 1..=22)) => Ok(Compression::Zstd(level)),
+            ("zstd", Some(_)) => Err("zstd levels range from 1 to 22".to_string()),
+            _ => Err(format!("unknown compression '{}' (expected gzip or zstd[:level])", value)),
+        }
+    }
+
+    /// File extension appended after the format's own extension.
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip(_) => Some("gz"),
+            Compression::Zstd(_) => Some("zst"),
+        }
+    }
+}
+
+/// Buffer between the encoders and the file. Compressors emit many small
+/// writes, so a large buffer saves a lot of syscalls.
+const WRITE_BUFFER_SIZE: usize = 1 << 20;
+
+/// Byte destination for the text formats, compressing if requested.
+pub enum ByteSink {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl ByteSink {
+    fn new(file: File, compression: Compression) -> io::Result<Self> {
+        let output = BufWriter::with_capacity(WRITE_BUFFER_SIZE, file);
+        Ok(match compression {
+            Compression::None => ByteSink::Plain(output),
+            Compression::Gzip(level) => ByteSink::Gzip(GzEncoder::new(output, flate2::Compression::new(level))),
+            Compression::Zstd(level) => ByteSink::Zstd(zstd::Encoder::new(output, level)?),
+        })
+    }
+
+    /// Writes any compression trailer and flushes the file.
+    fn finish(self) -> io::Result<()> {
+        match self {
+            ByteSink::Plain(mut output) => output.flush(),
+            ByteSink::Gzip(encoder) => encoder.finish()?.flush(),
+            ByteSink::Zstd(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
+
+impl Write for ByteSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ByteSink::Plain(output) => output.write(buf),
+            ByteSink::Gzip(encoder) => encoder.write(buf),
+            ByteSink::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ByteSink::Plain(output) => output.flush(),
+            ByteSink::Gzip(encoder) => encoder.flush(),
+            ByteSink::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// One chunk of rows, encoded on a worker thread.
+pub enum EncodedChunk {
+    Bytes(Vec<u8>),
+    #[cfg(feature = "parquet")]
+    Batch(arrow_array::RecordBatch),
+}
+
+impl EncodedChunk {
+    /// Drops the rows after the one that brings the chunk to `min_len`
+    /// bytes and returns how many rows are left. Text rows end at a newline,
+    /// except in CSV and TSV where a quoted value can hold newlines of its
+    /// own (JSON escapes them); Arrow batches are kept whole.
+    pub fn truncate_rows(&mut self, min_len: usize, format: OutputFormat) -> usize {
+        match self {
+            EncodedChunk::Bytes(data) => {
+                let quoting = matches!(format, OutputFormat::Csv | OutputFormat::Tsv);
+                let mut quoted = false;
+                let mut rows = 0;
+                for (index, &byte) in data.iter().enumerate() {
+                    // A doubled quote inside a quoted value toggles twice
+                    if byte == b'"' && quoting {
+                        quoted = !quoted;
+                    }
+                    if byte != b'\n' || quoted {
+                        continue;
+                    }
+                    rows += 1;
+                    if index + 1 >= min_len {
+                        data.truncate(index + 1);
+                        break;
+                    }
+                }
+                rows
+            }
+            #[cfg(feature = "parquet")]
+            EncodedChunk::Batch(batch) => batch.num_rows(),
+        }
+    }
+
+    /// Size of the encoded rows before compression. Arrow batches report
+    /// their in-memory size, which is only an estimate of the Parquet bytes.
+    pub fn len(&self) -> usize {
+        match self {
+            EncodedChunk::Bytes(data) => data.len(),
+            #[cfg(feature = "parquet")]
+            EncodedChunk::Batch(batch) => batch.get_array_memory_size(),
+        }
+    }
+}
+
+/// Encodes the rows of one chunk in the output format.
+pub enum ChunkEncoder<'a> {
+    Delimited { writer: Box<Writer<Vec<u8>>>, delimiter: u8 },
+    Jsonl { data: Vec<u8>, schema: &'a Schema },
+    #[cfg(feature = "parquet")]
+    Parquet { columns: Vec<Vec<Option<String>>>, schema: &'a Schema },
+}
+
+impl<'a> ChunkEncoder<'a> {
+    pub fn new(format: OutputFormat, schema: &'a Schema) -> Self {
+        match format {
+            OutputFormat::Csv | OutputFormat::Tsv => ChunkEncoder::Delimited {
+                writer: Box::new(delimited_writer(Vec::new(), format.delimiter())),
+                delimiter: format.delimiter(),
+            },
+            OutputFormat::Jsonl => ChunkEncoder::Jsonl { data: Vec::new(), schema },
+            #[cfg(feature = "parquet")]
+            OutputFormat::Parquet => ChunkEncoder::Parquet {
+                columns: vec![Vec::new(); schema.columns.len()],
+                schema,
+            },
+            #[cfg(not(feature = "parquet"))]
+            OutputFormat::Parquet => unreachable!("rejected by OutputFormat::parse"),
+        }
+    }
+
+    pub fn write_row(&mut self, fields: &[String]) -> Result<(), csv::Error> {
+        match self {
+            ChunkEncoder::Delimited { writer, .. } => writer.write_record(fields),
+            ChunkEncoder::Jsonl { data, schema } => {
+                let row: serde_json::Map<String, serde_json::Value> = schema
+                    .columns
+                    .iter()
+                    .zip(fields)
+                    .map(|(column, value)| (column.name.clone(), json_value(&column.kind, value)))
+                    .collect();
+                serde_json::to_writer(&mut *data, &row).map_err(std::io::Error::from)?;
+                data.push(b'\n');
+                Ok(())
+            }
+            #[cfg(feature = "parquet")]
+            ChunkEncoder::Parquet { columns, .. } => {
+                // Short (corrupted) rows leave the trailing columns null, as
+                // do empty values from `--null-rate`
+                for (index, column) in columns.iter_mut().enumerate() {
+                    column.push(fields.get(index).filter(|value| !value.is_empty()).cloned());
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Writes a row without any quoting or escaping, for deliberately
+    /// malformed output. Formats that can't represent broken rows fall back
+    /// to a normal write.
+    pub fn write_raw_row(&mut self, fields: &[String]) -> Result<(), csv::Error> {
+        let ChunkEncoder::Delimited { writer, delimiter } = self else {
+            return self.write_row(fields);
+        };
+
+        let delimiter = *delimiter;
+        let taken = std::mem::replace(&mut **writer, delimited_writer(Vec::new(), delimiter));
+        let mut data = taken.into_inner().map_err(|e| e.into_error())?;
+        data.extend_from_slice(fields.join(&(delimiter as char).to_string()).as_bytes());
+        data.push(b'\n');
+        **writer = delimited_writer(data, delimiter);
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<EncodedChunk, csv::Error> {
+        match self {
+            ChunkEncoder::Delimited { writer, .. } => {
+                Ok(EncodedChunk::Bytes(writer.into_inner().map_err(|e| e.into_error())?))
+            }
+            ChunkEncoder::Jsonl { data, .. } => Ok(EncodedChunk::Bytes(data)),
+            #[cfg(feature = "parquet")]
+            ChunkEncoder::Parquet { columns, schema } => {
+                let arrays = schema
+                    .columns
+                    .iter()
+                    .zip(columns)
+                    .map(|(column, values)| arrow_column(&column.kind, values))
+                    .collect();
+                arrow_array::RecordBatch::try_new(arrow_schema(schema), arrays)
+                    .map_err(|e| std::io::Error::other(e).into())
+                    .map(EncodedChunk::Batch)
+            }
+        }
+    }
+}
+
+/// Destination for the encoded chunks of one output file.
+pub enum FileWriter {
+    Bytes(ByteSink),
+    #[cfg(feature = "parquet")]
+    Parquet(Box<parquet::arrow::ArrowWriter<File>>),
+}
+
+impl FileWriter {
+    /// Creates `path` and writes the header, if the format has one.
+    pub fn create(
+        path: &str,
+        format: OutputFormat,
+        compression: Compression,
+        schema: &Schema,
+    ) -> Result<Self, Box<dyn Error>> {
+        let file = File::create(path)?;
+
+        match format {
+            OutputFormat::Csv | OutputFormat::Tsv => {
+                let mut writer = delimited_writer(ByteSink::new(file, compression)?, format.delimiter());
+                writer.write_record(schema.header())?;
+                writer.flush()?;
+                Ok(FileWriter::Bytes(writer.into_inner().map_err(|e| e.into_error())?))
+            }
+            OutputFormat::Jsonl => Ok(FileWriter::Bytes(ByteSink::new(file, compression)?)),
+            #[cfg(feature = "parquet")]
+            OutputFormat::Parquet => Ok(FileWriter::Parquet(Box::new(parquet::arrow::ArrowWriter::try_new(
+                file,
+                arrow_schema(schema),
+                None,
+            )?))),
+            #[cfg(not(feature = "parquet"))]
+            OutputFormat::Parquet => unreachable!("rejected by OutputFormat::parse"),
+        }
+    }
+
+    /// Opens an existing csv, tsv, or jsonl file for appending. The header
+    /// is already there, so only rows are written.
+    pub fn append(path: &str) -> Result<Self, Box<dyn Error>> {
+        let mut file = OpenOptions::new().read(true).append(true).open(path)?;
+
+        // A file cut off mid-line would glue the first new row onto the last
+        let length = file.metadata()?.len();
+        let mut last = [b'\n'];
+        if length > 0 {
+            file.seek(SeekFrom::Start(length - 1))?;
+            file.read_exact(&mut last)?;
+        }
+        let mut output = ByteSink::new(file, Compression::None)?;
+        if last[0] != b'\n' {
+            output.write_all(b"\n")?;
+        }
+        Ok(FileWriter::Bytes(output))
+    }
+
+    /// Bytes in the file so far, buffered ones included. Only known for
+    /// uncompressed text output.
+    pub fn position(&mut self) -> io::Result<Option<u64>> {
+        match self {
+            FileWriter::Bytes(ByteSink::Plain(output)) => {
+                let buffered = output.buffer().len() as u64;
+                Ok(Some(output.get_mut().stream_position()? + buffered))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    pub fn write_chunk(&mut self, chunk: &EncodedChunk) -> Result<(), Box<dyn Error>> {
+        match (self, chunk) {
+            (FileWriter::Bytes(output), EncodedChunk::Bytes(data)) => output.write_all(data)?,
+            #[cfg(feature = "parquet")]
+            (FileWriter::Parquet(writer), EncodedChunk::Batch(batch)) => writer.write(batch)?,
+            #[cfg(feature = "parquet")]
+            _ => unreachable!("chunks are encoded in the file's format"),
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        match self {
+            FileWriter::Bytes(output) => output.finish()?,
+            #[cfg(feature = "parquet")]
+            FileWriter::Parquet(writer) => {
+                writer.close()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Missing-field corruption produces short rows, so the writer must accept
+/// varying record lengths.
+fn delimited_writer<W: Write>(output: W, delimiter: u8) -> Writer<W> {
+    WriterBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_writer(output)
+}
+
+/// Numeric columns become JSON numbers; anything that doesn't parse (such as
+/// an injected bad number) stays a string.
+/// Empty values are the generator's nulls and become JSON `null`.
+fn json_value(kind: &ColumnKind, value: &str) -> serde_json::Value {
+    if value.is_empty() {
+        return serde_json::Value::Null;
+    }
+    let number = match kind {
+        ColumnKind::Sequence { .. } | ColumnKind::Integer { .. } => {
+            value.parse::<i64>().ok().map(serde_json::Value::from)
+        }
+        ColumnKind::Float { .. } => value.parse::<f64>().ok().map(serde_json::Value::from),
+        _ => None,
+    };
+    number.unwrap_or_else(|| serde_json::Value::from(value))
+}
+
+#[cfg(feature = "parquet")]
+fn arrow_schema(schema: &Schema) -> Arc<arrow_schema::Schema> {
+    use arrow_schema::{DataType, Field};
+
+    let fields: Vec<Field> = schema
+        .columns
+        .iter()
+        .map(|column| {
+            let data_type = match column.kind {
+                ColumnKind::Sequence { .. } | ColumnKind::Integer { .. } => DataType::Int64,
+                ColumnKind::Float { .. } => DataType::Float64,
+                _ => DataType::Utf8,
+            };
+            Field::new(&column.name, data_type, true)
+        })
+        .collect();
+    Arc::new(arrow_schema::Schema::new(fields))
+}
+
+/// Values that don't parse as the column's type are stored as nulls.
+#[cfg(feature = "parquet")]
+fn arrow_column(kind: &ColumnKind, values: Vec<Option<String>>) -> arrow_array::ArrayRef {
+    use arrow_array::{Float64Array, Int64Array, StringArray};
+
+    match kind {
+        ColumnKind::Sequence { .. } | ColumnKind::Integer { .. } => Arc::new(
+            values
+                .iter()
+                .map(|v| v.as_deref().and_then(|v| v.parse::<i64>().ok()))
+                .collect::<Int64Array>(),
+        ),
+        ColumnKind::Float { .. } => Arc::new(
+            values
+                .iter()
+                .map(|v| v.as_deref().and_then(|v| v.parse::<f64>().ok()))
+                .collect::<Float64Array>(),
+        ),
+        _ => Arc::new(values.into_iter().collect::<StringArray>()),
+    }
+}
+
+/// Reads an existing output file and returns the row number that continues
+/// it: one past the highest value of the schema's first sequence column, or
+/// the number of rows if the schema has no sequence. Rows whose id can't be
+/// parsed (e.g. from `--error-rate`) are skipped.
+pub fn next_row(path: &str, format: OutputFormat, schema: &Schema) -> Result<u64, Box<dyn Error>> {
+    let sequence = schema.columns.iter().enumerate().find_map(|(index, column)| match column.kind {
+        ColumnKind::Sequence { start } => Some((index, start)),
+        _ => None,
+    });
+    let mut rows = 0;
+    let mut max_id: Option<u64> = None;
+
+    match format {
+        OutputFormat::Csv | OutputFormat::Tsv => {
+            let mut reader = csv::ReaderBuilder::new()
+                .delimiter(format.delimiter())
+                .flexible(true)
+                .from_path(path)?;
+            if reader.headers()?.iter().ne(schema.header()) {
+                return Err(format!("the header of {} doesn't match the schema", path).into());
+            }
+            for record in reader.records() {
+                let Ok(record) = record else { continue };
+                rows += 1;
+                if let Some((index, _)) = sequence {
+                    let id = record.get(index).and_then(|value| value.parse::<u64>().ok());
+                    max_id = max_id.max(id);
+                }
+            }
+        }
+        OutputFormat::Jsonl => {
+            for line in BufReader::new(File::open(path)?).lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                rows += 1;
+                if let (Some((index, _)), Ok(value)) = (sequence, serde_json::from_str::<serde_json::Value>(&line)) {
+                    max_id = max_id.max(value[&schema.columns[index].name].as_u64());
+                }
+            }
+        }
+        OutputFormat::Parquet => return Err("parquet files can't be appended to".into()),
+    }
+
+    Ok(match (sequence, max_id) {
+        (Some((_, start)), Some(max_id)) => (max_id + 1).saturating_sub(start),
+        _ => rows,
+    })
+}
@@ -0,0 +1,520 @@
+use chrono::{Datelike, NaiveDate};
+use fake::faker::{address::en::CityName, company::en::Buzzword, internet::en::FreeEmail, name::en::Name};
+use fake::Fake;
+use rand::Rng;
+use rand_distr::{Distribution, LogNormal, Zipf};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt::Write;
+use std::path::Path;
+
+/// Describes the columns of a generated CSV. Loaded from a TOML or JSON file
+/// with `--schema`, or built in via [`Schema::sales`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schema {
+    pub columns: Vec<Column>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Column {
+    pub name: String,
+    /// Probability of writing an empty value instead of a generated one.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub null_rate: f64,
+    #[serde(flatten)]
+    pub kind: ColumnKind,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ColumnKind {
+    /// Row number, starting at `start`.
+    Sequence {
+        #[serde(default = "default_start")]
+        start: u64,
+    },
+    /// Uniform integer in `min..=max`.
+    Integer { min: i64, max: i64 },
+    /// Float in `min..=max`, written with `decimals` places.
+    Float {
+        min: f64,
+        max: f64,
+        #[serde(default = "default_decimals")]
+        decimals: usize,
+        #[serde(default)]
+        distribution: NumericDistribution,
+    },
+    /// One of a fixed pool of values.
+    Choice {
+        values: Vec<String>,
+        #[serde(default)]
+        distribution: ChoiceDistribution,
+    },
+    /// A random first name followed by a random last name.
+    FullName {
+        first_names: Vec<String>,
+        last_names: Vec<String>,
+    },
+    /// Date in `from..=to`, formatted as `YYYY-MM-DD`. `month_weights`
+    /// (January first) makes some months proportionally busier, and
+    /// `weekday_weights` (Monday first) does the same for days of the week.
+    Date {
+        from: NaiveDate,
+        to: NaiveDate,
+        #[serde(default)]
+        month_weights: Option<Vec<f64>>,
+        #[serde(default)]
+        weekday_weights: Option<Vec<f64>>,
+    },
+    /// Realistic value from the `fake` crate.
+    Fake { faker: FakeKind },
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FakeKind {
+    Name,
+    Email,
+    City,
+    /// A marketing adjective plus a product type, e.g. "Ergonomic Monitor".
+    Product,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ChoiceDistribution {
+    #[default]
+    Uniform,
+    /// Earlier values are more popular; the k-th value is picked with
+    /// probability proportional to `1 / k^exponent`.
+    Zipf { exponent: f64 },
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum NumericDistribution {
+    #[default]
+    Uniform,
+    /// Log-normal with the given parameters of the underlying normal
+    /// distribution, clamped to the column's `min..=max`.
+    LogNormal { mu: f64, sigma: f64 },
+}
+
+/// Holiday-heavy order volume used by [`Schema::skewed`].
+const SEASONAL_MONTH_WEIGHTS: [f64; 12] = [0.8, 0.7, 0.9, 0.9, 1.0, 1.0, 1.0, 1.1, 1.0, 1.1, 1.6, 2.0];
+
+/// Weekday-heavy volume, e.g. B2B orders placed during office hours.
+pub const BUSINESS_WEEKDAY_WEIGHTS: [f64; 7] = [1.0, 1.1, 1.1, 1.1, 0.9, 0.25, 0.15];
+
+/// Weekend-heavy volume, e.g. consumer retail.
+pub const RETAIL_WEEKDAY_WEIGHTS: [f64; 7] = [0.8, 0.8, 0.85, 0.9, 1.1, 1.5, 1.3];
+
+/// Gives up on rejection sampling a weighted date after this many draws and
+/// keeps the last one, so a range covering only zero-weight months still
+/// terminates.
+const MAX_DATE_DRAWS: usize = 1_000;
+
+const PRODUCT_TYPES: [&str; 16] = [
+    "Laptop", "Mouse", "Keyboard", "Monitor", "Headphones", "Tablet", "Phone", "Speaker",
+    "Webcam", "Router", "Charger", "Smartwatch", "Printer", "Microphone", "Dock", "Drive",
+];
+
+fn default_start() -> u64 {
+    1
+}
+
+fn default_decimals() -> usize {
+    2
+}
+
+impl Schema {
+    /// The built-in sales schema, matching `SalesRecord`.
+    pub fn sales() -> Self {
+        let strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect();
+
+        Self {
+            columns: vec![
+                Column::new("id", ColumnKind::Sequence { start: 1 }),
+                Column::new("customer_name", ColumnKind::FullName {
+                    first_names: strings(&["John", "Jane", "Bob", "Alice", "Charlie", "Diana", "Eve", "Frank"]),
+                    last_names: strings(&["Smith", "Johnson", "Williams", "Brown", "Jones", "Garcia", "Miller", "Davis"]),
+                }),
+                Column::new("product", ColumnKind::Choice {
+                    values: strings(&["Laptop", "Mouse", "Keyboard", "Monitor", "Headphones", "Tablet", "Phone", "Speaker"]),
+                    distribution: ChoiceDistribution::Uniform,
+                }),
+                Column::new("quantity", ColumnKind::Integer { min: 1, max: 10 }),
+                Column::new("price", ColumnKind::Float {
+                    min: 10.0,
+                    max: 1000.0,
+                    decimals: 2,
+                    distribution: NumericDistribution::Uniform,
+                }),
+                Column::new("date", ColumnKind::Date {
+                    from: NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(),
+                    to: NaiveDate::from_ymd_opt(2024, 12, 31).unwrap(),
+                    month_weights: None,
+                    weekday_weights: None,
+                }),
+                Column::new("region", ColumnKind::Choice {
+                    values: strings(&["North", "South", "East", "West", "Central"]),
+                    distribution: ChoiceDistribution::Uniform,
+                }),
+            ],
+        }
+    }
+
+    /// The sales schema with faker-generated names and products, plus email
+    /// and city columns. Cardinality is far higher than the 8×8 name pool,
+    /// which makes group-by benchmarks more representative.
+    pub fn realistic_sales() -> Self {
+        let mut schema = Self::sales();
+
+        for column in &mut schema.columns {
+            match column.name.as_str() {
+                "customer_name" => column.kind = ColumnKind::Fake { faker: FakeKind::Name },
+                "product" => column.kind = ColumnKind::Fake { faker: FakeKind::Product },
+                _ => {}
+            }
+        }
+        schema.columns.push(Column::new("customer_email", ColumnKind::Fake { faker: FakeKind::Email }));
+        schema.columns.push(Column::new("city", ColumnKind::Fake { faker: FakeKind::City }));
+
+        schema
+    }
+
+    /// Applies realistic skew to the sales columns: Zipf-distributed product
+    /// popularity, log-normal prices, and seasonal order dates. Columns that
+    /// don't support a distribution (e.g. faker products) are left alone.
+    pub fn skewed(mut self) -> Self {
+        for column in &mut self.columns {
+            match (column.name.as_str(), &mut column.kind) {
+                ("product", ColumnKind::Choice { distribution, .. }) => {
+                    *distribution = ChoiceDistribution::Zipf { exponent: 1.1 };
+                }
+                ("price", ColumnKind::Float { distribution, .. }) => {
+                    // Median around 100 with a long tail of expensive orders
+                    *distribution = NumericDistribution::LogNormal { mu: 100f64.ln(), sigma: 0.9 };
+                }
+                ("date", ColumnKind::Date { month_weights, .. }) => {
+                    *month_weights = Some(SEASONAL_MONTH_WEIGHTS.to_vec());
+                }
+                _ => {}
+            }
+        }
+        self
+    }
+
+    /// Narrows or moves every date column to the given range. Either end
+    /// can be left as the schema has it.
+    pub fn with_date_range(mut self, from: Option<NaiveDate>, to: Option<NaiveDate>) -> Self {
+        for column in &mut self.columns {
+            if let ColumnKind::Date { from: column_from, to: column_to, .. } = &mut column.kind {
+                *column_from = from.unwrap_or(*column_from);
+                *column_to = to.unwrap_or(*column_to);
+            }
+        }
+        self
+    }
+
+    /// Applies a weekly pattern (Monday first) to every date column.
+    pub fn with_weekday_weights(mut self, weights: &[f64]) -> Self {
+        for column in &mut self.columns {
+            if let ColumnKind::Date { weekday_weights, .. } = &mut column.kind {
+                *weekday_weights = Some(weights.to_vec());
+            }
+        }
+        self
+    }
+
+    /// Draws `column` from `values` instead of its built-in pool, keeping
+    /// the column's distribution if it already was a choice.
+    pub fn with_values(mut self, column: &str, values: Vec<String>) -> Result<Self, Box<dyn Error>> {
+        let target = self
+            .columns
+            .iter_mut()
+            .find(|c| c.name == column)
+            .ok_or_else(|| format!("the schema has no '{}' column", column))?;
+        let distribution = match target.kind {
+            ColumnKind::Choice { distribution, .. } => distribution,
+            _ => ChoiceDistribution::Uniform,
+        };
+        target.kind = ColumnKind::Choice { values, distribution };
+        Ok(self)
+    }
+
+    /// A customers dimension table for `--with-customers`. Customers sign
+    /// up before `orders_from`, the first date in the sales file.
+    pub fn customers(orders_from: NaiveDate) -> Self {
+        let strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect();
+        let signup_from = NaiveDate::from_ymd_opt(2018, 1, 1).unwrap().min(orders_from);
+
+        Self {
+            columns: vec![
+                Column::new("customer_id", ColumnKind::Sequence { start: 1 }),
+                Column::new("name", ColumnKind::Fake { faker: FakeKind::Name }),
+                Column::new("email", ColumnKind::Fake { faker: FakeKind::Email }),
+                Column::new("segment", ColumnKind::Choice {
+                    values: strings(&["Consumer", "Small Business", "Enterprise", "Public Sector"]),
+                    distribution: ChoiceDistribution::Zipf { exponent: 1.0 },
+                }),
+                Column::new("signup_date", ColumnKind::Date {
+                    from: signup_from,
+                    to: orders_from.pred_opt().unwrap_or(orders_from).max(signup_from),
+                    month_weights: None,
+                    weekday_weights: None,
+                }),
+            ],
+        }
+    }
+
+    /// Replaces the sales schema's `customer_name` with a `customer_id`
+    /// pointing into a customers table of `count` rows. Schemas without a
+    /// customer name get the id after their first column.
+    pub fn with_customer_ids(mut self, count: u64) -> Self {
+        let customer_id = Column::new("customer_id", ColumnKind::Integer { min: 1, max: count as i64 });
+        match self.columns.iter().position(|c| c.name == "customer_name") {
+            Some(index) => self.columns[index] = customer_id,
+            None => self.columns.insert(1.min(self.columns.len()), customer_id),
+        }
+        self
+    }
+
+    /// The earliest date any date column can produce.
+    pub fn first_date(&self) -> Option<NaiveDate> {
+        self.columns
+            .iter()
+            .filter_map(|column| match column.kind {
+                ColumnKind::Date { from, .. } => Some(from),
+                _ => None,
+            })
+            .min()
+    }
+
+    /// Makes `column` empty in a `rate` fraction of rows.
+    pub fn with_null_rate(mut self, column: &str, rate: f64) -> Result<Self, Box<dyn Error>> {
+        let target = self
+            .columns
+            .iter_mut()
+            .find(|c| c.name == column)
+            .ok_or_else(|| format!("the schema has no '{}' column", column))?;
+        target.null_rate = rate;
+        Ok(self)
+    }
+
+    /// Loads a schema from a `.toml` or `.json` file.
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let content = std::fs::read_to_string(path)?;
+        let schema: Schema = match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("json") => serde_json::from_str(&content)?,
+            Some("toml") => toml::from_str(&content)?,
+            _ => return Err(format!("unsupported schema format for {} (use .toml or .json)", path).into()),
+        };
+        schema.validate()?;
+        Ok(schema)
+    }
+
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.columns.is_empty() {
+            return Err("schema must define at least one column".into());
+        }
+
+        for column in &self.columns {
+            if !(0.0..=1.0).contains(&column.null_rate) {
+                return Err(format!("column '{}': null_rate must be between 0.0 and 1.0", column.name).into());
+            }
+
+            let problem = match &column.kind {
+                ColumnKind::Integer { min, max } if min > max => Some("min is greater than max"),
+                ColumnKind::Float { min, max, .. } if min > max => Some("min is greater than max"),
+                ColumnKind::Float { distribution: NumericDistribution::LogNormal { sigma, .. }, .. }
+                    if !(sigma.is_finite() && *sigma >= 0.0) =>
+                {
+                    Some("log_normal sigma must be a non-negative number")
+                }
+                ColumnKind::Choice { values, .. } if values.is_empty() => Some("values must not be empty"),
+                ColumnKind::Choice { distribution: ChoiceDistribution::Zipf { exponent }, .. }
+                    if !(exponent.is_finite() && *exponent >= 0.0) =>
+                {
+                    Some("zipf exponent must be a non-negative number")
+                }
+                ColumnKind::FullName { first_names, last_names }
+                    if first_names.is_empty() || last_names.is_empty() =>
+                {
+                    Some("first_names and last_names must not be empty")
+                }
+                ColumnKind::Date { from, to, .. } if from > to => Some("from is after to"),
+                ColumnKind::Date { month_weights: Some(weights), .. }
+                    if weights.len() != 12 || weights.iter().any(|w| !(w.is_finite() && *w >= 0.0)) =>
+                {
+                    Some("month_weights must have 12 non-negative entries")
+                }
+                ColumnKind::Date { weekday_weights: Some(weights), .. }
+                    if weights.len() != 7 || weights.iter().any(|w| !(w.is_finite() && *w >= 0.0)) =>
+                {
+                    Some("weekday_weights must have 7 non-negative entries")
+                }
+                _ => None,
+            };
+
+            if let Some(problem) = problem {
+                return Err(format!("column '{}': {}", column.name, problem).into());
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn header(&self) -> Vec<&str> {
+        self.columns.iter().map(|c| c.name.as_str()).collect()
+    }
+
+    /// Blanks the fields of columns with a null rate, each with its own
+    /// probability. Drawing from a separate RNG keeps the other values of
+    /// a seeded dataset unchanged.
+    pub fn apply_nulls<R: Rng>(&self, rng: &mut R, fields: &mut [String]) {
+        for (field, column) in fields.iter_mut().zip(&self.columns) {
+            if column.null_rate > 0.0 && rng.gen_bool(column.null_rate) {
+                field.clear();
+            }
+        }
+    }
+
+    pub fn has_nulls(&self) -> bool {
+        self.columns.iter().any(|column| column.null_rate > 0.0)
+    }
+
+    /// Generates the values of row `row` (zero-based) into `fields`. The
+    /// strings are overwritten in place, so reusing `fields` across rows
+    /// avoids allocating per value.
+    pub fn generate_row<R: Rng>(&self, rng: &mut R, row: u64, fields: &mut Vec<String>) {
+        // Restores any field a corrupted previous row dropped
+        fields.resize_with(self.columns.len(), String::new);
+        for (field, column) in fields.iter_mut().zip(&self.columns) {
+            field.clear();
+            column.kind.write(rng, row, field);
+        }
+    }
+}
+
+impl Column {
+    pub fn new(name: &str, kind: ColumnKind) -> Self {
+        Self {
+            name: name.to_string(),
+            null_rate: 0.0,
+            kind,
+        }
+    }
+}
+
+impl ColumnKind {
+    /// Appends one value to `out`. Writing into the caller's buffer rather
+    /// than returning a `String` keeps generation allocation-free for
+    /// everything but faker values.
+    fn write<R: Rng>(&self, rng: &mut R, row: u64, out: &mut String) {
+        // Writing to a String never fails
+        let _ = match self {
+            ColumnKind::Sequence { start } => write!(out, "{}", start + row),
+            ColumnKind::Integer { min, max } => write!(out, "{}", rng.gen_range(*min..=*max)),
+            ColumnKind::Float { min, max, decimals, distribution } => {
+                let value = match distribution {
+                    NumericDistribution::Uniform => rng.gen_range(*min..=*max),
+                    NumericDistribution::LogNormal { mu, sigma } => LogNormal::new(*mu, *sigma)
+                        .expect("log_normal parameters are validated")
+                        .sample(rng)
+                        .clamp(*min, *max),
+                };
+                write!(out, "{:.*}", decimals, value)
+            }
+            ColumnKind::Choice { values, distribution } => {
+                let index = match distribution {
+                    ChoiceDistribution::Uniform => rng.gen_range(0..values.len()),
+                    ChoiceDistribution::Zipf { exponent } => {
+                        let rank: f64 = Zipf::new(values.len() as u64, *exponent)
+                            .expect("zipf parameters are validated")
+                            .sample(rng);
+                        rank as usize - 1
+                    }
+                };
+                out.push_str(&values[index]);
+                Ok(())
+            }
+            ColumnKind::FullName { first_names, last_names } => write!(
+                out,
+                "{} {}",
+                first_names[rng.gen_range(0..first_names.len())],
+                last_names[rng.gen_range(0..last_names.len())]
+            ),
+            ColumnKind::Date { from, to, month_weights, weekday_weights } => {
+                let days = (*to - *from).num_days();
+                let mut date = *from + chrono::Duration::days(rng.gen_range(0..=days));
+
+                // Rejection sampling keeps each draw cheap and needs no
+                // per-schema precomputation
+                if month_weights.is_some() || weekday_weights.is_some() {
+                    let max_weight = max_weight(month_weights) * max_weight(weekday_weights);
+                    for _ in 0..MAX_DATE_DRAWS {
+                        let month_weight = month_weights.as_ref().map_or(1.0, |w| w[date.month0() as usize]);
+                        let weekday_weight = weekday_weights
+                            .as_ref()
+                            .map_or(1.0, |w| w[date.weekday().num_days_from_monday() as usize]);
+                        if rng.gen::<f64>() * max_weight < month_weight * weekday_weight {
+                            break;
+                        }
+                        date = *from + chrono::Duration::days(rng.gen_range(0..=days));
+                    }
+                }
+
+                // Same output as "%Y-%m-%d" without parsing the format string
+                write!(out, "{:04}-{:02}-{:02}", date.year(), date.month(), date.day())
+            }
+            ColumnKind::Fake { faker } => match faker {
+                FakeKind::Name => write!(out, "{}", Name().fake_with_rng::<String, _>(rng)),
+                FakeKind::Email => write!(out, "{}", FreeEmail().fake_with_rng::<String, _>(rng)),
+                FakeKind::City => write!(out, "{}", CityName().fake_with_rng::<String, _>(rng)),
+                FakeKind::Product => write!(
+                    out,
+                    "{} {}",
+                    Buzzword().fake_with_rng::<String, _>(rng),
+                    PRODUCT_TYPES[rng.gen_range(0..PRODUCT_TYPES.len())]
+                ),
+            },
+        };
+    }
+}
+
+/// Reads a value pool: one value per line, or the first column of a `.csv`
+/// file with a header row. Blank lines and `#` comments are skipped.
+pub fn load_values(path: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let values: Vec<String> = if path.ends_with(".csv") {
+        let mut reader = csv::Reader::from_path(path)?;
+        let mut values = Vec::new();
+        for record in reader.records() {
+            if let Some(value) = record?.get(0) {
+                values.push(value.trim().to_string());
+            }
+        }
+        values.retain(|value| !value.is_empty());
+        values
+    } else {
+        std::fs::read_to_string(path)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect()
+    };
+
+    if values.is_empty() {
+        return Err(format!("{} contains no values", path).into());
+    }
+    Ok(values)
+}
+
+fn is_zero(value: &f64) -> bool {
+    *value == 0.0
+}
+
+fn max_weight(weights: &Option<Vec<f64>>) -> f64 {
+    weights.as_ref().map_or(1.0, |w| w.iter().cloned().fold(0.0, f64::max))
+}
@@ -0,0 +1,92 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Bytes before a file's parsed end kept to recognise it by: a file whose
+/// bytes there changed was rewritten rather than appended to.
+pub const TAIL_BYTES: usize = 4 * 1024;
+
+/// How far a file has been parsed, so that once it grows only the bytes
+/// after `bytes` need parsing.
+#[derive(Debug, Clone)]
+pub struct Extent {
+    /// The fingerprint of the file when it was parsed this far.
+    pub fingerprint: u64,
+    /// Bytes parsed, always up to the end of a line.
+    pub bytes: u64,
+    /// The header line, line break included, when the file has one; rows
+    /// read from the middle of the file are parsed under it.
+    pub header: Option<String>,
+    tail: Vec<u8>,
+}
+
+/// What became of a file since it was parsed up to an [`Extent`].
+#[derive(Debug)]
+pub enum Growth {
+    /// Whole lines were added after the parsed end; possibly none, when
+    /// only the file's modification time moved.
+    Appended(String),
+    /// The bytes before the parsed end changed, the file shrank, or its
+    /// last line is unfinished; it has to be parsed whole.
+    Changed,
+}
+
+impl Extent {
+    /// The extent of a file parsed from `content`. `None` unless `content`
+    /// ends with a line break, as a last row without one may still grow.
+    pub fn of(content: &[u8], fingerprint: u64, has_header: bool) -> Option<Self> {
+        if !content.ends_with(b"\n") {
+            return None;
+        }
+        let header = match has_header {
+            true => {
+                let end = content.iter().position(|&byte| byte == b'\n')? + 1;
+                Some(String::from_utf8_lossy(&content[..end]).into_owned())
+            }
+            false => None,
+        };
+        let tail = content[content.len().saturating_sub(TAIL_BYTES)..].to_vec();
+        Some(Self { fingerprint, bytes: content.len() as u64, header, tail })
+    }
+
+    /// Reads what was added to the file at `path` after this extent.
+    pub fn growth(&self, path: &Path) -> io::Result<Growth> {
+        let mut file = File::open(path)?;
+        if file.metadata()?.len() < self.bytes {
+            return Ok(Growth::Changed);
+        }
+        file.seek(SeekFrom::Start(self.bytes - self.tail.len() as u64))?;
+        let mut tail = vec![0; self.tail.len()];
+        file.read_exact(&mut tail)?;
+        if tail != self.tail {
+            return Ok(Growth::Changed);
+        }
+        let mut appended = String::new();
+        file.read_to_string(&mut appended)?;
+        if !appended.is_empty() && !appended.ends_with('\n') {
+            return Ok(Growth::Changed);
+        }
+        Ok(Growth::Appended(appended))
+    }
+
+    /// The extent once `appended`, from [`Extent::growth`], is parsed too.
+    pub fn advance(&self, appended: &str, fingerprint: u64) -> Self {
+        let mut tail = self.tail.clone();
+        tail.extend_from_slice(appended.as_bytes());
+        let tail = tail.split_off(tail.len().saturating_sub(TAIL_BYTES));
+        Self { fingerprint, bytes: self.bytes + appended.len() as u64, header: self.header.clone(), tail }
+    }
+
+    /// `appended` as a CSV document of its own, under the header when the
+    /// file has one.
+    pub fn document(&self, appended: &str) -> String {
+        let mut document = self.header.clone().unwrap_or_default();
+        document.push_str(appended);
+        document
+    }
+}
+
+/// Whether `appended` holds a line that is not blank.
+pub fn has_rows(appended: &str) -> bool {
+    appended.lines().any(|line| !line.trim().is_empty())
+}
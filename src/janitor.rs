@@ -0,0 +1,196 @@
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Where the janitor looks and how old files must be before they go.
+#[derive(Debug, Clone)]
+pub struct JanitorConfig {
+    /// Searched, subdirectories included, for temporary files.
+    pub temp_dirs: Vec<PathBuf>,
+    /// Where an upload streams its hidden `.part` file until it is complete.
+    pub upload_dir: PathBuf,
+    pub reports_dir: PathBuf,
+    /// Temporary files untouched for this long are orphans: whatever was
+    /// writing them crashed or was killed before it could clean up.
+    pub temp_max_age: Duration,
+    /// Reports older than this are deleted; `None` keeps them.
+    pub report_max_age: Option<Duration>,
+    /// Where exports wait to be downloaded through signed links.
+    pub artifacts_dir: PathBuf,
+    /// Artifacts older than this are deleted; `None` keeps them.
+    pub artifact_max_age: Option<Duration>,
+}
+
+/// Files removed and the bytes they held.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Reclaimed {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+impl Reclaimed {
+    fn add(&mut self, bytes: u64) {
+        self.files += 1;
+        self.bytes += bytes;
+    }
+
+    fn merge(&mut self, other: Reclaimed) {
+        self.files += other.files;
+        self.bytes += other.bytes;
+    }
+}
+
+/// What one sweep removed, by kind.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Sweep {
+    /// Hidden `.part` files of uploads that never completed.
+    pub upload_parts: Reclaimed,
+    /// Other `.part` and `.tmp` files: rewrites, erasures, snapshots.
+    pub temp_files: Reclaimed,
+    /// Reports past their retention.
+    pub expired_reports: Reclaimed,
+    /// Exports past their retention, whatever links to them say.
+    pub expired_artifacts: Reclaimed,
+    /// Files that could not be inspected or removed.
+    pub errors: Vec<String>,
+}
+
+impl Sweep {
+    pub fn total(&self) -> Reclaimed {
+        let mut total = self.upload_parts;
+        total.merge(self.temp_files);
+        total.merge(self.expired_reports);
+        total.merge(self.expired_artifacts);
+        total
+    }
+}
+
+/// Runs and running totals for `/metrics`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct JanitorStats {
+    pub interval_seconds: u64,
+    pub runs: u64,
+    pub last_run_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Since startup.
+    pub reclaimed: Reclaimed,
+    pub upload_parts: Reclaimed,
+    pub temp_files: Reclaimed,
+    pub expired_reports: Reclaimed,
+    pub expired_artifacts: Reclaimed,
+    pub last_sweep: Option<Sweep>,
+}
+
+impl JanitorStats {
+    pub fn new(interval_seconds: u64) -> Self {
+        Self { interval_seconds, ..Self::default() }
+    }
+
+    pub fn record(&mut self, sweep: Sweep) {
+        self.runs += 1;
+        self.last_run_at = Some(chrono::Utc::now());
+        self.reclaimed.merge(sweep.total());
+        self.upload_parts.merge(sweep.upload_parts);
+        self.temp_files.merge(sweep.temp_files);
+        self.expired_reports.merge(sweep.expired_reports);
+        self.expired_artifacts.merge(sweep.expired_artifacts);
+        self.last_sweep = Some(sweep);
+    }
+}
+
+/// Removes orphaned temporary files, expired reports and expired artifacts
+/// as of `now`.
+/// Blocking; a file that cannot be removed is noted and the sweep goes on.
+pub fn sweep(config: &JanitorConfig, now: SystemTime) -> Sweep {
+    let mut sweep = Sweep::default();
+    for dir in &config.temp_dirs {
+        sweep_temp_files(config, dir, now, &mut sweep);
+    }
+    if let Some(max_age) = config.report_max_age {
+        sweep.expired_reports = remove_expired(&config.reports_dir, max_age, now, &mut sweep.errors);
+    }
+    if let Some(max_age) = config.artifact_max_age {
+        sweep.expired_artifacts = remove_expired(&config.artifacts_dir, max_age, now, &mut sweep.errors);
+    }
+    sweep
+}
+
+/// Removes the files in `dir` older than `max_age`, except hidden ones:
+/// those are still being written and go as temporary files.
+fn remove_expired(dir: &Path, max_age: Duration, now: SystemTime, errors: &mut Vec<String>) -> Reclaimed {
+    let mut reclaimed = Reclaimed::default();
+    for (path, bytes) in files_older_than(dir, max_age, now, errors) {
+        let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        if !name.starts_with('.') && remove(&path, errors) {
+            reclaimed.add(bytes);
+        }
+    }
+    reclaimed
+}
+
+fn sweep_temp_files(config: &JanitorConfig, dir: &Path, now: SystemTime, sweep: &mut Sweep) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return,
+        Err(error) => {
+            sweep.errors.push(format!("{}: {}", dir.display(), error));
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        // Symlinks are neither followed nor removed
+        let Ok(metadata) = std::fs::symlink_metadata(&path) else {
+            continue;
+        };
+        if metadata.is_dir() {
+            sweep_temp_files(config, &path, now, sweep);
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let upload_part = dir == config.upload_dir && name.starts_with('.') && name.ends_with(".csv.part");
+        let temporary = (name.starts_with('.') && name.ends_with(".part")) || name.ends_with(".tmp");
+        if !metadata.is_file() || !(upload_part || temporary) || age(&metadata, now) < config.temp_max_age {
+            continue;
+        }
+        if remove(&path, &mut sweep.errors) {
+            let kind = if upload_part { &mut sweep.upload_parts } else { &mut sweep.temp_files };
+            kind.add(metadata.len());
+        }
+    }
+}
+
+/// Regular files directly in `dir` last modified more than `max_age` ago.
+fn files_older_than(dir: &Path, max_age: Duration, now: SystemTime, errors: &mut Vec<String>) -> Vec<(PathBuf, u64)> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Vec::new(),
+        Err(error) => {
+            errors.push(format!("{}: {}", dir.display(), error));
+            return Vec::new();
+        }
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let metadata = std::fs::symlink_metadata(entry.path()).ok()?;
+            (metadata.is_file() && age(&metadata, now) >= max_age).then(|| (entry.path(), metadata.len()))
+        })
+        .collect()
+}
+
+/// Time since the last write; zero for files modified in the future.
+fn age(metadata: &std::fs::Metadata, now: SystemTime) -> Duration {
+    metadata.modified().ok().and_then(|modified| now.duration_since(modified).ok()).unwrap_or_default()
+}
+
+fn remove(path: &Path, errors: &mut Vec<String>) -> bool {
+    match std::fs::remove_file(path) {
+        Ok(()) => true,
+        // Cleaned up by its writer in the meantime
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => false,
+        Err(error) => {
+            errors.push(format!("{}: {}", path.display(), error));
+            false
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! The Axum CSV processing server as a library. [`build_router`] returns
+//! the whole API over state from [`new_state`], so the server example,
+//! the integration tests and embedders all serve the same router, each
+//! with its own listener, or none with `tower::ServiceExt::oneshot`.
+
+#[allow(dead_code)]
+pub mod performance_utils;
+
+#[allow(dead_code)]
+pub mod processing;
+
+#[allow(dead_code)]
+mod locale;
+
+#[allow(dead_code)]
+mod report;
+
+#[cfg(any(feature = "kafka", feature = "sqlite"))]
+#[allow(dead_code)]
+mod sinks {
+    #[cfg(feature = "kafka")]
+    pub mod kafka;
+    #[cfg(feature = "sqlite")]
+    pub mod sqlite;
+}
+
+#[cfg(feature = "nats")]
+#[allow(dead_code)]
+mod sources {
+    pub mod nats;
+}
+
+#[cfg(feature = "sql")]
+#[allow(dead_code)]
+mod query;
+
+#[cfg(feature = "scripting")]
+#[allow(dead_code)]
+mod scripting;
+
+#[cfg(feature = "wasm")]
+#[allow(dead_code)]
+mod wasm;
+
+#[cfg(feature = "chaos")]
+#[allow(dead_code)]
+mod chaos;
+
+#[allow(dead_code)]
+pub mod aggregators;
+
+#[allow(dead_code)]
+pub mod column_stats;
+
+#[cfg(feature = "columnar")]
+#[allow(dead_code)]
+pub mod columnar;
+
+#[allow(dead_code)]
+mod incremental;
+
+#[allow(dead_code)]
+mod provenance;
+
+#[allow(dead_code)]
+mod partitions;
+
+#[allow(dead_code)]
+mod snapshots;
+
+#[allow(dead_code)]
+mod search;
+
+#[allow(dead_code)]
+mod enrichment;
+
+#[allow(dead_code)]
+mod dedup;
+
+#[allow(dead_code)]
+mod bloom;
+
+#[allow(dead_code)]
+mod encryption;
+
+#[allow(dead_code)]
+mod currency;
+
+#[allow(dead_code)]
+mod anonymize;
+
+#[allow(dead_code)]
+mod metrics_push;
+
+#[allow(dead_code)]
+mod body_limit;
+
+#[allow(dead_code)]
+mod quantile;
+
+#[allow(dead_code)]
+mod describe;
+
+#[allow(dead_code)]
+mod price_bands;
+
+#[allow(dead_code)]
+mod trends;
+
+#[allow(dead_code)]
+mod row_count;
+
+#[allow(dead_code)]
+mod dialect;
+
+#[allow(dead_code)]
+mod schema_evolution;
+
+#[allow(dead_code)]
+mod event_log;
+
+#[allow(dead_code)]
+mod janitor;
+
+#[allow(dead_code)]
+mod cost;
+
+#[allow(dead_code)]
+mod compression;
+
+#[allow(dead_code)]
+mod retention;
+
+#[allow(dead_code)]
+mod workspace;
+
+#[allow(dead_code)]
+mod fields;
+
+#[allow(dead_code)]
+mod artifacts;
+
+#[allow(dead_code)]
+pub mod request_log;
+
+#[allow(dead_code)]
+mod cache_benchmark;
+
+#[allow(dead_code)]
+mod alerts;
+
+#[allow(dead_code)]
+mod distributed;
+
+#[allow(dead_code)]
+mod worker_pool;
+
+#[allow(dead_code)]
+pub mod generator;
+
+#[allow(dead_code)]
+mod generator_noise;
+
+#[allow(dead_code)]
+mod generator_output;
+
+#[allow(dead_code)]
+pub mod generator_schema;
+
+mod server;
+
+pub use server::{build_router, new_state, register_aggregators, serve, spawn_background_tasks, AppState, ServerConfig, SharedState, DATA_DIRS};
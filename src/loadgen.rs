@@ -0,0 +1,704 @@
+use clap::{Arg, ArgAction, Command};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+#[allow(dead_code)]
+mod performance_utils {
+    include!("performance_utils.rs");
+}
+
+use performance_utils::{parse_size, LatencyRecorder, LatencySummary, SystemInfo};
+
+/// Layout version of the report `--output` writes.
+const REPORT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Operation {
+    Upload,
+    Process,
+    Analyze,
+}
+
+impl Operation {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "upload" => Some(Operation::Upload),
+            "process" => Some(Operation::Process),
+            "analyze" => Some(Operation::Analyze),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Operation::Upload => "upload",
+            Operation::Process => "process",
+            Operation::Analyze => "analyze",
+        }
+    }
+}
+
+/// How often each operation is picked, relative to the others.
+struct Mix {
+    weights: Vec<(Operation, u32)>,
+}
+
+impl Mix {
+    /// Parses `operation=weight` pairs separated by commas, e.g.
+    /// `process=3,analyze=3,upload=1`.
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut weights: Vec<(Operation, u32)> = Vec::new();
+        for pair in text.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (name, weight) = pair.split_once('=').unwrap_or((pair, "1"));
+            let operation = Operation::parse(name.trim())
+                .ok_or_else(|| format!("unknown operation '{}'; use upload, process or analyze", name.trim()))?;
+            let weight: u32 = weight.trim().parse().map_err(|_| format!("'{}' is not a whole-number weight", weight.trim()))?;
+            if weights.iter().any(|(other, _)| *other == operation) {
+                return Err(format!("operation '{}' is listed twice", operation.name()));
+            }
+            if weight > 0 {
+                weights.push((operation, weight));
+            }
+        }
+        if weights.is_empty() {
+            return Err("the mix needs at least one operation with a weight above 0".to_string());
+        }
+        Ok(Self { weights })
+    }
+
+    fn pick(&self, rng: &mut StdRng) -> Operation {
+        let total: u32 = self.weights.iter().map(|(_, weight)| weight).sum();
+        let mut roll = rng.gen_range(0..total);
+        for (operation, weight) in &self.weights {
+            if roll < *weight {
+                return *operation;
+            }
+            roll -= weight;
+        }
+        self.weights[0].0
+    }
+}
+
+/// When the clients stop: after a number of requests between them, or
+/// once a deadline has passed.
+enum Budget {
+    Requests(AtomicU64),
+    Until(Instant),
+}
+
+impl Budget {
+    /// Whether another request may be sent, claiming it if so.
+    fn take(&self) -> bool {
+        match self {
+            Budget::Requests(remaining) => remaining
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |remaining| remaining.checked_sub(1))
+                .is_ok(),
+            Budget::Until(deadline) => Instant::now() < *deadline,
+        }
+    }
+}
+
+/// The server and what the requests send it.
+struct Target {
+    http: reqwest::Client,
+    base: String,
+    /// Files on the server that `/process` and `/analyze` pick from.
+    files: Vec<String>,
+    upload_name: String,
+    upload: Vec<u8>,
+}
+
+/// What came back for one request.
+enum Outcome {
+    Ok,
+    Status(u16),
+    /// No response: the connection failed or the request timed out.
+    Failed(String),
+}
+
+struct Sample {
+    operation: Operation,
+    latency: Duration,
+    outcome: Outcome,
+}
+
+#[derive(Serialize)]
+struct LoadReport {
+    version: u32,
+    generator: String,
+    generated_at: chrono::DateTime<chrono::Utc>,
+    /// The machine the load came from, not the server's.
+    environment: SystemInfo,
+    server: String,
+    files: Vec<String>,
+    concurrency: usize,
+    mix: BTreeMap<&'static str, u32>,
+    duration_seconds: f64,
+    totals: OperationReport,
+    operations: Vec<OperationReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    soak: Option<SoakReport>,
+}
+
+/// Requests of one operation, or of all of them.
+#[derive(Serialize)]
+struct OperationReport {
+    operation: String,
+    requests: u64,
+    /// Requests answered with a status outside 2xx, or not answered.
+    errors: u64,
+    error_rate: f64,
+    requests_per_second: f64,
+    /// Requests by status code, with `failed` for those never answered.
+    statuses: BTreeMap<String, u64>,
+    /// Client-side latency of the successful requests, from sending the
+    /// request to reading the last byte of the response.
+    latency: Option<LatencySummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    first_error: Option<String>,
+}
+
+impl OperationReport {
+    fn new(operation: &str, samples: &[&Sample], seconds: f64) -> Self {
+        let mut latencies = LatencyRecorder::new();
+        let mut statuses: BTreeMap<String, u64> = BTreeMap::new();
+        let mut first_error = None;
+        for sample in samples {
+            let status = match &sample.outcome {
+                Outcome::Ok => {
+                    latencies.record(operation, sample.latency);
+                    continue;
+                }
+                Outcome::Status(status) => status.to_string(),
+                Outcome::Failed(error) => {
+                    first_error.get_or_insert_with(|| error.clone());
+                    "failed".to_string()
+                }
+            };
+            *statuses.entry(status).or_default() += 1;
+        }
+        let requests = samples.len() as u64;
+        let errors: u64 = statuses.values().sum();
+        let succeeded = requests - errors;
+        if succeeded > 0 {
+            statuses.insert("2xx".to_string(), succeeded);
+        }
+        Self {
+            operation: operation.to_string(),
+            requests,
+            errors,
+            error_rate: if requests > 0 { errors as f64 / requests as f64 } else { 0.0 },
+            requests_per_second: if seconds > 0.0 { requests as f64 / seconds } else { 0.0 },
+            statuses,
+            latency: latencies.summary().pop(),
+            first_error,
+        }
+    }
+}
+
+/// What the server held at one point of a soak run, from its `/health`.
+/// Figures an older or simpler server does not report are left empty.
+#[derive(Debug, Clone, Serialize)]
+struct MemorySample {
+    elapsed_seconds: f64,
+    rss_bytes: Option<u64>,
+    cache_bytes: Option<u64>,
+    cached_files: Option<u64>,
+    /// Upload and processing metrics the server keeps in memory.
+    metrics_in_memory: Option<u64>,
+}
+
+/// One figure compared between the end of the warm-up and the end of the
+/// run: it leaks when it grew by more than `limit`.
+#[derive(Debug, Serialize)]
+struct GrowthCheck {
+    measure: &'static str,
+    baseline: u64,
+    last: u64,
+    growth: i64,
+    limit: u64,
+    passed: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct SoakReport {
+    sample_every_seconds: u64,
+    warmup_seconds: u64,
+    passed: bool,
+    checks: Vec<GrowthCheck>,
+    samples: Vec<MemorySample>,
+}
+
+/// Reads one figure out of a sample.
+type Measure = fn(&MemorySample) -> Option<u64>;
+
+/// How much each soak figure may grow after the warm-up.
+struct GrowthLimits {
+    rss_bytes: u64,
+    cache_bytes: u64,
+    metrics_in_memory: u64,
+}
+
+#[tokio::main]
+async fn main() {
+    let matches = Command::new("loadgen")
+        .about("Fires a concurrent mix of uploads, /process and /analyze requests at a running CSV server and reports client-side latency and errors")
+        .arg(
+            Arg::new("server")
+                .long("server")
+                .value_name("URL")
+                .help("Server to load")
+                .env("LOADGEN_SERVER")
+                .default_value("http://127.0.0.1:3000")
+        )
+        .arg(
+            Arg::new("concurrency")
+                .short('c')
+                .long("concurrency")
+                .value_name("N")
+                .help("Clients sending requests at the same time, each waiting for its response before the next")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("8")
+        )
+        .arg(
+            Arg::new("duration")
+                .short('d')
+                .long("duration")
+                .value_name("DURATION")
+                .help("How long to send requests for: seconds, or a number followed by s, m, h or d")
+                .value_parser(parse_seconds)
+                .default_value("30")
+                .conflicts_with("requests")
+        )
+        .arg(
+            Arg::new("requests")
+                .short('n')
+                .long("requests")
+                .value_name("N")
+                .help("Send this many requests in total instead of running for --duration")
+                .value_parser(clap::value_parser!(u64))
+        )
+        .arg(
+            Arg::new("mix")
+                .long("mix")
+                .value_name("OPERATION=WEIGHT,...")
+                .help("Relative weights of upload, process and analyze requests")
+                .default_value("process=3,analyze=3,upload=1")
+        )
+        .arg(
+            Arg::new("file")
+                .long("file")
+                .value_name("NAME,...")
+                .help("Files on the server that /process and /analyze read, one picked at random per request")
+                .value_delimiter(',')
+                .default_value("small_data.csv")
+        )
+        .arg(
+            Arg::new("upload")
+                .long("upload")
+                .value_name("PATH")
+                .help("Local CSV file sent by each upload")
+                .default_value("sample_data/small_data.csv")
+        )
+        .arg(
+            Arg::new("timeout")
+                .long("timeout")
+                .value_name("SECONDS")
+                .help("Per-request timeout; a request past it counts as failed")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("30")
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .value_name("N")
+                .help("Seed of the operation picks, for a repeatable sequence")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("42")
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("FILE.json")
+                .help("Write the report as JSON")
+        )
+        .arg(
+            Arg::new("max-error-rate")
+                .long("max-error-rate")
+                .value_name("PERCENT")
+                .help("Exit with status 1 when more than this share of requests fail, for CI")
+                .value_parser(clap::value_parser!(f64))
+        )
+        .arg(
+            Arg::new("soak")
+                .long("soak")
+                .help("Sample the server's memory through the run and fail when it keeps growing after the warm-up")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("sample-every")
+                .long("sample-every")
+                .value_name("DURATION")
+                .help("How often --soak samples the server's /health")
+                .value_parser(parse_seconds)
+                .default_value("10")
+        )
+        .arg(
+            Arg::new("warmup")
+                .long("warmup")
+                .value_name("DURATION")
+                .help("Time for caches to fill before --soak takes its baseline")
+                .value_parser(parse_seconds)
+                .default_value("60")
+        )
+        .arg(
+            Arg::new("max-rss-growth")
+                .long("max-rss-growth")
+                .value_name("SIZE")
+                .help("Resident memory the server may gain after the warm-up, e.g. 100MB")
+                .value_parser(parse_size)
+                .default_value("100MB")
+        )
+        .arg(
+            Arg::new("max-cache-growth")
+                .long("max-cache-growth")
+                .value_name("SIZE")
+                .help("Record cache size the server may gain after the warm-up")
+                .value_parser(parse_size)
+                .default_value("50MB")
+        )
+        .arg(
+            Arg::new("max-metrics-growth")
+                .long("max-metrics-growth")
+                .value_name("N")
+                .help("Stored metrics the server may gain in memory after the warm-up")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("20000")
+        )
+        .get_matches();
+
+    if let Err(error) = run(&matches).await {
+        eprintln!("❌ {}", error);
+        std::process::exit(1);
+    }
+}
+
+async fn run(matches: &clap::ArgMatches) -> Result<(), Box<dyn Error>> {
+    let base = matches.get_one::<String>("server").unwrap().trim_end_matches('/').to_string();
+    let concurrency = (*matches.get_one::<usize>("concurrency").unwrap()).max(1);
+    let mix_text = matches.get_one::<String>("mix").unwrap();
+    let mix = Arc::new(Mix::parse(mix_text).map_err(|message| format!("invalid --mix: {}", message))?);
+    let timeout = Duration::from_secs(*matches.get_one::<u64>("timeout").unwrap());
+    let seed = *matches.get_one::<u64>("seed").unwrap();
+
+    let upload_path = matches.get_one::<String>("upload").unwrap();
+    let upload = match mix.weights.iter().any(|(operation, _)| *operation == Operation::Upload) {
+        true => std::fs::read(upload_path).map_err(|error| format!("cannot read {}: {}", upload_path, error))?,
+        false => Vec::new(),
+    };
+    let target = Arc::new(Target {
+        http: reqwest::Client::builder().timeout(timeout).build()?,
+        files: matches.get_many::<String>("file").unwrap().cloned().collect(),
+        upload_name: std::path::Path::new(upload_path).file_name().unwrap_or_default().to_string_lossy().to_string(),
+        upload,
+        base,
+    });
+    target
+        .http
+        .get(format!("{}/health", target.base))
+        .send()
+        .await
+        .map_err(|error| format!("cannot reach {}: {}", target.base, error))?;
+
+    let budget = Arc::new(match matches.get_one::<u64>("requests") {
+        Some(requests) => Budget::Requests(AtomicU64::new(*requests)),
+        None => Budget::Until(Instant::now() + Duration::from_secs(*matches.get_one::<u64>("duration").unwrap())),
+    });
+    match budget.as_ref() {
+        Budget::Requests(requests) => println!(
+            "🚦 Sending {} requests to {} from {} clients ({})",
+            requests.load(Ordering::Relaxed),
+            target.base,
+            concurrency,
+            mix_text
+        ),
+        Budget::Until(deadline) => println!(
+            "🚦 Loading {} from {} clients for {}s ({})",
+            target.base,
+            concurrency,
+            deadline.saturating_duration_since(Instant::now()).as_secs_f64().round(),
+            mix_text
+        ),
+    }
+
+    let start = Instant::now();
+    let sample_every = *matches.get_one::<u64>("sample-every").unwrap();
+    let load_done = CancellationToken::new();
+    let sampler = matches
+        .get_flag("soak")
+        .then(|| tokio::spawn(sample_memory(target.clone(), Duration::from_secs(sample_every), start, load_done.clone())));
+    let clients: Vec<_> = (0..concurrency)
+        .map(|client| tokio::spawn(client_loop(target.clone(), mix.clone(), budget.clone(), seed.wrapping_add(client as u64))))
+        .collect();
+    let mut samples = Vec::new();
+    for client in clients {
+        samples.extend(client.await?);
+    }
+    let seconds = start.elapsed().as_secs_f64();
+    load_done.cancel();
+    let soak = match sampler {
+        Some(sampler) => {
+            let warmup_seconds = *matches.get_one::<u64>("warmup").unwrap();
+            let limits = GrowthLimits {
+                rss_bytes: *matches.get_one::<u64>("max-rss-growth").unwrap(),
+                cache_bytes: *matches.get_one::<u64>("max-cache-growth").unwrap(),
+                metrics_in_memory: *matches.get_one::<u64>("max-metrics-growth").unwrap(),
+            };
+            let samples = sampler.await?;
+            let checks = growth_checks(&samples, warmup_seconds as f64, &limits);
+            Some(SoakReport {
+                sample_every_seconds: sample_every,
+                warmup_seconds,
+                passed: checks.iter().all(|check| check.passed),
+                checks,
+                samples,
+            })
+        }
+        None => None,
+    };
+
+    let all: Vec<&Sample> = samples.iter().collect();
+    let operations = mix
+        .weights
+        .iter()
+        .map(|(operation, _)| {
+            let of_operation: Vec<&Sample> = samples.iter().filter(|sample| sample.operation == *operation).collect();
+            OperationReport::new(operation.name(), &of_operation, seconds)
+        })
+        .collect();
+    let report = LoadReport {
+        version: REPORT_VERSION,
+        generator: "loadgen".to_string(),
+        generated_at: chrono::Utc::now(),
+        environment: SystemInfo::collect(),
+        server: target.base.clone(),
+        files: target.files.clone(),
+        concurrency,
+        mix: mix.weights.iter().map(|(operation, weight)| (operation.name(), *weight)).collect(),
+        duration_seconds: seconds,
+        totals: OperationReport::new("all", &all, seconds),
+        operations,
+        soak,
+    };
+    print_report(&report);
+
+    if let Some(path) = matches.get_one::<String>("output") {
+        std::fs::write(path, serde_json::to_vec_pretty(&report)?).map_err(|error| format!("cannot write {}: {}", path, error))?;
+        println!("💾 Report written to {}", path);
+    }
+    if let Some(soak) = report.soak.as_ref().filter(|soak| !soak.passed) {
+        let leaks: Vec<&str> = soak.checks.iter().filter(|check| !check.passed).map(|check| check.measure).collect();
+        return Err(format!("the server kept growing after the warm-up: {}", leaks.join(", ")).into());
+    }
+    if let Some(limit) = matches.get_one::<f64>("max-error-rate") {
+        let rate = report.totals.error_rate * 100.0;
+        if rate > *limit {
+            return Err(format!("{:.2}% of requests failed, above --max-error-rate {}%", rate, limit).into());
+        }
+    }
+    Ok(())
+}
+
+/// One client: sends a request, waits for the whole response, and picks
+/// the next operation until the budget runs out.
+async fn client_loop(target: Arc<Target>, mix: Arc<Mix>, budget: Arc<Budget>, seed: u64) -> Vec<Sample> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut samples = Vec::new();
+    while budget.take() {
+        let operation = mix.pick(&mut rng);
+        let file = &target.files[rng.gen_range(0..target.files.len())];
+        let start = Instant::now();
+        let outcome = send(&target, operation, file).await;
+        samples.push(Sample { operation, latency: start.elapsed(), outcome });
+    }
+    samples
+}
+
+async fn send(target: &Target, operation: Operation, file: &str) -> Outcome {
+    let request = match operation {
+        Operation::Upload => {
+            let part = reqwest::multipart::Part::bytes(target.upload.clone()).file_name(target.upload_name.clone());
+            target.http.post(format!("{}/upload", target.base)).multipart(reqwest::multipart::Form::new().part("file", part))
+        }
+        Operation::Process => target.http.get(format!("{}/process/{}", target.base, file)),
+        Operation::Analyze => target.http.get(format!("{}/analyze/{}", target.base, file)),
+    };
+    let response = match request.send().await {
+        Ok(response) => response,
+        Err(error) => return Outcome::Failed(error.to_string()),
+    };
+    let status = response.status();
+    // Read to the end, so the latency covers the whole response
+    if let Err(error) = response.bytes().await {
+        return Outcome::Failed(error.to_string());
+    }
+    match status.is_success() {
+        true => Outcome::Ok,
+        false => Outcome::Status(status.as_u16()),
+    }
+}
+
+/// Samples the server's `/health` every `every` until the load is done,
+/// and once more after. Samples that fail are skipped.
+async fn sample_memory(target: Arc<Target>, every: Duration, start: Instant, load_done: CancellationToken) -> Vec<MemorySample> {
+    let mut samples = Vec::new();
+    let mut ticks = tokio::time::interval(every.max(Duration::from_secs(1)));
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {}
+            _ = load_done.cancelled() => break,
+        }
+        if let Some(sample) = memory_sample(&target, start).await {
+            print_sample(&sample);
+            samples.push(sample);
+        }
+    }
+    if let Some(sample) = memory_sample(&target, start).await {
+        print_sample(&sample);
+        samples.push(sample);
+    }
+    samples
+}
+
+async fn memory_sample(target: &Target, start: Instant) -> Option<MemorySample> {
+    let response = target.http.get(format!("{}/health", target.base)).send().await.ok()?;
+    let health: serde_json::Value = response.json().await.ok()?;
+    let memory = &health["memory"];
+    let metrics = match (memory["upload_metrics"].as_u64(), memory["processing_metrics"].as_u64()) {
+        (Some(uploads), Some(processing)) => Some(uploads + processing),
+        _ => None,
+    };
+    Some(MemorySample {
+        elapsed_seconds: start.elapsed().as_secs_f64(),
+        rss_bytes: memory["rss_bytes"].as_u64(),
+        cache_bytes: health["cache"]["memory_bytes"].as_u64(),
+        cached_files: health["cache"]["files"].as_u64(),
+        metrics_in_memory: metrics,
+    })
+}
+
+/// Compares the first sample after `warmup_seconds` (or the first one, in
+/// a run shorter than that) with the last, for each figure both have.
+fn growth_checks(samples: &[MemorySample], warmup_seconds: f64, limits: &GrowthLimits) -> Vec<GrowthCheck> {
+    let (Some(first), Some(last)) = (samples.first(), samples.last()) else {
+        return Vec::new();
+    };
+    let baseline = samples.iter().find(|sample| sample.elapsed_seconds >= warmup_seconds).unwrap_or(first);
+    let measures: [(&'static str, Measure, u64); 3] = [
+        ("rss_bytes", |sample| sample.rss_bytes, limits.rss_bytes),
+        ("cache_bytes", |sample| sample.cache_bytes, limits.cache_bytes),
+        ("metrics_in_memory", |sample| sample.metrics_in_memory, limits.metrics_in_memory),
+    ];
+    measures
+        .into_iter()
+        .filter_map(|(measure, value, limit)| {
+            let (baseline, last) = (value(baseline)?, value(last)?);
+            let growth = last as i64 - baseline as i64;
+            Some(GrowthCheck { measure, baseline, last, growth, limit, passed: growth <= limit as i64 })
+        })
+        .collect()
+}
+
+/// Seconds, or a number followed by `s`, `m`, `h` or `d`.
+fn parse_seconds(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let (number, unit) = match text.char_indices().last() {
+        Some((index, unit)) if unit.is_ascii_alphabetic() => (&text[..index], unit.to_ascii_lowercase()),
+        _ => (text, 's'),
+    };
+    let multiplier = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3_600,
+        'd' => 86_400,
+        _ => return Err(format!("unknown unit in '{}'; use s, m, h or d", text)),
+    };
+    number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(|| format!("'{}' is not a whole number of seconds, minutes, hours or days", text))
+}
+
+fn print_sample(sample: &MemorySample) {
+    let megabytes = |bytes: Option<u64>| bytes.map_or_else(|| "–".to_string(), |bytes| format!("{:.1} MB", bytes as f64 / 1_000_000.0));
+    let count = |count: Option<u64>| count.map_or_else(|| "–".to_string(), |count| count.to_string());
+    println!(
+        "🩺 {:>7.0}s: RSS {}, cache {} in {} file(s), {} metrics in memory",
+        sample.elapsed_seconds,
+        megabytes(sample.rss_bytes),
+        megabytes(sample.cache_bytes),
+        count(sample.cached_files),
+        count(sample.metrics_in_memory)
+    );
+}
+
+fn print_report(report: &LoadReport) {
+    println!("\n📊 {} requests in {:.1}s", report.totals.requests, report.duration_seconds);
+    for operation in report.operations.iter().chain([&report.totals]) {
+        println!(
+            "   {:<8} {:>7} requests, {:>8.1} req/s, {:>5.2}% errors",
+            operation.operation,
+            operation.requests,
+            operation.requests_per_second,
+            operation.error_rate * 100.0
+        );
+        match &operation.latency {
+            Some(latency) => println!(
+                "            p50 {:.2} ms, p90 {:.2} ms, p99 {:.2} ms, max {:.2} ms",
+                latency.p50_ms, latency.p90_ms, latency.p99_ms, latency.max_ms
+            ),
+            None => println!("            no successful request"),
+        }
+    }
+    let failed: Vec<String> = report
+        .totals
+        .statuses
+        .iter()
+        .filter(|(status, _)| *status != "2xx")
+        .map(|(status, count)| format!("{} × {}", count, status))
+        .collect();
+    if !failed.is_empty() {
+        println!("⚠️  Errors: {}", failed.join(", "));
+    }
+    if let Some(error) = &report.totals.first_error {
+        println!("   First failure: {}", error);
+    }
+    if let Some(soak) = &report.soak {
+        println!("\n🧪 Growth after the {}s warm-up:", soak.warmup_seconds);
+        for check in &soak.checks {
+            println!(
+                "   {} {}: {} → {} ({:+}, limit {})",
+                if check.passed { "✅" } else { "❌" },
+                check.measure,
+                check.baseline,
+                check.last,
+                check.growth,
+                check.limit
+            );
+        }
+        if soak.checks.is_empty() {
+            println!("   ⚠️  The server reported no memory figures to check");
+        }
+    }
+}
@@ -0,0 +1,183 @@
+/// Where a locale writes the currency of an amount.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CurrencyPosition {
+    /// No currency at all, as in [`Locale::CANONICAL`].
+    Omitted,
+    /// `$1,234.56`, with a space when the symbol is a word: `CHF 1’234.56`.
+    Before,
+    /// `€ 1.234,56`
+    BeforeSpaced,
+    /// `1.234,56 €`
+    After,
+}
+
+/// How numbers, amounts and dates are written for readers of one locale,
+/// for the reports people read. Machine-readable output is always
+/// [`Locale::CANONICAL`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Locale {
+    pub tag: &'static str,
+    decimal: char,
+    /// Between groups of three digits; `None` writes no separators.
+    group: Option<char>,
+    /// A chrono format for dates.
+    date: &'static str,
+    currency: CurrencyPosition,
+}
+
+/// The locales reports can be written for; the first of each language is
+/// the one a bare language, e.g. `de`, selects.
+pub const SUPPORTED: [Locale; 11] = [
+    Locale { tag: "en-US", decimal: '.', group: Some(','), date: "%m/%d/%Y", currency: CurrencyPosition::Before },
+    Locale { tag: "en-GB", decimal: '.', group: Some(','), date: "%d/%m/%Y", currency: CurrencyPosition::Before },
+    Locale { tag: "de-DE", decimal: ',', group: Some('.'), date: "%d.%m.%Y", currency: CurrencyPosition::After },
+    Locale { tag: "de-CH", decimal: '.', group: Some('’'), date: "%d.%m.%Y", currency: CurrencyPosition::Before },
+    // French groups with a narrow no-break space
+    Locale { tag: "fr-FR", decimal: ',', group: Some('\u{202f}'), date: "%d/%m/%Y", currency: CurrencyPosition::After },
+    Locale { tag: "es-ES", decimal: ',', group: Some('.'), date: "%d/%m/%Y", currency: CurrencyPosition::After },
+    Locale { tag: "it-IT", decimal: ',', group: Some('.'), date: "%d/%m/%Y", currency: CurrencyPosition::After },
+    Locale { tag: "nl-NL", decimal: ',', group: Some('.'), date: "%d-%m-%Y", currency: CurrencyPosition::BeforeSpaced },
+    Locale { tag: "pt-BR", decimal: ',', group: Some('.'), date: "%d/%m/%Y", currency: CurrencyPosition::BeforeSpaced },
+    Locale { tag: "sv-SE", decimal: ',', group: Some('\u{a0}'), date: "%Y-%m-%d", currency: CurrencyPosition::After },
+    Locale { tag: "ja-JP", decimal: '.', group: Some(','), date: "%Y/%m/%d", currency: CurrencyPosition::Before },
+];
+
+/// As its tag, e.g. `"de-DE"`.
+impl serde::Serialize for Locale {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.tag)
+    }
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Self::CANONICAL
+    }
+}
+
+impl Locale {
+    /// Plain numbers, ISO dates and no currency symbols: what reports show
+    /// when no locale is asked for, and what machine output always uses.
+    pub const CANONICAL: Locale = Locale { tag: "canonical", decimal: '.', group: None, date: "%Y-%m-%d", currency: CurrencyPosition::Omitted };
+
+    /// The supported locale named by `tag`, ignoring case, or the first of
+    /// its language for a bare language or an unsupported region.
+    pub fn parse(tag: &str) -> Option<Self> {
+        let tag = tag.trim().replace('_', "-");
+        if tag.eq_ignore_ascii_case(Self::CANONICAL.tag) {
+            return Some(Self::CANONICAL);
+        }
+        if let Some(exact) = SUPPORTED.iter().find(|locale| locale.tag.eq_ignore_ascii_case(&tag)) {
+            return Some(*exact);
+        }
+        let language = tag.split('-').next().unwrap_or_default();
+        SUPPORTED.iter().find(|locale| locale.language().eq_ignore_ascii_case(language)).copied()
+    }
+
+    /// The best supported locale of an `Accept-Language` header, e.g.
+    /// `de-CH,de;q=0.9,en;q=0.8`, by quality and then by order; `None`
+    /// when it names none of them.
+    pub fn negotiate(accept_language: &str) -> Option<Self> {
+        let mut ranges: Vec<(&str, f32)> = accept_language
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let tag = parts.next()?.trim();
+                let quality = parts
+                    .filter_map(|parameter| parameter.trim().strip_prefix("q="))
+                    .find_map(|quality| quality.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                (!tag.is_empty() && tag != "*" && quality > 0.0).then_some((tag, quality))
+            })
+            .collect();
+        // Stable, so equal qualities keep the client's order
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranges.into_iter().find_map(|(tag, _)| Self::parse(tag).filter(|locale| *locale != Self::CANONICAL))
+    }
+
+    pub fn language(&self) -> &'static str {
+        self.tag.split('-').next().unwrap_or(self.tag)
+    }
+
+    pub fn is_canonical(&self) -> bool {
+        *self == Self::CANONICAL
+    }
+
+    /// `value` with `decimals` digits after the decimal separator and its
+    /// whole part grouped by thousands.
+    pub fn number(&self, value: f64, decimals: usize) -> String {
+        if !value.is_finite() {
+            return value.to_string();
+        }
+        let plain = format!("{:.*}", decimals, value.abs());
+        let (whole, fraction) = plain.split_once('.').unwrap_or((&plain, ""));
+        let mut out = String::new();
+        // -0.00 is written as 0.00
+        if value < 0.0 && plain.bytes().any(|digit| matches!(digit, b'1'..=b'9')) {
+            out.push('-');
+        }
+        out.push_str(&self.group_digits(whole));
+        if !fraction.is_empty() {
+            out.push(self.decimal);
+            out.push_str(fraction);
+        }
+        out
+    }
+
+    /// A whole number grouped by thousands.
+    pub fn integer(&self, value: u64) -> String {
+        self.group_digits(&value.to_string())
+    }
+
+    /// `amount` of the currency `code` with its symbol where the locale
+    /// writes it, to the currency's usual decimals. Canonically, just the
+    /// number with two decimals.
+    pub fn currency(&self, amount: f64, code: &str) -> String {
+        let decimals = if self.currency == CurrencyPosition::Omitted || !matches!(code, "JPY" | "KRW") { 2 } else { 0 };
+        let number = self.number(amount, decimals);
+        let symbol = currency_symbol(code);
+        match self.currency {
+            CurrencyPosition::Omitted => number,
+            CurrencyPosition::Before if !symbol.chars().any(char::is_alphabetic) => format!("{}{}", symbol, number),
+            CurrencyPosition::Before | CurrencyPosition::BeforeSpaced => format!("{}\u{a0}{}", symbol, number),
+            CurrencyPosition::After => format!("{}\u{a0}{}", number, symbol),
+        }
+    }
+
+    /// The date of `time` in the locale's order and separators.
+    pub fn date<Tz: chrono::TimeZone>(&self, time: &chrono::DateTime<Tz>) -> String
+    where
+        Tz::Offset: std::fmt::Display,
+    {
+        time.format(self.date).to_string()
+    }
+
+    fn group_digits(&self, digits: &str) -> String {
+        let Some(separator) = self.group else {
+            return digits.to_string();
+        };
+        let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+        for (index, digit) in digits.chars().enumerate() {
+            if index > 0 && (digits.len() - index).is_multiple_of(3) {
+                out.push(separator);
+            }
+            out.push(digit);
+        }
+        out
+    }
+}
+
+/// The symbol of the common currencies, or the code itself.
+fn currency_symbol(code: &str) -> &str {
+    match code {
+        "USD" => "$",
+        "EUR" => "€",
+        "GBP" => "£",
+        "JPY" => "¥",
+        "BRL" => "R$",
+        "SEK" => "kr",
+        "INR" => "₹",
+        "KRW" => "₩",
+        code => code,
+    }
+}
@@ -0,0 +1,266 @@
+use crate::performance_utils::{LatencySummary, PerformanceMetrics, StrategyResult};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// How long one push may take; a process exiting should not hang on a
+/// gateway that went away.
+const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where collected metrics are pushed when a process exits, for runs too
+/// short-lived for Prometheus to scrape.
+#[derive(Debug, Clone)]
+pub enum PushTarget {
+    /// A Prometheus Pushgateway base URL. Metrics are PUT under
+    /// `/metrics/job/<job>/instance/<instance>`, replacing the previous
+    /// push of the same job and instance.
+    Pushgateway(String),
+    /// A Prometheus remote-write endpoint, e.g. `.../api/v1/write`.
+    RemoteWrite(String),
+}
+
+/// Pushes to `PUSHGATEWAY_URL` and/or `REMOTE_WRITE_URL`. Pushed series are
+/// labelled with the job, the binary's name unless `METRICS_PUSH_JOB` says
+/// otherwise, and the instance, `METRICS_PUSH_INSTANCE` or the host name.
+#[derive(Debug, Clone)]
+pub struct MetricsPusher {
+    targets: Vec<PushTarget>,
+    job: String,
+    instance: String,
+}
+
+/// Gauges to push, grouped into metric families by name.
+#[derive(Debug, Default)]
+pub struct Samples {
+    families: BTreeMap<&'static str, Family>,
+}
+
+#[derive(Debug, Default)]
+struct Family {
+    help: &'static str,
+    samples: Vec<(Vec<(&'static str, String)>, f64)>,
+}
+
+impl MetricsPusher {
+    /// `None` when neither URL is set.
+    pub fn from_env(job: &str) -> Option<Self> {
+        let mut targets = Vec::new();
+        if let Ok(url) = std::env::var("PUSHGATEWAY_URL") {
+            targets.push(PushTarget::Pushgateway(url.trim_end_matches('/').to_string()));
+        }
+        if let Ok(url) = std::env::var("REMOTE_WRITE_URL") {
+            targets.push(PushTarget::RemoteWrite(url));
+        }
+        if targets.is_empty() {
+            return None;
+        }
+        let job = std::env::var("METRICS_PUSH_JOB").unwrap_or_else(|_| job.to_string());
+        let instance = std::env::var("METRICS_PUSH_INSTANCE")
+            .or_else(|_| std::env::var("HOSTNAME"))
+            .ok()
+            .or_else(sysinfo::System::host_name)
+            .unwrap_or_else(|| "unknown".to_string());
+        Some(Self { targets, job, instance })
+    }
+
+    /// Pushes `samples` to every target, printing how each push went. A
+    /// failed push is reported, not returned: losing the metrics should not
+    /// fail the run that produced them.
+    pub async fn push(&self, samples: &Samples) {
+        let client = match reqwest::Client::builder().timeout(PUSH_TIMEOUT).build() {
+            Ok(client) => client,
+            Err(error) => {
+                println!("⚠️  Could not push metrics: {}", error);
+                return;
+            }
+        };
+        for target in &self.targets {
+            let (url, result) = match target {
+                PushTarget::Pushgateway(base) => {
+                    let url = format!("{}/metrics/job/{}/instance/{}", base, self.job, self.instance);
+                    let request = client
+                        .put(&url)
+                        .header(reqwest::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+                        .body(samples.text());
+                    (url, send(request).await)
+                }
+                PushTarget::RemoteWrite(url) => {
+                    let timestamp = chrono::Utc::now().timestamp_millis();
+                    let body = snap::raw::Encoder::new().compress_vec(&samples.write_request(&self.job, &self.instance, timestamp));
+                    let result = match body {
+                        Ok(body) => {
+                            let request = client
+                                .post(url)
+                                .header(reqwest::header::CONTENT_TYPE, "application/x-protobuf")
+                                .header(reqwest::header::CONTENT_ENCODING, "snappy")
+                                .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+                                .body(body);
+                            send(request).await
+                        }
+                        Err(error) => Err(error.to_string()),
+                    };
+                    (url.clone(), result)
+                }
+            };
+            match result {
+                Ok(()) => println!("📤 Pushed {} metric sample(s) to {}", samples.len(), url),
+                Err(error) => println!("⚠️  Could not push metrics to {}: {}", url, error),
+            }
+        }
+    }
+}
+
+async fn send(request: reqwest::RequestBuilder) -> Result<(), String> {
+    let response = request.send().await.map_err(|error| error.to_string())?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(format!("{} {}", status, body.trim()))
+}
+
+impl Samples {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, name: &'static str, help: &'static str, labels: Vec<(&'static str, String)>, value: f64) {
+        let family = self.families.entry(name).or_default();
+        family.help = help;
+        family.samples.push((labels, value));
+    }
+
+    /// Samples across all families.
+    pub fn len(&self) -> usize {
+        self.families.values().map(|family| family.samples.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Per operation: runs, records, total duration, and the throughput of
+    /// the latest run.
+    pub fn add_metrics(&mut self, metrics: &[PerformanceMetrics]) {
+        let mut operations: BTreeMap<&str, Vec<&PerformanceMetrics>> = BTreeMap::new();
+        for metric in metrics {
+            operations.entry(&metric.operation).or_default().push(metric);
+        }
+        for (operation, runs) in operations {
+            let labels = || vec![("operation", operation.to_string())];
+            let latest = runs[runs.len() - 1];
+            self.add("csv_operation_runs", "Timed runs of the operation", labels(), runs.len() as f64);
+            self.add(
+                "csv_operation_cancelled_runs",
+                "Runs of the operation that were cancelled",
+                labels(),
+                runs.iter().filter(|run| run.cancelled).count() as f64,
+            );
+            self.add(
+                "csv_operation_records",
+                "Records processed over all runs",
+                labels(),
+                runs.iter().map(|run| run.records_processed as f64).sum(),
+            );
+            self.add(
+                "csv_operation_duration_seconds",
+                "Time spent over all runs",
+                labels(),
+                runs.iter().map(|run| run.duration.as_secs_f64()).sum(),
+            );
+            self.add("csv_operation_records_per_second", "Throughput of the latest run", labels(), latest.records_per_second);
+            if let Some(megabytes_per_second) = latest.megabytes_per_second {
+                self.add("csv_operation_megabytes_per_second", "Byte throughput of the latest run", labels(), megabytes_per_second);
+            }
+        }
+    }
+
+    /// Per benchmark strategy: records, mean duration and throughput.
+    pub fn add_results(&mut self, results: &[StrategyResult]) {
+        for result in results {
+            let labels = || vec![("strategy", result.strategy.clone())];
+            self.add("csv_benchmark_records", "Records the strategy processed", labels(), result.records as f64);
+            self.add("csv_benchmark_duration_seconds", "Mean duration of the strategy", labels(), result.duration.as_secs_f64());
+            self.add("csv_benchmark_records_per_second", "Throughput of the strategy", labels(), result.records_per_second);
+            if let Some(megabytes_per_second) = result.megabytes_per_second {
+                self.add("csv_benchmark_megabytes_per_second", "Byte throughput of the strategy", labels(), megabytes_per_second);
+            }
+        }
+    }
+
+    /// Per operation: latency percentiles and the number of runs behind them.
+    pub fn add_latencies(&mut self, latencies: &[LatencySummary]) {
+        for latency in latencies {
+            for (quantile, ms) in [("0.5", latency.p50_ms), ("0.9", latency.p90_ms), ("0.99", latency.p99_ms), ("1", latency.max_ms)] {
+                self.add(
+                    "csv_latency_seconds",
+                    "Latency percentiles of the operation",
+                    vec![("operation", latency.operation.clone()), ("quantile", quantile.to_string())],
+                    ms / 1_000.0,
+                );
+            }
+            self.add("csv_latency_count", "Runs behind the latency percentiles", vec![("operation", latency.operation.clone())], latency.count as f64);
+        }
+    }
+
+    /// The Prometheus text exposition format, as a Pushgateway takes it.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for (name, family) in &self.families {
+            text.push_str(&format!("# HELP {} {}\n# TYPE {} gauge\n", name, family.help, name));
+            for (labels, value) in &family.samples {
+                let labels: Vec<String> = labels.iter().map(|(label, value)| format!("{}=\"{}\"", label, escape(value))).collect();
+                text.push_str(&format!("{}{{{}}} {}\n", name, labels.join(","), value));
+            }
+        }
+        text
+    }
+
+    /// A remote-write `WriteRequest` protobuf, uncompressed: one series per
+    /// sample with `__name__`, `job` and `instance` labels added and the
+    /// labels sorted by name, as the protocol requires.
+    fn write_request(&self, job: &str, instance: &str, timestamp_ms: i64) -> Vec<u8> {
+        let mut request = Vec::new();
+        for (name, family) in &self.families {
+            for (labels, value) in &family.samples {
+                let mut labels: Vec<(&str, &str)> = labels.iter().map(|(label, value)| (*label, value.as_str())).collect();
+                labels.extend([("__name__", *name), ("job", job), ("instance", instance)]);
+                labels.sort();
+                let mut series = Vec::new();
+                for (label, value) in labels {
+                    let mut encoded = Vec::new();
+                    protobuf_bytes(&mut encoded, 1, label.as_bytes());
+                    protobuf_bytes(&mut encoded, 2, value.as_bytes());
+                    protobuf_bytes(&mut series, 1, &encoded);
+                }
+                let mut sample = vec![1 << 3 | 1];
+                sample.extend_from_slice(&value.to_le_bytes());
+                sample.push(2 << 3);
+                protobuf_varint(&mut sample, timestamp_ms as u64);
+                protobuf_bytes(&mut series, 2, &sample);
+                protobuf_bytes(&mut request, 1, &series);
+            }
+        }
+        request
+    }
+}
+
+/// Label values escape backslashes, quotes and newlines.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn protobuf_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+/// A length-delimited field: strings and embedded messages.
+fn protobuf_bytes(buffer: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    protobuf_varint(buffer, field << 3 | 2);
+    protobuf_varint(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
@@ -0,0 +1,357 @@
+use crate::column_stats::month_of;
+use crate::performance_utils::SalesRecord;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// The manifest in a dataset's directory, describing its partitions.
+pub const MANIFEST: &str = "_dataset.json";
+
+type PartitionError = Box<dyn Error + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PartitionFormat {
+    Csv,
+    Parquet,
+}
+
+impl PartitionFormat {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "csv" => Ok(PartitionFormat::Csv),
+            "parquet" if cfg!(feature = "parquet") => Ok(PartitionFormat::Parquet),
+            "parquet" => Err("parquet partitions need the `parquet` feature (cargo run --features parquet ...)".to_string()),
+            _ => Err(format!("unknown format '{}' (expected csv or parquet)", value)),
+        }
+    }
+
+    pub fn file_name(self) -> &'static str {
+        match self {
+            PartitionFormat::Csv => "part.csv",
+            PartitionFormat::Parquet => "part.parquet",
+        }
+    }
+}
+
+/// The rows of one region in one month, in `region=<region>/month=<YYYY-MM>`
+/// under the dataset's directory. Dates without a month are in
+/// `month=unknown`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Partition {
+    pub region: String,
+    pub month: String,
+    pub rows: usize,
+    pub bytes: u64,
+}
+
+impl Partition {
+    /// The partition's directory relative to the dataset's, with characters
+    /// that cannot be in a directory name percent-encoded.
+    pub fn relative_dir(&self) -> PathBuf {
+        Path::new(&format!("region={}", escape(&self.region))).join(format!("month={}", escape(&self.month)))
+    }
+}
+
+/// A file exploded into partitions, as recorded in its [`MANIFEST`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Dataset {
+    /// The file the partitions were written from.
+    pub file: String,
+    pub format: PartitionFormat,
+    /// The file's fingerprint when it was partitioned. A file changed
+    /// since then is read whole rather than from stale partitions.
+    pub source_fingerprint: u64,
+    pub rows: usize,
+    pub bytes: u64,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub partitions: Vec<Partition>,
+}
+
+impl Dataset {
+    /// The partitions that can hold rows `filter` selects, and how many
+    /// were skipped without being read.
+    pub fn prune(&self, filter: &PartitionFilter) -> (Vec<&Partition>, usize) {
+        let selected: Vec<&Partition> = self
+            .partitions
+            .iter()
+            .filter(|partition| filter.matches(&partition.region, &partition.month))
+            .collect();
+        let pruned = self.partitions.len() - selected.len();
+        (selected, pruned)
+    }
+}
+
+/// What a partitioned read touched.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct PartitionScan {
+    pub scanned: usize,
+    pub pruned: usize,
+    pub bytes_read: u64,
+}
+
+/// Rows selected by region and an inclusive range of `YYYY-MM` months.
+/// Each part of it that is left out selects everything.
+#[derive(Debug, Clone, Default)]
+pub struct PartitionFilter {
+    regions: Option<Vec<String>>,
+    from: Option<String>,
+    to: Option<String>,
+}
+
+impl PartitionFilter {
+    /// `None` when nothing is filtered. `regions` is comma-separated.
+    pub fn new(regions: Option<&str>, from: Option<&str>, to: Option<&str>) -> Result<Option<Self>, String> {
+        let regions: Option<Vec<String>> = regions
+            .map(|regions| regions.split(',').map(str::trim).filter(|region| !region.is_empty()).map(String::from).collect::<Vec<_>>())
+            .filter(|regions| !regions.is_empty());
+        let month = |value: Option<&str>| -> Result<Option<String>, String> {
+            let Some(value) = value.map(str::trim).filter(|value| !value.is_empty()) else {
+                return Ok(None);
+            };
+            let valid = value.len() == 7
+                && value.as_bytes()[4] == b'-'
+                && value[..4].bytes().all(|byte| byte.is_ascii_digit())
+                && matches!(value[5..].parse::<u32>(), Ok(1..=12));
+            if !valid {
+                return Err(format!("{:?} is not a month; expected YYYY-MM", value));
+            }
+            Ok(Some(value.to_string()))
+        };
+        let (from, to) = (month(from)?, month(to)?);
+        if let (Some(from), Some(to)) = (&from, &to) {
+            if from > to {
+                return Err(format!("from {} is after to {}", from, to));
+            }
+        }
+        if regions.is_none() && from.is_none() && to.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Self { regions, from, to }))
+    }
+
+    /// Whether rows of `region` in `month` are selected. A month range
+    /// leaves out dates without a month.
+    pub fn matches(&self, region: &str, month: &str) -> bool {
+        let region_matches = self
+            .regions
+            .as_ref()
+            .is_none_or(|regions| regions.iter().any(|selected| selected.eq_ignore_ascii_case(region)));
+        let ranged = self.from.is_some() || self.to.is_some();
+        let month_matches = !ranged
+            || (month != "unknown"
+                && self.from.as_deref().is_none_or(|from| month >= from)
+                && self.to.as_deref().is_none_or(|to| month <= to));
+        region_matches && month_matches
+    }
+
+    pub fn matches_record(&self, record: &SalesRecord) -> bool {
+        self.matches(&record.region, month_of(&record.date))
+    }
+}
+
+/// Writes `records` as a dataset of partitions in `dir`, replacing any
+/// dataset there. The partitions are written into a hidden sibling
+/// directory first and swapped in once complete, so readers never see a
+/// half-written dataset.
+pub fn write(
+    records: &[SalesRecord],
+    dir: &Path,
+    format: PartitionFormat,
+    file: &str,
+    source_fingerprint: u64,
+) -> Result<Dataset, PartitionError> {
+    let mut groups: BTreeMap<(&str, &str), Vec<&SalesRecord>> = BTreeMap::new();
+    for record in records {
+        groups.entry((record.region.as_str(), month_of(&record.date))).or_default().push(record);
+    }
+
+    let name = dir.file_name().and_then(|name| name.to_str()).ok_or("the dataset directory has no name")?;
+    let staging = dir.with_file_name(format!(".{}.part", name));
+    if staging.exists() {
+        std::fs::remove_dir_all(&staging)?;
+    }
+    let written = (|| {
+        let mut partitions = Vec::with_capacity(groups.len());
+        for ((region, month), rows) in &groups {
+            let mut partition = Partition { region: region.to_string(), month: month.to_string(), rows: rows.len(), bytes: 0 };
+            let partition_dir = staging.join(partition.relative_dir());
+            std::fs::create_dir_all(&partition_dir)?;
+            let path = partition_dir.join(format.file_name());
+            match format {
+                PartitionFormat::Csv => write_csv(&path, rows)?,
+                #[cfg(feature = "parquet")]
+                PartitionFormat::Parquet => write_parquet(&path, rows)?,
+                #[cfg(not(feature = "parquet"))]
+                PartitionFormat::Parquet => return Err("parquet partitions need the `parquet` feature".into()),
+            }
+            partition.bytes = std::fs::metadata(&path)?.len();
+            partitions.push(partition);
+        }
+        let dataset = Dataset {
+            file: file.to_string(),
+            format,
+            source_fingerprint,
+            rows: records.len(),
+            bytes: partitions.iter().map(|partition| partition.bytes).sum(),
+            created_at: chrono::Utc::now(),
+            partitions,
+        };
+        std::fs::write(staging.join(MANIFEST), serde_json::to_vec_pretty(&dataset)?)?;
+        Ok::<_, PartitionError>(dataset)
+    })();
+    let dataset = match written {
+        Ok(dataset) => dataset,
+        Err(error) => {
+            let _ = std::fs::remove_dir_all(&staging);
+            return Err(error);
+        }
+    };
+    if dir.exists() {
+        std::fs::remove_dir_all(dir)?;
+    }
+    std::fs::rename(&staging, dir)?;
+    Ok(dataset)
+}
+
+/// The dataset in `dir`, or `None` when nothing was partitioned there.
+pub fn load(dir: &Path) -> Result<Option<Dataset>, PartitionError> {
+    match std::fs::read(dir.join(MANIFEST)) {
+        Ok(content) => Ok(Some(serde_json::from_slice(&content)?)),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Every dataset under `root`, wherever its directory is nested.
+/// Unreadable manifests are skipped.
+pub fn list(root: &Path) -> Vec<Dataset> {
+    let mut datasets = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        if let Ok(Some(dataset)) = load(&dir) {
+            datasets.push(dataset);
+            continue;
+        }
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+            if !hidden && entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                pending.push(entry.path());
+            }
+        }
+    }
+    datasets.sort_by(|a, b| a.file.cmp(&b.file));
+    datasets
+}
+
+/// The records of one partition of the dataset in `dir`.
+pub fn read(dir: &Path, dataset: &Dataset, partition: &Partition) -> Result<Vec<SalesRecord>, PartitionError> {
+    let path = dir.join(partition.relative_dir()).join(dataset.format.file_name());
+    match dataset.format {
+        PartitionFormat::Csv => {
+            let mut reader = csv::ReaderBuilder::new().from_path(&path)?;
+            Ok(reader.deserialize().collect::<csv::Result<Vec<SalesRecord>>>()?)
+        }
+        #[cfg(feature = "parquet")]
+        PartitionFormat::Parquet => read_parquet(&path),
+        #[cfg(not(feature = "parquet"))]
+        PartitionFormat::Parquet => Err(format!("{} is Parquet, which needs the `parquet` feature", path.display()).into()),
+    }
+}
+
+fn write_csv(path: &Path, rows: &[&SalesRecord]) -> Result<(), PartitionError> {
+    let mut writer = csv::Writer::from_path(path)?;
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(feature = "parquet")]
+fn parquet_schema() -> std::sync::Arc<arrow_schema::Schema> {
+    use arrow_schema::{DataType, Field, Schema};
+
+    std::sync::Arc::new(Schema::new(vec![
+        Field::new("id", DataType::UInt32, false),
+        Field::new("customer_name", DataType::Utf8, false),
+        Field::new("product", DataType::Utf8, false),
+        Field::new("quantity", DataType::UInt32, false),
+        Field::new("price", DataType::Float64, false),
+        Field::new("date", DataType::Utf8, false),
+        Field::new("region", DataType::Utf8, false),
+    ]))
+}
+
+#[cfg(feature = "parquet")]
+fn write_parquet(path: &Path, rows: &[&SalesRecord]) -> Result<(), PartitionError> {
+    use arrow_array::{ArrayRef, Float64Array, RecordBatch, StringArray, UInt32Array};
+    use std::sync::Arc;
+
+    let strings = |field: fn(&SalesRecord) -> &str| -> ArrayRef { Arc::new(rows.iter().map(|row| Some(field(row))).collect::<StringArray>()) };
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(rows.iter().map(|row| row.id).collect::<UInt32Array>()),
+        strings(|row| &row.customer_name),
+        strings(|row| &row.product),
+        Arc::new(rows.iter().map(|row| row.quantity).collect::<UInt32Array>()),
+        Arc::new(rows.iter().map(|row| row.price).collect::<Float64Array>()),
+        strings(|row| &row.date),
+        strings(|row| &row.region),
+    ];
+    let batch = RecordBatch::try_new(parquet_schema(), columns)?;
+    let mut writer = parquet::arrow::ArrowWriter::try_new(std::fs::File::create(path)?, parquet_schema(), None)?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+#[cfg(feature = "parquet")]
+fn read_parquet(path: &Path) -> Result<Vec<SalesRecord>, PartitionError> {
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Float64Type, UInt32Type};
+
+    let reader = parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(path)?)?.build()?;
+    let mut records = Vec::new();
+    for batch in reader {
+        let batch = batch?;
+        let column = |name: &str| batch.column_by_name(name).ok_or_else(|| format!("{} has no {} column", path.display(), name));
+        let ids = column("id")?.as_primitive::<UInt32Type>();
+        let customers = column("customer_name")?.as_string::<i32>();
+        let products = column("product")?.as_string::<i32>();
+        let quantities = column("quantity")?.as_primitive::<UInt32Type>();
+        let prices = column("price")?.as_primitive::<Float64Type>();
+        let dates = column("date")?.as_string::<i32>();
+        let regions = column("region")?.as_string::<i32>();
+        for row in 0..batch.num_rows() {
+            records.push(SalesRecord {
+                id: ids.value(row),
+                customer_name: customers.value(row).to_string(),
+                product: products.value(row).to_string(),
+                quantity: quantities.value(row),
+                price: prices.value(row),
+                date: dates.value(row).to_string(),
+                region: regions.value(row).to_string(),
+            });
+        }
+    }
+    Ok(records)
+}
+
+/// `value` usable as part of a directory name: bytes other than ASCII
+/// letters, digits, space, `-` and `_` (and `.`, except first) become
+/// `%XX`.
+fn escape(value: &str) -> String {
+    value
+        .bytes()
+        .enumerate()
+        .map(|(index, byte)| match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b' ' | b'-' | b'_' => (byte as char).to_string(),
+            b'.' if index > 0 => ".".to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
@@ -1,4 +1,7 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Duration, Instant};
+use hdrhistogram::Histogram;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -8,6 +11,132 @@ pub struct PerformanceMetrics {
     pub duration: Duration,
     pub records_per_second: f64,
     pub memory_estimate_mb: f64,
+    /// Input or output size, when the caller reported one with
+    /// [`PerformanceTimer::add_bytes`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes_processed: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub megabytes_per_second: Option<f64>,
+    #[serde(default)]
+    pub cancelled: bool,
+    /// Process CPU time spent in user and kernel mode while the timer ran,
+    /// on Unix. More CPU time than wall time means several cores were busy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_user: Option<Duration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_system: Option<Duration>,
+    /// Page faults the process took while the timer ran, on Unix. Minor
+    /// faults map a page that is already in memory, as on the first touch
+    /// of a memory-mapped file; major faults had to wait for the disk.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minor_page_faults: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub major_page_faults: Option<u64>,
+    /// Threads or tasks the work was spread over, for the chunked
+    /// strategies that take a concurrency level.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concurrency: Option<usize>,
+    /// Attempts repeated after transient I/O errors or timeouts, when the
+    /// caller reported them with [`PerformanceTimer::add_retries`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    /// Heap allocations made while the timer ran. Only measured with the
+    /// `alloc-tracking` feature, and includes other threads' allocations.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allocations: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allocations_per_record: Option<f64>,
+    /// Highest heap size above the starting point while the timer ran.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peak_heap_bytes: Option<u64>,
+    /// Where the time went, in order, when the timer was split with
+    /// [`PerformanceTimer::phase`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub phases: Vec<Phase>,
+    /// Throughput once a second while the operation ran, when progress was
+    /// reported with [`PerformanceTimer::record_progress`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub throughput_samples: Vec<ThroughputSample>,
+    /// When the run finished, for trends across runs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recorded_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// A named part of a timed operation, such as read, parse, aggregate or
+/// serialize.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Phase {
+    pub name: String,
+    pub duration: Duration,
+}
+
+/// Rows and bytes per second over one sampling interval.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThroughputSample {
+    /// Seconds since the operation started, at the end of the interval.
+    pub elapsed_seconds: f64,
+    pub rows_per_second: f64,
+    pub bytes_per_second: f64,
+}
+
+/// Most recent samples a [`ThroughputSampler`] keeps; older ones are dropped.
+pub const THROUGHPUT_SAMPLE_CAPACITY: usize = 300;
+
+/// Turns running row and byte totals into one [`ThroughputSample`] per
+/// second, kept in a ring buffer, so ramp-up, steady state and stalls show
+/// instead of one average. Cheap to call often; it only samples once the
+/// interval has passed.
+#[derive(Debug, Clone)]
+pub struct ThroughputSampler {
+    start: Instant,
+    interval: Duration,
+    /// Time and totals at the previous sample.
+    last: (Instant, u64, u64),
+    samples: VecDeque<ThroughputSample>,
+}
+
+impl Default for ThroughputSampler {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(1))
+    }
+}
+
+impl ThroughputSampler {
+    pub fn new(interval: Duration) -> Self {
+        let now = Instant::now();
+        Self {
+            start: now,
+            interval,
+            last: (now, 0, 0),
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Reports the rows and bytes processed so far. Returns true when this
+    /// took a new sample.
+    pub fn record(&mut self, rows: u64, bytes: u64) -> bool {
+        let now = Instant::now();
+        let (last_time, last_rows, last_bytes) = self.last;
+        let elapsed = now.duration_since(last_time);
+        if elapsed < self.interval {
+            return false;
+        }
+
+        if self.samples.len() == THROUGHPUT_SAMPLE_CAPACITY {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(ThroughputSample {
+            elapsed_seconds: now.duration_since(self.start).as_secs_f64(),
+            rows_per_second: rows.saturating_sub(last_rows) as f64 / elapsed.as_secs_f64(),
+            bytes_per_second: bytes.saturating_sub(last_bytes) as f64 / elapsed.as_secs_f64(),
+        });
+        self.last = (now, rows, bytes);
+        true
+    }
+
+    pub fn samples(&self) -> Vec<ThroughputSample> {
+        self.samples.iter().cloned().collect()
+    }
 }
 
 impl PerformanceMetrics {
@@ -21,42 +150,1408 @@ impl PerformanceMetrics {
             duration,
             records_per_second,
             memory_estimate_mb,
+            bytes_processed: None,
+            megabytes_per_second: None,
+            cancelled: false,
+            cpu_user: None,
+            cpu_system: None,
+            minor_page_faults: None,
+            major_page_faults: None,
+            concurrency: None,
+            retries: None,
+            allocations: None,
+            allocations_per_record: None,
+            peak_heap_bytes: None,
+            phases: Vec::new(),
+            throughput_samples: Vec::new(),
+            recorded_at: Some(chrono::Utc::now()),
         }
     }
 
+    /// Phase durations in milliseconds keyed by name, for JSON responses.
+    pub fn phases_ms(&self) -> serde_json::Map<String, serde_json::Value> {
+        self.phases
+            .iter()
+            .map(|phase| (phase.name.clone(), (phase.duration.as_secs_f64() * 1_000.0).into()))
+            .collect()
+    }
+
+    /// User plus system CPU time, where measured.
+    pub fn cpu_time(&self) -> Option<Duration> {
+        Some(self.cpu_user? + self.cpu_system?)
+    }
+
     pub fn display(&self) {
         println!("📊 Performance Metrics for: {}", self.operation);
         println!("   Records processed: {}", self.records_processed);
         println!("   Duration: {:?}", self.duration);
         println!("   Records/second: {:.2}", self.records_per_second);
         println!("   Est. memory usage: {:.2} MB", self.memory_estimate_mb);
+        if let (Some(bytes), Some(throughput)) = (self.bytes_processed, self.megabytes_per_second) {
+            println!("   Bytes processed: {} ({:.2} MB/s)", bytes, throughput);
+        }
+        if let (Some(user), Some(system), Some(total)) = (self.cpu_user, self.cpu_system, self.cpu_time()) {
+            println!(
+                "   CPU time: {:?} user, {:?} system ({:.2} cores busy)",
+                user,
+                system,
+                total.as_secs_f64() / self.duration.as_secs_f64()
+            );
+        }
+        if let (Some(minor), Some(major)) = (self.minor_page_faults, self.major_page_faults) {
+            println!("   Page faults: {} minor, {} major", minor, major);
+        }
+        if let Some(concurrency) = self.concurrency {
+            println!("   Concurrency: {}", concurrency);
+        }
+        if let Some(retries) = self.retries.filter(|&retries| retries > 0) {
+            println!("   Retries: {}", retries);
+        }
+        if let (Some(allocations), Some(peak)) = (self.allocations, self.peak_heap_bytes) {
+            println!("   Allocations: {} ({:.2} per record)", allocations, self.allocations_per_record.unwrap_or(0.0));
+            println!("   Peak heap growth: {:.2} MB", peak as f64 / 1_000_000.0);
+        }
+        if !self.phases.is_empty() {
+            let phases: Vec<String> = self
+                .phases
+                .iter()
+                .map(|phase| {
+                    let share = phase.duration.as_secs_f64() / self.duration.as_secs_f64() * 100.0;
+                    format!("{} {:?} ({:.0}%)", phase.name, phase.duration, share)
+                })
+                .collect();
+            println!("   Phases: {}", phases.join(", "));
+        }
+        if self.cancelled {
+            println!("   ⛔ Cancelled before completion (partial results)");
+        }
         println!();
     }
 }
 
+/// Whether timers print their emoji report to stdout. Spans and events are
+/// emitted either way.
+static CONSOLE_OUTPUT: AtomicBool = AtomicBool::new(true);
+
+/// Turns the console report of every [`PerformanceTimer`] on or off, for
+/// applications that rely on their own `tracing` subscriber instead.
+pub fn set_console_output(enabled: bool) {
+    CONSOLE_OUTPUT.store(enabled, Ordering::Relaxed);
+}
+
+pub fn console_output() -> bool {
+    CONSOLE_OUTPUT.load(Ordering::Relaxed)
+}
+
+/// Times one operation inside a `performance_timer` tracing span. The span
+/// carries the operation name and, once finished, rows, bytes, duration and
+/// throughput as structured fields; a `finished` event is emitted inside it.
 pub struct PerformanceTimer {
     start: Instant,
+    span: tracing::Span,
     operation: String,
+    bytes_processed: Option<u64>,
+    concurrency: Option<usize>,
+    retries: Option<u32>,
+    phases: Vec<Phase>,
+    /// The phase in progress and when it started.
+    current_phase: Option<(String, Instant)>,
+    sampler: ThroughputSampler,
+    usage_start: Option<ResourceUsage>,
+    #[cfg(feature = "alloc-tracking")]
+    allocations_start: alloc_tracking::Snapshot,
 }
 
 impl PerformanceTimer {
     pub fn new(operation: String) -> Self {
-        println!("⏱️  Starting: {}", operation);
+        if console_output() {
+            println!("⏱️  Starting: {}", operation);
+        }
+        let span = tracing::info_span!(
+            "performance_timer",
+            operation = %operation,
+            rows = tracing::field::Empty,
+            bytes = tracing::field::Empty,
+            duration_ms = tracing::field::Empty,
+            records_per_second = tracing::field::Empty,
+            megabytes_per_second = tracing::field::Empty,
+            cancelled = tracing::field::Empty,
+            concurrency = tracing::field::Empty,
+            retries = tracing::field::Empty,
+        );
         Self {
             start: Instant::now(),
+            span,
             operation,
+            bytes_processed: None,
+            concurrency: None,
+            retries: None,
+            phases: Vec::new(),
+            current_phase: None,
+            sampler: ThroughputSampler::default(),
+            usage_start: ResourceUsage::current(),
+            #[cfg(feature = "alloc-tracking")]
+            allocations_start: alloc_tracking::reset_peak(),
+        }
+    }
+
+    /// Counts bytes read or written towards the MB/s figure. Can be called
+    /// several times, e.g. once per chunk.
+    pub fn add_bytes(&mut self, bytes: usize) {
+        *self.bytes_processed.get_or_insert(0) += bytes as u64;
+    }
+
+    /// Records how many threads or tasks the work is spread over.
+    pub fn set_concurrency(&mut self, concurrency: usize) {
+        self.concurrency = Some(concurrency);
+        self.span.record("concurrency", concurrency);
+    }
+
+    /// Counts attempts repeated under a retry policy. Can be called once per
+    /// operation that ran under one.
+    pub fn add_retries(&mut self, retries: u32) {
+        let total = self.retries.get_or_insert(0);
+        *total += retries;
+        self.span.record("retries", *total);
+    }
+
+    /// Reports rows and bytes processed so far during a long operation; the
+    /// metrics then carry a once-a-second throughput series.
+    pub fn record_progress(&mut self, rows: usize, bytes: u64) {
+        self.sampler.record(rows as u64, bytes);
+    }
+
+    /// Starts the phase `name`, ending the previous one. The last phase ends
+    /// when the timer finishes.
+    pub fn phase(&mut self, name: &str) {
+        self.end_phase();
+        self.current_phase = Some((name.to_string(), Instant::now()));
+    }
+
+    fn end_phase(&mut self) {
+        if let Some((name, start)) = self.current_phase.take() {
+            self.phases.push(Phase { name, duration: start.elapsed() });
         }
     }
 
     pub fn finish(self, records_processed: usize) -> PerformanceMetrics {
+        self.complete(records_processed, false)
+    }
+
+    /// Finishes a timer whose work was stopped early by a cancellation token,
+    /// reporting whatever was processed up to that point.
+    pub fn cancelled(self, records_processed: usize) -> PerformanceMetrics {
+        self.complete(records_processed, true)
+    }
+
+    fn complete(mut self, records_processed: usize, cancelled: bool) -> PerformanceMetrics {
+        self.end_phase();
         let duration = self.start.elapsed();
-        let metrics = PerformanceMetrics::new(self.operation, records_processed, duration);
-        metrics.display();
+        let mut metrics = PerformanceMetrics::new(self.operation, records_processed, duration);
+        metrics.cancelled = cancelled;
+        metrics.concurrency = self.concurrency;
+        metrics.retries = self.retries;
+        metrics.phases = self.phases;
+        metrics.throughput_samples = self.sampler.samples();
+        if let Some(bytes) = self.bytes_processed {
+            metrics.bytes_processed = Some(bytes);
+            metrics.megabytes_per_second = Some(bytes as f64 / 1_000_000.0 / duration.as_secs_f64());
+        }
+        if let (Some(start), Some(now)) = (self.usage_start, ResourceUsage::current()) {
+            metrics.cpu_user = Some(now.user.saturating_sub(start.user));
+            metrics.cpu_system = Some(now.system.saturating_sub(start.system));
+            metrics.minor_page_faults = Some(now.minor_page_faults.saturating_sub(start.minor_page_faults));
+            metrics.major_page_faults = Some(now.major_page_faults.saturating_sub(start.major_page_faults));
+        }
+        #[cfg(feature = "alloc-tracking")]
+        {
+            let now = alloc_tracking::snapshot();
+            let allocations = now.allocations - self.allocations_start.allocations;
+            metrics.allocations = Some(allocations);
+            metrics.allocations_per_record = Some(allocations as f64 / records_processed.max(1) as f64);
+            metrics.peak_heap_bytes = Some(now.peak_bytes.saturating_sub(self.allocations_start.current_bytes) as u64);
+        }
+        self.span.record("rows", records_processed);
+        self.span.record("duration_ms", duration.as_secs_f64() * 1_000.0);
+        self.span.record("records_per_second", metrics.records_per_second);
+        self.span.record("cancelled", cancelled);
+        if let (Some(bytes), Some(throughput)) = (metrics.bytes_processed, metrics.megabytes_per_second) {
+            self.span.record("bytes", bytes);
+            self.span.record("megabytes_per_second", throughput);
+        }
+        // The fields live on the span; the event makes fmt-style subscribers print it
+        let span = &self.span;
+        tracing::info!(parent: span, "finished");
+        if console_output() {
+            metrics.display();
+        }
+        metrics
+    }
+}
+
+/// A [`PerformanceTimer`] that cannot be forgotten: its metrics go to
+/// `on_finish` when it is finished explicitly or, failing that, when it is
+/// dropped. A timer dropped without [`ScopedTimer::finish`] (a `?` return,
+/// a panic, an abandoned future) reports as cancelled with the records
+/// counted so far.
+pub struct ScopedTimer {
+    timer: Option<PerformanceTimer>,
+    records: usize,
+    on_finish: Option<Box<dyn FnOnce(PerformanceMetrics) + Send>>,
+}
+
+impl ScopedTimer {
+    pub fn new(operation: String, on_finish: impl FnOnce(PerformanceMetrics) + Send + 'static) -> Self {
+        Self {
+            timer: Some(PerformanceTimer::new(operation)),
+            records: 0,
+            on_finish: Some(Box::new(on_finish)),
+        }
+    }
+
+    pub fn add_records(&mut self, records: usize) {
+        self.records += records;
+    }
+
+    pub fn add_bytes(&mut self, bytes: usize) {
+        if let Some(timer) = &mut self.timer {
+            timer.add_bytes(bytes);
+        }
+    }
+
+    pub fn add_retries(&mut self, retries: u32) {
+        if let Some(timer) = &mut self.timer {
+            timer.add_retries(retries);
+        }
+    }
+
+    pub fn phase(&mut self, name: &str) {
+        if let Some(timer) = &mut self.timer {
+            timer.phase(name);
+        }
+    }
+
+    /// Reports progress with the records counted so far.
+    pub fn record_progress(&mut self, bytes: u64) {
+        if let Some(timer) = &mut self.timer {
+            timer.record_progress(self.records, bytes);
+        }
+    }
+
+    pub fn finish(mut self) -> PerformanceMetrics {
+        self.complete(false)
+    }
+
+    /// Finishes early because of a cancellation token.
+    pub fn cancelled(mut self) -> PerformanceMetrics {
+        self.complete(true)
+    }
+
+    fn complete(&mut self, cancelled: bool) -> PerformanceMetrics {
+        let timer = self.timer.take().expect("completed once");
+        let metrics = timer.complete(self.records, cancelled);
+        if let Some(on_finish) = self.on_finish.take() {
+            on_finish(metrics.clone());
+        }
         metrics
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Drop for ScopedTimer {
+    fn drop(&mut self) {
+        if self.timer.is_some() {
+            self.complete(true);
+        }
+    }
+}
+
+/// Times `future`, which resolves to its value and the number of records it
+/// processed. The future runs inside the timer's span, so tracing events it
+/// emits are attributed to the operation.
+pub async fn instrument_async<T>(
+    operation: impl Into<String>,
+    future: impl std::future::Future<Output = (T, usize)>,
+) -> (T, PerformanceMetrics) {
+    use tracing::Instrument;
+
+    let timer = PerformanceTimer::new(operation.into());
+    let (value, records) = future.instrument(timer.span.clone()).await;
+    (value, timer.finish(records))
+}
+
+/// CPU time and page faults of the whole process so far.
+#[derive(Debug, Clone, Copy)]
+struct ResourceUsage {
+    user: Duration,
+    system: Duration,
+    minor_page_faults: u64,
+    major_page_faults: u64,
+}
+
+impl ResourceUsage {
+    #[cfg(unix)]
+    fn current() -> Option<Self> {
+        let mut usage = std::mem::MaybeUninit::<libc::rusage>::zeroed();
+        // SAFETY: getrusage only writes into the struct it is given
+        if unsafe { libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr()) } != 0 {
+            return None;
+        }
+        // SAFETY: initialized by the successful call above
+        let usage = unsafe { usage.assume_init() };
+        let duration = |time: libc::timeval| Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1_000);
+        Some(Self {
+            user: duration(usage.ru_utime),
+            system: duration(usage.ru_stime),
+            minor_page_faults: usage.ru_minflt as u64,
+            major_page_faults: usage.ru_majflt as u64,
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn current() -> Option<Self> {
+        None
+    }
+}
+
+/// Space on the filesystem holding a path.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct DiskSpace {
+    /// Bytes an unprivileged process may still write.
+    pub free_bytes: u64,
+    pub total_bytes: u64,
+}
+
+impl DiskSpace {
+    /// `None` off Unix, or when `path` does not exist.
+    #[cfg(unix)]
+    // The statvfs field types differ between platforms
+    #[allow(clippy::unnecessary_cast)]
+    pub fn of(path: &std::path::Path) -> Option<Self> {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+        let mut stat = std::mem::MaybeUninit::<libc::statvfs>::zeroed();
+        // SAFETY: statvfs only reads the path and writes into the struct it is given
+        if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+            return None;
+        }
+        // SAFETY: initialized by the successful call above
+        let stat = unsafe { stat.assume_init() };
+        Some(Self {
+            free_bytes: stat.f_bavail as u64 * stat.f_frsize as u64,
+            total_bytes: stat.f_blocks as u64 * stat.f_frsize as u64,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn of(_path: &std::path::Path) -> Option<Self> {
+        None
+    }
+}
+
+/// Throughput of one operation across all its stored runs.
+#[derive(Debug, Clone, Serialize)]
+pub struct OperationSummary {
+    pub operation: String,
+    pub runs: usize,
+    pub total_records: usize,
+    pub average_records_per_second: f64,
+    pub best_records_per_second: f64,
+    pub latest_records_per_second: f64,
+    pub first_run: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_run: Option<chrono::DateTime<chrono::Utc>>,
+    /// Mean throughput of the later half of the runs against the earlier
+    /// half, in percent. Needs at least two runs.
+    pub trend_percent: Option<f64>,
+    /// "improving", "regressing" or "stable" (within 5%).
+    pub trend: &'static str,
+}
+
+/// Groups `metrics` by operation name, oldest run first within each group,
+/// and summarises each group's throughput. Cancelled runs are left out.
+pub fn aggregate_by_operation(metrics: &[PerformanceMetrics]) -> Vec<OperationSummary> {
+    let mut groups: BTreeMap<&str, Vec<&PerformanceMetrics>> = BTreeMap::new();
+    for run in metrics.iter().filter(|run| !run.cancelled) {
+        groups.entry(&run.operation).or_default().push(run);
+    }
+
+    groups
+        .into_iter()
+        .map(|(operation, mut runs)| {
+            runs.sort_by_key(|run| run.recorded_at);
+            let throughputs: Vec<f64> = runs.iter().map(|run| run.records_per_second).collect();
+            let mean = |values: &[f64]| values.iter().sum::<f64>() / values.len() as f64;
+            let trend_percent = (throughputs.len() >= 2).then(|| {
+                let (earlier, later) = throughputs.split_at(throughputs.len() / 2);
+                (mean(later) / mean(earlier) - 1.0) * 100.0
+            });
+
+            OperationSummary {
+                operation: operation.to_string(),
+                runs: runs.len(),
+                total_records: runs.iter().map(|run| run.records_processed).sum(),
+                average_records_per_second: mean(&throughputs),
+                best_records_per_second: throughputs.iter().copied().fold(0.0, f64::max),
+                latest_records_per_second: throughputs.last().copied().unwrap_or(0.0),
+                first_run: runs.first().and_then(|run| run.recorded_at),
+                last_run: runs.last().and_then(|run| run.recorded_at),
+                trend_percent,
+                trend: match trend_percent {
+                    Some(change) if change > 5.0 => "improving",
+                    Some(change) if change < -5.0 => "regressing",
+                    _ => "stable",
+                },
+            }
+        })
+        .collect()
+}
+
+/// Resident set size of this process, in bytes, where the platform
+/// reports it.
+pub fn resident_memory_bytes() -> Option<u64> {
+    use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, System};
+
+    let pid = Pid::from_u32(std::process::id());
+    let mut system = System::new();
+    system.refresh_processes_specifics(ProcessesToUpdate::Some(&[pid]), false, ProcessRefreshKind::nothing().with_memory());
+    system.process(pid).map(|process| process.memory())
+}
+
+/// The machine a benchmark ran on, so results shared between users can be
+/// compared like for like.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SystemInfo {
+    pub cpu_model: String,
+    pub physical_cores: usize,
+    pub logical_cpus: usize,
+    pub total_memory_bytes: u64,
+    pub os: String,
+    pub rustc_version: String,
+    /// Worker threads of the tokio runtime the report was made from, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokio_worker_threads: Option<usize>,
+}
+
+impl SystemInfo {
+    pub fn collect() -> Self {
+        use sysinfo::{CpuRefreshKind, MemoryRefreshKind, RefreshKind, System};
+
+        let system = System::new_with_specifics(
+            RefreshKind::nothing()
+                .with_cpu(CpuRefreshKind::nothing())
+                .with_memory(MemoryRefreshKind::nothing().with_ram()),
+        );
+        Self {
+            cpu_model: system
+                .cpus()
+                .first()
+                .map_or_else(|| "unknown".to_string(), |cpu| cpu.brand().trim().to_string()),
+            physical_cores: num_cpus::get_physical(),
+            logical_cpus: num_cpus::get(),
+            total_memory_bytes: system.total_memory(),
+            os: System::long_os_version().unwrap_or_else(|| std::env::consts::OS.to_string()),
+            rustc_version: env!("RUSTC_VERSION").to_string(),
+            tokio_worker_threads: tokio::runtime::Handle::try_current()
+                .ok()
+                .map(|handle| handle.metrics().num_workers()),
+        }
+    }
+
+    pub fn display(&self) {
+        println!("🖥️  System: {}", self.os);
+        println!(
+            "   CPU: {} ({} cores, {} threads)",
+            self.cpu_model, self.physical_cores, self.logical_cpus
+        );
+        println!("   RAM: {:.1} GB", self.total_memory_bytes as f64 / 1_000_000_000.0);
+        println!("   Compiler: {}", self.rustc_version);
+        if let Some(workers) = self.tokio_worker_threads {
+            println!("   Tokio worker threads: {}", workers);
+        }
+        println!();
+    }
+}
+
+/// Accumulates the durations of repeated operations into one histogram per
+/// operation name, so a summary can show the spread and tail rather than
+/// only the latest run.
+#[derive(Clone, Default)]
+pub struct LatencyRecorder {
+    histograms: BTreeMap<String, Histogram<u64>>,
+}
+
+/// Percentiles for one operation, in milliseconds.
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencySummary {
+    pub operation: String,
+    pub count: u64,
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+impl LatencyRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, operation: &str, duration: Duration) {
+        let histogram = self
+            .histograms
+            .entry(operation.to_string())
+            // Auto-resizing, so no upper bound needs to be guessed up front
+            .or_insert_with(|| Histogram::new(3).expect("3 significant figures is in range"));
+        // Microsecond resolution; a zero-length run still counts as one sample.
+        // `record` grows the histogram, where `saturating_record` would clamp
+        // to its current range
+        let _ = histogram.record((duration.as_micros() as u64).max(1));
+    }
+
+    pub fn summary(&self) -> Vec<LatencySummary> {
+        let ms = |micros: u64| micros as f64 / 1_000.0;
+        self.histograms
+            .iter()
+            .map(|(operation, histogram)| LatencySummary {
+                operation: operation.clone(),
+                count: histogram.len(),
+                mean_ms: histogram.mean() / 1_000.0,
+                p50_ms: ms(histogram.value_at_quantile(0.50)),
+                p90_ms: ms(histogram.value_at_quantile(0.90)),
+                p99_ms: ms(histogram.value_at_quantile(0.99)),
+                max_ms: ms(histogram.max()),
+            })
+            .collect()
+    }
+
+    pub fn display(&self) {
+        if self.histograms.is_empty() {
+            return;
+        }
+        println!("⏲️  Latency percentiles:");
+        for latency in self.summary() {
+            println!(
+                "   {} (n={}): p50 {:.2} ms, p90 {:.2} ms, p99 {:.2} ms, max {:.2} ms",
+                latency.operation, latency.count, latency.p50_ms, latency.p90_ms, latency.p99_ms, latency.max_ms
+            );
+        }
+        println!();
+    }
+}
+
+/// Runs an operation a few times unmeasured to warm caches and the
+/// allocator, then `iterations` measured times, and summarises the spread.
+/// Single runs are too noisy to tell strategies apart.
+#[derive(Debug, Clone, Copy)]
+pub struct BenchmarkRunner {
+    pub warmup: usize,
+    pub iterations: usize,
+}
+
+/// Duration statistics over the measured iterations of one operation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchmarkStats {
+    pub operation: String,
+    pub iterations: usize,
+    pub mean: Duration,
+    pub median: Duration,
+    pub stddev: Duration,
+    pub min: Duration,
+    pub max: Duration,
+    /// Mean records per second across the measured iterations.
+    pub records_per_second: f64,
+    /// True when an iteration was cancelled; the stats then only cover the
+    /// iterations that completed.
+    #[serde(default)]
+    pub cancelled: bool,
+    /// The individual measured runs.
+    #[serde(skip)]
+    pub runs: Vec<PerformanceMetrics>,
+}
+
+impl BenchmarkRunner {
+    pub fn new(warmup: usize, iterations: usize) -> Self {
+        Self { warmup, iterations: iterations.max(1) }
+    }
+
+    pub fn run<E>(&self, mut operation: impl FnMut() -> Result<PerformanceMetrics, E>) -> Result<BenchmarkStats, E> {
+        let mut runs = Vec::with_capacity(self.iterations);
+        for iteration in 0..self.warmup + self.iterations {
+            let metrics = operation()?;
+            if metrics.cancelled {
+                return Ok(BenchmarkStats::from_runs(runs, true, metrics.operation));
+            }
+            if iteration >= self.warmup {
+                runs.push(metrics);
+            }
+        }
+        Ok(BenchmarkStats::from_runs(runs, false, String::new()))
+    }
+
+    pub async fn run_async<E, F>(&self, mut operation: impl FnMut() -> F) -> Result<BenchmarkStats, E>
+    where
+        F: std::future::Future<Output = Result<PerformanceMetrics, E>>,
+    {
+        let mut runs = Vec::with_capacity(self.iterations);
+        for iteration in 0..self.warmup + self.iterations {
+            let metrics = operation().await?;
+            if metrics.cancelled {
+                return Ok(BenchmarkStats::from_runs(runs, true, metrics.operation));
+            }
+            if iteration >= self.warmup {
+                runs.push(metrics);
+            }
+        }
+        Ok(BenchmarkStats::from_runs(runs, false, String::new()))
+    }
+}
+
+impl BenchmarkStats {
+    /// `operation` names the stats when `runs` is empty.
+    fn from_runs(runs: Vec<PerformanceMetrics>, cancelled: bool, operation: String) -> Self {
+        let mut durations: Vec<Duration> = runs.iter().map(|run| run.duration).collect();
+        durations.sort();
+        let count = durations.len().max(1) as f64;
+        let mean_secs = durations.iter().map(Duration::as_secs_f64).sum::<f64>() / count;
+        let variance = durations.iter().map(|d| (d.as_secs_f64() - mean_secs).powi(2)).sum::<f64>() / count;
+        let median = match durations.len() {
+            0 => Duration::ZERO,
+            len if len % 2 == 0 => (durations[len / 2 - 1] + durations[len / 2]) / 2,
+            len => durations[len / 2],
+        };
+
+        Self {
+            operation: runs.first().map_or(operation, |run| run.operation.clone()),
+            iterations: runs.len(),
+            mean: Duration::from_secs_f64(mean_secs),
+            median,
+            stddev: Duration::from_secs_f64(variance.sqrt()),
+            min: durations.first().copied().unwrap_or_default(),
+            max: durations.last().copied().unwrap_or_default(),
+            records_per_second: runs.iter().map(|run| run.records_per_second).sum::<f64>() / count,
+            cancelled,
+            runs,
+        }
+    }
+
+    /// Headline numbers for baselines, labelled `strategy`.
+    pub fn result(&self, strategy: String) -> StrategyResult {
+        let throughputs: Vec<f64> = self.runs.iter().filter_map(|run| run.megabytes_per_second).collect();
+        StrategyResult {
+            strategy,
+            records: self.runs.first().map_or(0, |run| run.records_processed),
+            duration: self.mean,
+            records_per_second: self.records_per_second,
+            megabytes_per_second: (!throughputs.is_empty())
+                .then(|| throughputs.iter().sum::<f64>() / throughputs.len() as f64),
+            iterations: self.iterations,
+            spread: Some(Spread { median: self.median, stddev: self.stddev, min: self.min, max: self.max }),
+            concurrency: self.runs.first().and_then(|run| run.concurrency),
+        }
+    }
+
+    pub fn display(&self) {
+        println!("📈 {} over {} iterations", self.operation, self.iterations);
+        println!(
+            "   Mean: {:?} ± {:?} (median {:?}, min {:?}, max {:?})",
+            self.mean, self.stddev, self.median, self.min, self.max
+        );
+        println!("   Records/second: {:.2}", self.records_per_second);
+        if self.cancelled {
+            println!("   ⛔ Cancelled, stats cover completed iterations only");
+        }
+        println!();
+    }
+}
+
+/// One strategy's headline numbers from a benchmark run, as stored in
+/// baselines.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StrategyResult {
+    /// Strategy and dataset, e.g. "Synchronous — Small Dataset".
+    pub strategy: String,
+    pub records: usize,
+    /// Mean duration when the strategy ran several times.
+    pub duration: Duration,
+    pub records_per_second: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub megabytes_per_second: Option<f64>,
+    /// Measured runs behind `duration`; 1 for single-shot benchmarks and
+    /// baselines saved before this was recorded.
+    #[serde(default = "one")]
+    pub iterations: usize,
+    /// Spread of the durations when the strategy ran several times.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spread: Option<Spread>,
+    /// Threads or tasks, for strategies that take a concurrency level.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concurrency: Option<usize>,
+}
+
+fn one() -> usize {
+    1
+}
+
+/// Duration spread over the iterations of a [`StrategyResult`].
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Spread {
+    pub median: Duration,
+    pub stddev: Duration,
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl StrategyResult {
+    /// The result of a single measured run.
+    pub fn from_metrics(strategy: String, metrics: &PerformanceMetrics) -> Self {
+        Self {
+            strategy,
+            records: metrics.records_processed,
+            duration: metrics.duration,
+            records_per_second: metrics.records_per_second,
+            megabytes_per_second: metrics.megabytes_per_second,
+            iterations: 1,
+            spread: None,
+            concurrency: metrics.concurrency,
+        }
+    }
+
+    /// Splits the "Strategy — File" label; `None` when it names no file.
+    pub fn split_label(&self) -> (&str, Option<&str>) {
+        match self.strategy.split_once(" — ") {
+            Some((strategy, file)) => (strategy, Some(file)),
+            None => (&self.strategy, None),
+        }
+    }
+}
+
+/// Version of [`ResultsDocument`]. Bump it whenever a field is renamed,
+/// removed or changes meaning; adding optional fields keeps the version.
+pub const RESULTS_SCHEMA_VERSION: u32 = 1;
+
+/// Benchmark results in the stable, versioned layout described by
+/// `schemas/benchmark-results.schema.json`. Written by `--output FILE.json`
+/// and served by `GET /benchmark/results`, for CI and dashboards.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResultsDocument {
+    pub schema_version: u32,
+    /// The binary that produced the results.
+    pub generator: String,
+    pub generated_at: chrono::DateTime<chrono::Utc>,
+    pub environment: SystemInfo,
+    pub results: Vec<ResultEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResultEntry {
+    pub strategy: String,
+    /// Dataset the strategy ran on; null when the label names none.
+    pub file: Option<String>,
+    pub records: usize,
+    pub iterations: usize,
+    /// Threads or tasks, for strategies that take a concurrency level.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concurrency: Option<usize>,
+    pub stats: ResultStats,
+}
+
+/// Durations in milliseconds. Single-shot results report their one
+/// duration everywhere and a zero stddev.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResultStats {
+    pub mean_ms: f64,
+    pub median_ms: f64,
+    pub stddev_ms: f64,
+    pub min_ms: f64,
+    pub max_ms: f64,
+    pub records_per_second: f64,
+    pub megabytes_per_second: Option<f64>,
+}
+
+impl ResultsDocument {
+    pub fn new(generator: &str, results: &[StrategyResult]) -> Self {
+        let ms = |duration: Duration| duration.as_secs_f64() * 1_000.0;
+        let results = results
+            .iter()
+            .map(|result| {
+                let (strategy, file) = result.split_label();
+                let spread = result.spread.unwrap_or(Spread {
+                    median: result.duration,
+                    stddev: Duration::ZERO,
+                    min: result.duration,
+                    max: result.duration,
+                });
+                ResultEntry {
+                    strategy: strategy.to_string(),
+                    file: file.map(str::to_string),
+                    records: result.records,
+                    iterations: result.iterations,
+                    concurrency: result.concurrency,
+                    stats: ResultStats {
+                        mean_ms: ms(result.duration),
+                        median_ms: ms(spread.median),
+                        stddev_ms: ms(spread.stddev),
+                        min_ms: ms(spread.min),
+                        max_ms: ms(spread.max),
+                        records_per_second: result.records_per_second,
+                        megabytes_per_second: result.megabytes_per_second,
+                    },
+                }
+            })
+            .collect();
+
+        Self {
+            schema_version: RESULTS_SCHEMA_VERSION,
+            generator: generator.to_string(),
+            generated_at: chrono::Utc::now(),
+            environment: SystemInfo::collect(),
+            results,
+        }
+    }
+}
+
+impl ResultsDocument {
+    /// Reads a results file written with `--output FILE.json` or fetched
+    /// from `GET /benchmark/results`.
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let content = std::fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+        let document: Self = serde_json::from_str(&content).map_err(|e| format!("{} is not a results file: {}", path, e))?;
+        if document.schema_version != RESULTS_SCHEMA_VERSION {
+            return Err(format!(
+                "{} has schema version {}, this build reads version {}",
+                path, document.schema_version, RESULTS_SCHEMA_VERSION
+            )
+            .into());
+        }
+        Ok(document)
+    }
+}
+
+/// Whether a throughput change between two runs stands out from their
+/// run-to-run noise.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Significance {
+    /// Welch's t statistic of the mean durations is beyond ±2, roughly 95%
+    /// confidence that the change is real.
+    Significant(f64),
+    /// Within the spread of the runs.
+    Noise(f64),
+    /// One side ran once, so there is no spread to compare against.
+    Unknown,
+}
+
+/// How one strategy on one file moved between two results files.
+#[derive(Debug, Clone)]
+pub struct ResultDiff {
+    pub strategy: String,
+    pub file: Option<String>,
+    pub old_records_per_second: f64,
+    pub new_records_per_second: f64,
+    /// Positive when the new run is faster.
+    pub change_percent: f64,
+    pub significance: Significance,
+}
+
+impl ResultDiff {
+    fn new(old: &ResultEntry, new: &ResultEntry) -> Self {
+        let significance = if old.iterations < 2 || new.iterations < 2 {
+            Significance::Unknown
+        } else {
+            let variance = |entry: &ResultEntry| entry.stats.stddev_ms.powi(2) / entry.iterations as f64;
+            let standard_error = (variance(old) + variance(new)).sqrt();
+            let difference = old.stats.mean_ms - new.stats.mean_ms;
+            let t = if standard_error > 0.0 {
+                difference / standard_error
+            } else if difference == 0.0 {
+                0.0
+            } else {
+                difference.signum() * f64::INFINITY
+            };
+            if t.abs() > 2.0 {
+                Significance::Significant(t)
+            } else {
+                Significance::Noise(t)
+            }
+        };
+
+        Self {
+            strategy: new.strategy.clone(),
+            file: new.file.clone(),
+            old_records_per_second: old.stats.records_per_second,
+            new_records_per_second: new.stats.records_per_second,
+            change_percent: (new.stats.records_per_second / old.stats.records_per_second - 1.0) * 100.0,
+            significance,
+        }
+    }
+}
+
+/// Pairs up the results of two runs by strategy and file. Entries found in
+/// only one of them are returned separately, old then new.
+pub fn diff_results<'a>(
+    old: &'a ResultsDocument,
+    new: &'a ResultsDocument,
+) -> (Vec<ResultDiff>, Vec<&'a ResultEntry>, Vec<&'a ResultEntry>) {
+    let matches = |a: &ResultEntry, b: &ResultEntry| a.strategy == b.strategy && a.file == b.file;
+    let diffs = new
+        .results
+        .iter()
+        .filter_map(|entry| {
+            let previous = old.results.iter().find(|previous| matches(previous, entry))?;
+            Some(ResultDiff::new(previous, entry))
+        })
+        .collect();
+    let only_old = old.results.iter().filter(|entry| !new.results.iter().any(|other| matches(entry, other))).collect();
+    let only_new = new.results.iter().filter(|entry| !old.results.iter().any(|other| matches(entry, other))).collect();
+    (diffs, only_old, only_new)
+}
+
+/// Benchmark results saved under `baselines/<name>.json`, so a later run
+/// can check that no strategy got slower.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Baseline {
+    pub name: String,
+    pub recorded_at: chrono::DateTime<chrono::Utc>,
+    /// Missing from baselines saved before system info was recorded.
+    #[serde(default)]
+    pub system: Option<SystemInfo>,
+    pub results: Vec<StrategyResult>,
+}
+
+/// How one strategy's throughput moved against the baseline.
+#[derive(Debug, Clone)]
+pub struct BaselineComparison {
+    pub strategy: String,
+    pub baseline_records_per_second: f64,
+    pub records_per_second: f64,
+    /// Positive when faster than the baseline.
+    pub change_percent: f64,
+    pub regressed: bool,
+}
+
+impl Baseline {
+    pub fn new(name: &str, results: Vec<StrategyResult>) -> Self {
+        Self {
+            name: name.to_string(),
+            recorded_at: chrono::Utc::now(),
+            system: Some(SystemInfo::collect()),
+            results,
+        }
+    }
+
+    pub fn path(name: &str) -> std::path::PathBuf {
+        std::path::Path::new("baselines").join(format!("{}.json", name))
+    }
+
+    pub fn save(&self) -> Result<std::path::PathBuf, Box<dyn std::error::Error>> {
+        let path = Self::path(&self.name);
+        std::fs::create_dir_all("baselines")?;
+        std::fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(path)
+    }
+
+    pub fn load(name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = Self::path(name);
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| format!("cannot read baseline {}: {}", path.display(), e))?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Compares `results` with the baseline by strategy name. A strategy
+    /// regressed when its records/second fell by more than
+    /// `max_regression_percent`. Strategies missing on either side are
+    /// left out.
+    pub fn compare(&self, results: &[StrategyResult], max_regression_percent: f64) -> Vec<BaselineComparison> {
+        results
+            .iter()
+            .filter_map(|result| {
+                let baseline = self.results.iter().find(|b| b.strategy == result.strategy)?;
+                let change_percent =
+                    (result.records_per_second / baseline.records_per_second - 1.0) * 100.0;
+                Some(BaselineComparison {
+                    strategy: result.strategy.clone(),
+                    baseline_records_per_second: baseline.records_per_second,
+                    records_per_second: result.records_per_second,
+                    change_percent,
+                    regressed: change_percent < -max_regression_percent,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Prints a comparison table and returns how many strategies regressed.
+pub fn display_comparison(baseline: &Baseline, comparisons: &[BaselineComparison]) -> usize {
+    println!("📏 Compared with baseline '{}' from {}", baseline.name, baseline.recorded_at.format("%Y-%m-%d %H:%M"));
+    if let Some(system) = &baseline.system {
+        if system.cpu_model != SystemInfo::collect().cpu_model {
+            println!("   ⚠️  Baseline was recorded on a different CPU: {}", system.cpu_model);
+        }
+    }
+    for comparison in comparisons {
+        println!(
+            "   {} {}: {:.0} → {:.0} records/sec ({:+.1}%)",
+            if comparison.regressed { "❌" } else { "✅" },
+            comparison.strategy,
+            comparison.baseline_records_per_second,
+            comparison.records_per_second,
+            comparison.change_percent
+        );
+    }
+    let regressions = comparisons.iter().filter(|c| c.regressed).count();
+    if regressions > 0 {
+        println!("⚠️  {} strategies regressed", regressions);
+    }
+    println!();
+    regressions
+}
+
+/// File formats for exported results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// A [`ResultsDocument`].
+    Json,
+    /// One flat row per strategy.
+    Csv,
+}
+
+impl OutputFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(Self::Json),
+            "csv" => Some(Self::Csv),
+            _ => None,
+        }
+    }
+
+    /// `--format` if given, otherwise the extension of `path`.
+    pub fn for_output(path: &str, format: Option<&str>) -> Option<Self> {
+        format
+            .or_else(|| std::path::Path::new(path).extension().and_then(|e| e.to_str()))
+            .and_then(Self::parse)
+    }
+}
+
+/// Adds the `--output`, `--format`, `--save-baseline`, `--compare` and
+/// `--max-regression` options shared by the benchmark binaries.
+pub fn results_args(command: clap::Command) -> clap::Command {
+    command
+        .arg(
+            clap::Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("FILE")
+                .help("Also write the results to FILE (.json or .csv, or see --format)")
+        )
+        .arg(
+            clap::Arg::new("format")
+                .long("format")
+                .value_name("FORMAT")
+                .help("Format of --output when its extension does not say")
+                .value_parser(["json", "csv"])
+        )
+        .arg(
+            clap::Arg::new("save-baseline")
+                .long("save-baseline")
+                .value_name("NAME")
+                .help("Write the results to baselines/NAME.json")
+        )
+        .arg(
+            clap::Arg::new("compare")
+                .long("compare")
+                .value_name("NAME")
+                .help("Compare with baselines/NAME.json and exit non-zero on a regression")
+        )
+        .arg(
+            clap::Arg::new("max-regression")
+                .long("max-regression")
+                .value_name("PERCENT")
+                .help("Throughput drop that counts as a regression with --compare")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("5")
+        )
+}
+
+/// One row of an exported CSV results file.
+#[derive(Serialize)]
+struct ResultRow<'a> {
+    recorded_at: chrono::DateTime<chrono::Utc>,
+    strategy: &'a str,
+    records: usize,
+    duration_ms: f64,
+    records_per_second: f64,
+    megabytes_per_second: Option<f64>,
+    concurrency: Option<usize>,
+}
+
+/// Checks that `--output` has a format, so a typo fails before the
+/// benchmark runs rather than after.
+pub fn check_results_args(matches: &clap::ArgMatches) -> Result<(), String> {
+    let Some(path) = matches.get_one::<String>("output") else {
+        return Ok(());
+    };
+    match OutputFormat::for_output(path, matches.get_one::<String>("format").map(String::as_str)) {
+        Some(_) => Ok(()),
+        None => Err(format!("cannot tell the format of '{}'; use a .json or .csv name or pass --format", path)),
+    }
+}
+
+/// Writes `results` as a [`ResultsDocument`] or a flat CSV file.
+pub fn write_results(
+    path: &str,
+    format: OutputFormat,
+    generator: &str,
+    results: &[StrategyResult],
+) -> Result<(), Box<dyn std::error::Error>> {
+    if format == OutputFormat::Json {
+        std::fs::write(path, serde_json::to_string_pretty(&ResultsDocument::new(generator, results))?)?;
+        return Ok(());
+    }
+
+    let recorded_at = chrono::Utc::now();
+    let rows = results.iter().map(|result| ResultRow {
+        recorded_at,
+        strategy: &result.strategy,
+        records: result.records,
+        duration_ms: result.duration.as_secs_f64() * 1_000.0,
+        records_per_second: result.records_per_second,
+        megabytes_per_second: result.megabytes_per_second,
+        concurrency: result.concurrency,
+    });
+
+    let mut writer = csv::Writer::from_path(path)?;
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Acts on the options from [`results_args`]; `generator` names the binary
+/// in JSON output. Returns false when `--compare` found a regression.
+pub fn apply_results_args(
+    matches: &clap::ArgMatches,
+    generator: &str,
+    results: &[StrategyResult],
+) -> Result<bool, Box<dyn std::error::Error>> {
+    if let Some(path) = matches.get_one::<String>("output") {
+        let format = OutputFormat::for_output(path, matches.get_one::<String>("format").map(String::as_str))
+            .ok_or_else(|| format!("cannot tell the format of '{}'", path))?;
+        write_results(path, format, generator, results)?;
+        println!("📝 Wrote results to {}", path);
+    }
+    if let Some(name) = matches.get_one::<String>("save-baseline") {
+        let path = Baseline::new(name, results.to_vec()).save()?;
+        println!("💾 Saved baseline to {}", path.display());
+    }
+    if let Some(name) = matches.get_one::<String>("compare") {
+        let baseline = Baseline::load(name)?;
+        let max_regression = *matches.get_one::<f64>("max-regression").unwrap();
+        let comparisons = baseline.compare(results, max_regression);
+        if display_comparison(&baseline, &comparisons) > 0 {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Adds `--worker-threads`, `--max-blocking-threads` and
+/// `--thread-stack-size`, shared by the server and benchmark binaries to
+/// size their tokio runtime. Each can also come from the environment.
+pub fn runtime_args(command: clap::Command) -> clap::Command {
+    let count = || clap::builder::RangedU64ValueParser::<usize>::new().range(1..);
+    command
+        .arg(
+            clap::Arg::new("worker-threads")
+                .long("worker-threads")
+                .value_name("N")
+                .env("TOKIO_WORKER_THREADS")
+                .help("Tokio worker threads (default: one per CPU)")
+                .value_parser(count())
+        )
+        .arg(
+            clap::Arg::new("max-blocking-threads")
+                .long("max-blocking-threads")
+                .value_name("N")
+                .env("TOKIO_MAX_BLOCKING_THREADS")
+                .help("Most threads tokio starts for blocking work such as file reads (default: 512)")
+                .value_parser(count())
+        )
+        .arg(
+            clap::Arg::new("thread-stack-size")
+                .long("thread-stack-size")
+                .value_name("SIZE")
+                .env("TOKIO_THREAD_STACK_SIZE")
+                .help("Stack size of every tokio thread, e.g. 4MiB (default: 2MiB)")
+                .value_parser(|value: &str| {
+                    parse_size(value).and_then(|size| usize::try_from(size).map_err(|_| format!("{} bytes is too large", size)))
+                })
+        )
+}
+
+/// The tokio runtime sizing given with [`runtime_args`]; tokio's defaults
+/// for whatever was not.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RuntimeOptions {
+    pub worker_threads: Option<usize>,
+    pub max_blocking_threads: Option<usize>,
+    pub thread_stack_size: Option<usize>,
+}
+
+impl RuntimeOptions {
+    pub fn from_matches(matches: &clap::ArgMatches) -> Self {
+        Self {
+            worker_threads: matches.get_one::<usize>("worker-threads").copied(),
+            max_blocking_threads: matches.get_one::<usize>("max-blocking-threads").copied(),
+            thread_stack_size: matches.get_one::<usize>("thread-stack-size").copied(),
+        }
+    }
+
+    /// A multi-threaded runtime with every driver enabled, as
+    /// `#[tokio::main]` would build.
+    pub fn build(&self) -> std::io::Result<tokio::runtime::Runtime> {
+        let mut builder = tokio::runtime::Builder::new_multi_thread();
+        builder.enable_all();
+        if let Some(threads) = self.worker_threads {
+            builder.worker_threads(threads);
+        }
+        if let Some(threads) = self.max_blocking_threads {
+            builder.max_blocking_threads(threads);
+        }
+        if let Some(size) = self.thread_stack_size {
+            builder.thread_stack_size(size);
+        }
+        builder.build()
+    }
+
+    /// Prints the options that differ from tokio's defaults, if any.
+    pub fn display(&self) {
+        let mut tuned = Vec::new();
+        if let Some(threads) = self.worker_threads {
+            tuned.push(format!("{} worker threads", threads));
+        }
+        if let Some(threads) = self.max_blocking_threads {
+            tuned.push(format!("at most {} blocking threads", threads));
+        }
+        if let Some(size) = self.thread_stack_size {
+            tuned.push(format!("{}-byte thread stacks", size));
+        }
+        if !tuned.is_empty() {
+            println!("⚙️  Tokio runtime: {}", tuned.join(", "));
+        }
+    }
+}
+
+/// Parses a byte size such as `1GB`, `500 MB`, `2GiB`, or `1048576`./// Parses a byte size such as `1GB`, `500 MB`, `2GiB`, or `1048576`.
+/// Unsuffixed and SI units are powers of 1000, `KiB`-style ones of 1024.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "t" | "tb" => 1_000_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        _ => return Err(format!("unknown size unit in '{}' (use B, KB, MB, GB, TB, or KiB, MiB, GiB, TiB)", value)),
+    };
+    match number.parse::<f64>() {
+        Ok(number) if number > 0.0 => Ok((number * multiplier as f64) as u64),
+        _ => Err(format!("'{}' is not a positive size", value)),
+    }
+}
+
+/// Counting wrapper around the system allocator, installed as the global
+/// allocator of every binary that includes this file when the
+/// `alloc-tracking` feature is on. Peaks are process-wide, so overlapping
+/// timers see each other's allocations.
+#[cfg(feature = "alloc-tracking")]
+pub mod alloc_tracking {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+    static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+    static CURRENT_BYTES: AtomicUsize = AtomicUsize::new(0);
+    static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+    pub struct CountingAllocator;
+
+    #[global_allocator]
+    static GLOBAL: CountingAllocator = CountingAllocator;
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct Snapshot {
+        pub allocations: u64,
+        pub current_bytes: usize,
+        pub peak_bytes: usize,
+    }
+
+    pub fn snapshot() -> Snapshot {
+        Snapshot {
+            allocations: ALLOCATIONS.load(Ordering::Relaxed),
+            current_bytes: CURRENT_BYTES.load(Ordering::Relaxed),
+            peak_bytes: PEAK_BYTES.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Restarts peak tracking from the current heap size.
+    pub fn reset_peak() -> Snapshot {
+        PEAK_BYTES.store(CURRENT_BYTES.load(Ordering::Relaxed), Ordering::Relaxed);
+        snapshot()
+    }
+
+    fn grew(bytes: usize) {
+        let current = CURRENT_BYTES.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        PEAK_BYTES.fetch_max(current, Ordering::Relaxed);
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let ptr = System.alloc(layout);
+            if !ptr.is_null() {
+                ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+                grew(layout.size());
+            }
+            ptr
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            let ptr = System.alloc_zeroed(layout);
+            if !ptr.is_null() {
+                ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+                grew(layout.size());
+            }
+            ptr
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout);
+            CURRENT_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let new_ptr = System.realloc(ptr, layout, new_size);
+            if !new_ptr.is_null() {
+                // A growing Vec or String reallocates; count it like a fresh
+                // allocation, since that is the cost being measured
+                ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+                if new_size > layout.size() {
+                    grew(new_size - layout.size());
+                } else {
+                    CURRENT_BYTES.fetch_sub(layout.size() - new_size, Ordering::Relaxed);
+                }
+            }
+            new_ptr
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SalesRecord {
     pub id: u32,
     pub customer_name: String,
@@ -65,4 +1560,18 @@ pub struct SalesRecord {
     pub price: f64,
     pub date: String,
     pub region: String,
-}
\ No newline at end of file
+}
+
+impl SalesRecord {
+    /// Bytes the record takes in memory, its strings included.
+    pub fn memory_bytes(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.customer_name.capacity()
+            + self.product.capacity()
+            + self.date.capacity()
+            + self.region.capacity()
+    }
+}
+
+/// Header names a CSV needs to deserialize into [`SalesRecord`], in any order.
+pub const SALES_COLUMNS: [&str; 7] = ["id", "customer_name", "product", "quantity", "price", "date", "region"];
\ No newline at end of file
@@ -0,0 +1,130 @@
+use crate::performance_utils::SalesRecord;
+use crate::quantile::StreamingQuantile;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Most bands a file may be split into.
+pub const MAX_BANDS: usize = 100;
+
+/// Orders whose unit price falls between two estimated price quantiles.
+#[derive(Debug, Clone, Serialize)]
+pub struct PriceBand {
+    /// 1 for the cheapest band.
+    pub band: usize,
+    /// The lowest price in the band: the file's minimum for the first band,
+    /// otherwise the estimated quantile the band starts at.
+    pub from: f64,
+    /// The price the next band starts at, or the file's maximum, which the
+    /// last band includes.
+    pub to: f64,
+    pub orders: u64,
+    pub quantity: u64,
+    pub revenue: f64,
+    /// The band's share of the file's revenue, between 0 and 1.
+    pub revenue_share: f64,
+    /// Products with the most revenue in the band, most first.
+    pub top_products: Vec<BandProduct>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BandProduct {
+    pub product: String,
+    pub orders: u64,
+    pub quantity: u64,
+    pub revenue: f64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct PriceBands {
+    pub bands: Vec<PriceBand>,
+    pub total_revenue: f64,
+    /// Records read before the analysis stopped.
+    pub records: usize,
+    pub cancelled: bool,
+}
+
+#[derive(Default)]
+struct BandTotals {
+    orders: u64,
+    quantity: u64,
+    revenue: f64,
+    products: HashMap<String, (u64, u64, f64)>,
+}
+
+/// Splits `records` into `bands` bands of about equal order counts by unit
+/// price, e.g. 4 for quartiles, with the cut points estimated in one pass
+/// by [`StreamingQuantile`] and the bands totalled in a second. Prices are
+/// multiplied by `price_factor`, e.g. an exchange rate. Stops early, with
+/// what it has, once `cancelled` returns true.
+pub fn price_bands(records: &[SalesRecord], bands: usize, top: usize, price_factor: f64, cancelled: impl Fn() -> bool) -> PriceBands {
+    let bands = bands.clamp(1, MAX_BANDS);
+    let mut cuts: Vec<StreamingQuantile> = (1..bands).map(|band| StreamingQuantile::new(band as f64 / bands as f64)).collect();
+    let (mut min, mut max) = (f64::INFINITY, f64::NEG_INFINITY);
+    let prices = records.iter().map(|record| record.price * price_factor).filter(|price| !price.is_nan());
+    for (index, price) in prices.enumerate() {
+        if index % 1_024 == 0 && cancelled() {
+            return PriceBands { records: index, cancelled: true, ..PriceBands::default() };
+        }
+        min = min.min(price);
+        max = max.max(price);
+        for cut in &mut cuts {
+            cut.add(price);
+        }
+    }
+    if min > max {
+        return PriceBands { records: records.len(), ..PriceBands::default() };
+    }
+    // Estimates of neighbouring quantiles can cross on lumpy prices
+    let mut bounds: Vec<f64> = cuts.iter().filter_map(StreamingQuantile::value).map(|cut| cut.clamp(min, max)).collect();
+    bounds.sort_by(f64::total_cmp);
+
+    let mut totals: Vec<BandTotals> = (0..bands).map(|_| BandTotals::default()).collect();
+    let mut total_revenue = 0.0;
+    let mut read = 0;
+    for record in records {
+        if read % 1_024 == 0 && cancelled() {
+            break;
+        }
+        read += 1;
+        let price = record.price * price_factor;
+        if price.is_nan() {
+            continue;
+        }
+        let band = bounds.partition_point(|&bound| bound <= price);
+        let revenue = price * record.quantity as f64;
+        let totals = &mut totals[band];
+        totals.orders += 1;
+        totals.quantity += record.quantity as u64;
+        totals.revenue += revenue;
+        let product = totals.products.entry(record.product.clone()).or_default();
+        product.0 += 1;
+        product.1 += record.quantity as u64;
+        product.2 += revenue;
+        total_revenue += revenue;
+    }
+
+    let bands = totals
+        .into_iter()
+        .enumerate()
+        .map(|(index, totals)| {
+            let mut products: Vec<BandProduct> = totals
+                .products
+                .into_iter()
+                .map(|(product, (orders, quantity, revenue))| BandProduct { product, orders, quantity, revenue })
+                .collect();
+            products.sort_by(|a, b| b.revenue.total_cmp(&a.revenue).then_with(|| a.product.cmp(&b.product)));
+            products.truncate(top);
+            PriceBand {
+                band: index + 1,
+                from: if index == 0 { min } else { bounds[index - 1] },
+                to: bounds.get(index).copied().unwrap_or(max),
+                orders: totals.orders,
+                quantity: totals.quantity,
+                revenue: totals.revenue,
+                revenue_share: if total_revenue > 0.0 { totals.revenue / total_revenue } else { 0.0 },
+                top_products: products,
+            }
+        })
+        .collect();
+    PriceBands { bands, total_revenue, records: read, cancelled: read < records.len() }
+}
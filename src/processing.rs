@@ -0,0 +1,635 @@
+use crate::performance_utils::SalesRecord;
+use csv::{ByteRecord, ReaderBuilder};
+use rayon::prelude::*;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+// The CSV processing strategies the examples compare, reduced to their
+// parsing work on an in-memory file so they can be benchmarked in isolation.
+// Each returns the number of records parsed; its `*_records` form returns
+// the records themselves, which the golden-file tests compare across
+// strategies so none of them drifts from the others.
+
+/// Whether CSV `content` has a row after the header. Empty and header-only
+/// files have none.
+pub fn has_data_rows(content: &[u8]) -> bool {
+    content.split(|&byte| byte == b'\n').skip(1).any(|line| !line.trim_ascii().is_empty())
+}
+
+/// Finds where CSV rows end, one byte at a time, the way the csv reader
+/// quotes: a quote opens a quoted field only at the start of a field, and
+/// a doubled quote inside one is an escaped quote, not its end.
+#[derive(Debug, Clone, Copy)]
+pub struct RowEnds {
+    delimiter: u8,
+    field_start: bool,
+    quoted: bool,
+    /// Just past a closing quote, where another quote reopens the field.
+    closed: bool,
+}
+
+impl RowEnds {
+    /// For comma-separated rows.
+    pub fn new() -> Self {
+        Self::with_delimiter(b',')
+    }
+
+    pub fn with_delimiter(delimiter: u8) -> Self {
+        Self { delimiter, field_start: true, quoted: false, closed: false }
+    }
+
+    /// Whether `byte` is a newline that ends a row.
+    pub fn ends_row(&mut self, byte: u8) -> bool {
+        if self.quoted {
+            if byte == b'"' {
+                self.quoted = false;
+                self.closed = true;
+            }
+            return false;
+        }
+        let reopens = self.closed;
+        self.closed = false;
+        match byte {
+            b'"' if self.field_start || reopens => {
+                self.quoted = true;
+                self.field_start = false;
+                false
+            }
+            b'\n' => {
+                self.field_start = true;
+                true
+            }
+            byte => {
+                self.field_start = byte == self.delimiter;
+                false
+            }
+        }
+    }
+}
+
+impl Default for RowEnds {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Splits CSV `content` into its rows, header first, at the newlines that
+/// end a row. Unlike `str::lines`, a newline inside a quoted field stays
+/// in its row, so the chunked strategies can cut the file anywhere between
+/// rows. A `\r` before the newline is dropped, as `lines` does.
+pub fn split_rows(content: &str) -> Vec<&str> {
+    let mut rows = Vec::new();
+    let mut start = 0;
+    let mut row_ends = RowEnds::new();
+    for (index, byte) in content.bytes().enumerate() {
+        if row_ends.ends_row(byte) {
+            let row = &content[start..index];
+            rows.push(row.strip_suffix('\r').unwrap_or(row));
+            start = index + 1;
+        }
+    }
+    if start < content.len() {
+        rows.push(&content[start..]);
+    }
+    rows
+}
+
+/// Deserializes every record in one pass on the current thread.
+pub fn parse_sync(content: &str) -> csv::Result<usize> {
+    sync_records(content).map(|records| records.len())
+}
+
+/// The records [`parse_sync`] counts.
+pub fn sync_records(content: &str) -> csv::Result<Vec<SalesRecord>> {
+    let mut reader = ReaderBuilder::new().from_reader(content.as_bytes());
+    let mut records = Vec::new();
+    for result in reader.deserialize() {
+        let record: SalesRecord = result?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Like [`parse_sync`], but yields to the runtime every 1000 records.
+pub async fn parse_async(content: &str) -> csv::Result<usize> {
+    async_records(content).await.map(|records| records.len())
+}
+
+/// The records [`parse_async`] counts.
+pub async fn async_records(content: &str) -> csv::Result<Vec<SalesRecord>> {
+    let mut reader = ReaderBuilder::new().from_reader(content.as_bytes());
+    let mut records = Vec::new();
+    for result in reader.deserialize() {
+        let record: SalesRecord = result?;
+        records.push(record);
+        if records.len() % 1000 == 0 {
+            tokio::task::yield_now().await;
+        }
+    }
+    Ok(records)
+}
+
+/// Splits the file into `chunk_size`-row pieces, re-adds the header to
+/// each and parses them one after another, yielding between chunks.
+pub async fn parse_chunked(content: &str, chunk_size: usize) -> csv::Result<usize> {
+    chunked_records(content, chunk_size).await.map(|records| records.len())
+}
+
+/// The records [`parse_chunked`] counts.
+pub async fn chunked_records(content: &str, chunk_size: usize) -> csv::Result<Vec<SalesRecord>> {
+    let rows = split_rows(content);
+    let Some((header, data_rows)) = rows.split_first() else {
+        return Ok(Vec::new());
+    };
+
+    let mut records = Vec::new();
+    for chunk in data_rows.chunks(chunk_size.max(1)) {
+        let chunk_data = format!("{}\n{}", header, chunk.join("\n"));
+        let mut reader = ReaderBuilder::new().from_reader(chunk_data.as_bytes());
+        for result in reader.deserialize() {
+            let record: SalesRecord = result?;
+            records.push(record);
+        }
+        tokio::task::yield_now().await;
+    }
+    Ok(records)
+}
+
+/// Maps the file at `path` into memory instead of reading it. Pages are
+/// faulted in on first touch, so the first pass over a fresh mapping pays
+/// for them and later passes over the same mapping do not.
+pub fn map_file(path: impl AsRef<std::path::Path>) -> std::io::Result<memmap2::Mmap> {
+    let file = std::fs::File::open(path)?;
+    // SAFETY: the benchmarks only map sample files that nothing truncates
+    // or writes while they run
+    unsafe { memmap2::Mmap::map(&file) }
+}
+
+/// Reads into one reused `ByteRecord` and deserializes from it, skipping
+/// UTF-8 validation of fields that are not strings.
+pub fn parse_byte_records(content: &str) -> csv::Result<usize> {
+    byte_records(content).map(|records| records.len())
+}
+
+/// The records [`parse_byte_records`] counts.
+pub fn byte_records(content: &str) -> csv::Result<Vec<SalesRecord>> {
+    let mut reader = ReaderBuilder::new().from_reader(content.as_bytes());
+    let headers = reader.byte_headers()?.clone();
+    let mut record = ByteRecord::new();
+    let mut records = Vec::new();
+    while reader.read_byte_record(&mut record)? {
+        records.push(record.deserialize(Some(&headers))?);
+    }
+    Ok(records)
+}
+
+/// Column positions of the [`SalesRecord`] fields, looked up once from the
+/// header so [`parse_manual`] can index each row directly.
+struct SalesColumns {
+    id: usize,
+    customer_name: usize,
+    product: usize,
+    quantity: usize,
+    price: usize,
+    date: usize,
+    region: usize,
+}
+
+impl SalesColumns {
+    fn from_headers(headers: &ByteRecord) -> csv::Result<Self> {
+        let find = |name: &str| {
+            headers.iter().position(|header| header == name.as_bytes()).ok_or_else(|| {
+                csv::Error::from(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("missing column {}", name),
+                ))
+            })
+        };
+        Ok(Self {
+            id: find("id")?,
+            customer_name: find("customer_name")?,
+            product: find("product")?,
+            quantity: find("quantity")?,
+            price: find("price")?,
+            date: find("date")?,
+            region: find("region")?,
+        })
+    }
+}
+
+/// Builds each [`SalesRecord`] by hand from a reused `ByteRecord`, with
+/// unchecked UTF-8 and `lexical` number parsing, to measure what serde's
+/// deserialization costs on top of [`parse_byte_records`].
+pub fn parse_manual(content: &str) -> csv::Result<usize> {
+    manual_records(content).map(|records| records.len())
+}
+
+/// The records [`parse_manual`] counts.
+pub fn manual_records(content: &str) -> csv::Result<Vec<SalesRecord>> {
+    fn invalid(field: &str, row: usize) -> csv::Error {
+        csv::Error::from(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("row {}: invalid {}", row, field),
+        ))
+    }
+
+    let mut reader = ReaderBuilder::new().from_reader(content.as_bytes());
+    let headers = reader.byte_headers()?;
+    // An empty file has no header to look columns up in, and no rows
+    if headers.is_empty() {
+        return Ok(Vec::new());
+    }
+    let columns = SalesColumns::from_headers(headers)?;
+    let mut record = ByteRecord::new();
+    let mut records = Vec::new();
+    while reader.read_byte_record(&mut record)? {
+        let field = |index: usize| record.get(index).unwrap_or_default();
+        // SAFETY: `content` is a str and the CSV reader only splits and
+        // unescapes at ASCII bytes, so every field is valid UTF-8 too.
+        let text = |index: usize| unsafe { std::str::from_utf8_unchecked(field(index)) }.to_string();
+        let row = records.len() + 1;
+        records.push(SalesRecord {
+            id: lexical_core::parse(field(columns.id)).map_err(|_| invalid("id", row))?,
+            customer_name: text(columns.customer_name),
+            product: text(columns.product),
+            quantity: lexical_core::parse(field(columns.quantity)).map_err(|_| invalid("quantity", row))?,
+            price: lexical_core::parse(field(columns.price)).map_err(|_| invalid("price", row))?,
+            date: text(columns.date),
+            region: text(columns.region),
+        });
+    }
+    Ok(records)
+}
+
+/// Threads or tasks the chunked strategies use when not told otherwise:
+/// one per logical CPU.
+pub fn default_concurrency() -> usize {
+    num_cpus::get()
+}
+
+/// Rows per chunk that splits `data_rows` into one chunk per thread or
+/// task, but no smaller than 10,000 rows.
+pub fn chunk_size_for(data_rows: usize, concurrency: usize) -> usize {
+    10000.max(data_rows.div_ceil(concurrency.max(1)))
+}
+
+/// Parses chunks of rows on the rayon pool, one chunk per core. Rows that
+/// fail to parse are skipped rather than failing the whole file.
+pub fn parse_parallel(content: &str) -> usize {
+    let data_rows = split_rows(content).len().saturating_sub(1);
+    parse_parallel_chunks(content, chunk_size_for(data_rows, default_concurrency()))
+}
+
+/// [`parse_parallel`] with an explicit number of rows per chunk. Runs on
+/// the current rayon pool, so `ThreadPool::install` sets the concurrency.
+pub fn parse_parallel_chunks(content: &str, chunk_size: usize) -> usize {
+    parallel_records(content, chunk_size).len()
+}
+
+/// The records [`parse_parallel_chunks`] counts, in file order.
+pub fn parallel_records(content: &str, chunk_size: usize) -> Vec<SalesRecord> {
+    let rows = split_rows(content);
+    let Some((header, data_rows)) = rows.split_first() else {
+        return Vec::new();
+    };
+
+    data_rows
+        .par_chunks(chunk_size.max(1))
+        .flat_map_iter(|chunk| {
+            let chunk_content = format!("{}\n{}", header, chunk.join("\n"));
+            let mut reader = ReaderBuilder::new().from_reader(chunk_content.as_bytes());
+            reader.deserialize::<SalesRecord>().filter_map(Result::ok).collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Parses `chunk_size`-row chunks as tokio tasks, at most `concurrency` at
+/// a time. Rows that fail to parse are skipped; a chunk task that panics
+/// fails the whole parse with its `JoinError`.
+pub async fn parse_async_parallel(
+    content: &str,
+    chunk_size: usize,
+    concurrency: usize,
+) -> Result<usize, tokio::task::JoinError> {
+    async_parallel_records(content, chunk_size, concurrency).await.map(|records| records.len())
+}
+
+/// The records [`parse_async_parallel`] counts, in file order.
+pub async fn async_parallel_records(
+    content: &str,
+    chunk_size: usize,
+    concurrency: usize,
+) -> Result<Vec<SalesRecord>, tokio::task::JoinError> {
+    use futures::{StreamExt, TryStreamExt};
+
+    let rows = split_rows(content);
+    let Some((header, data_rows)) = rows.split_first() else {
+        return Ok(Vec::new());
+    };
+
+    // Indexed rather than `chunks()`, so the stream is `Send` for axum handlers
+    let chunk_size = chunk_size.max(1);
+    let tasks = (0..data_rows.len().div_ceil(chunk_size)).map(|index| {
+        let chunk = &data_rows[index * chunk_size..data_rows.len().min((index + 1) * chunk_size)];
+        let chunk_content = format!("{}\n{}", header, chunk.join("\n"));
+        tokio::spawn(async move {
+            let mut reader = ReaderBuilder::new().from_reader(chunk_content.as_bytes());
+            reader.deserialize::<SalesRecord>().filter_map(Result::ok).collect::<Vec<_>>()
+        })
+    });
+    // `buffered` runs as many tasks as `buffer_unordered`, but hands their
+    // records back in file order
+    futures::stream::iter(tasks)
+        .buffered(concurrency.max(1))
+        .try_fold(Vec::new(), |mut records, chunk| async move {
+            records.extend(chunk);
+            Ok(records)
+        })
+        .await
+}
+
+/// Default read size of [`StreamingReader`].
+pub const STREAMING_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Parses CSV while it is still being read: it fills a fixed-size buffer,
+/// parses the complete rows in it and carries the partial last row over
+/// to the next read. Memory stays at one buffer plus one batch of records
+/// however large the file, unlike the strategies above that read it whole.
+///
+/// Rows end at newlines outside quoted fields, as in [`split_rows`], so a
+/// row may span any number of reads.
+pub struct StreamingReader<R> {
+    reader: BufReader<R>,
+    headers: Option<ByteRecord>,
+    /// Bytes of a row that has not been fully read yet.
+    partial: Vec<u8>,
+    /// Where the bytes read so far left off, e.g. inside a quoted field.
+    row_ends: RowEnds,
+    bytes_read: usize,
+}
+
+impl<R: AsyncRead + Unpin> StreamingReader<R> {
+    pub fn new(reader: R, buffer_size: usize) -> Self {
+        Self {
+            reader: BufReader::with_capacity(buffer_size.max(1), reader),
+            headers: None,
+            partial: Vec::new(),
+            row_ends: RowEnds::new(),
+            bytes_read: 0,
+        }
+    }
+
+    pub fn bytes_read(&self) -> usize {
+        self.bytes_read
+    }
+
+    /// Reads until at least one row is complete and returns its records,
+    /// or `None` at the end of the input.
+    pub async fn next_batch(&mut self) -> csv::Result<Option<Vec<SalesRecord>>> {
+        loop {
+            let buffer = self.reader.fill_buf().await?;
+            if buffer.is_empty() {
+                if self.partial.is_empty() {
+                    return Ok(None);
+                }
+                // The last row had no trailing newline
+                let rows = std::mem::take(&mut self.partial);
+                return self.parse(&rows).map(Some);
+            }
+
+            let len = buffer.len();
+            let mut row_end = None;
+            for (index, &byte) in buffer.iter().enumerate() {
+                if self.row_ends.ends_row(byte) {
+                    row_end = Some(index);
+                }
+            }
+            let rows = row_end.map(|end| {
+                let mut rows = std::mem::take(&mut self.partial);
+                rows.extend_from_slice(&buffer[..=end]);
+                self.partial.extend_from_slice(&buffer[end + 1..]);
+                rows
+            });
+            if rows.is_none() {
+                self.partial.extend_from_slice(buffer);
+            }
+            self.reader.consume(len);
+            self.bytes_read += len;
+
+            if let Some(rows) = rows {
+                return self.parse(&rows).map(Some);
+            }
+        }
+    }
+
+    /// Parses complete rows; the first row of the input is the header.
+    fn parse(&mut self, rows: &[u8]) -> csv::Result<Vec<SalesRecord>> {
+        let mut reader = ReaderBuilder::new().has_headers(false).from_reader(rows);
+        let mut record = ByteRecord::new();
+        let mut records = Vec::new();
+        while reader.read_byte_record(&mut record)? {
+            match &self.headers {
+                Some(headers) => records.push(record.deserialize(Some(headers))?),
+                None => self.headers = Some(record.clone()),
+            }
+        }
+        Ok(records)
+    }
+}
+
+/// Deserializes with csv-async's `AsyncDeserializer`, which parses straight
+/// from an async reader rather than reading the file into memory first.
+pub async fn parse_csv_async<R: AsyncRead + Unpin + Send>(reader: R) -> csv_async::Result<usize> {
+    csv_async_records(reader).await.map(|records| records.len())
+}
+
+/// The records [`parse_csv_async`] counts.
+pub async fn csv_async_records<R: AsyncRead + Unpin + Send>(reader: R) -> csv_async::Result<Vec<SalesRecord>> {
+    use futures::TryStreamExt;
+
+    let mut deserializer = csv_async::AsyncDeserializer::from_reader(reader);
+    deserializer.deserialize::<SalesRecord>().try_collect().await
+}
+
+/// Parses `reader` incrementally with a [`StreamingReader`].
+pub async fn parse_streaming<R: AsyncRead + Unpin>(reader: R, buffer_size: usize) -> csv::Result<usize> {
+    streaming_records(reader, buffer_size).await.map(|records| records.len())
+}
+
+/// The records [`parse_streaming`] counts.
+pub async fn streaming_records<R: AsyncRead + Unpin>(reader: R, buffer_size: usize) -> csv::Result<Vec<SalesRecord>> {
+    let mut reader = StreamingReader::new(reader, buffer_size);
+    let mut records = Vec::new();
+    while let Some(batch) = reader.next_batch().await? {
+        records.extend(batch);
+    }
+    Ok(records)
+}
+
+/// How the I/O around parsing is attempted: a time limit per attempt, and
+/// how often a transient failure is retried, waiting twice as long before
+/// each retry as before the last. Meant for files on network filesystems,
+/// where a read can stall or fail while the server behind it recovers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Policy {
+    /// Limit for each attempt; `None` waits as long as it takes.
+    pub timeout: Option<std::time::Duration>,
+    /// Attempts after the first one.
+    pub max_retries: u32,
+    /// Wait before the first retry.
+    pub initial_backoff: std::time::Duration,
+    /// Longest wait between two attempts.
+    pub max_backoff: std::time::Duration,
+}
+
+impl Default for Policy {
+    /// No time limit, and two retries after 100 ms and 200 ms.
+    fn default() -> Self {
+        Self {
+            timeout: None,
+            max_retries: 2,
+            initial_backoff: std::time::Duration::from_millis(100),
+            max_backoff: std::time::Duration::from_secs(2),
+        }
+    }
+}
+
+/// What an operation run under a [`Policy`] returned, and how many
+/// retries it took.
+#[derive(Debug, Clone)]
+pub struct Attempted<T> {
+    pub value: T,
+    pub retries: u32,
+}
+
+/// Why an operation run under a [`Policy`] gave up.
+#[derive(Debug)]
+pub enum PolicyError<E> {
+    /// The last attempt ran past the policy's timeout.
+    TimedOut { timeout: std::time::Duration, attempts: u32 },
+    /// The error was not transient, or retries ran out.
+    Failed { error: E, attempts: u32 },
+}
+
+impl<E> PolicyError<E> {
+    pub fn attempts(&self) -> u32 {
+        match self {
+            PolicyError::TimedOut { attempts, .. } | PolicyError::Failed { attempts, .. } => *attempts,
+        }
+    }
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for PolicyError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PolicyError::TimedOut { timeout, attempts } => {
+                write!(f, "timed out after {:?} ({} attempt(s))", timeout, attempts)
+            }
+            PolicyError::Failed { error, attempts: 1 } => write!(f, "{}", error),
+            PolicyError::Failed { error, attempts } => write!(f, "{} (after {} attempts)", error, attempts),
+        }
+    }
+}
+
+impl<E: std::fmt::Debug + std::fmt::Display> std::error::Error for PolicyError<E> {}
+
+/// Errors that may go away if the operation is tried again.
+pub trait Transient {
+    fn is_transient(&self) -> bool;
+}
+
+impl Transient for std::io::Error {
+    /// Interrupted, timed-out or would-block I/O, dropped connections, and
+    /// on Unix the `EIO` and stale-handle errors of a network filesystem
+    /// whose server is failing over. A missing file or a permission error
+    /// stays an error however often it is retried.
+    fn is_transient(&self) -> bool {
+        use std::io::ErrorKind;
+
+        #[cfg(unix)]
+        if matches!(self.raw_os_error(), Some(libc::EIO | libc::ESTALE)) {
+            return true;
+        }
+        matches!(
+            self.kind(),
+            ErrorKind::Interrupted
+                | ErrorKind::WouldBlock
+                | ErrorKind::TimedOut
+                | ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::BrokenPipe
+        )
+    }
+}
+
+impl Transient for csv::Error {
+    /// Only reading failures; a malformed row stays malformed.
+    fn is_transient(&self) -> bool {
+        match self.kind() {
+            csv::ErrorKind::Io(error) => error.is_transient(),
+            _ => false,
+        }
+    }
+}
+
+impl Policy {
+    /// One attempt, without a time limit.
+    pub fn once() -> Self {
+        Self { max_retries: 0, ..Self::default() }
+    }
+
+    /// The wait before retry number `retry`, counted from 0.
+    pub fn backoff(&self, retry: u32) -> std::time::Duration {
+        self.initial_backoff.saturating_mul(1 << retry.min(16)).min(self.max_backoff)
+    }
+
+    /// Runs `operation` until it succeeds, fails with an error that is not
+    /// [`Transient`], or has been retried `max_retries` times. An attempt
+    /// that runs past the timeout is dropped and counts as transient.
+    pub async fn run<T, E, F, Fut>(&self, mut operation: F) -> Result<Attempted<T>, PolicyError<E>>
+    where
+        E: Transient,
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<T, E>>,
+    {
+        let mut retries = 0;
+        loop {
+            let outcome = match self.timeout {
+                Some(timeout) => tokio::time::timeout(timeout, operation()).await.map_err(|_| timeout),
+                None => Ok(operation().await),
+            };
+            let retryable = match &outcome {
+                Ok(Ok(_)) => false,
+                Ok(Err(error)) => error.is_transient(),
+                Err(_) => true,
+            };
+            if !retryable || retries == self.max_retries {
+                return match outcome {
+                    Ok(Ok(value)) => Ok(Attempted { value, retries }),
+                    Ok(Err(error)) => Err(PolicyError::Failed { error, attempts: retries + 1 }),
+                    Err(timeout) => Err(PolicyError::TimedOut { timeout, attempts: retries + 1 }),
+                };
+            }
+            tokio::time::sleep(self.backoff(retries)).await;
+            retries += 1;
+        }
+    }
+}
+
+/// Reads the file at `path` into a string under `policy`.
+pub async fn read_to_string(path: impl AsRef<std::path::Path>, policy: &Policy) -> Result<Attempted<String>, PolicyError<std::io::Error>> {
+    let path = path.as_ref();
+    policy.run(|| tokio::fs::read_to_string(path)).await
+}
+
+/// Reads and deserializes every record of the file at `path` under
+/// `policy`, like [`parse_sync`] but returning the records. A transient
+/// read error retries the whole read; a malformed row fails at once.
+pub async fn read_records(path: impl AsRef<std::path::Path>, policy: &Policy) -> Result<Attempted<Vec<SalesRecord>>, PolicyError<csv::Error>> {
+    let path = path.as_ref();
+    policy
+        .run(|| async move {
+            let content = tokio::fs::read(path).await?;
+            ReaderBuilder::new().from_reader(content.as_slice()).deserialize().collect::<csv::Result<Vec<SalesRecord>>>()
+        })
+        .await
+}
@@ -0,0 +1,126 @@
+use crate::performance_utils::SalesRecord;
+use chrono::{DateTime, Utc};
+use csv::{Reader, StringRecord};
+use serde::Serialize;
+use std::io::Read;
+
+/// Where a record came from: the line of its source file, and when and by
+/// which request or job that part of the file was ingested.
+#[derive(Debug, Clone, Serialize)]
+pub struct Provenance {
+    pub source: String,
+    /// 1-based line the record starts on.
+    pub line: u64,
+    /// Byte offset of that line in the file.
+    pub byte: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ingested_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<String>,
+}
+
+/// One parse of a file, or of the rows appended to it: the bytes from
+/// `from_byte` on were ingested at `ingested_at` by `job_id`, the request
+/// or job that asked for them, if any did.
+#[derive(Debug, Clone, Serialize)]
+pub struct Ingestion {
+    pub from_byte: u64,
+    pub rows: usize,
+    pub ingested_at: DateTime<Utc>,
+    pub job_id: Option<String>,
+}
+
+/// The ingestions behind a file's cached records, oldest first: the full
+/// parse, then one per batch of appended rows caught up on since.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Lineage {
+    ingestions: Vec<Ingestion>,
+}
+
+impl Lineage {
+    /// The lineage of a file just parsed whole into `rows` records.
+    pub fn parsed(rows: usize, job_id: Option<String>) -> Self {
+        Self { ingestions: vec![Ingestion { from_byte: 0, rows, ingested_at: Utc::now(), job_id }] }
+    }
+
+    /// Records that `rows` records were parsed from the bytes after `from_byte`.
+    pub fn appended(&mut self, from_byte: u64, rows: usize, job_id: Option<String>) {
+        self.ingestions.push(Ingestion { from_byte, rows, ingested_at: Utc::now(), job_id });
+    }
+
+    pub fn ingestions(&self) -> &[Ingestion] {
+        &self.ingestions
+    }
+
+    /// The ingestion that read the byte at `byte`.
+    pub fn ingestion_of(&self, byte: u64) -> Option<&Ingestion> {
+        self.ingestions.iter().rev().find(|ingestion| ingestion.from_byte <= byte)
+    }
+
+    /// The provenance of the record of `source` starting at `position`.
+    pub fn provenance(&self, source: &str, position: &csv::Position) -> Provenance {
+        let ingestion = self.ingestion_of(position.byte());
+        Provenance {
+            source: source.to_string(),
+            line: position.line(),
+            byte: position.byte(),
+            ingested_at: ingestion.map(|ingestion| ingestion.ingested_at),
+            job_id: ingestion.and_then(|ingestion| ingestion.job_id.clone()),
+        }
+    }
+}
+
+/// A record with its provenance alongside its own columns, flat so that it
+/// writes as one CSV row as well as one JSON object.
+#[derive(Debug, Serialize)]
+pub struct TracedRecord<'a> {
+    pub id: u32,
+    pub customer_name: &'a str,
+    pub product: &'a str,
+    pub quantity: u32,
+    pub price: f64,
+    pub date: &'a str,
+    pub region: &'a str,
+    pub source_file: &'a str,
+    pub source_line: u64,
+    pub ingested_at: Option<DateTime<Utc>>,
+    pub job_id: Option<&'a str>,
+}
+
+impl<'a> TracedRecord<'a> {
+    pub fn new(record: &'a SalesRecord, provenance: &'a Provenance) -> Self {
+        Self {
+            id: record.id,
+            customer_name: &record.customer_name,
+            product: &record.product,
+            quantity: record.quantity,
+            price: record.price,
+            date: &record.date,
+            region: &record.region,
+            source_file: &provenance.source,
+            source_line: provenance.line,
+            ingested_at: provenance.ingested_at,
+            job_id: provenance.job_id.as_deref(),
+        }
+    }
+}
+
+/// Where each record `reader` yields starts, in order; the same records,
+/// one for one, that deserializing from a reader set up alike yields.
+pub fn positions<R: Read>(mut reader: Reader<R>) -> csv::Result<Vec<csv::Position>> {
+    let mut positions = Vec::new();
+    let mut record = StringRecord::new();
+    while reader.read_record(&mut record)? {
+        if let Some(position) = record.position() {
+            positions.push(position.clone());
+        }
+    }
+    Ok(positions)
+}
+
+/// The line of `content` starting at `byte`, without its line break.
+pub fn source_line(content: &[u8], byte: u64) -> String {
+    let rest = content.get(byte as usize..).unwrap_or_default();
+    let end = rest.iter().position(|&byte| byte == b'\n').unwrap_or(rest.len());
+    String::from_utf8_lossy(&rest[..end]).trim_end_matches('\r').to_string()
+}
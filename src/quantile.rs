@@ -0,0 +1,115 @@
+/// One quantile of a stream of values, estimated in a single pass and
+/// constant memory with the P² algorithm (Jain & Chlamtac, 1985). It keeps
+/// five markers, the minimum, the maximum, the quantile and two between,
+/// and moves them toward where they belong as values arrive, adjusting
+/// their heights along a parabola through their neighbours. Estimates are
+/// within a fraction of a percent on smooth distributions; values seen
+/// before the fifth are kept and answered exactly.
+#[derive(Debug, Clone)]
+pub struct StreamingQuantile {
+    quantile: f64,
+    count: u64,
+    /// Marker heights, ascending.
+    heights: [f64; 5],
+    /// Marker positions, 1-based ranks among the values seen.
+    positions: [f64; 5],
+    desired: [f64; 5],
+    increments: [f64; 5],
+}
+
+impl StreamingQuantile {
+    /// Estimates `quantile`, between 0 and 1, e.g. 0.25 for the lower quartile.
+    pub fn new(quantile: f64) -> Self {
+        let quantile = quantile.clamp(0.0, 1.0);
+        Self {
+            quantile,
+            count: 0,
+            heights: [0.0; 5],
+            positions: [1.0, 2.0, 3.0, 4.0, 5.0],
+            desired: [1.0, 1.0 + 2.0 * quantile, 1.0 + 4.0 * quantile, 3.0 + 2.0 * quantile, 5.0],
+            increments: [0.0, quantile / 2.0, quantile, (1.0 + quantile) / 2.0, 1.0],
+        }
+    }
+
+    /// Adds a value. NaN is ignored.
+    pub fn add(&mut self, value: f64) {
+        if value.is_nan() {
+            return;
+        }
+        if self.count < 5 {
+            self.heights[self.count as usize] = value;
+            self.count += 1;
+            if self.count == 5 {
+                self.heights.sort_by(f64::total_cmp);
+            }
+            return;
+        }
+        self.count += 1;
+
+        // The cell the value falls in, stretching the ends to hold it
+        let cell = if value < self.heights[0] {
+            self.heights[0] = value;
+            0
+        } else if value >= self.heights[4] {
+            self.heights[4] = value;
+            3
+        } else {
+            (1..5).find(|&marker| value < self.heights[marker]).map_or(3, |marker| marker - 1)
+        };
+        for position in &mut self.positions[cell + 1..] {
+            *position += 1.0;
+        }
+        for (desired, increment) in self.desired.iter_mut().zip(self.increments) {
+            *desired += increment;
+        }
+
+        for marker in 1..4 {
+            let offset = self.desired[marker] - self.positions[marker];
+            let room_above = self.positions[marker + 1] - self.positions[marker];
+            let room_below = self.positions[marker - 1] - self.positions[marker];
+            if (offset >= 1.0 && room_above > 1.0) || (offset <= -1.0 && room_below < -1.0) {
+                let step = offset.signum();
+                let parabolic = self.parabolic(marker, step);
+                self.heights[marker] = if self.heights[marker - 1] < parabolic && parabolic < self.heights[marker + 1] {
+                    parabolic
+                } else {
+                    self.linear(marker, step)
+                };
+                self.positions[marker] += step;
+            }
+        }
+    }
+
+    fn parabolic(&self, marker: usize, step: f64) -> f64 {
+        let (q, n) = (&self.heights, &self.positions);
+        q[marker]
+            + step / (n[marker + 1] - n[marker - 1])
+                * ((n[marker] - n[marker - 1] + step) * (q[marker + 1] - q[marker]) / (n[marker + 1] - n[marker])
+                    + (n[marker + 1] - n[marker] - step) * (q[marker] - q[marker - 1]) / (n[marker] - n[marker - 1]))
+    }
+
+    fn linear(&self, marker: usize, step: f64) -> f64 {
+        let neighbour = if step > 0.0 { marker + 1 } else { marker - 1 };
+        self.heights[marker]
+            + step * (self.heights[neighbour] - self.heights[marker]) / (self.positions[neighbour] - self.positions[marker])
+    }
+
+    /// Values added so far.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The estimate; `None` before the first value.
+    pub fn value(&self) -> Option<f64> {
+        match self.count {
+            0 => None,
+            count @ 1..=4 => {
+                let mut seen = self.heights[..count as usize].to_vec();
+                seen.sort_by(f64::total_cmp);
+                let rank = (self.quantile * (count - 1) as f64).round() as usize;
+                Some(seen[rank])
+            }
+            _ => Some(self.heights[2]),
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::json::ArrayWriter;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::util::pretty::pretty_format_batches;
+use datafusion::error::Result;
+use datafusion::execution::context::SQLOptions;
+use datafusion::prelude::{CsvReadOptions, SessionContext};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Directories whose CSV files become tables. Files in the first are named
+/// after the file; the others' names get the directory as a prefix, e.g.
+/// `uploads_orders` for `uploads/orders.csv`.
+pub const TABLE_DIRS: [&str; 2] = ["sample_data", "uploads"];
+
+/// SQL over the data files, shared by the `csvsql` REPL and the server's
+/// POST /query. Queries are read-only: DDL, DML and statements such as
+/// `COPY ... TO` or `SET` are rejected, so SQL cannot write files.
+pub struct QueryEngine {
+    ctx: SessionContext,
+    /// Registered tables and their files; also serializes refreshes.
+    tables: tokio::sync::Mutex<BTreeMap<String, PathBuf>>,
+}
+
+/// The result of one query.
+pub struct QueryOutput {
+    pub schema: SchemaRef,
+    pub batches: Vec<RecordBatch>,
+    pub rows: usize,
+    pub elapsed: Duration,
+}
+
+impl QueryEngine {
+    pub fn new() -> Self {
+        Self { ctx: SessionContext::new(), tables: tokio::sync::Mutex::new(BTreeMap::new()) }
+    }
+
+    /// Registers CSV files that appeared since the last refresh and drops
+    /// tables whose file is gone. Returns the table names.
+    pub async fn refresh(&self) -> Result<Vec<String>> {
+        let mut tables = self.tables.lock().await;
+        let found = scan_tables()?;
+        for name in tables.keys().filter(|name| !found.contains_key(*name)).cloned().collect::<Vec<_>>() {
+            self.ctx.deregister_table(name.as_str())?;
+            tables.remove(&name);
+        }
+        for (name, path) in found {
+            if tables.contains_key(&name) {
+                continue;
+            }
+            self.ctx.register_csv(name.as_str(), path.to_string_lossy().as_ref(), CsvReadOptions::new()).await?;
+            tables.insert(name, path);
+        }
+        Ok(tables.keys().cloned().collect())
+    }
+
+    /// Registered tables and their files.
+    pub async fn tables(&self) -> BTreeMap<String, PathBuf> {
+        self.tables.lock().await.clone()
+    }
+
+    pub async fn schema(&self, table: &str) -> Result<SchemaRef> {
+        Ok(self.ctx.table_provider(table).await?.schema())
+    }
+
+    pub async fn run(&self, sql: &str) -> Result<QueryOutput> {
+        let start = Instant::now();
+        let options = SQLOptions::new().with_allow_ddl(false).with_allow_dml(false).with_allow_statements(false);
+        let frame = self.ctx.sql_with_options(sql, options).await?;
+        let schema = frame.schema().inner().clone();
+        let batches = frame.collect().await?;
+        let rows = batches.iter().map(RecordBatch::num_rows).sum();
+        Ok(QueryOutput { schema, batches, rows, elapsed: start.elapsed() })
+    }
+}
+
+impl Default for QueryEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl QueryOutput {
+    /// The rows as an ASCII table.
+    pub fn to_table(&self) -> Result<String> {
+        Ok(pretty_format_batches(&self.batches)?.to_string())
+    }
+
+    /// Column names, in order, also when there are no rows.
+    pub fn columns(&self) -> Vec<String> {
+        self.schema.fields().iter().map(|field| field.name().clone()).collect()
+    }
+
+    /// The first `limit` rows as JSON objects keyed by column name.
+    pub fn to_json_rows(&self, limit: usize) -> Result<serde_json::Value> {
+        let mut remaining = limit;
+        let mut slices = Vec::new();
+        for batch in &self.batches {
+            if remaining == 0 {
+                break;
+            }
+            let take = batch.num_rows().min(remaining);
+            slices.push(batch.slice(0, take));
+            remaining -= take;
+        }
+
+        let mut writer = ArrayWriter::new(Vec::new());
+        writer.write_batches(&slices.iter().collect::<Vec<_>>())?;
+        writer.finish()?;
+        let json = writer.into_inner();
+        // An empty result writes nothing at all rather than "[]"
+        if json.is_empty() {
+            return Ok(serde_json::Value::Array(Vec::new()));
+        }
+        serde_json::from_slice(&json).map_err(|error| datafusion::error::DataFusionError::External(Box::new(error)))
+    }
+}
+
+/// CSV files in [`TABLE_DIRS`] by table name. Hidden files, such as
+/// uploads still being written, are skipped.
+fn scan_tables() -> Result<BTreeMap<String, PathBuf>> {
+    let mut tables = BTreeMap::new();
+    for (index, dir) in TABLE_DIRS.iter().enumerate() {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries {
+            let path = entry?.path();
+            let hidden = path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.'));
+            if hidden || !path.is_file() || path.extension().is_none_or(|extension| extension != "csv") {
+                continue;
+            }
+            let prefix = if index == 0 { None } else { Some(*dir) };
+            tables.entry(table_name(prefix, &path)).or_insert(path);
+        }
+    }
+    Ok(tables)
+}
+
+/// A lowercase identifier for `path`, usable in SQL without quotes.
+fn table_name(prefix: Option<&str>, path: &Path) -> String {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match prefix {
+        Some(prefix) => format!("{}_{}", prefix, stem),
+        None => stem.to_string(),
+    };
+    let mut name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, 't');
+    }
+    name
+}
@@ -0,0 +1,274 @@
+use crate::locale::Locale;
+use crate::performance_utils::{StrategyResult, SystemInfo};
+use std::fmt::Write;
+use std::path::PathBuf;
+
+/// Width in characters of the longest markdown bar.
+const BAR_WIDTH: usize = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Markdown,
+    Html,
+}
+
+impl ReportFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "md" | "markdown" => Some(Self::Markdown),
+            "html" => Some(Self::Html),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Markdown => "md",
+            Self::Html => "html",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Markdown => "text/markdown; charset=utf-8",
+            Self::Html => "text/html; charset=utf-8",
+        }
+    }
+}
+
+/// Results arranged as strategies × files, in the order they first appear.
+/// Labels follow the benchmarks' "Strategy — File" convention; a label
+/// without a file goes in a column of its own.
+struct Grid<'a> {
+    strategies: Vec<&'a str>,
+    files: Vec<&'a str>,
+    cells: Vec<(&'a str, &'a str, &'a StrategyResult)>,
+}
+
+impl<'a> Grid<'a> {
+    fn new(results: &'a [StrategyResult]) -> Self {
+        let mut grid = Grid { strategies: Vec::new(), files: Vec::new(), cells: Vec::new() };
+        for result in results {
+            let (strategy, file) = result.split_label();
+            let file = file.unwrap_or("all");
+            if !grid.strategies.contains(&strategy) {
+                grid.strategies.push(strategy);
+            }
+            if !grid.files.contains(&file) {
+                grid.files.push(file);
+            }
+            grid.cells.push((strategy, file, result));
+        }
+        grid
+    }
+
+    fn get(&self, strategy: &str, file: &str) -> Option<&'a StrategyResult> {
+        self.cells
+            .iter()
+            .find(|(s, f, _)| *s == strategy && *f == file)
+            .map(|(_, _, result)| *result)
+    }
+
+    /// Speedup over the first strategy measured on the same file.
+    fn speedup(&self, strategy: &str, file: &str) -> Option<f64> {
+        let baseline = self.strategies.iter().find_map(|s| self.get(s, file))?;
+        Some(self.get(strategy, file)?.records_per_second / baseline.records_per_second)
+    }
+
+    fn fastest(&self, file: &str) -> f64 {
+        self.strategies
+            .iter()
+            .filter_map(|s| self.get(s, file))
+            .map(|result| result.records_per_second)
+            .fold(0.0, f64::max)
+    }
+}
+
+/// Renders a comparison of `results`: a records/sec table with speedups
+/// over the first strategy, and a bar chart per file, with numbers and
+/// dates written for `locale`.
+pub fn render(title: &str, results: &[StrategyResult], system: Option<&SystemInfo>, format: ReportFormat, locale: &Locale) -> String {
+    let grid = Grid::new(results);
+    match format {
+        ReportFormat::Markdown => render_markdown(title, &grid, system, locale),
+        ReportFormat::Html => render_html(title, &grid, system, locale),
+    }
+}
+
+/// When a report was generated, e.g. `2024-05-02 14:30 UTC`.
+fn generated(locale: &Locale) -> String {
+    let now = chrono::Utc::now();
+    format!("{} {}", locale.date(&now), now.format("%H:%M UTC"))
+}
+
+fn render_markdown(title: &str, grid: &Grid, system: Option<&SystemInfo>, locale: &Locale) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "# {}\n", title);
+    let _ = writeln!(out, "Generated {}\n", generated(locale));
+    if let Some(system) = system {
+        let _ = writeln!(
+            out,
+            "{} · {} ({} cores) · {} GB RAM · {}\n",
+            system.os,
+            system.cpu_model,
+            system.physical_cores,
+            locale.number(system.total_memory_bytes as f64 / 1_000_000_000.0, 1),
+            system.rustc_version
+        );
+    }
+
+    let _ = writeln!(out, "| Strategy | {} |", grid.files.join(" | "));
+    let _ = writeln!(out, "|---|{}", "---:|".repeat(grid.files.len()));
+    for strategy in &grid.strategies {
+        let cells: Vec<String> = grid
+            .files
+            .iter()
+            .map(|file| match (grid.get(strategy, file), grid.speedup(strategy, file)) {
+                (Some(result), Some(speedup)) => format!("{} rec/s ({}×)", locale.number(result.records_per_second, 0), locale.number(speedup, 2)),
+                _ => "–".to_string(),
+            })
+            .collect();
+        let _ = writeln!(out, "| {} | {} |", strategy, cells.join(" | "));
+    }
+
+    for file in &grid.files {
+        let fastest = grid.fastest(file);
+        let _ = writeln!(out, "\n## {}\n\n```", file);
+        for strategy in &grid.strategies {
+            if let Some(result) = grid.get(strategy, file) {
+                let width = (result.records_per_second / fastest * BAR_WIDTH as f64).round() as usize;
+                let _ = writeln!(
+                    out,
+                    "{:<32} {:<width$} {} rec/s",
+                    strategy,
+                    "█".repeat(width.max(1)),
+                    locale.number(result.records_per_second, 0),
+                    width = BAR_WIDTH
+                );
+            }
+        }
+        let _ = writeln!(out, "```");
+    }
+    out
+}
+
+fn render_html(title: &str, grid: &Grid, system: Option<&SystemInfo>, locale: &Locale) -> String {
+    let mut out = String::new();
+    let _ = write!(out, "<!DOCTYPE html>\n<html");
+    if !locale.is_canonical() {
+        let _ = write!(out, " lang=\"{}\"", locale.tag);
+    }
+    let _ = writeln!(out, ">\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>", escape(title));
+    let _ = writeln!(
+        out,
+        "<style>body{{font-family:sans-serif;margin:2em}}table{{border-collapse:collapse}}\
+         td,th{{border:1px solid #ccc;padding:4px 8px;text-align:right}}td:first-child{{text-align:left}}\
+         .bar{{background:#4a90d9;height:1em;display:inline-block;vertical-align:middle}}</style>\n</head>\n<body>"
+    );
+    let _ = writeln!(out, "<h1>{}</h1>", escape(title));
+    let _ = writeln!(out, "<p>Generated {}</p>", generated(locale));
+    if let Some(system) = system {
+        let _ = writeln!(
+            out,
+            "<p>{} · {} ({} cores) · {} GB RAM · {}</p>",
+            escape(&system.os),
+            escape(&system.cpu_model),
+            system.physical_cores,
+            locale.number(system.total_memory_bytes as f64 / 1_000_000_000.0, 1),
+            escape(&system.rustc_version)
+        );
+    }
+
+    let _ = write!(out, "<table>\n<tr><th>Strategy</th>");
+    for file in &grid.files {
+        let _ = write!(out, "<th>{}</th>", escape(file));
+    }
+    let _ = writeln!(out, "</tr>");
+    for strategy in &grid.strategies {
+        let _ = write!(out, "<tr><td>{}</td>", escape(strategy));
+        for file in &grid.files {
+            match (grid.get(strategy, file), grid.speedup(strategy, file)) {
+                (Some(result), Some(speedup)) => {
+                    let _ = write!(out, "<td>{} rec/s ({}×)</td>", locale.number(result.records_per_second, 0), locale.number(speedup, 2));
+                }
+                _ => out.push_str("<td>–</td>"),
+            }
+        }
+        let _ = writeln!(out, "</tr>");
+    }
+    let _ = writeln!(out, "</table>");
+
+    for file in &grid.files {
+        let fastest = grid.fastest(file);
+        let _ = writeln!(out, "<h2>{}</h2>\n<table>", escape(file));
+        for strategy in &grid.strategies {
+            if let Some(result) = grid.get(strategy, file) {
+                let _ = writeln!(
+                    out,
+                    "<tr><td>{}</td><td style=\"width:400px;text-align:left\"><span class=\"bar\" style=\"width:{:.1}%\"></span></td><td>{} rec/s</td></tr>",
+                    escape(strategy),
+                    result.records_per_second / fastest * 100.0,
+                    locale.number(result.records_per_second, 0)
+                );
+            }
+        }
+        let _ = writeln!(out, "</table>");
+    }
+    let _ = writeln!(out, "</body>\n</html>");
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Writes a report to `reports/<name>.<ext>` and returns its path.
+pub fn write_report(name: &str, content: &str, format: ReportFormat) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all("reports")?;
+    let path = PathBuf::from("reports").join(format!("{}.{}", name, format.extension()));
+    std::fs::write(&path, content)?;
+    Ok(path)
+}
+
+/// Adds the `--report` and `--locale` options to a benchmark binary.
+pub fn report_arg(command: clap::Command) -> clap::Command {
+    command
+        .arg(
+            clap::Arg::new("report")
+                .long("report")
+                .value_name("FORMAT")
+                .help("Write a comparison report to reports/ (md or html)")
+                .value_parser(["md", "html"])
+        )
+        .arg(
+            clap::Arg::new("locale")
+                .long("locale")
+                .value_name("LOCALE")
+                .help("Write the report's numbers and dates for a locale, e.g. de-DE (plain by default)")
+                .value_parser(|tag: &str| {
+                    Locale::parse(tag).ok_or_else(|| {
+                        let supported: Vec<&str> = crate::locale::SUPPORTED.iter().map(|locale| locale.tag).collect();
+                        format!("expected one of {}", supported.join(", "))
+                    })
+                })
+                .requires("report")
+        )
+}
+
+/// Acts on `--report`, naming the file after `name` and the current time.
+pub fn apply_report_arg(
+    matches: &clap::ArgMatches,
+    name: &str,
+    title: &str,
+    results: &[StrategyResult],
+) -> std::io::Result<()> {
+    let Some(format) = matches.get_one::<String>("report").and_then(|name| ReportFormat::parse(name)) else {
+        return Ok(());
+    };
+    let locale = matches.get_one::<Locale>("locale").copied().unwrap_or_default();
+    let content = render(title, results, Some(&SystemInfo::collect()), format, &locale);
+    let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    let path = write_report(&format!("{}-{}", name, stamp), &content, format)?;
+    println!("📄 Wrote report to {}", path.display());
+    Ok(())
+}
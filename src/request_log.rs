@@ -0,0 +1,101 @@
+use axum::body::{Body, HttpBody};
+use axum::http::{HeaderMap, Method, StatusCode};
+use futures::StreamExt;
+use std::future::Future;
+use std::time::Instant;
+use tracing::Instrument;
+
+/// The header a request ID is taken from and returned in.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longer IDs from clients are replaced rather than logged.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// The client's `X-Request-Id` when it is usable, printable ASCII without
+/// spaces and at most 128 characters, so a caller's ID follows its request
+/// through the logs. Otherwise a new random one.
+pub fn request_id(headers: &HeaderMap) -> String {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|byte| byte.is_ascii_graphic()))
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("{:032x}", rand::random::<u128>()))
+}
+
+/// The ID of the request being handled, or of the one that started the
+/// running job.
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+/// Runs `future` as the request `id`, in a `request` span carrying it so
+/// tracing events inside are tagged too.
+pub async fn scope<F: Future>(id: String, future: F) -> F::Output {
+    let span = tracing::info_span!("request", request_id = %id);
+    REQUEST_ID.scope(id, future).instrument(span).await
+}
+
+/// Spawns `future` under the current request ID, so a background job logs
+/// and records the request that started it.
+pub fn spawn<F: Future<Output = ()> + Send + 'static>(future: F) {
+    match current() {
+        Some(id) => tokio::spawn(scope(id, future)),
+        None => tokio::spawn(future),
+    };
+}
+
+/// One access log line, written when dropped: once the response body has
+/// been sent, or given up on by a client that went away.
+pub struct AccessEntry {
+    request_id: String,
+    method: Method,
+    path: String,
+    status: StatusCode,
+    started: Instant,
+    bytes: u64,
+}
+
+impl AccessEntry {
+    pub fn new(request_id: String, method: Method, path: String, status: StatusCode, started: Instant) -> Self {
+        Self { request_id, method, path, status, started, bytes: 0 }
+    }
+
+    fn sent(&mut self, bytes: usize) {
+        self.bytes += bytes as u64;
+    }
+}
+
+impl Drop for AccessEntry {
+    fn drop(&mut self) {
+        tracing::info!(
+            target: "access",
+            request_id = %self.request_id,
+            method = %self.method,
+            path = %self.path,
+            status = self.status.as_u16(),
+            latency_ms = %format!("{:.2}", self.started.elapsed().as_secs_f64() * 1000.0),
+            bytes = self.bytes,
+        );
+    }
+}
+
+/// `body` with `entry` logged once it is sent. A body of known size is
+/// logged straight away; a streamed one is counted as it goes out, so its
+/// latency covers the whole transfer.
+pub fn logged(body: Body, mut entry: AccessEntry) -> Body {
+    if let Some(bytes) = body.size_hint().exact() {
+        entry.bytes = bytes;
+        return body;
+    }
+    Body::from_stream(body.into_data_stream().map(move |chunk| {
+        if let Ok(chunk) = &chunk {
+            entry.sent(chunk.len());
+        }
+        chunk
+    }))
+}
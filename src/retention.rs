@@ -0,0 +1,293 @@
+use crate::compression::{self, Dictionary};
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// Name of the audit log in the archive directory: one JSON line for each
+/// upload archived or archive deleted.
+pub const AUDIT_LOG: &str = "audit.jsonl";
+
+/// Endings of archives: gzipped, or zstd-compressed with a dictionary.
+pub const ARCHIVE_SUFFIXES: [&str; 2] = [".gz", ".zst"];
+
+/// When uploads are archived, and archives deleted.
+#[derive(Debug, Clone)]
+pub struct RetentionPolicy {
+    pub upload_dir: PathBuf,
+    /// Where archived uploads go, gzipped, along with the [`AUDIT_LOG`].
+    pub archive_dir: PathBuf,
+    /// Uploads last written longer ago than this are archived; `None`
+    /// archives by total size alone.
+    pub max_age: Option<Duration>,
+    /// The oldest uploads are archived until the others take no more than
+    /// this; `None` archives by age alone.
+    pub max_total_bytes: Option<u64>,
+    /// Archives older than this are deleted; `None` keeps them.
+    pub archive_max_age: Option<Duration>,
+    /// Uploads are compressed with zstd and this dictionary when one was
+    /// trained, and gzipped otherwise.
+    pub dictionary: Option<Arc<Dictionary>>,
+}
+
+/// Which rule an action followed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    MaxAge,
+    MaxTotalSize,
+    ArchiveRetention,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Archived,
+    Deleted,
+}
+
+/// An upload archived or an archive deleted, as written to the audit log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivalEvent {
+    pub at: chrono::DateTime<chrono::Utc>,
+    pub action: Action,
+    pub reason: Reason,
+    /// The upload archived, or the archive deleted.
+    pub file: String,
+    /// Where an upload was archived to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub archive: Option<String>,
+    /// The size of `file`.
+    pub bytes: u64,
+    /// The size of the archive written.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub archived_bytes: Option<u64>,
+    /// The zstd dictionary the archive needs to be decompressed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dictionary_id: Option<u32>,
+}
+
+/// A file retention looks at.
+#[derive(Debug, Clone)]
+pub struct StoredFile {
+    pub path: PathBuf,
+    pub bytes: u64,
+    pub modified: SystemTime,
+}
+
+/// What one run did.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RetentionRun {
+    pub archived: Vec<ArchivalEvent>,
+    pub deleted: Vec<ArchivalEvent>,
+    /// Files that could not be archived or deleted, and audit entries that
+    /// could not be written.
+    pub errors: Vec<String>,
+}
+
+/// Runs and running totals for `/files/status` and `/metrics`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RetentionStats {
+    pub interval_seconds: u64,
+    pub runs: u64,
+    pub last_run_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Uploads archived since startup.
+    pub archived: u64,
+    /// Archives deleted since startup.
+    pub deleted: u64,
+    pub last_run: Option<RetentionRun>,
+}
+
+impl RetentionStats {
+    pub fn new(interval_seconds: u64) -> Self {
+        Self { interval_seconds, ..Self::default() }
+    }
+
+    pub fn record(&mut self, run: RetentionRun) {
+        self.runs += 1;
+        self.last_run_at = Some(chrono::Utc::now());
+        self.archived += run.archived.len() as u64;
+        self.deleted += run.deleted.len() as u64;
+        self.last_run = Some(run);
+    }
+}
+
+/// Archives the uploads the policy no longer keeps, then deletes expired
+/// archives, as of `now`, and writes each to the audit log. Blocking; the
+/// caller holds off writes to the uploads meanwhile. A file that cannot be
+/// archived or deleted is noted and the run goes on.
+pub fn run(policy: &RetentionPolicy, now: SystemTime) -> RetentionRun {
+    let mut run = RetentionRun::default();
+    let uploads = match list(&policy.upload_dir, &[".csv"]) {
+        Ok(uploads) => uploads,
+        Err(error) => {
+            run.errors.push(format!("{}: {}", policy.upload_dir.display(), error));
+            Vec::new()
+        }
+    };
+    for (upload, reason) in select(&uploads, policy, now) {
+        match archive(&upload.path, &policy.archive_dir, policy.dictionary.as_deref()) {
+            Ok((archive, archived_bytes)) => run.archived.push(ArchivalEvent {
+                at: chrono::Utc::now(),
+                action: Action::Archived,
+                reason,
+                file: upload.path.display().to_string(),
+                archive: Some(archive.display().to_string()),
+                bytes: upload.bytes,
+                archived_bytes: Some(archived_bytes),
+                dictionary_id: policy.dictionary.as_ref().map(|dictionary| dictionary.id),
+            }),
+            Err(error) => run.errors.push(format!("{}: {}", upload.path.display(), error)),
+        }
+    }
+
+    if let Some(max_age) = policy.archive_max_age {
+        let archives = list(&policy.archive_dir, &ARCHIVE_SUFFIXES).unwrap_or_default();
+        for expired in archives.into_iter().filter(|archive| age(archive.modified, now) >= max_age) {
+            match std::fs::remove_file(&expired.path) {
+                Ok(()) => run.deleted.push(ArchivalEvent {
+                    at: chrono::Utc::now(),
+                    action: Action::Deleted,
+                    reason: Reason::ArchiveRetention,
+                    file: expired.path.display().to_string(),
+                    archive: None,
+                    bytes: expired.bytes,
+                    archived_bytes: None,
+                    dictionary_id: None,
+                }),
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+                Err(error) => run.errors.push(format!("{}: {}", expired.path.display(), error)),
+            }
+        }
+    }
+
+    let events: Vec<&ArchivalEvent> = run.archived.iter().chain(&run.deleted).collect();
+    if let Err(error) = record(&policy.archive_dir.join(AUDIT_LOG), &events) {
+        run.errors.push(format!("{}: {}", AUDIT_LOG, error));
+    }
+    run
+}
+
+/// The uploads to archive: those past the maximum age, then the oldest of
+/// the rest until what is left fits the maximum total size.
+pub fn select<'a>(uploads: &'a [StoredFile], policy: &RetentionPolicy, now: SystemTime) -> Vec<(&'a StoredFile, Reason)> {
+    let mut oldest_first: Vec<&StoredFile> = uploads.iter().collect();
+    oldest_first.sort_by_key(|upload| upload.modified);
+    let mut selected = Vec::new();
+    let mut kept = Vec::new();
+    for upload in oldest_first {
+        if policy.max_age.is_some_and(|max_age| age(upload.modified, now) >= max_age) {
+            selected.push((upload, Reason::MaxAge));
+        } else {
+            kept.push(upload);
+        }
+    }
+    if let Some(max_total) = policy.max_total_bytes {
+        let mut total: u64 = kept.iter().map(|upload| upload.bytes).sum();
+        for upload in kept {
+            if total <= max_total {
+                break;
+            }
+            total -= upload.bytes;
+            selected.push((upload, Reason::MaxTotalSize));
+        }
+    }
+    selected
+}
+
+/// Regular files directly in `dir` ending in one of `suffixes`, except
+/// hidden ones, which are still being written. A missing directory has none.
+pub fn list(dir: &Path, suffixes: &[&str]) -> io::Result<Vec<StoredFile>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error),
+    };
+    Ok(entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // Symlinks are neither followed nor archived
+            let metadata = std::fs::symlink_metadata(entry.path()).ok()?;
+            let wanted = metadata.is_file() && !name.starts_with('.') && suffixes.iter().any(|suffix| name.ends_with(suffix));
+            wanted.then(|| StoredFile { path: entry.path(), bytes: metadata.len(), modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH) })
+        })
+        .collect())
+}
+
+/// Gzips `upload` into `archive_dir`, or compresses it with zstd and
+/// `dictionary` when given, and removes it. The archive is written to a
+/// hidden part file and renamed into place once complete, and the upload
+/// removed only then, so a crash never leaves a truncated archive in its
+/// place. Returns the archive's path and size.
+pub fn archive(upload: &Path, archive_dir: &Path, dictionary: Option<&Dictionary>) -> io::Result<(PathBuf, u64)> {
+    let name = upload.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file"))?.to_string_lossy();
+    std::fs::create_dir_all(archive_dir)?;
+    let suffix = if dictionary.is_some() { ".zst" } else { ".gz" };
+    let archive = archive_dir.join(format!("{}{}", name, suffix));
+    let part = archive_dir.join(format!(".{}{}.part", name, suffix));
+    let written = match dictionary {
+        Some(dictionary) => compression::compress_file(upload, &part, zstd::DEFAULT_COMPRESSION_LEVEL, Some(dictionary)),
+        None => (|| {
+            let mut encoder = GzEncoder::new(BufWriter::new(File::create(&part)?), flate2::Compression::default());
+            io::copy(&mut File::open(upload)?, &mut encoder)?;
+            let file = encoder.finish()?.into_inner().map_err(|error| error.into_error())?;
+            file.sync_all()?;
+            Ok::<_, io::Error>(file.metadata()?.len())
+        })(),
+    };
+    let bytes = match written.and_then(|bytes| std::fs::rename(&part, &archive).map(|()| bytes)) {
+        Ok(bytes) => bytes,
+        Err(error) => {
+            let _ = std::fs::remove_file(&part);
+            return Err(error);
+        }
+    };
+    std::fs::remove_file(upload)?;
+    Ok((archive, bytes))
+}
+
+/// Appends `events` to the audit log at `path`, one JSON line each.
+pub fn record(path: &Path, events: &[&ArchivalEvent]) -> io::Result<()> {
+    if events.is_empty() {
+        return Ok(());
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut log = BufWriter::new(OpenOptions::new().create(true).append(true).open(path)?);
+    for event in events {
+        serde_json::to_writer(&mut log, event)?;
+        log.write_all(b"\n")?;
+    }
+    log.into_inner().map_err(|error| error.into_error())?.sync_all()
+}
+
+/// The last `count` events of the audit log at `path`, oldest first. Lines
+/// that do not parse are skipped.
+pub fn recent_events(path: &Path, count: usize) -> io::Result<Vec<ArchivalEvent>> {
+    let log = match File::open(path) {
+        Ok(log) => log,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error),
+    };
+    let mut events = std::collections::VecDeque::with_capacity(count);
+    for line in BufReader::new(log).lines() {
+        let Ok(event) = serde_json::from_str(&line?) else {
+            continue;
+        };
+        events.push_back(event);
+        if events.len() > count {
+            events.pop_front();
+        }
+    }
+    Ok(events.into())
+}
+
+/// Time since the last write; zero for files modified in the future.
+fn age(modified: SystemTime, now: SystemTime) -> Duration {
+    now.duration_since(modified).unwrap_or_default()
+}
@@ -0,0 +1,112 @@
+use crate::processing::RowEnds;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+
+/// Windows read by [`estimate`], spread evenly over the rows.
+const SAMPLE_WINDOWS: u64 = 16;
+
+/// Bytes read per sample window.
+const SAMPLE_WINDOW_BYTES: u64 = 64 * 1024;
+
+/// Bytes read per call while scanning for line breaks.
+const SCAN_BUFFER_BYTES: usize = 1 << 20;
+
+/// Data rows of a CSV file, not counting the header.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct RowCount {
+    pub rows: u64,
+    /// Counted row by row rather than estimated.
+    pub exact: bool,
+    pub bytes: u64,
+    /// Complete rows inside the sample windows an estimate is based on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sampled_rows: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub average_row_bytes: Option<f64>,
+}
+
+/// Estimates the rows of `path` from the average length of the lines in a
+/// few windows spread over the file, reading about 1 MB however large it
+/// is. Files too small to be worth sampling are counted with [`exact`].
+/// Rows of very different lengths in different parts of the file, e.g.
+/// appended with longer names, skew the estimate, and so do quoted fields
+/// spanning lines, which the windows count as several rows.
+pub fn estimate(path: &str, delimiter: u8) -> std::io::Result<RowCount> {
+    let mut file = BufReader::new(File::open(path)?);
+    let bytes = file.get_ref().metadata()?.len();
+    let mut header = Vec::new();
+    let header_end = file.read_until(b'\n', &mut header)? as u64;
+    let data_bytes = bytes - header_end;
+    if data_bytes <= SAMPLE_WINDOWS * SAMPLE_WINDOW_BYTES * 2 {
+        return Ok(exact(path, delimiter, || false)?.expect("never cancelled"));
+    }
+
+    let stride = data_bytes / SAMPLE_WINDOWS;
+    let mut window = Vec::with_capacity(SAMPLE_WINDOW_BYTES as usize);
+    let (mut sampled_rows, mut sampled_bytes) = (0, 0);
+    for index in 0..SAMPLE_WINDOWS {
+        file.seek(SeekFrom::Start(header_end + index * stride))?;
+        window.clear();
+        file.by_ref().take(SAMPLE_WINDOW_BYTES).read_to_end(&mut window)?;
+        // Only whole lines count: from the first line start in the window,
+        // the first window excepted, to the last line break
+        let first = if index == 0 { 0 } else { window.iter().position(|&byte| byte == b'\n').map_or(window.len(), |at| at + 1) };
+        let last = window.iter().rposition(|&byte| byte == b'\n').map_or(0, |at| at + 1);
+        if last > first {
+            sampled_rows += newlines(&window[first..last]);
+            sampled_bytes += (last - first) as u64;
+        }
+    }
+    if sampled_rows == 0 {
+        // Lines longer than a window: nothing to average over
+        return Ok(exact(path, delimiter, || false)?.expect("never cancelled"));
+    }
+
+    let average_row_bytes = sampled_bytes as f64 / sampled_rows as f64;
+    Ok(RowCount {
+        rows: (data_bytes as f64 / average_row_bytes).round() as u64,
+        exact: false,
+        bytes,
+        sampled_rows: Some(sampled_rows),
+        average_row_bytes: Some(average_row_bytes),
+    })
+}
+
+/// Counts the rows of `path` after the header, fields separated by
+/// `delimiter`, a last row without a line break included. Only line breaks
+/// outside quoted fields end a row, found as the parsers find them, so a
+/// field spanning lines stays one row. Reads the whole file but parses
+/// nothing, so it is much faster than parsing. Blocking; run it on the
+/// parser pool. `Ok(None)` once `cancelled` returns true.
+pub fn exact(path: &str, delimiter: u8, cancelled: impl Fn() -> bool) -> std::io::Result<Option<RowCount>> {
+    let mut file = File::open(path)?;
+    let bytes = file.metadata()?.len();
+    let mut buffer = vec![0; SCAN_BUFFER_BYTES];
+    let mut row_ends = RowEnds::with_delimiter(delimiter);
+    // Whether the last byte read ended a row
+    let (mut rows, mut ended) = (0, true);
+    loop {
+        if cancelled() {
+            return Ok(None);
+        }
+        let read = match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(error) if error.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        };
+        for &byte in &buffer[..read] {
+            ended = row_ends.ends_row(byte);
+            rows += u64::from(ended);
+        }
+    }
+    if !ended {
+        rows += 1;
+    }
+    Ok(Some(RowCount { rows: rows.saturating_sub(1), exact: true, bytes, sampled_rows: None, average_row_bytes: None }))
+}
+
+fn newlines(bytes: &[u8]) -> u64 {
+    bytes.iter().filter(|&&byte| byte == b'\n').count() as u64
+}
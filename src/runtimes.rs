@@ -0,0 +1,108 @@
+use crate::performance_utils::SalesRecord;
+use csv::ReaderBuilder;
+use std::future::Future;
+use std::path::Path;
+
+// The async runtimes the benchmarks can run their async strategies on.
+// Tokio is always built; async-std and smol come with the `async-std` and
+// `smol` features, so the same code compares runtimes as well as strategies.
+
+/// What the benchmarks need from a runtime: running a future to completion,
+/// reading a file and yielding to other tasks.
+pub trait AsyncRuntime {
+    /// Name accepted by `--runtime` and shown in results.
+    const NAME: &'static str;
+
+    /// Runs `future` on a runtime of this kind, blocking the calling
+    /// thread until it finishes. Must not be called from inside that
+    /// runtime.
+    fn block_on<F: Future>(future: F) -> F::Output;
+
+    fn read_to_string(path: &Path) -> impl Future<Output = std::io::Result<String>>;
+
+    fn yield_now() -> impl Future<Output = ()>;
+}
+
+pub struct Tokio;
+
+impl AsyncRuntime for Tokio {
+    const NAME: &'static str = "tokio";
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Runtime::new().expect("starting a tokio runtime").block_on(future)
+    }
+
+    fn read_to_string(path: &Path) -> impl Future<Output = std::io::Result<String>> {
+        tokio::fs::read_to_string(path.to_path_buf())
+    }
+
+    fn yield_now() -> impl Future<Output = ()> {
+        tokio::task::yield_now()
+    }
+}
+
+#[cfg(feature = "async-std")]
+pub struct AsyncStd;
+
+#[cfg(feature = "async-std")]
+impl AsyncRuntime for AsyncStd {
+    const NAME: &'static str = "async-std";
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        async_std::task::block_on(future)
+    }
+
+    fn read_to_string(path: &Path) -> impl Future<Output = std::io::Result<String>> {
+        async_std::fs::read_to_string(path.to_path_buf())
+    }
+
+    fn yield_now() -> impl Future<Output = ()> {
+        async_std::task::yield_now()
+    }
+}
+
+#[cfg(feature = "smol")]
+pub struct Smol;
+
+#[cfg(feature = "smol")]
+impl AsyncRuntime for Smol {
+    const NAME: &'static str = "smol";
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        smol::block_on(future)
+    }
+
+    fn read_to_string(path: &Path) -> impl Future<Output = std::io::Result<String>> {
+        smol::fs::read_to_string(path.to_path_buf())
+    }
+
+    fn yield_now() -> impl Future<Output = ()> {
+        smol::future::yield_now()
+    }
+}
+
+/// Names of the runtimes this build includes, tokio first.
+pub fn available() -> Vec<&'static str> {
+    vec![
+        Tokio::NAME,
+        #[cfg(feature = "async-std")]
+        AsyncStd::NAME,
+        #[cfg(feature = "smol")]
+        Smol::NAME,
+    ]
+}
+
+/// [`crate::processing::parse_async`] on runtime `R`: deserializes every
+/// record, yielding every 1000.
+pub async fn parse_async<R: AsyncRuntime>(content: &str) -> csv::Result<usize> {
+    let mut reader = ReaderBuilder::new().from_reader(content.as_bytes());
+    let mut records = Vec::new();
+    for result in reader.deserialize() {
+        let record: SalesRecord = result?;
+        records.push(record);
+        if records.len() % 1000 == 0 {
+            R::yield_now().await;
+        }
+    }
+    Ok(records.len())
+}
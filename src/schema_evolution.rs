@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// What an upload does when its header differs from the previous upload
+/// under the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compatibility {
+    /// Renamed columns are read under their previous names, so requests
+    /// written for the previous version keep working.
+    Map,
+    /// Any removed or renamed column refuses the upload.
+    Strict,
+    /// The header is taken as it is; the diff is only reported.
+    Off,
+}
+
+impl Compatibility {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "map" => Some(Compatibility::Map),
+            "strict" => Some(Compatibility::Strict),
+            "off" => Some(Compatibility::Off),
+            _ => None,
+        }
+    }
+}
+
+/// A column of the previous version found under another name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rename {
+    /// The name in the previous version.
+    pub from: String,
+    /// The name in the new header.
+    pub to: String,
+    /// `declared` with the upload, or guessed from the same `name` spelt
+    /// differently or the same `position`.
+    pub matched_by: String,
+}
+
+/// How a new header differs from the columns of the previous version.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SchemaDiff {
+    pub previous: Vec<String>,
+    pub current: Vec<String>,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub renamed: Vec<Rename>,
+    /// Whether the columns both versions have come in another order.
+    pub reordered: bool,
+}
+
+impl SchemaDiff {
+    /// Compares the `current` header with the `previous` columns. Renames
+    /// `declared` as new name to previous name come first; of the other
+    /// columns only in one version, an added one is taken for a removed
+    /// one that is spelt the same but for case and punctuation, or else
+    /// that stood at the same position.
+    pub fn between(previous: &[String], current: &[String], declared: &BTreeMap<String, String>) -> Self {
+        let mut renamed = Vec::new();
+        for (to, from) in declared {
+            if current.contains(to) && previous.contains(from) && !current.contains(from) {
+                renamed.push(Rename { from: from.clone(), to: to.clone(), matched_by: "declared".to_string() });
+            }
+        }
+        let unmatched = |renamed: &[Rename]| -> (Vec<String>, Vec<String>) {
+            let added = current
+                .iter()
+                .filter(|column| !previous.contains(column) && !renamed.iter().any(|rename| &rename.to == *column))
+                .cloned()
+                .collect();
+            let removed = previous
+                .iter()
+                .filter(|column| !current.contains(column) && !renamed.iter().any(|rename| &rename.from == *column))
+                .cloned()
+                .collect();
+            (added, removed)
+        };
+
+        let (added, removed) = unmatched(&renamed);
+        for column in &added {
+            if let Some(from) = removed.iter().find(|from| normalized(from) == normalized(column)) {
+                if !renamed.iter().any(|rename| &rename.from == from) {
+                    renamed.push(Rename { from: from.clone(), to: column.clone(), matched_by: "name".to_string() });
+                }
+            }
+        }
+        let (added, removed) = unmatched(&renamed);
+        for column in &added {
+            let position = current.iter().position(|other| other == column);
+            if let Some(from) = position.and_then(|position| previous.get(position)).filter(|from| removed.contains(from)) {
+                renamed.push(Rename { from: from.clone(), to: column.clone(), matched_by: "position".to_string() });
+            }
+        }
+        let (added, removed) = unmatched(&renamed);
+
+        let previous_name = |column: &String| renamed.iter().find(|rename| &rename.to == column).map_or(column, |rename| &rename.from).clone();
+        let kept: Vec<String> = current.iter().map(previous_name).filter(|column| previous.contains(column)).collect();
+        let kept_before: Vec<&String> = previous.iter().filter(|column| kept.contains(column)).collect();
+        let reordered = kept.iter().ne(kept_before);
+
+        Self { previous: previous.to_vec(), current: current.to_vec(), added, removed, renamed, reordered }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.renamed.is_empty() && !self.reordered
+    }
+
+    /// New name to previous name, for every rename.
+    pub fn renames(&self) -> BTreeMap<String, String> {
+        self.renamed.iter().map(|rename| (rename.to.clone(), rename.from.clone())).collect()
+    }
+}
+
+/// Parses `new:previous` pairs separated by commas, e.g. `qty:quantity`.
+pub fn parse_renames(text: &str) -> Result<BTreeMap<String, String>, String> {
+    let mut renames = BTreeMap::new();
+    for pair in text.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+        let (to, from) = pair
+            .split_once(':')
+            .map(|(to, from)| (to.trim(), from.trim()))
+            .filter(|(to, from)| !to.is_empty() && !from.is_empty())
+            .ok_or_else(|| format!("'{}' is not new_name:previous_name", pair))?;
+        if renames.insert(to.to_string(), from.to_string()).is_some() {
+            return Err(format!("column '{}' is renamed twice", to));
+        }
+    }
+    Ok(renames)
+}
+
+/// A column name without case or punctuation: `Customer Name`,
+/// `customer-name` and `customer_name` are the same column.
+fn normalized(column: &str) -> String {
+    column.chars().filter(char::is_ascii_alphanumeric).map(|c| c.to_ascii_lowercase()).collect()
+}
@@ -0,0 +1,72 @@
+use crate::performance_utils::SalesRecord;
+use rhai::{Dynamic, Engine, Position, Scope, AST};
+
+/// Operations one record's run may take, so a runaway loop fails the
+/// script instead of hanging the request.
+const MAX_OPERATIONS_PER_RECORD: u64 = 100_000;
+
+/// A compiled per-record script. The record is in scope as `record`, a map
+/// of the sales columns the script may change or add to. If the script's
+/// last expression is a boolean, it filters: `false` drops the record.
+pub struct RowScript {
+    engine: Engine,
+    ast: AST,
+}
+
+/// A script that does not compile or fails on a record, with its position
+/// in the script when known (1-based).
+#[derive(Debug)]
+pub struct ScriptError {
+    pub message: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl ScriptError {
+    fn new(message: impl Into<String>, position: Position) -> Self {
+        Self { message: message.into(), line: position.line(), column: position.position() }
+    }
+}
+
+impl RowScript {
+    pub fn compile(source: &str) -> Result<Self, ScriptError> {
+        let mut engine = Engine::new();
+        engine
+            .set_max_operations(MAX_OPERATIONS_PER_RECORD)
+            .set_max_expr_depths(64, 32)
+            .set_max_string_size(64 * 1024)
+            .set_max_array_size(10_000)
+            .set_max_map_size(1_000);
+        // Scripts run once per record; printing would flood the server's log
+        engine.on_print(|_| {}).on_debug(|_, _, _| {});
+        let ast = engine.compile(source).map_err(|error| ScriptError::new(error.err_type().to_string(), error.position()))?;
+        Ok(Self { engine, ast })
+    }
+
+    /// Runs the script on `record`. Returns the record as the script left
+    /// it, or `None` when the script filtered it out.
+    pub fn apply(&self, record: &SalesRecord) -> Result<Option<serde_json::Value>, ScriptError> {
+        let map = rhai::serde::to_dynamic(record).map_err(|error| ScriptError::new(error.to_string(), Position::NONE))?;
+        let mut scope = Scope::new();
+        scope.push("record", map);
+        let result = self
+            .engine
+            .eval_ast_with_scope::<Dynamic>(&mut scope, &self.ast)
+            .map_err(|error| ScriptError::new(error.unwrap_inner().to_string(), error.position()))?;
+
+        if !result.is_unit() {
+            match result.as_bool() {
+                Ok(true) => {}
+                Ok(false) => return Ok(None),
+                Err(type_name) => {
+                    return Err(ScriptError::new(
+                        format!("the script ends in a value of type {}; end it with a boolean to filter, or with a statement", type_name),
+                        Position::NONE,
+                    ))
+                }
+            }
+        }
+        let record = scope.get_value::<Dynamic>("record").unwrap_or_default();
+        rhai::serde::from_dynamic(&record).map(Some).map_err(|error| ScriptError::new(error.to_string(), Position::NONE))
+    }
+}
@@ -0,0 +1,105 @@
+use crate::performance_utils::SalesRecord;
+use serde::Serialize;
+use std::collections::HashMap;
+
+// BM25 parameters, at their usual values: how quickly repeating a term
+// stops adding to a row's score, and how much longer rows are penalized.
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+/// An inverted index over the customer name and product of a file's
+/// records: for each term, the rows containing it and how often. Built once
+/// per parsed file, it answers searches without scanning the records.
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    postings: HashMap<String, Vec<Posting>>,
+    /// Terms in each row.
+    lengths: Vec<u32>,
+    average_length: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Posting {
+    row: u32,
+    count: u32,
+}
+
+/// A row matching a search, by its index in the file's records.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct SearchHit {
+    pub row: usize,
+    pub score: f64,
+    /// Query terms found in the row.
+    pub matched: usize,
+}
+
+/// Lowercased runs of letters and digits, e.g. `o'brien` gives `o` and `brien`.
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(str::to_lowercase)
+}
+
+impl SearchIndex {
+    pub fn build(records: &[SalesRecord]) -> Self {
+        let mut index = Self::default();
+        let mut counts: HashMap<String, u32> = HashMap::new();
+        for (row, record) in records.iter().enumerate() {
+            counts.clear();
+            for token in tokenize(&record.customer_name).chain(tokenize(&record.product)) {
+                *counts.entry(token).or_default() += 1;
+            }
+            index.lengths.push(counts.values().sum());
+            for (term, &count) in &counts {
+                index.postings.entry(term.clone()).or_default().push(Posting { row: row as u32, count });
+            }
+        }
+        let total: u64 = index.lengths.iter().map(|&length| length as u64).sum();
+        index.average_length = if records.is_empty() { 0.0 } else { total as f64 / records.len() as f64 };
+        index
+    }
+
+    /// Distinct terms indexed.
+    pub fn terms(&self) -> usize {
+        self.postings.len()
+    }
+
+    /// Rows containing any term of `query`, ranked by how many of the terms
+    /// they contain and then by BM25 score, with the total number of
+    /// matching rows. At most `limit` hits are returned.
+    pub fn search(&self, query: &str, limit: usize) -> (Vec<SearchHit>, usize) {
+        let mut terms: Vec<String> = tokenize(query).collect();
+        terms.sort();
+        terms.dedup();
+        let rows = self.lengths.len() as f64;
+        let mut scores: HashMap<u32, (f64, usize)> = HashMap::new();
+        for term in &terms {
+            let Some(postings) = self.postings.get(term) else {
+                continue;
+            };
+            let idf = ((rows - postings.len() as f64 + 0.5) / (postings.len() as f64 + 0.5) + 1.0).ln();
+            for posting in postings {
+                let count = posting.count as f64;
+                let length = self.lengths[posting.row as usize] as f64;
+                let norm = K1 * (1.0 - B + B * length / self.average_length.max(1.0));
+                let entry = scores.entry(posting.row).or_default();
+                entry.0 += idf * count * (K1 + 1.0) / (count + norm);
+                entry.1 += 1;
+            }
+        }
+
+        let total = scores.len();
+        let mut hits: Vec<SearchHit> = scores
+            .into_iter()
+            .map(|(row, (score, matched))| SearchHit { row: row as usize, score, matched })
+            .collect();
+        let ranking = |a: &SearchHit, b: &SearchHit| b.matched.cmp(&a.matched).then(b.score.total_cmp(&a.score)).then(a.row.cmp(&b.row));
+        // Only the hits returned need sorting, not every match
+        if hits.len() > limit && limit > 0 {
+            hits.select_nth_unstable_by(limit - 1, ranking);
+        }
+        hits.truncate(limit);
+        hits.sort_by(ranking);
+        (hits, total)
+    }
+}
@@ -0,0 +1,9842 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{
+        multipart::{MultipartError, MultipartRejection},
+        rejection::{JsonRejection, PathRejection, QueryRejection},
+        DefaultBodyLimit, Multipart, Query, Request, State,
+    },
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Json, Response},
+    routing::{get, patch, post, put, MethodRouter},
+    Router,
+};
+use csv::{ReaderBuilder, WriterBuilder};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::fs;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio_util::sync::{CancellationToken, DropGuard};
+use tower_http::services::ServeDir;
+
+use crate::{
+    performance_utils, processing, locale, report, aggregators, column_stats, incremental,
+    provenance, partitions, snapshots, search, enrichment, dedup, bloom, encryption, currency,
+    anonymize, metrics_push, body_limit, describe, price_bands, trends, row_count, dialect,
+    schema_evolution, event_log, janitor, cost, compression, retention, workspace, fields,
+    artifacts, request_log, cache_benchmark, alerts, distributed, worker_pool, generator,
+};
+#[cfg(any(feature = "kafka", feature = "sqlite"))]
+use crate::sinks;
+#[cfg(feature = "nats")]
+use crate::sources;
+#[cfg(feature = "sql")]
+use crate::query;
+#[cfg(feature = "scripting")]
+use crate::scripting;
+#[cfg(feature = "wasm")]
+use crate::wasm;
+#[cfg(feature = "chaos")]
+use crate::chaos;
+#[cfg(feature = "columnar")]
+use crate::columnar;
+
+use crate::generator::{GenerateObserver, GenerateOptions};
+use crate::generator_schema::Schema;
+/// `println!`, prefixed with the request ID while handling a request or
+/// running a job one started.
+macro_rules! log {
+    ($($arg:tt)*) => {
+        match request_log::current() {
+            Some(request_id) => println!("[{}] {}", request_id, format_args!($($arg)*)),
+            None => println!($($arg)*),
+        }
+    };
+}
+
+use crate::performance_utils::{aggregate_by_operation, parse_size, DiskSpace, LatencyRecorder, LatencySummary, PerformanceTimer, ResultsDocument, ScopedTimer, StrategyResult, SystemInfo, ThroughputSample, ThroughputSampler, PerformanceMetrics, RuntimeOptions, SalesRecord, SALES_COLUMNS};
+
+// Shared application state
+pub type SharedState = Arc<Mutex<AppState>>;
+
+/// How parsed files are kept between requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum CacheLayout {
+    /// `Vec<SalesRecord>`, which every endpoint reads.
+    Rows,
+    /// Arrow record batches, which /analyze and the built-in aggregations
+    /// read directly; other endpoints get rows converted back per request.
+    #[cfg(feature = "columnar")]
+    Columnar,
+}
+
+/// Everything the handlers share: caches, jobs, metrics and the settings
+/// read from the environment at [`new_state`].
+#[derive(Clone)]
+pub struct AppState {
+    /// Appended to outside the state lock; see [`record_processing`].
+    upload_metrics: Arc<event_log::EventLog<PerformanceMetrics>>,
+    processing_metrics: Arc<event_log::EventLog<PerformanceMetrics>>,
+    /// Duration distribution of every timed operation, by operation name.
+    latencies: LatencyRecorder,
+    /// Latest /benchmark and /compare result per strategy and file, for
+    /// GET /benchmark/report.
+    benchmark_results: Vec<StrategyResult>,
+    cached_data: HashMap<FileId, Arc<Vec<SalesRecord>>>,
+    /// How parsed files are cached, from `CACHE_LAYOUT`.
+    cache_layout: CacheLayout,
+    /// Parsed files as Arrow record batches, with `CACHE_LAYOUT=columnar`.
+    #[cfg(feature = "columnar")]
+    columnar_data: HashMap<FileId, Arc<columnar::ColumnarRecords>>,
+    /// Parses running now, so concurrent requests for a file share one.
+    parse_flights: HashMap<FileId, ParseFlight>,
+    /// How far each cached file was parsed, so rows appended to it since
+    /// are parsed on their own and added to what is cached.
+    parsed_extents: HashMap<FileId, incremental::Extent>,
+    /// When, and by which request or job, each cached file's records were
+    /// ingested, for GET /provenance and exports and sinks that carry it.
+    lineages: HashMap<FileId, provenance::Lineage>,
+    /// Held while appended rows are added, so they are added once.
+    catch_up_lock: Arc<tokio::sync::Mutex<()>>,
+    /// Files produced by POST /generate.
+    catalog: HashMap<FileId, CatalogEntry>,
+    /// Column statistics of analyzed files, answering /analyze and
+    /// /aggregate until the file changes.
+    column_stats: HashMap<FileId, StatisticsEntry>,
+    /// Bloom filters over the key columns of analyzed files, so GET
+    /// /contains skips the files that cannot hold a value.
+    bloom_filters: HashMap<FileId, BloomEntry>,
+    /// /analyze results kept in `snapshots/`.
+    snapshots: snapshots::SnapshotStore,
+    /// Content hashes of the files analyzed since startup, checked by the
+    /// snapshot watcher.
+    hashed_files: HashMap<FileId, HashedFile>,
+    /// Search indexes, each with the cached records it was built from; one
+    /// whose records left the cache is rebuilt on the next search.
+    search_indexes: HashMap<FileId, (Arc<Vec<SalesRecord>>, Arc<search::SearchIndex>)>,
+    /// Held while rows of a file are appended, updated or deleted, so
+    /// writes never interleave or lose each other's rows.
+    write_lock: Arc<tokio::sync::Mutex<()>>,
+    /// Files received by POST /upload, keyed by upload id.
+    uploads: HashMap<String, UploadEntry>,
+    generation_jobs: HashMap<u64, GenerationJob>,
+    next_job_id: u64,
+    /// Exports written to `artifacts/` by POST /exports.
+    export_jobs: HashMap<u64, ExportJob>,
+    next_export_id: u64,
+    /// Pipelines run by POST /pipelines.
+    pipeline_jobs: HashMap<u64, PipelineJob>,
+    next_pipeline_id: u64,
+    /// Signs download links to artifacts, with `ARTIFACT_KEY`.
+    artifact_signer: artifacts::ArtifactSigner,
+    /// Lifetime of a download link unless the request asks for another,
+    /// from `ARTIFACT_TTL`.
+    artifact_ttl_seconds: u64,
+    /// Timeout and retries for reading files to parse, from `READ_TIMEOUT`
+    /// and `READ_RETRIES`.
+    io_policy: processing::Policy,
+    shutdown: CancellationToken,
+    /// Producer for POST /publish; `None` when it could not be created.
+    #[cfg(feature = "kafka")]
+    kafka: Option<sinks::kafka::KafkaSink>,
+    /// Database for POST /ingest, at `SQLITE_PATH`.
+    #[cfg(feature = "sqlite")]
+    sqlite: sinks::sqlite::SqliteSink,
+    /// Faults injected into requests and file reads, from `CHAOS` or
+    /// PUT /admin/chaos.
+    #[cfg(feature = "chaos")]
+    chaos: Arc<chaos::Chaos>,
+    /// What the NATS subscription has ingested.
+    #[cfg(feature = "nats")]
+    ingest: sources::nats::IngestStats,
+    /// Tables over sample_data/ and uploads/ for POST /query.
+    #[cfg(feature = "sql")]
+    query: Arc<query::QueryEngine>,
+    /// Runs uploaded transform modules; `None` when the engine could not start.
+    #[cfg(feature = "wasm")]
+    wasm_sandbox: Option<wasm::WasmSandbox>,
+    #[cfg(feature = "wasm")]
+    wasm_modules: HashMap<String, WasmModuleEntry>,
+    /// Aggregations offered on /aggregate.
+    aggregators: Arc<aggregators::Registry>,
+    /// Lookup tables joined onto exported records.
+    enrichment: Arc<enrichment::Enricher>,
+    /// Exchange rates for `?currency=` on the analysis endpoints.
+    currency: Arc<currency::CurrencyRates>,
+    /// Rules checked after every parse, and where their alerts go.
+    alerts: alerts::AlertMonitor,
+    /// `None` unless `REPORT_INTERVAL` is set.
+    report_schedule: Option<ReportSchedule>,
+    /// Threads that parse files for every request and background job.
+    parser_pool: worker_pool::WorkerPool,
+    /// Workers that /distributed/analyze splits files across.
+    coordinator: distributed::Coordinator,
+    /// For the runtime busy ratio in /metrics.
+    started_at: std::time::Instant,
+    /// When a timed operation last completed, for /health.
+    last_processed_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Free bytes each data directory should keep, from `HEALTH_MIN_FREE_DISK`.
+    min_free_disk: u64,
+    /// Sweeps of orphaned temporary files and expired reports, for /metrics.
+    janitor: janitor::JanitorStats,
+    /// When uploads are archived and archives deleted, from
+    /// `RETENTION_MAX_AGE` and `RETENTION_MAX_TOTAL`; `None` keeps them.
+    retention_policy: Option<Arc<retention::RetentionPolicy>>,
+    /// Retention runs, for /files/status and /metrics.
+    retention: retention::RetentionStats,
+    /// The zstd dictionary archives and compressed exports use, from
+    /// POST /admin/compression/train or the last one saved to `compression/`.
+    dictionary: Option<TrainedDictionary>,
+    /// Limits on the estimated cost of parses, sorts and joins, from
+    /// `COST_MAX_ROWS`, `COST_MAX_MEMORY` and `COST_MAX_OUTPUT_ROWS`.
+    cost_guard: cost::CostGuard,
+    /// Open workspaces of POST /workspaces, keyed by id.
+    workspaces: HashMap<String, WorkspaceEntry>,
+    /// How long a workspace may sit idle before the janitor discards it,
+    /// from `WORKSPACE_TTL`; `None` keeps them until they are committed or
+    /// discarded.
+    workspace_ttl_seconds: Option<u64>,
+    /// Which appended records are dropped as duplicates, from `DEDUP_KEYS`;
+    /// `None` keeps them all.
+    dedup: Option<Arc<dedup::DedupConfig>>,
+    /// The duplicate filter of each file appended to since startup.
+    deduplicators: HashMap<FileId, Arc<Mutex<DedupEntry>>>,
+    /// What each filter has seen and dropped, for /metrics without
+    /// waiting on a filter being seeded.
+    dedup_stats: HashMap<FileId, dedup::DedupStats>,
+    /// Columns encrypted in the files the server stores, from
+    /// `COLUMN_ENCRYPTION_KEYS`; `None` stores them as sent.
+    encryption: Option<Arc<encryption::ColumnCipher>>,
+    /// Roles of bearer tokens, from `ROLE_TOKENS`, and which of them see
+    /// encrypted columns decrypted, from `DECRYPT_ROLES`.
+    roles: Arc<encryption::RoleTokens>,
+}
+
+/// A file's duplicate filter, and the file's size once the rows it saw
+/// were written; `None` while an append is under way. A filter whose size
+/// differs from the file's is seeded again from the file's rows.
+struct DedupEntry {
+    filter: dedup::Deduplicator,
+    size: Option<u64>,
+}
+
+/// The records of a file parsed by one request.
+#[derive(Clone)]
+struct ParsedFile {
+    records: Arc<Vec<SalesRecord>>,
+    bytes: usize,
+    /// Parsing stopped at shutdown; the records are partial and not cached.
+    cancelled: bool,
+}
+
+/// A parse in progress, awaited by every request for the same file.
+type ParseFlight = Arc<tokio::sync::OnceCell<Result<ParsedFile, ApiError>>>;
+
+/// Requests for more rows than this are generated in the background and
+/// polled with GET /generate/:id.
+const BACKGROUND_GENERATION_ROWS: u32 = 100_000;
+
+/// Products listed per file in the scheduled analysis reports.
+const REPORT_TOP_PRODUCTS: usize = 10;
+
+/// Products listed per band by /price-bands unless `top` says otherwise,
+/// and the most it may ask for.
+const DEFAULT_BAND_PRODUCTS: usize = 3;
+const MAX_BAND_PRODUCTS: usize = 50;
+
+/// Most byte ranges /distributed/analyze may split a file into.
+const MAX_DISTRIBUTED_RANGES: usize = 1_024;
+
+/// Parsed rows between throughput progress reports in /process.
+const PROGRESS_INTERVAL_ROWS: usize = 1_024;
+
+/// Records serialized into each body chunk of GET /process?records=true.
+const RECORD_BATCH_ROWS: usize = 1_000;
+
+/// Sample records in a GET /process summary unless `?samples=` says otherwise.
+const DEFAULT_SAMPLE_RECORDS: usize = 3;
+
+/// The most sample records `?samples=` may ask for.
+const MAX_SAMPLE_RECORDS: usize = 50;
+
+/// Rows encoded into each body chunk of GET /stream/synthetic.
+const SYNTHETIC_BATCH_ROWS: u64 = 1_000;
+
+/// How often rates are fetched from `CURRENCY_RATES_URL` when
+/// `CURRENCY_REFRESH` is unset.
+const DEFAULT_CURRENCY_REFRESH_SECONDS: u64 = 3_600;
+
+/// Largest upload or POST /records body when `MAX_UPLOAD_SIZE` is unset.
+const DEFAULT_MAX_UPLOAD_BYTES: u64 = 1 << 30;
+
+/// Memory each file's duplicate filter may hold when `DEDUP_MEMORY` is unset.
+const DEFAULT_DEDUP_MEMORY_BYTES: u64 = 64 << 20;
+
+/// Free space below which a data directory turns /health `degraded`, when
+/// `HEALTH_MIN_FREE_DISK` is unset.
+const DEFAULT_MIN_FREE_DISK_BYTES: u64 = 1 << 30;
+
+/// Format of [`StateSnapshot`] files; `--restore` refuses others.
+const STATE_SNAPSHOT_VERSION: u32 = 1;
+
+/// How often analyzed files are checked for changes when
+/// `SNAPSHOT_WATCH_INTERVAL` is unset.
+const DEFAULT_SNAPSHOT_WATCH_SECONDS: u64 = 5;
+
+/// Where exports are written for download through signed links.
+const ARTIFACTS_DIR: &str = "artifacts";
+
+/// How long a download link lasts when `ARTIFACT_TTL` is unset.
+const DEFAULT_ARTIFACT_TTL_SECONDS: u64 = 3_600;
+
+/// The longest a download link may last.
+const MAX_ARTIFACT_TTL_SECONDS: u64 = 7 * 86_400;
+
+/// Where files are exploded into partitions by region and month.
+const PARTITIONS_DIR: &str = "data";
+
+/// Runs of each aggregation in /compare's cache layout comparison; the
+/// fastest is reported, leaving out the first run's cold caches.
+#[cfg(feature = "columnar")]
+const LAYOUT_COMPARISON_RUNS: usize = 5;
+
+/// The most retries `READ_RETRIES` may ask for; with the backoff capped at
+/// two seconds, a failing read spends at most about 15 seconds waiting.
+const MAX_READ_RETRIES: u32 = 10;
+
+/// How long artifacts are kept when `ARTIFACT_RETENTION` is unset.
+const DEFAULT_ARTIFACT_RETENTION_SECONDS: u64 = 7 * 86_400;
+
+/// How often the janitor sweeps when `JANITOR_INTERVAL` is unset.
+const DEFAULT_JANITOR_INTERVAL_SECONDS: u64 = 600;
+
+/// Age at which an untouched temporary file counts as orphaned, when
+/// `JANITOR_TEMP_AGE` is unset.
+const DEFAULT_JANITOR_TEMP_AGE_SECONDS: u64 = 3_600;
+
+/// How long reports are kept when `REPORT_RETENTION` is unset.
+const DEFAULT_REPORT_RETENTION_SECONDS: u64 = 30 * 86_400;
+
+/// How often uploads are checked against the retention rules when
+/// `RETENTION_INTERVAL` is unset.
+const DEFAULT_RETENTION_INTERVAL_SECONDS: u64 = 3_600;
+
+/// How long archived uploads are kept when `ARCHIVE_RETENTION` is unset.
+const DEFAULT_ARCHIVE_RETENTION_SECONDS: u64 = 90 * 86_400;
+
+/// Where uploads are archived when `ARCHIVE_DIR` is unset.
+const DEFAULT_ARCHIVE_DIR: &str = "archive";
+
+/// Where trained zstd dictionaries are kept, all of them, as archives and
+/// exports compressed with an older one still need it.
+const COMPRESSION_DIR: &str = "compression";
+
+/// Most bytes a trained dictionary may take.
+const MAX_DICTIONARY_BYTES: usize = 1 << 20;
+
+/// Most rows POST /admin/compression/train samples.
+const MAX_TRAINING_ROWS: usize = 1_000_000;
+
+/// Audit log entries /files/status shows.
+const RECENT_ARCHIVAL_EVENTS: usize = 50;
+
+/// Where each workspace keeps its artifacts, in a directory named by its id.
+const WORKSPACES_DIR: &str = "workspaces";
+
+/// How long a workspace may sit idle before it is discarded, unless
+/// `WORKSPACE_TTL` says otherwise.
+const DEFAULT_WORKSPACE_TTL_SECONDS: u64 = 86_400;
+
+/// Most steps a pipeline may have.
+const MAX_PIPELINE_STEPS: usize = 64;
+
+/// Invalid records a pipeline's validate step lists; it counts them all.
+const MAX_INVALID_EXAMPLES: usize = 10;
+
+#[derive(Deserialize)]
+struct SyntheticQuery {
+    /// Stream until the client disconnects when omitted.
+    rows: Option<u64>,
+    seed: Option<u64>,
+}
+
+#[cfg(feature = "pprof")]
+#[derive(Deserialize)]
+struct ProfileQuery {
+    seconds: Option<u64>,
+    /// `flamegraph` (SVG, the default) or `pprof` (protobuf for `go tool pprof`).
+    format: Option<String>,
+}
+
+#[cfg(feature = "kafka")]
+#[derive(Deserialize)]
+struct PublishQuery {
+    /// `sales` when omitted.
+    topic: Option<String>,
+    /// `json`, the only message format so far.
+    format: Option<String>,
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(Deserialize)]
+struct IngestQuery {
+    /// `sales` when omitted.
+    table: Option<String>,
+    /// Comma-separated columns rows are matched on; `id` when omitted.
+    key: Option<String>,
+}
+
+#[cfg(feature = "sql")]
+#[derive(Deserialize)]
+struct QueryRequest {
+    sql: String,
+    /// Rows to return, at most [`MAX_QUERY_ROWS`]; that many when omitted.
+    limit: Option<usize>,
+}
+
+/// Most rows POST /query returns; the total is still counted.
+#[cfg(feature = "sql")]
+const MAX_QUERY_ROWS: usize = 10_000;
+
+#[cfg(any(feature = "scripting", feature = "wasm"))]
+#[derive(Deserialize)]
+struct TransformQuery {
+    /// Transformed records to return, at most [`MAX_TRANSFORM_ROWS`];
+    /// [`DEFAULT_TRANSFORM_ROWS`] when omitted. All records are processed.
+    limit: Option<usize>,
+}
+
+#[cfg(any(feature = "scripting", feature = "wasm"))]
+const DEFAULT_TRANSFORM_ROWS: usize = 100;
+
+#[cfg(any(feature = "scripting", feature = "wasm"))]
+const MAX_TRANSFORM_ROWS: usize = 10_000;
+
+#[derive(Deserialize)]
+struct RecordStreamQuery {
+    /// `cursor` of the previous batch; the first batch when omitted.
+    cursor: Option<String>,
+    /// Records per batch, at most [`MAX_STREAM_BATCH`];
+    /// [`DEFAULT_STREAM_BATCH`] when omitted.
+    limit: Option<usize>,
+    /// `column:value` pairs, enriched columns included, that returned
+    /// records must all match; see [`enrichment::RecordFilter`].
+    filter: Option<String>,
+}
+
+const DEFAULT_STREAM_BATCH: usize = 1_000;
+
+const MAX_STREAM_BATCH: usize = 10_000;
+
+/// Most records one POST /records/:filename may append.
+const MAX_APPEND_RECORDS: usize = 10_000;
+
+/// Hits returned by GET /search when `limit` is omitted, and at most.
+const DEFAULT_SEARCH_HITS: usize = 20;
+const MAX_SEARCH_HITS: usize = 1_000;
+
+/// Where a GET /records/:filename/stream batch starts: a byte offset at a
+/// row start, and the file's length and modification time when the cursor
+/// was issued, so a cursor into a file that has since changed is refused.
+/// Clients see it as `<offset>-<fingerprint>` in hex and should treat that
+/// as opaque.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RecordCursor {
+    offset: u64,
+    fingerprint: u64,
+}
+
+impl RecordCursor {
+    fn parse(text: &str) -> Option<Self> {
+        let (offset, fingerprint) = text.split_once('-')?;
+        Some(Self {
+            offset: u64::from_str_radix(offset, 16).ok()?,
+            fingerprint: u64::from_str_radix(fingerprint, 16).ok()?,
+        })
+    }
+    
+    fn fingerprint(metadata: &std::fs::Metadata) -> u64 {
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+            .map_or(0, |modified| modified.as_nanos() as u64);
+        modified ^ metadata.len().rotate_left(32)
+    }
+}
+
+impl std::fmt::Display for RecordCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:x}-{:x}", self.offset, self.fingerprint)
+    }
+}
+
+#[cfg(feature = "wasm")]
+#[derive(Deserialize)]
+struct WasmUploadQuery {
+    /// A label for listings; the module id when omitted.
+    name: Option<String>,
+}
+
+/// A transform module uploaded to POST /wasm/modules, compiled once.
+#[cfg(feature = "wasm")]
+#[derive(Clone, Serialize)]
+struct WasmModuleEntry {
+    id: String,
+    name: String,
+    size_bytes: usize,
+    uploaded_at: chrono::DateTime<chrono::Utc>,
+    #[serde(skip)]
+    module: wasmtime::Module,
+}
+
+/// Longest CPU profile GET /debug/pprof will take.
+#[cfg(feature = "pprof")]
+const MAX_PROFILE_SECONDS: u64 = 300;
+
+#[derive(Deserialize)]
+struct CompareQuery {
+    /// Tasks for the async + parallel method; one per CPU when omitted.
+    concurrency: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct ReportQuery {
+    /// `html` (the default) or `md`.
+    format: Option<String>,
+    /// Overrides `Accept-Language`, e.g. `de-DE`.
+    locale: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CacheBenchmarkQuery {
+    /// Measured /analyze runs per strategy and file, 5 by default.
+    iterations: Option<usize>,
+    /// Comma-separated files; the small, medium and large samples by default.
+    files: Option<String>,
+    /// `md` or `html` for a report instead of JSON.
+    format: Option<String>,
+    /// For the report, overrides `Accept-Language`, e.g. `de-DE`.
+    locale: Option<String>,
+}
+
+/// The filter is given as a [`FilterQuery`] beside these.
+#[derive(Deserialize)]
+struct PartitionBenchmarkQuery {
+    /// `large_data.csv` by default.
+    filename: Option<String>,
+    /// Measured runs of each read, 3 by default.
+    iterations: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct GenerateRequest {
+    rows: u32,
+    seed: Option<u64>,
+    error_rate: Option<f64>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct CatalogEntry {
+    filename: FileId,
+    rows: u32,
+    size_bytes: u64,
+    seed: Option<u64>,
+    error_rate: f64,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// What POST /admin/snapshot saves and `--restore` loads: the history a
+/// server accumulates and which files it had cached. Cached rows are not
+/// saved; they are parsed again from the files.
+#[derive(Serialize, Deserialize)]
+struct StateSnapshot {
+    version: u32,
+    created_at: chrono::DateTime<chrono::Utc>,
+    upload_metrics: Vec<PerformanceMetrics>,
+    processing_metrics: Vec<PerformanceMetrics>,
+    benchmark_results: Vec<StrategyResult>,
+    catalog: Vec<CatalogEntry>,
+    uploads: Vec<UploadEntry>,
+    cache: Vec<CachedFile>,
+}
+
+/// A file the server had derived something from when it was snapshotted.
+#[derive(Serialize, Deserialize)]
+struct CachedFile {
+    filename: FileId,
+    /// Of the contents it was cached as; a file that changed since is not
+    /// parsed again on restore.
+    fingerprint: u64,
+    /// Rows cached, or `None` when only statistics or a hash were kept.
+    rows: Option<usize>,
+    statistics: bool,
+    /// For the snapshot watcher, which drops the file's analysis snapshots
+    /// once it changes.
+    content_hash: Option<u64>,
+}
+
+/// Column statistics of a file, with the fingerprint of the contents they
+/// were computed from.
+#[derive(Clone)]
+struct StatisticsEntry {
+    fingerprint: u64,
+    computed_at: chrono::DateTime<chrono::Utc>,
+    statistics: Arc<column_stats::ColumnStatistics>,
+}
+
+/// Bloom filters over a file's key columns, with the fingerprint of the
+/// contents they were built from.
+#[derive(Clone)]
+struct BloomEntry {
+    fingerprint: u64,
+    built_at: chrono::DateTime<chrono::Utc>,
+    filters: Arc<bloom::KeyFilters>,
+}
+
+/// The content hash of a file as of its fingerprint, so the file is only
+/// read again to hash it once it changed.
+#[derive(Clone, Copy)]
+struct HashedFile {
+    fingerprint: u64,
+    hash: u64,
+}
+
+/// An uploaded file. It is stored as `uploads/<id>.csv`, so uploads never
+/// overwrite each other whatever the client called them.
+#[derive(Clone, Serialize, Deserialize)]
+struct UploadEntry {
+    id: String,
+    /// The client's name for the file. It addresses the latest upload
+    /// under that name, unless a file in `sample_data/` has it too.
+    filename: String,
+    file_id: FileId,
+    size_bytes: usize,
+    uploaded_at: chrono::DateTime<chrono::Utc>,
+    /// How the file is written, as declared with the upload.
+    #[serde(default)]
+    dialect: dialect::Dialect,
+    /// The columns of the file as it is read, renames applied; the schema
+    /// the next upload under the same name is compared with.
+    #[serde(default)]
+    columns: Vec<String>,
+    /// How the header differed from the previous upload under the same name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    schema_diff: Option<schema_evolution::SchemaDiff>,
+}
+
+/// When and what the server writes analysis reports to `reports/`, from
+/// `REPORT_INTERVAL` and `REPORT_FILES`, and how the last run went.
+#[derive(Clone, Serialize)]
+struct ReportSchedule {
+    interval_seconds: u64,
+    files: Vec<String>,
+    runs: u64,
+    last_run_at: Option<chrono::DateTime<chrono::Utc>>,
+    next_run_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Reports written by the last run.
+    last_reports: Vec<String>,
+    last_error: Option<String>,
+    /// How the markdown report writes numbers and dates, from
+    /// `REPORT_LOCALE`; the CSV is always plain.
+    locale: locale::Locale,
+}
+
+impl ReportSchedule {
+    /// `None` when `REPORT_INTERVAL` is unset or invalid. `REPORT_FILES` is
+    /// a comma-separated list of files, `small_data.csv` by default, and
+    /// `REPORT_LOCALE` a locale such as `de-DE`.
+    fn from_env() -> Option<Self> {
+        let interval = std::env::var("REPORT_INTERVAL").ok()?;
+        let Some(interval_seconds) = parse_interval(&interval) else {
+            println!("⚠️  Ignoring REPORT_INTERVAL={}: expected seconds, or a number followed by s, m, h or d", interval);
+            return None;
+        };
+        let files = std::env::var("REPORT_FILES")
+            .unwrap_or_else(|_| "small_data.csv".to_string())
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(String::from)
+            .collect();
+        let locale = match std::env::var("REPORT_LOCALE") {
+            Ok(tag) => locale::Locale::parse(&tag).unwrap_or_else(|| {
+                println!("⚠️  Ignoring REPORT_LOCALE={}: expected one of {}", tag, supported_locales().join(", "));
+                locale::Locale::CANONICAL
+            }),
+            Err(_) => locale::Locale::CANONICAL,
+        };
+        Some(Self {
+            interval_seconds,
+            files,
+            runs: 0,
+            last_run_at: None,
+            next_run_at: None,
+            last_reports: Vec::new(),
+            last_error: None,
+            locale,
+        })
+    }
+}
+
+/// A file in `reports/`, as listed by GET /reports.
+#[derive(Serialize)]
+struct ReportFile {
+    name: String,
+    size_bytes: u64,
+    modified: chrono::DateTime<chrono::Utc>,
+    url: String,
+}
+
+#[derive(Clone, Serialize)]
+struct GenerationJob {
+    id: u64,
+    filename: FileId,
+    rows: u32,
+    status: JobStatus,
+    error: Option<String>,
+    /// The request that started the job.
+    request_id: Option<String>,
+    /// Rows and bytes per second, sampled every second while running.
+    throughput: Vec<ThroughputSample>,
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum JobStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+/// A file being converted into a download in `artifacts/`.
+#[derive(Clone, Serialize)]
+struct ExportJob {
+    id: u64,
+    filename: FileId,
+    format: ExportFormat,
+    /// Whether each record carries its source file, line, ingestion time and job.
+    provenance: bool,
+    /// Whether the export is zstd-compressed, as `<name>.zst`.
+    compress: bool,
+    /// The dictionary a compressed export needs to be decompressed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dictionary_id: Option<u32>,
+    status: JobStatus,
+    error: Option<String>,
+    /// The request that started the job.
+    request_id: Option<String>,
+    /// The file in `artifacts/`, once written.
+    artifact: Option<String>,
+    records: Option<usize>,
+    size_bytes: Option<u64>,
+    created_at: chrono::DateTime<chrono::Utc>,
+    finished_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ExportFormat {
+    /// Comma-separated with a header, whatever dialect the file was uploaded in.
+    Csv,
+    /// One JSON object per line.
+    Jsonl,
+    /// One JSON array.
+    Json,
+}
+
+impl ExportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Jsonl => "jsonl",
+            ExportFormat::Json => "json",
+        }
+    }
+    
+    fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Jsonl => "application/x-ndjson",
+            ExportFormat::Json => "application/json",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ExportRequest {
+    filename: String,
+    /// `csv` when omitted.
+    format: Option<ExportFormat>,
+    /// Adds `source_file`, `source_line`, `ingested_at` and `job_id` to
+    /// each record; false when omitted.
+    #[serde(default)]
+    provenance: bool,
+    /// Compresses the export with zstd, using the trained dictionary when
+    /// there is one; false when omitted.
+    #[serde(default)]
+    compress: bool,
+}
+
+/// A pipeline for POST /pipelines, in JSON or YAML: steps run in order
+/// over one set of records, as a background job.
+#[derive(Clone, Serialize, Deserialize)]
+struct PipelineDefinition {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    /// What a failed step means for the steps after it; `stop` when omitted.
+    #[serde(default)]
+    on_failure: OnFailure,
+    steps: Vec<PipelineStep>,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum OnFailure {
+    /// The steps after it are skipped and the job fails.
+    #[default]
+    Stop,
+    /// The steps after it run on the records as they were before it.
+    Continue,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct PipelineStep {
+    #[serde(flatten)]
+    operation: PipelineOperation,
+    /// Overrides the pipeline's `on_failure` for this step.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    on_failure: Option<OnFailure>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum PipelineOperation {
+    /// Adds the records of a data file to those of earlier ingests.
+    Ingest { file: String },
+    /// Checks records as appended ones are checked.
+    Validate {
+        #[serde(default)]
+        on_invalid: OnInvalid,
+    },
+    /// Keeps the records matching every condition of `filter`, then runs
+    /// the Rhai `script` on them as /transform does.
+    Transform {
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        filter: Vec<workspace::Condition>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        script: Option<String>,
+    },
+    /// Drops records whose comma-separated `keys` columns repeat an
+    /// earlier record's, as `DEDUP_KEYS` does for appends.
+    Dedupe { keys: String },
+    /// Runs registered aggregations, as /aggregate does; the records pass
+    /// through.
+    Aggregate { metrics: Vec<String> },
+    /// Writes the records to `artifacts/`, as /exports does.
+    Export {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        format: Option<ExportFormat>,
+    },
+}
+
+impl PipelineOperation {
+    fn name(&self) -> &'static str {
+        match self {
+            PipelineOperation::Ingest { .. } => "ingest",
+            PipelineOperation::Validate { .. } => "validate",
+            PipelineOperation::Transform { .. } => "transform",
+            PipelineOperation::Dedupe { .. } => "dedupe",
+            PipelineOperation::Aggregate { .. } => "aggregate",
+            PipelineOperation::Export { .. } => "export",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum OnInvalid {
+    /// Invalid records are dropped.
+    #[default]
+    Drop,
+    /// The first invalid record fails the step.
+    Fail,
+}
+
+/// A pipeline run by POST /pipelines.
+#[derive(Clone, Serialize)]
+struct PipelineJob {
+    id: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    status: JobStatus,
+    error: Option<String>,
+    /// The request that started the job.
+    request_id: Option<String>,
+    on_failure: OnFailure,
+    steps: Vec<PipelineStepReport>,
+    /// Records left after the last step.
+    records: Option<usize>,
+    /// The last file exported to `artifacts/`.
+    artifact: Option<String>,
+    created_at: chrono::DateTime<chrono::Utc>,
+    finished_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// How one step of a pipeline went.
+#[derive(Clone, Serialize)]
+struct PipelineStepReport {
+    op: &'static str,
+    status: StepStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    records_in: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    records_out: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    records_per_second: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// What the step found or wrote: records dropped, aggregation
+    /// results, the export.
+    #[serde(skip_serializing_if = "serde_json::Value::is_null")]
+    output: serde_json::Value,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum StepStatus {
+    Pending,
+    Running,
+    Completed,
+    Failed,
+    /// Not run, as an earlier step failed with `on_failure: stop` or the
+    /// server is shutting down.
+    Skipped,
+}
+
+#[derive(Deserialize)]
+struct ArtifactLinkQuery {
+    /// Seconds the download link lasts; `ARTIFACT_TTL` when omitted.
+    expires_in: Option<u64>,
+}
+
+/// An open workspace, and what its steps, commit and discard take turns on.
+#[derive(Clone)]
+struct WorkspaceEntry {
+    workspace: workspace::Workspace,
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+#[derive(Deserialize)]
+struct CommitQuery {
+    /// Comma-separated artifacts to keep; the results, those no step read,
+    /// when omitted.
+    artifacts: Option<String>,
+}
+
+/// Directories data files are read from, searched in order. Files in the
+/// first are named without a prefix; the others keep theirs, e.g.
+/// `uploads/orders.csv`.
+pub const DATA_DIRS: [&str; 2] = ["sample_data", "uploads"];
+
+/// Records of an upload whose structure is checked before it is accepted.
+const VALIDATED_RECORDS: usize = 100;
+
+/// Most of an upload's start held for validating those records; longer
+/// rows are validated as far as the complete lines within it.
+const VALIDATED_HEAD_BYTES: usize = 64 * 1024;
+
+/// Upload content types accepted as CSV. Browsers send Excel's type for
+/// .csv on Windows, and curl sends octet-stream for unknown extensions.
+const CSV_CONTENT_TYPES: [&str; 5] = ["text/csv", "application/csv", "text/plain", "application/vnd.ms-excel", "application/octet-stream"];
+
+/// A data file in one of the [`DATA_DIRS`]. Clients may send
+/// `small_data.csv` or `sample_data/small_data.csv`; both give the same id,
+/// which keys the record cache, stored results, metrics and catalog.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct FileId {
+    dir: &'static str,
+    /// The canonical name: relative to `dir`, prefixed with it unless it is
+    /// the default directory.
+    id: String,
+}
+
+impl FileId {
+    /// Normalizes a client-supplied name without touching the disk. A
+    /// leading data directory selects it; otherwise the name is in the
+    /// default one.
+    fn parse(name: &str) -> Result<Self, ApiError> {
+        let (dir, relative) = Self::split(name)?;
+        Ok(Self::new(dir.unwrap_or(DATA_DIRS[0]), relative))
+    }
+    
+    /// Like [`FileId::parse`], but a name without a directory is looked up
+    /// as an upload id, in the catalog, in `sample_data/`, as the name of
+    /// an upload and finally in the other data directories.
+    async fn resolve(name: &str, state: &SharedState) -> Result<Self, ApiError> {
+        let (dir, relative) = Self::split(name)?;
+        if let Some(dir) = dir {
+            return Ok(Self::new(dir, relative));
+        }
+        let default = Self::new(DATA_DIRS[0], relative);
+        let latest_upload = {
+            let app_state = lock_state(state)?;
+            if let Some(upload) = app_state.uploads.get(relative) {
+                return Ok(upload.file_id.clone());
+            }
+            if app_state.catalog.contains_key(&default) {
+                return Ok(default);
+            }
+            app_state
+                .uploads
+                .values()
+                .filter(|upload| upload.filename == relative)
+                .max_by_key(|upload| upload.uploaded_at)
+                .map(|upload| upload.file_id.clone())
+        };
+        if fs::try_exists(default.path()).await.unwrap_or(false) {
+            return Ok(default);
+        }
+        if let Some(file) = latest_upload {
+            return Ok(file);
+        }
+        for dir in &DATA_DIRS[1..] {
+            let candidate = Self::new(dir, relative);
+            if fs::try_exists(candidate.path()).await.unwrap_or(false) {
+                return Ok(candidate);
+            }
+        }
+        // Not found anywhere; reading it reports the 404
+        Ok(default)
+    }
+    
+    /// Splits off a leading data directory, rejecting names that would
+    /// resolve outside the data directories.
+    fn split(name: &str) -> Result<(Option<&'static str>, &str), ApiError> {
+        let name = name.trim_start_matches("./");
+        let (dir, relative) = DATA_DIRS
+            .iter()
+            .find_map(|dir| Some((Some(*dir), name.strip_prefix(dir)?.strip_prefix('/')?)))
+            .unwrap_or((None, name));
+        let escapes = relative.starts_with('/')
+            || relative.contains('\\')
+            || relative.split('/').any(|part| part.is_empty() || part == "." || part == "..");
+        if escapes {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "invalid_filename",
+                format!("'{}' does not name a file in {}", name, DATA_DIRS.join(" or ")),
+            )
+            .detail("filename", name));
+        }
+        Ok((dir, relative))
+    }
+    
+    fn new(dir: &'static str, relative: &str) -> Self {
+        let id = if dir == DATA_DIRS[0] {
+            relative.to_string()
+        } else {
+            format!("{}/{}", dir, relative)
+        };
+        Self { dir, id }
+    }
+    
+    fn as_str(&self) -> &str {
+        &self.id
+    }
+    
+    fn path(&self) -> String {
+        if self.dir == DATA_DIRS[0] {
+            format!("{}/{}", self.dir, self.id)
+        } else {
+            self.id.clone()
+        }
+    }
+}
+
+impl std::fmt::Display for FileId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.id)
+    }
+}
+
+impl Serialize for FileId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.id)
+    }
+}
+
+/// Parsed as a client-supplied name, so a name read back from a state
+/// snapshot cannot point outside the data directories either.
+impl<'de> Deserialize<'de> for FileId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        FileId::parse(&name).map_err(|error| serde::de::Error::custom(error.message))
+    }
+}
+
+/// A failed request, answered with its status and a JSON body:
+/// `{"error": {"code": ..., "message": ..., "details": {...}}}`. Codes are
+/// stable for clients to match on; details locate the problem, such as the
+/// file, line or multipart field.
+#[derive(Debug, Clone)]
+struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+    details: serde_json::Map<String, serde_json::Value>,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self { status, code, message: message.into(), details: serde_json::Map::new() }
+    }
+    
+    fn detail(mut self, key: &str, value: impl Serialize) -> Self {
+        self.details.insert(key.to_string(), serde_json::json!(value));
+        self
+    }
+    
+    /// A file that could not be read: 404 when it does not exist, 500
+    /// otherwise.
+    fn read_failed(path: &str, error: std::io::Error) -> Self {
+        let (status, code) = match error.kind() {
+            std::io::ErrorKind::NotFound => (StatusCode::NOT_FOUND, "file_not_found"),
+            _ => (StatusCode::INTERNAL_SERVER_ERROR, "read_failed"),
+        };
+        Self::new(status, code, format!("cannot read {}: {}", path, error)).detail("path", path)
+    }
+    
+    /// [`ApiError::read_failed`] for a read under the I/O policy, or 504
+    /// `read_timeout` when its last attempt ran out of time.
+    fn read_under_policy(path: &str, error: processing::PolicyError<std::io::Error>) -> Self {
+        let attempts = error.attempts();
+        let api_error = match error {
+            processing::PolicyError::TimedOut { timeout, .. } => Self::new(
+                StatusCode::GATEWAY_TIMEOUT,
+                "read_timeout",
+                format!("reading {} took longer than {:?}", path, timeout),
+            )
+            .detail("path", path),
+            processing::PolicyError::Failed { error, .. } => Self::read_failed(path, error),
+        };
+        api_error.detail("attempts", attempts)
+    }
+    
+    /// A row that does not parse, located where the CSV reader knows it by
+    /// line and column (both 1-based) and record index (the header is 0).
+    fn invalid_csv(filename: &str, error: &csv::Error) -> Self {
+        let mut api_error = Self::new(StatusCode::BAD_REQUEST, "invalid_csv", error.to_string()).detail("filename", filename);
+        if let Some(position) = error.position() {
+            api_error = api_error.detail("line", position.line()).detail("record", position.record());
+        }
+        if let csv::ErrorKind::Deserialize { err, .. } = error.kind() {
+            if let Some(field) = err.field() {
+                api_error = api_error.detail("column", field + 1);
+            }
+        }
+        api_error
+    }
+    
+    /// A stored field that does not decrypt: its key is not configured, or
+    /// the file was tampered with.
+    fn decryption_failed(filename: &str, (column, error): (String, encryption::DecryptError)) -> Self {
+        let api_error = Self::new(StatusCode::INTERNAL_SERVER_ERROR, "decryption_failed", format!("cannot decrypt {} in {}: {}", column, filename, error))
+            .detail("filename", filename)
+            .detail("column", column);
+        match error {
+            encryption::DecryptError::UnknownKey(id) => api_error.detail("key_id", id),
+            encryption::DecryptError::Corrupt => api_error,
+        }
+    }
+    
+    /// A body cut off by [`limit_body`] at `MAX_UPLOAD_SIZE`.
+    fn payload_too_large(exceeded: body_limit::LimitExceeded) -> Self {
+        Self::new(StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large", exceeded.to_string())
+            .detail("limit_bytes", exceeded.limit)
+            .detail("received_bytes", exceeded.received)
+    }
+    
+    /// A file with no rows to process: 422, since the request was well
+    /// formed but there is nothing to compute on. The details carry the
+    /// analysis of no rows, zeroed, for clients that show it anyway.
+    fn empty_csv(filename: &str, content: &[u8]) -> Self {
+        let has_header = !content.trim_ascii().is_empty();
+        let message = if has_header {
+            format!("{} has a header but no data rows", filename)
+        } else {
+            format!("{} is empty", filename)
+        };
+        Self::new(StatusCode::UNPROCESSABLE_ENTITY, "empty_csv", message)
+            .detail("filename", filename)
+            .detail("has_header", has_header)
+            .detail("summary", serde_json::json!({
+                "total_records": 0,
+                "total_revenue": 0.0,
+                "average_price": 0.0,
+                "top_products": []
+            }))
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut body = serde_json::json!({
+            "error": {
+                "code": self.code,
+                "message": self.message,
+                "details": self.details
+            }
+        });
+        // For quoting in bug reports; the same ID is on the server's log lines
+        if let Some(request_id) = request_log::current() {
+            body["error"]["request_id"] = serde_json::json!(request_id);
+        }
+        (self.status, Json(body)).into_response()
+    }
+}
+
+// Extractor failures get the same JSON body instead of axum's plain text
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        if let Some(exceeded) = body_limit::exceeded(&rejection) {
+            return Self::payload_too_large(exceeded);
+        }
+        Self::new(rejection.status(), "invalid_json", rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        Self::new(rejection.status(), "invalid_query", rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(rejection: PathRejection) -> Self {
+        Self::new(rejection.status(), "invalid_path", rejection.body_text())
+    }
+}
+
+impl From<MultipartRejection> for ApiError {
+    fn from(rejection: MultipartRejection) -> Self {
+        if let Some(exceeded) = body_limit::exceeded(&rejection) {
+            return Self::payload_too_large(exceeded);
+        }
+        Self::new(rejection.status(), "invalid_multipart", rejection.body_text())
+    }
+}
+
+impl From<MultipartError> for ApiError {
+    fn from(error: MultipartError) -> Self {
+        if let Some(exceeded) = body_limit::exceeded(&error) {
+            return Self::payload_too_large(exceeded);
+        }
+        Self::new(error.status(), "invalid_multipart", error.body_text())
+    }
+}
+
+/// Parsing hints for POST /upload, kept with the upload so later requests
+/// read the file the same way.
+#[derive(Deserialize)]
+struct UploadQuery {
+    /// `,` when omitted; `tab` for tab-separated files.
+    delimiter: Option<String>,
+    /// Whether the first line names the columns; true when omitted.
+    has_header: Option<bool>,
+    /// `sales`, or a schema in `schemas/`, whose columns name the fields of
+    /// a file without a header, or must all be in the header of one with.
+    schema: Option<String>,
+    /// `csv` or `parquet` to explode the upload into partitions by region
+    /// and month once it is saved.
+    partition: Option<String>,
+    /// `map` (the default), `strict` or `off`: what to do when the header
+    /// differs from the previous upload under the same name.
+    compatibility: Option<String>,
+    /// Renamed columns as `new:previous` pairs, e.g. `qty:quantity`.
+    rename: Option<String>,
+}
+
+/// `?fields=`, accepted by the endpoints with large responses beside their
+/// own parameters.
+#[derive(Deserialize)]
+struct FieldsQuery {
+    fields: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ProcessQuery {
+    /// Stream every record after the summary.
+    records: Option<bool>,
+    /// Sample records in the summary, 0 to 50.
+    samples: Option<usize>,
+    /// Whether customer names in the samples are replaced with `REDACTED`;
+    /// true unless `redact=false`.
+    redact: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct AnalysisQuery {
+    #[allow(dead_code)] // accepted for forward compatibility, grouping is by product only
+    group_by: Option<String>,
+    /// Top products to return, best first; all of them when omitted.
+    limit: Option<usize>,
+    /// Currency to report prices and revenue in; see GET /currency.
+    currency: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DistributedQuery {
+    /// Byte ranges to split the file into (default: one per worker).
+    ranges: Option<usize>,
+    limit: Option<usize>,
+    currency: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ClusterBenchmarkQuery {
+    /// Benchmark one host at a time, for workers sharing a machine.
+    sequential: Option<bool>,
+}
+
+/// `?region=North,South&from=2024-03&to=2024-06`, accepted by /analyze
+/// and /aggregate beside their own parameters. Months are `YYYY-MM` and
+/// the range includes both ends.
+#[derive(Deserialize)]
+struct FilterQuery {
+    region: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PartitionQuery {
+    /// `csv` (the default) or `parquet`.
+    format: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AggregateQuery {
+    /// Comma-separated aggregation names; all of them when omitted.
+    metrics: Option<String>,
+    currency: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    /// Words to look for in customer names and products.
+    q: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct CostQuery {
+    /// Runs an operation whose estimated cost goes over the limits anyway,
+    /// unless `COST_FORCE=off`; false when omitted.
+    #[serde(default)]
+    force: bool,
+}
+
+#[derive(Deserialize)]
+struct TrainDictionaryQuery {
+    /// Comma-separated files to sample; every stored file when omitted.
+    files: Option<String>,
+    /// Rows sampled across all files; 100,000 when omitted.
+    sample_rows: Option<usize>,
+    /// Rows per training sample; 32 when omitted.
+    rows_per_sample: Option<usize>,
+    /// The dictionary's size limit, e.g. `64KB`; 110 KiB when omitted.
+    max_size: Option<String>,
+}
+
+/// A zstd dictionary in use, with what training it reported.
+#[derive(Clone)]
+struct TrainedDictionary {
+    dictionary: Arc<compression::Dictionary>,
+    path: std::path::PathBuf,
+    /// The response of the POST /admin/compression/train that trained it;
+    /// `None` for one loaded at startup.
+    training: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct ContainsQuery {
+    /// Comma-separated files to look in; every stored file when omitted.
+    file: Option<String>,
+    /// `id` or `customer_name`.
+    column: String,
+    value: String,
+    /// Whether a file its filter cannot rule out is scanned for an exact
+    /// answer rather than reported as "maybe"; true when omitted.
+    verify: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct TrendQuery {
+    /// Moving-average window, e.g. `7d`, `4w` or `3m`; 7 days when omitted.
+    window: Option<String>,
+    /// `revenue`, `orders` or `quantity`; revenue when omitted.
+    metric: Option<String>,
+    currency: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StatsQuery {
+    /// Comma-separated numeric columns; quantity, price and revenue when omitted.
+    columns: Option<String>,
+    currency: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PriceBandQuery {
+    /// Bands of about equal order counts, e.g. 4 for quartiles; 4 when omitted.
+    bands: Option<usize>,
+    /// Products listed per band; [`DEFAULT_BAND_PRODUCTS`] when omitted.
+    top: Option<usize>,
+    currency: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CountQuery {
+    /// Scan the whole file for line breaks instead of sampling it.
+    #[serde(default)]
+    exact: bool,
+}
+
+#[derive(Deserialize)]
+struct AnonymizeRequest {
+    /// Strategy per column; the other columns are copied as they are.
+    columns: std::collections::BTreeMap<String, anonymize::Strategy>,
+    /// HMAC key for `pseudonymize`, `ANONYMIZE_KEY` when omitted.
+    key: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ErasureRequest {
+    /// Matched against every row's customer_name, trimmed and ignoring case.
+    customer_name: String,
+    #[serde(default)]
+    action: ErasureAction,
+}
+
+/// What POST /erasure does with a customer's rows.
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ErasureAction {
+    /// Remove the rows.
+    #[default]
+    Delete,
+    /// Keep the rows, so totals do not change, with the name redacted.
+    Mask,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AnalysisResult {
+    total_records: usize,
+    total_revenue: f64,
+    average_price: f64,
+    top_products: Vec<ProductSummary>,
+    processing_time_ms: u128,
+    cancelled: bool,
+    /// Answered from the file's column statistics, without its records.
+    from_statistics: bool,
+    /// A stored result of the same request on the same file contents.
+    #[serde(default)]
+    from_snapshot: bool,
+    /// The rate amounts were converted with, for `?currency=`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    currency: Option<currency::AppliedRate>,
+    /// The partitions read and skipped, for a filtered analysis of a
+    /// partitioned file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    partitions: Option<partitions::PartitionScan>,
+}
+
+impl AnalysisResult {
+    /// The result in another currency. Every amount in it is a sum or mean
+    /// of prices, so scaling them equals converting each price first, and
+    /// stored statistics and snapshots serve every currency.
+    fn converted(mut self, rate: Option<currency::AppliedRate>) -> Self {
+        let Some(rate) = rate else {
+            return self;
+        };
+        self.total_revenue *= rate.rate;
+        self.average_price *= rate.rate;
+        for product in &mut self.top_products {
+            product.total_sales *= rate.rate;
+        }
+        self.currency = Some(rate);
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ProductSummary {
+    product: String,
+    total_sales: f64,
+    quantity_sold: u32,
+}
+
+/// Runs the server on `BIND_ADDR` (127.0.0.1:3000 by default) until Ctrl+C,
+/// with its background tasks, starting from the state snapshot at
+/// `restore` when given.
+pub async fn serve(restore: Option<&str>, runtime: RuntimeOptions) {
+    // Timer spans, events and the access log go to stderr, at warn level (info for the access
+    // log) unless RUST_LOG says otherwise
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("warn,access=info")),
+        )
+        .with_writer(std::io::stderr)
+        .init();
+    
+    println!("🌐 Axum CSV Processing Server");
+    println!("============================");
+    runtime.display();
+    
+    // Cancelled on Ctrl+C; every request derives a child token from it
+    let shutdown = CancellationToken::new();
+    let state = new_state(shutdown.clone());
+    
+    if let Some(path) = restore {
+        match restore_state(&state, path, &shutdown) {
+            Ok(restored) => println!("📦 Restored {} from {}", restored, path),
+            Err(error) => {
+                eprintln!("❌ Could not restore {}: {}", path, error);
+                std::process::exit(1);
+            }
+        }
+    }
+    
+    let config = ServerConfig::from_env();
+    println!("📦 Upload and POST /records bodies are limited to {} bytes", config.body_limit);
+    
+    spawn_background_tasks(&state, &shutdown);
+    let app = build_router(&config, state.clone());
+    
+    // Another address lets several servers, e.g. distributed workers, share a machine
+    let address = std::env::var("BIND_ADDR").unwrap_or_else(|_| "127.0.0.1:3000".to_string());
+    let listener = tokio::net::TcpListener::bind(&address)
+        .await
+        .unwrap();
+        
+    println!("🚀 Server running on http://{}", address);
+    println!("\n📋 CSV Processing Endpoints:");
+    println!("  GET  / - API documentation");
+    println!("  POST /upload - Upload CSV file");
+    println!("  GET  /uploads/:filename/schema - Columns of each upload under a name and how they changed");
+    println!("  GET  /files/status - Uploads, archives and recent archival actions under the retention rules");
+    println!("  GET  /process/:filename - Process CSV with performance metrics");
+    println!("  GET  /analyze/:filename?region=North&from=2024-01&to=2024-06 - Analyze CSV data, all of it or the rows of some regions and months; force=true past the cost limits");
+    println!("  GET  /compare?concurrency=N - Compare different processing methods");
+    println!("  GET  /health - Disk space, cache size, queue depth and last processing time");
+    println!("  GET  /metrics - View performance metrics");
+    println!("  GET  /metrics/summary - Metrics aggregated by operation, with trends");
+    println!("  POST /admin/snapshot - Save metrics, catalog and cache manifest for --restore");
+    println!("  POST /admin/compression/train?files=a.csv,b.csv - Train a zstd dictionary for archives and compressed exports");
+    println!("  GET  /admin/compression - The dictionary in use and how much it saves");
+    println!("  GET  /admin/compression/dictionaries/:id - Download a dictionary, for zstd -d -D");
+    println!("  POST /benchmark - Run performance benchmark");
+    println!("  GET  /benchmark/report?format=html|md - Comparison report of /benchmark and /compare runs");
+    println!("  GET  /benchmark/results - Latest benchmark results as versioned JSON");
+    println!("  POST /benchmark/cluster?sequential=true - Benchmark every WORKER_URLS host and compare them");
+    println!("  POST /benchmark/cache?iterations=5&format=md - /analyze latency without a cache, with records and with statistics");
+    println!("  POST /benchmark/partitions?filename=large_data.csv&region=North - Filtered analysis with and without partition pruning");
+    println!("  POST /partitions/:filename?format=csv|parquet - Explode a file into data/ by region and month");
+    println!("  GET  /partitions - Partitioned files");
+    println!("  POST /generate - Generate a sample CSV ({{ rows, seed, error_rate }})");
+    println!("  GET  /generate - Background generation jobs");
+    println!("  GET  /generate/:id - Status of a background generation job");
+    println!("  GET  /catalog - Files generated by this server");
+    println!("  GET  /catalog/:filename/statistics - Column statistics kept for an analyzed file");
+    println!("  GET  /contains?column=id&value=42&file=a.csv,b.csv - Which files hold a key value, skipping those their Bloom filters rule out");
+    println!("  GET  /aggregate - Registered aggregations");
+    println!("  GET  /aggregate/:filename?metrics=a,b&region=North - Run aggregations over a file, or the rows of some regions and months");
+    println!("  GET  /search/:filename?q=smith+laptop - Search customer names and products, best matches first");
+    println!("  GET  /trends/:filename?window=7d&metric=revenue - Series per period with moving average and growth");
+    println!("  GET  /stats/:filename?columns=price,quantity - Descriptive statistics and correlation matrix");
+    println!("  GET  /price-bands/:filename?bands=4&top=3 - Revenue share and top products per price quantile band");
+    println!("  GET  /count/:filename?exact=true - Row count, estimated from a sample unless exact");
+    println!("  POST /anonymize/:filename - Masked copy of a CSV, from per-column strategies");
+    println!("  POST /erasure - Delete or mask a customer's rows in the data files; lists the stores it does not reach");
+    println!("  GET  /enrichment - Lookup tables joined onto records");
+    println!("  PUT  /enrichment/:name - Register a lookup table from a CSV body");
+    println!("  DELETE /enrichment/:name - Remove a lookup table");
+    println!("  GET  /currency - Exchange rates for ?currency= on /analyze and /aggregate");
+    println!("  GET  /alerts - ALERT_RULES and the alerts they raised");
+    println!("  GET  /reports - Reports in reports/ and the REPORT_INTERVAL schedule");
+    println!("  GET  /reports/:name - Download a report");
+    println!("  POST /exports - Export a file as csv, jsonl or json in the background ({{ filename, format }})");
+    println!("  GET  /exports - Export jobs");
+    println!("  GET  /exports/:id?expires_in=3600 - Status of an export and a signed download link");
+    println!("  GET  /artifacts/:token - Download an export through a signed, expiring link");
+    println!("  POST /pipelines - Run a JSON or YAML pipeline of ingest, validate, transform, dedupe, aggregate and export steps");
+    println!("  GET  /pipelines - Pipeline jobs");
+    println!("  GET  /pipelines/:id - Status and per-step metrics of a pipeline");
+    println!("  POST /worker/analyze - Analyze a byte range of a file for a coordinator");
+    println!("  GET  /distributed/analyze/:filename?ranges=N - Analyze a file across WORKER_URLS");
+    println!("  POST /records/:filename - Append one or more JSON records to a file");
+    println!("  PATCH /records/:filename/:id - Change fields of the records with an id");
+    println!("  DELETE /records/:filename/:id - Remove the records with an id");
+    println!("  GET  /records/:filename/stream?cursor=C&limit=N - Page through a file's records by cursor");
+    println!("  GET  /provenance/:filename/:id - Source line, ingestion time and job of the records with an id");
+    println!("  POST /workspaces - Open a workspace for a multi-step pipeline");
+    println!("  GET  /workspaces - Open workspaces and their artifacts");
+    println!("  GET  /workspaces/:id - A workspace's steps, artifacts and results");
+    println!("  POST /workspaces/:id/steps - Run a transform, join or sort step ({{ op, input, output, ... }}), @name reading an artifact; ?force=true past the cost limits");
+    println!("  GET  /workspaces/:id/artifacts/:name - Download an artifact");
+    println!("  POST /workspaces/:id/commit?artifacts=a,b - Move artifacts, the results by default, into uploads/ and close the workspace");
+    println!("  DELETE /workspaces/:id - Discard a workspace and its artifacts");
+    println!("  GET  /stream/synthetic?rows=N - Stream generated CSV without touching disk");
+    println!("  GET  /files/ - Access uploaded files");
+    #[cfg(feature = "pprof")]
+    println!("  GET  /debug/pprof?seconds=10&format=flamegraph|pprof - CPU profile of the server");
+    #[cfg(feature = "kafka")]
+    println!("  POST /publish/:filename?topic=sales - Publish parsed records to Kafka as JSON");
+    #[cfg(feature = "sqlite")]
+    println!("  POST /ingest/:filename?table=sales&key=id - Upsert parsed records into SQLite by key");
+    #[cfg(feature = "sql")]
+    println!("  POST /query - Read-only SQL over sample_data/ and uploads/ ({{ sql, limit }})");
+    #[cfg(feature = "scripting")]
+    println!("  POST /transform/:filename?limit=N - Apply the Rhai script in the body to each record");
+    #[cfg(feature = "wasm")]
+    println!("  POST /wasm/modules?name=x - Upload a WASM transform module (binary or text)");
+    #[cfg(feature = "wasm")]
+    println!("  GET  /wasm/modules - Uploaded WASM modules");
+    #[cfg(feature = "wasm")]
+    println!("  POST /wasm/transform/:module/:filename?limit=N - Run a WASM module on each record, sandboxed");
+    #[cfg(feature = "chaos")]
+    println!("  GET  /admin/chaos - Faults being injected and how many were");
+    #[cfg(feature = "chaos")]
+    println!("  PUT  /admin/chaos - Set the fault rates and seed ({{ latency_rate, latency_min_ms, latency_max_ms, http_error_rate, io_error_rate, truncate_rate, seed }})");
+    println!("\n💡 Try these curl commands:");
+    println!("  curl http://127.0.0.1:3000/");
+    println!("  curl http://127.0.0.1:3000/process/small_data.csv");
+    println!("  curl http://127.0.0.1:3000/analyze/small_data.csv");
+    println!("  curl -F 'file=@sample_data/small_data.csv' http://127.0.0.1:3000/upload");
+    println!("  curl -X POST -H 'Content-Type: application/json' -d '{{\"rows\": 5000, \"seed\": 42}}' http://127.0.0.1:3000/generate");
+    
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal(shutdown))
+        .await
+        .unwrap();
+    
+    if let Some(pusher) = metrics_push::MetricsPusher::from_env("axum_csv_server") {
+        pusher.push(&push_samples(&state)).await;
+    }
+}
+
+/// The server's shared state, configured from the environment, with
+/// nothing processed or cached yet. Cancelling `shutdown` cancels every
+/// request and background task started on it.
+pub fn new_state(shutdown: CancellationToken) -> SharedState {
+    Arc::new(Mutex::new(AppState {
+        upload_metrics: Arc::new(metrics_log("upload")),
+        processing_metrics: Arc::new(metrics_log("processing")),
+        latencies: LatencyRecorder::new(),
+        benchmark_results: Vec::new(),
+        cached_data: HashMap::new(),
+        cache_layout: cache_layout(),
+        #[cfg(feature = "columnar")]
+        columnar_data: HashMap::new(),
+        parse_flights: HashMap::new(),
+        parsed_extents: HashMap::new(),
+        lineages: HashMap::new(),
+        catch_up_lock: Arc::new(tokio::sync::Mutex::new(())),
+        catalog: HashMap::new(),
+        column_stats: HashMap::new(),
+        bloom_filters: HashMap::new(),
+        snapshots: snapshots::SnapshotStore::new("snapshots"),
+        hashed_files: HashMap::new(),
+        search_indexes: HashMap::new(),
+        write_lock: Arc::new(tokio::sync::Mutex::new(())),
+        uploads: HashMap::new(),
+        generation_jobs: HashMap::new(),
+        next_job_id: 1,
+        export_jobs: HashMap::new(),
+        next_export_id: 1,
+        pipeline_jobs: HashMap::new(),
+        next_pipeline_id: 1,
+        artifact_signer: artifact_signer(),
+        artifact_ttl_seconds: artifact_ttl_seconds(),
+        io_policy: io_policy(),
+        shutdown: shutdown.clone(),
+        #[cfg(feature = "kafka")]
+        kafka: kafka_sink(),
+        #[cfg(feature = "sqlite")]
+        sqlite: sinks::sqlite::SqliteSink::new(std::env::var("SQLITE_PATH").unwrap_or_else(|_| "sales.db".to_string())),
+        #[cfg(feature = "chaos")]
+        chaos: Arc::new(chaos::Chaos::new(chaos_config())),
+        #[cfg(feature = "nats")]
+        ingest: sources::nats::IngestStats::default(),
+        #[cfg(feature = "sql")]
+        query: Arc::new(query::QueryEngine::new()),
+        #[cfg(feature = "wasm")]
+        wasm_sandbox: wasm_sandbox(),
+        #[cfg(feature = "wasm")]
+        wasm_modules: HashMap::new(),
+        aggregators: Arc::new(aggregator_registry()),
+        enrichment: Arc::new(enrichment::Enricher::default()),
+        currency: Arc::new(currency_rates()),
+        alerts: alerts::AlertMonitor::from_env(),
+        report_schedule: ReportSchedule::from_env(),
+        parser_pool: parser_pool(),
+        coordinator: distributed::Coordinator::from_env(),
+        started_at: std::time::Instant::now(),
+        last_processed_at: None,
+        min_free_disk: size_from_env("HEALTH_MIN_FREE_DISK", DEFAULT_MIN_FREE_DISK_BYTES),
+        janitor: janitor::JanitorStats::default(),
+        retention_policy: retention_policy().map(Arc::new),
+        retention: retention::RetentionStats::default(),
+        dictionary: trained_dictionary(),
+        cost_guard: cost::CostGuard { limits: cost_limits(), ..Default::default() },
+        workspaces: HashMap::new(),
+        workspace_ttl_seconds: seconds_from_env("WORKSPACE_TTL", DEFAULT_WORKSPACE_TTL_SECONDS),
+        dedup: dedup_config().map(Arc::new),
+        deduplicators: HashMap::new(),
+        dedup_stats: HashMap::new(),
+        encryption: encryption_config().map(Arc::new),
+        roles: Arc::new(role_tokens()),
+    }))
+}
+
+/// Settings of the HTTP layer, apart from what the shared state holds.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Largest body POST /upload and POST /records accept, in bytes.
+    pub body_limit: u64,
+}
+
+impl ServerConfig {
+    /// `MAX_UPLOAD_SIZE`, as the server is started with.
+    pub fn from_env() -> Self {
+        Self { body_limit: size_from_env("MAX_UPLOAD_SIZE", DEFAULT_MAX_UPLOAD_BYTES) }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self { body_limit: DEFAULT_MAX_UPLOAD_BYTES }
+    }
+}
+
+/// The full API over `state`: every route, behind the body limit, the
+/// request context and, with the chaos feature, fault injection. Spawns
+/// nothing, so the integration tests can serve it in-process, with
+/// `axum::serve` on any listener or request by request with
+/// `tower::ServiceExt::oneshot`, and start [`spawn_background_tasks`]
+/// only if they want them. The server is an example, not a library, so
+/// they reach it by compiling this file in.
+pub fn build_router(config: &ServerConfig, state: SharedState) -> Router {
+    let body_limit = config.body_limit;
+    let app = Router::new()
+        // File serving
+        .route("/files/status", get(files_status))
+        .nest_service("/files", ServeDir::new("sample_data"))
+        
+        // CSV processing endpoints
+        .route("/", get(root_handler))
+        .route("/upload", with_body_limit(post(upload_csv), body_limit))
+        .route("/uploads/:filename/schema", get(upload_schema_history))
+        .route("/process/*filename", get(process_csv_file))
+        .route("/analyze/*filename", get(analyze_csv))
+        .route("/compare", get(compare_processing_methods))
+        .route("/health", get(health_check))
+        .route("/metrics", get(get_metrics))
+        .route("/metrics/summary", get(get_metrics_summary))
+        .route("/admin/snapshot", post(snapshot_state))
+        .route("/admin/compression", get(compression_status))
+        .route("/admin/compression/train", post(train_dictionary))
+        .route("/admin/compression/dictionaries/:id", get(download_dictionary))
+        .route("/benchmark", post(run_benchmark))
+        .route("/benchmark/report", get(benchmark_report))
+        .route("/benchmark/results", get(benchmark_results))
+        .route("/benchmark/cluster", post(cluster_benchmark))
+        .route("/benchmark/cache", post(cache_benchmark))
+        .route("/benchmark/partitions", post(partition_benchmark))
+        .route("/partitions", get(list_partitions))
+        .route("/partitions/*filename", post(create_partitions))
+        .route("/generate", post(generate_data).get(list_generation_jobs))
+        .route("/generate/:id", get(get_generation_job))
+        .route("/catalog", get(get_catalog))
+        .route("/catalog/:filename/statistics", get(get_file_statistics))
+        .route("/contains", get(contains_value))
+        .route("/aggregate", get(list_aggregators))
+        .route("/aggregate/*filename", get(aggregate_file))
+        .route("/search/*filename", get(search_records))
+        .route("/count/*filename", get(count_records))
+        .route("/trends/*filename", get(file_trends))
+        .route("/price-bands/*filename", get(file_price_bands))
+        .route("/stats/*filename", get(file_stats))
+        .route("/anonymize/*filename", post(anonymize_file))
+        .route("/enrichment", get(list_lookup_tables))
+        .route("/enrichment/:name", put(register_lookup_table).delete(remove_lookup_table))
+        .route("/currency", get(get_currency_rates))
+        .route("/alerts", get(list_alerts))
+        .route("/reports", get(list_reports))
+        .route("/reports/:name", get(download_report))
+        .route("/exports", post(start_export).get(list_exports))
+        .route("/exports/:id", get(get_export))
+        .route("/pipelines", post(start_pipeline).get(list_pipelines))
+        .route("/pipelines/:id", get(get_pipeline))
+        .route("/artifacts/:token", get(download_artifact))
+        .route("/worker/analyze", post(analyze_range))
+        .route("/distributed/analyze/*filename", get(distributed_analyze))
+        .route("/records/:filename", with_body_limit(post(append_records), body_limit))
+        .route("/records/:filename/stream", get(stream_records))
+        .route("/provenance/:filename/:row_id", get(record_provenance))
+        .route("/records/:filename/:id", patch(update_record).delete(delete_record))
+        .route("/erasure", post(erase_customer))
+        .route("/workspaces", post(create_workspace).get(list_workspaces))
+        .route("/workspaces/:id", get(get_workspace).delete(discard_workspace))
+        .route("/workspaces/:id/steps", post(run_workspace_step))
+        .route("/workspaces/:id/artifacts/:name", get(download_workspace_artifact))
+        .route("/workspaces/:id/commit", post(commit_workspace))
+        .route("/stream/synthetic", get(stream_synthetic));
+    
+    #[cfg(feature = "pprof")]
+    let app = app.route("/debug/pprof", get(cpu_profile));
+    #[cfg(feature = "kafka")]
+    let app = app.route("/publish/*filename", post(publish_records));
+    #[cfg(feature = "sqlite")]
+    let app = app.route("/ingest/*filename", post(ingest_records));
+    #[cfg(feature = "sql")]
+    let app = app.route("/query", post(run_query));
+    #[cfg(feature = "scripting")]
+    let app = app.route("/transform/*filename", post(transform_records));
+    #[cfg(feature = "wasm")]
+    let app = app
+        .route("/wasm/modules", post(upload_wasm_module).get(list_wasm_modules))
+        .route("/wasm/transform/:module/*filename", post(wasm_transform_records));
+    #[cfg(feature = "chaos")]
+    let app = app.route("/admin/chaos", get(get_chaos).put(configure_chaos));
+    
+    #[cfg(feature = "chaos")]
+    let chaos = state.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).chaos.clone();
+    let app = app.with_state(state).layer(middleware::from_fn(cost_scope));
+    // Inside the request context, so injected faults are logged under the request's id
+    #[cfg(feature = "chaos")]
+    let app = app.layer(middleware::from_fn_with_state(chaos, inject_faults));
+    app.layer(middleware::from_fn(request_context))
+}
+
+/// The tasks that run beside the requests until `shutdown`: scheduled
+/// reports, the snapshot watcher, currency refreshes, the janitor, upload
+/// retention and, with the nats feature, ingestion.
+pub fn spawn_background_tasks(state: &SharedState, shutdown: &CancellationToken) {
+    #[cfg(feature = "nats")]
+    spawn_nats_ingest(state.clone(), shutdown.clone());
+    spawn_report_schedule(state.clone(), shutdown.clone());
+    spawn_snapshot_watcher(state.clone(), shutdown.clone());
+    spawn_currency_refresh(state.clone(), shutdown.clone());
+    spawn_janitor(state.clone(), shutdown.clone());
+    spawn_retention(state.clone(), shutdown.clone());
+}
+
+/// What the server pushes on exit with `PUSHGATEWAY_URL` or
+/// `REMOTE_WRITE_URL`: its upload and processing metrics, request
+/// latencies and latest benchmark results.
+fn push_samples(state: &SharedState) -> metrics_push::Samples {
+    let mut samples = metrics_push::Samples::new();
+    if let Ok(app_state) = state.lock() {
+        samples.add_metrics(&app_state.upload_metrics.snapshot().to_vec());
+        samples.add_metrics(&app_state.processing_metrics.snapshot().to_vec());
+        samples.add_latencies(&app_state.latencies.summary());
+        samples.add_results(&app_state.benchmark_results);
+    }
+    samples
+}
+
+async fn shutdown_signal(shutdown: CancellationToken) {
+    let _ = tokio::signal::ctrl_c().await;
+    println!("\n🛑 Shutting down, cancelling in-flight processing...");
+    shutdown.cancel();
+}
+
+/// A size in the environment variable `name`, e.g. `500MB` or `2GiB`, or
+/// `default`.
+fn size_from_env(name: &str, default: u64) -> u64 {
+    match std::env::var(name) {
+        Ok(size) => parse_size(&size).unwrap_or_else(|error| {
+            println!("⚠️  Ignoring {}: {}", name, error);
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+/// `route` with [`limit_body`] in place of axum's default 2 MB limit,
+/// which cut uploads off with a bare 400.
+fn with_body_limit(route: MethodRouter<SharedState>, limit: u64) -> MethodRouter<SharedState> {
+    route.layer(
+        tower::ServiceBuilder::new()
+            .layer(DefaultBodyLimit::disable())
+            .layer(middleware::from_fn_with_state(limit, limit_body)),
+    )
+}
+
+/// Gives every request an ID, the client's `X-Request-Id` or a new one,
+/// returned in the same header. Its log lines, error body and any job it
+/// starts carry the ID, and it gets an access log line once answered.
+async fn request_context(request: Request, next: Next) -> Response {
+    let request_id = request_log::request_id(request.headers());
+    let (method, path) = (request.method().clone(), request.uri().path().to_string());
+    let started = std::time::Instant::now();
+    let mut response = request_log::scope(request_id.clone(), next.run(request)).await;
+    
+    if let Ok(value) = header::HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(request_log::REQUEST_ID_HEADER, value);
+    }
+    let entry = request_log::AccessEntry::new(request_id, method, path, response.status(), started);
+    let (parts, body) = response.into_parts();
+    Response::from_parts(parts, request_log::logged(body, entry))
+}
+
+/// Runs a request with its `?force=`, so every file it parses is checked
+/// against the cost limits. A malformed value is left to the handlers that
+/// take `?force=` to reject.
+async fn cost_scope(request: Request, next: Next) -> Response {
+    let force = Query::<CostQuery>::try_from_uri(request.uri()).is_ok_and(|Query(query)| query.force);
+    cost::scope(force, next.run(request)).await
+}
+
+/// Delays or fails requests as the chaos configuration says, before they
+/// reach their handler. PUT and GET /admin/chaos are spared, so faults can
+/// always be turned off again.
+#[cfg(feature = "chaos")]
+async fn inject_faults(State(chaos): State<Arc<chaos::Chaos>>, request: Request, next: Next) -> Response {
+    if request.uri().path() == "/admin/chaos" {
+        return next.run(request).await;
+    }
+    let target = format!("{} {}", request.method(), request.uri().path());
+    if let Some(delay) = chaos.delay() {
+        log!("🐒 Delaying {} by {} ms", target, delay.as_millis());
+        tokio::time::sleep(delay).await;
+    }
+    if chaos.http_error() {
+        log!("🐒 Failing {} with an injected 503", target);
+        return ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "injected_fault", "the chaos layer failed this request").into_response();
+    }
+    next.run(request).await
+}
+
+/// Caps request bodies at `limit` bytes while they stream in. A declared
+/// Content-Length over the limit is refused before any of the body is
+/// read; otherwise the body fails as soon as the limit is crossed, which
+/// the handler answers with 413, and the rest is never received.
+async fn limit_body(State(limit): State<u64>, request: Request, next: Next) -> Response {
+    let declared = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok()?.parse::<u64>().ok());
+    let target = format!("{} {}", request.method(), request.uri().path());
+    if let Some(declared) = declared.filter(|declared| *declared > limit) {
+        log!("🚫 {} declared {} bytes, over the {}-byte limit; refused unread", target, declared, limit);
+        return ApiError::payload_too_large(body_limit::LimitExceeded { limit, received: 0 })
+            .detail("declared_bytes", declared)
+            .into_response();
+    }
+    let (parts, body) = request.into_parts();
+    let body = body_limit::limited(body, limit, move |received| {
+        log!("🚫 {} aborted after {} bytes, over the {}-byte limit", target, received, limit);
+    });
+    next.run(Request::from_parts(parts, body)).await
+}
+
+/// Creates a cancellation token for a single request. It is cancelled on
+/// server shutdown, or when the returned guard is dropped because the
+/// handler future was abandoned (e.g. the client disconnected).
+fn request_token(state: &SharedState) -> Result<(CancellationToken, DropGuard), ApiError> {
+    let token = lock_state(state)?.shutdown.child_token();
+    let guard = token.clone().drop_guard();
+    Ok((token, guard))
+}
+
+/// Locks the shared state. A lock poisoned by a panic fails this request
+/// with a 500 and is cleared, so one failure does not fail every request
+/// after it.
+fn lock_state(state: &SharedState) -> Result<MutexGuard<'_, AppState>, ApiError> {
+    state.lock().map_err(|_| {
+        state.clear_poison();
+        ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "state_poisoned",
+            "a request failed while updating the server state; retry the request",
+        )
+    })
+}
+
+async fn root_handler() -> Json<serde_json::Value> {
+    Json(serde_json::json!({
+        "service": "Axum CSV Processing Server",
+        "description": "Demonstrates CSV processing performance using Axum + Tokio",
+        "endpoints": {
+            "upload": "POST /upload?delimiter=;&has_header=false&schema=sales&partition=csv&compatibility=map&rename=qty:quantity - Upload CSV files, with optional parsing hints, partition them and map renamed columns",
+            "upload_schema": "GET /uploads/:filename/schema - Columns of each upload under a name, with the diff from the one before",
+            "files_status": "GET /files/status - Stored uploads with when they are archived, archived uploads with when they are deleted, and the archival audit log",
+            "compression_train": "POST /admin/compression/train?files=a.csv,b.csv&sample_rows=100000&max_size=110KB - Train a zstd dictionary on sampled rows, used for archives and compressed exports, and report each file's ratio with and without it",
+            "compression": "GET /admin/compression - The dictionary in use, its training report and the dictionaries kept",
+            "compression_dictionary": "GET /admin/compression/dictionaries/:id - Download a dictionary to decompress archives and exports with zstd -d -D",
+            "process": "GET /process/:filename?samples=3&redact=false - Process CSV with metrics and up to 50 sample records, names redacted by default",
+            "analyze": "GET /analyze/:filename?region=North,South&from=2024-01&to=2024-06 - Analyze CSV data, optionally only some regions and months, read from its partitions when it is partitioned; force=true runs it past the cost limits",
+            "compare": "GET /compare?concurrency=N - Compare processing methods, N tasks for async + parallel (default: one per CPU), and row against columnar caching with the columnar feature",
+            "health": "GET /health - Disk space, cache size, queue depth and last processing time",
+            "metrics": "GET /metrics - View performance metrics",
+            "metrics_summary": "GET /metrics/summary - Runs, average/best throughput and trend per operation",
+            "benchmark": "POST /benchmark - Run benchmarks",
+            "benchmark_report": "GET /benchmark/report?format=html|md - Download a comparison report of /benchmark and /compare results",
+            "benchmark_results": "GET /benchmark/results - Latest /benchmark and /compare results in the versioned JSON schema",
+            "benchmark_cache": "POST /benchmark/cache?iterations=5&files=a.csv,b.csv&format=md - Compare /analyze latency with no cache, the record cache and the statistics cache",
+            "benchmark_partitions": "POST /benchmark/partitions?filename=large_data.csv&region=North&from=2024-06&to=2024-06 - Compare a filtered analysis of the whole file with one of the partitions it selects",
+            "partition": "POST /partitions/:filename?format=csv|parquet - Explode a file into data/<file>/region=<region>/month=<YYYY-MM>/",
+            "partitions": "GET /partitions - Partitioned files, with their partition counts and sizes",
+            "generate": "POST /generate - Generate a sample CSV from { rows, seed, error_rate }",
+            "generation_job": "GET /generate/:id - Status of a background generation job",
+            "export": "POST /exports - Convert a file to csv, jsonl or json in the background from { filename, format, provenance, compress }",
+            "export_job": "GET /exports/:id?expires_in=3600 - Status of an export, with a signed download link once done",
+            "artifact": "GET /artifacts/:token - Download an export through its signed link",
+            "pipeline": "POST /pipelines - Run { name, on_failure, steps } (JSON, or YAML with Content-Type: application/yaml) as a background job",
+            "pipeline_job": "GET /pipelines/:id?expires_in=3600 - Status, per-step records and timings, and a signed link to the last export",
+            "catalog": "GET /catalog - Files generated by this server",
+            "statistics": "GET /catalog/:filename/statistics - Column statistics kept for an analyzed file",
+            "contains": "GET /contains?column=id|customer_name&value=42&file=a.csv,b.csv&verify=true - Which files hold a key value; Bloom filters rule files out without reading them",
+            "provenance": "GET /provenance/:filename/:id - Source file, line, ingestion time and job id of the records with an id",
+            "workspaces": "POST /workspaces - Open a scratch workspace; GET lists them",
+            "workspace": "GET|DELETE /workspaces/:id - A workspace's steps and artifacts, or discard it",
+            "workspace_steps": "POST /workspaces/:id/steps - Run { op: transform|join|sort, input|left+right, output, ... } on data files or @artifacts; ?force=true runs a join or sort past the cost limits",
+            "workspace_artifact": "GET /workspaces/:id/artifacts/:name - Download an artifact of a workspace",
+            "workspace_commit": "POST /workspaces/:id/commit?artifacts=a,b - Move artifacts into uploads/ and close the workspace",
+            "stream_synthetic": "GET /stream/synthetic?rows=100000&seed=42 - Stream generated CSV rows (endless without rows)"
+        },
+        "sample_files": [
+            "/files/small_data.csv",
+            "/files/medium_data.csv", 
+            "/files/large_data.csv"
+        ]
+    }))
+}
+
+async fn upload_csv(
+    State(state): State<SharedState>,
+    query: Result<Query<UploadQuery>, QueryRejection>,
+    multipart: Result<Multipart, MultipartRejection>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let Query(mut params) = query?;
+    let partition = params.partition.take().map(|format| partition_format(Some(&format))).transpose()?;
+    let compatibility = match params.compatibility.take() {
+        Some(mode) => schema_evolution::Compatibility::parse(mode.trim()).ok_or_else(|| {
+            ApiError::new(StatusCode::BAD_REQUEST, "invalid_compatibility", format!("unknown compatibility mode '{}'", mode))
+                .detail("compatibility", &mode)
+                .detail("supported", ["map", "strict", "off"])
+        })?,
+        None => schema_evolution::Compatibility::Map,
+    };
+    let declared = match params.rename.take() {
+        Some(text) => schema_evolution::parse_renames(&text)
+            .map_err(|message| ApiError::new(StatusCode::BAD_REQUEST, "invalid_rename", message).detail("rename", &text))?,
+        None => Default::default(),
+    };
+    let mut dialect = upload_dialect(params)?;
+    let mut multipart = multipart?;
+    let mut timer = PerformanceTimer::new("CSV File Upload".to_string());
+    let mut fields_seen = Vec::new();
+    
+    while let Some(mut field) = multipart.next_field().await? {
+        let name = field.name().unwrap_or("").to_string();
+        if name == "file" {
+            let filename = field
+                .file_name()
+                .and_then(|name| name.rsplit(['/', '\\']).next())
+                .filter(|name| !name.is_empty())
+                .unwrap_or("uploaded.csv")
+                .to_string();
+            // The client's name is only kept as metadata, never used as a path
+            let previous = lock_state(&state)?
+                .uploads
+                .values()
+                .filter(|upload| upload.filename == filename && !upload.columns.is_empty())
+                .max_by_key(|upload| upload.uploaded_at)
+                .cloned();
+            let mut schema_diff = None;
+            let uploaded_at = chrono::Utc::now();
+            let id = format!("{}-{:08x}", uploaded_at.format("%Y%m%d%H%M%S"), rand::random::<u32>());
+            let file = FileId::new(DATA_DIRS[1], &format!("{}.csv", id));
+            check_upload_type(&filename, field.content_type()).map_err(|error| error.detail("field", &name))?;
+            let file_path = file.path();
+            let storage_failed = |error: std::io::Error| {
+                ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "storage_failed", format!("cannot save upload: {}", error))
+                    .detail("filename", &filename)
+                    .detail("path", &file_path)
+            };
+            fs::create_dir_all("uploads").await.map_err(storage_failed)?;
+            
+            // Stream into a part file and rename it into place only once the
+            // whole upload arrived and validated, so an interrupted upload
+            // never leaves a truncated CSV for /process to parse and cache
+            let mut part = PartFile { path: format!("uploads/.{}.csv.part", id), keep: false };
+            let mut out = fs::File::create(&part.path).await.map_err(storage_failed)?;
+            let mut size = 0;
+            // The start of the file, until the header and first records are
+            // validated, which rejects a bad file before the rest is sent
+            let mut head = Vec::new();
+            let (mut head_lines, mut validated) = (0, false);
+            while let Some(chunk) = field
+                .chunk()
+                .await
+                .map_err(|error| ApiError::from(error).detail("field", &name).detail("filename", &filename))?
+            {
+                if !validated {
+                    let taken = &chunk[..chunk.len().min(VALIDATED_HEAD_BYTES - head.len())];
+                    head_lines += taken.iter().filter(|&&byte| byte == b'\n').count();
+                    head.extend_from_slice(taken);
+                    if head.len() == VALIDATED_HEAD_BYTES && head_lines == 0 {
+                        return Err(ApiError::new(
+                            StatusCode::BAD_REQUEST,
+                            "invalid_csv",
+                            format!("{} has no line break in its first {} bytes", filename, VALIDATED_HEAD_BYTES),
+                        )
+                        .detail("filename", &filename)
+                        .detail("field", &name));
+                    }
+                    if head_lines > VALIDATED_RECORDS || head.len() == VALIDATED_HEAD_BYTES {
+                        let complete_lines = head.iter().rposition(|&byte| byte == b'\n').map_or(0, |end| end + 1);
+                        schema_diff = evolve_schema(&filename, &head, &mut dialect, previous.as_ref(), compatibility, &declared)?;
+                        check_csv_structure(&filename, &head, complete_lines, &dialect).map_err(|error| error.detail("field", &name))?;
+                        validated = true;
+                    }
+                }
+                out.write_all(&chunk).await.map_err(storage_failed)?;
+                size += chunk.len();
+            }
+            if !validated {
+                schema_diff = evolve_schema(&filename, &head, &mut dialect, previous.as_ref(), compatibility, &declared)?;
+                check_csv_structure(&filename, &head, head.len(), &dialect).map_err(|error| error.detail("field", &name))?;
+            }
+            let columns = upload_columns(&head, &dialect);
+            out.sync_all().await.map_err(storage_failed)?;
+            drop(out);
+            let (pool, cipher) = {
+                let app_state = lock_state(&state)?;
+                (app_state.parser_pool.clone(), app_state.encryption.clone())
+            };
+            if let Some(cipher) = cipher.clone() {
+                let (path, encrypted_dialect) = (part.path.clone(), dialect.clone());
+                pool.run(move || encrypt_upload(&path, &encrypted_dialect, &cipher, false))
+                    .await
+                    .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "task_failed", error.to_string()))?
+                    .map_err(storage_failed)?;
+            }
+            fs::rename(&part.path, &file_path).await.map_err(storage_failed)?;
+            part.keep = true;
+            
+            // Record metrics
+            timer.add_bytes(size);
+            let metrics = timer.finish(size);
+            let upload_metrics = {
+                let mut app_state = lock_state(&state)?;
+                record_latency(&mut app_state, &metrics);
+                app_state.uploads.insert(
+                    id.clone(),
+                    UploadEntry {
+                        id: id.clone(),
+                        filename: filename.clone(),
+                        file_id: file.clone(),
+                        size_bytes: size,
+                        uploaded_at,
+                        dialect: dialect.clone(),
+                        columns: columns.clone(),
+                        schema_diff: schema_diff.clone(),
+                    },
+                );
+                app_state.upload_metrics.clone()
+            };
+            upload_metrics.push(metrics.clone());
+            
+            let mut body = serde_json::json!({
+                "message": "File uploaded successfully",
+                "id": id,
+                "filename": filename,
+                "file_id": file,
+                "size_bytes": size,
+                "dialect": dialect,
+                "mb_per_second": metrics.megabytes_per_second,
+                "path": file_path,
+                "process": format!("/process/{}", id),
+                "analyze": format!("/analyze/{}", id)
+            });
+            if let Some(cipher) = &cipher {
+                body["encrypted_columns"] = serde_json::json!(cipher.columns());
+            }
+            if let Some(previous) = &previous {
+                body["schema"] = serde_json::json!({
+                    "previous_upload": previous.id,
+                    "compatibility": compatibility,
+                    "changed": schema_diff.as_ref().is_some_and(|diff| !diff.is_empty()),
+                    "diff": schema_diff
+                });
+            }
+            if let Some(format) = partition {
+                let (token, _guard) = request_token(&state)?;
+                let dataset = write_partitions(&state, &file, format, &token).await?;
+                body["partitions"] = serde_json::json!({
+                    "dir": dataset_dir(&file),
+                    "format": dataset.format,
+                    "partitions": dataset.partitions.len(),
+                    "bytes": dataset.bytes
+                });
+            }
+            return Ok(Json(body));
+        }
+        fields_seen.push(name);
+    }
+    
+    Err(ApiError::new(StatusCode::BAD_REQUEST, "missing_file_field", "the form has no `file` field")
+        .detail("expected_field", "file")
+        .detail("fields_seen", fields_seen))
+}
+
+/// Rejects an upload whose extension or content type says it is not CSV
+/// with 415. A missing content type or extension is not held against it.
+fn check_upload_type(filename: &str, content_type: Option<&str>) -> Result<(), ApiError> {
+    let extension = filename.rsplit_once('.').map(|(_, extension)| extension.to_ascii_lowercase());
+    if let Some(extension) = extension.filter(|extension| extension != "csv" && extension != "txt") {
+        return Err(ApiError::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "unsupported_media_type",
+            format!("{} has extension .{}, expected .csv", filename, extension),
+        )
+        .detail("filename", filename)
+        .detail("extension", extension));
+    }
+    // Parameters such as "; charset=utf-8" do not matter
+    let essence = content_type.map(|content_type| content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase());
+    if let Some(essence) = essence.filter(|essence| !CSV_CONTENT_TYPES.contains(&essence.as_str())) {
+        return Err(ApiError::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "unsupported_media_type",
+            format!("{} was sent as {}, expected text/csv", filename, essence),
+        )
+        .detail("filename", filename)
+        .detail("content_type", essence)
+        .detail("accepted", CSV_CONTENT_TYPES));
+    }
+    Ok(())
+}
+
+/// The dialect an upload declares in its query. A schema is `sales` or the
+/// name of a `.toml` file in `schemas/`; a file without a header takes its
+/// column names from the schema, the sales one when it names none. 400 for
+/// a delimiter that cannot separate fields or a schema that is not there.
+fn upload_dialect(params: UploadQuery) -> Result<dialect::Dialect, ApiError> {
+    let mut dialect = dialect::Dialect { has_header: params.has_header.unwrap_or(true), ..Default::default() };
+    if let Some(delimiter) = params.delimiter {
+        dialect.delimiter = dialect::Dialect::parse_delimiter(&delimiter)
+            .map_err(|message| ApiError::new(StatusCode::BAD_REQUEST, "invalid_delimiter", message).detail("delimiter", &delimiter))?;
+    }
+    
+    let schema = params.schema.map(|schema| schema.trim().to_string()).filter(|schema| !schema.is_empty());
+    let schema = match schema {
+        Some(name) if name != "sales" => {
+            // A name, never a path
+            let path = format!("schemas/{}.toml", name);
+            let named = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !named || !std::path::Path::new(&path).is_file() {
+                return Err(ApiError::new(StatusCode::BAD_REQUEST, "unknown_schema", format!("there is no schema named {:?}", name))
+                    .detail("schema", &name)
+                    .detail("available", available_schemas()));
+            }
+            let loaded = Schema::load(&path).map_err(|error| {
+                ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "invalid_schema", format!("cannot load {}: {}", path, error))
+                    .detail("schema", &name)
+            })?;
+            Some((name, loaded))
+        }
+        Some(name) => Some((name, Schema::sales())),
+        None if !dialect.has_header => Some(("sales".to_string(), Schema::sales())),
+        None => None,
+    };
+    if let Some((name, schema)) = schema {
+        dialect.columns = Some(schema.header().into_iter().map(String::from).collect());
+        dialect.schema = Some(name);
+    }
+    Ok(dialect)
+}
+
+/// Compares the header at the start of an upload, `head`, with the columns
+/// of the `previous` upload under the same name, and settles how the file
+/// is read: renames declared with the upload apply in every mode, and in
+/// `map` mode the renames found in the diff too, so requests that name the
+/// previous columns keep working. In `strict` mode a removed column, or a
+/// renamed one that was not declared, is refused with 409.
+fn evolve_schema(
+    filename: &str,
+    head: &[u8],
+    dialect: &mut dialect::Dialect,
+    previous: Option<&UploadEntry>,
+    compatibility: schema_evolution::Compatibility,
+    declared: &std::collections::BTreeMap<String, String>,
+) -> Result<Option<schema_evolution::SchemaDiff>, ApiError> {
+    use schema_evolution::Compatibility;
+    
+    let current = header_columns(head, dialect);
+    if dialect.has_header {
+        for (to, from) in declared {
+            if !current.contains(to) || current.contains(from) {
+                return Err(ApiError::new(
+                    StatusCode::BAD_REQUEST,
+                    "invalid_rename",
+                    format!("cannot read column '{}' as '{}': the header needs the first and not the second", to, from),
+                )
+                .detail("filename", filename)
+                .detail("header", &current));
+            }
+        }
+    }
+    let diff = previous.map(|previous| schema_evolution::SchemaDiff::between(&previous.columns, &current, declared));
+    if let Some(diff) = diff.as_ref().filter(|_| compatibility == Compatibility::Strict) {
+        let undeclared = diff.renamed.iter().any(|rename| rename.matched_by != "declared");
+        if !diff.removed.is_empty() || undeclared {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                "schema_changed",
+                format!("{} no longer has every column of its previous upload", filename),
+            )
+            .detail("filename", filename)
+            .detail("previous_upload", previous.map(|previous| &previous.id))
+            .detail("diff", diff)
+            .detail("hint", "declare renamed columns with ?rename=new:previous, or upload with ?compatibility=map"));
+        }
+    }
+    if dialect.has_header {
+        let mut renames = declared.clone();
+        if let Some(diff) = diff.as_ref().filter(|_| compatibility == Compatibility::Map) {
+            renames.extend(diff.renames());
+        }
+        dialect.renames = renames;
+    }
+    Ok(diff)
+}
+
+/// The columns of a file starting with `head`, as written: its header, or
+/// the dialect's columns for a file without one.
+fn header_columns(head: &[u8], dialect: &dialect::Dialect) -> Vec<String> {
+    if !dialect.has_header {
+        return dialect.columns.clone().unwrap_or_else(|| SALES_COLUMNS.iter().map(|column| column.to_string()).collect());
+    }
+    ReaderBuilder::new()
+        .delimiter(dialect.delimiter as u8)
+        .has_headers(false)
+        .from_reader(head)
+        .records()
+        .next()
+        .and_then(Result::ok)
+        .map(|header| header.iter().map(String::from).collect())
+        .unwrap_or_default()
+}
+
+/// The columns of an upload as it is read, renames applied.
+fn upload_columns(head: &[u8], dialect: &dialect::Dialect) -> Vec<String> {
+    header_columns(head, dialect).iter().map(|column| dialect.column(column).to_string()).collect()
+}
+
+/// Names of the schemas an upload can declare: `sales` and the `.toml`
+/// files in `schemas/`.
+fn available_schemas() -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir("schemas")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.strip_suffix(".toml").map(String::from))
+        .collect();
+    names.sort();
+    names.insert(0, "sales".to_string());
+    names
+}
+
+/// Checks the start of an upload, read in `dialect`: it must have data
+/// rows, columns that include every sales column and the schema's, and
+/// records with as many fields as there are columns. Failures are 422 with
+/// the line that broke the rule. Records are read from the first `complete`
+/// bytes, where the last whole line ends.
+fn check_csv_structure(filename: &str, head: &[u8], complete: usize, dialect: &dialect::Dialect) -> Result<(), ApiError> {
+    if !dialect.has_data_rows(head) {
+        return Err(ApiError::empty_csv(filename, head));
+    }
+    let head = &head[..complete];
+    let unreadable = |error: csv::Error| {
+        let mut api_error = ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_csv_structure", error.to_string())
+            .detail("filename", filename);
+        if let Some(position) = error.position() {
+            api_error = api_error.detail("line", position.line());
+        }
+        api_error
+    };
+    let mut reader = dialect.reader(true, head);
+    let headers = reader.headers().map_err(unreadable)?.clone();
+    let mut required: Vec<&str> = SALES_COLUMNS.to_vec();
+    required.extend(dialect.columns.iter().flatten().map(String::as_str).filter(|column| !SALES_COLUMNS.contains(column)));
+    let missing: Vec<&str> = required.into_iter().filter(|column| !headers.iter().any(|header| header == *column)).collect();
+    if !missing.is_empty() {
+        let mut error = ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "missing_columns",
+            format!("{} is missing the column(s) {}", filename, missing.join(", ")),
+        )
+        .detail("filename", filename)
+        .detail("missing", missing)
+        .detail("header", headers.iter().collect::<Vec<_>>());
+        // A whole header in one field was most likely split on another character
+        let separator = headers.get(0).filter(|_| headers.len() == 1).and_then(|header| header.chars().find(|c| matches!(c, ';' | '\t' | '|')));
+        if let Some(separator) = separator {
+            let delimiter = if separator == '\t' { "tab".to_string() } else { separator.to_string() };
+            error = error.detail("hint", format!("the header looks {:?}-separated; upload with ?delimiter={}", separator, delimiter));
+        }
+        return Err(error);
+    }
+    for record in reader.records().take(VALIDATED_RECORDS) {
+        let record = record.map_err(unreadable)?;
+        if record.len() != headers.len() {
+            let line = record.position().map_or(0, |position| position.line());
+            return Err(ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "invalid_csv_structure",
+                format!("line {} has {} fields but the header has {}", line, record.len(), headers.len()),
+            )
+            .detail("filename", filename)
+            .detail("line", line)
+            .detail("expected_fields", headers.len())
+            .detail("found_fields", record.len()));
+        }
+    }
+    Ok(())
+}
+
+/// Rewrites the upload at `path`, read in `dialect`, with `cipher`'s
+/// columns encrypted, through a second part file renamed over it. Rows
+/// too short to have a column are copied as they are, and with
+/// `keep_encrypted`, so are fields already encrypted, as a workspace
+/// artifact holds those it read from stored files.
+fn encrypt_upload(path: &str, dialect: &dialect::Dialect, cipher: &encryption::ColumnCipher, keep_encrypted: bool) -> std::io::Result<()> {
+    let source = std::fs::File::open(path)?;
+    let mut reader = dialect.reader(true, std::io::BufReader::new(source));
+    let headers = reader.headers()?.clone();
+    let encrypted: Vec<(usize, &str)> = cipher
+        .columns()
+        .iter()
+        .filter_map(|column| Some((headers.iter().position(|header| header == column)?, column.as_str())))
+        .collect();
+    
+    let mut part = PartFile { path: format!("{}.encrypted", path), keep: false };
+    let mut writer = dialect.writer(std::io::BufWriter::new(std::fs::File::create(&part.path)?));
+    if dialect.has_header {
+        writer.write_record(&dialect.file_header(&headers))?;
+    }
+    let mut fields = Vec::new();
+    for row in reader.byte_records() {
+        let row = row?;
+        fields.clear();
+        fields.extend(row.iter().map(<[u8]>::to_vec));
+        for &(index, column) in &encrypted {
+            let Some(field) = fields.get_mut(index) else {
+                continue;
+            };
+            if keep_encrypted && field.starts_with(encryption::PREFIX.as_bytes()) {
+                continue;
+            }
+            let value = std::str::from_utf8(field)
+                .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{} is not UTF-8 on line {}", column, row.position().map_or(0, |position| position.line()))))?;
+            *field = cipher.encrypt(column, value).into_bytes();
+        }
+        writer.write_record(&fields)?;
+    }
+    let output = writer.into_inner().map_err(|error| error.into_error())?;
+    output.into_inner().map_err(|error| error.into_error())?.sync_all()?;
+    std::fs::rename(&part.path, path)?;
+    part.keep = true;
+    Ok(())
+}
+
+/// An upload or rewritten file being written. The file is removed when
+/// this is dropped before it was renamed into place, including when the
+/// client disconnects and the request is dropped mid-stream.
+struct PartFile {
+    path: String,
+    keep: bool,
+}
+
+impl Drop for PartFile {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+async fn process_csv_file(
+    axum::extract::Path(filename): axum::extract::Path<String>,
+    query: Result<Query<ProcessQuery>, QueryRejection>,
+    fields: Result<Query<FieldsQuery>, QueryRejection>,
+    State(state): State<SharedState>,
+    headers: axum::http::HeaderMap,
+) -> Result<Response, ApiError> {
+    let Query(params) = query?;
+    let access = column_access(&state, &headers)?;
+    let fields = requested_fields(fields)?;
+    let wanted = |field: &str| fields.as_ref().is_none_or(|fields| fields.includes(field));
+    let samples = params.samples.unwrap_or(DEFAULT_SAMPLE_RECORDS);
+    if samples > MAX_SAMPLE_RECORDS {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_samples",
+            format!("samples must be between 0 and {}", MAX_SAMPLE_RECORDS),
+        )
+        .detail("samples", samples));
+    }
+    let file = FileId::resolve(&filename, &state).await?;
+    
+    let (token, _guard) = request_token(&state)?;
+    // Stores its metrics even when a read or parse error returns early
+    let sink = state.clone();
+    let mut timer = ScopedTimer::new(format!("Processing {}", file), move |metrics| {
+        // Nothing to report a poisoned lock to here, so the metrics are dropped
+        let _ = record_processing(&sink, metrics);
+    });
+    
+    let (parsed, shared) = load_records(&state, &file, &token, Some(&mut timer)).await?;
+    let ParsedFile { records, cancelled, .. } = parsed;
+    
+    timer.phase("serialize");
+    let serialization_failed = |error: serde_json::Error| {
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "serialization_failed", error.to_string()).detail("filename", file.as_str())
+    };
+    let metrics = if cancelled {
+        timer.cancelled()
+    } else {
+        timer.finish()
+    };
+    let summary = ProcessResponse {
+        filename: &file,
+        records_processed: records.len(),
+        processing_time_ms: metrics.duration.as_millis(),
+        records_per_second: metrics.records_per_second,
+        bytes_processed: metrics.bytes_processed,
+        mb_per_second: metrics.megabytes_per_second,
+        retries: metrics.retries,
+        phases_ms: wanted("phases_ms").then(|| metrics.phases_ms()),
+        throughput_samples: wanted("throughput_samples").then_some(metrics.throughput_samples.as_slice()),
+        cancelled,
+        shared_parse: shared,
+        sample_records: wanted("sample_records").then(|| sample_records(&records, samples, params.redact.unwrap_or(true), &access)),
+    };
+    let mut head = match &fields {
+        Some(fields) => {
+            let summary = serde_json::to_value(&summary).map_err(serialization_failed)?;
+            serde_json::to_vec(&sparse(summary, Some(fields))?)
+        }
+        None => serde_json::to_vec(&summary),
+    }
+    .map_err(serialization_failed)?;
+    if !params.records.unwrap_or(false) {
+        return Ok(([(header::CONTENT_TYPE, "application/json")], head).into_response());
+    }
+    
+    // Leave the object open and stream every record into a "records" array,
+    // a batch at a time from the cached rows, instead of building the whole
+    // body in memory next to them
+    head.pop();
+    head.extend_from_slice(b",\"records\":[");
+    let enricher = lock_state(&state)?.enrichment.clone();
+    let batches = (0..records.len().div_ceil(RECORD_BATCH_ROWS)).map(move |batch| {
+        let start = batch * RECORD_BATCH_ROWS;
+        let mut data = Vec::new();
+        for (index, record) in records.iter().enumerate().skip(start).take(RECORD_BATCH_ROWS) {
+            if index > 0 {
+                data.push(b',');
+            }
+            if enricher.is_empty() {
+                serde_json::to_writer(&mut data, record)?;
+            } else {
+                serde_json::to_writer(&mut data, &enricher.enrich(record))?;
+            }
+        }
+        Ok::<_, serde_json::Error>(Bytes::from(data))
+    });
+    let body = std::iter::once(Ok(Bytes::from(head)))
+        .chain(batches)
+        .chain(std::iter::once(Ok(Bytes::from_static(b"]}"))));
+    Ok(([(header::CONTENT_TYPE, "application/json")], Body::from_stream(futures::stream::iter(body))).into_response())
+}
+
+/// Body of GET /process, serialized straight from the parsed records
+/// rather than through a `serde_json::Value` copy of them. The optional
+/// fields are left out when `?fields=` does not ask for them.
+#[derive(Serialize)]
+struct ProcessResponse<'a> {
+    filename: &'a FileId,
+    records_processed: usize,
+    processing_time_ms: u128,
+    records_per_second: f64,
+    bytes_processed: Option<u64>,
+    mb_per_second: Option<f64>,
+    /// Reads repeated after transient errors; `None` when the file was
+    /// served from the cache.
+    retries: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    phases_ms: Option<serde_json::Map<String, serde_json::Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    throughput_samples: Option<&'a [ThroughputSample]>,
+    cancelled: bool,
+    shared_parse: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sample_records: Option<Vec<SalesRecord>>,
+}
+
+/// The first `count` records, with customer names masked when `redact` is
+/// set, so a preview does not hand out personal data by default, and
+/// encrypted columns as `access` allows.
+fn sample_records(records: &[SalesRecord], count: usize, redact: bool, access: &ColumnAccess) -> Vec<SalesRecord> {
+    records
+        .iter()
+        .take(count)
+        .map(|record| access.reveal(record))
+        .map(|mut record| {
+            if redact {
+                record.customer_name = "REDACTED".to_string();
+            }
+            record
+        })
+        .collect()
+}
+
+/// The `?fields=` of a request; `None` for the whole response.
+fn requested_fields(query: Result<Query<FieldsQuery>, QueryRejection>) -> Result<Option<fields::FieldSet>, ApiError> {
+    let Query(query) = query?;
+    let Some(text) = query.fields else {
+        return Ok(None);
+    };
+    fields::FieldSet::parse(&text).map_err(|message| ApiError::new(StatusCode::BAD_REQUEST, "invalid_fields", message).detail("fields", &text))
+}
+
+fn requested_filter(query: Result<Query<FilterQuery>, QueryRejection>) -> Result<Option<partitions::PartitionFilter>, ApiError> {
+    let Query(query) = query?;
+    partitions::PartitionFilter::new(query.region.as_deref(), query.from.as_deref(), query.to.as_deref()).map_err(|message| {
+        ApiError::new(StatusCode::BAD_REQUEST, "invalid_filter", message)
+            .detail("region", &query.region)
+            .detail("from", &query.from)
+            .detail("to", &query.to)
+    })
+}
+
+/// Cuts a response down to `fields`. 400 naming the fields the response
+/// has when one asked for is not among them.
+fn sparse(value: serde_json::Value, fields: Option<&fields::FieldSet>) -> Result<serde_json::Value, ApiError> {
+    let Some(fields) = fields else {
+        return Ok(value);
+    };
+    let available: Vec<String> = value.as_object().map(|object| object.keys().cloned().collect()).unwrap_or_default();
+    fields.select(value).map_err(|unknown| {
+        ApiError::new(StatusCode::BAD_REQUEST, "unknown_field", format!("the response has no field {}", unknown.join(", ")))
+            .detail("unknown", unknown)
+            .detail("available", available)
+    })
+}
+
+/// `value` as JSON, cut down to `fields` when there are any.
+fn sparse_json<T: Serialize>(value: &T, fields: Option<&fields::FieldSet>) -> Result<Response, ApiError> {
+    if fields.is_none() {
+        return Ok(Json(value).into_response());
+    }
+    let value = serde_json::to_value(value)
+        .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "serialization_failed", error.to_string()))?;
+    Ok(Json(sparse(value, fields)?).into_response())
+}
+
+/// Reads and parses `file` once for all the requests that ask for it while
+/// the parse runs: the first leads and records its phases on `timer`, the
+/// others wait for its result, so a large file is never parsed or cached
+/// twice at once. If the leader is dropped, a waiting request takes over.
+/// Returns whether the result came from another request's parse.
+async fn load_records(
+    state: &SharedState,
+    file: &FileId,
+    token: &CancellationToken,
+    mut timer: Option<&mut ScopedTimer>,
+) -> Result<(ParsedFile, bool), ApiError> {
+    // Joining a parse already running costs nothing more
+    if let Some(force) = cost::pending() {
+        let parsing = lock_state(state)?.parse_flights.contains_key(file);
+        if !parsing && cost_limits_set(state)? {
+            check_cost(state, cost::CostEstimate::parse(input_size(state, file, None).await?), force)?;
+        }
+    }
+    let flight = lock_state(state)?.parse_flights.entry(file.clone()).or_default().clone();
+    // Time spent waiting on another request's parse
+    if let Some(timer) = timer.as_deref_mut() {
+        timer.phase("wait");
+    }
+    let mut shared = true;
+    let result = flight
+        .get_or_init(|| {
+            shared = false;
+            async {
+                let start = std::time::Instant::now();
+                let result = parse_file(state, file, token, timer.as_deref_mut()).await;
+                check_alerts(state, file, &result, start.elapsed());
+                result
+            }
+        })
+        .await
+        .clone();
+    // Requests arriving from now on start a fresh parse
+    if let Ok(mut app_state) = state.lock() {
+        if app_state.parse_flights.get(file).is_some_and(|current| Arc::ptr_eq(current, &flight)) {
+            app_state.parse_flights.remove(file);
+        }
+    }
+    if let (Some(timer), true, Ok(parsed)) = (timer, shared, &result) {
+        timer.add_bytes(parsed.bytes);
+        timer.add_records(parsed.records.len());
+    }
+    result.map(|parsed| (parsed, shared))
+}
+
+async fn parse_file(
+    state: &SharedState,
+    file: &FileId,
+    token: &CancellationToken,
+    mut timer: Option<&mut ScopedTimer>,
+) -> Result<ParsedFile, ApiError> {
+    let file_path = file.path();
+    if let Some(timer) = timer.as_deref_mut() {
+        timer.phase("read");
+    }
+    // Taken before reading, so rows appended meanwhile are caught up on later
+    let fingerprint = file_fingerprint(file).await.ok();
+    let processing::Attempted { value: content, retries } = read_data_file(state, &file_path)
+        .await
+        .map_err(|error| error.detail("filename", file.as_str()))?;
+    if retries > 0 {
+        log!("🔁 Read {} after {} retr{}", file_path, retries, if retries == 1 { "y" } else { "ies" });
+    }
+    
+    if let Some(timer) = timer.as_deref_mut() {
+        timer.add_retries(retries);
+        timer.add_bytes(content.len());
+        timer.phase("parse");
+    }
+    let bytes = content.len();
+    
+    // Parsed on the pool; progress comes back as (rows, byte offset)
+    let (pool, cipher) = {
+        let app_state = lock_state(state)?;
+        (app_state.parser_pool.clone(), app_state.encryption.clone())
+    };
+    let dialect = file_dialect(state, file)?;
+    let extent = fingerprint.and_then(|fingerprint| incremental::Extent::of(content.as_bytes(), fingerprint, dialect.has_header));
+    let (progress, mut progress_updates) = tokio::sync::mpsc::unbounded_channel();
+    let parse_token = token.clone();
+    let parse_file = file.clone();
+    let mut parse = std::pin::pin!(pool.run(move || parse_records(&content, &parse_file, &dialect, cipher.as_deref(), &parse_token, progress)));
+    let mut counted = 0;
+    let result = loop {
+        tokio::select! {
+            result = &mut parse => break result,
+            Some((rows, byte)) = progress_updates.recv() => {
+                if let Some(timer) = timer.as_deref_mut() {
+                    timer.add_records(rows - counted);
+                    counted = rows;
+                    timer.record_progress(byte);
+                }
+            }
+        }
+    };
+    let (records, cancelled) = result
+        .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "parse_failed", error.to_string()).detail("filename", file.as_str()))??;
+    if let Some(timer) = timer.as_deref_mut() {
+        timer.add_records(records.len() - counted);
+    }
+    
+    // Cache the data (partial results are never cached)
+    let records = Arc::new(records);
+    if let Some(timer) = timer {
+        timer.phase("cache");
+    }
+    if !cancelled {
+        cache_parsed(state, file, &records).await?;
+        let mut app_state = lock_state(state)?;
+        match extent {
+            Some(extent) => app_state.parsed_extents.insert(file.clone(), extent),
+            None => app_state.parsed_extents.remove(file),
+        };
+        app_state.lineages.insert(file.clone(), provenance::Lineage::parsed(records.len(), request_log::current()));
+    }
+    Ok(ParsedFile { records, bytes, cancelled })
+}
+
+/// Keeps a parsed file's records in the cache, as they are or, with the
+/// columnar layout, converted on the parser pool.
+async fn cache_parsed(state: &SharedState, file: &FileId, records: &Arc<Vec<SalesRecord>>) -> Result<(), ApiError> {
+    let layout = lock_state(state)?.cache_layout;
+    match layout {
+        CacheLayout::Rows => {
+            lock_state(state)?.cached_data.insert(file.clone(), records.clone());
+        }
+        #[cfg(feature = "columnar")]
+        CacheLayout::Columnar => {
+            let pool = lock_state(state)?.parser_pool.clone();
+            let converted = records.clone();
+            let columns = pool
+                .run(move || columnar::ColumnarRecords::from_records(&converted))
+                .await
+                .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "parse_failed", error.to_string()))?
+                .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "cache_failed", error.to_string()).detail("filename", file.as_str()))?;
+            lock_state(state)?.columnar_data.insert(file.clone(), Arc::new(columns));
+        }
+    }
+    Ok(())
+}
+
+/// The dialect `file` was uploaded with; files that were not uploaded are
+/// comma-separated with a header.
+fn file_dialect(state: &SharedState, file: &FileId) -> Result<dialect::Dialect, ApiError> {
+    Ok(lock_state(state)?
+        .uploads
+        .values()
+        .find(|upload| upload.file_id == *file)
+        .map(|upload| upload.dialect.clone())
+        .unwrap_or_default())
+}
+
+/// 422 for a file uploaded as anything but comma-separated with a header,
+/// from the endpoints that read files only that way.
+fn require_standard_dialect(state: &SharedState, file: &FileId) -> Result<(), ApiError> {
+    let dialect = file_dialect(state, file)?;
+    if dialect.is_standard() {
+        return Ok(());
+    }
+    Err(ApiError::new(
+        StatusCode::UNPROCESSABLE_ENTITY,
+        "unsupported_dialect",
+        format!("{} was uploaded with another delimiter or without a header, which this endpoint cannot read", file),
+    )
+    .detail("filename", file.as_str())
+    .detail("dialect", dialect))
+}
+
+/// Deserializes `content`, written in `dialect`, on a parser thread,
+/// sending `(rows, byte offset)` to `progress` every
+/// [`PROGRESS_INTERVAL_ROWS`] rows. Returns the records and whether `token`
+/// stopped the parse.
+fn parse_records(
+    content: &str,
+    file: &FileId,
+    dialect: &dialect::Dialect,
+    cipher: Option<&encryption::ColumnCipher>,
+    token: &CancellationToken,
+    progress: tokio::sync::mpsc::UnboundedSender<(usize, u64)>,
+) -> Result<(Vec<SalesRecord>, bool), ApiError> {
+    let mut reader = dialect.reader(false, content.as_bytes());
+    let mut records = Vec::new();
+    let mut cancelled = false;
+    
+    let mut rows = reader.deserialize();
+    while let Some(result) = rows.next() {
+        if token.is_cancelled() {
+            cancelled = true;
+            break;
+        }
+        let mut record: SalesRecord = match result {
+            Ok(record) => record,
+            Err(error) => {
+                // Count the rest of the file too, so clients and alert rules see how much of it is bad
+                let mut total_rows = records.len() + 1;
+                let mut invalid_rows = 1;
+                for rest in rows.by_ref() {
+                    total_rows += 1;
+                    invalid_rows += rest.is_err() as usize;
+                }
+                return Err(ApiError::invalid_csv(file.as_str(), &error)
+                    .detail("rows", total_rows)
+                    .detail("invalid_rows", invalid_rows));
+            }
+        };
+        if let Some(cipher) = cipher {
+            cipher
+                .decrypt_record(&mut record)
+                .map_err(|error| ApiError::decryption_failed(file.as_str(), error).detail("record", records.len() + 1))?;
+        }
+        records.push(record);
+        if records.len() % PROGRESS_INTERVAL_ROWS == 0 {
+            // Nobody listening only means nobody is timing this parse
+            let _ = progress.send((records.len(), rows.reader().position().byte()));
+        }
+    }
+    
+    if records.is_empty() && !cancelled {
+        return Err(ApiError::empty_csv(file.as_str(), content.as_bytes()));
+    }
+    Ok((records, cancelled))
+}
+
+/// The parser pool, `PARSER_THREADS` threads or one per CPU.
+fn parser_pool() -> worker_pool::WorkerPool {
+    let threads = match std::env::var("PARSER_THREADS") {
+        Ok(threads) => match threads.trim().parse::<usize>() {
+            Ok(threads) if threads > 0 => threads,
+            _ => {
+                println!("⚠️  Ignoring PARSER_THREADS={}: expected a positive number of threads", threads);
+                num_cpus::get()
+            }
+        },
+        Err(_) => num_cpus::get(),
+    };
+    worker_pool::WorkerPool::new("csv-parser", threads).expect("starting the parser threads")
+}
+
+/// The cost guardrails from `COST_MAX_ROWS` (rows read), `COST_MAX_MEMORY`
+/// (e.g. `512MB`) and `COST_MAX_OUTPUT_ROWS`, all unset by default. With
+/// `COST_FORCE=off`, `?force=true` no longer overrides them.
+fn cost_limits() -> cost::CostLimits {
+    let count = |name: &str| match std::env::var(name) {
+        Ok(value) => match value.trim().replace('_', "").parse::<u64>() {
+            Ok(count) => Some(count),
+            Err(_) => {
+                println!("⚠️  Ignoring {}={}: expected a number of rows", name, value);
+                None
+            }
+        },
+        Err(_) => None,
+    };
+    let max_memory_bytes = match std::env::var("COST_MAX_MEMORY") {
+        Ok(value) => parse_size(&value).map_err(|error| println!("⚠️  Ignoring COST_MAX_MEMORY: {}", error)).ok(),
+        Err(_) => None,
+    };
+    let allow_force = match std::env::var("COST_FORCE") {
+        Ok(value) if value.trim().eq_ignore_ascii_case("off") || value.trim().eq_ignore_ascii_case("false") => false,
+        Ok(value) if value.trim().eq_ignore_ascii_case("on") || value.trim().eq_ignore_ascii_case("true") => true,
+        Ok(value) => {
+            println!("⚠️  Ignoring COST_FORCE={}: expected on or off", value);
+            true
+        }
+        Err(_) => true,
+    };
+    let limits = cost::CostLimits {
+        max_rows_read: count("COST_MAX_ROWS"),
+        max_memory_bytes,
+        max_output_rows: count("COST_MAX_OUTPUT_ROWS"),
+        allow_force,
+    };
+    if limits.is_set() {
+        let limit = |value: Option<u64>| value.map_or("none".to_string(), |value| value.to_string());
+        println!(
+            "🛑 Refusing parses, sorts and joins estimated past {} rows read, {} bytes in memory or {} rows out{}",
+            limit(limits.max_rows_read),
+            limit(limits.max_memory_bytes),
+            limit(limits.max_output_rows),
+            if limits.allow_force { " unless ?force=true" } else { "" }
+        );
+    }
+    limits
+}
+
+fn cost_limits_set(state: &SharedState) -> Result<bool, ApiError> {
+    Ok(lock_state(state)?.cost_guard.limits.is_set())
+}
+
+/// Whether an analysis of `file` can be answered from memory: its records
+/// are cached or its statistics are current.
+async fn precomputed(state: &SharedState, file: &FileId) -> Result<bool, ApiError> {
+    let fingerprint = file_fingerprint(file).await?;
+    let statistics = current_statistics(state, file, fingerprint)?;
+    Ok(statistics.is_some() || lock_state(state)?.cached_data.contains_key(file))
+}
+
+/// What is known of a file's size before reading it: its rows from the
+/// record cache, its statistics or the catalog when current, or else
+/// estimated from a few sampled windows of it. With `key`, the distinct
+/// values of that column too, when its statistics keep them.
+async fn input_size(state: &SharedState, file: &FileId, key: Option<&str>) -> Result<cost::InputSize, ApiError> {
+    let bytes = fs::metadata(file.path()).await.map_err(|error| ApiError::read_failed(&file.path(), error))?.len();
+    let statistics = current_statistics(state, file, file_fingerprint(file).await?)?;
+    let key_values = key.zip(statistics.as_ref()).and_then(|(key, statistics)| match key {
+        // Ids are unique, one per row
+        "id" => Some(statistics.rows as u64),
+        key => statistics.groups(key).map(|values| values.len() as u64),
+    });
+    let known = {
+        let app_state = lock_state(state)?;
+        let cached = app_state.cached_data.get(file).map(|records| (records.len() as u64, "cache"));
+        let counted = statistics.as_ref().map(|statistics| (statistics.rows as u64, "statistics"));
+        let listed = app_state.catalog.get(file).filter(|entry| entry.size_bytes == bytes).map(|entry| (u64::from(entry.rows), "catalog"));
+        cached.or(counted).or(listed)
+    };
+    let (rows, source) = match known {
+        Some(known) => known,
+        None => {
+            let (path, delimiter) = (file.path(), file_dialect(state, file)?.delimiter as u8);
+            let estimated = tokio::task::spawn_blocking(move || row_count::estimate(&path, delimiter))
+                .await
+                .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "task_failed", error.to_string()))?
+                .map_err(|error| ApiError::read_failed(&file.path(), error))?;
+            (estimated.rows, if estimated.exact { "count" } else { "estimate" })
+        }
+    };
+    Ok(cost::InputSize { name: file.to_string(), rows, bytes, source, key_values })
+}
+
+/// Refuses an operation whose estimated cost goes over the limits, unless
+/// forced where that is allowed. The error carries the estimate and the
+/// limits it goes over, so a client can decide whether to force it.
+fn check_cost(state: &SharedState, estimate: cost::CostEstimate, force: bool) -> Result<(), ApiError> {
+    let mut app_state = lock_state(state)?;
+    let guard = &mut app_state.cost_guard;
+    let exceeded = guard.limits.exceeded(&estimate);
+    if exceeded.is_empty() {
+        return Ok(());
+    }
+    let over: Vec<String> = exceeded.iter().map(|exceeded| format!("{} {} > {}", exceeded.limit, exceeded.estimate, exceeded.max)).collect();
+    if force && guard.limits.allow_force {
+        guard.forced += 1;
+        log!("⚠️  Running a {} past the cost limits, as forced: {}", estimate.operation, over.join(", "));
+        return Ok(());
+    }
+    guard.rejected += 1;
+    let allow_force = guard.limits.allow_force;
+    let mut message = format!("the estimated cost of this {} goes past the server's limits: {}", estimate.operation, over.join(", "));
+    if allow_force {
+        message.push_str("; repeat it with ?force=true to run it anyway");
+    }
+    Err(ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "cost_limit_exceeded", message)
+        .detail("estimate", estimate)
+        .detail("exceeded", exceeded)
+        .detail("force_allowed", allow_force))
+}
+
+async fn analyze_csv(
+    axum::extract::Path(filename): axum::extract::Path<String>,
+    query: Result<Query<AnalysisQuery>, QueryRejection>,
+    fields: Result<Query<FieldsQuery>, QueryRejection>,
+    filter: Result<Query<FilterQuery>, QueryRejection>,
+    cost_query: Result<Query<CostQuery>, QueryRejection>,
+    State(state): State<SharedState>,
+) -> Result<Response, ApiError> {
+    let Query(params) = query?;
+    let Query(cost_query) = cost_query?;
+    let fields = requested_fields(fields)?;
+    let filter = requested_filter(filter)?;
+    let rate = applied_rate(&state, params.currency.as_deref())?;
+    let file = FileId::resolve(&filename, &state).await?;
+    if cost_limits_set(&state)? {
+        // A filtered analysis goes through the records, statistics or not
+        let precomputed = filter.is_none() && precomputed(&state, &file).await?;
+        let estimate = cost::CostEstimate::analyze(input_size(&state, &file, None).await?, precomputed);
+        check_cost(&state, estimate, cost_query.force)?;
+    }
+    let (token, _guard) = request_token(&state)?;
+    let result = cost::checked(async {
+        match filter {
+            Some(filter) => analyze_filtered(&state, &file, &filter, &token, params.limit).await,
+            None => analyze_file(&state, &file, &token, params.limit).await,
+        }
+    })
+    .await?;
+    sparse_json(&result.converted(rate), fields.as_ref())
+}
+
+/// The configured exchange rates: `CURRENCY_RATES` over `BASE_CURRENCY`,
+/// until rates from `CURRENCY_RATES_URL` arrive.
+fn currency_rates() -> currency::CurrencyRates {
+    currency::CurrencyRates::from_env().unwrap_or_else(|error| {
+        println!("⚠️  Ignoring CURRENCY_RATES: {}", error);
+        currency::CurrencyRates::base_only(&std::env::var("BASE_CURRENCY").unwrap_or_else(|_| "USD".to_string()))
+    })
+}
+
+/// The rate for `?currency=`, `None` when it was not given.
+fn applied_rate(state: &SharedState, currency: Option<&str>) -> Result<Option<currency::AppliedRate>, ApiError> {
+    let Some(currency) = currency else {
+        return Ok(None);
+    };
+    let rates = lock_state(state)?.currency.clone();
+    match rates.rate(currency) {
+        Some(rate) => Ok(Some(rate)),
+        None => Err(ApiError::new(StatusCode::BAD_REQUEST, "unknown_currency", format!("no exchange rate from {} to {}", rates.base, currency))
+            .detail("currency", currency)
+            .detail("available", rates.currencies())),
+    }
+}
+
+/// The exchange rates `?currency=` converts with.
+async fn get_currency_rates(State(state): State<SharedState>) -> Result<Json<currency::CurrencyRates>, ApiError> {
+    let rates = lock_state(&state)?.currency.clone();
+    Ok(Json((*rates).clone()))
+}
+
+/// Fetches rates from `CURRENCY_RATES_URL` at startup and then every
+/// `CURRENCY_REFRESH` (1h by default). A failed fetch keeps the rates
+/// already in use. Does nothing without a URL.
+fn spawn_currency_refresh(state: SharedState, shutdown: CancellationToken) {
+    let Ok(url) = std::env::var("CURRENCY_RATES_URL") else {
+        return;
+    };
+    let interval_seconds = match std::env::var("CURRENCY_REFRESH") {
+        Ok(interval) => parse_interval(&interval).unwrap_or_else(|| {
+            println!("⚠️  Ignoring CURRENCY_REFRESH={}: expected seconds, or a number followed by s, m, h or d", interval);
+            DEFAULT_CURRENCY_REFRESH_SECONDS
+        }),
+        Err(_) => DEFAULT_CURRENCY_REFRESH_SECONDS,
+    };
+    println!("💱 Fetching exchange rates from {} every {}s", url, interval_seconds);
+    
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(interval_seconds));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = ticker.tick() => {}
+            }
+            let Ok(base) = lock_state(&state).map(|app_state| app_state.currency.base.clone()) else {
+                continue;
+            };
+            match currency::CurrencyRates::fetch(&url, &base).await {
+                Ok(rates) => {
+                    if let Ok(mut app_state) = state.lock() {
+                        app_state.currency = Arc::new(rates);
+                    }
+                }
+                Err(error) => println!("⚠️  Could not fetch exchange rates from {}: {}", url, error),
+            }
+        }
+    });
+}
+
+/// A file's records from the cache, or from a parse shared with concurrent
+/// requests. Returns whether the parse was cancelled.
+async fn cached_records(
+    state: &SharedState,
+    file: &FileId,
+    token: &CancellationToken,
+) -> Result<(Arc<Vec<SalesRecord>>, bool), ApiError> {
+    if let Ok(fingerprint) = file_fingerprint(file).await {
+        catch_up(state, file, fingerprint).await?;
+    }
+    let cached = lock_state(state)?.cached_data.get(file).cloned();
+    #[cfg(feature = "columnar")]
+    if cached.is_none() {
+        if let Some(columns) = cached_columns(state, file)? {
+            let pool = lock_state(state)?.parser_pool.clone();
+            let records = pool
+                .run(move || columns.to_records())
+                .await
+                .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "task_failed", error.to_string()))?;
+            return Ok((Arc::new(records), false));
+        }
+    }
+    match cached {
+        Some(records) => Ok((records, false)),
+        None => {
+            let (parsed, _) = load_records(state, file, token, None).await?;
+            Ok((parsed.records, parsed.cancelled))
+        }
+    }
+}
+
+/// The analysis of the rows `filter` selects. Statistics and snapshots
+/// cover whole files, so the rows are read: from the selected partitions
+/// when the file is partitioned, otherwise from the whole file.
+async fn analyze_filtered(
+    state: &SharedState,
+    file: &FileId,
+    filter: &partitions::PartitionFilter,
+    token: &CancellationToken,
+    limit: Option<usize>,
+) -> Result<AnalysisResult, ApiError> {
+    let start = std::time::Instant::now();
+    let (records, cancelled, scan) = filtered_records(state, file, filter, token).await?;
+    let mut result = analyze_records(&records, token, limit, start);
+    result.cancelled |= cancelled;
+    result.partitions = scan;
+    Ok(result)
+}
+
+/// The rows of a file `filter` selects, and what was read of its
+/// partitions when it is partitioned. An unpartitioned file is filtered
+/// from the record cache.
+async fn filtered_records(
+    state: &SharedState,
+    file: &FileId,
+    filter: &partitions::PartitionFilter,
+    token: &CancellationToken,
+) -> Result<(Vec<SalesRecord>, bool, Option<partitions::PartitionScan>), ApiError> {
+    if let Some((dir, dataset)) = current_dataset(file).await? {
+        let (pool, cipher) = {
+            let app_state = lock_state(state)?;
+            (app_state.parser_pool.clone(), app_state.encryption.clone())
+        };
+        let (records, scan) = read_partitions(&pool, cipher, dir, dataset, filter.clone()).await?;
+        return Ok((records, false, Some(scan)));
+    }
+    let (records, cancelled) = cached_records(state, file, token).await?;
+    let selected = records.iter().filter(|record| filter.matches_record(record)).cloned().collect();
+    Ok((selected, cancelled, None))
+}
+
+/// Where a file's partitions go: under `data/`, named after the file
+/// without `.csv`.
+fn dataset_dir(file: &FileId) -> std::path::PathBuf {
+    let name = file.as_str();
+    std::path::Path::new(PARTITIONS_DIR).join(name.strip_suffix(".csv").unwrap_or(name))
+}
+
+/// A file's partitions, unless it was never partitioned or has changed
+/// since.
+async fn current_dataset(file: &FileId) -> Result<Option<(std::path::PathBuf, partitions::Dataset)>, ApiError> {
+    let dir = dataset_dir(file);
+    let manifest = dir.join(partitions::MANIFEST);
+    let loaded = {
+        let dir = dir.clone();
+        tokio::task::spawn_blocking(move || partitions::load(&dir).map_err(|error| error.to_string()))
+            .await
+            .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "task_failed", error.to_string()))?
+            .map_err(|message| {
+                ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "read_failed", format!("cannot read {}: {}", manifest.display(), message))
+                    .detail("path", manifest.display().to_string())
+            })?
+    };
+    let Some(dataset) = loaded else {
+        return Ok(None);
+    };
+    if dataset.source_fingerprint != file_fingerprint(file).await? {
+        log!("⚠️  {} changed since it was partitioned; reading it whole", file);
+        return Ok(None);
+    }
+    Ok(Some((dir, dataset)))
+}
+
+/// Reads the partitions of `dataset` that `filter` selects, on the pool.
+async fn read_partitions(
+    pool: &worker_pool::WorkerPool,
+    cipher: Option<Arc<encryption::ColumnCipher>>,
+    dir: std::path::PathBuf,
+    dataset: partitions::Dataset,
+    filter: partitions::PartitionFilter,
+) -> Result<(Vec<SalesRecord>, partitions::PartitionScan), ApiError> {
+    let display = dir.display().to_string();
+    pool.run(move || {
+        let (selected, pruned) = dataset.prune(&filter);
+        let mut scan = partitions::PartitionScan { scanned: selected.len(), pruned, bytes_read: 0 };
+        let mut records = Vec::new();
+        for partition in selected {
+            records.extend(partitions::read(&dir, &dataset, partition).map_err(|error| error.to_string())?);
+            scan.bytes_read += partition.bytes;
+        }
+        if let Some(cipher) = cipher {
+            for record in &mut records {
+                cipher.decrypt_record(record).map_err(|(column, error)| format!("{}: {}", column, error))?;
+            }
+        }
+        Ok::<_, String>((records, scan))
+    })
+    .await
+    .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "task_failed", error.to_string()))?
+    .map_err(|message| {
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "read_failed", format!("cannot read the partitions in {}: {}", display, message))
+            .detail("path", &display)
+    })
+}
+
+/// Explodes a file into partitions by region and month, replacing any
+/// earlier partitioning of it.
+async fn write_partitions(
+    state: &SharedState,
+    file: &FileId,
+    format: partitions::PartitionFormat,
+    token: &CancellationToken,
+) -> Result<partitions::Dataset, ApiError> {
+    let fingerprint = file_fingerprint(file).await?;
+    let (records, cancelled) = cached_records(state, file, token).await?;
+    if cancelled {
+        return Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "cancelled", "the server is shutting down").detail("filename", file.as_str()));
+    }
+    let (pool, cipher) = {
+        let app_state = lock_state(state)?;
+        (app_state.parser_pool.clone(), app_state.encryption.clone())
+    };
+    let mut timer = PerformanceTimer::new(format!("Partitioning {}", file));
+    let dir = dataset_dir(file);
+    let (written_dir, name) = (dir.clone(), file.as_str().to_string());
+    let dataset = pool
+        .run(move || {
+            // Partitions are stored files too
+            let records = match cipher {
+                Some(cipher) => Arc::new(records.iter().map(|record| cipher.encrypt_record(record)).collect()),
+                None => records,
+            };
+            partitions::write(&records, &written_dir, format, &name, fingerprint).map_err(|error| error.to_string())
+        })
+        .await
+        .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "task_failed", error.to_string()))?
+        .map_err(|message| {
+            ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "storage_failed", format!("cannot write the partitions of {}: {}", file, message))
+                .detail("path", dir.display().to_string())
+        })?;
+    timer.add_bytes(dataset.bytes as usize);
+    let metrics = timer.finish(dataset.rows);
+    record_processing(state, metrics)?;
+    log!("🗂️  Partitioned {} into {} partition(s) under {}", file, dataset.partitions.len(), dir.display());
+    Ok(dataset)
+}
+
+fn partition_format(requested: Option<&str>) -> Result<partitions::PartitionFormat, ApiError> {
+    let Some(requested) = requested else {
+        return Ok(partitions::PartitionFormat::Csv);
+    };
+    partitions::PartitionFormat::parse(requested).map_err(|message| {
+        ApiError::new(StatusCode::BAD_REQUEST, "invalid_format", message)
+            .detail("format", requested)
+            .detail("supported", ["csv", "parquet"])
+    })
+}
+
+/// Explodes a file into `data/<file>/region=<region>/month=<YYYY-MM>/`,
+/// after which filtered /analyze and /aggregate requests read only the
+/// partitions they select.
+async fn create_partitions(
+    axum::extract::Path(filename): axum::extract::Path<String>,
+    query: Result<Query<PartitionQuery>, QueryRejection>,
+    State(state): State<SharedState>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let Query(params) = query?;
+    let format = partition_format(params.format.as_deref())?;
+    let file = FileId::resolve(&filename, &state).await?;
+    let (token, _guard) = request_token(&state)?;
+    let start = std::time::Instant::now();
+    let dataset = write_partitions(&state, &file, format, &token).await?;
+    
+    Ok(Json(serde_json::json!({
+        "filename": file,
+        "dir": dataset_dir(&file),
+        "format": dataset.format,
+        "partitions": dataset.partitions.len(),
+        "rows": dataset.rows,
+        "bytes": dataset.bytes,
+        "processing_time_ms": start.elapsed().as_millis()
+    })))
+}
+
+/// The partitioned files under `data/`.
+async fn list_partitions() -> Result<Json<serde_json::Value>, ApiError> {
+    let datasets = tokio::task::spawn_blocking(|| partitions::list(std::path::Path::new(PARTITIONS_DIR)))
+        .await
+        .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "task_failed", error.to_string()))?;
+    let datasets: Vec<serde_json::Value> = datasets
+        .iter()
+        .map(|dataset| {
+            serde_json::json!({
+                "filename": dataset.file,
+                "format": dataset.format,
+                "partitions": dataset.partitions.len(),
+                "rows": dataset.rows,
+                "bytes": dataset.bytes,
+                "created_at": dataset.created_at
+            })
+        })
+        .collect();
+    Ok(Json(serde_json::json!({
+        "count": datasets.len(),
+        "datasets": datasets
+    })))
+}
+
+/// A file's records from the columnar cache, when it holds them.
+#[cfg(feature = "columnar")]
+fn cached_columns(state: &SharedState, file: &FileId) -> Result<Option<Arc<columnar::ColumnarRecords>>, ApiError> {
+    Ok(lock_state(state)?.columnar_data.get(file).cloned())
+}
+
+async fn analyze_file(
+    state: &SharedState,
+    file: &FileId,
+    token: &CancellationToken,
+    limit: Option<usize>,
+) -> Result<AnalysisResult, ApiError> {
+    let start = std::time::Instant::now();
+    let fingerprint = file_fingerprint(file).await?;
+    catch_up(state, file, fingerprint).await?;
+    let hash = file_hash(state, file, fingerprint).await?;
+    let key = match limit {
+        Some(limit) => format!("analyze-limit-{}", limit),
+        None => "analyze".to_string(),
+    };
+    let store = lock_state(state)?.snapshots.clone();
+    let snapshot = {
+        let (store, key) = (store.clone(), key.clone());
+        tokio::task::spawn_blocking(move || store.load::<AnalysisResult>(hash, &key)).await.ok().flatten()
+    };
+    if let Some(mut result) = snapshot {
+        result.processing_time_ms = start.elapsed().as_millis();
+        result.from_statistics = false;
+        result.from_snapshot = true;
+        return Ok(result);
+    }
+    
+    let answered = current_statistics(state, file, fingerprint)?.and_then(|statistics| analyze_statistics(&statistics, limit, start));
+    // Without statistics, the columnar cache is the next fastest
+    #[cfg(feature = "columnar")]
+    let answered = match (answered, cached_columns(state, file)?) {
+        (None, Some(columns)) => {
+            let pool = lock_state(state)?.parser_pool.clone();
+            let result = pool
+                .run(move || analyze_columns(&columns, limit, start))
+                .await
+                .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "task_failed", error.to_string()))?;
+            Some(result)
+        }
+        (answered, _) => answered,
+    };
+    let result = match answered {
+        Some(result) => result,
+        None => {
+            let (records, cancelled) = cached_records(state, file, token).await?;
+            let mut result = analyze_records(&records, token, limit, start);
+            result.cancelled |= cancelled;
+            if !result.cancelled {
+                compute_statistics(state, file, fingerprint, records);
+            }
+            result
+        }
+    };
+    if !result.cancelled {
+        let snapshot = serde_json::to_value(&result).unwrap_or_default();
+        tokio::task::spawn_blocking(move || {
+            if let Err(error) = store.save(hash, &key, &snapshot) {
+                log!("⚠️  Could not store the analysis snapshot {}: {}", store.path(hash, &key).display(), error);
+            }
+        });
+    }
+    Ok(result)
+}
+
+/// The content hash of a file, read again only when its fingerprint moved
+/// on. A file seen to have changed loses what was derived from its old
+/// contents first.
+async fn file_hash(state: &SharedState, file: &FileId, fingerprint: u64) -> Result<u64, ApiError> {
+    let (known, pool) = {
+        let app_state = lock_state(state)?;
+        (app_state.hashed_files.get(file).copied(), app_state.parser_pool.clone())
+    };
+    match known {
+        Some(known) if known.fingerprint == fingerprint => return Ok(known.hash),
+        Some(_) => {
+            forget_file(state, file).await;
+        }
+        None => {}
+    }
+    
+    let path = file.path();
+    let hash = pool
+        .run(move || snapshots::content_hash(&path))
+        .await
+        .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "parse_failed", error.to_string()))?
+        .map_err(|error| ApiError::read_failed(&file.path(), error))?;
+    lock_state(state)?.hashed_files.insert(file.clone(), HashedFile { fingerprint, hash });
+    Ok(hash)
+}
+
+/// Drops everything derived from a file's contents: cached records, column
+/// statistics, and the snapshots of its old hash unless another file has
+/// the same contents.
+async fn forget_file(state: &SharedState, file: &FileId) -> usize {
+    if let Ok(mut app_state) = lock_state(state) {
+        app_state.cached_data.remove(file);
+        #[cfg(feature = "columnar")]
+        app_state.columnar_data.remove(file);
+        app_state.parsed_extents.remove(file);
+        app_state.lineages.remove(file);
+        app_state.column_stats.remove(file);
+        app_state.bloom_filters.remove(file);
+        app_state.search_indexes.remove(file);
+    }
+    forget_snapshots(state, file).await
+}
+
+/// Drops a file's content hash, and the snapshots of that hash unless
+/// another file has the same contents.
+async fn forget_snapshots(state: &SharedState, file: &FileId) -> usize {
+    let (forgotten, store) = {
+        let Ok(mut app_state) = lock_state(state) else {
+            return 0;
+        };
+        let forgotten = app_state.hashed_files.remove(file);
+        let shared = forgotten.is_some_and(|forgotten| app_state.hashed_files.values().any(|other| other.hash == forgotten.hash));
+        (forgotten.filter(|_| !shared), app_state.snapshots.clone())
+    };
+    let Some(forgotten) = forgotten else {
+        return 0;
+    };
+    match tokio::task::spawn_blocking(move || store.remove(forgotten.hash)).await {
+        Ok(Ok(removed)) => removed,
+        Ok(Err(error)) => {
+            log!("⚠️  Could not remove the snapshots of {}: {}", file, error);
+            0
+        }
+        Err(_) => 0,
+    }
+}
+
+/// Brings what is cached of a file up to date with rows appended to it
+/// since it was parsed: only the bytes past its parsed extent are read and
+/// parsed, and their rows are added to the cached records and merged into
+/// its column statistics and Bloom filters. Snapshots of the old contents are dropped. A file
+/// changed any other way loses its extent and is left to the usual
+/// invalidation. Returns the rows added, `None` when nothing was caught up.
+async fn catch_up(state: &SharedState, file: &FileId, fingerprint: u64) -> Result<Option<usize>, ApiError> {
+    let catch_up_lock = lock_state(state)?.catch_up_lock.clone();
+    let _catching_up = catch_up_lock.lock().await;
+    let Some(extent) = lock_state(state)?.parsed_extents.get(file).cloned() else {
+        return Ok(None);
+    };
+    if extent.fingerprint == fingerprint {
+        return Ok(None);
+    }
+    let path = file.path();
+    let growth = {
+        let (extent, path) = (extent.clone(), path.clone());
+        tokio::task::spawn_blocking(move || extent.growth(std::path::Path::new(&path)))
+            .await
+            .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "task_failed", error.to_string()))?
+    };
+    let appended = match growth {
+        Ok(incremental::Growth::Appended(appended)) => appended,
+        Ok(incremental::Growth::Changed) | Err(_) => {
+            lock_state(state)?.parsed_extents.remove(file);
+            return Ok(None);
+        }
+    };
+    
+    let mut timer = PerformanceTimer::new(format!("Incremental parse of {}", file));
+    timer.add_bytes(appended.len());
+    let records = match incremental::has_rows(&appended) {
+        true => {
+            let (pool, cipher) = {
+                let app_state = lock_state(state)?;
+                (app_state.parser_pool.clone(), app_state.encryption.clone())
+            };
+            let dialect = file_dialect(state, file)?;
+            let (document, parse_file, token) = (extent.document(&appended), file.clone(), CancellationToken::new());
+            let (progress, _) = tokio::sync::mpsc::unbounded_channel();
+            let (records, _) = pool
+                .run(move || parse_records(&document, &parse_file, &dialect, cipher.as_deref(), &token, progress))
+                .await
+                .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "parse_failed", error.to_string()).detail("filename", file.as_str()))??;
+            records
+        }
+        false => Vec::new(),
+    };
+    let rows = records.len();
+    
+    // Taken out of the cache while they grow, so they are not copied when
+    // no request holds them
+    let (cached, statistics, filters, pool) = {
+        let mut app_state = lock_state(state)?;
+        app_state.search_indexes.remove(file);
+        let statistics = app_state.column_stats.remove(file).filter(|entry| entry.fingerprint == extent.fingerprint);
+        let filters = app_state.bloom_filters.remove(file).filter(|entry| entry.fingerprint == extent.fingerprint);
+        (app_state.cached_data.remove(file), statistics, filters, app_state.parser_pool.clone())
+    };
+    let records = Arc::new(records);
+    let appended_records = records.clone();
+    let (cached, statistics, filters) = pool
+        .run(move || {
+            let cached = cached.map(|mut cached| {
+                Arc::make_mut(&mut cached).extend(appended_records.iter().cloned());
+                cached
+            });
+            let statistics = statistics.map(|mut entry| {
+                Arc::make_mut(&mut entry.statistics).merge(column_stats::ColumnStatistics::compute(&appended_records));
+                entry
+            });
+            // Filters grown past their size are left to be rebuilt
+            let filters = filters
+                .map(|mut entry| {
+                    Arc::make_mut(&mut entry.filters).extend(&appended_records);
+                    entry
+                })
+                .filter(|entry| !entry.filters.saturated());
+            (cached, statistics, filters)
+        })
+        .await
+        .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "task_failed", error.to_string()))?;
+    #[cfg(feature = "columnar")]
+    let columns = lock_state(state)?.columnar_data.remove(file);
+    #[cfg(feature = "columnar")]
+    let columns = match columns {
+        Some(mut columns) => pool
+            .run(move || Arc::make_mut(&mut columns).append(&records).ok().map(|_| columns))
+            .await
+            .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "task_failed", error.to_string()))?,
+        None => None,
+    };
+    
+    let metrics = timer.finish(rows);
+    {
+        let mut app_state = lock_state(state)?;
+        if let Some(cached) = cached {
+            app_state.cached_data.insert(file.clone(), cached);
+        }
+        #[cfg(feature = "columnar")]
+        if let Some(columns) = columns {
+            app_state.columnar_data.insert(file.clone(), columns);
+        }
+        if let Some(mut entry) = statistics {
+            entry.fingerprint = fingerprint;
+            entry.computed_at = chrono::Utc::now();
+            app_state.column_stats.insert(file.clone(), entry);
+        }
+        if let Some(mut entry) = filters {
+            entry.fingerprint = fingerprint;
+            app_state.bloom_filters.insert(file.clone(), entry);
+        }
+        app_state.parsed_extents.insert(file.clone(), extent.advance(&appended, fingerprint));
+        if let Some(lineage) = app_state.lineages.get_mut(file).filter(|_| rows > 0) {
+            lineage.appended(extent.bytes, rows, request_log::current());
+        }
+    }
+    record_processing(state, metrics)?;
+    forget_snapshots(state, file).await;
+    log!("➕ {} grew by {} bytes; parsed {} appended row(s) instead of the whole file", file, appended.len(), rows);
+    Ok(Some(rows))
+}
+
+/// Checks the files analyzed since startup every `SNAPSHOT_WATCH_INTERVAL`
+/// (5s by default). Rows appended to a cached file are parsed and added to
+/// its cache; files that changed otherwise or disappeared are forgotten,
+/// so their snapshots, statistics and cached records go as soon as
+/// possible rather than at the next request.
+fn spawn_snapshot_watcher(state: SharedState, shutdown: CancellationToken) {
+    let interval_seconds = match std::env::var("SNAPSHOT_WATCH_INTERVAL") {
+        Ok(interval) => parse_interval(&interval).unwrap_or_else(|| {
+            println!(
+                "⚠️  Ignoring SNAPSHOT_WATCH_INTERVAL={}: expected seconds, or a number followed by s, m, h or d",
+                interval
+            );
+            DEFAULT_SNAPSHOT_WATCH_SECONDS
+        }),
+        Err(_) => DEFAULT_SNAPSHOT_WATCH_SECONDS,
+    };
+    
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(interval_seconds));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = ticker.tick() => {}
+            }
+            let Ok(files) = lock_state(&state).map(|app_state| {
+                let hashed = app_state.hashed_files.iter().map(|(file, hashed)| (file.clone(), hashed.fingerprint));
+                let parsed = app_state.parsed_extents.iter().map(|(file, extent)| (file.clone(), extent.fingerprint));
+                hashed.chain(parsed).collect::<HashMap<_, _>>()
+            }) else {
+                continue;
+            };
+            for (file, fingerprint) in files {
+                let current = fs::metadata(file.path()).await.ok().map(|metadata| RecordCursor::fingerprint(&metadata));
+                if current == Some(fingerprint) {
+                    continue;
+                }
+                if let Some(current) = current {
+                    match catch_up(&state, &file, current).await {
+                        Ok(Some(_)) => continue,
+                        Ok(None) => {}
+                        Err(error) => println!("⚠️  Could not parse the rows appended to {}: {}", file, error.message),
+                    }
+                }
+                // Caught up on already, for a file both hashed and parsed
+                let caught_up = lock_state(&state).is_ok_and(|app_state| {
+                    app_state.parsed_extents.get(&file).is_some_and(|extent| Some(extent.fingerprint) == current)
+                });
+                if !caught_up {
+                    let removed = forget_file(&state, &file).await;
+                    println!("♻️  {} changed, dropped {} analysis snapshot(s)", file, removed);
+                }
+            }
+        }
+    });
+}
+
+/// Every `JANITOR_INTERVAL` (10m by default, `off` to disable) removes
+/// temporary files untouched for `JANITOR_TEMP_AGE` (1h), which a crash or
+/// kill left behind in the data, snapshot and state directories, and
+/// reports older than `REPORT_RETENTION` (30d, `off` to keep them). What it
+/// reclaimed is counted in /metrics.
+fn spawn_janitor(state: SharedState, shutdown: CancellationToken) {
+    let Some(interval_seconds) = seconds_from_env("JANITOR_INTERVAL", DEFAULT_JANITOR_INTERVAL_SECONDS) else {
+        return;
+    };
+    let Ok(snapshot_dir) = lock_state(&state).map(|mut app_state| {
+        app_state.janitor = janitor::JanitorStats::new(interval_seconds);
+        app_state.snapshots.dir().to_path_buf()
+    }) else {
+        return;
+    };
+    let mut temp_dirs: Vec<std::path::PathBuf> = DATA_DIRS.iter().map(std::path::PathBuf::from).collect();
+    temp_dirs.push(snapshot_dir);
+    temp_dirs.push(std::env::var("STATE_SNAPSHOT_DIR").unwrap_or_else(|_| "state".to_string()).into());
+    temp_dirs.push(ARTIFACTS_DIR.into());
+    temp_dirs.push(archive_dir());
+    temp_dirs.push(WORKSPACES_DIR.into());
+    temp_dirs.push(COMPRESSION_DIR.into());
+    let temp_max_age = seconds_from_env("JANITOR_TEMP_AGE", DEFAULT_JANITOR_TEMP_AGE_SECONDS).unwrap_or(DEFAULT_JANITOR_TEMP_AGE_SECONDS);
+    let config = Arc::new(janitor::JanitorConfig {
+        temp_dirs,
+        upload_dir: DATA_DIRS[1].into(),
+        reports_dir: "reports".into(),
+        temp_max_age: std::time::Duration::from_secs(temp_max_age),
+        report_max_age: seconds_from_env("REPORT_RETENTION", DEFAULT_REPORT_RETENTION_SECONDS).map(std::time::Duration::from_secs),
+        artifacts_dir: ARTIFACTS_DIR.into(),
+        artifact_max_age: seconds_from_env("ARTIFACT_RETENTION", DEFAULT_ARTIFACT_RETENTION_SECONDS).map(std::time::Duration::from_secs),
+    });
+    println!("🧹 Sweeping orphaned temporary files every {}s", interval_seconds);
+    
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(interval_seconds));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = ticker.tick() => {}
+            }
+            let swept = config.clone();
+            let Ok(sweep) = tokio::task::spawn_blocking(move || janitor::sweep(&swept, std::time::SystemTime::now())).await else {
+                continue;
+            };
+            let total = sweep.total();
+            if total.files > 0 {
+                println!("🧹 Janitor: removed {} file(s), reclaimed {} bytes", total.files, total.bytes);
+            }
+            for error in &sweep.errors {
+                println!("⚠️  Janitor: {}", error);
+            }
+            let workspaces = expire_workspaces(&state).await;
+            if workspaces > 0 {
+                println!("🧹 Janitor: discarded {} idle workspace(s)", workspaces);
+            }
+            if let Ok(mut app_state) = state.lock() {
+                app_state.janitor.record(sweep);
+            }
+        }
+    });
+}
+
+/// Seconds from the environment variable `name`, in `parse_interval`'s
+/// syntax: `default` when it is unset or invalid, `None` when it is `off`.
+fn seconds_from_env(name: &str, default: u64) -> Option<u64> {
+    match std::env::var(name) {
+        Ok(value) if value.trim().eq_ignore_ascii_case("off") => None,
+        Ok(value) => Some(parse_interval(&value).unwrap_or_else(|| {
+            println!("⚠️  Ignoring {}={}: expected seconds, or a number followed by s, m, h or d, or off", name, value);
+            default
+        })),
+        Err(_) => Some(default),
+    }
+}
+
+/// Where archived uploads and the archival audit log are kept.
+fn archive_dir() -> std::path::PathBuf {
+    std::env::var("ARCHIVE_DIR").ok().filter(|dir| !dir.trim().is_empty()).unwrap_or_else(|| DEFAULT_ARCHIVE_DIR.to_string()).into()
+}
+
+/// The retention rules for uploads, when `RETENTION_MAX_AGE` (e.g. `30d`)
+/// or `RETENTION_MAX_TOTAL` (e.g. `10GB`) is set. Archives are deleted
+/// after `ARCHIVE_RETENTION` (90d, `off` to keep them).
+fn retention_policy() -> Option<retention::RetentionPolicy> {
+    let max_age = match std::env::var("RETENTION_MAX_AGE") {
+        Ok(value) => match parse_interval(&value) {
+            Some(seconds) => Some(std::time::Duration::from_secs(seconds)),
+            None => {
+                println!("⚠️  Ignoring RETENTION_MAX_AGE={}: expected seconds, or a number followed by s, m, h or d", value);
+                None
+            }
+        },
+        Err(_) => None,
+    };
+    let max_total_bytes = match std::env::var("RETENTION_MAX_TOTAL") {
+        Ok(value) => parse_size(&value)
+            .map_err(|error| println!("⚠️  Ignoring RETENTION_MAX_TOTAL: {}", error))
+            .ok(),
+        Err(_) => None,
+    };
+    if max_age.is_none() && max_total_bytes.is_none() {
+        return None;
+    }
+    Some(retention::RetentionPolicy {
+        upload_dir: DATA_DIRS[1].into(),
+        archive_dir: archive_dir(),
+        max_age,
+        max_total_bytes,
+        archive_max_age: seconds_from_env("ARCHIVE_RETENTION", DEFAULT_ARCHIVE_RETENTION_SECONDS).map(std::time::Duration::from_secs),
+        // Set from the current dictionary on each run
+        dictionary: None,
+    })
+}
+
+/// The dictionary last saved to `compression/`, so archiving and exports
+/// go on using it across restarts.
+fn trained_dictionary() -> Option<TrainedDictionary> {
+    match compression::latest(std::path::Path::new(COMPRESSION_DIR)) {
+        Ok(Some(dictionary)) => {
+            let path = std::path::Path::new(COMPRESSION_DIR).join(dictionary.file_name());
+            println!("🗜️  Compressing archives and exports with zstd dictionary {} ({} bytes)", dictionary.id, dictionary.bytes.len());
+            Some(TrainedDictionary { dictionary: Arc::new(dictionary), path, training: None })
+        }
+        Ok(None) => None,
+        Err(error) => {
+            println!("⚠️  Ignoring the dictionaries in {}/: {}", COMPRESSION_DIR, error);
+            None
+        }
+    }
+}
+
+/// Every `RETENTION_INTERVAL` (1h by default) gzips the uploads past the
+/// retention rules into the archive directory and deletes archives past
+/// `ARCHIVE_RETENTION`, holding off writes meanwhile. Each action goes to
+/// the audit log, and what archived uploads left in memory is dropped.
+fn spawn_retention(state: SharedState, shutdown: CancellationToken) {
+    let Ok(Some(policy)) = lock_state(&state).map(|app_state| app_state.retention_policy.clone()) else {
+        return;
+    };
+    let interval_seconds = seconds_from_env("RETENTION_INTERVAL", DEFAULT_RETENTION_INTERVAL_SECONDS).unwrap_or(DEFAULT_RETENTION_INTERVAL_SECONDS);
+    let Ok(write_lock) = lock_state(&state).map(|mut app_state| {
+        app_state.retention = retention::RetentionStats::new(interval_seconds);
+        app_state.write_lock.clone()
+    }) else {
+        return;
+    };
+    println!("🗄️  Archiving uploads to {} by retention rules every {}s", policy.archive_dir.display(), interval_seconds);
+    
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(interval_seconds));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = ticker.tick() => {}
+            }
+            let _write = write_lock.lock().await;
+            let mut applied = (*policy).clone();
+            applied.dictionary = lock_state(&state).ok().and_then(|app_state| app_state.dictionary.as_ref().map(|trained| trained.dictionary.clone()));
+            let Ok(run) = tokio::task::spawn_blocking(move || retention::run(&applied, std::time::SystemTime::now())).await else {
+                continue;
+            };
+            for event in &run.archived {
+                let Ok(file) = FileId::parse(&event.file) else {
+                    continue;
+                };
+                forget_file(&state, &file).await;
+                if let Ok(mut app_state) = lock_state(&state) {
+                    app_state.catalog.remove(&file);
+                    app_state.deduplicators.remove(&file);
+                    app_state.dedup_stats.remove(&file);
+                }
+            }
+            if !run.archived.is_empty() || !run.deleted.is_empty() {
+                println!("🗄️  Retention: archived {} upload(s), deleted {} archive(s)", run.archived.len(), run.deleted.len());
+            }
+            for error in &run.errors {
+                println!("⚠️  Retention: {}", error);
+            }
+            if let Ok(mut app_state) = state.lock() {
+                app_state.retention.record(run);
+            }
+        }
+    });
+}
+
+/// Identifies the current contents of a file, as in record cursors.
+async fn file_fingerprint(file: &FileId) -> Result<u64, ApiError> {
+    let metadata = tokio::fs::metadata(file.path()).await.map_err(|error| ApiError::read_failed(&file.path(), error))?;
+    Ok(RecordCursor::fingerprint(&metadata))
+}
+
+/// The column statistics of a file, if they were computed from the contents
+/// with `fingerprint`. Statistics and cached records of older contents are
+/// dropped, so the file is parsed afresh.
+fn current_statistics(
+    state: &SharedState,
+    file: &FileId,
+    fingerprint: u64,
+) -> Result<Option<Arc<column_stats::ColumnStatistics>>, ApiError> {
+    let mut app_state = lock_state(state)?;
+    match app_state.column_stats.get(file) {
+        Some(entry) if entry.fingerprint == fingerprint => Ok(Some(entry.statistics.clone())),
+        Some(_) => {
+            app_state.column_stats.remove(file);
+            app_state.bloom_filters.remove(file);
+            app_state.cached_data.remove(file);
+            #[cfg(feature = "columnar")]
+            app_state.columnar_data.remove(file);
+            app_state.parsed_extents.remove(file);
+            app_state.search_indexes.remove(file);
+            Ok(None)
+        }
+        None => Ok(None),
+    }
+}
+
+/// Computes a file's column statistics and key Bloom filters on the parser
+/// pool after its first analysis, without holding up the response.
+fn compute_statistics(state: &SharedState, file: &FileId, fingerprint: u64, records: Arc<Vec<SalesRecord>>) {
+    let state = state.clone();
+    let file = file.clone();
+    tokio::spawn(async move {
+        let Ok(pool) = lock_state(&state).map(|app_state| app_state.parser_pool.clone()) else {
+            return;
+        };
+        let computed = pool.run(move || (column_stats::ColumnStatistics::compute(&records), bloom::KeyFilters::build(&records)));
+        if let Ok((statistics, filters)) = computed.await {
+            let now = chrono::Utc::now();
+            let entry = StatisticsEntry {
+                fingerprint,
+                computed_at: now,
+                statistics: Arc::new(statistics),
+            };
+            if let Ok(mut app_state) = lock_state(&state) {
+                app_state.column_stats.insert(file.clone(), entry);
+                app_state.bloom_filters.insert(file, BloomEntry { fingerprint, built_at: now, filters: Arc::new(filters) });
+            }
+        }
+    });
+}
+
+/// The aggregations offered on /aggregate. Add your own here, e.g.
+/// `registry.register::<MyMetric>("my_metric", "What it measures");`, and
+/// they are served without touching the analysis code.
+fn aggregator_registry() -> aggregators::Registry {
+    aggregators::Registry::with_builtins()
+}
+
+/// Changes the aggregations /aggregate offers on `state`, e.g. to register
+/// an embedder's own [`aggregators::Aggregator`] beside the built-ins.
+pub fn register_aggregators(state: &SharedState, register: impl FnOnce(&mut aggregators::Registry)) {
+    let mut app_state = state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    register(Arc::make_mut(&mut app_state.aggregators));
+}
+
+async fn list_aggregators(State(state): State<SharedState>) -> Result<Json<serde_json::Value>, ApiError> {
+    let aggregators = lock_state(&state)?.aggregators.list();
+    Ok(Json(serde_json::json!({
+        "count": aggregators.len(),
+        "aggregators": aggregators
+    })))
+}
+
+/// Runs registered aggregations over a file's records in one pass.
+async fn aggregate_file(
+    axum::extract::Path(filename): axum::extract::Path<String>,
+    query: Result<Query<AggregateQuery>, QueryRejection>,
+    filter: Result<Query<FilterQuery>, QueryRejection>,
+    State(state): State<SharedState>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let Query(params) = query?;
+    let filter = requested_filter(filter)?;
+    let registry = lock_state(&state)?.aggregators.clone();
+    let names: Vec<String> = match &params.metrics {
+        Some(metrics) => metrics.split(',').map(str::trim).filter(|name| !name.is_empty()).map(String::from).collect(),
+        None => registry.list().into_iter().map(|info| info.name).collect(),
+    };
+    let unknown: Vec<_> = names.iter().filter(|name| !registry.contains(name)).collect();
+    if names.is_empty() || !unknown.is_empty() {
+        let available: Vec<_> = registry.list().into_iter().map(|info| info.name).collect();
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "unknown_aggregator", "metrics must name registered aggregations")
+            .detail("unknown", unknown)
+            .detail("available", available));
+    }
+    
+    let rate = applied_rate(&state, params.currency.as_deref())?;
+    
+    let file = FileId::resolve(&filename, &state).await?;
+    let (token, _guard) = request_token(&state)?;
+    let start = std::time::Instant::now();
+    let fingerprint = file_fingerprint(&file).await?;
+    catch_up(&state, &file, fingerprint).await?;
+    let statistics = current_statistics(&state, &file, fingerprint)?;
+    // Statistics hold amounts in the base currency only, and cover whole files
+    let whole = rate.is_none() && filter.is_none();
+    let from_statistics = statistics
+        .as_ref()
+        .filter(|_| whole)
+        .and_then(|statistics| registry.run_from_statistics(&names, statistics));
+    let answered = from_statistics.is_some();
+    #[cfg(feature = "columnar")]
+    let from_statistics = match (from_statistics, cached_columns(&state, &file)?.filter(|_| whole)) {
+        (None, Some(columns)) => registry.run_from_columns(&names, &columns),
+        (run, _) => run,
+    };
+    let run_over = |records: &[SalesRecord]| match &rate {
+        // Aggregations are opaque, so they get converted prices
+        Some(rate) => {
+            let converted: Vec<SalesRecord> = records
+                .iter()
+                .map(|record| SalesRecord { price: record.price * rate.rate, ..record.clone() })
+                .collect();
+            registry.run(&names, &converted, || token.is_cancelled())
+        }
+        None => registry.run(&names, records, || token.is_cancelled()),
+    };
+    let mut scan = None;
+    let (run, cancelled) = match (from_statistics, &filter) {
+        (Some(run), _) => (run, false),
+        (None, Some(filter)) => {
+            let (records, cancelled, partitions) = filtered_records(&state, &file, filter, &token).await?;
+            scan = partitions;
+            (run_over(&records), cancelled)
+        }
+        (None, None) => {
+            let (records, cancelled) = cached_records(&state, &file, &token).await?;
+            let run = run_over(&records);
+            if statistics.is_none() && !cancelled && !run.cancelled {
+                compute_statistics(&state, &file, fingerprint, records);
+            }
+            (run, cancelled)
+        }
+    };
+    
+    let mut body = serde_json::json!({
+        "filename": file,
+        "records": run.records,
+        "results": run.results,
+        "processing_time_ms": start.elapsed().as_millis(),
+        "cancelled": cancelled || run.cancelled,
+        "from_statistics": answered
+    });
+    if let Some(rate) = rate {
+        body["currency"] = serde_json::json!(rate);
+    }
+    if let Some(scan) = scan {
+        body["partitions"] = serde_json::json!(scan);
+    }
+    Ok(Json(body))
+}
+
+/// A file's metric per day, week or month, with its moving average and
+/// growth over the window, for charting.
+async fn file_trends(
+    axum::extract::Path(filename): axum::extract::Path<String>,
+    query: Result<Query<TrendQuery>, QueryRejection>,
+    State(state): State<SharedState>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let Query(params) = query?;
+    let window = trends::Window::parse(params.window.as_deref().unwrap_or("7d"))
+        .map_err(|message| ApiError::new(StatusCode::BAD_REQUEST, "invalid_window", message).detail("window", &params.window))?;
+    let metric = match params.metric.as_deref() {
+        None => trends::Metric::Revenue,
+        Some(name) => trends::Metric::parse(name).ok_or_else(|| {
+            ApiError::new(StatusCode::BAD_REQUEST, "unknown_metric", format!("no trend metric {}", name))
+                .detail("metric", name)
+                .detail("available", trends::Metric::NAMES)
+        })?,
+    };
+    let rate = applied_rate(&state, params.currency.as_deref())?;
+    
+    let file = FileId::resolve(&filename, &state).await?;
+    let (token, _guard) = request_token(&state)?;
+    let start = std::time::Instant::now();
+    let (records, cancelled) = cached_records(&state, &file, &token).await?;
+    let price_factor = rate.as_ref().map_or(1.0, |rate| rate.rate);
+    let trend = trends::trend(&records, metric, window, price_factor, || token.is_cancelled()).map_err(|error| {
+        ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "too_many_periods", error.to_string())
+            .detail("filename", file.as_str())
+            .detail("first", error.first)
+            .detail("last", error.last)
+    })?;
+    
+    let mut body = serde_json::json!({
+        "filename": file,
+        "metric": trend.metric,
+        "window": window.to_string(),
+        "period": trend.period,
+        "window_periods": trend.window_periods,
+        "records": records.len(),
+        "skipped_rows": trend.skipped_rows,
+        "processing_time_ms": start.elapsed().as_millis(),
+        "cancelled": cancelled || trend.cancelled,
+        "points": trend.points
+    });
+    // Counts are the same in any currency
+    if let Some(rate) = rate.filter(|_| metric == trends::Metric::Revenue) {
+        body["currency"] = serde_json::json!(rate);
+    }
+    Ok(Json(body))
+}
+
+/// Descriptive statistics of a file's numeric columns and the Pearson
+/// correlation between each pair, from one pass over its records.
+async fn file_stats(
+    axum::extract::Path(filename): axum::extract::Path<String>,
+    query: Result<Query<StatsQuery>, QueryRejection>,
+    State(state): State<SharedState>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let Query(params) = query?;
+    let mut columns: Vec<String> = Vec::new();
+    for column in params.columns.as_deref().unwrap_or("quantity,price,revenue").split(',').map(str::trim) {
+        if !column.is_empty() && !columns.iter().any(|seen| seen == column) {
+            columns.push(column.to_string());
+        }
+    }
+    if columns.is_empty() {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "unknown_column", "columns must name at least one numeric column")
+            .detail("available", describe::NUMERIC_COLUMNS));
+    }
+    let mut describer = describe::Describer::new(&columns).map_err(|column| {
+        ApiError::new(StatusCode::BAD_REQUEST, "unknown_column", format!("{} is not a numeric column", column))
+            .detail("column", column)
+            .detail("available", describe::NUMERIC_COLUMNS)
+    })?;
+    let rate = applied_rate(&state, params.currency.as_deref())?;
+    
+    let file = FileId::resolve(&filename, &state).await?;
+    let (token, _guard) = request_token(&state)?;
+    let start = std::time::Instant::now();
+    let (records, mut cancelled) = cached_records(&state, &file, &token).await?;
+    let price_factor = rate.as_ref().map_or(1.0, |rate| rate.rate);
+    for (index, record) in records.iter().enumerate() {
+        if index % 1_024 == 0 && token.is_cancelled() {
+            cancelled = true;
+            break;
+        }
+        describer.add(record, price_factor);
+    }
+    
+    let names = describer.columns();
+    let statistics: serde_json::Map<String, serde_json::Value> = names
+        .iter()
+        .enumerate()
+        .map(|(index, name)| (name.to_string(), serde_json::json!(describer.summary(index))))
+        .collect();
+    let correlation: serde_json::Map<String, serde_json::Value> = names
+        .iter()
+        .enumerate()
+        .map(|(a, name)| {
+            let row: serde_json::Map<String, serde_json::Value> = names
+                .iter()
+                .enumerate()
+                .map(|(b, other)| (other.to_string(), serde_json::json!(describer.correlation(a, b))))
+                .collect();
+            (name.to_string(), serde_json::Value::Object(row))
+        })
+        .collect();
+    
+    let mut body = serde_json::json!({
+        "filename": file,
+        "records": describer.count(),
+        "columns": statistics,
+        "correlation": correlation,
+        "processing_time_ms": start.elapsed().as_millis(),
+        "cancelled": cancelled
+    });
+    if let Some(rate) = rate {
+        body["currency"] = serde_json::json!(rate);
+    }
+    Ok(Json(body))
+}
+
+/// Splits a file's orders into price quantile bands and reports each band's
+/// orders, quantity, revenue share and best-selling products.
+async fn file_price_bands(
+    axum::extract::Path(filename): axum::extract::Path<String>,
+    query: Result<Query<PriceBandQuery>, QueryRejection>,
+    State(state): State<SharedState>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let Query(params) = query?;
+    let bands = params.bands.unwrap_or(4);
+    if bands == 0 || bands > price_bands::MAX_BANDS {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid_bands", format!("bands must be between 1 and {}", price_bands::MAX_BANDS))
+            .detail("bands", bands));
+    }
+    let top = params.top.unwrap_or(DEFAULT_BAND_PRODUCTS);
+    if top > MAX_BAND_PRODUCTS {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid_top", format!("top must be at most {}", MAX_BAND_PRODUCTS))
+            .detail("top", top));
+    }
+    let rate = applied_rate(&state, params.currency.as_deref())?;
+    
+    let file = FileId::resolve(&filename, &state).await?;
+    let (token, _guard) = request_token(&state)?;
+    let start = std::time::Instant::now();
+    let (records, cancelled) = cached_records(&state, &file, &token).await?;
+    let price_factor = rate.as_ref().map_or(1.0, |rate| rate.rate);
+    let analysis = price_bands::price_bands(&records, bands, top, price_factor, || token.is_cancelled());
+    
+    let mut body = serde_json::json!({
+        "filename": file,
+        "records": analysis.records,
+        "total_revenue": analysis.total_revenue,
+        "bands": analysis.bands,
+        "processing_time_ms": start.elapsed().as_millis(),
+        "cancelled": cancelled || analysis.cancelled
+    });
+    if let Some(rate) = rate {
+        body["currency"] = serde_json::json!(rate);
+    }
+    Ok(Json(body))
+}
+
+/// The rows of a file without parsing it: estimated from a sample of its
+/// lines by default, so a multi-GB file is sized at once, or counted row
+/// by row with `?exact=true`.
+async fn count_records(
+    axum::extract::Path(filename): axum::extract::Path<String>,
+    query: Result<Query<CountQuery>, QueryRejection>,
+    State(state): State<SharedState>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let Query(params) = query?;
+    let (token, _guard) = request_token(&state)?;
+    let pool = lock_state(&state)?.parser_pool.clone();
+    let file = FileId::resolve(&filename, &state).await?;
+    let path = file.path();
+    let start = std::time::Instant::now();
+    
+    let dialect = file_dialect(&state, &file)?;
+    
+    let (scan_path, delimiter) = (path.clone(), dialect.delimiter as u8);
+    let count = pool
+        .run(move || {
+            if params.exact {
+                row_count::exact(&scan_path, delimiter, || token.is_cancelled())
+            } else {
+                row_count::estimate(&scan_path, delimiter).map(Some)
+            }
+        })
+        .await
+        .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "parse_failed", error.to_string()).detail("filename", file.as_str()))?
+        .map_err(|error| ApiError::read_failed(&path, error).detail("filename", file.as_str()))?;
+    let Some(mut count) = count else {
+        return Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "cancelled", "the server is shutting down").detail("filename", file.as_str()));
+    };
+    // The first line of a file uploaded without a header is a row too
+    if !dialect.has_header && count.bytes > 0 {
+        count.rows += 1;
+    }
+    
+    let mut body = serde_json::to_value(count)
+        .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "serialization_failed", error.to_string()))?;
+    body["filename"] = serde_json::json!(file);
+    body["duration_ms"] = serde_json::json!(start.elapsed().as_secs_f64() * 1_000.0);
+    Ok(Json(body))
+}
+
+/// Searches a file's customer names and products with an inverted index
+/// built on the first search of its cached records, so later searches do
+/// not scan them.
+async fn search_records(
+    axum::extract::Path(filename): axum::extract::Path<String>,
+    query: Result<Query<SearchQuery>, QueryRejection>,
+    State(state): State<SharedState>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let Query(params) = query?;
+    let access = column_access(&state, &headers)?;
+    let text = params.q.unwrap_or_default();
+    let terms: Vec<String> = search::tokenize(&text).collect();
+    if terms.is_empty() {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid_query", "q must contain at least one word to search for")
+            .detail("q", text));
+    }
+    let limit = params.limit.unwrap_or(DEFAULT_SEARCH_HITS);
+    if limit == 0 || limit > MAX_SEARCH_HITS {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid_limit", format!("limit must be between 1 and {}", MAX_SEARCH_HITS))
+            .detail("limit", limit));
+    }
+    
+    let file = FileId::resolve(&filename, &state).await?;
+    let (token, _guard) = request_token(&state)?;
+    let start = std::time::Instant::now();
+    let (records, cancelled) = cached_records(&state, &file, &token).await?;
+    // A request that sees names masked searches the masked records, so it
+    // cannot find rows by an encrypted name; that index is not kept
+    let records = access.reveal_all(&records);
+    let (index, built_ms) = search_index(&state, &file, &records, cancelled || access.masked.is_some()).await?;
+    let (hits, total) = index.search(&text, limit);
+    let hits: Vec<_> = hits
+        .into_iter()
+        .map(|hit| serde_json::json!({ "row": hit.row, "score": hit.score, "matched_terms": hit.matched, "record": &records[hit.row] }))
+        .collect();
+    
+    Ok(Json(serde_json::json!({
+        "filename": file,
+        "q": text,
+        "terms": terms,
+        "total_matches": total,
+        "count": hits.len(),
+        "hits": hits,
+        "index": {
+            "terms": index.terms(),
+            "built_ms": built_ms
+        },
+        "processing_time_ms": start.elapsed().as_millis(),
+        "cancelled": cancelled
+    })))
+}
+
+/// The search index of `records`, built on the parser pool unless the one
+/// kept for `file` was built from these very records. Also returns how long
+/// building took, `None` when it was reused. Indexes of partial records,
+/// from a cancelled parse, are not kept.
+async fn search_index(
+    state: &SharedState,
+    file: &FileId,
+    records: &Arc<Vec<SalesRecord>>,
+    partial: bool,
+) -> Result<(Arc<search::SearchIndex>, Option<u128>), ApiError> {
+    let (kept, pool) = {
+        let app_state = lock_state(state)?;
+        (app_state.search_indexes.get(file).cloned(), app_state.parser_pool.clone())
+    };
+    if let Some((indexed, index)) = kept {
+        if Arc::ptr_eq(&indexed, records) {
+            return Ok((index, None));
+        }
+    }
+    
+    let start = std::time::Instant::now();
+    let indexed = records.clone();
+    let index = pool
+        .run(move || search::SearchIndex::build(&indexed))
+        .await
+        .map(Arc::new)
+        .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "parse_failed", error.to_string()).detail("filename", file.as_str()))?;
+    if !partial {
+        lock_state(state)?.search_indexes.insert(file.clone(), (records.clone(), index.clone()));
+    }
+    Ok((index, Some(start.elapsed().as_millis())))
+}
+
+/// A copy of a file with columns masked as the request says: hashed,
+/// redacted, pseudonymized with a keyed HMAC or bucketed. The file itself is
+/// left as it is; the copy is the response, ready to share.
+async fn anonymize_file(
+    axum::extract::Path(filename): axum::extract::Path<String>,
+    State(state): State<SharedState>,
+    headers: axum::http::HeaderMap,
+    request: Result<Json<AnonymizeRequest>, JsonRejection>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Json(request) = request?;
+    if request.columns.is_empty() {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid_strategy", "give a strategy for at least one column")
+            .detail("strategies", ["hash", "redact", "pseudonymize", "{\"bucket\": width}"]));
+    }
+    // A role that sees a column masked gets it as stored, and may only
+    // redact it: a hash or pseudonym of the plaintext would confirm guesses
+    let access = column_access(&state, &headers)?;
+    let hidden = request.columns.iter().find(|(column, strategy)| access.hides(column) && **strategy != anonymize::Strategy::Redact);
+    if let Some((column, _)) = hidden {
+        return Err(ApiError::new(StatusCode::FORBIDDEN, "column_encrypted", format!("{} is encrypted and this role may only redact it", column))
+            .detail("column", column));
+    }
+    let cipher = match access.masked {
+        Some(_) => None,
+        None => lock_state(&state)?.encryption.clone(),
+    };
+    let key = request.key.or_else(|| std::env::var("ANONYMIZE_KEY").ok()).filter(|key| !key.is_empty());
+    
+    let file = FileId::resolve(&filename, &state).await?;
+    require_standard_dialect(&state, &file)?;
+    let (token, _guard) = request_token(&state)?;
+    let pool = lock_state(&state)?.parser_pool.clone();
+    let timer = PerformanceTimer::new(format!("Anonymizing {}", file));
+    let path = file.path();
+    let columns = request.columns;
+    let worker_token = token.clone();
+    let masked = pool
+        .run(move || {
+            let source = std::fs::File::open(&path).map_err(|error| ApiError::read_failed(&path, error))?;
+            let mut output = Vec::new();
+            let rows = anonymize::anonymize(
+                std::io::BufReader::new(source),
+                &mut output,
+                &columns,
+                key.as_deref().map(str::as_bytes),
+                cipher.as_deref(),
+                || worker_token.is_cancelled(),
+            );
+            Ok::<_, ApiError>((rows, output))
+        })
+        .await
+        .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "parse_failed", error.to_string()).detail("filename", file.as_str()))??;
+    let (rows, output) = masked;
+    let rows = rows.map_err(|error| match error {
+        anonymize::AnonymizeError::Plan(error) => {
+            let api_error = ApiError::new(StatusCode::BAD_REQUEST, "invalid_strategy", error.to_string()).detail("filename", file.as_str());
+            match error {
+                anonymize::PlanError::UnknownColumn(column) => api_error.detail("column", column),
+                anonymize::PlanError::InvalidBucket { column, .. } => api_error.detail("column", column),
+                anonymize::PlanError::MissingKey(column) => api_error.detail("column", column).detail("hint", "send a key or set ANONYMIZE_KEY"),
+            }
+        }
+        anonymize::AnonymizeError::Csv(error) => ApiError::invalid_csv(file.as_str(), &error),
+        anonymize::AnonymizeError::Decrypt { line, column, error } => ApiError::decryption_failed(file.as_str(), (column, error)).detail("line", line),
+        anonymize::AnonymizeError::Value { line, column, value } => {
+            ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_value", format!("{} on line {} is not a number: {:?}", column, line, value))
+                .detail("filename", file.as_str())
+                .detail("line", line)
+                .detail("column", column)
+        }
+    })?;
+    let Some(rows) = rows else {
+        return Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "cancelled", "the server is shutting down").detail("filename", file.as_str()));
+    };
+    
+    let metrics = timer.finish(rows as usize);
+    record_processing(&state, metrics)?;
+    let name = file.as_str().rsplit('/').next().unwrap_or_default();
+    let stem = name.strip_suffix(".csv").unwrap_or(name);
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}.anonymized.csv\"", stem)),
+            (header::HeaderName::from_static("x-anonymized-rows"), rows.to_string()),
+        ],
+        output,
+    ))
+}
+
+async fn list_lookup_tables(State(state): State<SharedState>) -> Result<Json<serde_json::Value>, ApiError> {
+    let tables = lock_state(&state)?.enrichment.list();
+    Ok(Json(serde_json::json!({
+        "count": tables.len(),
+        "tables": tables
+    })))
+}
+
+/// Registers the CSV body as the lookup table `name`, replacing the table
+/// of that name, and an aggregation per column it adds.
+async fn register_lookup_table(
+    axum::extract::Path(name): axum::extract::Path<String>,
+    State(state): State<SharedState>,
+    body: Bytes,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-')) {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid_table_name", "table names may only use letters, digits, _ and -")
+            .detail("table", name));
+    }
+    let table = enrichment::LookupTable::from_csv(&name, &body)
+        .map_err(|message| ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_lookup_table", message).detail("table", &name))?;
+    let info = table.info();
+    
+    let mut app_state = lock_state(&state)?;
+    let mut enricher = (*app_state.enrichment).clone();
+    let replaced = enricher.remove(&name);
+    enricher
+        .register(table)
+        .map_err(|message| ApiError::new(StatusCode::CONFLICT, "column_conflict", message).detail("table", &name))?;
+    let registry = Arc::make_mut(&mut app_state.aggregators);
+    for column in replaced.iter().flat_map(|table| &table.columns) {
+        registry.unregister(&enrichment::aggregation_name(column));
+    }
+    let mut aggregations = Vec::new();
+    if let Some(table) = enricher.table(&name) {
+        for (aggregation, description, aggregator) in enrichment::Enricher::aggregations(table) {
+            registry.register_with(&aggregation, &description, move || Box::new(aggregator.clone()));
+            aggregations.push(aggregation);
+        }
+    }
+    app_state.enrichment = Arc::new(enricher);
+    
+    Ok(Json(serde_json::json!({
+        "table": info,
+        "replaced": replaced.is_some(),
+        "aggregations": aggregations
+    })))
+}
+
+async fn remove_lookup_table(
+    axum::extract::Path(name): axum::extract::Path<String>,
+    State(state): State<SharedState>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let mut app_state = lock_state(&state)?;
+    let mut enricher = (*app_state.enrichment).clone();
+    let Some(table) = enricher.remove(&name) else {
+        return Err(ApiError::new(StatusCode::NOT_FOUND, "unknown_table", format!("no lookup table is called {}", name))
+            .detail("table", name));
+    };
+    let registry = Arc::make_mut(&mut app_state.aggregators);
+    for column in &table.columns {
+        registry.unregister(&enrichment::aggregation_name(column));
+    }
+    app_state.enrichment = Arc::new(enricher);
+    
+    Ok(Json(serde_json::json!({ "removed": table.info() })))
+}
+
+/// The standard analysis of /analyze and the scheduled reports: revenue,
+/// average price and per-product totals, best-selling first. Stops early
+/// when `token` is cancelled.
+fn analyze_records(
+    records: &[SalesRecord],
+    token: &CancellationToken,
+    limit: Option<usize>,
+    start: std::time::Instant,
+) -> AnalysisResult {
+    let mut cancelled = false;
+    let total_revenue: f64 = records.iter()
+        .map(|r| r.price * r.quantity as f64)
+        .sum();
+    
+    // Zero rather than NaN when cancellation left no records
+    let average_price = if records.is_empty() {
+        0.0
+    } else {
+        records.iter().map(|r| r.price).sum::<f64>() / records.len() as f64
+    };
+    
+    // Group by product for top products, under names borrowed from the
+    // records; only the products returned are copied
+    let mut product_map: HashMap<&str, (f64, u32)> = HashMap::new();
+    for record in records.iter() {
+        if token.is_cancelled() {
+            cancelled = true;
+            break;
+        }
+        let sales = record.price * record.quantity as f64;
+        let entry = product_map.entry(record.product.as_str()).or_insert((0.0, 0));
+        entry.0 += sales;
+        entry.1 += record.quantity;
+    }
+    
+    AnalysisResult {
+        total_records: records.len(),
+        total_revenue,
+        average_price,
+        top_products: top_products(product_map, limit),
+        processing_time_ms: start.elapsed().as_millis(),
+        cancelled,
+        from_statistics: false,
+        from_snapshot: false,
+        currency: None,
+        partitions: None,
+    }
+}
+
+/// The analysis of [`analyze_records`] from column statistics, or `None`
+/// when the file has too many products to have kept them.
+fn analyze_statistics(
+    statistics: &column_stats::ColumnStatistics,
+    limit: Option<usize>,
+    start: std::time::Instant,
+) -> Option<AnalysisResult> {
+    let products = statistics.groups("product")?;
+    let product_map = products.iter().map(|(product, group)| (product.as_str(), (group.revenue, group.quantity as u32)));
+    let (total_revenue, average_price) = match (statistics.numeric.get("revenue"), statistics.numeric.get("price")) {
+        (Some(revenue), Some(price)) => (revenue.sum, price.sum / price.count as f64),
+        _ => (0.0, 0.0),
+    };
+    
+    Some(AnalysisResult {
+        total_records: statistics.rows,
+        total_revenue,
+        average_price,
+        top_products: top_products(product_map, limit),
+        processing_time_ms: start.elapsed().as_millis(),
+        cancelled: false,
+        from_statistics: true,
+        from_snapshot: false,
+        currency: None,
+        partitions: None,
+    })
+}
+
+/// The analysis of [`analyze_records`] over the columnar cache, from its
+/// per-product totals.
+#[cfg(feature = "columnar")]
+fn analyze_columns(columns: &columnar::ColumnarRecords, limit: Option<usize>, start: std::time::Instant) -> AnalysisResult {
+    let products = columns.group_by("product", false).unwrap_or_default();
+    let (total_revenue, price_sum) = products.values().fold((0.0, 0.0), |(revenue, price_sum), group| {
+        (revenue + group.revenue, price_sum + group.price_sum)
+    });
+    let product_map = products.into_iter().map(|(product, group)| (product, (group.revenue, group.quantity as u32)));
+    
+    AnalysisResult {
+        total_records: columns.rows(),
+        total_revenue,
+        average_price: if columns.rows() == 0 { 0.0 } else { price_sum / columns.rows() as f64 },
+        top_products: top_products(product_map, limit),
+        processing_time_ms: start.elapsed().as_millis(),
+        cancelled: false,
+        from_statistics: false,
+        from_snapshot: false,
+        currency: None,
+        partitions: None,
+    }
+}
+
+/// Products by total sales, best first, at most `limit` of them. With a
+/// limit, they are picked by [`aggregators::top_k`] without sorting the
+/// rest, and only their names are turned into `String`s.
+fn top_products<S: Into<String>>(product_map: impl IntoIterator<Item = (S, (f64, u32))>, limit: Option<usize>) -> Vec<ProductSummary> {
+    aggregators::top_k(product_map, limit.unwrap_or(usize::MAX), |(_, (total_sales, _))| *total_sales)
+        .into_iter()
+        .map(|(product, (total_sales, quantity_sold))| ProductSummary {
+            product: product.into(),
+            total_sales,
+            quantity_sold,
+        })
+        .collect()
+}
+
+fn no_workers() -> ApiError {
+    ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "no_workers", "this server coordinates no workers; set WORKER_URLS to their base URLs")
+}
+
+/// One batch of a file's records and the cursor of the next. The cursor
+/// is a byte offset, so the server keeps nothing between batches and a
+/// client can page through a file of any size, resuming where it stopped.
+async fn stream_records(
+    axum::extract::Path(filename): axum::extract::Path<String>,
+    query: Result<Query<RecordStreamQuery>, QueryRejection>,
+    State(state): State<SharedState>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let Query(params) = query?;
+    let access = column_access(&state, &headers)?;
+    let limit = params.limit.unwrap_or(DEFAULT_STREAM_BATCH);
+    if limit == 0 || limit > MAX_STREAM_BATCH {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid_limit", format!("limit must be between 1 and {}", MAX_STREAM_BATCH))
+            .detail("limit", limit));
+    }
+    let cursor = match params.cursor.as_deref() {
+        Some(text) => Some(RecordCursor::parse(text).ok_or_else(|| {
+            ApiError::new(StatusCode::BAD_REQUEST, "invalid_cursor", "the cursor is not one this endpoint returned").detail("cursor", text)
+        })?),
+        None => None,
+    };
+    let (pool, enricher, cipher) = {
+        let app_state = lock_state(&state)?;
+        (app_state.parser_pool.clone(), app_state.enrichment.clone(), app_state.encryption.clone())
+    };
+    let filter = enrichment::RecordFilter::parse(params.filter.as_deref().unwrap_or_default(), &enricher).map_err(|message| {
+        ApiError::new(StatusCode::BAD_REQUEST, "invalid_filter", message).detail("filter", &params.filter)
+    })?;
+    let file = FileId::resolve(&filename, &state).await?;
+    let path = file.path();
+    let dialect = file_dialect(&state, &file)?;
+    
+    let batch_file = file.clone();
+    let batch = pool
+        .run(move || read_record_batch(&path, &batch_file, &dialect, cipher.as_deref(), &access, cursor, limit, &enricher, &filter))
+        .await
+        .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "parse_failed", error.to_string()).detail("filename", file.as_str()))??;
+    Ok(Json(batch))
+}
+
+/// Reads up to `limit` records of `file`, written in `dialect`, matching
+/// `filter` from `cursor` on, checking the cursor still points at a row
+/// start of the same file, and adds the enriched columns to them. Columns
+/// `cipher` encrypts are decrypted, then filtered and returned as `access`
+/// allows.
+#[allow(clippy::too_many_arguments)]
+fn read_record_batch(
+    path: &str,
+    file: &FileId,
+    dialect: &dialect::Dialect,
+    cipher: Option<&encryption::ColumnCipher>,
+    access: &ColumnAccess,
+    cursor: Option<RecordCursor>,
+    limit: usize,
+    enricher: &enrichment::Enricher,
+    filter: &enrichment::RecordFilter,
+) -> Result<serde_json::Value, ApiError> {
+    use std::io::{Read, Seek, SeekFrom};
+    
+    let read_failed = |error| ApiError::read_failed(path, error).detail("filename", file.as_str());
+    let mut handle = std::fs::File::open(path).map_err(read_failed)?;
+    let fingerprint = RecordCursor::fingerprint(&handle.metadata().map_err(read_failed)?);
+    if let Some(cursor) = cursor {
+        if cursor.fingerprint != fingerprint {
+            return Err(ApiError::new(StatusCode::CONFLICT, "stale_cursor", format!("{} changed since the cursor was issued; start again without one", file))
+                .detail("filename", file.as_str()));
+        }
+        // A row starts at the beginning of a line
+        let mut previous = [0; 1];
+        let at_row_start = cursor.offset > 0
+            && handle.seek(SeekFrom::Start(cursor.offset - 1)).is_ok()
+            && handle.read_exact(&mut previous).is_ok()
+            && previous[0] == b'\n';
+        if !at_row_start {
+            return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid_cursor", "the cursor is not one this endpoint returned")
+                .detail("cursor", cursor.to_string()));
+        }
+        handle.rewind().map_err(read_failed)?;
+    }
+    
+    let mut reader = dialect.reader(false, handle);
+    let headers = reader.byte_headers().map_err(|error| ApiError::invalid_csv(file.as_str(), &error))?.clone();
+    if let Some(cursor) = cursor {
+        let mut position = csv::Position::new();
+        position.set_byte(cursor.offset);
+        reader.seek(position).map_err(|error| ApiError::invalid_csv(file.as_str(), &error))?;
+    }
+    let start = reader.position().byte();
+    let mut records = Vec::with_capacity(limit);
+    let mut row = csv::ByteRecord::new();
+    let mut more = true;
+    while records.len() < limit {
+        let byte = reader.position().byte();
+        if !reader.read_byte_record(&mut row).map_err(|error| ApiError::invalid_csv(file.as_str(), &error))? {
+            more = false;
+            break;
+        }
+        // Line numbers restart at the cursor, so bad rows are located by byte
+        let mut record: SalesRecord = row
+            .deserialize(Some(&headers))
+            .map_err(|error| ApiError::invalid_csv(file.as_str(), &error).detail("byte", byte))?;
+        if let Some(cipher) = cipher {
+            cipher.decrypt_record(&mut record).map_err(|error| ApiError::decryption_failed(file.as_str(), error).detail("byte", byte))?;
+        }
+        let record = access.reveal(&record);
+        if filter.matches(enricher, &record) {
+            records.push(enricher.enrich(&record));
+        }
+    }
+    let next = reader.position().byte();
+    // A full batch that ended the file leaves nothing for the next one
+    if more {
+        more = reader.read_byte_record(&mut row).map_err(|error| ApiError::invalid_csv(file.as_str(), &error))?;
+    }
+    
+    Ok(serde_json::json!({
+        "filename": file,
+        "cursor": cursor.map(|cursor| cursor.to_string()),
+        "offset": start,
+        "count": records.len(),
+        "records": records,
+        "next_cursor": more.then(|| RecordCursor { offset: next, fingerprint }.to_string()),
+        "done": !more
+    }))
+}
+
+/// Where each of `records`, the cached records of `file`, was read from,
+/// in the same order, found by reading the file again for the line each
+/// record starts on. Also returns the content read. Answers 409 when the
+/// file no longer holds as many records as are cached, as it changed
+/// since they were parsed.
+async fn trace_records(
+    state: &SharedState,
+    file: &FileId,
+    records: &[SalesRecord],
+) -> Result<(Vec<provenance::Provenance>, Arc<String>), ApiError> {
+    let path = file.path();
+    let processing::Attempted { value: content, .. } = read_data_file(state, &path)
+        .await
+        .map_err(|error| error.detail("filename", file.as_str()))?;
+    let content = Arc::new(content);
+    let (pool, lineage) = {
+        let app_state = lock_state(state)?;
+        (app_state.parser_pool.clone(), app_state.lineages.get(file).cloned().unwrap_or_default())
+    };
+    let dialect = file_dialect(state, file)?;
+    let scanned = content.clone();
+    let positions = pool
+        .run(move || provenance::positions(dialect.reader(false, scanned.as_bytes())))
+        .await
+        .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "task_failed", error.to_string()))?
+        .map_err(|error| ApiError::invalid_csv(file.as_str(), &error))?;
+    if positions.len() != records.len() {
+        return Err(ApiError::new(StatusCode::CONFLICT, "file_changed", format!("{} changed while it was being traced; try again", file))
+            .detail("filename", file.as_str())
+            .detail("cached_records", records.len())
+            .detail("file_records", positions.len()));
+    }
+    let provenance = positions.iter().map(|position| lineage.provenance(&path, position)).collect();
+    Ok((provenance, content))
+}
+
+/// The records of a file with an id, each with its provenance: the line of
+/// the file it was read from, as it reads there, and when and by which
+/// request or job it was ingested, so a value found wrong downstream can be
+/// traced back to its source.
+async fn record_provenance(
+    path: Result<axum::extract::Path<(String, u32)>, PathRejection>,
+    State(state): State<SharedState>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let axum::extract::Path((filename, id)) = path?;
+    let access = column_access(&state, &headers)?;
+    let file = FileId::resolve(&filename, &state).await?;
+    let (token, _guard) = request_token(&state)?;
+    let (records, _) = cached_records(&state, &file, &token).await?;
+    let (provenance, content) = trace_records(&state, &file, &records).await?;
+    
+    let matches: Vec<_> = records
+        .iter()
+        .zip(&provenance)
+        .filter(|(record, _)| record.id == id)
+        .map(|(record, provenance)| {
+            serde_json::json!({
+                "record": access.reveal(record),
+                "provenance": provenance,
+                "source_line": provenance::source_line(content.as_bytes(), provenance.byte)
+            })
+        })
+        .collect();
+    if matches.is_empty() {
+        return Err(ApiError::new(StatusCode::NOT_FOUND, "record_not_found", format!("{} has no record with id {}", file, id))
+            .detail("filename", file.as_str())
+            .detail("id", id));
+    }
+    let ingestions = lock_state(&state)?.lineages.get(&file).map(|lineage| lineage.ingestions().to_vec()).unwrap_or_default();
+    
+    Ok(Json(serde_json::json!({
+        "filename": file,
+        "id": id,
+        "count": matches.len(),
+        "records": matches,
+        "ingestions": ingestions
+    })))
+}
+
+/// Appends records to a file, so a dataset can grow without being uploaded
+/// again. The body is one `SalesRecord` or an array of them; none are
+/// written unless all of them are valid.
+async fn append_records(
+    axum::extract::Path(filename): axum::extract::Path<String>,
+    State(state): State<SharedState>,
+    body: Result<Json<serde_json::Value>, JsonRejection>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let Json(body) = body?;
+    let records = appended_records(body)?;
+    let file = FileId::resolve(&filename, &state).await?;
+    let (size_bytes, duplicates) = append_to_file(&state, &file, &records, false).await?;
+    
+    Ok(Json(serde_json::json!({
+        "filename": file,
+        "appended": records.len() - duplicates,
+        "duplicates_dropped": duplicates,
+        "size_bytes": size_bytes
+    })))
+}
+
+/// The records of a POST /records body, each checked with [`check_record`].
+/// Failures are 422 with the index of the record in the body.
+fn appended_records(body: serde_json::Value) -> Result<Vec<SalesRecord>, ApiError> {
+    let values = match body {
+        serde_json::Value::Array(values) => values,
+        value => vec![value],
+    };
+    if values.is_empty() || values.len() > MAX_APPEND_RECORDS {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_record_count",
+            format!("send between 1 and {} records", MAX_APPEND_RECORDS),
+        )
+        .detail("records", values.len()));
+    }
+    
+    let invalid = |index: usize, message: String| {
+        ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_record", message).detail("index", index)
+    };
+    values
+        .into_iter()
+        .enumerate()
+        .map(|(index, value)| {
+            let record: SalesRecord = serde_json::from_value(value).map_err(|error| invalid(index, error.to_string()))?;
+            check_record(&record).map_err(|(field, message)| invalid(index, message).detail("field", field))?;
+            Ok(record)
+        })
+        .collect()
+}
+
+/// Changes fields of the records with `id`. The body is an object with
+/// the sales fields to change, all but `id`.
+async fn update_record(
+    path: Result<axum::extract::Path<(String, u32)>, PathRejection>,
+    State(state): State<SharedState>,
+    headers: axum::http::HeaderMap,
+    body: Result<Json<serde_json::Map<String, serde_json::Value>>, JsonRejection>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let axum::extract::Path((filename, id)) = path?;
+    let access = column_access(&state, &headers)?;
+    let Json(changes) = body?;
+    let invalid = |field: &str, message: String| {
+        ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_record", message).detail("field", field)
+    };
+    if changes.is_empty() {
+        return Err(ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_record", "send at least one field to change"));
+    }
+    for field in changes.keys() {
+        if field == "id" {
+            return Err(invalid(field, "id cannot be changed; delete the record and append a new one".to_string()));
+        }
+        if !SALES_COLUMNS.contains(&field.as_str()) {
+            return Err(invalid(field, format!("unknown field {}, expected one of {}", field, SALES_COLUMNS.join(", "))));
+        }
+    }
+    
+    let file = FileId::resolve(&filename, &state).await?;
+    let (records, size_bytes) = rewrite_records(&state, &file, id, Some(changes)).await?;
+    let records: Vec<SalesRecord> = records.iter().map(|record| access.reveal(record)).collect();
+    Ok(Json(serde_json::json!({
+        "filename": file,
+        "id": id,
+        "updated": records.len(),
+        "records": records,
+        "size_bytes": size_bytes
+    })))
+}
+
+/// Removes the records with `id`.
+async fn delete_record(
+    path: Result<axum::extract::Path<(String, u32)>, PathRejection>,
+    State(state): State<SharedState>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let axum::extract::Path((filename, id)) = path?;
+    let file = FileId::resolve(&filename, &state).await?;
+    let (records, size_bytes) = rewrite_records(&state, &file, id, None).await?;
+    Ok(Json(serde_json::json!({
+        "filename": file,
+        "id": id,
+        "deleted": records.len(),
+        "size_bytes": size_bytes
+    })))
+}
+
+/// Rewrites `file` with the records with `id` changed by `changes`, or
+/// removed without, through a copy renamed over it, so readers see either
+/// the old file or the new one. Returns the records as changed (or as they
+/// were, when removed) and the file's new size. 404 when no record has `id`.
+async fn rewrite_records(
+    state: &SharedState,
+    file: &FileId,
+    id: u32,
+    changes: Option<serde_json::Map<String, serde_json::Value>>,
+) -> Result<(Vec<SalesRecord>, u64), ApiError> {
+    let (write_lock, pool, cipher) = {
+        let app_state = lock_state(state)?;
+        (app_state.write_lock.clone(), app_state.parser_pool.clone(), app_state.encryption.clone())
+    };
+    let _writing = write_lock.lock().await;
+    let deleting = changes.is_none();
+    let rewritten = file.clone();
+    let dialect = file_dialect(state, file)?;
+    let (records, size_bytes) = pool
+        .run(move || rewrite_file(&rewritten, &dialect, cipher.as_deref(), id, changes.as_ref()))
+        .await
+        .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "parse_failed", error.to_string()).detail("filename", file.as_str()))??;
+    let rows_added = if deleting { -(records.len() as i64) } else { 0 };
+    file_written(state, file, size_bytes, rows_added, false).await?;
+    Ok((records, size_bytes))
+}
+
+/// The blocking part of [`rewrite_records`]: copies the file row by row to
+/// a hidden `.part` file beside it, changing or leaving out the rows with
+/// `id`, and renames the copy over the original. Other rows and columns
+/// beyond the sales ones are copied as they are, in the file's `dialect`.
+/// Changed rows are written with `cipher`'s columns encrypted.
+fn rewrite_file(
+    file: &FileId,
+    dialect: &dialect::Dialect,
+    cipher: Option<&encryption::ColumnCipher>,
+    id: u32,
+    changes: Option<&serde_json::Map<String, serde_json::Value>>,
+) -> Result<(Vec<SalesRecord>, u64), ApiError> {
+    let path = file.path();
+    let source = std::fs::File::open(&path).map_err(|error| ApiError::read_failed(&path, error))?;
+    let mut reader = dialect.reader(false, std::io::BufReader::new(source));
+    let headers = reader.headers().map_err(|error| ApiError::invalid_csv(file.as_str(), &error))?.clone();
+    let columns: Vec<String> = headers.iter().map(String::from).collect();
+    check_sales_header(file, &columns)?;
+    let id_column = columns.iter().position(|column| column == "id").unwrap_or_default();
+    
+    let (dir, name) = path.rsplit_once('/').unwrap_or((".", &path));
+    let mut part = PartFile { path: format!("{}/.{}.part", dir, name), keep: false };
+    let storage_failed = |error: std::io::Error| {
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "storage_failed", format!("cannot rewrite {}: {}", path, error))
+    };
+    let output = std::fs::File::create(&part.path).map_err(storage_failed)?;
+    let mut writer = dialect.writer(std::io::BufWriter::new(output));
+    if dialect.has_header {
+        writer.write_record(&dialect.file_header(&headers)).map_err(|error| storage_failed(error.into()))?;
+    }
+    
+    let id_text = id.to_string();
+    let mut matched = Vec::new();
+    for row in reader.records() {
+        let row = row.map_err(|error| ApiError::invalid_csv(file.as_str(), &error))?;
+        if row.get(id_column).map(str::trim) != Some(id_text.as_str()) {
+            writer.write_record(&row).map_err(|error| storage_failed(error.into()))?;
+            continue;
+        }
+        let mut record: SalesRecord = row.deserialize(Some(&headers)).map_err(|error| ApiError::invalid_csv(file.as_str(), &error))?;
+        if let Some(cipher) = cipher {
+            cipher.decrypt_record(&mut record).map_err(|error| ApiError::decryption_failed(file.as_str(), error))?;
+        }
+        let Some(changes) = changes else {
+            matched.push(record);
+            continue;
+        };
+        
+        let mut fields = match serde_json::to_value(&record) {
+            Ok(serde_json::Value::Object(fields)) => fields,
+            _ => serde_json::Map::new(),
+        };
+        fields.extend(changes.clone());
+        let invalid = |field: Option<&str>, message: String| {
+            let error = ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_record", message);
+            match field {
+                Some(field) => error.detail("field", field),
+                None => error,
+            }
+        };
+        let changed: SalesRecord =
+            serde_json::from_value(serde_json::Value::Object(fields)).map_err(|error| invalid(None, error.to_string()))?;
+        check_record(&changed).map_err(|(field, message)| invalid(Some(field), message))?;
+        let stored = cipher.map(|cipher| cipher.encrypt_record(&changed));
+        let row: Vec<String> = columns
+            .iter()
+            .zip(row.iter())
+            .map(|(column, value)| record_field(stored.as_ref().unwrap_or(&changed), column).unwrap_or_else(|| value.to_string()))
+            .collect();
+        writer.write_record(&row).map_err(|error| storage_failed(error.into()))?;
+        matched.push(changed);
+    }
+    if matched.is_empty() {
+        return Err(ApiError::new(StatusCode::NOT_FOUND, "record_not_found", format!("{} has no record with id {}", file, id))
+            .detail("filename", file.as_str())
+            .detail("id", id));
+    }
+    
+    let output = writer.into_inner().map_err(|error| storage_failed(error.into_error()))?;
+    let output = output.into_inner().map_err(|error| storage_failed(error.into_error()))?;
+    output.sync_all().map_err(storage_failed)?;
+    std::fs::rename(&part.path, &path).map_err(storage_failed)?;
+    part.keep = true;
+    let size_bytes = std::fs::metadata(&path).map_err(|error| ApiError::read_failed(&path, error))?.len();
+    Ok((matched, size_bytes))
+}
+
+/// Deletes or masks the rows of a customer in every CSV in the data
+/// directories, rewriting each affected file through a copy as PATCH and
+/// DELETE do, and reports the rows affected per file. A file that cannot be
+/// rewritten is reported and the others are still erased. Copies of the
+/// data files elsewhere are left as they are and reported as not reached.
+async fn erase_customer(
+    State(state): State<SharedState>,
+    headers: axum::http::HeaderMap,
+    request: Result<Json<ErasureRequest>, JsonRejection>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let Json(request) = request?;
+    let customer = request.customer_name.trim().to_lowercase();
+    if customer.is_empty() {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid_customer", "customer_name must not be empty"));
+    }
+    // The rows it reports would tell a role that sees names masked whose they are
+    if column_access(&state, &headers)?.hides("customer_name") {
+        return Err(ApiError::new(StatusCode::FORBIDDEN, "column_encrypted", "customer_name is encrypted and this role may not decrypt it")
+            .detail("column", "customer_name"));
+    }
+    
+    let (write_lock, pool, cipher) = {
+        let app_state = lock_state(&state)?;
+        (app_state.write_lock.clone(), app_state.parser_pool.clone(), app_state.encryption.clone())
+    };
+    // No append or record rewrite can bring rows back in behind the scan
+    let _writing = write_lock.lock().await;
+    let start = std::time::Instant::now();
+    #[allow(unused_mut)]
+    let (files, mut not_reached) = tokio::task::spawn_blocking(|| (stored_files(), unreached_stores()))
+        .await
+        .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "scan_failed", error.to_string()))?;
+    #[cfg(feature = "sqlite")]
+    {
+        let database = lock_state(&state)?.sqlite.path().to_path_buf();
+        if fs::try_exists(&database).await.unwrap_or(false) {
+            not_reached.push(serde_json::json!({ "store": "sqlite", "path": database.display().to_string() }));
+        }
+    }
+    
+    let mut affected = Vec::new();
+    let mut failed = Vec::new();
+    let mut rows_affected = 0;
+    for file in &files {
+        let (erased_file, erased_customer, action, cipher) = (file.clone(), customer.clone(), request.action, cipher.clone());
+        let dialect = file_dialect(&state, file)?;
+        let erased = pool
+            .run(move || erase_from_file(&erased_file, &dialect, cipher.as_deref(), &erased_customer, action))
+            .await
+            .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "parse_failed", error.to_string()).detail("filename", file.as_str()))
+            .and_then(|erased| erased);
+        match erased {
+            Ok(Some((rows, size_bytes))) => {
+                let rows_added = match request.action {
+                    ErasureAction::Delete => -(rows as i64),
+                    ErasureAction::Mask => 0,
+                };
+                file_written(&state, file, size_bytes, rows_added, false).await?;
+                rows_affected += rows;
+                affected.push(serde_json::json!({ "filename": file, "rows": rows, "size_bytes": size_bytes }));
+            }
+            Ok(None) => {}
+            Err(error) => failed.push(serde_json::json!({ "filename": file, "code": error.code, "message": error.message })),
+        }
+    }
+    // The name itself stays out of the log
+    log!("🧹 Erasure: {} row(s) in {} of {} file(s), {} failed", rows_affected, affected.len(), files.len(), failed.len());
+    
+    Ok(Json(serde_json::json!({
+        "action": request.action,
+        "files_scanned": files.len(),
+        "rows_affected": rows_affected,
+        "files": affected,
+        "failed": failed,
+        "not_reached": not_reached,
+        "duration_ms": start.elapsed().as_millis()
+    })))
+}
+
+/// Stores holding copies of data files that [`erase_customer`] leaves as
+/// they are, with how many files each holds, so a caller knows what else to
+/// clear: partitions, exports, archives and workspace artifacts.
+fn unreached_stores() -> Vec<serde_json::Value> {
+    let stores: [(&str, std::path::PathBuf); 4] = [
+        ("partitions", PARTITIONS_DIR.into()),
+        ("exports", ARTIFACTS_DIR.into()),
+        ("archive", archive_dir()),
+        ("workspaces", WORKSPACES_DIR.into()),
+    ];
+    let mut unreached = Vec::new();
+    for (store, dir) in stores {
+        let mut files = 0;
+        let mut pending = vec![dir.clone()];
+        while let Some(path) = pending.pop() {
+            let Ok(entries) = std::fs::read_dir(&path) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                if entry.file_name().to_string_lossy().starts_with('.') {
+                    continue;
+                }
+                match entry.file_type() {
+                    Ok(kind) if kind.is_dir() => pending.push(entry.path()),
+                    Ok(kind) if kind.is_file() => files += 1,
+                    _ => {}
+                }
+            }
+        }
+        if files > 0 {
+            unreached.push(serde_json::json!({ "store": store, "path": dir.display().to_string(), "files": files }));
+        }
+    }
+    unreached
+}
+
+/// Every CSV in the data directories, subdirectories included, by name.
+/// Hidden files, such as uploads still being written, are left out.
+fn stored_files() -> Vec<FileId> {
+    let mut files = Vec::new();
+    for dir in DATA_DIRS {
+        let mut pending = vec![String::new()];
+        while let Some(relative) = pending.pop() {
+            let Ok(entries) = std::fs::read_dir(std::path::Path::new(dir).join(&relative)) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if name.starts_with('.') {
+                    continue;
+                }
+                let path = if relative.is_empty() { name } else { format!("{}/{}", relative, name) };
+                match entry.file_type() {
+                    Ok(kind) if kind.is_dir() => pending.push(path),
+                    Ok(kind) if kind.is_file() && path.ends_with(".csv") => files.push(FileId::new(dir, &path)),
+                    _ => {}
+                }
+            }
+        }
+    }
+    files.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+    files
+}
+
+/// The blocking part of [`erase_customer`] for one file: copies it to a
+/// hidden `.part` file beside it without the rows of `customer` (already
+/// lowercased), or with their name redacted, and renames the copy over the
+/// original. Returns the rows affected and the new size, or `None` when
+/// the file has no rows of the customer and was left alone. Names are
+/// compared decrypted when `cipher` encrypts them.
+fn erase_from_file(
+    file: &FileId,
+    dialect: &dialect::Dialect,
+    cipher: Option<&encryption::ColumnCipher>,
+    customer: &str,
+    action: ErasureAction,
+) -> Result<Option<(u64, u64)>, ApiError> {
+    let path = file.path();
+    let source = std::fs::File::open(&path).map_err(|error| ApiError::read_failed(&path, error))?;
+    let mut reader = dialect.reader(false, std::io::BufReader::new(source));
+    let headers = reader.headers().map_err(|error| ApiError::invalid_csv(file.as_str(), &error))?.clone();
+    let columns: Vec<String> = headers.iter().map(String::from).collect();
+    check_sales_header(file, &columns)?;
+    let name_column = columns.iter().position(|column| column == "customer_name").unwrap_or_default();
+    
+    let (dir, name) = path.rsplit_once('/').unwrap_or((".", &path));
+    let mut part = PartFile { path: format!("{}/.{}.part", dir, name), keep: false };
+    let storage_failed = |error: std::io::Error| {
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "storage_failed", format!("cannot rewrite {}: {}", path, error))
+    };
+    let output = std::fs::File::create(&part.path).map_err(storage_failed)?;
+    let mut writer = dialect.writer(std::io::BufWriter::new(output));
+    if dialect.has_header {
+        writer.write_record(&dialect.file_header(&headers)).map_err(|error| storage_failed(error.into()))?;
+    }
+    
+    let mut rows = 0;
+    for row in reader.records() {
+        let row = row.map_err(|error| ApiError::invalid_csv(file.as_str(), &error))?;
+        let name = match (cipher.filter(|cipher| cipher.encrypts("customer_name")), row.get(name_column)) {
+            (Some(cipher), Some(name)) => {
+                Some(cipher.decrypt("customer_name", name).map_err(|error| ApiError::decryption_failed(file.as_str(), ("customer_name".to_string(), error)))?)
+            }
+            (_, name) => name.map(String::from),
+        };
+        if name.map(|name| name.trim().to_lowercase()).as_deref() != Some(customer) {
+            writer.write_record(&row).map_err(|error| storage_failed(error.into()))?;
+            continue;
+        }
+        rows += 1;
+        if let ErasureAction::Mask = action {
+            let masked: Vec<&str> = row.iter().enumerate().map(|(index, value)| if index == name_column { "REDACTED" } else { value }).collect();
+            writer.write_record(&masked).map_err(|error| storage_failed(error.into()))?;
+        }
+    }
+    if rows == 0 {
+        // Dropping the part file removes it
+        return Ok(None);
+    }
+    
+    let output = writer.into_inner().map_err(|error| storage_failed(error.into_error()))?;
+    let output = output.into_inner().map_err(|error| storage_failed(error.into_error()))?;
+    output.sync_all().map_err(storage_failed)?;
+    std::fs::rename(&part.path, &path).map_err(storage_failed)?;
+    part.keep = true;
+    let size_bytes = std::fs::metadata(&path).map_err(|error| ApiError::read_failed(&path, error))?.len();
+    Ok(Some((rows, size_bytes)))
+}
+
+/// What the record APIs require beyond the field types: a finite,
+/// non-negative price and a `YYYY-MM-DD` date. Returns the field at fault.
+fn check_record(record: &SalesRecord) -> Result<(), (&'static str, String)> {
+    if !record.price.is_finite() || record.price < 0.0 {
+        return Err(("price", format!("price must be a non-negative number, not {}", record.price)));
+    }
+    if chrono::NaiveDate::parse_from_str(&record.date, "%Y-%m-%d").is_err() {
+        return Err(("date", format!("date must be YYYY-MM-DD, not {:?}", record.date)));
+    }
+    Ok(())
+}
+
+/// A record's value for a CSV column, or `None` for columns beyond the
+/// sales ones.
+fn record_field(record: &SalesRecord, column: &str) -> Option<String> {
+    match column {
+        "id" => Some(record.id.to_string()),
+        "customer_name" => Some(record.customer_name.clone()),
+        "product" => Some(record.product.clone()),
+        "quantity" => Some(record.quantity.to_string()),
+        "price" => Some(record.price.to_string()),
+        "date" => Some(record.date.clone()),
+        "region" => Some(record.region.clone()),
+        _ => None,
+    }
+}
+
+/// Why a file cannot take the record APIs' rows: it lacks a sales column.
+fn check_sales_header(file: &FileId, columns: &[String]) -> Result<(), ApiError> {
+    let missing: Vec<&str> = SALES_COLUMNS.iter().copied().filter(|column| !columns.iter().any(|header| header == column)).collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    Err(ApiError::new(
+        StatusCode::CONFLICT,
+        "missing_columns",
+        format!("{} is missing the column(s) {}, so its records cannot be written", file, missing.join(", ")),
+    )
+    .detail("filename", file.as_str())
+    .detail("missing", missing)
+    .detail("header", columns))
+}
+
+/// After a write to `file`: adds `appended` rows to what is cached of it,
+/// or otherwise drops what was derived from its old contents, and updates
+/// its size and row count in the catalog and its size in the uploads list.
+async fn file_written(state: &SharedState, file: &FileId, size_bytes: u64, rows_added: i64, appended: bool) -> Result<(), ApiError> {
+    let caught_up = match (appended, file_fingerprint(file).await) {
+        (true, Ok(fingerprint)) => matches!(catch_up(state, file, fingerprint).await, Ok(Some(_))),
+        _ => false,
+    };
+    if !caught_up {
+        forget_file(state, file).await;
+    }
+    let mut app_state = lock_state(state)?;
+    // Rows rewritten or removed may have keys the filter still holds
+    if !appended {
+        app_state.deduplicators.remove(file);
+        app_state.dedup_stats.remove(file);
+    }
+    if let Some(entry) = app_state.catalog.get_mut(file) {
+        entry.rows = (entry.rows as i64 + rows_added).max(0) as u32;
+        entry.size_bytes = size_bytes;
+    }
+    for upload in app_state.uploads.values_mut().filter(|upload| upload.file_id == *file) {
+        upload.size_bytes = size_bytes as usize;
+    }
+    Ok(())
+}
+
+/// Appends `records` to `file` in the column order of its header, and
+/// returns the file's new size and the records dropped as duplicates. With
+/// `create`, a missing or empty file is started with the sales header;
+/// otherwise it must exist. Appends are serialized and all-or-nothing: a
+/// failed write is truncated away. With `DEDUP_KEYS`, records whose keys
+/// the file or an earlier record of the append already has are left out.
+/// The rows are added to the file's cached records and statistics,
+/// snapshots of the old contents are dropped, and the catalog and upload
+/// sizes updated.
+async fn append_to_file(state: &SharedState, file: &FileId, records: &[SalesRecord], create: bool) -> Result<(u64, usize), ApiError> {
+    let write_lock = lock_state(state)?.write_lock.clone();
+    let _writing = write_lock.lock().await;
+    let path = file.path();
+    let storage_failed = |error: std::io::Error| {
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "storage_failed", format!("cannot append to {}: {}", path, error))
+    };
+    
+    if create {
+        if let Some(dir) = std::path::Path::new(&path).parent() {
+            fs::create_dir_all(dir).await.map_err(storage_failed)?;
+        }
+    }
+    let mut handle = fs::OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(create)
+        .open(&path)
+        .await
+        .map_err(|error| ApiError::read_failed(&path, error))?;
+    let size = handle.metadata().await.map_err(|error| ApiError::read_failed(&path, error))?.len();
+    let dialect = file_dialect(state, file)?;
+    
+    let mut data = Vec::new();
+    let columns: Vec<String> = if size == 0 {
+        if dialect.has_header {
+            data.extend_from_slice(SALES_COLUMNS.join(&dialect.delimiter.to_string()).as_bytes());
+            data.push(b'\n');
+        }
+        dialect.columns.clone().unwrap_or_else(|| SALES_COLUMNS.iter().map(|column| column.to_string()).collect())
+    } else {
+        let mut header = Vec::new();
+        if dialect.has_header {
+            tokio::io::BufReader::new(&mut handle).read_until(b'\n', &mut header).await.map_err(|error| ApiError::read_failed(&path, error))?;
+        }
+        // Under the names the records' fields have, renames applied
+        let columns = upload_columns(&header, &dialect);
+        check_sales_header(file, &columns)?;
+        // A last row without its line break would run into the first new one
+        handle.seek(std::io::SeekFrom::Start(size - 1)).await.map_err(|error| ApiError::read_failed(&path, error))?;
+        if handle.read_u8().await.map_err(|error| ApiError::read_failed(&path, error))? != b'\n' {
+            data.push(b'\n');
+        }
+        columns
+    };
+    
+    let (records, duplicates) = drop_duplicates(state, file, &dialect, size, records).await?;
+    let cipher = lock_state(state)?.encryption.clone();
+    let mut writer = dialect.writer(data);
+    for record in records.iter() {
+        let record = match &cipher {
+            Some(cipher) => std::borrow::Cow::Owned(cipher.encrypt_record(record)),
+            None => std::borrow::Cow::Borrowed(record),
+        };
+        // Columns beyond the sales ones are left empty
+        let row = columns.iter().map(|column| record_field(&record, column).unwrap_or_default());
+        writer.write_record(row).map_err(|error| storage_failed(error.into()))?;
+    }
+    let data = writer.into_inner().map_err(|error| storage_failed(error.into_error()))?;
+    
+    let written = async {
+        handle.write_all(&data).await?;
+        handle.sync_data().await
+    }
+    .await;
+    if let Err(error) = written {
+        let _ = handle.set_len(size).await;
+        return Err(storage_failed(error));
+    }
+    let size_bytes = size + data.len() as u64;
+    if let Some(entry) = lock_state(state)?.deduplicators.get(file) {
+        entry.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).size = Some(size_bytes);
+    }
+    file_written(state, file, size_bytes, records.len() as i64, true).await?;
+    Ok((size_bytes, duplicates))
+}
+
+/// The settings of duplicate filtering on appends: `DEDUP_KEYS`, the
+/// columns that identify a record, e.g. `id` or `customer_name,product,date`,
+/// `DEDUP_MODE`, `hash` or `xor`, `DEDUP_MEMORY` per file and
+/// `DEDUP_SPILL_DIR`. `None` without `DEDUP_KEYS`.
+fn dedup_config() -> Option<dedup::DedupConfig> {
+    let keys = std::env::var("DEDUP_KEYS").ok()?;
+    let mode = match std::env::var("DEDUP_MODE") {
+        Ok(mode) => dedup::DedupMode::parse(&mode).unwrap_or_else(|| {
+            println!("⚠️  Ignoring DEDUP_MODE={}: expected hash or xor", mode);
+            dedup::DedupMode::HashSet
+        }),
+        Err(_) => dedup::DedupMode::HashSet,
+    };
+    let memory_bytes = size_from_env("DEDUP_MEMORY", DEFAULT_DEDUP_MEMORY_BYTES);
+    let spill_dir = std::env::var("DEDUP_SPILL_DIR").ok().map(std::path::PathBuf::from);
+    match dedup::DedupConfig::new(&keys, mode, memory_bytes, spill_dir) {
+        Ok(config) => {
+            println!("👯 Dropping appended records whose {} repeat, in up to {} bytes per file", config.keys.join(", "), memory_bytes);
+            Some(config)
+        }
+        Err(message) => {
+            println!("⚠️  Ignoring DEDUP_KEYS={}: {}", keys, message);
+            None
+        }
+    }
+}
+
+/// The column cipher, when `COLUMN_ENCRYPTION_KEYS` holds comma-separated
+/// `id:key` pairs, as a KMS or secret store would inject them: 32-byte
+/// keys in hex or base64, the first of which encrypts. `ENCRYPT_COLUMNS`
+/// names the columns, customer_name by default.
+fn encryption_config() -> Option<encryption::ColumnCipher> {
+    let keys = std::env::var("COLUMN_ENCRYPTION_KEYS").ok().filter(|keys| !keys.trim().is_empty())?;
+    let columns = std::env::var("ENCRYPT_COLUMNS").unwrap_or_else(|_| "customer_name".to_string());
+    match encryption::ColumnCipher::new(&columns, &keys) {
+        Ok(cipher) => {
+            println!("🔐 Encrypting {} in stored files with key {}", cipher.columns().join(", "), cipher.key_id());
+            Some(cipher)
+        }
+        // The keys themselves are never printed
+        Err(message) => {
+            println!("⚠️  Ignoring COLUMN_ENCRYPTION_KEYS: {}", message);
+            None
+        }
+    }
+}
+
+/// Roles of bearer tokens from `ROLE_TOKENS`, as `role:token` pairs; those
+/// in `DECRYPT_ROLES`, admin by default, see encrypted columns decrypted.
+fn role_tokens() -> encryption::RoleTokens {
+    let tokens = std::env::var("ROLE_TOKENS").unwrap_or_default();
+    let decrypting = std::env::var("DECRYPT_ROLES").unwrap_or_else(|_| "admin".to_string());
+    encryption::RoleTokens::new(&tokens, &decrypting).unwrap_or_else(|message| {
+        println!("⚠️  Ignoring ROLE_TOKENS: {}", message);
+        encryption::RoleTokens::default()
+    })
+}
+
+/// What a request may see of encrypted columns, from [`column_access`].
+#[derive(Clone, Default)]
+struct ColumnAccess {
+    /// The cipher whose columns the request sees masked; `None` when it
+    /// sees them decrypted or nothing is encrypted.
+    masked: Option<Arc<encryption::ColumnCipher>>,
+}
+
+impl ColumnAccess {
+    /// Whether the request sees `column` masked, so it must not be able to
+    /// look values of it up either.
+    fn hides(&self, column: &str) -> bool {
+        self.masked.as_ref().is_some_and(|cipher| cipher.encrypts(column))
+    }
+    
+    /// `record` as the request may see it.
+    fn reveal(&self, record: &SalesRecord) -> SalesRecord {
+        let mut record = record.clone();
+        if let Some(cipher) = &self.masked {
+            cipher.mask_record(&mut record);
+        }
+        record
+    }
+    
+    /// `records` as the request may see them, copied only when masked.
+    fn reveal_all(&self, records: &Arc<Vec<SalesRecord>>) -> Arc<Vec<SalesRecord>> {
+        match &self.masked {
+            Some(_) => Arc::new(records.iter().map(|record| self.reveal(record)).collect()),
+            None => records.clone(),
+        }
+    }
+}
+
+/// With encryption on, a request sees encrypted columns decrypted when its
+/// `Authorization: Bearer` token belongs to a role in `DECRYPT_ROLES`, and
+/// masked otherwise. A token that belongs to no role is 401.
+fn column_access(state: &SharedState, headers: &axum::http::HeaderMap) -> Result<ColumnAccess, ApiError> {
+    let app_state = lock_state(state)?;
+    let Some(cipher) = app_state.encryption.clone() else {
+        return Ok(ColumnAccess::default());
+    };
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim);
+    let role = match token {
+        Some(token) => Some(app_state.roles.role(token).ok_or_else(|| {
+            ApiError::new(StatusCode::UNAUTHORIZED, "invalid_token", "the bearer token belongs to no role in ROLE_TOKENS")
+        })?),
+        None => None,
+    };
+    let decrypted = role.is_some_and(|role| app_state.roles.may_decrypt(role));
+    Ok(ColumnAccess { masked: (!decrypted).then_some(cipher) })
+}
+
+/// The records of an append to `file` that are not duplicates, and how
+/// many were dropped. Called under the write lock with the file's `size`;
+/// the file's filter is first seeded from its rows when it has none or was
+/// left at another size, by a rewrite or a failed write.
+async fn drop_duplicates<'a>(
+    state: &SharedState,
+    file: &FileId,
+    dialect: &dialect::Dialect,
+    size: u64,
+    records: &'a [SalesRecord],
+) -> Result<(std::borrow::Cow<'a, [SalesRecord]>, usize), ApiError> {
+    let (config, entry, pool, cipher) = {
+        let mut app_state = lock_state(state)?;
+        let Some(config) = app_state.dedup.clone() else {
+            return Ok((std::borrow::Cow::Borrowed(records), 0));
+        };
+        let entry = app_state
+            .deduplicators
+            .entry(file.clone())
+            .or_insert_with(|| Arc::new(Mutex::new(DedupEntry { filter: dedup::Deduplicator::new(config.clone(), file.as_str()), size: None })))
+            .clone();
+        (config, entry, app_state.parser_pool.clone(), app_state.encryption.clone())
+    };
+    let (filtered, dialect, kept) = (file.clone(), dialect.clone(), records.to_vec());
+    let (kept, stats) = pool
+        .run(move || {
+            let mut entry = entry.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if entry.size != Some(size) {
+                entry.filter = dedup::Deduplicator::new(config, filtered.as_str());
+                seed_deduplicator(&mut entry.filter, &filtered, &dialect, cipher.as_deref(), size)?;
+            }
+            // Unknown until the rows are written, or left out by a failed write
+            entry.size = None;
+            let mut kept = kept;
+            entry.filter.retain(&mut kept);
+            Ok::<_, ApiError>((kept, entry.filter.stats()))
+        })
+        .await
+        .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "parse_failed", error.to_string()).detail("filename", file.as_str()))??;
+    lock_state(state)?.dedup_stats.insert(file.clone(), stats);
+    
+    let duplicates = records.len() - kept.len();
+    if duplicates > 0 {
+        log!("👯 Dropped {} duplicate records of {} appended to {}", duplicates, records.len(), file);
+    }
+    Ok((std::borrow::Cow::Owned(kept), duplicates))
+}
+
+/// Feeds the rows `file` holds to `filter`, decrypted, so appended copies
+/// of them are dropped. A file of `size` 0 has none.
+fn seed_deduplicator(
+    filter: &mut dedup::Deduplicator,
+    file: &FileId,
+    dialect: &dialect::Dialect,
+    cipher: Option<&encryption::ColumnCipher>,
+    size: u64,
+) -> Result<(), ApiError> {
+    if size == 0 {
+        return Ok(());
+    }
+    let path = file.path();
+    let source = std::fs::File::open(&path).map_err(|error| ApiError::read_failed(&path, error))?;
+    let mut reader = dialect.reader(false, std::io::BufReader::new(source));
+    let headers = reader.headers().map_err(|error| ApiError::invalid_csv(file.as_str(), &error))?.clone();
+    for row in reader.records() {
+        let row = row.map_err(|error| ApiError::invalid_csv(file.as_str(), &error))?;
+        let mut record: SalesRecord = row.deserialize(Some(&headers)).map_err(|error| ApiError::invalid_csv(file.as_str(), &error))?;
+        if let Some(cipher) = cipher {
+            cipher.decrypt_record(&mut record).map_err(|error| ApiError::decryption_failed(file.as_str(), error))?;
+        }
+        filter.admit(&record);
+    }
+    Ok(())
+}
+
+/// Worker side of /distributed/analyze: the /analyze aggregates of the rows
+/// starting in one byte range of a file, left unmerged.
+async fn analyze_range(
+    State(state): State<SharedState>,
+    request: Result<Json<distributed::RangeRequest>, JsonRejection>,
+) -> Result<Json<distributed::PartialAnalysis>, ApiError> {
+    let Json(request) = request?;
+    let range = request.range;
+    if range.start > range.end {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid_range", format!("range {}..{} ends before it starts", range.start, range.end)));
+    }
+    let file = FileId::resolve(&request.filename, &state).await?;
+    let path = file.path();
+    fs::metadata(&path)
+        .await
+        .map_err(|error| ApiError::read_failed(&path, error).detail("filename", file.as_str()))?;
+    
+    let (token, _guard) = request_token(&state)?;
+    let pool = lock_state(&state)?.parser_pool.clone();
+    let result = pool
+        .run(move || distributed::analyze_range(&path, range, || token.is_cancelled()))
+        .await
+        .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "parse_failed", error.to_string()).detail("filename", file.as_str()))?;
+    result.map(Json).map_err(|error| match error {
+        distributed::RangeError::Io(error) => ApiError::read_failed(&file.path(), error).detail("filename", file.as_str()),
+        distributed::RangeError::Row { byte, message } => {
+            ApiError::new(StatusCode::BAD_REQUEST, "invalid_csv", format!("row at byte {} of {}: {}", byte, file, message))
+                .detail("filename", file.as_str())
+                .detail("byte", byte)
+        }
+    })
+}
+
+/// Coordinator side: splits a file into byte ranges, has the workers in
+/// `WORKER_URLS` analyze them concurrently (ranges go round-robin) and
+/// merges their aggregates into an /analyze result. Workers read the file
+/// themselves, so it must be at the same path on every node.
+async fn distributed_analyze(
+    axum::extract::Path(filename): axum::extract::Path<String>,
+    query: Result<Query<DistributedQuery>, QueryRejection>,
+    State(state): State<SharedState>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let Query(params) = query?;
+    let rate = applied_rate(&state, params.currency.as_deref())?;
+    let start = std::time::Instant::now();
+    let coordinator = lock_state(&state)?.coordinator.clone();
+    let workers = coordinator.workers();
+    if workers.is_empty() {
+        return Err(no_workers());
+    }
+    let range_count = params.ranges.unwrap_or(workers.len());
+    if range_count == 0 || range_count > MAX_DISTRIBUTED_RANGES {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_ranges",
+            format!("ranges must be between 1 and {}", MAX_DISTRIBUTED_RANGES),
+        )
+        .detail("ranges", range_count));
+    }
+    let file = FileId::resolve(&filename, &state).await?;
+    require_standard_dialect(&state, &file)?;
+    let path = file.path();
+    let size = fs::metadata(&path)
+        .await
+        .map_err(|error| ApiError::read_failed(&path, error).detail("filename", file.as_str()))?
+        .len();
+    
+    let ranges = distributed::split_ranges(size, range_count);
+    let dispatches = ranges.iter().enumerate().map(|(index, range)| {
+        let worker = &workers[index % workers.len()];
+        let coordinator = &coordinator;
+        let path = &path;
+        async move { (worker, *range, coordinator.dispatch(worker, path, *range).await) }
+    });
+    let mut merged = distributed::PartialAnalysis::default();
+    let mut reports = Vec::new();
+    for (worker, range, result) in futures::future::join_all(dispatches).await {
+        let partial = result.map_err(|failure| {
+            ApiError::new(
+                StatusCode::BAD_GATEWAY,
+                "worker_failed",
+                format!("worker {} failed on bytes {}..{}: {}", failure.worker, range.start, range.end, failure.message()),
+            )
+            .detail("worker", &failure.worker)
+            .detail("range", range)
+            .detail("worker_status", failure.status)
+            .detail("worker_error", failure.error)
+        })?;
+        reports.push(distributed::RangeReport {
+            worker: worker.clone(),
+            range,
+            records: partial.records,
+            bytes: partial.bytes,
+            duration_ms: partial.duration_ms,
+        });
+        merged.merge(partial);
+    }
+    
+    let analysis = AnalysisResult {
+        total_records: merged.records,
+        total_revenue: merged.revenue,
+        // Zero rather than NaN for a file without rows
+        average_price: if merged.records > 0 { merged.price_sum / merged.records as f64 } else { 0.0 },
+        top_products: top_products(merged.products, params.limit),
+        processing_time_ms: start.elapsed().as_millis(),
+        cancelled: false,
+        from_statistics: false,
+        from_snapshot: false,
+        currency: None,
+        partitions: None,
+    };
+    let mut body = serde_json::json!(analysis.converted(rate));
+    body["distributed"] = serde_json::json!({
+        "workers": workers.len(),
+        "bytes": merged.bytes,
+        // Summed over the workers, against processing_time_ms of wall time
+        "worker_time_ms": merged.duration_ms,
+        "ranges": reports
+    });
+    Ok(Json(body))
+}
+
+async fn compare_processing_methods(
+    State(state): State<SharedState>,
+    query: Result<Query<CompareQuery>, QueryRejection>,
+    fields: Result<Query<FieldsQuery>, QueryRejection>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let Query(params) = query?;
+    let fields = requested_fields(fields)?;
+    log!("🔄 Running processing method comparison...");
+    
+    let (token, _guard) = request_token(&state)?;
+    let test_file = FileId::parse("small_data.csv")?;
+    let test_path = test_file.path();
+    let concurrency = params.concurrency.unwrap_or_else(processing::default_concurrency).max(1);
+    
+    // Every method would time zero rows
+    if let Ok(content) = fs::read(&test_path).await {
+        if !processing::has_data_rows(&content) {
+            return Err(ApiError::empty_csv(test_file.as_str(), &content));
+        }
+    }
+    let mut results = Vec::new();
+    
+    // Method 1: Standard async processing
+    if let Ok(content) = fs::read_to_string(&test_path).await {
+        let mut timer = PerformanceTimer::new("Standard Async Processing".to_string());
+        timer.add_bytes(content.len());
+        
+        let mut reader = ReaderBuilder::new().from_reader(content.as_bytes());
+        let mut count = 0;
+        for result in reader.deserialize() {
+            if token.is_cancelled() {
+                break;
+            }
+            let _record: SalesRecord = result.map_err(|error| ApiError::invalid_csv(test_file.as_str(), &error))?;
+            count += 1;
+        }
+        
+        let metrics = if token.is_cancelled() {
+            timer.cancelled(count)
+        } else {
+            timer.finish(count)
+        };
+        {
+            let mut app_state = lock_state(&state)?;
+            record_latency(&mut app_state, &metrics);
+            store_result(&mut app_state, "Standard Async", &test_file, &metrics);
+        }
+        results.push(serde_json::json!({
+            "method": "Standard Async",
+            "records": count,
+            "duration_ms": metrics.duration.as_millis(),
+            "records_per_second": metrics.records_per_second,
+            "bytes_processed": metrics.bytes_processed,
+            "mb_per_second": metrics.megabytes_per_second,
+            "cpu_time_ms": metrics.cpu_time().map(|cpu| cpu.as_millis()),
+            "cancelled": metrics.cancelled
+        }));
+    }
+    
+    // Method 2: Chunked processing
+    if let Ok(content) = fs::read_to_string(&test_path).await {
+        let mut timer = PerformanceTimer::new("Chunked Processing".to_string());
+        timer.add_bytes(content.len());
+        
+        let lines: Vec<&str> = processing::split_rows(&content);
+        let (header, data_lines) = lines.split_first().unwrap_or((&"", &[]));
+        let chunk_size = 1000;
+        let chunks: Vec<_> = data_lines.chunks(chunk_size).collect();
+        
+        let mut total_count = 0;
+        for chunk in chunks {
+            if token.is_cancelled() {
+                break;
+            }
+            
+            let chunk_data = format!("{}\n{}", header, chunk.join("\n"));
+            let mut reader = ReaderBuilder::new().from_reader(chunk_data.as_bytes());
+            
+            for result in reader.deserialize() {
+                let _record: SalesRecord = result.map_err(|error| ApiError::invalid_csv(test_file.as_str(), &error))?;
+                total_count += 1;
+            }
+            
+            // Yield to allow other tasks
+            tokio::task::yield_now().await;
+        }
+        
+        let metrics = if token.is_cancelled() {
+            timer.cancelled(total_count)
+        } else {
+            timer.finish(total_count)
+        };
+        {
+            let mut app_state = lock_state(&state)?;
+            record_latency(&mut app_state, &metrics);
+            store_result(&mut app_state, "Chunked Processing", &test_file, &metrics);
+        }
+        results.push(serde_json::json!({
+            "method": "Chunked Processing",
+            "records": total_count,
+            "duration_ms": metrics.duration.as_millis(),
+            "records_per_second": metrics.records_per_second,
+            "bytes_processed": metrics.bytes_processed,
+            "mb_per_second": metrics.megabytes_per_second,
+            "cpu_time_ms": metrics.cpu_time().map(|cpu| cpu.as_millis()),
+            "cancelled": metrics.cancelled
+        }));
+    }
+    
+    // Method 3: Streaming, parsing while the file is read
+    if let Ok(file) = fs::File::open(&test_path).await {
+        let mut timer = PerformanceTimer::new("Streaming Processing".to_string());
+        let mut reader = processing::StreamingReader::new(file, processing::STREAMING_BUFFER_SIZE);
+        
+        let mut count = 0;
+        while let Ok(Some(records)) = reader.next_batch().await {
+            if token.is_cancelled() {
+                break;
+            }
+            count += records.len();
+        }
+        timer.add_bytes(reader.bytes_read());
+        
+        let metrics = if token.is_cancelled() {
+            timer.cancelled(count)
+        } else {
+            timer.finish(count)
+        };
+        {
+            let mut app_state = lock_state(&state)?;
+            record_latency(&mut app_state, &metrics);
+            store_result(&mut app_state, "Streaming", &test_file, &metrics);
+        }
+        results.push(serde_json::json!({
+            "method": "Streaming",
+            "records": count,
+            "duration_ms": metrics.duration.as_millis(),
+            "records_per_second": metrics.records_per_second,
+            "bytes_processed": metrics.bytes_processed,
+            "mb_per_second": metrics.megabytes_per_second,
+            "cpu_time_ms": metrics.cpu_time().map(|cpu| cpu.as_millis()),
+            "cancelled": metrics.cancelled
+        }));
+    }
+    
+    // Method 4: Memory-mapped, parsing straight from the page cache
+    if let Ok(mapping) = processing::map_file(&test_path) {
+        let mut timer = PerformanceTimer::new("Memory-Mapped Processing".to_string());
+        timer.add_bytes(mapping.len());
+        
+        let mut reader = ReaderBuilder::new().from_reader(&mapping[..]);
+        let mut count = 0;
+        for result in reader.deserialize() {
+            if token.is_cancelled() {
+                break;
+            }
+            let _record: SalesRecord = result.map_err(|error| ApiError::invalid_csv(test_file.as_str(), &error))?;
+            count += 1;
+        }
+        
+        let metrics = if token.is_cancelled() {
+            timer.cancelled(count)
+        } else {
+            timer.finish(count)
+        };
+        {
+            let mut app_state = lock_state(&state)?;
+            record_latency(&mut app_state, &metrics);
+            store_result(&mut app_state, "Memory-Mapped", &test_file, &metrics);
+        }
+        results.push(serde_json::json!({
+            "method": "Memory-Mapped",
+            "records": count,
+            "duration_ms": metrics.duration.as_millis(),
+            "records_per_second": metrics.records_per_second,
+            "bytes_processed": metrics.bytes_processed,
+            "mb_per_second": metrics.megabytes_per_second,
+            "cpu_time_ms": metrics.cpu_time().map(|cpu| cpu.as_millis()),
+            "cancelled": metrics.cancelled
+        }));
+    }
+    
+    // Method 5: Async + parallel, one chunk per task
+    if let Ok(content) = fs::read_to_string(&test_path).await {
+        let mut timer = PerformanceTimer::new("Async + Parallel Processing".to_string());
+        timer.add_bytes(content.len());
+        timer.set_concurrency(concurrency);
+        
+        let data_lines = processing::split_rows(&content).len().saturating_sub(1);
+        let chunk_size = processing::chunk_size_for(data_lines, concurrency);
+        let count = processing::parse_async_parallel(&content, chunk_size, concurrency)
+            .await
+            .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "task_failed", error.to_string()).detail("filename", test_file.as_str()))?;
+        
+        let metrics = if token.is_cancelled() {
+            timer.cancelled(count)
+        } else {
+            timer.finish(count)
+        };
+        {
+            let mut app_state = lock_state(&state)?;
+            record_latency(&mut app_state, &metrics);
+            store_result(&mut app_state, "Async + Parallel", &test_file, &metrics);
+        }
+        results.push(serde_json::json!({
+            "method": "Async + Parallel",
+            "records": count,
+            "duration_ms": metrics.duration.as_millis(),
+            "records_per_second": metrics.records_per_second,
+            "bytes_processed": metrics.bytes_processed,
+            "mb_per_second": metrics.megabytes_per_second,
+            "cpu_time_ms": metrics.cpu_time().map(|cpu| cpu.as_millis()),
+            "concurrency": metrics.concurrency,
+            "cancelled": metrics.cancelled
+        }));
+    }
+    
+    let body = serde_json::json!({
+        "comparison": "CSV Processing Methods",
+        "test_file": test_file,
+        "results": results
+    });
+    #[cfg(feature = "columnar")]
+    let body = {
+        let mut body = body;
+        if let Ok(content) = fs::read_to_string(&test_path).await {
+            let (registry, pool) = {
+                let app_state = lock_state(&state)?;
+                (app_state.aggregators.clone(), app_state.parser_pool.clone())
+            };
+            let file = test_file.clone();
+            body["cache_layouts"] = pool
+                .run(move || compare_cache_layouts(&content, &file, &registry))
+                .await
+                .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "task_failed", error.to_string()))??;
+        }
+        body
+    };
+    Ok(Json(sparse(body, fields.as_ref())?))
+}
+
+/// Row and columnar caches of the same file side by side: what each holds
+/// on to, what building it takes beyond the parse, and the fastest of
+/// [`LAYOUT_COMPARISON_RUNS`] runs of /analyze and of every registered
+/// aggregation over it.
+#[cfg(feature = "columnar")]
+fn compare_cache_layouts(content: &str, file: &FileId, registry: &aggregators::Registry) -> Result<serde_json::Value, ApiError> {
+    let records: Vec<SalesRecord> = ReaderBuilder::new()
+        .from_reader(content.as_bytes())
+        .deserialize()
+        .collect::<csv::Result<_>>()
+        .map_err(|error| ApiError::invalid_csv(file.as_str(), &error))?;
+    let build_start = std::time::Instant::now();
+    let columns = columnar::ColumnarRecords::from_records(&records)
+        .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "cache_failed", error.to_string()).detail("filename", file.as_str()))?;
+    let build = build_start.elapsed();
+    
+    let names: Vec<String> = registry.list().into_iter().map(|info| info.name).collect();
+    let token = CancellationToken::new();
+    let fastest = |run: &mut dyn FnMut()| {
+        (0..LAYOUT_COMPARISON_RUNS)
+            .map(|_| {
+                let start = std::time::Instant::now();
+                run();
+                start.elapsed()
+            })
+            .min()
+            .unwrap_or_default()
+    };
+    let rows_analyze = fastest(&mut || {
+        analyze_records(&records, &token, None, std::time::Instant::now());
+    });
+    let columns_analyze = fastest(&mut || {
+        analyze_columns(&columns, None, std::time::Instant::now());
+    });
+    let rows_aggregate = fastest(&mut || {
+        registry.run(&names, &records, || false);
+    });
+    // Aggregations without a columnar form, e.g. enrichment ones, run over
+    // the records converted back, as /aggregate does
+    let columns_aggregate = fastest(&mut || {
+        if registry.run_from_columns(&names, &columns).is_none() {
+            registry.run(&names, &columns.to_records(), || false);
+        }
+    });
+    
+    let ms = |duration: std::time::Duration| duration.as_secs_f64() * 1_000.0;
+    let rows_bytes: usize = records.iter().map(SalesRecord::memory_bytes).sum();
+    let columns_bytes = columns.memory_bytes();
+    Ok(serde_json::json!({
+        "records": records.len(),
+        "aggregations": names,
+        "layouts": [
+            {
+                "layout": "rows",
+                "memory_bytes": rows_bytes,
+                "build_ms": 0.0,
+                "analyze_ms": ms(rows_analyze),
+                "aggregate_ms": ms(rows_aggregate)
+            },
+            {
+                "layout": "columnar",
+                "memory_bytes": columns_bytes,
+                "build_ms": ms(build),
+                "analyze_ms": ms(columns_analyze),
+                "aggregate_ms": ms(columns_aggregate)
+            }
+        ],
+        "memory_ratio": columns_bytes as f64 / rows_bytes.max(1) as f64,
+        "analyze_speedup": ms(rows_analyze) / ms(columns_analyze).max(0.001),
+        "aggregate_speedup": ms(rows_aggregate) / ms(columns_aggregate).max(0.001)
+    }))
+}
+
+/// What monitoring needs to alert before the server runs out of room:
+/// free space where files are written, the size of the record cache, work
+/// waiting for the parser pool, and when processing last succeeded.
+/// `degraded` once a data directory has less than `HEALTH_MIN_FREE_DISK`
+/// free.
+async fn health_check(State(state): State<SharedState>) -> Result<Json<serde_json::Value>, ApiError> {
+    let (cached, layout, pool, generation_jobs, last_processed_at, min_free_disk, uptime, upload_metrics, processing_metrics) = {
+        let app_state = lock_state(&state)?;
+        (
+            app_state.cached_data.values().cloned().collect::<Vec<_>>(),
+            app_state.cache_layout,
+            app_state.parser_pool.stats(),
+            app_state.generation_jobs.values().filter(|job| matches!(job.status, JobStatus::Running)).count(),
+            app_state.last_processed_at,
+            app_state.min_free_disk,
+            app_state.started_at.elapsed().as_secs_f64(),
+            app_state.upload_metrics.stats(),
+            app_state.processing_metrics.stats(),
+        )
+    };
+    // Summed without the lock, which other requests need while a large
+    // cache is walked
+    let cached_records: usize = cached.iter().map(|records| records.len()).sum();
+    let cache_bytes: usize = cached
+        .iter()
+        .map(|records| records.iter().map(SalesRecord::memory_bytes).sum::<usize>())
+        .sum();
+    #[cfg(feature = "columnar")]
+    let (cached_files, cached_records, cache_bytes) = {
+        let columns: Vec<_> = lock_state(&state)?.columnar_data.values().cloned().collect();
+        (
+            cached.len() + columns.len(),
+            cached_records + columns.iter().map(|columns| columns.rows()).sum::<usize>(),
+            cache_bytes + columns.iter().map(|columns| columns.memory_bytes()).sum::<usize>(),
+        )
+    };
+    #[cfg(not(feature = "columnar"))]
+    let cached_files = cached.len();
+    
+    let mut warnings = Vec::new();
+    let disks: Vec<serde_json::Value> = DATA_DIRS
+        .iter()
+        .map(|dir| {
+            let space = DiskSpace::of(std::path::Path::new(dir));
+            if let Some(space) = space.filter(|space| space.free_bytes < min_free_disk) {
+                warnings.push(format!("{} has {} bytes free, under the {}-byte minimum", dir, space.free_bytes, min_free_disk));
+            }
+            serde_json::json!({
+                "path": dir,
+                "free_bytes": space.map(|space| space.free_bytes),
+                "total_bytes": space.map(|space| space.total_bytes)
+            })
+        })
+        .collect();
+    
+    Ok(Json(serde_json::json!({
+        "status": if warnings.is_empty() { "ok" } else { "degraded" },
+        "warnings": warnings,
+        "uptime_seconds": uptime,
+        "disk": disks,
+        "min_free_disk_bytes": min_free_disk,
+        "cache": {
+            "layout": layout,
+            "files": cached_files,
+            "records": cached_records,
+            "memory_bytes": cache_bytes
+        },
+        // What a soak test watches for growth
+        "memory": {
+            "rss_bytes": performance_utils::resident_memory_bytes(),
+            "upload_metrics": upload_metrics.in_memory,
+            "processing_metrics": processing_metrics.in_memory
+        },
+        "queue": {
+            "parser_pool_queued": pool.queued,
+            "parser_pool_busy": pool.busy,
+            "generation_jobs_running": generation_jobs
+        },
+        "last_processed_at": last_processed_at
+    })))
+}
+
+async fn get_metrics(
+    State(state): State<SharedState>,
+    fields: Result<Query<FieldsQuery>, QueryRejection>,
+) -> Result<Response, ApiError> {
+    let fields = requested_fields(fields)?;
+    let wanted = |field: &str| fields.as_ref().is_none_or(|fields| fields.includes(field));
+    let serialization_failed =
+        |error: serde_json::Error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "serialization_failed", error.to_string());
+    // Serialized from snapshots of the metric logs, which grow with every
+    // request, once the state lock is let go and without first copying
+    // them into a `serde_json::Value`; with `?fields=`, only what was
+    // asked for is copied
+    let response = {
+        let app_state = lock_state(&state)?;
+        MetricsResponse {
+            upload_metrics: wanted("upload_metrics").then(|| app_state.upload_metrics.snapshot()),
+            processing_metrics: wanted("processing_metrics").then(|| app_state.processing_metrics.snapshot()),
+            spilled: wanted("spilled").then(|| MetricsSpilled {
+                upload_metrics: app_state.upload_metrics.stats(),
+                processing_metrics: app_state.processing_metrics.stats(),
+            }),
+            latency_percentiles: wanted("latency_percentiles").then(|| app_state.latencies.summary()),
+            runtime: runtime_metrics(app_state.started_at),
+            parser_pool: app_state.parser_pool.stats(),
+            cached_files: cached_files(&app_state).into_iter().cloned().collect(),
+            janitor: app_state.janitor.clone(),
+            retention: app_state.retention.clone(),
+            cost_guard: app_state.cost_guard.clone(),
+            dedup: app_state.dedup.as_ref().map(|config| DedupMetrics {
+                config: config.as_ref().clone(),
+                files: app_state.dedup_stats.iter().map(|(file, stats)| (file.as_str().to_string(), *stats)).collect(),
+            }),
+            #[cfg(feature = "nats")]
+            ingest: app_state.ingest.clone(),
+        }
+    };
+    let body = match &fields {
+        Some(fields) => {
+            let value = serde_json::to_value(&response).map_err(serialization_failed)?;
+            serde_json::to_vec(&sparse(value, Some(fields))?)
+        }
+        None => serde_json::to_vec(&response),
+    }
+    .map_err(serialization_failed)?;
+    
+    Ok(([(header::CONTENT_TYPE, "application/json")], body).into_response())
+}
+
+/// Body of GET /metrics. The stored metrics are left out when `?fields=`
+/// does not ask for them.
+#[derive(Serialize)]
+struct MetricsResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upload_metrics: Option<event_log::Snapshot<PerformanceMetrics>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    processing_metrics: Option<event_log::Snapshot<PerformanceMetrics>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spilled: Option<MetricsSpilled>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency_percentiles: Option<Vec<LatencySummary>>,
+    runtime: serde_json::Value,
+    parser_pool: worker_pool::WorkerPoolStats,
+    cached_files: Vec<FileId>,
+    janitor: janitor::JanitorStats,
+    retention: retention::RetentionStats,
+    /// The cost limits and how often they refused or were forced.
+    cost_guard: cost::CostGuard,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dedup: Option<DedupMetrics>,
+    #[cfg(feature = "nats")]
+    ingest: sources::nats::IngestStats,
+}
+
+/// Duplicate filtering on appends, with `DEDUP_KEYS`: its settings and
+/// each file's filter.
+#[derive(Serialize)]
+struct DedupMetrics {
+    config: dedup::DedupConfig,
+    files: std::collections::BTreeMap<String, dedup::DedupStats>,
+}
+
+/// How many runs of each metric log are in memory and how many have
+/// left it, spilled to disk or dropped.
+#[derive(Serialize)]
+struct MetricsSpilled {
+    upload_metrics: event_log::LogStats,
+    processing_metrics: event_log::LogStats,
+}
+
+/// The files in the record cache, whatever its layout.
+fn cached_files(app_state: &AppState) -> Vec<&FileId> {
+    let files = app_state.cached_data.keys();
+    #[cfg(feature = "columnar")]
+    let files = files.chain(app_state.columnar_data.keys());
+    files.collect()
+}
+
+/// Tokio runtime statistics. A busy ratio near 1 with a growing global
+/// queue means CSV work is hogging the workers and other requests wait;
+/// that work belongs on the parser pool, in `spawn_blocking` or on rayon. Blocking-pool and
+/// per-worker queue figures need a `--cfg tokio_unstable` build.
+fn runtime_metrics(started_at: std::time::Instant) -> serde_json::Value {
+    let metrics = tokio::runtime::Handle::current().metrics();
+    let workers = metrics.num_workers();
+    let uptime = started_at.elapsed().as_secs_f64();
+    let busy: Vec<f64> = (0..workers)
+        .map(|worker| metrics.worker_total_busy_duration(worker).as_secs_f64())
+        .collect();
+    
+    #[allow(unused_mut)]
+    let mut runtime = serde_json::json!({
+        "workers": workers,
+        "alive_tasks": metrics.num_alive_tasks(),
+        "global_queue_depth": metrics.global_queue_depth(),
+        "uptime_seconds": uptime,
+        "busy_ratio": busy.iter().sum::<f64>() / (uptime * workers as f64),
+        "worker_busy_seconds": busy,
+        "worker_park_counts": (0..workers).map(|worker| metrics.worker_park_count(worker)).collect::<Vec<_>>()
+    });
+    #[cfg(tokio_unstable)]
+    {
+        runtime["blocking_threads"] = metrics.num_blocking_threads().into();
+        runtime["idle_blocking_threads"] = metrics.num_idle_blocking_threads().into();
+        runtime["blocking_queue_depth"] = metrics.blocking_queue_depth().into();
+        runtime["spawned_tasks"] = metrics.spawned_tasks_count().into();
+        runtime["worker_local_queue_depths"] = (0..workers)
+            .map(|worker| metrics.worker_local_queue_depth(worker))
+            .collect::<Vec<_>>()
+            .into();
+    }
+    runtime
+}
+
+/// Upload and processing metrics rolled up by operation name, to see
+/// whether repeated runs got faster.
+async fn get_metrics_summary(State(state): State<SharedState>) -> Result<Json<serde_json::Value>, ApiError> {
+    let (uploads, processing) = {
+        let app_state = lock_state(&state)?;
+        (app_state.upload_metrics.snapshot(), app_state.processing_metrics.snapshot())
+    };
+    let runs: Vec<PerformanceMetrics> = uploads.iter().chain(processing.iter()).cloned().collect();
+    
+    Ok(Json(serde_json::json!({
+        "total_runs": runs.len(),
+        "spilled_runs": uploads.spilled + processing.spilled,
+        "operations": aggregate_by_operation(&runs)
+    })))
+}
+
+/// Keeps the latest result of `strategy` on `file` for the benchmark report.
+/// Cancelled runs are partial and left out.
+fn store_result(app_state: &mut AppState, strategy: &str, file: &FileId, metrics: &PerformanceMetrics) {
+    if metrics.cancelled {
+        return;
+    }
+    let label = format!("{} — {}", strategy, file);
+    app_state.benchmark_results.retain(|result| result.strategy != label);
+    app_state.benchmark_results.push(StrategyResult::from_metrics(label, metrics));
+}
+
+/// The stored /benchmark and /compare results in the versioned layout the
+/// benchmark binaries write with `--output FILE.json`. 404 until one of
+/// them has run.
+async fn benchmark_results(State(state): State<SharedState>) -> Result<Json<ResultsDocument>, ApiError> {
+    let results = lock_state(&state)?.benchmark_results.clone();
+    if results.is_empty() {
+        return Err(no_benchmark_results());
+    }
+    Ok(Json(ResultsDocument::new("axum_csv_server", &results)))
+}
+
+/// Runs POST /benchmark on every worker in `WORKER_URLS` and lines up
+/// their results by strategy and file. Each host's results are returned in
+/// the schema of GET /benchmark/results; hosts that fail are listed under
+/// `errors` instead, and the request fails only when all of them do.
+async fn cluster_benchmark(
+    query: Result<Query<ClusterBenchmarkQuery>, QueryRejection>,
+    State(state): State<SharedState>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let Query(params) = query?;
+    let coordinator = lock_state(&state)?.coordinator.clone();
+    let workers = coordinator.workers();
+    if workers.is_empty() {
+        return Err(no_workers());
+    }
+    log!("🏃 Running the benchmark on {} hosts...", workers.len());
+    
+    let outcomes = if params.sequential.unwrap_or(false) {
+        let mut outcomes = Vec::new();
+        for worker in workers {
+            outcomes.push((worker, coordinator.benchmark(worker).await));
+        }
+        outcomes
+    } else {
+        futures::future::join_all(workers.iter().map(|worker| {
+            let coordinator = &coordinator;
+            async move { (worker, coordinator.benchmark(worker).await) }
+        }))
+        .await
+    };
+    let mut hosts = std::collections::BTreeMap::new();
+    let mut errors = std::collections::BTreeMap::new();
+    for (worker, outcome) in outcomes {
+        let host = distributed::host_name(worker).to_string();
+        match outcome {
+            Ok(document) => {
+                hosts.insert(host, document);
+            }
+            Err(failure) => {
+                log!("  ⚠️  {}: {}", host, failure.message());
+                errors.insert(host, serde_json::json!({
+                    "message": failure.message(),
+                    "status": failure.status,
+                    "error": failure.error
+                }));
+            }
+        }
+    }
+    if hosts.is_empty() {
+        return Err(ApiError::new(StatusCode::BAD_GATEWAY, "worker_failed", "no host could run the benchmark").detail("errors", errors));
+    }
+    
+    Ok(Json(serde_json::json!({
+        "hosts": hosts,
+        "comparison": distributed::compare_hosts(&hosts),
+        "errors": errors
+    })))
+}
+
+fn supported_locales() -> Vec<&'static str> {
+    locale::SUPPORTED.iter().map(|locale| locale.tag).collect()
+}
+
+/// The locale a report is written for: `?locale=`, else the best of
+/// `Accept-Language`, else plain numbers and ISO dates. An unknown
+/// `?locale=` is 400; an `Accept-Language` naming no supported locale is
+/// not an error.
+fn report_locale(requested: Option<&str>, headers: &axum::http::HeaderMap) -> Result<locale::Locale, ApiError> {
+    if let Some(tag) = requested {
+        return locale::Locale::parse(tag).ok_or_else(|| {
+            ApiError::new(StatusCode::BAD_REQUEST, "invalid_locale", format!("unknown locale '{}'", tag))
+                .detail("locale", tag)
+                .detail("supported", supported_locales())
+        });
+    }
+    let negotiated = headers
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(locale::Locale::negotiate);
+    Ok(negotiated.unwrap_or_default())
+}
+
+/// The headers of a report to download as `filename`, written for `locale`.
+fn report_headers(format: report::ReportFormat, filename: &str, locale: &locale::Locale) -> [(header::HeaderName, String); 4] {
+    [
+        (header::CONTENT_TYPE, format.content_type().to_string()),
+        (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}.{}\"", filename, format.extension())),
+        (header::CONTENT_LANGUAGE, if locale.is_canonical() { "en".to_string() } else { locale.tag.to_string() }),
+        (header::VARY, header::ACCEPT_LANGUAGE.to_string()),
+    ]
+}
+
+fn no_benchmark_results() -> ApiError {
+    ApiError::new(StatusCode::NOT_FOUND, "no_results", "no benchmark results yet; run POST /benchmark or GET /compare first")
+}
+
+/// Renders the stored /benchmark and /compare results as a downloadable
+/// markdown or HTML report, for the locale of `?locale=` or
+/// `Accept-Language`. 404 until one of them has run.
+async fn benchmark_report(
+    query: Result<Query<ReportQuery>, QueryRejection>,
+    State(state): State<SharedState>,
+    headers: axum::http::HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let Query(params) = query?;
+    let locale = report_locale(params.locale.as_deref(), &headers)?;
+    let requested = params.format.as_deref().unwrap_or("html");
+    let format = report::ReportFormat::parse(requested).ok_or_else(|| {
+        ApiError::new(StatusCode::BAD_REQUEST, "invalid_format", format!("unknown report format '{}'", requested))
+            .detail("format", requested)
+            .detail("supported", ["html", "md"])
+    })?;
+    let results = lock_state(&state)?.benchmark_results.clone();
+    if results.is_empty() {
+        return Err(no_benchmark_results());
+    }
+    
+    let content = report::render("CSV Processing Benchmark", &results, Some(&SystemInfo::collect()), format, &locale);
+    Ok((report_headers(format, "benchmark-report", &locale), content))
+}
+
+/// Adds a run to the latency histograms. Cancelled runs stopped early, so
+/// their durations would drag the percentiles down and are left out.
+/// Writes the metrics, benchmark results, catalog, uploads and a manifest
+/// of the cached files to `STATE_SNAPSHOT_DIR` (`state/` by default), for
+/// starting a server elsewhere or later with `--restore`.
+async fn snapshot_state(State(state): State<SharedState>) -> Result<Json<serde_json::Value>, ApiError> {
+    let (mut snapshot, files) = {
+        let app_state = lock_state(&state)?;
+        let snapshot = StateSnapshot {
+            version: STATE_SNAPSHOT_VERSION,
+            created_at: chrono::Utc::now(),
+            upload_metrics: app_state.upload_metrics.snapshot().to_vec(),
+            processing_metrics: app_state.processing_metrics.snapshot().to_vec(),
+            benchmark_results: app_state.benchmark_results.clone(),
+            catalog: app_state.catalog.values().cloned().collect(),
+            uploads: app_state.uploads.values().cloned().collect(),
+            cache: Vec::new(),
+        };
+        let mut files: Vec<FileId> = app_state
+            .cached_data
+            .keys()
+            .chain(app_state.column_stats.keys())
+            .chain(app_state.hashed_files.keys())
+            .cloned()
+            .collect();
+        files.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        files.dedup();
+        (snapshot, files)
+    };
+    for file in files {
+        // Cached rows keep no fingerprint; they are as fresh as the file
+        // unless the statistics or hash say otherwise
+        let current = file_fingerprint(&file).await.ok();
+        let app_state = lock_state(&state)?;
+        let statistics = app_state.column_stats.get(&file);
+        let hashed = app_state.hashed_files.get(&file);
+        let Some(fingerprint) = statistics.map(|entry| entry.fingerprint).or(hashed.map(|hashed| hashed.fingerprint)).or(current) else {
+            continue;
+        };
+        snapshot.cache.push(CachedFile {
+            rows: app_state.cached_data.get(&file).map(|records| records.len()),
+            statistics: statistics.is_some(),
+            content_hash: hashed.filter(|hashed| hashed.fingerprint == fingerprint).map(|hashed| hashed.hash),
+            filename: file,
+            fingerprint,
+        });
+    }
+    
+    let dir = std::env::var("STATE_SNAPSHOT_DIR").unwrap_or_else(|_| "state".to_string());
+    let path = format!("{}/state-{}.json", dir, snapshot.created_at.format("%Y%m%d%H%M%S"));
+    let content = serde_json::to_vec_pretty(&snapshot)
+        .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "serialization_failed", error.to_string()))?;
+    let size_bytes = content.len();
+    let written = path.clone();
+    // Through a temporary file, so a crash never leaves half a snapshot to restore
+    tokio::task::spawn_blocking(move || {
+        std::fs::create_dir_all(&dir)?;
+        let temporary = format!("{}.tmp", written);
+        std::fs::write(&temporary, content)?;
+        std::fs::rename(&temporary, &written)
+    })
+    .await
+    .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "storage_failed", error.to_string()))?
+    .map_err(|error| {
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "storage_failed", format!("cannot write {}: {}", path, error)).detail("path", &path)
+    })?;
+    log!("📦 Saved the server state to {}", path);
+    
+    Ok(Json(serde_json::json!({
+        "path": path,
+        "size_bytes": size_bytes,
+        "created_at": snapshot.created_at,
+        "upload_metrics": snapshot.upload_metrics.len(),
+        "processing_metrics": snapshot.processing_metrics.len(),
+        "benchmark_results": snapshot.benchmark_results.len(),
+        "catalog": snapshot.catalog.len(),
+        "uploads": snapshot.uploads.len(),
+        "cached_files": snapshot.cache.len(),
+        "restore": format!("cargo run --release --example axum_csv_server -- --restore {}", path)
+    })))
+}
+
+/// Loads a [`StateSnapshot`] into the fresh `state`. Latency histograms are
+/// rebuilt from the metrics, and files that were cached and have not
+/// changed since are parsed again in the background, their statistics
+/// recomputed if they had some. Returns what was restored, for the log.
+fn restore_state(state: &SharedState, path: &str, shutdown: &CancellationToken) -> Result<String, Box<dyn std::error::Error>> {
+    let snapshot: StateSnapshot = serde_json::from_slice(&std::fs::read(path)?)?;
+    if snapshot.version != STATE_SNAPSHOT_VERSION {
+        return Err(format!("snapshot version {} is not {}", snapshot.version, STATE_SNAPSHOT_VERSION).into());
+    }
+    let summary = format!(
+        "{} metric(s), {} catalog file(s), {} upload(s) and {} cached file(s) of {}",
+        snapshot.upload_metrics.len() + snapshot.processing_metrics.len(),
+        snapshot.catalog.len(),
+        snapshot.uploads.len(),
+        snapshot.cache.len(),
+        snapshot.created_at.format("%Y-%m-%d %H:%M:%S UTC")
+    );
+    {
+        let mut app_state = state.lock().map_err(|_| "the state lock is poisoned")?;
+        for metrics in snapshot.upload_metrics.iter().chain(&snapshot.processing_metrics) {
+            if !metrics.cancelled {
+                app_state.latencies.record(&metrics.operation, metrics.duration);
+            }
+        }
+        app_state.upload_metrics.replace(snapshot.upload_metrics);
+        app_state.processing_metrics.replace(snapshot.processing_metrics);
+        app_state.benchmark_results = snapshot.benchmark_results;
+        app_state.catalog = snapshot.catalog.into_iter().map(|entry| (entry.filename.clone(), entry)).collect();
+        app_state.uploads = snapshot.uploads.into_iter().map(|upload| (upload.id.clone(), upload)).collect();
+        for cached in &snapshot.cache {
+            if let Some(hash) = cached.content_hash {
+                app_state.hashed_files.insert(cached.filename.clone(), HashedFile { fingerprint: cached.fingerprint, hash });
+            }
+        }
+    }
+    
+    let state = state.clone();
+    let token = shutdown.child_token();
+    tokio::spawn(async move {
+        let mut warmed = 0;
+        for cached in snapshot.cache.into_iter().filter(|cached| cached.rows.is_some() || cached.statistics) {
+            if file_fingerprint(&cached.filename).await.ok() != Some(cached.fingerprint) {
+                continue;
+            }
+            let Ok((records, false)) = cached_records(&state, &cached.filename, &token).await else {
+                continue;
+            };
+            if cached.statistics {
+                compute_statistics(&state, &cached.filename, cached.fingerprint, records);
+            }
+            warmed += 1;
+        }
+        if warmed > 0 {
+            println!("📦 Parsed {} cached file(s) again", warmed);
+        }
+    });
+    Ok(summary)
+}
+
+fn record_latency(app_state: &mut AppState, metrics: &PerformanceMetrics) {
+    if !metrics.cancelled {
+        app_state.latencies.record(&metrics.operation, metrics.duration);
+        app_state.last_processed_at = Some(chrono::Utc::now());
+    }
+}
+
+/// Records the latency of a processing run and appends it to the
+/// processing metrics. The append happens once the state lock is let go,
+/// and the log spills to disk on its own thread, so a run finishing never
+/// waits on the disk.
+fn record_processing(state: &SharedState, metrics: PerformanceMetrics) -> Result<(), ApiError> {
+    let log = {
+        let mut app_state = lock_state(state)?;
+        record_latency(&mut app_state, &metrics);
+        app_state.processing_metrics.clone()
+    };
+    log.push(metrics);
+    Ok(())
+}
+
+/// The log of `kind` metrics: the latest `METRICS_IN_MEMORY` runs (10,000
+/// by default) stay in memory and older ones are appended to
+/// `METRICS_SPILL_DIR/<kind>-metrics.jsonl` (`state/` by default), which
+/// each start begins afresh. `METRICS_SPILL_DIR=off` drops them instead.
+fn metrics_log(kind: &str) -> event_log::EventLog<PerformanceMetrics> {
+    let in_memory = std::env::var("METRICS_IN_MEMORY").ok().and_then(|value| value.parse().ok()).unwrap_or(10_000);
+    let dir = std::env::var("METRICS_SPILL_DIR").unwrap_or_else(|_| "state".to_string());
+    let spill_path = (dir != "off").then(|| std::path::Path::new(&dir).join(format!("{}-metrics.jsonl", kind)));
+    event_log::EventLog::new(in_memory, spill_path)
+}
+
+async fn run_benchmark(
+    State(state): State<SharedState>,
+    fields: Result<Query<FieldsQuery>, QueryRejection>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let fields = requested_fields(fields)?;
+    log!("🏃 Running comprehensive CSV processing benchmark...");
+    
+    let (token, _guard) = request_token(&state)?;
+    let files = ["small_data.csv", "medium_data.csv", "large_data.csv"];
+    let mut benchmark_results = Vec::new();
+    let mut skipped = Vec::new();
+    
+    for filename in files {
+        if token.is_cancelled() {
+            break;
+        }
+        
+        let file = FileId::parse(filename)?;
+        let file_path = file.path();
+        
+        if !std::path::Path::new(&file_path).exists() {
+            continue;
+        }
+        
+        log!("  Benchmarking: {}", filename);
+        
+        // One timer, split into the read and parse phases
+        let mut timer = PerformanceTimer::new(format!("Benchmark: {}", filename));
+        timer.phase("read");
+        let content = match fs::read_to_string(&file_path).await {
+            Ok(content) => content,
+            Err(_) => continue,
+        };
+        timer.add_bytes(content.len());
+        if !processing::has_data_rows(content.as_bytes()) {
+            log!("  ⚠️  {} has no data rows, skipping", filename);
+            skipped.push(serde_json::json!({ "file": filename, "reason": "no data rows" }));
+            continue;
+        }
+        
+        timer.phase("parse");
+        let mut reader = ReaderBuilder::new().from_reader(content.as_bytes());
+        let mut records = Vec::new();
+        
+        for result in reader.deserialize() {
+            if token.is_cancelled() {
+                break;
+            }
+            
+            match result {
+                Ok(record) => {
+                    let record: SalesRecord = record;
+                    records.push(record);
+                }
+                Err(_) => break,
+            }
+        }
+        
+        let metrics = if token.is_cancelled() {
+            timer.cancelled(records.len())
+        } else {
+            timer.finish(records.len())
+        };
+        {
+            let mut app_state = lock_state(&state)?;
+            record_latency(&mut app_state, &metrics);
+            store_result(&mut app_state, "Read + Parse", &file, &metrics);
+        }
+        let read_secs = metrics.phases[0].duration.as_secs_f64();
+        let parse_secs = metrics.phases[1].duration.as_secs_f64();
+        
+        benchmark_results.push(serde_json::json!({
+            "file": filename,
+            "file_size_bytes": content.len(),
+            "records_count": records.len(),
+            "duration_ms": metrics.duration.as_millis(),
+            "phases_ms": metrics.phases_ms(),
+            "read_performance": {
+                "duration_ms": metrics.phases[0].duration.as_millis(),
+                "bytes_per_second": content.len() as f64 / read_secs,
+                "mb_per_second": content.len() as f64 / 1_000_000.0 / read_secs
+            },
+            "parse_performance": {
+                "duration_ms": metrics.phases[1].duration.as_millis(),
+                "records_per_second": records.len() as f64 / parse_secs,
+                "bytes_processed": metrics.bytes_processed,
+                "mb_per_second": content.len() as f64 / 1_000_000.0 / parse_secs,
+                "cpu_time_ms": metrics.cpu_time().map(|cpu| cpu.as_millis()),
+                "cancelled": metrics.cancelled
+            }
+        }));
+    }
+    
+    let body = serde_json::json!({
+        "benchmark": "CSV Processing Performance",
+        "timestamp": chrono::Utc::now(),
+        "system": SystemInfo::collect(),
+        "cancelled": token.is_cancelled(),
+        "results": benchmark_results,
+        "skipped": skipped
+    });
+    Ok(Json(sparse(body, fields.as_ref())?))
+}
+
+/// Measures /analyze on each file three ways: parsing the file every time,
+/// from cached records, and from cached column statistics. Answers with
+/// latencies, what each cache costs to build and hold, and findings that
+/// compare them; `format=md` or `html` makes it a report to download, for
+/// the locale of `?locale=` or `Accept-Language`. The server's own caches
+/// are neither used nor filled.
+async fn cache_benchmark(
+    query: Result<Query<CacheBenchmarkQuery>, QueryRejection>,
+    State(state): State<SharedState>,
+    headers: axum::http::HeaderMap,
+) -> Result<Response, ApiError> {
+    let Query(params) = query?;
+    let iterations = params.iterations.unwrap_or(5);
+    if !(1..=100).contains(&iterations) {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid_iterations", "iterations must be between 1 and 100")
+            .detail("iterations", iterations));
+    }
+    let format = params
+        .format
+        .as_deref()
+        .map(|requested| {
+            report::ReportFormat::parse(requested).ok_or_else(|| {
+                ApiError::new(StatusCode::BAD_REQUEST, "invalid_format", format!("unknown report format '{}'", requested))
+                    .detail("format", requested)
+                    .detail("supported", ["html", "md"])
+            })
+        })
+        .transpose()?;
+    let locale = report_locale(params.locale.as_deref(), &headers)?;
+    let files = match params.files.as_deref() {
+        Some(names) => {
+            let mut files = Vec::new();
+            for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+                files.push(FileId::resolve(name, &state).await?);
+            }
+            files
+        }
+        None => ["small_data.csv", "medium_data.csv", "large_data.csv"]
+            .into_iter()
+            .filter_map(|name| FileId::parse(name).ok())
+            .filter(|file| std::path::Path::new(&file.path()).exists())
+            .collect(),
+    };
+    log!("🏃 Benchmarking /analyze caching over {} file(s), {} runs each...", files.len(), iterations);
+    
+    let (token, _guard) = request_token(&state)?;
+    let mut results = Vec::new();
+    for file in &files {
+        if token.is_cancelled() {
+            break;
+        }
+        log!("  Benchmarking: {}", file);
+        results.push(benchmark_caching(&state, file, iterations, &token).await?);
+    }
+    let benchmark = cache_benchmark::CacheBenchmark::new(iterations, results, token.is_cancelled());
+    for finding in &benchmark.findings {
+        log!("  📈 {}", finding);
+    }
+    
+    let Some(format) = format else {
+        return Ok(Json(benchmark).into_response());
+    };
+    Ok((report_headers(format, "cache-benchmark", &locale), benchmark.render(format, &locale)).into_response())
+}
+
+/// Times a filtered analysis of a file two ways: reading and parsing the
+/// whole file and filtering its rows, the way an unpartitioned file is
+/// served without a cache, and reading only the partitions the filter
+/// selects. The file is partitioned as CSV first when it is not already.
+async fn partition_benchmark(
+    query: Result<Query<PartitionBenchmarkQuery>, QueryRejection>,
+    filter: Result<Query<FilterQuery>, QueryRejection>,
+    State(state): State<SharedState>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let Query(params) = query?;
+    let filter = requested_filter(filter)?.ok_or_else(|| {
+        ApiError::new(StatusCode::BAD_REQUEST, "missing_filter", "without region, from or to every partition is read, so there is nothing to prune")
+            .detail("example", "?region=North&from=2024-06&to=2024-06")
+    })?;
+    let iterations = params.iterations.unwrap_or(3);
+    if !(1..=100).contains(&iterations) {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid_iterations", "iterations must be between 1 and 100")
+            .detail("iterations", iterations));
+    }
+    let file = FileId::resolve(params.filename.as_deref().unwrap_or("large_data.csv"), &state).await?;
+    let (token, _guard) = request_token(&state)?;
+    let (dir, dataset) = match current_dataset(&file).await? {
+        Some(current) => current,
+        None => (dataset_dir(&file), write_partitions(&state, &file, partitions::PartitionFormat::Csv, &token).await?),
+    };
+    log!("🏃 Benchmarking partition pruning on {}, {} runs each...", file, iterations);
+    
+    let (pool, cipher) = {
+        let app_state = lock_state(&state)?;
+        (app_state.parser_pool.clone(), app_state.encryption.clone())
+    };
+    let dialect = file_dialect(&state, &file)?;
+    let mut latencies = LatencyRecorder::new();
+    let mut size_bytes = 0;
+    let mut scan = partitions::PartitionScan::default();
+    let mut results = None;
+    for _ in 0..iterations {
+        if token.is_cancelled() {
+            break;
+        }
+        let start = std::time::Instant::now();
+        let content = fs::read_to_string(file.path())
+            .await
+            .map_err(|error| ApiError::read_failed(&file.path(), error).detail("filename", file.as_str()))?;
+        size_bytes = content.len() as u64;
+        let (parse_file, parse_dialect, parse_cipher, parse_token) = (file.clone(), dialect.clone(), cipher.clone(), token.clone());
+        let (progress, _) = tokio::sync::mpsc::unbounded_channel();
+        let (parsed, _) = pool
+            .run(move || parse_records(&content, &parse_file, &parse_dialect, parse_cipher.as_deref(), &parse_token, progress))
+            .await
+            .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "parse_failed", error.to_string()))??;
+        let selected: Vec<SalesRecord> = parsed.into_iter().filter(|record| filter.matches_record(record)).collect();
+        let full = analyze_records(&selected, &token, None, start);
+        latencies.record("full scan", start.elapsed());
+        
+        let start = std::time::Instant::now();
+        let (records, partition_scan) = read_partitions(&pool, cipher.clone(), dir.clone(), dataset.clone(), filter.clone()).await?;
+        let pruned = analyze_records(&records, &token, None, start);
+        latencies.record("partition pruning", start.elapsed());
+        scan = partition_scan;
+        results = Some((full, pruned));
+    }
+    
+    let summaries = latencies.summary();
+    let latency = |operation: &str| summaries.iter().find(|summary| summary.operation == operation).cloned();
+    let (full_scan, pruning) = (latency("full scan"), latency("partition pruning"));
+    let speedup = full_scan
+        .as_ref()
+        .zip(pruning.as_ref())
+        .map(|(full_scan, pruning)| full_scan.mean_ms / pruning.mean_ms.max(0.001));
+    // The partitions hold the rows in another order, so sums may differ in
+    // their last digits
+    let matching = results.as_ref().map(|(full, pruned)| {
+        full.total_records == pruned.total_records
+            && (full.total_revenue - pruned.total_revenue).abs() <= full.total_revenue.abs() * 1e-9
+    });
+    if let Some(speedup) = speedup {
+        log!(
+            "  📈 {}: {} of {} partitions, {:.1}% of the bytes, read {:.1}× faster",
+            file,
+            scan.scanned,
+            dataset.partitions.len(),
+            scan.bytes_read as f64 / size_bytes.max(1) as f64 * 100.0,
+            speedup
+        );
+    }
+    Ok(Json(serde_json::json!({
+        "filename": file,
+        "iterations": iterations,
+        "format": dataset.format,
+        "file_bytes": size_bytes,
+        "partitions": dataset.partitions.len(),
+        "scanned": scan.scanned,
+        "pruned": scan.pruned,
+        "bytes_read": scan.bytes_read,
+        "rows_selected": results.as_ref().map(|(_, pruned)| pruned.total_records),
+        "full_scan": full_scan,
+        "partition_pruning": pruning,
+        "speedup": speedup,
+        "results_match": matching,
+        "cancelled": token.is_cancelled()
+    })))
+}
+
+/// Times `iterations` analyses of `file` under each caching strategy, the
+/// way /analyze runs them: reading and parsing on the parser pool, then
+/// aggregating the records, or answering from statistics.
+async fn benchmark_caching(
+    state: &SharedState,
+    file: &FileId,
+    iterations: usize,
+    token: &CancellationToken,
+) -> Result<cache_benchmark::FileResult, ApiError> {
+    use cache_benchmark::{CacheStrategy, Measurement};
+    
+    let (pool, cipher) = {
+        let app_state = lock_state(state)?;
+        (app_state.parser_pool.clone(), app_state.encryption.clone())
+    };
+    let dialect = file_dialect(state, file)?;
+    let mut uncached = Vec::with_capacity(iterations);
+    let mut parse_time = std::time::Duration::ZERO;
+    let mut records = Arc::new(Vec::new());
+    let mut size_bytes = 0;
+    for _ in 0..iterations {
+        let start = std::time::Instant::now();
+        let content = fs::read_to_string(file.path())
+            .await
+            .map_err(|error| ApiError::read_failed(&file.path(), error).detail("filename", file.as_str()))?;
+        size_bytes = content.len() as u64;
+        let (parse_file, parse_dialect, parse_cipher, parse_token) = (file.clone(), dialect.clone(), cipher.clone(), token.clone());
+        // Nobody listens to the progress of an uncached parse
+        let (progress, _) = tokio::sync::mpsc::unbounded_channel();
+        let (parsed, cancelled) = pool
+            .run(move || parse_records(&content, &parse_file, &parse_dialect, parse_cipher.as_deref(), &parse_token, progress))
+            .await
+            .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "parse_failed", error.to_string()))??;
+        parse_time += start.elapsed();
+        records = Arc::new(parsed);
+        analyze_records(&records, token, None, start);
+        uncached.push(start.elapsed());
+        if cancelled {
+            break;
+        }
+    }
+    let parse_time = parse_time / uncached.len().max(1) as u32;
+    
+    let cached = (0..iterations)
+        .map(|_| {
+            let start = std::time::Instant::now();
+            analyze_records(&records, token, None, start);
+            start.elapsed()
+        })
+        .collect();
+    
+    let start = std::time::Instant::now();
+    let computed = records.clone();
+    let statistics = pool
+        .run(move || column_stats::ColumnStatistics::compute(&computed))
+        .await
+        .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "parse_failed", error.to_string()))?;
+    let compute_time = start.elapsed();
+    let mut from_statistics = Vec::with_capacity(iterations);
+    for _ in 0..iterations {
+        let start = std::time::Instant::now();
+        if analyze_statistics(&statistics, None, start).is_none() {
+            break;
+        }
+        from_statistics.push(start.elapsed());
+    }
+    
+    let mut measurements = vec![
+        Measurement { strategy: CacheStrategy::None, fill: std::time::Duration::ZERO, footprint_bytes: 0, runs: uncached },
+        Measurement {
+            strategy: CacheStrategy::Records,
+            fill: parse_time,
+            footprint_bytes: (records.capacity() * std::mem::size_of::<SalesRecord>()
+                + records.iter().map(|record| record.memory_bytes() - std::mem::size_of::<SalesRecord>()).sum::<usize>()) as u64,
+            runs: cached,
+        },
+    ];
+    let statistics_available = !from_statistics.is_empty();
+    if statistics_available {
+        measurements.push(Measurement {
+            strategy: CacheStrategy::Statistics,
+            fill: parse_time + compute_time,
+            footprint_bytes: serde_json::to_vec(&statistics).map_or(0, |json| json.len() as u64),
+            runs: from_statistics,
+        });
+    }
+    let mut result = cache_benchmark::FileResult::new(file.to_string(), size_bytes, records.len(), measurements);
+    if !statistics_available {
+        result.unavailable.push((
+            CacheStrategy::Statistics,
+            format!("it has more than {} products to group by", column_stats::MAX_GROUP_VALUES),
+        ));
+    }
+    Ok(result)
+}
+
+/// Generates `sample_data/generated_<rows>_<seed>.csv` with the same
+/// generator as the `generate_data` binary. Small requests answer once the file is written;
+/// larger ones return a job id straight away.
+async fn generate_data(
+    State(state): State<SharedState>,
+    request: Result<Json<GenerateRequest>, JsonRejection>,
+) -> Result<(StatusCode, Json<serde_json::Value>), ApiError> {
+    let Json(request) = request?;
+    let error_rate = request.error_rate.unwrap_or(0.0);
+    if request.rows == 0 {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid_rows", "rows must be at least 1").detail("rows", request.rows));
+    }
+    if !(0.0..=1.0).contains(&error_rate) {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid_error_rate", "error_rate must be between 0 and 1")
+            .detail("error_rate", error_rate));
+    }
+    let suffix = match request.seed {
+        Some(seed) => seed.to_string(),
+        None => chrono::Utc::now().timestamp_millis().to_string(),
+    };
+    let filename = FileId::parse(&format!("generated_{}_{}.csv", request.rows, suffix))?;
+    
+    if request.rows <= BACKGROUND_GENERATION_ROWS {
+        let (token, _guard) = request_token(&state)?;
+        run_generator(&request, &filename, token, None).await.map_err(|error| {
+            ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "generation_failed", error).detail("filename", filename.as_str())
+        })?;
+        let entry = register_generated_file(&state, &request, &filename).await?;
+        
+        return Ok((StatusCode::CREATED, Json(serde_json::json!({
+            "status": "completed",
+            "file": entry,
+            "process": format!("/process/{}", filename)
+        }))));
+    }
+    
+    let (job, token) = {
+        let mut app_state = lock_state(&state)?;
+        let id = app_state.next_job_id;
+        app_state.next_job_id += 1;
+        let job = GenerationJob {
+            id,
+            filename: filename.clone(),
+            rows: request.rows,
+            status: JobStatus::Running,
+            error: None,
+            request_id: request_log::current(),
+            throughput: Vec::new(),
+        };
+        app_state.generation_jobs.insert(id, job.clone());
+        (job, app_state.shutdown.child_token())
+    };
+    
+    let job_state = state.clone();
+    let job_id = job.id;
+    request_log::spawn(async move {
+        let result = match run_generator(&request, &filename, token, Some((job_state.clone(), job_id))).await {
+            Ok(()) => register_generated_file(&job_state, &request, &filename)
+                .await
+                .map(|_| ())
+                .map_err(|error| error.message),
+            Err(error) => Err(error),
+        };
+        
+        let Ok(mut app_state) = job_state.lock() else {
+            return;
+        };
+        if let Some(job) = app_state.generation_jobs.get_mut(&job_id) {
+            match result {
+                Ok(()) => job.status = JobStatus::Completed,
+                Err(error) => {
+                    job.status = JobStatus::Failed;
+                    job.error = Some(error);
+                }
+            }
+        }
+    });
+    
+    Ok((StatusCode::ACCEPTED, Json(serde_json::json!({
+        "status": "running",
+        "job": job,
+        "poll": format!("/generate/{}", job_id)
+    }))))
+}
+
+async fn get_generation_job(
+    path: Result<axum::extract::Path<u64>, PathRejection>,
+    State(state): State<SharedState>,
+) -> Result<Json<GenerationJob>, ApiError> {
+    let axum::extract::Path(id) = path?;
+    let app_state = lock_state(&state)?;
+    app_state
+        .generation_jobs
+        .get(&id)
+        .cloned()
+        .map(Json)
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, "job_not_found", format!("no generation job {}", id)).detail("id", id))
+}
+
+async fn list_generation_jobs(State(state): State<SharedState>) -> Result<Json<serde_json::Value>, ApiError> {
+    let app_state = lock_state(&state)?;
+    let mut jobs: Vec<_> = app_state.generation_jobs.values().cloned().collect();
+    jobs.sort_by_key(|job| job.id);
+    
+    Ok(Json(serde_json::json!({
+        "count": jobs.len(),
+        "jobs": jobs
+    })))
+}
+
+/// The versions of a file uploaded under one name, oldest first, each with
+/// its columns, the renames it is read with and how its header differed
+/// from the version before.
+async fn upload_schema_history(
+    axum::extract::Path(filename): axum::extract::Path<String>,
+    State(state): State<SharedState>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let app_state = lock_state(&state)?;
+    let mut versions: Vec<&UploadEntry> = app_state.uploads.values().filter(|upload| upload.filename == filename).collect();
+    if versions.is_empty() {
+        return Err(ApiError::new(StatusCode::NOT_FOUND, "upload_not_found", format!("nothing was uploaded as {}", filename))
+            .detail("filename", &filename));
+    }
+    versions.sort_by_key(|upload| upload.uploaded_at);
+    let versions: Vec<_> = versions
+        .iter()
+        .map(|upload| {
+            serde_json::json!({
+                "id": upload.id,
+                "file_id": upload.file_id,
+                "uploaded_at": upload.uploaded_at,
+                "columns": upload.columns,
+                "renames": upload.dialect.renames,
+                "diff": upload.schema_diff
+            })
+        })
+        .collect();
+    
+    Ok(Json(serde_json::json!({
+        "filename": filename,
+        "versions": versions.len(),
+        "history": versions
+    })))
+}
+
+/// The uploads on disk, with when the retention rules archive them, the
+/// archived uploads, with when they are deleted, and the latest entries of
+/// the archival audit log.
+async fn files_status(State(state): State<SharedState>) -> Result<Json<serde_json::Value>, ApiError> {
+    let (policy, stats, upload_ids) = {
+        let app_state = lock_state(&state)?;
+        let upload_ids: HashMap<FileId, String> =
+            app_state.uploads.values().map(|upload| (upload.file_id.clone(), upload.id.clone())).collect();
+        (app_state.retention_policy.clone(), app_state.retention.clone(), upload_ids)
+    };
+    let dictionary_id = lock_state(&state)?.dictionary.as_ref().map(|trained| trained.dictionary.id);
+    let archive_dir = policy.as_ref().map_or_else(archive_dir, |policy| policy.archive_dir.clone());
+    let listed = tokio::task::spawn_blocking(move || {
+        let uploads = retention::list(std::path::Path::new(DATA_DIRS[1]), &[".csv"])?;
+        let archives: Vec<_> = retention::list(&archive_dir, &retention::ARCHIVE_SUFFIXES)?
+            .into_iter()
+            .map(|archive| {
+                let dictionary_id = archive_dictionary_id(&archive.path);
+                (archive, dictionary_id)
+            })
+            .collect();
+        let events = retention::recent_events(&archive_dir.join(retention::AUDIT_LOG), RECENT_ARCHIVAL_EVENTS)?;
+        Ok::<_, std::io::Error>((uploads, archives, events))
+    })
+    .await
+    .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "task_failed", error.to_string()))?;
+    let (mut uploads, mut archives, events) = listed.map_err(|error| ApiError::read_failed(DATA_DIRS[1], error))?;
+    uploads.sort_by_key(|upload| upload.modified);
+    archives.sort_by_key(|(archive, _)| archive.modified);
+    
+    let after = |modified: std::time::SystemTime, max_age: Option<std::time::Duration>| {
+        max_age.map(|max_age| chrono::DateTime::<chrono::Utc>::from(modified + max_age))
+    };
+    let total_bytes: u64 = uploads.iter().map(|upload| upload.bytes).sum();
+    let uploads: Vec<_> = uploads
+        .iter()
+        .map(|upload| {
+            let file = FileId::new(DATA_DIRS[1], &upload.path.file_name().unwrap_or_default().to_string_lossy());
+            serde_json::json!({
+                "filename": file,
+                "upload_id": upload_ids.get(&file),
+                "size_bytes": upload.bytes,
+                "modified_at": chrono::DateTime::<chrono::Utc>::from(upload.modified),
+                "archive_after": after(upload.modified, policy.as_ref().and_then(|policy| policy.max_age))
+            })
+        })
+        .collect();
+    let archived: Vec<_> = archives
+        .iter()
+        .map(|(archive, dictionary_id)| {
+            serde_json::json!({
+                "archive": archive.path,
+                "dictionary_id": dictionary_id,
+                "size_bytes": archive.bytes,
+                "archived_at": chrono::DateTime::<chrono::Utc>::from(archive.modified),
+                "delete_after": after(archive.modified, policy.as_ref().and_then(|policy| policy.archive_max_age))
+            })
+        })
+        .collect();
+    let rules = policy.as_ref().map(|policy| {
+        serde_json::json!({
+            "max_age_seconds": policy.max_age.map(|max_age| max_age.as_secs()),
+            "max_total_bytes": policy.max_total_bytes,
+            "archive_dir": policy.archive_dir,
+            "archive_retention_seconds": policy.archive_max_age.map(|max_age| max_age.as_secs()),
+            "interval_seconds": stats.interval_seconds,
+            "compression": if dictionary_id.is_some() { "zstd" } else { "gzip" },
+            "dictionary_id": dictionary_id
+        })
+    });
+    
+    Ok(Json(serde_json::json!({
+        "retention": rules,
+        "uploads": uploads,
+        "total_bytes": total_bytes,
+        "archived": archived,
+        "recent_actions": events,
+        "runs": stats.runs,
+        "last_run_at": stats.last_run_at,
+        "last_run_errors": stats.last_run.map(|run| run.errors).unwrap_or_default()
+    })))
+}
+
+/// The dictionary a zstd archive needs, from its frame header; `None` for
+/// gzipped archives and those compressed without one.
+fn archive_dictionary_id(path: &std::path::Path) -> Option<u32> {
+    if path.extension().is_none_or(|extension| extension != "zst") {
+        return None;
+    }
+    // A frame header takes at most 18 bytes
+    let mut header = [0; 18];
+    let read = std::io::Read::read(&mut std::fs::File::open(path).ok()?, &mut header).ok()?;
+    compression::frame_dictionary_id(&header[..read])
+}
+
+/// Trains a zstd dictionary on rows sampled evenly from stored CSVs, saves
+/// it to `compression/` and uses it for archives and compressed exports
+/// from then on. Each sampled file is compressed with and without it to
+/// report what it saves.
+async fn train_dictionary(
+    query: Result<Query<TrainDictionaryQuery>, QueryRejection>,
+    State(state): State<SharedState>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let Query(params) = query?;
+    let defaults = compression::TrainingOptions::default();
+    let max_dictionary_bytes = match &params.max_size {
+        Some(size) => parse_size(size).map_err(|message| ApiError::new(StatusCode::BAD_REQUEST, "invalid_size", message))? as usize,
+        None => defaults.max_dictionary_bytes,
+    };
+    if !(1_024..=MAX_DICTIONARY_BYTES).contains(&max_dictionary_bytes) {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid_size", format!("max_size must be 1KiB to {}KiB", MAX_DICTIONARY_BYTES / 1_024))
+            .detail("max_size", max_dictionary_bytes));
+    }
+    let max_rows = params.sample_rows.unwrap_or(defaults.max_rows);
+    let options = compression::TrainingOptions {
+        max_rows,
+        rows_per_sample: params.rows_per_sample.unwrap_or(defaults.rows_per_sample.min(max_rows)),
+        max_dictionary_bytes,
+        ..defaults
+    };
+    if !(1..=MAX_TRAINING_ROWS).contains(&options.max_rows) || !(1..=options.max_rows).contains(&options.rows_per_sample) {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid_limit", format!("sample_rows must be 1 to {} and rows_per_sample 1 to sample_rows", MAX_TRAINING_ROWS))
+            .detail("sample_rows", options.max_rows)
+            .detail("rows_per_sample", options.rows_per_sample));
+    }
+    let files = match &params.files {
+        Some(names) => {
+            let mut files = Vec::new();
+            for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+                let file = FileId::resolve(name, &state).await?;
+                fs::metadata(file.path()).await.map_err(|error| ApiError::read_failed(&file.path(), error))?;
+                files.push(file);
+            }
+            files
+        }
+        None => tokio::task::spawn_blocking(stored_files)
+            .await
+            .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "scan_failed", error.to_string()))?,
+    };
+    if files.is_empty() {
+        return Err(ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "no_files", "there are no stored files to sample"));
+    }
+    log!("🗜️  Training a zstd dictionary on {} file(s)", files.len());
+    
+    let start = std::time::Instant::now();
+    let pool = lock_state(&state)?.parser_pool.clone();
+    let paths: Vec<std::path::PathBuf> = files.iter().map(|file| file.path().into()).collect();
+    let trained = pool
+        .run(move || {
+            let samples = compression::sample_rows(&paths, options.max_rows, options.rows_per_sample)?;
+            let dictionary = compression::Dictionary::train(&samples, options.max_dictionary_bytes)?;
+            let path = dictionary.save(std::path::Path::new(COMPRESSION_DIR))?;
+            let ratios = paths.iter().map(|file| compression::measure(file, options.level, &dictionary)).collect::<std::io::Result<Vec<_>>>()?;
+            Ok::<_, std::io::Error>((samples, dictionary, path, ratios))
+        })
+        .await
+        .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "task_failed", error.to_string()))?;
+    let (samples, dictionary, path, ratios) = trained.map_err(|error| {
+        // zstd refuses to train on too little data
+        ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "training_failed", format!("could not train a dictionary: {}", error))
+            .detail("files", files.len())
+    })?;
+    let total = compression::CompressionRatio::total(&ratios);
+    log!("🗜️  Trained zstd dictionary {} ({} bytes): {:.1}% smaller than plain zstd", dictionary.id, dictionary.bytes.len(), total.improvement_percent);
+    
+    let body = serde_json::json!({
+        "dictionary": {
+            "id": dictionary.id,
+            "size_bytes": dictionary.bytes.len(),
+            "path": path,
+            "download": format!("/admin/compression/dictionaries/{}", dictionary.id)
+        },
+        "samples": {
+            "files": samples.files,
+            "rows": samples.rows,
+            "samples": samples.samples.len(),
+            "bytes": samples.bytes
+        },
+        "level": options.level,
+        "files": ratios,
+        "total": total,
+        "trained_at": chrono::Utc::now(),
+        "processing_time_ms": start.elapsed().as_millis()
+    });
+    lock_state(&state)?.dictionary = Some(TrainedDictionary { dictionary: Arc::new(dictionary), path, training: Some(body.clone()) });
+    Ok(Json(body))
+}
+
+/// The dictionary in use, with what training it reported, and every
+/// dictionary kept in `compression/`.
+async fn compression_status(State(state): State<SharedState>) -> Result<Json<serde_json::Value>, ApiError> {
+    let current = lock_state(&state)?.dictionary.clone();
+    let mut kept = Vec::new();
+    if let Ok(mut entries) = fs::read_dir(COMPRESSION_DIR).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let Some(id) = name.strip_suffix(&format!(".{}", compression::DICTIONARY_EXTENSION)).and_then(|id| id.parse::<u32>().ok()) else {
+                continue;
+            };
+            let metadata = entry.metadata().await.ok();
+            kept.push(serde_json::json!({
+                "id": id,
+                "size_bytes": metadata.as_ref().map(|metadata| metadata.len()),
+                "saved_at": metadata.and_then(|metadata| metadata.modified().ok()).map(chrono::DateTime::<chrono::Utc>::from),
+                "download": format!("/admin/compression/dictionaries/{}", id)
+            }));
+        }
+    }
+    kept.sort_by_key(|dictionary| dictionary["saved_at"].as_str().map(str::to_string));
+    
+    Ok(Json(serde_json::json!({
+        "dictionary": current.as_ref().map(|trained| serde_json::json!({
+            "id": trained.dictionary.id,
+            "size_bytes": trained.dictionary.bytes.len(),
+            "path": trained.path
+        })),
+        "training": current.and_then(|trained| trained.training),
+        "dictionaries": kept
+    })))
+}
+
+/// Downloads a dictionary from `compression/`, for decompressing archives
+/// and exports with `zstd -d -D <id>.dict`.
+async fn download_dictionary(
+    path: Result<axum::extract::Path<u32>, PathRejection>,
+) -> Result<Response, ApiError> {
+    let axum::extract::Path(id) = path?;
+    let path = std::path::Path::new(COMPRESSION_DIR).join(format!("{}.{}", id, compression::DICTIONARY_EXTENSION));
+    let bytes = fs::read(&path).await.map_err(|error| match error.kind() {
+        std::io::ErrorKind::NotFound => {
+            ApiError::new(StatusCode::NOT_FOUND, "dictionary_not_found", format!("no dictionary {}", id)).detail("id", id)
+        }
+        _ => ApiError::read_failed(&path.display().to_string(), error),
+    })?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}.{}\"", id, compression::DICTIONARY_EXTENSION)),
+        ],
+        bytes,
+    )
+        .into_response())
+}
+
+fn workspace_dir(id: &str) -> std::path::PathBuf {
+    std::path::Path::new(WORKSPACES_DIR).join(id)
+}
+
+fn workspace_not_found(id: &str) -> ApiError {
+    ApiError::new(StatusCode::NOT_FOUND, "workspace_not_found", format!("no workspace {} is open", id))
+        .detail("workspace", id)
+        .detail("hint", "workspaces are gone once committed, discarded or idle past WORKSPACE_TTL")
+}
+
+/// A workspace with its results, when it expires and where to go next.
+fn workspace_json(workspace: &workspace::Workspace, ttl_seconds: Option<u64>) -> serde_json::Value {
+    let expires_at = ttl_seconds.map(|seconds| workspace.last_used_at + chrono::Duration::seconds(seconds as i64));
+    serde_json::json!({
+        "id": workspace.id,
+        "created_at": workspace.created_at,
+        "last_used_at": workspace.last_used_at,
+        "expires_at": expires_at,
+        "artifacts": workspace.artifacts,
+        "results": workspace.results(),
+        "steps": workspace.steps,
+        "run": format!("/workspaces/{}/steps", workspace.id),
+        "commit": format!("/workspaces/{}/commit", workspace.id)
+    })
+}
+
+/// What the steps, commit and discard of an open workspace take turns on.
+fn workspace_lock(state: &SharedState, id: &str) -> Result<Arc<tokio::sync::Mutex<()>>, ApiError> {
+    lock_state(state)?
+        .workspaces
+        .get(id)
+        .map(|entry| entry.lock.clone())
+        .ok_or_else(|| workspace_not_found(id))
+}
+
+/// Opens a workspace: a directory of its own for the artifacts of a
+/// multi-step pipeline, kept out of uploads/ until committed.
+async fn create_workspace(State(state): State<SharedState>) -> Result<(StatusCode, Json<serde_json::Value>), ApiError> {
+    let created_at = chrono::Utc::now();
+    let id = format!("{}-{:08x}", created_at.format("%Y%m%d%H%M%S"), rand::random::<u32>());
+    let dir = workspace_dir(&id);
+    fs::create_dir_all(&dir).await.map_err(|error| {
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "storage_failed", format!("cannot create the workspace: {}", error))
+            .detail("path", dir.display().to_string())
+    })?;
+    let workspace = workspace::Workspace::new(id.clone());
+    let ttl_seconds = {
+        let mut app_state = lock_state(&state)?;
+        app_state.workspaces.insert(id.clone(), WorkspaceEntry { workspace: workspace.clone(), lock: Default::default() });
+        app_state.workspace_ttl_seconds
+    };
+    log!("🧪 Opened workspace {}", id);
+    
+    Ok((StatusCode::CREATED, Json(workspace_json(&workspace, ttl_seconds))))
+}
+
+async fn list_workspaces(State(state): State<SharedState>) -> Result<Json<serde_json::Value>, ApiError> {
+    let app_state = lock_state(&state)?;
+    let mut workspaces: Vec<&workspace::Workspace> = app_state.workspaces.values().map(|entry| &entry.workspace).collect();
+    workspaces.sort_by_key(|workspace| workspace.created_at);
+    let workspaces: Vec<serde_json::Value> = workspaces
+        .into_iter()
+        .map(|workspace| workspace_json(workspace, app_state.workspace_ttl_seconds))
+        .collect();
+    
+    Ok(Json(serde_json::json!({
+        "count": workspaces.len(),
+        "ttl_seconds": app_state.workspace_ttl_seconds,
+        "workspaces": workspaces
+    })))
+}
+
+async fn get_workspace(
+    axum::extract::Path(id): axum::extract::Path<String>,
+    State(state): State<SharedState>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let app_state = lock_state(&state)?;
+    let entry = app_state.workspaces.get(&id).ok_or_else(|| workspace_not_found(&id))?;
+    Ok(Json(workspace_json(&entry.workspace, app_state.workspace_ttl_seconds)))
+}
+
+/// Runs one transform, join or sort step of a workspace. Inputs are data
+/// files or, as `@name`, artifacts of earlier steps; the result is stored
+/// as the artifact the step names. Steps of a workspace run one at a time.
+async fn run_workspace_step(
+    axum::extract::Path(id): axum::extract::Path<String>,
+    query: Result<Query<CostQuery>, QueryRejection>,
+    State(state): State<SharedState>,
+    headers: axum::http::HeaderMap,
+    request: Result<Json<workspace::Step>, JsonRejection>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let Query(params) = query?;
+    let Json(step) = request?;
+    // A role that sees a column masked may pass it through but not match,
+    // order or rename by its values, which would tell them apart
+    let access = column_access(&state, &headers)?;
+    if let Some(column) = step.referenced_columns().into_iter().find(|column| access.hides(column)) {
+        return Err(ApiError::new(StatusCode::FORBIDDEN, "column_encrypted", format!("{} is encrypted and this role may not decrypt it", column))
+            .detail("column", column));
+    }
+    // Others read it decrypted; the commit encrypts it again
+    let cipher = match access.masked {
+        Some(_) => None,
+        None => lock_state(&state)?.encryption.clone(),
+    };
+    let output = step.output().to_string();
+    if !workspace::valid_name(&output) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_artifact_name",
+            format!("artifact names are up to {} letters, digits, _, - and ., not starting with a dot", workspace::MAX_NAME_LENGTH),
+        )
+        .detail("output", &output));
+    }
+    let lock = workspace_lock(&state, &id)?;
+    let _turn = lock.lock().await;
+    
+    let dir = workspace_dir(&id);
+    // Sorts and joins hold their input in memory, so their cost is checked
+    let guarded = matches!(step, workspace::Step::Sort { .. } | workspace::Step::Join { .. }) && cost_limits_set(&state)?;
+    let key = match &step {
+        workspace::Step::Join { on, .. } => Some(on.as_str()),
+        _ => None,
+    };
+    let (mut inputs, mut sizes) = (Vec::new(), Vec::new());
+    for name in step.inputs() {
+        let input = match workspace::reference(name) {
+            Some(artifact) => {
+                let app_state = lock_state(&state)?;
+                let entry = app_state.workspaces.get(&id).ok_or_else(|| workspace_not_found(&id))?;
+                let Some(stored) = entry.workspace.artifacts.get(artifact) else {
+                    return Err(ApiError::new(StatusCode::NOT_FOUND, "artifact_not_found", format!("workspace {} has no artifact {}", id, artifact))
+                        .detail("workspace", &id)
+                        .detail("artifact", artifact)
+                        .detail("artifacts", entry.workspace.artifacts.keys().collect::<Vec<_>>()));
+                };
+                sizes.push(cost::InputSize { name: name.to_string(), rows: stored.rows, bytes: stored.bytes, source: "artifact", key_values: None });
+                workspace::Input {
+                    name: name.to_string(),
+                    path: workspace::artifact_path(&dir, artifact),
+                    dialect: dialect::Dialect::default(),
+                    cipher: cipher.clone(),
+                }
+            }
+            None => {
+                let file = FileId::resolve(name, &state).await?;
+                fs::metadata(file.path()).await.map_err(|error| ApiError::read_failed(&file.path(), error))?;
+                if guarded {
+                    sizes.push(input_size(&state, &file, key).await?);
+                }
+                workspace::Input { name: file.to_string(), path: file.path().into(), dialect: file_dialect(&state, &file)?, cipher: cipher.clone() }
+            }
+        };
+        inputs.push(input);
+    }
+    let estimate = match (&step, sizes.as_slice()) {
+        (workspace::Step::Sort { .. }, [input]) if guarded => Some(cost::CostEstimate::sort(input.clone())),
+        (workspace::Step::Join { kind, .. }, [left, right]) if guarded => {
+            Some(cost::CostEstimate::join(left.clone(), right.clone(), *kind == workspace::JoinKind::Left))
+        }
+        _ => None,
+    };
+    if let Some(estimate) = estimate {
+        check_cost(&state, estimate, params.force)?;
+    }
+    {
+        let app_state = lock_state(&state)?;
+        let entry = app_state.workspaces.get(&id).ok_or_else(|| workspace_not_found(&id))?;
+        if entry.workspace.artifacts.contains_key(&output) {
+            return Err(ApiError::new(StatusCode::CONFLICT, "artifact_exists", format!("workspace {} already has an artifact {}", id, output))
+                .detail("workspace", &id)
+                .detail("artifact", &output));
+        }
+    }
+    
+    let (token, _guard) = request_token(&state)?;
+    let pool = lock_state(&state)?.parser_pool.clone();
+    let timer = PerformanceTimer::new(format!("Workspace step writing {}", output));
+    let (applied, path, worker_token) = (step.clone(), workspace::artifact_path(&dir, &output), token.clone());
+    let written = pool
+        .run(move || workspace::apply(&applied, &inputs, &path, || worker_token.is_cancelled()))
+        .await
+        .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "task_failed", error.to_string()))?
+        .map_err(|error| match error {
+            workspace::StepError::UnknownColumn { input, column } => {
+                ApiError::new(StatusCode::BAD_REQUEST, "unknown_column", format!("{} has no column {}", input, column))
+                    .detail("input", input)
+                    .detail("column", column)
+            }
+            workspace::StepError::DuplicateColumn(column) => {
+                ApiError::new(StatusCode::BAD_REQUEST, "duplicate_column", format!("the output would have two columns called {}", column))
+                    .detail("column", column)
+            }
+            workspace::StepError::Csv { input, error } => ApiError::invalid_csv(&input, &error),
+            workspace::StepError::Decrypt { input, column, error } => ApiError::decryption_failed(&input, (column, error)),
+            workspace::StepError::Io(error) => ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "storage_failed", format!("cannot write {}: {}", output, error))
+                .detail("workspace", &id),
+            workspace::StepError::Cancelled => ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "cancelled", "the server is shutting down"),
+        })?;
+    let metrics = timer.finish(written.rows as usize);
+    let duration_ms = metrics.duration.as_secs_f64() * 1_000.0;
+    record_processing(&state, metrics)?;
+    
+    let mut app_state = lock_state(&state)?;
+    let entry = app_state.workspaces.get_mut(&id).ok_or_else(|| workspace_not_found(&id))?;
+    let artifact = workspace::Artifact {
+        columns: written.columns,
+        rows: written.rows,
+        bytes: written.bytes,
+        step: entry.workspace.steps.len(),
+        created_at: chrono::Utc::now(),
+    };
+    entry.workspace.steps.push(step);
+    entry.workspace.artifacts.insert(output.clone(), artifact.clone());
+    entry.workspace.last_used_at = artifact.created_at;
+    log!("🧪 Workspace {}: wrote {} ({} rows)", id, output, artifact.rows);
+    
+    Ok(Json(serde_json::json!({
+        "workspace": id,
+        "artifact": output,
+        "columns": artifact.columns,
+        "rows": artifact.rows,
+        "bytes": artifact.bytes,
+        "step": artifact.step,
+        "duration_ms": duration_ms,
+        "reference": format!("@{}", output),
+        "download": format!("/workspaces/{}/artifacts/{}", id, output)
+    })))
+}
+
+/// An artifact's encrypted columns as a request may see them: decrypted, or
+/// masked when `masked`. Steps may have written them either way.
+fn reveal_artifact(content: &[u8], cipher: &encryption::ColumnCipher, masked: bool) -> Result<Vec<u8>, workspace::StepError> {
+    let csv_error = |error| workspace::StepError::Csv { input: String::new(), error };
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(content);
+    let headers = reader.headers().map_err(csv_error)?.clone();
+    let encrypted: Vec<(usize, &str)> = headers.iter().enumerate().filter(|(_, header)| cipher.encrypts(header)).collect();
+    let mut writer = csv::Writer::from_writer(Vec::with_capacity(content.len()));
+    writer.write_record(&headers).map_err(csv_error)?;
+    let mut fields = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(csv_error)?;
+        fields.clear();
+        fields.extend(record.iter().map(str::to_string));
+        for &(index, column) in &encrypted {
+            let Some(field) = fields.get_mut(index) else {
+                continue;
+            };
+            *field = match masked {
+                true => encryption::MASK.to_string(),
+                false => cipher
+                    .decrypt(column, field)
+                    .map_err(|error| workspace::StepError::Decrypt { input: String::new(), column: column.to_string(), error })?,
+            };
+        }
+        writer.write_record(&fields).map_err(csv_error)?;
+    }
+    writer.into_inner().map_err(|error| workspace::StepError::Io(error.into_error()))
+}
+
+async fn download_workspace_artifact(
+    axum::extract::Path((id, name)): axum::extract::Path<(String, String)>,
+    State(state): State<SharedState>,
+    headers: axum::http::HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let access = column_access(&state, &headers)?;
+    let name = name.strip_suffix(".csv").unwrap_or(&name).to_string();
+    let known = lock_state(&state)?
+        .workspaces
+        .get(&id)
+        .ok_or_else(|| workspace_not_found(&id))?
+        .workspace
+        .artifacts
+        .contains_key(&name);
+    if !known {
+        return Err(ApiError::new(StatusCode::NOT_FOUND, "artifact_not_found", format!("workspace {} has no artifact {}", id, name))
+            .detail("workspace", &id)
+            .detail("artifact", &name));
+    }
+    let path = workspace::artifact_path(&workspace_dir(&id), &name).display().to_string();
+    let mut content = fs::read(&path).await.map_err(|error| ApiError::read_failed(&path, error))?;
+    let cipher = lock_state(&state)?.encryption.clone();
+    if let Some(cipher) = cipher {
+        let artifact = format!("@{}", name);
+        content = tokio::task::spawn_blocking(move || reveal_artifact(&content, &cipher, access.masked.is_some()))
+            .await
+            .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "task_failed", error.to_string()))?
+            .map_err(|error| match error {
+                workspace::StepError::Csv { error, .. } => ApiError::invalid_csv(&artifact, &error),
+                workspace::StepError::Decrypt { column, error, .. } => ApiError::decryption_failed(&artifact, (column, error)),
+                error => ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "read_failed", error.to_string()),
+            })?;
+    }
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}.csv\"", name)),
+        ],
+        content,
+    ))
+}
+
+/// Moves artifacts of a workspace into uploads/, each as an upload of its
+/// own, and closes the workspace with the rest of its artifacts. Either
+/// every artifact is committed or none is.
+async fn commit_workspace(
+    axum::extract::Path(id): axum::extract::Path<String>,
+    query: Result<Query<CommitQuery>, QueryRejection>,
+    State(state): State<SharedState>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let Query(params) = query?;
+    let lock = workspace_lock(&state, &id)?;
+    let _turn = lock.lock().await;
+    let (workspace, cipher) = {
+        let app_state = lock_state(&state)?;
+        let entry = app_state.workspaces.get(&id).ok_or_else(|| workspace_not_found(&id))?;
+        (entry.workspace.clone(), app_state.encryption.clone())
+    };
+    let names: Vec<String> = match &params.artifacts {
+        Some(list) => list.split(',').map(str::trim).filter(|name| !name.is_empty()).map(String::from).collect(),
+        None => workspace.results().into_iter().map(String::from).collect(),
+    };
+    if names.is_empty() {
+        return Err(ApiError::new(StatusCode::CONFLICT, "nothing_to_commit", format!("workspace {} has no artifacts to commit", id))
+            .detail("workspace", &id));
+    }
+    if let Some(unknown) = names.iter().find(|name| !workspace.artifacts.contains_key(*name)) {
+        return Err(ApiError::new(StatusCode::NOT_FOUND, "artifact_not_found", format!("workspace {} has no artifact {}", id, unknown))
+            .detail("workspace", &id)
+            .detail("artifact", unknown)
+            .detail("artifacts", workspace.artifacts.keys().collect::<Vec<_>>()));
+    }
+    
+    let dir = workspace_dir(&id);
+    let storage_failed = |error: std::io::Error| {
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "storage_failed", format!("cannot commit workspace {}: {}", id, error)).detail("workspace", &id)
+    };
+    fs::create_dir_all(DATA_DIRS[1]).await.map_err(storage_failed)?;
+    let uploaded_at = chrono::Utc::now();
+    let mut committed: Vec<(String, UploadEntry)> = Vec::new();
+    for name in &names {
+        let artifact = &workspace.artifacts[name];
+        let upload_id = format!("{}-{:08x}", uploaded_at.format("%Y%m%d%H%M%S"), rand::random::<u32>());
+        let file = FileId::new(DATA_DIRS[1], &format!("{}.csv", upload_id));
+        let source = workspace::artifact_path(&dir, name);
+        // Columns stored encrypted are encrypted here too, as on upload
+        let encrypted = match cipher.clone() {
+            Some(cipher) => {
+                let path = source.display().to_string();
+                tokio::task::spawn_blocking(move || encrypt_upload(&path, &dialect::Dialect::default(), &cipher, true))
+                    .await
+                    .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "task_failed", error.to_string()))?
+            }
+            None => Ok(()),
+        };
+        let moved = match encrypted {
+            Ok(()) => fs::rename(&source, file.path()).await,
+            Err(error) => Err(error),
+        };
+        if let Err(error) = moved {
+            // Put back what was already moved, so the workspace stays whole
+            for (name, upload) in &committed {
+                let _ = fs::rename(upload.file_id.path(), workspace::artifact_path(&dir, name)).await;
+            }
+            return Err(storage_failed(error));
+        }
+        let size_bytes = fs::metadata(file.path()).await.map(|metadata| metadata.len() as usize).unwrap_or(artifact.bytes as usize);
+        committed.push((
+            name.clone(),
+            UploadEntry {
+                id: upload_id,
+                filename: format!("{}.csv", name),
+                file_id: file,
+                size_bytes,
+                uploaded_at,
+                dialect: dialect::Dialect::default(),
+                columns: artifact.columns.clone(),
+                schema_diff: None,
+            },
+        ));
+    }
+    {
+        let mut app_state = lock_state(&state)?;
+        app_state.workspaces.remove(&id);
+        for (_, upload) in &committed {
+            app_state.uploads.insert(upload.id.clone(), upload.clone());
+        }
+    }
+    if let Err(error) = fs::remove_dir_all(&dir).await {
+        log!("⚠️  Cannot remove workspace {}: {}", dir.display(), error);
+    }
+    log!("🧪 Committed workspace {}: {}", id, names.join(", "));
+    
+    let committed: serde_json::Map<String, serde_json::Value> = committed
+        .iter()
+        .map(|(name, upload)| {
+            (name.clone(), serde_json::json!({
+                "id": upload.id,
+                "filename": upload.filename,
+                "file_id": upload.file_id,
+                "size_bytes": upload.size_bytes,
+                "rows": workspace.artifacts[name].rows,
+                "analyze": format!("/analyze/{}", upload.id)
+            }))
+        })
+        .collect();
+    Ok(Json(serde_json::json!({
+        "workspace": id,
+        "committed": committed,
+        "discarded": workspace.artifacts.keys().filter(|name| !names.contains(*name)).collect::<Vec<_>>()
+    })))
+}
+
+/// Closes a workspace and deletes all its artifacts, after any step
+/// running in it.
+async fn discard_workspace(
+    axum::extract::Path(id): axum::extract::Path<String>,
+    State(state): State<SharedState>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let lock = workspace_lock(&state, &id)?;
+    let _turn = lock.lock().await;
+    let entry = lock_state(&state)?.workspaces.remove(&id).ok_or_else(|| workspace_not_found(&id))?;
+    let dir = workspace_dir(&id);
+    match fs::remove_dir_all(&dir).await {
+        Ok(()) => {}
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+        Err(error) => {
+            return Err(ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "storage_failed", format!("cannot remove workspace {}: {}", id, error))
+                .detail("path", dir.display().to_string()));
+        }
+    }
+    log!("🧪 Discarded workspace {}", id);
+    
+    Ok(Json(serde_json::json!({
+        "discarded": id,
+        "artifacts": entry.workspace.artifacts.keys().collect::<Vec<_>>()
+    })))
+}
+
+/// Discards the workspaces idle past `WORKSPACE_TTL`, and the directories
+/// of those a previous run left behind. Returns how many went.
+async fn expire_workspaces(state: &SharedState) -> usize {
+    let (expired, live, max_age) = {
+        let Ok(mut app_state) = lock_state(state) else {
+            return 0;
+        };
+        let Some(ttl_seconds) = app_state.workspace_ttl_seconds else {
+            return 0;
+        };
+        let cutoff = chrono::Utc::now() - chrono::Duration::seconds(ttl_seconds as i64);
+        // One running a step is not idle
+        let expired: Vec<String> = app_state
+            .workspaces
+            .iter()
+            .filter(|(_, entry)| entry.workspace.last_used_at < cutoff && entry.lock.try_lock().is_ok())
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &expired {
+            app_state.workspaces.remove(id);
+        }
+        let live: std::collections::HashSet<String> = app_state.workspaces.keys().cloned().collect();
+        (expired, live, std::time::Duration::from_secs(ttl_seconds))
+    };
+    let removed = tokio::task::spawn_blocking(move || {
+        let mut dirs: Vec<std::path::PathBuf> = expired.iter().map(|id| workspace_dir(id)).collect();
+        match workspace::orphaned_dirs(std::path::Path::new(WORKSPACES_DIR), &live, max_age, std::time::SystemTime::now()) {
+            Ok(orphans) => dirs.extend(orphans),
+            Err(error) => println!("⚠️  Janitor: {}: {}", WORKSPACES_DIR, error),
+        }
+        dirs.into_iter()
+            .filter(|dir| match std::fs::remove_dir_all(dir) {
+                Ok(()) => true,
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => false,
+                Err(error) => {
+                    println!("⚠️  Janitor: {}: {}", dir.display(), error);
+                    false
+                }
+            })
+            .count()
+    })
+    .await;
+    removed.unwrap_or(0)
+}
+
+async fn get_catalog(State(state): State<SharedState>) -> Result<Json<serde_json::Value>, ApiError> {
+    let app_state = lock_state(&state)?;
+    let mut files: Vec<_> = app_state.catalog.values().cloned().collect();
+    files.sort_by_key(|entry| entry.created_at);
+    let mut with_statistics: Vec<_> = app_state.column_stats.keys().collect();
+    with_statistics.sort_by_key(|file| file.as_str());
+    
+    let mut bloom_filters: Vec<_> = app_state
+        .bloom_filters
+        .iter()
+        .map(|(file, entry)| {
+            serde_json::json!({
+                "filename": file,
+                "built_at": entry.built_at,
+                "columns": entry.filters.info()
+            })
+        })
+        .collect();
+    bloom_filters.sort_by(|a, b| a["filename"].as_str().cmp(&b["filename"].as_str()));
+    
+    Ok(Json(serde_json::json!({
+        "count": files.len(),
+        "files": files,
+        "with_statistics": with_statistics,
+        "bloom_filters": bloom_filters
+    })))
+}
+
+/// A file's key Bloom filters as of `fingerprint`, built from its records
+/// when there are none yet. Returns whether they were built now.
+async fn key_filters(
+    state: &SharedState,
+    file: &FileId,
+    fingerprint: u64,
+    token: &CancellationToken,
+) -> Result<(Arc<bloom::KeyFilters>, bool), ApiError> {
+    let current = lock_state(state)?
+        .bloom_filters
+        .get(file)
+        .filter(|entry| entry.fingerprint == fingerprint)
+        .map(|entry| entry.filters.clone());
+    if let Some(filters) = current {
+        return Ok((filters, false));
+    }
+    let (records, cancelled) = cached_records(state, file, token).await?;
+    // Filters of part of the file would rule out values it holds
+    if cancelled {
+        return Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "cancelled", "the server is shutting down").detail("filename", file.as_str()));
+    }
+    let pool = lock_state(state)?.parser_pool.clone();
+    let filters = pool
+        .run(move || bloom::KeyFilters::build(&records))
+        .await
+        .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "task_failed", error.to_string()))?;
+    let filters = Arc::new(filters);
+    lock_state(state)?.bloom_filters.insert(
+        file.clone(),
+        BloomEntry { fingerprint, built_at: chrono::Utc::now(), filters: filters.clone() },
+    );
+    Ok((filters, true))
+}
+
+/// Whether files hold a record with a key value. Each file's Bloom filter
+/// rules it out without reading it; only the files it cannot rule out are
+/// scanned, or reported as "maybe" with `verify=false`. Files looked up
+/// for the first time have their filters built on the way.
+async fn contains_value(
+    query: Result<Query<ContainsQuery>, QueryRejection>,
+    State(state): State<SharedState>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let Query(params) = query?;
+    let value = bloom::KeyValue::parse(&params.column, &params.value)
+        .map_err(|message| ApiError::new(StatusCode::BAD_REQUEST, "invalid_key", message).detail("columns", bloom::KEY_COLUMNS))?;
+    if column_access(&state, &headers)?.hides(&params.column) {
+        return Err(ApiError::new(StatusCode::FORBIDDEN, "column_encrypted", format!("{} is encrypted and this role may not decrypt it", params.column))
+            .detail("column", &params.column));
+    }
+    let verify = params.verify.unwrap_or(true);
+    let named = params.file.is_some();
+    let files = match &params.file {
+        Some(names) => {
+            let mut files = Vec::new();
+            for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+                files.push(FileId::resolve(name, &state).await?);
+            }
+            files
+        }
+        None => tokio::task::spawn_blocking(stored_files)
+            .await
+            .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "scan_failed", error.to_string()))?,
+    };
+    
+    let (token, _guard) = request_token(&state)?;
+    let start = std::time::Instant::now();
+    let (mut ruled_out, mut scanned, mut built) = (0, 0, 0);
+    let mut results = Vec::new();
+    for file in files {
+        let looked_up: Result<&str, ApiError> = async {
+            let fingerprint = file_fingerprint(&file).await?;
+            catch_up(&state, &file, fingerprint).await?;
+            let (filters, fresh) = key_filters(&state, &file, fingerprint, &token).await?;
+            built += fresh as usize;
+            if !filters.might_contain(&value) {
+                ruled_out += 1;
+                return Ok("no");
+            }
+            if !verify {
+                return Ok("maybe");
+            }
+            scanned += 1;
+            let (records, _) = cached_records(&state, &file, &token).await?;
+            Ok(if records.iter().any(|record| value.matches(record)) { "yes" } else { "no" })
+        }
+        .await;
+        match looked_up {
+            Ok(contains) => results.push(serde_json::json!({ "filename": file, "contains": contains })),
+            // A file that cannot be read is reported, unless it was asked for
+            Err(error) if !named && error.code != "cancelled" => {
+                results.push(serde_json::json!({ "filename": file, "error": error.code, "message": error.message }));
+            }
+            Err(error) => return Err(error),
+        }
+    }
+    
+    Ok(Json(serde_json::json!({
+        "column": params.column,
+        "value": params.value,
+        "files": results,
+        "ruled_out_by_filter": ruled_out,
+        "scanned": scanned,
+        "filters_built": built,
+        "processing_time_ms": start.elapsed().as_millis()
+    })))
+}
+
+/// The column statistics /analyze and /aggregate answer from, once the
+/// file has been analyzed and while it is unchanged.
+async fn get_file_statistics(
+    axum::extract::Path(filename): axum::extract::Path<String>,
+    State(state): State<SharedState>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let file = FileId::resolve(&filename, &state).await?;
+    let fingerprint = file_fingerprint(&file).await?;
+    let entry = lock_state(&state)?
+        .column_stats
+        .get(&file)
+        .filter(|entry| entry.fingerprint == fingerprint)
+        .cloned();
+    let Some(entry) = entry else {
+        return Err(ApiError::new(StatusCode::NOT_FOUND, "no_statistics", "the file has no statistics yet; analyze it first")
+            .detail("filename", &file));
+    };
+    
+    Ok(Json(serde_json::json!({
+        "filename": file,
+        "computed_at": entry.computed_at,
+        "statistics": *entry.statistics
+    })))
+}
+
+/// Checks the alert rules against a finished parse and sends the alerts it
+/// raised in the background. Cancelled parses stopped early and are not
+/// judged.
+fn check_alerts(state: &SharedState, file: &FileId, result: &Result<ParsedFile, ApiError>, duration: std::time::Duration) {
+    let mut outcome = match result {
+        Ok(parsed) if parsed.cancelled => return,
+        Ok(parsed) => alerts::JobOutcome {
+            rows: parsed.records.len(),
+            revenue: Some(parsed.records.iter().map(|record| record.price * record.quantity as f64).sum()),
+            ..alerts::JobOutcome::default()
+        },
+        Err(error) => {
+            let count = |key: &str| error.details.get(key).and_then(serde_json::Value::as_u64).unwrap_or(0) as usize;
+            alerts::JobOutcome {
+                rows: count("rows"),
+                invalid_rows: count("invalid_rows"),
+                error: Some(format!("{}: {}", error.code, error.message)),
+                ..alerts::JobOutcome::default()
+            }
+        }
+    };
+    outcome.file = file.to_string();
+    outcome.duration_ms = duration.as_secs_f64() * 1_000.0;
+    
+    // Nothing to report a poisoned lock to here, so the job goes unchecked
+    let Ok(mut app_state) = state.lock() else {
+        return;
+    };
+    // Uploads are compared with the previous upload under the same name
+    outcome.series = match app_state.uploads.values().find(|upload| &upload.file_id == file) {
+        Some(upload) => upload.filename.clone(),
+        None => file.to_string(),
+    };
+    let raised = app_state.alerts.evaluate(&outcome);
+    if raised.is_empty() {
+        return;
+    }
+    let notifier = app_state.alerts.notifier().clone();
+    drop(app_state);
+    
+    request_log::spawn(async move {
+        for alert in raised {
+            log!("🚨 {} ({})", alert.message, alert.rule);
+            for failure in notifier.send(&alert).await {
+                log!("⚠️  Could not send alert to {}", failure);
+            }
+        }
+    });
+}
+
+async fn list_alerts(State(state): State<SharedState>) -> Result<Json<serde_json::Value>, ApiError> {
+    let app_state = lock_state(&state)?;
+    let monitor = &app_state.alerts;
+    let rules: Vec<_> = monitor.rules().iter().map(alerts::AlertRule::as_str).collect();
+    // Newest first
+    let raised: Vec<_> = monitor.recent().iter().rev().collect();
+    
+    Ok(Json(serde_json::json!({
+        "rules": rules,
+        "destinations": monitor.notifier().destinations(),
+        "count": raised.len(),
+        "alerts": raised
+    })))
+}
+
+/// Seconds in `30`, `90s`, `15m`, `2h` or `1d`; `None` for zero or
+/// anything else.
+fn parse_interval(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let (number, unit) = match text.char_indices().last()? {
+        (index, unit) if unit.is_ascii_alphabetic() => (&text[..index], unit.to_ascii_lowercase()),
+        _ => (text, 's'),
+    };
+    let multiplier = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3_600,
+        'd' => 86_400,
+        _ => return None,
+    };
+    number.trim().parse::<u64>().ok()?.checked_mul(multiplier).filter(|seconds| *seconds > 0)
+}
+
+/// Writes an analysis report of the scheduled files at startup and then
+/// every interval until shutdown. Does nothing without a schedule.
+fn spawn_report_schedule(state: SharedState, shutdown: CancellationToken) {
+    let Some(schedule) = state.lock().ok().and_then(|app_state| app_state.report_schedule.clone()) else {
+        return;
+    };
+    println!(
+        "🗓️  Writing analysis reports of {} to reports/ every {}s",
+        schedule.files.join(", "),
+        schedule.interval_seconds
+    );
+    
+    tokio::spawn(async move {
+        let interval = std::time::Duration::from_secs(schedule.interval_seconds);
+        let mut ticker = tokio::time::interval(interval);
+        // A run that overran its slot is followed by a full interval, not a burst
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = ticker.tick() => {}
+            }
+            let outcome = write_analysis_report(&state, &schedule.files, &schedule.locale, &shutdown.child_token()).await;
+            // Nothing to report a poisoned lock to here, so the run goes unrecorded
+            let Ok(mut app_state) = state.lock() else {
+                continue;
+            };
+            let Some(status) = app_state.report_schedule.as_mut() else {
+                continue;
+            };
+            let now = chrono::Utc::now();
+            status.runs += 1;
+            status.last_run_at = Some(now);
+            status.next_run_at = chrono::TimeDelta::from_std(interval).ok().map(|interval| now + interval);
+            match outcome {
+                Ok(reports) => {
+                    println!("📄 Wrote {}", reports.join(" and "));
+                    status.last_reports = reports;
+                    status.last_error = None;
+                }
+                Err(error) => {
+                    println!("⚠️  Scheduled report failed: {}", error);
+                    status.last_error = Some(error.to_string());
+                }
+            }
+        }
+    });
+}
+
+/// Analyzes `files` as /analyze would and writes the results to
+/// `reports/analysis-<timestamp>.md`, with numbers, amounts and dates
+/// written for `locale`, and `.csv`, returning their paths. A file that
+/// cannot be analyzed is reported with its error rather than failing the
+/// run.
+async fn write_analysis_report(
+    state: &SharedState,
+    files: &[String],
+    locale: &locale::Locale,
+    token: &CancellationToken,
+) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let generated_at = chrono::Local::now();
+    let base_currency = lock_state(state).map(|app_state| app_state.currency.base.clone()).unwrap_or_default();
+    let mut analyses = Vec::new();
+    for name in files {
+        let analysis = match FileId::resolve(name, state).await {
+            Ok(file) => analyze_file(state, &file, token, Some(REPORT_TOP_PRODUCTS)).await,
+            Err(error) => Err(error),
+        };
+        analyses.push((name.as_str(), analysis));
+    }
+    
+    let escape = |text: &str| text.replace('|', "\\|");
+    let mut markdown = format!(
+        "# Sales Analysis Report\n\nGenerated {} {} for {} file{}.\n\n",
+        locale.date(&generated_at),
+        generated_at.format("%H:%M:%S %:z"),
+        files.len(),
+        if files.len() == 1 { "" } else { "s" }
+    );
+    markdown.push_str("| File | Records | Revenue | Average price | Top product |\n|---|---:|---:|---:|---|\n");
+    for (name, analysis) in &analyses {
+        match analysis {
+            Ok(result) => markdown.push_str(&format!(
+                "| {} | {} | {} | {} | {} |\n",
+                escape(name),
+                locale.integer(result.total_records as u64),
+                locale.currency(result.total_revenue, &base_currency),
+                locale.currency(result.average_price, &base_currency),
+                result.top_products.first().map(|top| escape(&top.product)).unwrap_or_default()
+            )),
+            Err(error) => markdown.push_str(&format!("| {} | ❌ {} | | | |\n", escape(name), escape(&error.message))),
+        }
+    }
+    for (name, analysis) in &analyses {
+        let Ok(result) = analysis else {
+            continue;
+        };
+        markdown.push_str(&format!("\n## {}\n\n| # | Product | Sales | Quantity |\n|---:|---|---:|---:|\n", name));
+        for (rank, product) in result.top_products.iter().enumerate() {
+            markdown.push_str(&format!(
+                "| {} | {} | {} | {} |\n",
+                rank + 1,
+                escape(&product.product),
+                locale.currency(product.total_sales, &base_currency),
+                locale.integer(product.quantity_sold as u64)
+            ));
+        }
+    }
+    
+    // One row per file and top product; a failed file gets one row with its error
+    let mut csv = WriterBuilder::new().from_writer(Vec::new());
+    csv.write_record(["file", "total_records", "total_revenue", "average_price", "rank", "product", "total_sales", "quantity_sold", "error"])?;
+    for (name, analysis) in &analyses {
+        match analysis {
+            Ok(result) => {
+                for (rank, product) in result.top_products.iter().enumerate() {
+                    csv.write_record([
+                        name.to_string(),
+                        result.total_records.to_string(),
+                        format!("{:.2}", result.total_revenue),
+                        format!("{:.2}", result.average_price),
+                        (rank + 1).to_string(),
+                        product.product.clone(),
+                        format!("{:.2}", product.total_sales),
+                        product.quantity_sold.to_string(),
+                        String::new(),
+                    ])?;
+                }
+            }
+            Err(error) => csv.write_record([name, "", "", "", "", "", "", "", error.message.as_str()])?,
+        }
+    }
+    let csv = csv.into_inner().map_err(|error| error.into_error())?;
+    
+    fs::create_dir_all("reports").await?;
+    let stem = format!("reports/analysis-{}", generated_at.format("%Y%m%d-%H%M%S"));
+    let mut written = Vec::new();
+    for (extension, content) in [("md", markdown.into_bytes()), ("csv", csv)] {
+        let path = format!("{}.{}", stem, extension);
+        fs::write(&path, content).await?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Files in `reports/`, newest first: the scheduled analysis reports and
+/// the benchmark binaries' `--report` output.
+async fn list_reports(State(state): State<SharedState>) -> Result<Json<serde_json::Value>, ApiError> {
+    let schedule = lock_state(&state)?.report_schedule.clone();
+    let mut reports = Vec::new();
+    match fs::read_dir("reports").await {
+        Ok(mut entries) => {
+            while let Some(entry) = entries.next_entry().await.map_err(|error| ApiError::read_failed("reports", error))? {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let Ok(metadata) = entry.metadata().await else {
+                    continue;
+                };
+                if name.starts_with('.') || !metadata.is_file() {
+                    continue;
+                }
+                reports.push(ReportFile {
+                    url: format!("/reports/{}", name),
+                    name,
+                    size_bytes: metadata.len(),
+                    modified: metadata.modified().map(chrono::DateTime::from).unwrap_or_default(),
+                });
+            }
+        }
+        // No report written yet
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+        Err(error) => return Err(ApiError::read_failed("reports", error)),
+    }
+    reports.sort_by(|a, b| b.modified.cmp(&a.modified).then_with(|| a.name.cmp(&b.name)));
+    
+    Ok(Json(serde_json::json!({
+        "count": reports.len(),
+        "reports": reports,
+        "schedule": schedule
+    })))
+}
+
+async fn download_report(axum::extract::Path(name): axum::extract::Path<String>) -> Result<impl IntoResponse, ApiError> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid_report_name", "report names are file names in reports/")
+            .detail("name", &name));
+    }
+    let path = format!("reports/{}", name);
+    let content = fs::read(&path).await.map_err(|error| ApiError::read_failed(&path, error))?;
+    let content_type = match std::path::Path::new(&name).extension().and_then(|extension| extension.to_str()) {
+        Some("md") => report::ReportFormat::Markdown.content_type(),
+        Some("html") => report::ReportFormat::Html.content_type(),
+        Some("csv") => "text/csv; charset=utf-8",
+        Some("json") => "application/json",
+        _ => "application/octet-stream",
+    };
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", name)),
+        ],
+        content,
+    ))
+}
+
+/// Signs artifact links with `ARTIFACT_KEY`. Without it a random key is
+/// drawn at startup, so links handed out stop working on restart.
+fn artifact_signer() -> artifacts::ArtifactSigner {
+    match std::env::var("ARTIFACT_KEY") {
+        Ok(key) if !key.is_empty() => artifacts::ArtifactSigner::new(key.as_bytes()),
+        _ => {
+            println!("⚠️  ARTIFACT_KEY is not set; download links will not survive a restart");
+            artifacts::ArtifactSigner::new(&rand::random::<[u8; 32]>())
+        }
+    }
+}
+
+/// How long download links last by default, from `ARTIFACT_TTL` (1h unless
+/// set, at most 7d).
+fn artifact_ttl_seconds() -> u64 {
+    let seconds = match std::env::var("ARTIFACT_TTL") {
+        Ok(ttl) => parse_interval(&ttl).filter(|&seconds| seconds > 0).unwrap_or_else(|| {
+            println!("⚠️  Ignoring ARTIFACT_TTL={}: expected seconds, or a number followed by s, m, h or d", ttl);
+            DEFAULT_ARTIFACT_TTL_SECONDS
+        }),
+        Err(_) => DEFAULT_ARTIFACT_TTL_SECONDS,
+    };
+    seconds.min(MAX_ARTIFACT_TTL_SECONDS)
+}
+
+/// Reads a file to parse under the server's I/O policy, through the chaos
+/// layer's faults when built with it, so they are retried like real ones.
+async fn read_data_file(state: &SharedState, path: &str) -> Result<processing::Attempted<String>, ApiError> {
+    let policy = lock_state(state)?.io_policy;
+    #[cfg(feature = "chaos")]
+    let read = {
+        let chaos = lock_state(state)?.chaos.clone();
+        policy.run(|| chaos.read_to_string(std::path::Path::new(path))).await
+    };
+    #[cfg(not(feature = "chaos"))]
+    let read = processing::read_to_string(path, &policy).await;
+    read.map_err(|error| ApiError::read_under_policy(path, error))
+}
+
+/// Faults to inject from `CHAOS`, e.g. `latency=0.1:50-500,io_error=0.05,seed=7`;
+/// none when it is not set.
+#[cfg(feature = "chaos")]
+fn chaos_config() -> chaos::ChaosConfig {
+    let Ok(spec) = std::env::var("CHAOS") else {
+        return chaos::ChaosConfig::default();
+    };
+    match chaos::ChaosConfig::parse(&spec) {
+        Ok(config) => {
+            println!("🐒 Injecting faults: {}", spec);
+            config
+        }
+        Err(message) => {
+            println!("⚠️  Ignoring CHAOS={}: {}", spec, message);
+            chaos::ChaosConfig::default()
+        }
+    }
+}
+
+/// The faults being injected, and how many of each were since they were set.
+#[cfg(feature = "chaos")]
+async fn get_chaos(State(state): State<SharedState>) -> Result<Json<serde_json::Value>, ApiError> {
+    let chaos = lock_state(&state)?.chaos.clone();
+    Ok(Json(serde_json::json!({
+        "config": chaos.config(),
+        "off": chaos.config().is_off(),
+        "injected": chaos.stats()
+    })))
+}
+
+/// Replaces the faults to inject. Fields left out are 0, so `{}` turns
+/// every fault off. The decisions restart from the seed and the counts from
+/// zero, so a test run after this sees the same faults each time.
+#[cfg(feature = "chaos")]
+async fn configure_chaos(
+    State(state): State<SharedState>,
+    body: Result<Json<chaos::ChaosConfig>, JsonRejection>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let Json(config) = body?;
+    config
+        .validate()
+        .map_err(|message| ApiError::new(StatusCode::BAD_REQUEST, "invalid_chaos", message))?;
+    let chaos = lock_state(&state)?.chaos.clone();
+    chaos.configure(config.clone());
+    log!("🐒 Fault injection {}", if config.is_off() { "turned off".to_string() } else { format!("set to {:?}", config) });
+    
+    Ok(Json(serde_json::json!({ "config": config, "off": config.is_off() })))
+}
+
+/// The policy reads of files to parse run under: `READ_TIMEOUT` limits each
+/// attempt (an interval, or `off`, the default) and `READ_RETRIES` sets how
+/// often a transient error or timeout is retried (2 by default).
+fn io_policy() -> processing::Policy {
+    let mut policy = processing::Policy::default();
+    if let Ok(timeout) = std::env::var("READ_TIMEOUT") {
+        match parse_interval(&timeout).filter(|&seconds| seconds > 0) {
+            Some(seconds) => policy.timeout = Some(std::time::Duration::from_secs(seconds)),
+            None if timeout.trim().eq_ignore_ascii_case("off") => {}
+            None => println!("⚠️  Ignoring READ_TIMEOUT={}: expected off, seconds, or a number followed by s, m, h or d", timeout),
+        }
+    }
+    if let Ok(retries) = std::env::var("READ_RETRIES") {
+        match retries.trim().parse() {
+            Ok(retries) if retries <= MAX_READ_RETRIES => policy.max_retries = retries,
+            _ => println!("⚠️  Ignoring READ_RETRIES={}: expected a number from 0 to {}", retries, MAX_READ_RETRIES),
+        }
+    }
+    policy
+}
+
+/// The cache layout from `CACHE_LAYOUT`: `rows` (the default) or, when
+/// built with the `columnar` feature, `columnar`.
+fn cache_layout() -> CacheLayout {
+    match std::env::var("CACHE_LAYOUT").as_deref().map(str::trim) {
+        Err(_) | Ok("rows") => CacheLayout::Rows,
+        #[cfg(feature = "columnar")]
+        Ok("columnar") => CacheLayout::Columnar,
+        #[cfg(not(feature = "columnar"))]
+        Ok("columnar") => {
+            println!("⚠️  Ignoring CACHE_LAYOUT=columnar: built without the columnar feature");
+            CacheLayout::Rows
+        }
+        Ok(layout) => {
+            println!("⚠️  Ignoring CACHE_LAYOUT={}: expected rows or columnar", layout);
+            CacheLayout::Rows
+        }
+    }
+}
+
+/// Starts converting a file into a download in `artifacts/`. Answers 202
+/// with the job; once it completes, GET /exports/:id hands out a signed
+/// link to the artifact.
+async fn start_export(
+    State(state): State<SharedState>,
+    headers: axum::http::HeaderMap,
+    request: Result<Json<ExportRequest>, JsonRejection>,
+) -> Result<(StatusCode, Json<serde_json::Value>), ApiError> {
+    let Json(request) = request?;
+    let access = column_access(&state, &headers)?;
+    let file = FileId::resolve(&request.filename, &state).await?;
+    // A missing file is refused now rather than left for the job to fail on
+    fs::metadata(file.path()).await.map_err(|error| ApiError::read_failed(&file.path(), error))?;
+    let format = request.format.unwrap_or(ExportFormat::Csv);
+    
+    let (job, token) = {
+        let mut app_state = lock_state(&state)?;
+        let id = app_state.next_export_id;
+        app_state.next_export_id += 1;
+        let job = ExportJob {
+            id,
+            filename: file.clone(),
+            format,
+            provenance: request.provenance,
+            compress: request.compress,
+            dictionary_id: None,
+            status: JobStatus::Running,
+            error: None,
+            request_id: request_log::current(),
+            artifact: None,
+            records: None,
+            size_bytes: None,
+            created_at: chrono::Utc::now(),
+            finished_at: None,
+        };
+        app_state.export_jobs.insert(id, job.clone());
+        (job, app_state.shutdown.child_token())
+    };
+    
+    let job_state = state.clone();
+    let (job_id, provenance, compress) = (job.id, job.provenance, job.compress);
+    request_log::spawn(cost::inherit(async move {
+        let result = run_export(&job_state, &file, format, provenance, compress, &access, job_id, &token).await;
+        let Ok(mut app_state) = job_state.lock() else {
+            return;
+        };
+        if let Some(job) = app_state.export_jobs.get_mut(&job_id) {
+            job.finished_at = Some(chrono::Utc::now());
+            match result {
+                Ok((artifact, records, size_bytes, dictionary_id)) => {
+                    job.status = JobStatus::Completed;
+                    job.artifact = Some(artifact);
+                    job.records = Some(records);
+                    job.size_bytes = Some(size_bytes);
+                    job.dictionary_id = dictionary_id;
+                }
+                Err(error) => {
+                    job.status = JobStatus::Failed;
+                    job.error = Some(error);
+                }
+            }
+        }
+    }));
+    
+    Ok((StatusCode::ACCEPTED, Json(serde_json::json!({
+        "status": "running",
+        "job": job,
+        "poll": format!("/exports/{}", job_id)
+    }))))
+}
+
+/// Writes the records of `file` to `artifacts/export-<id>-<stem>.<ext>`,
+/// through a hidden part file so a download never sees half an export.
+/// With `provenance`, each record is written with where it came from, and
+/// with `compress` the export is zstd-compressed, with the trained
+/// dictionary if any, to `<name>.zst`. Encrypted columns are written as
+/// `access`, the requester's, allows. Returns the artifact name, the
+/// records written, its size and the dictionary it needs.
+#[allow(clippy::too_many_arguments)]
+async fn run_export(
+    state: &SharedState,
+    file: &FileId,
+    format: ExportFormat,
+    provenance: bool,
+    compress: bool,
+    access: &ColumnAccess,
+    id: u64,
+    token: &CancellationToken,
+) -> Result<(String, usize, u64, Option<u32>), String> {
+    let (records, cancelled) = cached_records(state, file, token).await.map_err(|error| error.message)?;
+    let records = access.reveal_all(&records);
+    if cancelled {
+        return Err("the export was cancelled by shutdown".to_string());
+    }
+    let traced = match provenance {
+        true => Some(trace_records(state, file, &records).await.map_err(|error| error.message)?.0),
+        false => None,
+    };
+    fs::create_dir_all(ARTIFACTS_DIR).await.map_err(|error| format!("could not create {}/: {}", ARTIFACTS_DIR, error))?;
+    let stem = std::path::Path::new(file.as_str()).file_stem().and_then(|stem| stem.to_str()).unwrap_or("export");
+    let mut name = format!("export-{}-{}.{}", id, stem, format.extension());
+    let part = PartFile { path: format!("{}/.{}.part", ARTIFACTS_DIR, name), keep: false };
+    
+    let (pool, dictionary) = {
+        let app_state = lock_state(state).map_err(|error| error.message)?;
+        let dictionary = app_state.dictionary.as_ref().map(|trained| trained.dictionary.clone());
+        (app_state.parser_pool.clone(), dictionary.filter(|_| compress))
+    };
+    let part_path = part.path.clone();
+    let count = records.len();
+    pool.run(move || write_records(&part_path, format, &records, traced))
+    .await
+    .map_err(|error| error.to_string())?
+    .map_err(|error| format!("could not write {}: {}", part.path, error))?;
+    let dictionary_id = dictionary.as_ref().map(|dictionary| dictionary.id);
+    let part = match compress {
+        true => {
+            name.push_str(".zst");
+            let compressed = PartFile { path: format!("{}/.{}.part", ARTIFACTS_DIR, name), keep: false };
+            let (source, destination) = (part.path.clone(), compressed.path.clone());
+            pool.run(move || compression::compress_file(source.as_ref(), destination.as_ref(), zstd::DEFAULT_COMPRESSION_LEVEL, dictionary.as_deref()))
+                .await
+                .map_err(|error| error.to_string())?
+                .map_err(|error| format!("could not compress {}: {}", part.path, error))?;
+            compressed
+        }
+        false => part,
+    };
+    
+    let path = format!("{}/{}", ARTIFACTS_DIR, name);
+    fs::rename(&part.path, &path).await.map_err(|error| format!("could not move the export to {}: {}", path, error))?;
+    let size_bytes = fs::metadata(&path).await.map(|metadata| metadata.len()).unwrap_or(0);
+    log!("📦 Exported {} record(s) of {} to {}", count, file, path);
+    Ok((name, count, size_bytes, dictionary_id))
+}
+
+/// Writes `records` to `path` in `format`, each with where it came from
+/// when `traced` is given. Blocking.
+fn write_records(
+    path: &str,
+    format: ExportFormat,
+    records: &[SalesRecord],
+    traced: Option<Vec<provenance::Provenance>>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut output = std::io::BufWriter::new(std::fs::File::create(path)?);
+    match (format, traced) {
+        (ExportFormat::Csv, None) => {
+            let mut writer = WriterBuilder::new().from_writer(&mut output);
+            for record in records.iter() {
+                writer.serialize(record)?;
+            }
+            writer.flush()?;
+        }
+        (ExportFormat::Csv, Some(traced)) => {
+            let mut writer = WriterBuilder::new().from_writer(&mut output);
+            for (record, provenance) in records.iter().zip(&traced) {
+                writer.serialize(provenance::TracedRecord::new(record, provenance))?;
+            }
+            writer.flush()?;
+        }
+        (ExportFormat::Jsonl, None) => {
+            for record in records.iter() {
+                serde_json::to_writer(&mut output, record)?;
+                std::io::Write::write_all(&mut output, b"\n")?;
+            }
+        }
+        (ExportFormat::Jsonl, Some(traced)) => {
+            for (record, provenance) in records.iter().zip(&traced) {
+                serde_json::to_writer(&mut output, &provenance::TracedRecord::new(record, provenance))?;
+                std::io::Write::write_all(&mut output, b"\n")?;
+            }
+        }
+        (ExportFormat::Json, None) => serde_json::to_writer(&mut output, records)?,
+        (ExportFormat::Json, Some(traced)) => {
+            let traced: Vec<_> = records.iter().zip(&traced).map(|(record, provenance)| provenance::TracedRecord::new(record, provenance)).collect();
+            serde_json::to_writer(&mut output, &traced)?
+        }
+    }
+    output.into_inner().map_err(|error| error.into_error())?.sync_all()?;
+    Ok(())
+}
+
+/// An export job; a completed one includes a freshly signed download link,
+/// valid for `expires_in` seconds (`ARTIFACT_TTL` by default).
+async fn get_export(
+    path: Result<axum::extract::Path<u64>, PathRejection>,
+    query: Result<Query<ArtifactLinkQuery>, QueryRejection>,
+    State(state): State<SharedState>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let axum::extract::Path(id) = path?;
+    let Query(params) = query?;
+    let app_state = lock_state(&state)?;
+    let job = app_state
+        .export_jobs
+        .get(&id)
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, "job_not_found", format!("no export job {}", id)).detail("id", id))?;
+    let expires_in = link_lifetime(&app_state, params.expires_in)?;
+    
+    let mut body = serde_json::to_value(job).unwrap_or_default();
+    if let Some(artifact) = job.artifact.as_ref().filter(|_| matches!(job.status, JobStatus::Completed)) {
+        body["download"] = download_link(&app_state, artifact, expires_in);
+    }
+    Ok(Json(body))
+}
+
+/// Seconds a download link asked for with `expires_in` lasts:
+/// `ARTIFACT_TTL` when omitted, and at most [`MAX_ARTIFACT_TTL_SECONDS`].
+fn link_lifetime(app_state: &AppState, expires_in: Option<u64>) -> Result<u64, ApiError> {
+    let expires_in = expires_in.unwrap_or(app_state.artifact_ttl_seconds);
+    if expires_in == 0 || expires_in > MAX_ARTIFACT_TTL_SECONDS {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_expiry",
+            format!("expires_in must be between 1 and {} seconds", MAX_ARTIFACT_TTL_SECONDS),
+        )
+        .detail("expires_in", expires_in));
+    }
+    Ok(expires_in)
+}
+
+/// A freshly signed link to `artifact` in `artifacts/`, with its expiry.
+fn download_link(app_state: &AppState, artifact: &str, expires_in: u64) -> serde_json::Value {
+    let expires_at = chrono::Utc::now() + chrono::Duration::seconds(expires_in as i64);
+    serde_json::json!({
+        "url": format!("/artifacts/{}", app_state.artifact_signer.sign(artifact, expires_at)),
+        "expires_at": expires_at.to_rfc3339()
+    })
+}
+
+async fn list_exports(State(state): State<SharedState>) -> Result<Json<serde_json::Value>, ApiError> {
+    let app_state = lock_state(&state)?;
+    let mut jobs: Vec<_> = app_state.export_jobs.values().cloned().collect();
+    jobs.sort_by_key(|job| job.id);
+    
+    Ok(Json(serde_json::json!({
+        "count": jobs.len(),
+        "jobs": jobs
+    })))
+}
+
+/// Starts the pipeline in the request body, JSON or, with a YAML content
+/// type, YAML, as a background job. The definition is checked first:
+/// files, aggregations, dedupe keys and scripts it names.
+async fn start_pipeline(
+    State(state): State<SharedState>,
+    headers: axum::http::HeaderMap,
+    body: Bytes,
+) -> Result<(StatusCode, Json<serde_json::Value>), ApiError> {
+    let mut definition = parse_pipeline(&headers, &body)?;
+    let access = column_access(&state, &headers)?;
+    check_pipeline(&state, &mut definition).await?;
+    
+    let (job, token) = {
+        let mut app_state = lock_state(&state)?;
+        let id = app_state.next_pipeline_id;
+        app_state.next_pipeline_id += 1;
+        let steps = definition
+            .steps
+            .iter()
+            .map(|step| PipelineStepReport {
+                op: step.operation.name(),
+                status: StepStatus::Pending,
+                records_in: None,
+                records_out: None,
+                duration_ms: None,
+                records_per_second: None,
+                error: None,
+                output: serde_json::Value::Null,
+            })
+            .collect();
+        let job = PipelineJob {
+            id,
+            name: definition.name.clone(),
+            status: JobStatus::Running,
+            error: None,
+            request_id: request_log::current(),
+            on_failure: definition.on_failure,
+            steps,
+            records: None,
+            artifact: None,
+            created_at: chrono::Utc::now(),
+            finished_at: None,
+        };
+        app_state.pipeline_jobs.insert(id, job.clone());
+        (job, app_state.shutdown.child_token())
+    };
+    
+    let job_state = state.clone();
+    let job_id = job.id;
+    log!("🪈 Running pipeline {} ({} steps)", job_id, definition.steps.len());
+    request_log::spawn(cost::inherit(async move {
+        run_pipeline(&job_state, job_id, definition, access, token).await;
+    }));
+    
+    Ok((StatusCode::ACCEPTED, Json(serde_json::json!({
+        "status": "running",
+        "job": job,
+        "poll": format!("/pipelines/{}", job_id)
+    }))))
+}
+
+/// A pipeline definition from a request body: YAML when the content type
+/// says so, JSON otherwise.
+fn parse_pipeline(headers: &axum::http::HeaderMap, body: &[u8]) -> Result<PipelineDefinition, ApiError> {
+    let content_type = headers.get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok()).unwrap_or_default();
+    let yaml = content_type.contains("yaml");
+    let parsed = match yaml {
+        true => serde_yaml::from_slice(body).map_err(|error| error.to_string()),
+        false => serde_json::from_slice(body).map_err(|error| error.to_string()),
+    };
+    parsed.map_err(|message| {
+        ApiError::new(StatusCode::BAD_REQUEST, "invalid_pipeline", message)
+            .detail("format", if yaml { "yaml" } else { "json" })
+            .detail("operations", ["ingest", "validate", "transform", "dedupe", "aggregate", "export"])
+    })
+}
+
+/// Refuses a pipeline that could not run: no steps, records used before
+/// anything is ingested, or a step naming a file, column, aggregation or
+/// script that does not work. Ingested files are resolved to their ids.
+async fn check_pipeline(state: &SharedState, definition: &mut PipelineDefinition) -> Result<(), ApiError> {
+    let invalid = |index: usize, op: &str, message: String| {
+        ApiError::new(StatusCode::BAD_REQUEST, "invalid_pipeline", message).detail("step", index + 1).detail("op", op)
+    };
+    if definition.steps.is_empty() || definition.steps.len() > MAX_PIPELINE_STEPS {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid_pipeline", format!("a pipeline has 1 to {} steps", MAX_PIPELINE_STEPS))
+            .detail("steps", definition.steps.len()));
+    }
+    if let Some(first) = definition.steps.first().filter(|step| !matches!(step.operation, PipelineOperation::Ingest { .. })) {
+        return Err(invalid(0, first.operation.name(), "the first step must ingest a file".to_string()));
+    }
+    let registry = lock_state(state)?.aggregators.clone();
+    for (index, step) in definition.steps.iter_mut().enumerate() {
+        let op = step.operation.name();
+        match &mut step.operation {
+            PipelineOperation::Ingest { file } => {
+                let resolved = FileId::resolve(file, state).await?;
+                fs::metadata(resolved.path())
+                    .await
+                    .map_err(|error| ApiError::read_failed(&resolved.path(), error).detail("step", index + 1))?;
+                *file = resolved.to_string();
+            }
+            PipelineOperation::Validate { .. } | PipelineOperation::Export { .. } => {}
+            PipelineOperation::Transform { filter, script } => {
+                if filter.is_empty() && script.is_none() {
+                    return Err(invalid(index, op, "a transform needs a filter, a script or both".to_string()));
+                }
+                if let Some(condition) = filter.iter().find(|condition| !SALES_COLUMNS.contains(&condition.column.as_str())) {
+                    return Err(invalid(index, op, format!("records have no column {}", condition.column)).detail("columns", SALES_COLUMNS));
+                }
+                #[cfg(feature = "scripting")]
+                if let Some(source) = script {
+                    scripting::RowScript::compile(source).map_err(|error| {
+                        invalid(index, op, error.message).detail("line", error.line).detail("column", error.column)
+                    })?;
+                }
+                #[cfg(not(feature = "scripting"))]
+                if script.is_some() {
+                    return Err(invalid(index, op, "scripts need the server built with the scripting feature".to_string()));
+                }
+            }
+            PipelineOperation::Dedupe { keys } => {
+                dedup::DedupConfig::new(keys, dedup::DedupMode::HashSet, u64::MAX, None).map_err(|message| invalid(index, op, message))?;
+            }
+            PipelineOperation::Aggregate { metrics } => {
+                let unknown: Vec<&String> = metrics.iter().filter(|name| !registry.contains(name)).collect();
+                if metrics.is_empty() || !unknown.is_empty() {
+                    let available: Vec<_> = registry.list().into_iter().map(|info| info.name).collect();
+                    return Err(invalid(index, op, "metrics must name registered aggregations".to_string())
+                        .detail("unknown", unknown)
+                        .detail("available", available));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Runs the steps of pipeline job `id` in order, each on the records the
+/// steps before it left, and reports each on the job as it goes. Records
+/// are ingested as `access`, the requester's, may see them.
+async fn run_pipeline(state: &SharedState, id: u64, definition: PipelineDefinition, access: ColumnAccess, token: CancellationToken) {
+    let mut records: Arc<Vec<SalesRecord>> = Arc::new(Vec::new());
+    let (mut error, mut failed) = (None, 0);
+    for (index, step) in definition.steps.iter().enumerate() {
+        if token.is_cancelled() {
+            error = Some("the pipeline was cancelled by shutdown".to_string());
+            break;
+        }
+        update_pipeline(state, id, |job| job.steps[index].status = StepStatus::Running);
+        let op = step.operation.name();
+        let timer = PerformanceTimer::new(format!("Pipeline {} step {} ({})", id, index + 1, op));
+        let records_in = records.len();
+        let result = run_pipeline_step(state, id, index, &step.operation, &records, &access, &token).await;
+        // An ingest is measured by the records it reads
+        let handled = match &result {
+            Ok((_, Some(kept))) => records_in.max(kept.len()),
+            _ => records_in,
+        };
+        let metrics = timer.finish(handled);
+        let (duration_ms, records_per_second) = (metrics.duration.as_secs_f64() * 1_000.0, metrics.records_per_second);
+        let _ = record_processing(state, metrics);
+        match result {
+            Ok((output, kept)) => {
+                if let Some(kept) = kept {
+                    records = kept;
+                }
+                let records_out = records.len();
+                update_pipeline(state, id, |job| {
+                    if let Some(artifact) = output.get("artifact").and_then(|artifact| artifact.as_str()) {
+                        job.artifact = Some(artifact.to_string());
+                    }
+                    let report = &mut job.steps[index];
+                    report.status = StepStatus::Completed;
+                    (report.records_in, report.records_out) = (Some(records_in), Some(records_out));
+                    (report.duration_ms, report.records_per_second) = (Some(duration_ms), Some(records_per_second));
+                    report.output = output;
+                });
+            }
+            Err(message) => {
+                log!("⚠️  Pipeline {} step {} ({}) failed: {}", id, index + 1, op, message);
+                failed += 1;
+                update_pipeline(state, id, |job| {
+                    let report = &mut job.steps[index];
+                    report.status = StepStatus::Failed;
+                    report.records_in = Some(records_in);
+                    report.duration_ms = Some(duration_ms);
+                    report.error = Some(message.clone());
+                });
+                if step.on_failure.unwrap_or(definition.on_failure) == OnFailure::Stop {
+                    error = Some(format!("step {} ({}) failed: {}", index + 1, op, message));
+                    break;
+                }
+            }
+        }
+    }
+    
+    log!("🪈 Pipeline {} {}: {} record(s), {} failed step(s)", id, if error.is_some() { "stopped" } else { "finished" }, records.len(), failed);
+    update_pipeline(state, id, |job| {
+        for report in job.steps.iter_mut().filter(|report| report.status == StepStatus::Pending) {
+            report.status = StepStatus::Skipped;
+        }
+        job.status = if error.is_some() { JobStatus::Failed } else { JobStatus::Completed };
+        job.error = error;
+        job.records = Some(records.len());
+        job.finished_at = Some(chrono::Utc::now());
+    });
+}
+
+fn update_pipeline(state: &SharedState, id: u64, change: impl FnOnce(&mut PipelineJob)) {
+    if let Some(job) = state.lock().ok().as_mut().and_then(|app_state| app_state.pipeline_jobs.get_mut(&id)) {
+        change(job);
+    }
+}
+
+/// Runs one step of pipeline job `id` over `records`. Returns what the
+/// step reports and, unless they pass through, the records it left; the
+/// records are untouched when it fails.
+async fn run_pipeline_step(
+    state: &SharedState,
+    id: u64,
+    index: usize,
+    operation: &PipelineOperation,
+    records: &Arc<Vec<SalesRecord>>,
+    access: &ColumnAccess,
+    token: &CancellationToken,
+) -> Result<(serde_json::Value, Option<Arc<Vec<SalesRecord>>>), String> {
+    let pool = lock_state(state).map_err(|error| error.message)?.parser_pool.clone();
+    let input = records.clone();
+    let worker_token = token.clone();
+    match operation {
+        PipelineOperation::Ingest { file } => {
+            let file = FileId::resolve(file, state).await.map_err(|error| error.message)?;
+            let (loaded, cancelled) = cached_records(state, &file, token).await.map_err(|error| error.message)?;
+            if cancelled {
+                return Err("cancelled by shutdown".to_string());
+            }
+            let loaded = access.reveal_all(&loaded);
+            let output = serde_json::json!({ "file": file, "records": loaded.len() });
+            let combined = match input.is_empty() {
+                true => loaded,
+                false => Arc::new(input.iter().chain(loaded.iter()).cloned().collect()),
+            };
+            Ok((output, Some(combined)))
+        }
+        PipelineOperation::Validate { on_invalid } => {
+            let on_invalid = *on_invalid;
+            pool.run(move || {
+                let mut invalid = Vec::new();
+                for (position, record) in input.iter().enumerate() {
+                    if let Err((column, message)) = check_record(record) {
+                        if matches!(on_invalid, OnInvalid::Fail) {
+                            return Err(format!("record {} (id {}): {}", position + 1, record.id, message));
+                        }
+                        invalid.push((position, record.id, column, message));
+                    }
+                }
+                let examples: Vec<serde_json::Value> = invalid
+                    .iter()
+                    .take(MAX_INVALID_EXAMPLES)
+                    .map(|(_, id, column, message)| serde_json::json!({ "id": id, "column": column, "message": message }))
+                    .collect();
+                let output = serde_json::json!({ "invalid": invalid.len(), "examples": examples });
+                if invalid.is_empty() {
+                    return Ok((output, None));
+                }
+                let mut dropped = invalid.iter().map(|(position, ..)| *position).peekable();
+                let kept: Vec<SalesRecord> = input
+                    .iter()
+                    .enumerate()
+                    .filter(|(position, _)| dropped.next_if_eq(position).is_none())
+                    .map(|(_, record)| record.clone())
+                    .collect();
+                Ok((output, Some(Arc::new(kept))))
+            })
+            .await
+            .map_err(|error| error.to_string())?
+        }
+        PipelineOperation::Transform { filter, script } => {
+            #[cfg(feature = "scripting")]
+            let script = script.as_deref().map(scripting::RowScript::compile).transpose().map_err(|error| error.message)?;
+            #[cfg(not(feature = "scripting"))]
+            let _ = script;
+            let filter = filter.clone();
+            pool.run(move || {
+                let mut kept = Vec::new();
+                for record in input.iter() {
+                    if worker_token.is_cancelled() {
+                        return Err("cancelled by shutdown".to_string());
+                    }
+                    let matched = filter
+                        .iter()
+                        .all(|condition| record_field(record, &condition.column).is_some_and(|field| condition.matches(&field)));
+                    if !matched {
+                        continue;
+                    }
+                    #[cfg(feature = "scripting")]
+                    if let Some(script) = &script {
+                        let transformed = script.apply(record).map_err(|error| format!("record id {}: {}", record.id, error.message))?;
+                        if let Some(transformed) = transformed {
+                            let transformed = serde_json::from_value(transformed)
+                                .map_err(|error| format!("the script did not leave record id {} a sales record: {}", record.id, error))?;
+                            kept.push(transformed);
+                        }
+                        continue;
+                    }
+                    kept.push(record.clone());
+                }
+                let output = serde_json::json!({ "kept": kept.len(), "filtered": input.len() - kept.len() });
+                Ok((output, Some(Arc::new(kept))))
+            })
+            .await
+            .map_err(|error| error.to_string())?
+        }
+        PipelineOperation::Dedupe { keys } => {
+            // Exact: the records are all in memory already
+            let config = dedup::DedupConfig::new(keys, dedup::DedupMode::HashSet, u64::MAX, None)?;
+            let name = format!("pipeline-{}", id);
+            pool.run(move || {
+                let mut deduplicator = dedup::Deduplicator::new(Arc::new(config), &name);
+                let mut kept = (*input).clone();
+                let dropped = deduplicator.retain(&mut kept);
+                let output = serde_json::json!({ "keys": deduplicator.config().keys, "dropped": dropped });
+                (output, Some(Arc::new(kept)))
+            })
+            .await
+            .map_err(|error| error.to_string())
+        }
+        PipelineOperation::Aggregate { metrics } => {
+            let registry = lock_state(state).map_err(|error| error.message)?.aggregators.clone();
+            let names = metrics.clone();
+            let run = pool
+                .run(move || registry.run(&names, &input, || worker_token.is_cancelled()))
+                .await
+                .map_err(|error| error.to_string())?;
+            if run.cancelled {
+                return Err("cancelled by shutdown".to_string());
+            }
+            Ok((serde_json::json!({ "results": run.results }), None))
+        }
+        PipelineOperation::Export { format } => {
+            let format = format.unwrap_or(ExportFormat::Csv);
+            fs::create_dir_all(ARTIFACTS_DIR).await.map_err(|error| format!("could not create {}/: {}", ARTIFACTS_DIR, error))?;
+            let name = format!("pipeline-{}-step{}.{}", id, index + 1, format.extension());
+            let path = format!("{}/{}", ARTIFACTS_DIR, name);
+            let part = PartFile { path: format!("{}/.{}.part", ARTIFACTS_DIR, name), keep: false };
+            let part_path = part.path.clone();
+            let count = input.len();
+            pool.run(move || write_records(&part_path, format, &input, None))
+                .await
+                .map_err(|error| error.to_string())?
+                .map_err(|error| format!("could not write {}: {}", part.path, error))?;
+            fs::rename(&part.path, &path).await.map_err(|error| format!("could not move the export to {}: {}", path, error))?;
+            let size_bytes = fs::metadata(&path).await.map(|metadata| metadata.len()).unwrap_or(0);
+            log!("📦 Exported {} record(s) of pipeline {} to {}", count, id, path);
+            Ok((serde_json::json!({ "artifact": name, "format": format, "records": count, "size_bytes": size_bytes }), None))
+        }
+    }
+}
+
+/// A pipeline job; one that exported includes a freshly signed link to
+/// the last export, valid for `expires_in` seconds (`ARTIFACT_TTL` by
+/// default).
+async fn get_pipeline(
+    path: Result<axum::extract::Path<u64>, PathRejection>,
+    query: Result<Query<ArtifactLinkQuery>, QueryRejection>,
+    State(state): State<SharedState>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let axum::extract::Path(id) = path?;
+    let Query(params) = query?;
+    let app_state = lock_state(&state)?;
+    let job = app_state
+        .pipeline_jobs
+        .get(&id)
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, "job_not_found", format!("no pipeline job {}", id)).detail("id", id))?;
+    let expires_in = link_lifetime(&app_state, params.expires_in)?;
+    
+    let mut body = serde_json::to_value(job).unwrap_or_default();
+    if let Some(artifact) = &job.artifact {
+        body["download"] = download_link(&app_state, artifact, expires_in);
+    }
+    Ok(Json(body))
+}
+
+async fn list_pipelines(State(state): State<SharedState>) -> Result<Json<serde_json::Value>, ApiError> {
+    let app_state = lock_state(&state)?;
+    let mut jobs: Vec<_> = app_state.pipeline_jobs.values().cloned().collect();
+    jobs.sort_by_key(|job| job.id);
+    
+    Ok(Json(serde_json::json!({
+        "count": jobs.len(),
+        "jobs": jobs
+    })))
+}
+
+/// Streams the artifact a signed token grants. The token is all a client
+/// needs, so links can be shared; they name one file and stop working at
+/// their deadline.
+async fn download_artifact(
+    axum::extract::Path(token): axum::extract::Path<String>,
+    State(state): State<SharedState>,
+) -> Result<Response, ApiError> {
+    let signer = lock_state(&state)?.artifact_signer.clone();
+    let name = signer.verify(&token, chrono::Utc::now()).map_err(|error| match error {
+        artifacts::TokenError::Invalid => ApiError::new(StatusCode::FORBIDDEN, "invalid_token", error.to_string()),
+        artifacts::TokenError::Expired(at) => {
+            ApiError::new(StatusCode::GONE, "link_expired", error.to_string()).detail("expired_at", at.to_rfc3339())
+        }
+    })?;
+    let path = format!("{}/{}", ARTIFACTS_DIR, name);
+    let artifact = fs::File::open(&path).await.map_err(|error| match error.kind() {
+        std::io::ErrorKind::NotFound => {
+            ApiError::new(StatusCode::NOT_FOUND, "artifact_not_found", "the artifact was removed").detail("artifact", &name)
+        }
+        _ => ApiError::read_failed(&path, error),
+    })?;
+    let length = artifact.metadata().await.map_err(|error| ApiError::read_failed(&path, error))?.len();
+    let content_type = match std::path::Path::new(&name).extension().and_then(|extension| extension.to_str()) {
+        Some("csv") => ExportFormat::Csv.content_type(),
+        Some("jsonl") => ExportFormat::Jsonl.content_type(),
+        Some("json") => ExportFormat::Json.content_type(),
+        Some("zst") => "application/zstd",
+        _ => "application/octet-stream",
+    };
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_LENGTH, length.to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", name)),
+        ],
+        Body::from_stream(tokio_util::io::ReaderStream::new(artifact)),
+    )
+        .into_response())
+}
+
+/// Stops a generation run once the request or the server is cancelled, and
+/// for background jobs publishes a throughput sample to the job every second.
+struct JobObserver {
+    token: CancellationToken,
+    sampler: ThroughputSampler,
+    job: Option<(SharedState, u64)>,
+}
+
+impl GenerateObserver for JobObserver {
+    fn chunk_written(&mut self, stats: &generator::GenerateStats, _position: u64) {
+        if self.sampler.record(stats.rows, stats.bytes) {
+            if let Some((state, id)) = &self.job {
+                if let Some(job) = state.lock().ok().as_mut().and_then(|app_state| app_state.generation_jobs.get_mut(id)) {
+                    job.throughput = self.sampler.samples();
+                }
+            }
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+}
+
+/// Runs the shared generator on a blocking thread. Cancelling `token` stops
+/// it between chunks and removes the partial file. `job` names the
+/// background job that receives throughput samples.
+async fn run_generator(
+    request: &GenerateRequest,
+    file: &FileId,
+    token: CancellationToken,
+    job: Option<(SharedState, u64)>,
+) -> Result<(), String> {
+    let path = file.path();
+    let options = GenerateOptions {
+        seed: request.seed,
+        error_rate: request.error_rate.unwrap_or(0.0),
+        ..GenerateOptions::new(request.rows)
+    };
+    
+    log!("🏭 Generating {} ({} rows)", path, request.rows);
+    let job_path = path.clone();
+    let mut observer = JobObserver {
+        token: token.clone(),
+        sampler: ThroughputSampler::default(),
+        job,
+    };
+    let result = tokio::task::spawn_blocking(move || {
+        generator::generate(&job_path, &Schema::sales(), &options, &mut observer)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|result| result);
+    
+    let result = match result {
+        Ok(()) if token.is_cancelled() => Err("cancelled".to_string()),
+        result => result,
+    };
+    if result.is_err() {
+        let _ = fs::remove_file(&path).await;
+    }
+    result
+}
+
+async fn register_generated_file(
+    state: &SharedState,
+    request: &GenerateRequest,
+    file: &FileId,
+) -> Result<CatalogEntry, ApiError> {
+    let size_bytes = fs::metadata(file.path())
+        .await
+        .map(|metadata| metadata.len())
+        .unwrap_or(0);
+    let entry = CatalogEntry {
+        filename: file.clone(),
+        rows: request.rows,
+        size_bytes,
+        seed: request.seed,
+        error_rate: request.error_rate.unwrap_or(0.0),
+        created_at: chrono::Utc::now(),
+    };
+    
+    let mut app_state = lock_state(state)?;
+    // A regenerated file must not be analyzed from the old cached rows or statistics
+    app_state.cached_data.remove(file);
+    #[cfg(feature = "columnar")]
+    app_state.columnar_data.remove(file);
+    app_state.parsed_extents.remove(file);
+    app_state.column_stats.remove(file);
+    app_state.bloom_filters.remove(file);
+    app_state.search_indexes.remove(file);
+    app_state.catalog.insert(file.clone(), entry.clone());
+    Ok(entry)
+}
+
+/// Streams sales rows generated on the fly, a batch per body chunk, so any
+/// volume can be served in constant memory. The seed is echoed in the
+/// `x-synthetic-seed` header to make a run reproducible.
+async fn stream_synthetic(
+    query: Result<Query<SyntheticQuery>, QueryRejection>,
+    State(state): State<SharedState>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Query(params) = query?;
+    // Endless streams would otherwise hold up graceful shutdown
+    let shutdown = lock_state(&state)?.shutdown.clone();
+    let seed = params.seed.unwrap_or_else(rand::random);
+    let schema = Schema::sales();
+    let rng = ChaCha8Rng::seed_from_u64(seed);
+    
+    let stream = futures::stream::unfold((0u64, schema, rng), move |(row, schema, mut rng)| {
+        let shutdown = shutdown.clone();
+        async move {
+            let end = match params.rows {
+                Some(rows) if row >= rows => return None,
+                Some(rows) => (row + SYNTHETIC_BATCH_ROWS).min(rows),
+                None => row + SYNTHETIC_BATCH_ROWS,
+            };
+            if shutdown.is_cancelled() {
+                return None;
+            }
+            
+            let mut writer = WriterBuilder::new().has_headers(false).from_writer(Vec::new());
+            let mut fields = Vec::with_capacity(schema.columns.len());
+            if row == 0 {
+                writer.write_record(schema.header()).ok()?;
+            }
+            for current in row..end {
+                schema.generate_row(&mut rng, current, &mut fields);
+                writer.write_record(&fields).ok()?;
+            }
+            let data = writer.into_inner().ok()?;
+            
+            Some((Ok::<_, std::io::Error>(Bytes::from(data)), (end, schema, rng)))
+        }
+    });
+    
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv".to_string()),
+            (header::HeaderName::from_static("x-synthetic-seed"), seed.to_string()),
+        ],
+        Body::from_stream(stream),
+    ))
+}
+
+/// Samples the whole process for `seconds` while other requests keep being
+/// served, so put the server under load (e.g. /benchmark) meanwhile. Only
+/// one profile can run at a time; a second request gets 409 Conflict.
+#[cfg(feature = "pprof")]
+async fn cpu_profile(
+    query: Result<Query<ProfileQuery>, QueryRejection>,
+    State(state): State<SharedState>,
+) -> Result<impl IntoResponse, ApiError> {
+    use pprof::protos::Message;
+    
+    let Query(params) = query?;
+    let seconds = params.seconds.unwrap_or(10);
+    if seconds == 0 || seconds > MAX_PROFILE_SECONDS {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_duration",
+            format!("seconds must be between 1 and {}", MAX_PROFILE_SECONDS),
+        )
+        .detail("seconds", seconds));
+    }
+    let format = params.format.unwrap_or_else(|| "flamegraph".to_string());
+    if format != "flamegraph" && format != "pprof" {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid_format", format!("unknown profile format '{}'", format))
+            .detail("format", &format)
+            .detail("supported", ["flamegraph", "pprof"]));
+    }
+    
+    let guard = pprof::ProfilerGuardBuilder::default()
+        .frequency(99)
+        .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+        .build()
+        .map_err(|error| ApiError::new(StatusCode::CONFLICT, "profiler_busy", error.to_string()))?;
+    log!("🔬 Profiling CPU for {}s...", seconds);
+    
+    // Shutdown ends the profile early rather than holding the server up
+    let shutdown = lock_state(&state)?.shutdown.clone();
+    tokio::select! {
+        _ = tokio::time::sleep(std::time::Duration::from_secs(seconds)) => {}
+        _ = shutdown.cancelled() => {}
+    }
+    
+    let profile_failed = |error: String| {
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "profile_failed", error).detail("format", &format)
+    };
+    let report = guard.report().build().map_err(|error| profile_failed(error.to_string()))?;
+    let mut body = Vec::new();
+    let content_type = if format == "pprof" {
+        let profile = report.pprof().map_err(|error| profile_failed(error.to_string()))?;
+        profile.encode(&mut body).map_err(|error| profile_failed(error.to_string()))?;
+        "application/octet-stream"
+    } else {
+        report.flamegraph(&mut body).map_err(|error| profile_failed(error.to_string()))?;
+        "image/svg+xml"
+    };
+    
+    Ok(([(header::CONTENT_TYPE, content_type)], body))
+}
+
+/// Producer for POST /publish, for the brokers in `KAFKA_BROKERS`
+/// (localhost:9092 by default).
+#[cfg(feature = "kafka")]
+fn kafka_sink() -> Option<sinks::kafka::KafkaSink> {
+    let brokers = std::env::var("KAFKA_BROKERS").unwrap_or_else(|_| "localhost:9092".to_string());
+    match sinks::kafka::KafkaSink::new(&brokers) {
+        Ok(sink) => Some(sink),
+        Err(error) => {
+            println!("⚠️  No Kafka producer for {}: {}", brokers, error);
+            None
+        }
+    }
+}
+
+/// Publishes every record of a file to a Kafka topic as a JSON message
+/// keyed by record id. The rows come from the cache, or from a parse shared
+/// with concurrent requests, as in /analyze.
+#[cfg(feature = "kafka")]
+async fn publish_records(
+    axum::extract::Path(filename): axum::extract::Path<String>,
+    query: Result<Query<PublishQuery>, QueryRejection>,
+    State(state): State<SharedState>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let Query(params) = query?;
+    let topic = params.topic.unwrap_or_else(|| "sales".to_string());
+    // Kafka's own rule for topic names
+    let valid_topic = !topic.is_empty()
+        && topic.len() <= 249
+        && topic != "."
+        && topic != ".."
+        && topic.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+    if !valid_topic {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_topic",
+            "topic names are 1-249 letters, digits, '.', '_' or '-'",
+        )
+        .detail("topic", &topic));
+    }
+    let format = params.format.unwrap_or_else(|| "json".to_string());
+    if format != "json" {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid_format", format!("unknown message format '{}'", format))
+            .detail("format", &format)
+            .detail("supported", ["json"]));
+    }
+    
+    let file = FileId::resolve(&filename, &state).await?;
+    let (token, _guard) = request_token(&state)?;
+    let sink = lock_state(&state)?.kafka.clone().ok_or_else(|| {
+        ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "kafka_unavailable", "the server has no Kafka producer; check KAFKA_BROKERS")
+    })?;
+    // Kafka keeps what it is sent, so it gets what the request may see
+    let access = column_access(&state, &headers)?;
+    let (records, _) = cached_records(&state, &file, &token).await?;
+    let records = access.reveal_all(&records);
+    
+    let mut timer = PerformanceTimer::new(format!("Publishing {} to {}", file, topic));
+    let report = sink
+        .publish_json(&topic, &records, |record| record.id.to_string(), &token)
+        .await
+        .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "serialization_failed", error.to_string()))?;
+    timer.add_bytes(report.bytes_delivered);
+    let metrics = timer.finish(report.delivered);
+    record_processing(&state, metrics)?;
+    
+    if report.delivered == 0 && report.failed > 0 {
+        return Err(ApiError::new(StatusCode::BAD_GATEWAY, "publish_failed", "no message reached the Kafka brokers")
+            .detail("brokers", sink.brokers())
+            .detail("topic", &topic)
+            .detail("first_error", &report.first_error));
+    }
+    
+    Ok(Json(serde_json::json!({
+        "filename": file,
+        "records": records.len(),
+        "brokers": sink.brokers(),
+        "format": format,
+        "delivery": report
+    })))
+}
+
+/// Upserts every record of a file into a SQLite table, matched on the key
+/// columns, so ingesting a file again, or shards that overlap, updates rows
+/// instead of duplicating them. Each row written keeps its provenance.
+/// Reports how many rows were inserted, updated and left unchanged.
+#[cfg(feature = "sqlite")]
+async fn ingest_records(
+    axum::extract::Path(filename): axum::extract::Path<String>,
+    query: Result<Query<IngestQuery>, QueryRejection>,
+    State(state): State<SharedState>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    use sinks::sqlite::IngestError;
+    
+    let Query(params) = query?;
+    let table = params.table.unwrap_or_else(|| "sales".to_string());
+    let key: Vec<String> = params
+        .key
+        .as_deref()
+        .unwrap_or("id")
+        .split(',')
+        .map(|column| column.trim().to_string())
+        .filter(|column| !column.is_empty())
+        .collect();
+    
+    let file = FileId::resolve(&filename, &state).await?;
+    let (token, _guard) = request_token(&state)?;
+    let sink = lock_state(&state)?.sqlite.clone();
+    // SQLite keeps what it is given, so it gets what the request may see
+    let access = column_access(&state, &headers)?;
+    let (records, _) = cached_records(&state, &file, &token).await?;
+    let (provenance, _) = trace_records(&state, &file, &records).await?;
+    let records = access.reveal_all(&records);
+    
+    let timer = PerformanceTimer::new(format!("Ingesting {} into {}", file, table));
+    let report = {
+        let (sink, table, key, records, token) = (sink.clone(), table.clone(), key.clone(), records.clone(), token.clone());
+        tokio::task::spawn_blocking(move || sink.ingest(&table, &key, &records, &provenance, &token))
+            .await
+            .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "ingest_failed", error.to_string()))?
+    }
+    .map_err(|error| match &error {
+        IngestError::Invalid(message) => ApiError::new(StatusCode::BAD_REQUEST, "invalid_ingest", message.clone())
+            .detail("table", &table)
+            .detail("key", &key)
+            .detail("columns", sinks::sqlite::COLUMNS),
+        IngestError::DuplicateKeys { .. } => ApiError::new(StatusCode::CONFLICT, "duplicate_keys", error.to_string())
+            .detail("table", &table)
+            .detail("key", &key),
+        IngestError::Sqlite(_) => ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "ingest_failed", error.to_string())
+            .detail("database", sink.path().display().to_string()),
+    })?;
+    let metrics = timer.finish(report.inserted + report.updated + report.unchanged);
+    record_processing(&state, metrics)?;
+    log!(
+        "🗄️  Ingested {} into {}: {} inserted, {} updated, {} unchanged",
+        file,
+        table,
+        report.inserted,
+        report.updated,
+        report.unchanged
+    );
+    
+    Ok(Json(serde_json::json!({
+        "filename": file,
+        "records": records.len(),
+        "database": sink.path().display().to_string(),
+        "ingest": report
+    })))
+}
+
+/// Runs a read-only SQL query over the CSV files in sample_data/ and
+/// uploads/, with the same engine as the `csvsql` REPL. Tables are
+/// refreshed first, so a file uploaded a moment ago can be queried by its
+/// upload id, e.g. `uploads_20240101120000_1a2b3c4d`.
+#[cfg(feature = "sql")]
+async fn run_query(
+    State(state): State<SharedState>,
+    headers: axum::http::HeaderMap,
+    request: Result<Json<QueryRequest>, JsonRejection>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let Json(request) = request?;
+    let access = column_access(&state, &headers)?;
+    let limit = request.limit.unwrap_or(MAX_QUERY_ROWS);
+    if limit > MAX_QUERY_ROWS {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid_limit", format!("limit must be at most {}", MAX_QUERY_ROWS))
+            .detail("limit", limit));
+    }
+    
+    let engine = lock_state(&state)?.query.clone();
+    let tables = engine
+        .refresh()
+        .await
+        .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "refresh_failed", error.to_string()))?;
+    let timer = PerformanceTimer::new("SQL query".to_string());
+    let output = engine.run(&request.sql).await.map_err(|error| {
+        use datafusion::error::DataFusionError;
+        // Mistakes in the query itself, as opposed to failures running it
+        let (status, code) = match error.find_root() {
+            DataFusionError::SQL(..) | DataFusionError::Plan(_) | DataFusionError::SchemaError(..) | DataFusionError::NotImplemented(_) => {
+                (StatusCode::BAD_REQUEST, "invalid_query")
+            }
+            _ => (StatusCode::INTERNAL_SERVER_ERROR, "query_failed"),
+        };
+        ApiError::new(status, code, error.to_string()).detail("tables", &tables)
+    })?;
+    let mut rows = output
+        .to_json_rows(limit)
+        .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "serialization_failed", error.to_string()))?;
+    let cipher = lock_state(&state)?.encryption.clone();
+    if let Some(cipher) = cipher {
+        reveal_query_rows(&mut rows, &cipher, access.masked.is_some()).map_err(|error| ApiError::decryption_failed("the query result", error))?;
+    }
+    let metrics = timer.finish(output.rows);
+    record_processing(&state, metrics)?;
+    
+    Ok(Json(serde_json::json!({
+        "columns": output.columns(),
+        "rows": rows,
+        "row_count": output.rows,
+        "truncated": output.rows > limit,
+        "elapsed_ms": output.elapsed.as_secs_f64() * 1_000.0
+    })))
+}
+
+/// Query result rows as a request may see them. The tables hold fields as
+/// stored, so encrypted columns, and encrypted values selected under
+/// another name, are decrypted, or masked when `masked`.
+#[cfg(feature = "sql")]
+fn reveal_query_rows(rows: &mut serde_json::Value, cipher: &encryption::ColumnCipher, masked: bool) -> Result<(), (String, encryption::DecryptError)> {
+    let rows = rows.as_array_mut().into_iter().flatten().filter_map(serde_json::Value::as_object_mut);
+    for row in rows {
+        for (column, value) in row.iter_mut() {
+            let Some(field) = value.as_str() else {
+                continue;
+            };
+            if !cipher.encrypts(column) && !field.starts_with(encryption::PREFIX) {
+                continue;
+            }
+            if masked {
+                *value = serde_json::json!(encryption::MASK);
+                continue;
+            }
+            // A value selected under another name is bound to the column it
+            // was encrypted for, which is one of the cipher's
+            let candidates: Vec<&str> = match cipher.encrypts(column) {
+                true => vec![column.as_str()],
+                false => cipher.columns().iter().map(String::as_str).collect(),
+            };
+            let decrypted = candidates
+                .iter()
+                .map(|encrypted| cipher.decrypt(encrypted, field))
+                .find(Result::is_ok)
+                .unwrap_or_else(|| cipher.decrypt(candidates[0], field));
+            *value = serde_json::json!(decrypted.map_err(|error| (column.clone(), error))?);
+        }
+    }
+    Ok(())
+}
+
+/// Runs the Rhai script in the request body on every record of a file and
+/// returns the records it kept, as it left them. The script sees each
+/// record as `record`; ending it with a boolean filters.
+#[cfg(feature = "scripting")]
+async fn transform_records(
+    axum::extract::Path(filename): axum::extract::Path<String>,
+    query: Result<Query<TransformQuery>, QueryRejection>,
+    State(state): State<SharedState>,
+    headers: axum::http::HeaderMap,
+    body: Bytes,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let Query(params) = query?;
+    let access = column_access(&state, &headers)?;
+    let limit = params.limit.unwrap_or(DEFAULT_TRANSFORM_ROWS);
+    if limit > MAX_TRANSFORM_ROWS {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid_limit", format!("limit must be at most {}", MAX_TRANSFORM_ROWS))
+            .detail("limit", limit));
+    }
+    let source = std::str::from_utf8(&body)
+        .map_err(|_| ApiError::new(StatusCode::BAD_REQUEST, "invalid_script", "the script is not UTF-8"))?;
+    if source.trim().is_empty() {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid_script", "send the script as the request body"));
+    }
+    let script = scripting::RowScript::compile(source).map_err(|error| {
+        ApiError::new(StatusCode::BAD_REQUEST, "invalid_script", error.message)
+            .detail("line", error.line)
+            .detail("column", error.column)
+    })?;
+    
+    let file = FileId::resolve(&filename, &state).await?;
+    let (token, _guard) = request_token(&state)?;
+    let (records, cancelled) = cached_records(&state, &file, &token).await?;
+    
+    // Scripts are CPU-bound, so they run off the async workers
+    let timer = PerformanceTimer::new(format!("Rhai transform of {}", file));
+    let worker_token = token.clone();
+    let input = access.reveal_all(&records);
+    let run = tokio::task::spawn_blocking(move || {
+        let mut output = Vec::new();
+        let (mut kept, mut processed, mut stopped) = (0, 0, false);
+        for record in input.iter() {
+            if worker_token.is_cancelled() {
+                stopped = true;
+                break;
+            }
+            match script.apply(record) {
+                Ok(Some(transformed)) => {
+                    kept += 1;
+                    if output.len() < limit {
+                        output.push(transformed);
+                    }
+                }
+                Ok(None) => {}
+                Err(error) => return Err((processed, record.id, error)),
+            }
+            processed += 1;
+        }
+        Ok((output, kept, processed, stopped))
+    })
+    .await
+    .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "script_panicked", error.to_string()))?;
+    let (output, kept, processed, stopped) = run.map_err(|(index, id, error)| {
+        ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "script_failed", error.message)
+            .detail("record", index + 1)
+            .detail("id", id)
+            .detail("line", error.line)
+            .detail("column", error.column)
+    })?;
+    let metrics = if stopped { timer.cancelled(processed) } else { timer.finish(processed) };
+    let records_per_second = metrics.records_per_second;
+    let duration_ms = metrics.duration.as_secs_f64() * 1_000.0;
+    record_processing(&state, metrics)?;
+    
+    Ok(Json(serde_json::json!({
+        "filename": file,
+        "input_records": records.len(),
+        "processed": processed,
+        "kept": kept,
+        "filtered": processed - kept,
+        "returned": output.len(),
+        "truncated": kept > output.len(),
+        "duration_ms": duration_ms,
+        "records_per_second": records_per_second,
+        "cancelled": cancelled || stopped,
+        "records": output
+    })))
+}
+
+#[cfg(feature = "wasm")]
+fn wasm_sandbox() -> Option<wasm::WasmSandbox> {
+    match wasm::WasmSandbox::new() {
+        Ok(sandbox) => Some(sandbox),
+        Err(error) => {
+            println!("⚠️  No WASM engine: {}", wasm::describe(&error));
+            None
+        }
+    }
+}
+
+#[cfg(feature = "wasm")]
+fn wasm_unavailable() -> ApiError {
+    ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "wasm_unavailable", "the server could not start its WASM engine")
+}
+
+/// Compiles the module in the request body and keeps it for
+/// /wasm/transform. The module is checked by instantiating it once, so one
+/// that imports anything or lacks the transform exports is refused here.
+#[cfg(feature = "wasm")]
+async fn upload_wasm_module(
+    query: Result<Query<WasmUploadQuery>, QueryRejection>,
+    State(state): State<SharedState>,
+    body: Bytes,
+) -> Result<(StatusCode, Json<serde_json::Value>), ApiError> {
+    let Query(params) = query?;
+    if body.is_empty() {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid_module", "send the module as the request body"));
+    }
+    let sandbox = lock_state(&state)?.wasm_sandbox.clone().ok_or_else(wasm_unavailable)?;
+    // Compiling is CPU-bound and can take a while for large modules
+    let module = tokio::task::spawn_blocking(move || sandbox.compile(&body).map(|module| (module, body.len())))
+        .await
+        .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "compile_panicked", error.to_string()))?;
+    let (module, size_bytes) = module
+        .map_err(|error| ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "invalid_module", wasm::describe(&error)))?;
+    
+    let uploaded_at = chrono::Utc::now();
+    let id = format!("{}-{:08x}", uploaded_at.format("%Y%m%d%H%M%S"), rand::random::<u32>());
+    let entry = WasmModuleEntry {
+        name: params.name.unwrap_or_else(|| id.clone()),
+        id: id.clone(),
+        size_bytes,
+        uploaded_at,
+        module,
+    };
+    lock_state(&state)?.wasm_modules.insert(id.clone(), entry.clone());
+    log!("🧩 Compiled WASM module {} ({} bytes)", entry.name, size_bytes);
+    
+    Ok((
+        StatusCode::CREATED,
+        Json(serde_json::json!({
+            "module": entry,
+            "fuel_per_record": wasm::FUEL_PER_RECORD,
+            "memory_limit_bytes": wasm::MEMORY_LIMIT_BYTES,
+            "transform": format!("/wasm/transform/{}/:filename", id)
+        })),
+    ))
+}
+
+#[cfg(feature = "wasm")]
+async fn list_wasm_modules(State(state): State<SharedState>) -> Result<Json<serde_json::Value>, ApiError> {
+    let app_state = lock_state(&state)?;
+    let mut modules: Vec<_> = app_state.wasm_modules.values().cloned().collect();
+    modules.sort_by_key(|entry| entry.uploaded_at);
+    
+    Ok(Json(serde_json::json!({
+        "count": modules.len(),
+        "modules": modules
+    })))
+}
+
+/// Runs an uploaded module's `transform` on every record of a file in a
+/// fresh instance, with fuel and memory limits, and returns the records it
+/// kept as it left them.
+#[cfg(feature = "wasm")]
+async fn wasm_transform_records(
+    axum::extract::Path((module_id, filename)): axum::extract::Path<(String, String)>,
+    query: Result<Query<TransformQuery>, QueryRejection>,
+    State(state): State<SharedState>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let Query(params) = query?;
+    let access = column_access(&state, &headers)?;
+    let limit = params.limit.unwrap_or(DEFAULT_TRANSFORM_ROWS);
+    if limit > MAX_TRANSFORM_ROWS {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "invalid_limit", format!("limit must be at most {}", MAX_TRANSFORM_ROWS))
+            .detail("limit", limit));
+    }
+    let (sandbox, entry) = {
+        let app_state = lock_state(&state)?;
+        (app_state.wasm_sandbox.clone(), app_state.wasm_modules.get(&module_id).cloned())
+    };
+    let sandbox = sandbox.ok_or_else(wasm_unavailable)?;
+    let entry = entry.ok_or_else(|| {
+        ApiError::new(StatusCode::NOT_FOUND, "module_not_found", format!("no WASM module {}", module_id)).detail("module", &module_id)
+    })?;
+    
+    let file = FileId::resolve(&filename, &state).await?;
+    let (token, _guard) = request_token(&state)?;
+    let (records, cancelled) = cached_records(&state, &file, &token).await?;
+    
+    let timer = PerformanceTimer::new(format!("WASM transform {} of {}", entry.name, file));
+    let worker_token = token.clone();
+    let input = access.reveal_all(&records);
+    let run = tokio::task::spawn_blocking(move || {
+        let mut instance = sandbox.instantiate(&entry.module).map_err(|error| (0, None, error))?;
+        let mut output = Vec::new();
+        let (mut kept, mut processed, mut stopped) = (0, 0, false);
+        for record in input.iter() {
+            if worker_token.is_cancelled() {
+                stopped = true;
+                break;
+            }
+            match instance.apply(record) {
+                Ok(Some(transformed)) => {
+                    kept += 1;
+                    if output.len() < limit {
+                        output.push(transformed);
+                    }
+                }
+                Ok(None) => {}
+                Err(error) => return Err((processed, Some(record.id), error)),
+            }
+            processed += 1;
+        }
+        Ok((output, kept, processed, stopped, instance.fuel_used()))
+    })
+    .await
+    .map_err(|error| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "transform_panicked", error.to_string()))?;
+    let (output, kept, processed, stopped, fuel_used) = run.map_err(|(index, id, error)| {
+        let api_error = ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "transform_failed", wasm::describe(&error))
+            .detail("module", &module_id);
+        match id {
+            Some(id) => api_error.detail("record", index + 1).detail("id", id),
+            None => api_error,
+        }
+    })?;
+    let metrics = if stopped { timer.cancelled(processed) } else { timer.finish(processed) };
+    let records_per_second = metrics.records_per_second;
+    let duration_ms = metrics.duration.as_secs_f64() * 1_000.0;
+    record_processing(&state, metrics)?;
+    
+    Ok(Json(serde_json::json!({
+        "filename": file,
+        "module": module_id,
+        "input_records": records.len(),
+        "processed": processed,
+        "kept": kept,
+        "filtered": processed - kept,
+        "returned": output.len(),
+        "truncated": kept > output.len(),
+        "fuel_used": fuel_used,
+        "duration_ms": duration_ms,
+        "records_per_second": records_per_second,
+        "cancelled": cancelled || stopped,
+        "records": output
+    })))
+}
+
+/// Subscribes to the NATS subject in `NATS_SUBJECT` and feeds each message
+/// into the same pipeline as HTTP requests until shutdown. Rows are
+/// appended to `uploads/nats-<subject>.csv`, which /process and /analyze
+/// then read like any upload; `file:<name>` messages parse and cache that
+/// file as /analyze would.
+#[cfg(feature = "nats")]
+fn spawn_nats_ingest(state: SharedState, shutdown: CancellationToken) {
+    let source = sources::nats::NatsSource::from_env();
+    if let Ok(mut app_state) = state.lock() {
+        app_state.ingest.url = source.url.clone();
+        app_state.ingest.subject = source.subject.clone();
+    }
+    println!("📥 Ingesting from NATS subject {} at {}", source.subject, source.url);
+    
+    tokio::spawn(async move {
+        let result = async {
+            let client = source.connect().await?;
+            let dataset = ingest_dataset(&source.subject);
+            source
+                .run(&client, shutdown.clone(), |message| {
+                    let state = state.clone();
+                    let dataset = dataset.clone();
+                    let token = shutdown.child_token();
+                    async move {
+                        let outcome = ingest_message(&state, &dataset, &message.payload, &token).await;
+                        // Nothing to report a poisoned lock to here, so the counts are dropped
+                        if let Ok(mut app_state) = state.lock() {
+                            let stats = &mut app_state.ingest;
+                            stats.messages += 1;
+                            match outcome {
+                                Ok((rows, duplicates, is_file)) => {
+                                    stats.rows += rows as u64;
+                                    stats.duplicates += duplicates as u64;
+                                    stats.files += is_file as u64;
+                                }
+                                Err(error) => {
+                                    stats.rejected += 1;
+                                    stats.last_error = Some(error.message);
+                                }
+                            }
+                        }
+                    }
+                })
+                .await?;
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(())
+        }
+        .await;
+        if let Err(error) = result {
+            println!("⚠️  NATS ingestion stopped: {}", error);
+            if let Ok(mut app_state) = state.lock() {
+                app_state.ingest.last_error = Some(error.to_string());
+            }
+        }
+    });
+}
+
+/// The upload that rows received on `subject` are appended to.
+#[cfg(feature = "nats")]
+fn ingest_dataset(subject: &str) -> FileId {
+    let name: String = subject
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') { c } else { '_' })
+        .collect();
+    FileId::new(DATA_DIRS[1], &format!("nats-{}.csv", name))
+}
+
+/// Handles one ingest message, returning the rows it covered, the rows
+/// dropped as duplicates and whether it referenced a file. A row message
+/// is appended only if all its rows parse.
+#[cfg(feature = "nats")]
+async fn ingest_message(
+    state: &SharedState,
+    dataset: &FileId,
+    payload: &[u8],
+    token: &CancellationToken,
+) -> Result<(usize, usize, bool), ApiError> {
+    match sources::nats::Ingest::interpret(payload) {
+        sources::nats::Ingest::File(name) => {
+            let file = FileId::resolve(name, state).await?;
+            let (parsed, _) = load_records(state, &file, token, None).await?;
+            Ok((parsed.records.len(), 0, true))
+        }
+        sources::nats::Ingest::Rows(rows) => {
+            let records = ReaderBuilder::new()
+                .has_headers(false)
+                .from_reader(rows)
+                .deserialize()
+                .collect::<Result<Vec<SalesRecord>, _>>()
+                .map_err(|error| ApiError::invalid_csv(dataset.as_str(), &error))?;
+            
+            let (_, duplicates) = append_to_file(state, dataset, &records, true).await?;
+            Ok((records.len() - duplicates, duplicates, false))
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use rdkafka::config::ClientConfig;
+use rdkafka::error::KafkaError;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::util::Timeout;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+/// Messages enqueued before waiting for their delivery reports. The
+/// producer groups them into broker requests on its own, per `linger.ms`.
+pub const PUBLISH_BATCH: usize = 1_000;
+
+/// How long a message may wait for room in the producer queue.
+const QUEUE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A Kafka producer that publishes serialized records to a topic.
+#[derive(Clone)]
+pub struct KafkaSink {
+    producer: FutureProducer,
+    brokers: String,
+}
+
+/// Delivery counts of one [`KafkaSink::publish_json`] call.
+#[derive(Debug, Default, Serialize)]
+pub struct PublishReport {
+    pub topic: String,
+    pub sent: usize,
+    pub delivered: usize,
+    pub failed: usize,
+    /// Payload bytes of the delivered messages.
+    pub bytes_delivered: usize,
+    pub batches: usize,
+    /// Delivered messages per partition.
+    pub partitions: BTreeMap<i32, usize>,
+    /// The first delivery error, when any message failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_error: Option<String>,
+    pub duration_ms: f64,
+    pub messages_per_second: f64,
+    pub cancelled: bool,
+}
+
+impl KafkaSink {
+    /// Creates a producer for `brokers`, a comma-separated list of
+    /// host:port. It connects lazily, so an unreachable broker shows up as
+    /// failed deliveries rather than here.
+    pub fn new(brokers: &str) -> Result<Self, KafkaError> {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("linger.ms", "5")
+            .set("batch.num.messages", PUBLISH_BATCH.to_string())
+            .set("message.timeout.ms", "10000")
+            .create()?;
+        Ok(Self { producer, brokers: brokers.to_string() })
+    }
+
+    pub fn brokers(&self) -> &str {
+        &self.brokers
+    }
+
+    /// Publishes each record as a JSON message keyed by `key(record)`,
+    /// enqueueing [`PUBLISH_BATCH`] messages and awaiting their deliveries
+    /// before the next batch. Stops between batches when `token` is
+    /// cancelled, and after a batch with no deliveries at all, since the
+    /// broker is then most likely unreachable.
+    pub async fn publish_json<T: Serialize>(
+        &self,
+        topic: &str,
+        records: &[T],
+        key: impl Fn(&T) -> String,
+        token: &CancellationToken,
+    ) -> Result<PublishReport, serde_json::Error> {
+        let start = Instant::now();
+        let mut report = PublishReport { topic: topic.to_string(), ..PublishReport::default() };
+
+        for batch in records.chunks(PUBLISH_BATCH) {
+            if token.is_cancelled() {
+                report.cancelled = true;
+                break;
+            }
+            let messages = batch
+                .iter()
+                .map(|record| Ok((key(record), serde_json::to_vec(record)?)))
+                .collect::<Result<Vec<_>, serde_json::Error>>()?;
+            let deliveries = messages.iter().map(|(key, payload)| {
+                self.producer.send(FutureRecord::to(topic).key(key).payload(payload), Timeout::After(QUEUE_TIMEOUT))
+            });
+
+            let delivered_before = report.delivered;
+            for (delivery, (_, payload)) in futures::future::join_all(deliveries).await.into_iter().zip(&messages) {
+                report.sent += 1;
+                match delivery {
+                    Ok((partition, _offset)) => {
+                        report.delivered += 1;
+                        report.bytes_delivered += payload.len();
+                        *report.partitions.entry(partition).or_default() += 1;
+                    }
+                    Err((error, _message)) => {
+                        report.failed += 1;
+                        report.first_error.get_or_insert_with(|| error.to_string());
+                    }
+                }
+            }
+            report.batches += 1;
+            if report.delivered == delivered_before {
+                break;
+            }
+        }
+
+        let seconds = start.elapsed().as_secs_f64();
+        report.duration_ms = seconds * 1_000.0;
+        report.messages_per_second = if seconds > 0.0 { report.delivered as f64 / seconds } else { 0.0 };
+        Ok(report)
+    }
+}
@@ -0,0 +1,254 @@
+use crate::performance_utils::SalesRecord;
+use crate::provenance::Provenance;
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, Connection, OptionalExtension};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+/// Rows written between checks of the cancellation token. Each batch is a
+/// transaction of its own, so a cancelled ingestion keeps whole batches.
+pub const INGEST_BATCH: usize = 10_000;
+
+/// The columns of a sales table, in the order of [`SalesRecord`]'s fields.
+pub const COLUMNS: [&str; 7] = ["id", "customer_name", "product", "quantity", "price", "date", "region"];
+
+/// The columns after [`COLUMNS`] saying where each row was last written
+/// from. They are not compared: a row read again from another line, or at
+/// another time, is unchanged.
+pub const PROVENANCE_COLUMNS: [(&str, &str); 4] =
+    [("source_file", "TEXT"), ("source_line", "INTEGER"), ("ingested_at", "TEXT"), ("job_id", "TEXT")];
+
+/// A SQLite database that records are upserted into, one table per name,
+/// so that ingesting the same rows again changes nothing.
+#[derive(Debug, Clone)]
+pub struct SqliteSink {
+    path: PathBuf,
+}
+
+/// What one [`SqliteSink::ingest`] call did to the table, row by row.
+#[derive(Debug, Default, Serialize)]
+pub struct IngestReport {
+    pub table: String,
+    /// The columns rows are matched on.
+    pub key: Vec<String>,
+    /// Rows whose key was not in the table yet.
+    pub inserted: usize,
+    /// Rows whose key was there with other values, now replaced.
+    pub updated: usize,
+    /// Rows already in the table exactly as they were.
+    pub unchanged: usize,
+    /// Rows in the table once the call is done.
+    pub table_rows: usize,
+    pub batches: usize,
+    pub duration_ms: f64,
+    pub rows_per_second: f64,
+    pub cancelled: bool,
+}
+
+/// Why an ingestion was refused or failed.
+#[derive(Debug)]
+pub enum IngestError {
+    /// The table or key is not one this sink takes; the message says why.
+    Invalid(String),
+    /// The table already holds rows that share a key, so it cannot be
+    /// keyed on it.
+    DuplicateKeys { table: String, key: Vec<String> },
+    Sqlite(rusqlite::Error),
+}
+
+impl std::fmt::Display for IngestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IngestError::Invalid(message) => write!(f, "{}", message),
+            IngestError::DuplicateKeys { table, key } => {
+                write!(f, "table '{}' already holds rows sharing a ({}) key", table, key.join(", "))
+            }
+            IngestError::Sqlite(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for IngestError {}
+
+impl From<rusqlite::Error> for IngestError {
+    fn from(error: rusqlite::Error) -> Self {
+        IngestError::Sqlite(error)
+    }
+}
+
+impl SqliteSink {
+    /// A sink writing to the database file at `path`, created on first use.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Upserts `records` into `table`, matching rows on the `key` columns:
+    /// a row whose key is new is inserted, one whose key is already there
+    /// with other values replaces that row, so re-running an ingestion of
+    /// the same file, or of shards that overlap, leaves one row per key.
+    /// When the input itself repeats a key, its last row wins. Inserted and
+    /// replaced rows keep the record's `provenance`, one per record.
+    /// Creates the table, a unique index on the key and the provenance
+    /// columns when they are missing. Blocks, so callers run it off the
+    /// async runtime.
+    pub fn ingest(
+        &self,
+        table: &str,
+        key: &[String],
+        records: &[SalesRecord],
+        provenance: &[Provenance],
+        token: &CancellationToken,
+    ) -> Result<IngestReport, IngestError> {
+        validate(table, key)?;
+        let start = Instant::now();
+        let mut report = IngestReport { table: table.to_string(), key: key.to_vec(), ..IngestReport::default() };
+
+        let mut connection = Connection::open(&self.path)?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS \"{}\" (
+                id INTEGER NOT NULL,
+                customer_name TEXT NOT NULL,
+                product TEXT NOT NULL,
+                quantity INTEGER NOT NULL,
+                price REAL NOT NULL,
+                date TEXT NOT NULL,
+                region TEXT NOT NULL
+            )",
+            table
+        ))?;
+        let existing: Vec<String> = connection
+            .prepare(&format!("SELECT name FROM pragma_table_info('{}')", table))?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        for (column, kind) in PROVENANCE_COLUMNS {
+            if !existing.iter().any(|name| name == column) {
+                connection.execute(&format!("ALTER TABLE \"{}\" ADD COLUMN {} {}", table, column, kind), [])?;
+            }
+        }
+        let index = format!("{}_by_{}", table, key.join("_"));
+        let created = connection.execute(
+            &format!("CREATE UNIQUE INDEX IF NOT EXISTS \"{}\" ON \"{}\" ({})", index, table, key.join(", ")),
+            [],
+        );
+        if let Err(rusqlite::Error::SqliteFailure(error, _)) = &created {
+            if error.code == rusqlite::ErrorCode::ConstraintViolation {
+                return Err(IngestError::DuplicateKeys { table: table.to_string(), key: key.to_vec() });
+            }
+        }
+        created?;
+
+        let matches = key.iter().map(|column| format!("{} = ?", column)).collect::<Vec<_>>().join(" AND ");
+        let select = format!("SELECT {} FROM \"{}\" WHERE {}", COLUMNS.join(", "), table, matches);
+        let written: Vec<&str> = COLUMNS.iter().copied().chain(PROVENANCE_COLUMNS.iter().map(|(column, _)| *column)).collect();
+        let updates = written
+            .iter()
+            .filter(|column| !key.iter().any(|key| key == *column))
+            .map(|column| format!("{} = excluded.{}", column, column))
+            .collect::<Vec<_>>();
+        let upsert = format!(
+            "INSERT INTO \"{}\" ({}) VALUES ({}) ON CONFLICT ({}) DO UPDATE SET {}",
+            table,
+            written.join(", "),
+            vec!["?"; written.len()].join(", "),
+            key.join(", "),
+            updates.join(", ")
+        );
+        let positions: Vec<usize> = key.iter().map(|column| COLUMNS.iter().position(|c| c == column).unwrap_or(0)).collect();
+
+        for (batch, batch_provenance) in records.chunks(INGEST_BATCH).zip(provenance.chunks(INGEST_BATCH)) {
+            if token.is_cancelled() {
+                report.cancelled = true;
+                break;
+            }
+            let transaction = connection.transaction()?;
+            {
+                let mut existing = transaction.prepare_cached(&select)?;
+                let mut write = transaction.prepare_cached(&upsert)?;
+                for (record, provenance) in batch.iter().zip(batch_provenance) {
+                    let values = values(record);
+                    let current = existing
+                        .query_row(params_from_iter(positions.iter().map(|&position| &values[position])), |row| {
+                            (0..COLUMNS.len()).map(|column| row.get::<_, Value>(column)).collect::<Result<Vec<_>, _>>()
+                        })
+                        .optional()?;
+                    match current {
+                        None => report.inserted += 1,
+                        Some(current) if current == values => {
+                            report.unchanged += 1;
+                            continue;
+                        }
+                        Some(_) => report.updated += 1,
+                    }
+                    write.execute(params_from_iter(values.iter().chain(&provenance_values(provenance))))?;
+                }
+            }
+            transaction.commit()?;
+            report.batches += 1;
+        }
+
+        report.table_rows = connection.query_row(&format!("SELECT COUNT(*) FROM \"{}\"", table), [], |row| row.get(0))?;
+        let seconds = start.elapsed().as_secs_f64();
+        report.duration_ms = seconds * 1_000.0;
+        let written = report.inserted + report.updated + report.unchanged;
+        report.rows_per_second = if seconds > 0.0 { written as f64 / seconds } else { 0.0 };
+        Ok(report)
+    }
+}
+
+/// Table names are letters, digits and underscores, not starting with a
+/// digit, so they can be quoted into statements as they are; key columns
+/// must be distinct columns of [`COLUMNS`].
+fn validate(table: &str, key: &[String]) -> Result<(), IngestError> {
+    let valid_table = table.len() <= 64
+        && table.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !table.to_ascii_lowercase().starts_with("sqlite_");
+    if !valid_table {
+        return Err(IngestError::Invalid(format!(
+            "invalid table name '{}': use up to 64 letters, digits or underscores, not starting with a digit or sqlite_",
+            table
+        )));
+    }
+    if key.is_empty() {
+        return Err(IngestError::Invalid("the key needs at least one column".to_string()));
+    }
+    for (index, column) in key.iter().enumerate() {
+        if !COLUMNS.contains(&column.as_str()) {
+            return Err(IngestError::Invalid(format!("unknown key column '{}'; columns are {}", column, COLUMNS.join(", "))));
+        }
+        if key[..index].contains(column) {
+            return Err(IngestError::Invalid(format!("key column '{}' is repeated", column)));
+        }
+    }
+    Ok(())
+}
+
+/// A record's provenance in [`PROVENANCE_COLUMNS`] order.
+fn provenance_values(provenance: &Provenance) -> [Value; 4] {
+    [
+        Value::Text(provenance.source.clone()),
+        Value::Integer(provenance.line as i64),
+        provenance.ingested_at.map_or(Value::Null, |at| Value::Text(at.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true))),
+        provenance.job_id.clone().map_or(Value::Null, Value::Text),
+    ]
+}
+
+/// A record's values in [`COLUMNS`] order, as SQLite stores them.
+fn values(record: &SalesRecord) -> Vec<Value> {
+    vec![
+        Value::Integer(record.id as i64),
+        Value::Text(record.customer_name.clone()),
+        Value::Text(record.product.clone()),
+        Value::Integer(record.quantity as i64),
+        Value::Real(record.price),
+        Value::Text(record.date.clone()),
+        Value::Text(record.region.clone()),
+    ]
+}
@@ -0,0 +1,80 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Completed results on disk, keyed by the hash of the file they were
+/// computed from and a key naming the request's parameters, e.g.
+/// `analyze-limit-10`. A result can only be found again for the same bytes,
+/// so an edited file never gets an old result, and results outlive restarts.
+#[derive(Debug, Clone)]
+pub struct SnapshotStore {
+    dir: PathBuf,
+}
+
+/// FNV-1a of a file's bytes. Unlike std's hasher it is the same across
+/// builds and platforms, which results kept on disk rely on.
+pub fn content_hash(path: impl AsRef<Path>) -> std::io::Result<u64> {
+    let mut file = std::fs::File::open(path)?;
+    let mut buffer = vec![0; 64 * 1024];
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(hash);
+        }
+        for byte in &buffer[..read] {
+            hash = (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+}
+
+impl SnapshotStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// `<dir>/<hash>-<key>.json`.
+    pub fn path(&self, hash: u64, key: &str) -> PathBuf {
+        self.dir.join(format!("{:016x}-{}.json", hash, key))
+    }
+
+    /// The result stored under `hash` and `key`. Missing and unreadable
+    /// snapshots alike are `None`, so the caller computes the result again.
+    pub fn load<T: DeserializeOwned>(&self, hash: u64, key: &str) -> Option<T> {
+        let content = std::fs::read(self.path(hash, key)).ok()?;
+        serde_json::from_slice(&content).ok()
+    }
+
+    /// Stores `value` under `hash` and `key`, through a temporary file so a
+    /// concurrent [`SnapshotStore::load`] never sees half of it.
+    pub fn save<T: Serialize>(&self, hash: u64, key: &str, value: &T) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.path(hash, key);
+        let temporary = path.with_extension(format!("{}.tmp", std::process::id()));
+        std::fs::write(&temporary, serde_json::to_vec(value)?)?;
+        std::fs::rename(&temporary, &path)
+    }
+
+    /// Deletes every snapshot of the file with `hash`, returning how many.
+    pub fn remove(&self, hash: u64) -> std::io::Result<usize> {
+        let prefix = format!("{:016x}-", hash);
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(error) => return Err(error),
+        };
+        let mut removed = 0;
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                std::fs::remove_file(entry.path())?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
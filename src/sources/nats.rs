@@ -0,0 +1,82 @@
+use async_nats::{Client, ConnectOptions, Message};
+use futures::StreamExt;
+use serde::Serialize;
+use std::future::Future;
+use tokio_util::sync::CancellationToken;
+
+/// What a message on the ingest subject asks for.
+pub enum Ingest<'a> {
+    /// `file:<name>`: process a file the server can already read.
+    File(&'a str),
+    /// Anything else: CSV rows without a header, in the sales column order.
+    Rows(&'a [u8]),
+}
+
+impl<'a> Ingest<'a> {
+    pub fn interpret(payload: &'a [u8]) -> Self {
+        match payload.strip_prefix(b"file:").map(std::str::from_utf8) {
+            Some(Ok(name)) => Self::File(name.trim()),
+            _ => Self::Rows(payload),
+        }
+    }
+}
+
+/// Counts of what the subscription received, for /metrics.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct IngestStats {
+    pub url: String,
+    pub subject: String,
+    pub messages: u64,
+    /// Rows appended from row messages plus rows of the referenced files.
+    pub rows: u64,
+    /// Rows of row messages left out as duplicates, with `DEDUP_KEYS`.
+    pub duplicates: u64,
+    pub files: u64,
+    /// Messages that could not be parsed or whose file could not be read.
+    pub rejected: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+/// A NATS subject to ingest from.
+#[derive(Debug, Clone)]
+pub struct NatsSource {
+    pub url: String,
+    pub subject: String,
+}
+
+impl NatsSource {
+    /// Reads `NATS_URL` (nats://localhost:4222 by default) and `NATS_SUBJECT`
+    /// (`sales.ingest` by default).
+    pub fn from_env() -> Self {
+        Self {
+            url: std::env::var("NATS_URL").unwrap_or_else(|_| "nats://localhost:4222".to_string()),
+            subject: std::env::var("NATS_SUBJECT").unwrap_or_else(|_| "sales.ingest".to_string()),
+        }
+    }
+
+    /// Connects in the background, retrying until the server is reachable.
+    pub async fn connect(&self) -> Result<Client, async_nats::ConnectError> {
+        ConnectOptions::new().retry_on_initial_connect().connect(&self.url).await
+    }
+
+    /// Hands every message to `handle`, one at a time, until
+    /// `token` is cancelled or the subscription ends.
+    pub async fn run<F, Fut>(&self, client: &Client, token: CancellationToken, mut handle: F) -> Result<(), async_nats::SubscribeError>
+    where
+        F: FnMut(Message) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let mut subscriber = client.subscribe(self.subject.clone()).await?;
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => break,
+                message = subscriber.next() => match message {
+                    Some(message) => handle(message).await,
+                    None => break,
+                },
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,201 @@
+use crate::performance_utils::SalesRecord;
+use chrono::{Datelike, Months, NaiveDate};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Most periods a series may span. Daily buckets over a file whose dates
+/// range over decades are better asked for by week or month.
+pub const MAX_PERIODS: usize = 10_000;
+
+/// What is summed per period.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    /// Price times quantity.
+    Revenue,
+    Orders,
+    Quantity,
+}
+
+impl Metric {
+    pub const NAMES: [&'static str; 3] = ["revenue", "orders", "quantity"];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim() {
+            "revenue" => Some(Metric::Revenue),
+            "orders" => Some(Metric::Orders),
+            "quantity" => Some(Metric::Quantity),
+            _ => None,
+        }
+    }
+
+    fn value(self, record: &SalesRecord, price_factor: f64) -> f64 {
+        match self {
+            Metric::Revenue => record.price * price_factor * record.quantity as f64,
+            Metric::Orders => 1.0,
+            Metric::Quantity => record.quantity as f64,
+        }
+    }
+}
+
+/// The length of the periods a series is bucketed into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Period {
+    Day,
+    /// Monday to Sunday.
+    Week,
+    Month,
+}
+
+impl Period {
+    /// The first day of the period holding `date`.
+    fn start(self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Period::Day => date,
+            Period::Week => date - chrono::Duration::days(date.weekday().num_days_from_monday() as i64),
+            Period::Month => date.with_day(1).expect("every month has a first day"),
+        }
+    }
+
+    fn next(self, start: NaiveDate) -> Option<NaiveDate> {
+        match self {
+            Period::Day => start.succ_opt(),
+            Period::Week => start.checked_add_signed(chrono::Duration::days(7)),
+            Period::Month => start.checked_add_months(Months::new(1)),
+        }
+    }
+}
+
+/// A moving-average window of whole periods: `7d`, `4w` or `3m`. The unit
+/// is also the period the series is bucketed by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    pub periods: usize,
+    pub period: Period,
+}
+
+impl Window {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+        let (count, unit) = text.split_at(split);
+        let period = match unit {
+            "d" => Period::Day,
+            "w" => Period::Week,
+            "m" => Period::Month,
+            _ => return Err(format!("{:?} is not a number of days, weeks or months, e.g. 7d, 4w or 3m", text)),
+        };
+        match count.parse() {
+            Ok(periods) if periods > 0 => Ok(Self { periods, period }),
+            _ => Err(format!("{:?} must be at least one period", text)),
+        }
+    }
+}
+
+impl std::fmt::Display for Window {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let unit = match self.period {
+            Period::Day => 'd',
+            Period::Week => 'w',
+            Period::Month => 'm',
+        };
+        write!(f, "{}{}", self.periods, unit)
+    }
+}
+
+/// One period of a series.
+#[derive(Debug, Clone, Serialize)]
+pub struct TrendPoint {
+    /// The period's first day.
+    pub period: NaiveDate,
+    pub value: f64,
+    /// Mean of the window ending with this period; `None` until a whole
+    /// window of periods has passed.
+    pub moving_average: Option<f64>,
+    /// Change of the moving average from the window before, e.g. the last
+    /// 7 days against the 7 days before them: 0.1 is 10% up. `None`
+    /// without an earlier window or when its average was zero.
+    pub growth: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Trend {
+    pub metric: Metric,
+    pub period: Period,
+    pub window_periods: usize,
+    /// Every period from the first order's to the last's; periods without
+    /// orders have a value of zero.
+    pub points: Vec<TrendPoint>,
+    /// Rows whose date is not `YYYY-MM-DD`, left out of the series.
+    pub skipped_rows: usize,
+    pub cancelled: bool,
+}
+
+/// The series spans more than [`MAX_PERIODS`] periods.
+#[derive(Debug)]
+pub struct TooManyPeriods {
+    pub first: NaiveDate,
+    pub last: NaiveDate,
+}
+
+impl std::fmt::Display for TooManyPeriods {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} to {} spans more than {} periods; use a window in longer units", self.first, self.last, MAX_PERIODS)
+    }
+}
+
+/// Buckets `records` by `window.period`, sums `metric` per period, and
+/// adds the moving average and period-over-period growth over `window`.
+/// Prices are multiplied by `price_factor`, e.g. an exchange rate. Stops
+/// early, with the periods seen so far, once `cancelled` returns true.
+pub fn trend(
+    records: &[SalesRecord],
+    metric: Metric,
+    window: Window,
+    price_factor: f64,
+    cancelled: impl Fn() -> bool,
+) -> Result<Trend, TooManyPeriods> {
+    let mut sums: BTreeMap<NaiveDate, f64> = BTreeMap::new();
+    let mut skipped_rows = 0;
+    let mut stopped = false;
+    for (index, record) in records.iter().enumerate() {
+        if index % 1_024 == 0 && cancelled() {
+            stopped = true;
+            break;
+        }
+        match NaiveDate::parse_from_str(record.date.trim(), "%Y-%m-%d") {
+            Ok(date) => *sums.entry(window.period.start(date)).or_default() += metric.value(record, price_factor),
+            Err(_) => skipped_rows += 1,
+        }
+    }
+
+    let mut values = Vec::new();
+    if let (Some((&first, _)), Some((&last, _))) = (sums.first_key_value(), sums.last_key_value()) {
+        let mut period = Some(first);
+        while let Some(start) = period.filter(|start| *start <= last) {
+            if values.len() == MAX_PERIODS {
+                return Err(TooManyPeriods { first, last });
+            }
+            values.push((start, sums.get(&start).copied().unwrap_or(0.0)));
+            period = window.period.next(start);
+        }
+    }
+
+    let mut points: Vec<TrendPoint> = Vec::with_capacity(values.len());
+    for (index, &(period, value)) in values.iter().enumerate() {
+        // Summed afresh for every period rather than kept as a running
+        // sum, which would drift on long series
+        let moving_average = (index + 1)
+            .checked_sub(window.periods)
+            .map(|from| values[from..=index].iter().map(|(_, value)| value).sum::<f64>() / window.periods as f64);
+        let earlier = index
+            .checked_sub(window.periods)
+            .and_then(|earlier| points[earlier].moving_average)
+            .filter(|earlier| *earlier != 0.0);
+        let growth = moving_average.zip(earlier).map(|(now, earlier)| now / earlier - 1.0);
+        points.push(TrendPoint { period, value, moving_average, growth });
+    }
+
+    Ok(Trend { metric, period: window.period, window_periods: window.periods, points, skipped_rows, cancelled: stopped })
+}
@@ -0,0 +1,121 @@
+use crate::performance_utils::SalesRecord;
+use wasmtime::{Config, Engine, Instance, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, Trap, TypedFunc};
+
+/// Fuel one record's `transform` call may burn, about one unit per
+/// WebAssembly instruction. Running out stops the transform.
+pub const FUEL_PER_RECORD: u64 = 10_000_000;
+
+/// Most linear memory one module instance may grow to.
+pub const MEMORY_LIMIT_BYTES: usize = 64 * 1024 * 1024;
+
+/// Compiles and runs untrusted transform modules. A module gets no
+/// imports, so it can only compute, and must export:
+///
+/// - `memory`
+/// - `alloc(len: i32) -> i32`: room for the next record's input. Each call
+///   starts a new record, so the module may reuse everything it allocated
+///   for the previous one.
+/// - `transform(ptr: i32, len: i32) -> i64`: takes the record as a JSON
+///   object and returns the output object's location as `ptr << 32 | len`,
+///   or a negative number to drop the record.
+#[derive(Clone)]
+pub struct WasmSandbox {
+    engine: Engine,
+}
+
+/// An instance of a module, running the records of one request.
+pub struct WasmTransform {
+    store: Store<StoreLimits>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    transform: TypedFunc<(i32, i32), i64>,
+    fuel_used: u64,
+}
+
+impl WasmSandbox {
+    pub fn new() -> wasmtime::Result<Self> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        Ok(Self { engine: Engine::new(&config)? })
+    }
+
+    /// Compiles a module in the binary or text format and checks that it
+    /// instantiates without imports and has the exports above.
+    pub fn compile(&self, bytes: &[u8]) -> wasmtime::Result<Module> {
+        let module = Module::new(&self.engine, bytes)?;
+        self.instantiate(&module)
+            .map_err(|error| error.context("the module does not fit the transform interface (no imports; memory, alloc, transform)"))?;
+        Ok(module)
+    }
+
+    pub fn instantiate(&self, module: &Module) -> wasmtime::Result<WasmTransform> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(MEMORY_LIMIT_BYTES)
+            .instances(1)
+            .memories(1)
+            .tables(1)
+            .table_elements(100_000)
+            .build();
+        let mut store = Store::new(&self.engine, limits);
+        store.limiter(|limits| limits);
+        // A start function runs on instantiation and burns fuel too
+        store.set_fuel(FUEL_PER_RECORD)?;
+        let instance = Instance::new(&mut store, module, &[])?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| wasmtime::Error::msg("the module does not export its memory as `memory`"))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+        let transform = instance.get_typed_func::<(i32, i32), i64>(&mut store, "transform")?;
+        Ok(WasmTransform { store, memory, alloc, transform, fuel_used: 0 })
+    }
+}
+
+impl WasmTransform {
+    /// Runs `transform` on `record` with a fresh [`FUEL_PER_RECORD`].
+    /// Returns the output object, or `None` when the module dropped the
+    /// record.
+    pub fn apply(&mut self, record: &SalesRecord) -> wasmtime::Result<Option<serde_json::Value>> {
+        let input = serde_json::to_vec(record)?;
+        let len = i32::try_from(input.len())?;
+        self.store.set_fuel(FUEL_PER_RECORD)?;
+        let result = self.call(&input, len);
+        self.fuel_used += FUEL_PER_RECORD - self.store.get_fuel()?;
+        let packed = result?;
+        if packed < 0 {
+            return Ok(None);
+        }
+
+        let (ptr, len) = ((packed as u64 >> 32) as usize, (packed as u64 & 0xffff_ffff) as usize);
+        // Read in place: the length is the module's word, not worth allocating for
+        let output = ptr
+            .checked_add(len)
+            .and_then(|end| self.memory.data(&self.store).get(ptr..end))
+            .ok_or_else(|| wasmtime::Error::msg("transform returned a location outside its memory"))?;
+        let output: serde_json::Value = serde_json::from_slice(output)?;
+        if !output.is_object() {
+            return Err(wasmtime::Error::msg("transform returned JSON that is not an object"));
+        }
+        Ok(Some(output))
+    }
+
+    fn call(&mut self, input: &[u8], len: i32) -> wasmtime::Result<i64> {
+        let ptr = self.alloc.call(&mut self.store, len)?;
+        self.memory.write(&mut self.store, ptr as u32 as usize, input)?;
+        self.transform.call(&mut self.store, (ptr, len))
+    }
+
+    /// Fuel burned by all the records so far.
+    pub fn fuel_used(&self) -> u64 {
+        self.fuel_used
+    }
+}
+
+/// A one-line reason for a failed compile or call: the trap when the
+/// module trapped (out of fuel, out of bounds...), the error chain
+/// otherwise.
+pub fn describe(error: &wasmtime::Error) -> String {
+    match error.downcast_ref::<Trap>() {
+        Some(trap) => trap.to_string(),
+        None => format!("{:#}", error),
+    }
+}
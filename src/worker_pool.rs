@@ -0,0 +1,133 @@
+use serde::Serialize;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed number of OS threads dedicated to CPU-bound CSV work. Async
+/// callers hand it a closure with [`WorkerPool::run`] and await the result
+/// over a channel, so parsing neither stalls tokio's workers nor competes
+/// with everything else in its blocking pool, and its throughput is tuned
+/// by the thread count alone. Clones share the threads, which exit once
+/// the last clone is dropped and the queue is drained.
+#[derive(Clone)]
+pub struct WorkerPool {
+    sender: mpsc::Sender<Job>,
+    counters: Arc<Counters>,
+}
+
+#[derive(Default)]
+struct Counters {
+    threads: usize,
+    queued: AtomicUsize,
+    busy: AtomicUsize,
+    completed: AtomicU64,
+    panicked: AtomicU64,
+}
+
+/// A snapshot of a pool's load, as reported by GET /metrics.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct WorkerPoolStats {
+    pub threads: usize,
+    /// Jobs waiting for a free thread.
+    pub queued: usize,
+    /// Threads running a job.
+    pub busy: usize,
+    pub completed: u64,
+    pub panicked: u64,
+}
+
+/// Why [`WorkerPool::run`] returned no result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkerPoolError {
+    /// The job panicked; the thread survives and takes the next job.
+    Panicked,
+    /// Every thread of the pool is gone.
+    Closed,
+}
+
+impl std::fmt::Display for WorkerPoolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WorkerPoolError::Panicked => write!(f, "the job panicked on its worker thread"),
+            WorkerPoolError::Closed => write!(f, "the worker pool has no threads left"),
+        }
+    }
+}
+
+impl std::error::Error for WorkerPoolError {}
+
+impl WorkerPool {
+    /// Starts `threads` threads (at least one) named `name-0`, `name-1`...
+    pub fn new(name: &str, threads: usize) -> std::io::Result<Self> {
+        let threads = threads.max(1);
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let counters = Arc::new(Counters { threads, ..Counters::default() });
+        for index in 0..threads {
+            let receiver = receiver.clone();
+            let counters = counters.clone();
+            std::thread::Builder::new()
+                .name(format!("{}-{}", name, index))
+                .spawn(move || loop {
+                    // The lock is only held while waiting, not while working
+                    let job = match receiver.lock() {
+                        Ok(receiver) => receiver.recv(),
+                        Err(_) => break,
+                    };
+                    let Ok(job) = job else {
+                        break;
+                    };
+                    counters.queued.fetch_sub(1, Ordering::Relaxed);
+                    counters.busy.fetch_add(1, Ordering::Relaxed);
+                    let finished = std::panic::catch_unwind(AssertUnwindSafe(job)).is_ok();
+                    counters.busy.fetch_sub(1, Ordering::Relaxed);
+                    let counter = if finished { &counters.completed } else { &counters.panicked };
+                    counter.fetch_add(1, Ordering::Relaxed);
+                })?;
+        }
+        Ok(Self { sender, counters })
+    }
+
+    /// One thread per CPU.
+    pub fn with_cpus(name: &str) -> std::io::Result<Self> {
+        Self::new(name, num_cpus::get())
+    }
+
+    pub fn threads(&self) -> usize {
+        self.counters.threads
+    }
+
+    /// Queues `job` and waits for its result. Dropping the returned future
+    /// does not stop a job that already started; pass it a cancellation
+    /// token for that.
+    pub async fn run<T, F>(&self, job: F) -> Result<T, WorkerPoolError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (result_sender, result) = tokio::sync::oneshot::channel();
+        let job: Job = Box::new(move || {
+            // The caller may have given up waiting; the result is then dropped
+            let _ = result_sender.send(job());
+        });
+        self.counters.queued.fetch_add(1, Ordering::Relaxed);
+        if self.sender.send(job).is_err() {
+            self.counters.queued.fetch_sub(1, Ordering::Relaxed);
+            return Err(WorkerPoolError::Closed);
+        }
+        // A panicking job drops the sender without sending
+        result.await.map_err(|_| WorkerPoolError::Panicked)
+    }
+
+    pub fn stats(&self) -> WorkerPoolStats {
+        WorkerPoolStats {
+            threads: self.counters.threads,
+            queued: self.counters.queued.load(Ordering::Relaxed),
+            busy: self.counters.busy.load(Ordering::Relaxed),
+            completed: self.counters.completed.load(Ordering::Relaxed),
+            panicked: self.counters.panicked.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,489 @@
+use crate::dialect::Dialect;
+use crate::encryption::{ColumnCipher, DecryptError};
+use csv::{StringRecord, WriterBuilder};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// Longest artifact name a step may write.
+pub const MAX_NAME_LENGTH: usize = 64;
+
+/// A scratch area for a multi-step pipeline: the artifacts its steps wrote,
+/// each a CSV file in the workspace's own directory, until they are
+/// committed or discarded together.
+#[derive(Debug, Clone, Serialize)]
+pub struct Workspace {
+    pub id: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    /// When a step last ran, or the workspace was created; idle workspaces
+    /// expire from this.
+    pub last_used_at: chrono::DateTime<chrono::Utc>,
+    pub artifacts: BTreeMap<String, Artifact>,
+    /// In the order they ran.
+    pub steps: Vec<Step>,
+}
+
+/// What a step wrote.
+#[derive(Debug, Clone, Serialize)]
+pub struct Artifact {
+    pub columns: Vec<String>,
+    pub rows: u64,
+    pub bytes: u64,
+    /// Index of the step that wrote it in [`Workspace::steps`].
+    pub step: usize,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl Workspace {
+    pub fn new(id: String) -> Self {
+        let now = chrono::Utc::now();
+        Self { id, created_at: now, last_used_at: now, artifacts: BTreeMap::new(), steps: Vec::new() }
+    }
+
+    /// Artifacts no step read: the results of the pipeline, as opposed to
+    /// its intermediate steps.
+    pub fn results(&self) -> Vec<&str> {
+        let read: HashSet<&str> = self.steps.iter().flat_map(Step::inputs).filter_map(reference).collect();
+        self.artifacts.keys().map(String::as_str).filter(|name| !read.contains(name)).collect()
+    }
+}
+
+/// One step of a pipeline. Inputs are data files, or `@name` for an
+/// artifact of the same workspace; the step writes its result as the
+/// artifact `output`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Step {
+    /// Keeps the records matching every condition of `filter`, then only
+    /// `columns`, in that order, when given, and renames columns.
+    Transform {
+        input: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        filter: Vec<Condition>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        columns: Option<Vec<String>>,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        rename: BTreeMap<String, String>,
+        output: String,
+    },
+    /// Records of `left` alongside the records of `right` with the same
+    /// `on` column. The right side is held in memory.
+    Join {
+        left: String,
+        right: String,
+        on: String,
+        #[serde(default)]
+        kind: JoinKind,
+        output: String,
+    },
+    /// Records ordered by the columns of `by`, a column prefixed with `-`
+    /// descending. Numbers compare as numbers; the sort is stable and held
+    /// in memory.
+    Sort { input: String, by: Vec<String>, output: String },
+}
+
+impl Step {
+    /// The columns whose values the step matches, orders or renames, as
+    /// opposed to only passing them through.
+    pub fn referenced_columns(&self) -> Vec<&str> {
+        match self {
+            Step::Transform { filter, rename, .. } => {
+                filter.iter().map(|condition| condition.column.as_str()).chain(rename.keys().map(String::as_str)).collect()
+            }
+            Step::Join { on, .. } => vec![on],
+            Step::Sort { by, .. } => by.iter().map(|key| key.strip_prefix('-').unwrap_or(key)).collect(),
+        }
+    }
+
+    pub fn inputs(&self) -> Vec<&str> {
+        match self {
+            Step::Transform { input, .. } | Step::Sort { input, .. } => vec![input],
+            Step::Join { left, right, .. } => vec![left, right],
+        }
+    }
+
+    pub fn output(&self) -> &str {
+        match self {
+            Step::Transform { output, .. } | Step::Join { output, .. } | Step::Sort { output, .. } => output,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JoinKind {
+    /// Left records with no match are dropped.
+    #[default]
+    Inner,
+    /// Left records with no match are kept, the right columns empty.
+    Left,
+}
+
+/// `column op value`, comparing numbers as numbers when both sides are.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Condition {
+    pub column: String,
+    pub op: Comparison,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+}
+
+impl Condition {
+    pub fn matches(&self, field: &str) -> bool {
+        let ordering = || compare(field, &self.value);
+        match self.op {
+            Comparison::Eq => ordering().is_eq(),
+            Comparison::Ne => ordering().is_ne(),
+            Comparison::Lt => ordering().is_lt(),
+            Comparison::Le => ordering().is_le(),
+            Comparison::Gt => ordering().is_gt(),
+            Comparison::Ge => ordering().is_ge(),
+            Comparison::Contains => field.contains(&self.value),
+        }
+    }
+}
+
+/// A step input, resolved to a file and how it is written.
+#[derive(Debug, Clone)]
+pub struct Input {
+    /// As the step named it.
+    pub name: String,
+    pub path: PathBuf,
+    pub dialect: Dialect,
+    /// Decrypts the columns it encrypts as they are read, so steps match
+    /// and order plaintext; `None` reads them as stored.
+    pub cipher: Option<Arc<ColumnCipher>>,
+}
+
+/// What a step wrote.
+#[derive(Debug, Clone)]
+pub struct Output {
+    pub columns: Vec<String>,
+    pub rows: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug)]
+pub enum StepError {
+    /// A column the step names is not in its input.
+    UnknownColumn { input: String, column: String },
+    /// Two output columns would have the same name.
+    DuplicateColumn(String),
+    Csv { input: String, error: csv::Error },
+    Decrypt { input: String, column: String, error: DecryptError },
+    Io(io::Error),
+    Cancelled,
+}
+
+impl std::fmt::Display for StepError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StepError::UnknownColumn { input, column } => write!(f, "{} has no column {}", input, column),
+            StepError::DuplicateColumn(column) => write!(f, "the output would have two columns called {}", column),
+            StepError::Csv { input, error } => write!(f, "{}: {}", input, error),
+            StepError::Decrypt { input, column, error } => write!(f, "cannot decrypt {} in {}: {}", column, input, error),
+            StepError::Io(error) => error.fmt(f),
+            StepError::Cancelled => f.write_str("cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for StepError {}
+
+impl From<io::Error> for StepError {
+    fn from(error: io::Error) -> Self {
+        StepError::Io(error)
+    }
+}
+
+/// Whether `name` may name an artifact: letters, digits, `_`, `-` and `.`,
+/// not starting with a dot.
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// The artifact an input refers to, for `@name`.
+pub fn reference(input: &str) -> Option<&str> {
+    input.strip_prefix('@')
+}
+
+/// Where the artifact `name` of the workspace in `dir` is stored.
+pub fn artifact_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.csv", name))
+}
+
+/// Runs `step` over `inputs`, in the order of [`Step::inputs`], and writes
+/// its result to `output`, through a hidden part file so a failed step
+/// leaves nothing behind. Blocking; stops when `cancelled` returns true.
+pub fn apply(step: &Step, inputs: &[Input], output: &Path, cancelled: impl Fn() -> bool) -> Result<Output, StepError> {
+    let name = output.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let part = output.with_file_name(format!(".{}.{:08x}.part", name, rand::random::<u32>()));
+    let written = (|| {
+        let mut writer = WriterBuilder::new().from_writer(BufWriter::new(File::create(&part)?));
+        let (columns, rows) = match step {
+            Step::Transform { filter, columns, rename, .. } => transform(&inputs[0], filter, columns.as_deref(), rename, &mut writer, &cancelled)?,
+            Step::Join { on, kind, .. } => join(&inputs[0], &inputs[1], on, *kind, &mut writer, &cancelled)?,
+            Step::Sort { by, .. } => sort(&inputs[0], by, &mut writer, &cancelled)?,
+        };
+        let file = writer.into_inner().map_err(|error| error.into_error())?.into_inner().map_err(|error| error.into_error())?;
+        file.sync_all()?;
+        Ok::<_, StepError>((columns, rows, file.metadata()?.len()))
+    })();
+    match written.and_then(|written| std::fs::rename(&part, output).map(|()| written).map_err(StepError::from)) {
+        Ok((columns, rows, bytes)) => Ok(Output { columns, rows, bytes }),
+        Err(error) => {
+            let _ = std::fs::remove_file(&part);
+            Err(error)
+        }
+    }
+}
+
+/// Directories in `root` of workspaces not in `live`, last written more
+/// than `max_age` ago: those of a previous run, whose state is gone.
+pub fn orphaned_dirs(root: &Path, live: &HashSet<String>, max_age: Duration, now: SystemTime) -> io::Result<Vec<PathBuf>> {
+    let entries = match std::fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error),
+    };
+    Ok(entries
+        .flatten()
+        .filter_map(|entry| {
+            let metadata = std::fs::symlink_metadata(entry.path()).ok()?;
+            let modified = metadata.modified().ok()?;
+            let old = now.duration_since(modified).unwrap_or_default() >= max_age;
+            let id = entry.file_name().to_string_lossy().into_owned();
+            (metadata.is_dir() && old && !live.contains(&id)).then(|| entry.path())
+        })
+        .collect())
+}
+
+type Writer = csv::Writer<BufWriter<File>>;
+
+/// An input being read, its encrypted columns decrypted.
+struct Source<'a> {
+    input: &'a Input,
+    reader: csv::Reader<BufReader<File>>,
+    headers: StringRecord,
+    /// Index and name of each column to decrypt.
+    encrypted: Vec<(usize, String)>,
+}
+
+impl<'a> Source<'a> {
+    fn open(input: &'a Input) -> Result<Self, StepError> {
+        let mut reader = input.dialect.reader(false, BufReader::new(File::open(&input.path)?));
+        let headers = reader.headers().map_err(|error| csv_error(input, error))?.clone();
+        let encrypted = match &input.cipher {
+            Some(cipher) => headers
+                .iter()
+                .enumerate()
+                .filter(|(_, header)| cipher.encrypts(header))
+                .map(|(index, header)| (index, header.to_string()))
+                .collect(),
+            None => Vec::new(),
+        };
+        Ok(Self { input, reader, headers, encrypted })
+    }
+
+    fn records(&mut self) -> impl Iterator<Item = Result<StringRecord, StepError>> + '_ {
+        let Source { input, reader, encrypted, .. } = self;
+        let input: &Input = input;
+        reader.records().map(move |record| {
+            let record = record.map_err(|error| csv_error(input, error))?;
+            match (&input.cipher, encrypted.is_empty()) {
+                (Some(cipher), false) => decrypt(input, cipher, encrypted, &record),
+                _ => Ok(record),
+            }
+        })
+    }
+}
+
+fn decrypt(input: &Input, cipher: &ColumnCipher, encrypted: &[(usize, String)], record: &StringRecord) -> Result<StringRecord, StepError> {
+    let fields = record
+        .iter()
+        .enumerate()
+        .map(|(index, field)| match encrypted.iter().find(|(encrypted, _)| *encrypted == index) {
+            Some((_, column)) => cipher
+                .decrypt(column, field)
+                .map_err(|error| StepError::Decrypt { input: input.name.clone(), column: column.clone(), error }),
+            None => Ok(field.to_string()),
+        })
+        .collect::<Result<Vec<String>, StepError>>()?;
+    Ok(StringRecord::from(fields))
+}
+
+fn csv_error(input: &Input, error: csv::Error) -> StepError {
+    StepError::Csv { input: input.name.clone(), error }
+}
+
+fn column_index(input: &Input, headers: &StringRecord, column: &str) -> Result<usize, StepError> {
+    headers
+        .iter()
+        .position(|header| header == column)
+        .ok_or_else(|| StepError::UnknownColumn { input: input.name.clone(), column: column.to_string() })
+}
+
+fn check_unique(columns: &[String]) -> Result<(), StepError> {
+    let mut seen = HashSet::new();
+    match columns.iter().find(|column| !seen.insert(column.as_str())) {
+        Some(column) => Err(StepError::DuplicateColumn(column.clone())),
+        None => Ok(()),
+    }
+}
+
+fn transform(
+    input: &Input,
+    filter: &[Condition],
+    columns: Option<&[String]>,
+    rename: &BTreeMap<String, String>,
+    writer: &mut Writer,
+    cancelled: &impl Fn() -> bool,
+) -> Result<(Vec<String>, u64), StepError> {
+    let mut source = Source::open(input)?;
+    let headers = source.headers.clone();
+    let conditions = filter
+        .iter()
+        .map(|condition| Ok((column_index(input, &headers, &condition.column)?, condition)))
+        .collect::<Result<Vec<_>, StepError>>()?;
+    let kept: Vec<usize> = match columns {
+        Some(columns) => columns.iter().map(|column| column_index(input, &headers, column)).collect::<Result<_, _>>()?,
+        None => (0..headers.len()).collect(),
+    };
+    for column in rename.keys() {
+        column_index(input, &headers, column)?;
+    }
+    let names: Vec<String> = kept
+        .iter()
+        .map(|&index| rename.get(&headers[index]).cloned().unwrap_or_else(|| headers[index].to_string()))
+        .collect();
+    check_unique(&names)?;
+
+    writer.write_record(&names).map_err(|error| csv_error(input, error))?;
+    let mut rows = 0;
+    for record in source.records() {
+        if cancelled() {
+            return Err(StepError::Cancelled);
+        }
+        let record = record?;
+        if conditions.iter().all(|(index, condition)| condition.matches(record.get(*index).unwrap_or_default())) {
+            writer
+                .write_record(kept.iter().map(|&index| record.get(index).unwrap_or_default()))
+                .map_err(|error| csv_error(input, error))?;
+            rows += 1;
+        }
+    }
+    Ok((names, rows))
+}
+
+fn join(left: &Input, right: &Input, on: &str, kind: JoinKind, writer: &mut Writer, cancelled: &impl Fn() -> bool) -> Result<(Vec<String>, u64), StepError> {
+    let mut left_source = Source::open(left)?;
+    let mut right_source = Source::open(right)?;
+    let (left_headers, right_headers) = (left_source.headers.clone(), right_source.headers.clone());
+    let left_key = column_index(left, &left_headers, on)?;
+    let right_key = column_index(right, &right_headers, on)?;
+    // The right key is the left one, so it is written once; other right
+    // columns named like a left one are suffixed
+    let right_kept: Vec<usize> = (0..right_headers.len()).filter(|&index| index != right_key).collect();
+    let mut names: Vec<String> = left_headers.iter().map(str::to_string).collect();
+    for &index in &right_kept {
+        let column = &right_headers[index];
+        names.push(if left_headers.iter().any(|left| left == column) { format!("{}_right", column) } else { column.to_string() });
+    }
+    check_unique(&names)?;
+
+    let mut matches: HashMap<String, Vec<StringRecord>> = HashMap::new();
+    for record in right_source.records() {
+        if cancelled() {
+            return Err(StepError::Cancelled);
+        }
+        let record = record?;
+        matches.entry(record.get(right_key).unwrap_or_default().to_string()).or_default().push(record);
+    }
+
+    writer.write_record(&names).map_err(|error| csv_error(left, error))?;
+    let unmatched = StringRecord::from(vec![""; right_headers.len()]);
+    let mut rows = 0;
+    for record in left_source.records() {
+        if cancelled() {
+            return Err(StepError::Cancelled);
+        }
+        let record = record?;
+        let found = matches.get(record.get(left_key).unwrap_or_default());
+        let paired: Vec<&StringRecord> = match (found, kind) {
+            (Some(found), _) => found.iter().collect(),
+            (None, JoinKind::Left) => vec![&unmatched],
+            (None, JoinKind::Inner) => Vec::new(),
+        };
+        for other in paired {
+            let fields = record.iter().chain(right_kept.iter().map(|&index| other.get(index).unwrap_or_default()));
+            writer.write_record(fields).map_err(|error| csv_error(left, error))?;
+            rows += 1;
+        }
+    }
+    Ok((names, rows))
+}
+
+fn sort(input: &Input, by: &[String], writer: &mut Writer, cancelled: &impl Fn() -> bool) -> Result<(Vec<String>, u64), StepError> {
+    let mut source = Source::open(input)?;
+    let headers = source.headers.clone();
+    let keys = by
+        .iter()
+        .map(|key| {
+            let (column, descending) = key.strip_prefix('-').map_or((key.as_str(), false), |column| (column, true));
+            Ok((column_index(input, &headers, column)?, descending))
+        })
+        .collect::<Result<Vec<_>, StepError>>()?;
+    let mut records = Vec::new();
+    for record in source.records() {
+        if cancelled() {
+            return Err(StepError::Cancelled);
+        }
+        records.push(record?);
+    }
+    records.sort_by(|a, b| {
+        keys.iter()
+            .map(|&(index, descending)| {
+                let ordering = compare(a.get(index).unwrap_or_default(), b.get(index).unwrap_or_default());
+                if descending { ordering.reverse() } else { ordering }
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    });
+
+    writer.write_record(&headers).map_err(|error| csv_error(input, error))?;
+    for record in &records {
+        writer.write_record(record).map_err(|error| csv_error(input, error))?;
+    }
+    Ok((headers.iter().map(str::to_string).collect(), records.len() as u64))
+}
+
+/// Numerically when both parse as numbers, otherwise as text, with numbers
+/// before text. Mixing the two orders pair by pair would not be a total
+/// order ("2" < "10" < "1a" < "2"), which `sort_by` may panic on.
+fn compare(a: &str, b: &str) -> Ordering {
+    match (a.trim().parse::<f64>(), b.trim().parse::<f64>()) {
+        (Ok(a), Ok(b)) => a.total_cmp(&b),
+        (Ok(_), Err(_)) => Ordering::Less,
+        (Err(_), Ok(_)) => Ordering::Greater,
+        (Err(_), Err(_)) => a.cmp(b),
+    }
+}
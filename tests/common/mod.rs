@@ -0,0 +1,74 @@
+// What the integration tests share: every processing strategy run over
+// one input, for comparing what each parsed.
+
+use crate::performance_utils::SalesRecord;
+use crate::processing;
+use std::path::Path;
+
+/// Chunk sizes the chunked strategies run with: one row per chunk, a size
+/// that leaves a short last chunk, and one chunk for the whole file.
+pub const CHUNK_SIZES: [usize; 3] = [1, 3, 10_000];
+
+/// Read sizes of the streaming reader: small enough to cut every row, and
+/// the default.
+pub const BUFFER_SIZES: [usize; 2] = [7, processing::STREAMING_BUFFER_SIZE];
+
+/// The records each strategy other than the sync path parses from
+/// `content`, which is also the file at `path`, by strategy name. A
+/// strategy that fails panics with its name.
+pub async fn every_strategy(content: &str, path: &Path) -> Vec<(String, Vec<SalesRecord>)> {
+    let mut results: Vec<(String, Vec<SalesRecord>)> = vec![
+        ("async".to_string(), processing::async_records(content).await.expect("async")),
+        ("byte_records".to_string(), processing::byte_records(content).expect("byte_records")),
+        ("manual".to_string(), processing::manual_records(content).expect("manual")),
+        ("csv_async".to_string(), processing::csv_async_records(content.as_bytes()).await.expect("csv_async")),
+        (
+            "read_records".to_string(),
+            processing::read_records(path, &processing::Policy::once()).await.expect("read_records").value,
+        ),
+        ("mapped".to_string(), {
+            let mapped = processing::map_file(path).expect("map_file");
+            processing::sync_records(std::str::from_utf8(&mapped).expect("utf-8")).expect("mapped")
+        }),
+    ];
+    for chunk_size in CHUNK_SIZES {
+        results.push((
+            format!("chunked/{}", chunk_size),
+            processing::chunked_records(content, chunk_size).await.expect("chunked"),
+        ));
+        results.push((format!("parallel/{}", chunk_size), processing::parallel_records(content, chunk_size)));
+        results.push((
+            format!("async_parallel/{}", chunk_size),
+            processing::async_parallel_records(content, chunk_size, 2).await.expect("async_parallel"),
+        ));
+    }
+    for buffer_size in BUFFER_SIZES {
+        results.push((
+            format!("streaming/{}", buffer_size),
+            processing::streaming_records(content.as_bytes(), buffer_size).await.expect("streaming"),
+        ));
+    }
+    results
+}
+
+/// The first record of `records` that differs from `expected`, by index,
+/// or the shorter length when one runs out first.
+pub fn first_difference(records: &[SalesRecord], expected: &[SalesRecord]) -> Option<usize> {
+    records
+        .iter()
+        .zip(expected)
+        .position(|(record, wanted)| !same_record(record, wanted))
+        .or_else(|| (records.len() != expected.len()).then(|| records.len().min(expected.len())))
+}
+
+/// Field by field, with prices compared by their bits so that a
+/// strategy parsing a number differently cannot hide behind rounding.
+pub fn same_record(record: &SalesRecord, wanted: &SalesRecord) -> bool {
+    record.id == wanted.id
+        && record.customer_name == wanted.customer_name
+        && record.product == wanted.product
+        && record.quantity == wanted.quantity
+        && record.price.to_bits() == wanted.price.to_bits()
+        && record.date == wanted.date
+        && record.region == wanted.region
+}
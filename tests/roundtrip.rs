@@ -0,0 +1,273 @@
+// Property tests of the generator and the parsers together. Random schemas
+// with the columns of `SalesRecord`, in any order, with extra columns, null
+// rates and values that need quoting, generate datasets through the
+// generator library; every processing strategy must parse them back to
+// exactly what was generated. A quoting, escaping or number-formatting
+// mismatch between the two sides fails here with a shrunk example.
+//
+// PROPTEST_CASES sets how many datasets each property tries (64 by default).
+
+use chrono::NaiveDate;
+use proptest::prelude::*;
+use proptest::test_runner::FileFailurePersistence;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use std::path::{Path, PathBuf};
+
+#[allow(dead_code)]
+mod performance_utils {
+    include!("../src/performance_utils.rs");
+}
+
+#[allow(dead_code)]
+mod processing {
+    include!("../src/processing.rs");
+}
+
+#[allow(dead_code)]
+mod generator {
+    include!("../src/generator.rs");
+}
+
+#[allow(dead_code)]
+mod generator_noise {
+    include!("../src/generator_noise.rs");
+}
+
+#[allow(dead_code)]
+mod generator_output {
+    include!("../src/generator_output.rs");
+}
+
+#[allow(dead_code)]
+mod generator_schema {
+    include!("../src/generator_schema.rs");
+}
+
+mod common;
+
+use generator::GenerateOptions;
+use generator_output::{ChunkEncoder, Compression, FileWriter, OutputFormat};
+use generator_schema::{ChoiceDistribution, Column, ColumnKind, FakeKind, NumericDistribution, Schema};
+use performance_utils::SalesRecord;
+
+/// Rows per dataset. Enough for every chunk size in [`common::CHUNK_SIZES`]
+/// but the largest to split the file.
+const MAX_ROWS: u32 = 40;
+
+/// Plain words, and text the CSV writer has to quote or escape.
+fn text() -> impl Strategy<Value = String> {
+    prop_oneof![
+        "[A-Za-z ]{0,12}",
+        "[\"',;\r\n\t a-zé東😀]{0,12}",
+        any::<String>(),
+    ]
+}
+
+fn choice_distribution() -> impl Strategy<Value = ChoiceDistribution> {
+    prop_oneof![
+        Just(ChoiceDistribution::Uniform),
+        (0.0..3.0f64).prop_map(|exponent| ChoiceDistribution::Zipf { exponent }),
+    ]
+}
+
+fn weights(count: usize) -> impl Strategy<Value = Option<Vec<f64>>> {
+    prop::option::of(prop::collection::vec(0.0..3.0f64, count))
+}
+
+fn date_kind() -> impl Strategy<Value = ColumnKind> {
+    (1i32..=9999, 1u32..=12, 1u32..=28, 0i64..=4000, weights(12), weights(7)).prop_map(
+        |(year, month, day, days, month_weights, weekday_weights)| {
+            let from = NaiveDate::from_ymd_opt(year, month, day).expect("a valid date");
+            let last = NaiveDate::from_ymd_opt(9999, 12, 31).expect("a valid date");
+            let to = (from + chrono::Duration::days(days)).min(last);
+            ColumnKind::Date { from, to, month_weights, weekday_weights }
+        },
+    )
+}
+
+/// A column kind for the string fields of a [`SalesRecord`], with a null
+/// rate: any of them deserializes into a `String`.
+fn string_column() -> impl Strategy<Value = (ColumnKind, f64)> {
+    let kind = prop_oneof![
+        (prop::collection::vec(text(), 1..5), choice_distribution())
+            .prop_map(|(values, distribution)| ColumnKind::Choice { values, distribution }),
+        (prop::collection::vec(text(), 1..3), prop::collection::vec(text(), 1..3))
+            .prop_map(|(first_names, last_names)| ColumnKind::FullName { first_names, last_names }),
+        date_kind(),
+        prop_oneof![Just(FakeKind::Name), Just(FakeKind::Email), Just(FakeKind::City), Just(FakeKind::Product)]
+            .prop_map(|faker| ColumnKind::Fake { faker }),
+    ];
+    (kind, prop_oneof![Just(0.0), 0.0..=1.0f64])
+}
+
+/// Integers in `min..=max`, from a sequence or drawn at random.
+fn integer_kind(min: i64, max: i64) -> impl Strategy<Value = ColumnKind> {
+    prop_oneof![
+        (min.max(0) as u64..=(max - MAX_ROWS as i64) as u64).prop_map(|start| ColumnKind::Sequence { start }),
+        (min..=max).prop_flat_map(move |low| (Just(low), low..=max)).prop_map(|(min, max)| ColumnKind::Integer { min, max }),
+    ]
+}
+
+/// Floats at most 15 significant digits long, which an `f64` holds exactly.
+fn float_kind() -> impl Strategy<Value = ColumnKind> {
+    let distribution = prop_oneof![
+        Just(NumericDistribution::Uniform),
+        (-3.0..10.0f64, 0.0..2.0f64).prop_map(|(mu, sigma)| NumericDistribution::LogNormal { mu, sigma }),
+    ];
+    (-1e8..1e8f64, -1e8..1e8f64, 0usize..=6, distribution).prop_map(|(a, b, decimals, distribution)| {
+        ColumnKind::Float { min: a.min(b), max: a.max(b), decimals, distribution }
+    })
+}
+
+/// Columns a [`SalesRecord`] ignores, of any kind.
+fn extra_kind() -> impl Strategy<Value = ColumnKind> {
+    prop_oneof![integer_kind(i64::MIN / 2, i64::MAX / 2), float_kind(), string_column().prop_map(|(kind, _)| kind)]
+}
+
+/// A schema with every [`SalesRecord`] column and up to two others, in
+/// any order.
+fn sales_schema() -> impl Strategy<Value = Schema> {
+    let sales = (
+        integer_kind(0, u32::MAX as i64),
+        string_column(),
+        string_column(),
+        integer_kind(0, u32::MAX as i64),
+        float_kind(),
+        string_column(),
+        string_column(),
+    );
+    (sales, prop::collection::vec(extra_kind(), 0..=2)).prop_flat_map(
+        |((id, customer_name, product, quantity, price, date, region), extras)| {
+            let strings = [("customer_name", customer_name), ("product", product), ("date", date), ("region", region)];
+            let mut columns = vec![Column::new("id", id), Column::new("quantity", quantity), Column::new("price", price)];
+            for (name, (kind, null_rate)) in strings {
+                columns.push(Column { null_rate, ..Column::new(name, kind) });
+            }
+            for (index, kind) in extras.into_iter().enumerate() {
+                columns.push(Column::new(&format!("extra_{}", index), kind));
+            }
+            Just(columns).prop_shuffle().prop_map(|columns| Schema { columns })
+        },
+    )
+}
+
+/// A schema, a number of rows and a seed.
+fn dataset() -> impl Strategy<Value = (Schema, u32, u64)> {
+    (sales_schema(), 0..=MAX_ROWS, any::<u64>())
+}
+
+fn dataset_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("roundtrip_{}.csv", name))
+}
+
+/// Every case parses its dataset some twenty ways, so fewer cases than
+/// proptest's 256 run unless PROPTEST_CASES asks for more.
+fn cases() -> u32 {
+    std::env::var("PROPTEST_CASES").ok().and_then(|cases| cases.parse().ok()).unwrap_or(64)
+}
+
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread().enable_all().build().expect("a runtime")
+}
+
+/// Generates `rows` rows of `schema` and writes them to `path` with the
+/// generator's encoder and file writer. Returns the generated fields.
+fn write_dataset(schema: &Schema, rows: u32, seed: u64, path: &Path) -> Vec<Vec<String>> {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut encoder = ChunkEncoder::new(OutputFormat::Csv, schema);
+    let mut fields = Vec::new();
+    let mut generated = Vec::new();
+    for row in 0..rows as u64 {
+        schema.generate_row(&mut rng, row, &mut fields);
+        schema.apply_nulls(&mut rng, &mut fields);
+        encoder.write_row(&fields).expect("encode row");
+        generated.push(fields.clone());
+    }
+    let path = path.to_str().expect("a UTF-8 path");
+    let mut writer = FileWriter::create(path, OutputFormat::Csv, Compression::None, schema).expect("create file");
+    writer.write_chunk(&encoder.finish().expect("encode chunk")).expect("write chunk");
+    writer.finish().expect("finish file");
+    generated
+}
+
+/// The record the generated `fields` of a row stand for.
+fn expected_record(schema: &Schema, fields: &[String]) -> SalesRecord {
+    let field = |name: &str| {
+        let index = schema.columns.iter().position(|column| column.name == name).expect("a sales column");
+        fields[index].clone()
+    };
+    SalesRecord {
+        id: field("id").parse().expect("a generated id"),
+        customer_name: field("customer_name"),
+        product: field("product"),
+        quantity: field("quantity").parse().expect("a generated quantity"),
+        price: field("price").parse().expect("a generated price"),
+        date: field("date"),
+        region: field("region"),
+    }
+}
+
+/// Checks every strategy's records for the file at `path` against `expected`.
+fn check_parsed(path: &Path, expected: &[SalesRecord]) -> Result<(), TestCaseError> {
+    let content = std::fs::read_to_string(path).expect("read dataset");
+    let sync = processing::sync_records(&content).map_err(|error| TestCaseError::fail(format!("sync: {}", error)))?;
+    let mut results = runtime().block_on(common::every_strategy(&content, path));
+    results.insert(0, ("sync".to_string(), sync));
+    for (strategy, records) in &results {
+        if let Some(index) = common::first_difference(records, expected) {
+            return Err(TestCaseError::fail(format!(
+                "{} differs at record {} of {} (expected {}):\n{:?}\nexpected:\n{:?}",
+                strategy,
+                index,
+                records.len(),
+                expected.len(),
+                records.get(index),
+                expected.get(index)
+            )));
+        }
+    }
+    Ok(())
+}
+
+proptest! {
+    // Failing cases are kept in tests/roundtrip.proptest-regressions and
+    // tried first on every later run
+    #![proptest_config(ProptestConfig {
+        cases: cases(),
+        failure_persistence: Some(Box::new(FileFailurePersistence::WithSource("proptest-regressions"))),
+        ..ProptestConfig::default()
+    })]
+
+    /// Rows written by the generator's writer come back from every
+    /// strategy as generated, and prices parse back to the same text.
+    #[test]
+    fn generated_rows_parse_back_unchanged((schema, rows, seed) in dataset()) {
+        let path = dataset_path("rows");
+        let generated = write_dataset(&schema, rows, seed, &path);
+        let expected: Vec<SalesRecord> = generated.iter().map(|fields| expected_record(&schema, fields)).collect();
+        check_parsed(&path, &expected)?;
+
+        let Some(ColumnKind::Float { decimals, .. }) = schema.columns.iter().find(|column| column.name == "price").map(|column| &column.kind) else {
+            unreachable!("the price column is a float");
+        };
+        let price = schema.columns.iter().position(|column| column.name == "price").expect("a price column");
+        for (fields, record) in generated.iter().zip(&expected) {
+            prop_assert_eq!(format!("{:.*}", decimals, record.price), fields[price].clone());
+        }
+    }
+
+    /// Files from a full generator run, also cut short by a byte target,
+    /// hold whole rows that every strategy parses alike.
+    #[test]
+    fn generated_files_parse_alike((schema, rows, seed) in dataset(), target_bytes in prop::option::of(1u64..4_000)) {
+        let path = dataset_path("files");
+        let options = GenerateOptions { seed: Some(seed), threads: 1, target_bytes, ..GenerateOptions::new(rows) };
+        let stats = generator::generate(path.to_str().expect("a UTF-8 path"), &schema, &options, &mut ()).expect("generate");
+
+        let content = std::fs::read_to_string(&path).expect("read dataset");
+        let expected = processing::sync_records(&content).map_err(|error| TestCaseError::fail(format!("sync: {}", error)))?;
+        prop_assert_eq!(expected.len() as u64, stats.rows);
+        check_parsed(&path, &expected)?;
+    }
+}
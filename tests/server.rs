@@ -0,0 +1,158 @@
+// The server's API in-process: `build_router` from the library serves
+// fresh state without binding port 3000, starting background tasks or
+// touching anything but sample_data, where the tests generate what they
+// read.
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::{header, StatusCode};
+use axum::response::Response;
+use axum::Router;
+use tokio_axum_csv_demo::aggregators::Aggregator;
+use tokio_axum_csv_demo::generator::{self, GenerateOptions};
+use tokio_axum_csv_demo::generator_schema::Schema;
+use tokio_axum_csv_demo::performance_utils::SalesRecord;
+use tokio_axum_csv_demo::{build_router, new_state, processing, register_aggregators, request_log, ServerConfig, DATA_DIRS};
+use tokio_util::sync::CancellationToken;
+use tower::ServiceExt;
+
+fn router(config: &ServerConfig) -> Router {
+    build_router(config, new_state(CancellationToken::new()))
+}
+
+/// A generated sales file in sample_data/, removed again when dropped, so
+/// the tests need neither the generator run by hand nor its output.
+struct GeneratedFile {
+    name: String,
+}
+
+impl GeneratedFile {
+    fn new(name: &str, rows: u32) -> Self {
+        let name = format!("{}_{}.csv", name, std::process::id());
+        std::fs::create_dir_all(DATA_DIRS[0]).expect("create sample_data");
+        let options = GenerateOptions { seed: Some(1), threads: 1, ..GenerateOptions::new(rows) };
+        generator::generate(&format!("{}/{}", DATA_DIRS[0], name), &Schema::sales(), &options, &mut ()).expect("generate");
+        Self { name }
+    }
+
+    fn path(&self) -> String {
+        format!("{}/{}", DATA_DIRS[0], self.name)
+    }
+}
+
+impl Drop for GeneratedFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(self.path());
+    }
+}
+
+async fn json_body(response: Response) -> serde_json::Value {
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.expect("read body");
+    serde_json::from_slice(&bytes).unwrap_or_else(|error| panic!("{}: {}", error, String::from_utf8_lossy(&bytes)))
+}
+
+#[tokio::test]
+async fn oneshot_health() {
+    let request = Request::builder().uri("/health").header(request_log::REQUEST_ID_HEADER, "health-1").body(Body::empty()).expect("request");
+    let response = router(&ServerConfig::default()).oneshot(request).await.expect("response");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[request_log::REQUEST_ID_HEADER], "health-1");
+    let body = json_body(response).await;
+    assert!(body["status"].is_string(), "{}", body);
+    assert_eq!(body["cache"]["files"], 0, "{}", body);
+}
+
+#[tokio::test]
+async fn oneshot_body_limit() {
+    let request = Request::builder()
+        .method("POST")
+        .uri("/records/small_data.csv")
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::CONTENT_LENGTH, 65)
+        .body(Body::from(vec![b' '; 65]))
+        .expect("request");
+    let response = router(&ServerConfig { body_limit: 64 }).oneshot(request).await.expect("response");
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let body = json_body(response).await;
+    assert_eq!(body["error"]["code"], "payload_too_large", "{}", body);
+    assert_eq!(body["error"]["details"]["limit_bytes"], 64, "{}", body);
+}
+
+/// Counts records, as an embedder's own aggregation.
+struct RecordCount(u64);
+
+impl Aggregator for RecordCount {
+    fn init() -> Self {
+        RecordCount(0)
+    }
+
+    fn update(&mut self, _record: &SalesRecord) {
+        self.0 += 1;
+    }
+
+    fn finalize(self: Box<Self>) -> serde_json::Value {
+        serde_json::json!(self.0)
+    }
+}
+
+#[tokio::test]
+async fn oneshot_custom_aggregator() {
+    let data = GeneratedFile::new("aggregator_test", 50);
+    let state = new_state(CancellationToken::new());
+    register_aggregators(&state, |registry| {
+        registry.register::<RecordCount>("record_count", "Records in the file");
+    });
+    let request = Request::builder()
+        .uri(format!("/aggregate/{}?metrics=record_count,revenue_by_region", data.name))
+        .body(Body::empty())
+        .expect("request");
+    let response = build_router(&ServerConfig::default(), state).oneshot(request).await.expect("response");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = json_body(response).await;
+    assert_eq!(body["results"]["record_count"], 50, "{}", body);
+    assert!(body["results"]["revenue_by_region"].is_object(), "{}", body);
+}
+
+/// Over a real socket on an ephemeral port, as a client sees it.
+#[tokio::test]
+async fn served_on_ephemeral_port() {
+    let shutdown = CancellationToken::new();
+    let app = build_router(&ServerConfig::default(), new_state(shutdown.clone()));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let address = listener.local_addr().expect("address");
+    let server = tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move { axum::serve(listener, app).with_graceful_shutdown(shutdown.cancelled_owned()).await }
+    });
+
+    let data = GeneratedFile::new("server_test", 500);
+    let client = reqwest::Client::new();
+    let processed: serde_json::Value = client
+        .get(format!("http://{}/process/{}", address, data.name))
+        .send()
+        .await
+        .expect("GET /process")
+        .error_for_status()
+        .expect("processed")
+        .json()
+        .await
+        .expect("JSON");
+    let content = std::fs::read_to_string(data.path()).expect("read generated file");
+    let rows = processing::parse_sync(&content).expect("parse generated file");
+    assert_eq!(rows, 500);
+    assert_eq!(processed["records_processed"], rows, "{}", processed);
+
+    let metrics: serde_json::Value = client
+        .get(format!("http://{}/metrics", address))
+        .send()
+        .await
+        .expect("GET /metrics")
+        .json()
+        .await
+        .expect("JSON");
+    let runs = metrics["processing_metrics"].as_array().expect("processing metrics");
+    assert_eq!(runs.len(), 1, "{}", metrics);
+
+    shutdown.cancel();
+    server.await.expect("server task").expect("server");
+}
@@ -0,0 +1,151 @@
+// Golden-file tests of the processing strategies: every strategy parses
+// each fixture under tests/fixtures, and must return the same records, in
+// the same order, as the sync path. The sync path itself is held to the
+// row count, record digest and aggregates in tests/golden, so a change that
+// alters what gets parsed shows up as a diff there.
+//
+// Run with UPDATE_GOLDEN=1 to rewrite the golden files after a deliberate
+// change, and review the diff.
+
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+#[allow(dead_code)]
+mod performance_utils {
+    include!("../src/performance_utils.rs");
+}
+
+#[allow(dead_code)]
+mod processing {
+    include!("../src/processing.rs");
+}
+
+#[allow(dead_code)]
+mod column_stats {
+    include!("../src/column_stats.rs");
+}
+
+mod common;
+
+use column_stats::ColumnStatistics;
+use performance_utils::SalesRecord;
+
+fn fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+/// Compares every strategy's records for `content` with the sync path's,
+/// then the sync path's with the golden file `name`.json.
+async fn check_strategies(name: &str, content: &str, path: &Path) {
+    let expected = processing::sync_records(content).unwrap_or_else(|error| panic!("{}: sync: {}", name, error));
+    let results = common::every_strategy(content, path).await;
+    for (strategy, records) in &results {
+        assert_eq!(records.len(), expected.len(), "{}: {} parsed a different number of records", name, strategy);
+        if let Some(index) = common::first_difference(records, &expected) {
+            panic!("{}: {} differs at record {}:\n{:?}\nsync:\n{:?}", name, strategy, index, records[index], expected[index]);
+        }
+        let aggregates = serde_json::to_value(ColumnStatistics::compute(records)).expect("aggregates");
+        let wanted = serde_json::to_value(ColumnStatistics::compute(&expected)).expect("aggregates");
+        assert_eq!(aggregates, wanted, "{}: {} aggregates differ", name, strategy);
+    }
+
+    // The counting forms the benchmarks and the server call
+    let counts = [
+        ("sync", processing::parse_sync(content).expect("sync")),
+        ("parallel", processing::parse_parallel(content)),
+        ("chunked", processing::parse_chunked(content, 2).await.expect("chunked")),
+        (
+            "streaming",
+            processing::parse_streaming(content.as_bytes(), common::BUFFER_SIZES[0]).await.expect("streaming"),
+        ),
+    ];
+    for (strategy, count) in counts {
+        assert_eq!(count, expected.len(), "{}: {} counted a different number of records", name, strategy);
+    }
+
+    check_golden(name, &expected);
+}
+
+/// The row count, a digest of the records and the aggregates /analyze is
+/// answered from, as kept in the golden files.
+fn golden_summary(records: &[SalesRecord]) -> serde_json::Value {
+    let mut digest = Sha256::new();
+    for record in records {
+        digest.update(serde_json::to_vec(record).expect("record"));
+        digest.update(b"\n");
+    }
+    let statistics = ColumnStatistics::compute(records);
+    let groups = |column: &str| statistics.groups(column).map(|groups| serde_json::to_value(groups).expect("groups"));
+    json!({
+        "rows": records.len(),
+        "records_sha256": format!("{:x}", digest.finalize()),
+        "numeric": statistics.numeric,
+        "by_region": groups("region"),
+        "by_month": groups("month"),
+    })
+}
+
+fn check_golden(name: &str, records: &[SalesRecord]) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(format!("{}.json", name));
+    // Compared as text: serde_json does not read every float back to the
+    // same bits it wrote
+    let summary = serde_json::to_string_pretty(&golden_summary(records)).expect("golden") + "\n";
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, summary).unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+        return;
+    }
+    let golden = std::fs::read_to_string(&path)
+        .unwrap_or_else(|error| panic!("{}: {} (run with UPDATE_GOLDEN=1 to create it)", path.display(), error));
+    assert_eq!(summary, golden, "{}: sync output no longer matches {}", name, path.display());
+}
+
+async fn check_fixture(name: &str) {
+    let path = fixtures_dir().join(format!("{}.csv", name));
+    let content = std::fs::read_to_string(&path).unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+    check_strategies(name, &content, &path).await;
+}
+
+#[tokio::test]
+async fn quoted_newlines() {
+    check_fixture("quoted_newlines").await;
+}
+
+#[tokio::test]
+async fn crlf() {
+    check_fixture("crlf").await;
+}
+
+#[tokio::test]
+async fn unicode() {
+    check_fixture("unicode").await;
+}
+
+#[tokio::test]
+async fn empty_fields() {
+    check_fixture("empty_fields").await;
+}
+
+/// Rows far longer than a streaming read, with quoted newlines and
+/// escaped quotes spread through them. Built here rather than checked in.
+#[tokio::test]
+async fn huge_rows() {
+    let mut content = String::from("id,customer_name,product,quantity,price,date,region\n");
+    for id in 1..=4u32 {
+        let name: String = (0..20_000).map(|index| ["Zoë ", "\"\"quoted\"\" ", "line\n", "a,b "][(index + id as usize) % 4]).collect();
+        content.push_str(&format!("{},\"{}\",Product {},{},{}.25,2024-0{}-01,Region {}\n", id, name, id, id * 3, id * 10, id, id % 2));
+        content.push_str(&format!("{}0,Short Row,Widget,1,1.5,2024-0{}-15,Region 0\n", id, id));
+    }
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("huge_rows.csv");
+    std::fs::write(&path, &content).expect("write huge_rows.csv");
+    check_strategies("huge_rows", &content, &path).await;
+}
+
+#[tokio::test]
+async fn header_only_and_empty() {
+    for (name, content) in [("header_only", "id,customer_name,product,quantity,price,date,region\n"), ("empty", "")] {
+        let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.csv", name));
+        std::fs::write(&path, content).expect("write fixture");
+        check_strategies(name, content, &path).await;
+    }
+}
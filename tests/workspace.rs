@@ -0,0 +1,86 @@
+// Workspace steps run on files written here, as the server runs them on
+// uploads and artifacts.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[allow(dead_code)]
+mod performance_utils {
+    include!("../src/performance_utils.rs");
+}
+
+#[allow(dead_code)]
+mod encryption {
+    include!("../src/encryption.rs");
+}
+
+#[allow(dead_code)]
+mod dialect {
+    include!("../src/dialect.rs");
+}
+
+#[allow(dead_code)]
+mod workspace {
+    include!("../src/workspace.rs");
+}
+
+use encryption::ColumnCipher;
+use workspace::{Input, JoinKind, Step};
+
+fn write_input(name: &str, content: &str, cipher: Option<Arc<ColumnCipher>>) -> Input {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.csv", name));
+    std::fs::write(&path, content).expect("write input");
+    Input { name: name.to_string(), path, dialect: dialect::Dialect::default(), cipher }
+}
+
+fn run(step: &Step, inputs: &[Input], name: &str) -> Vec<String> {
+    let output = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.out.csv", name));
+    workspace::apply(step, inputs, &output, || false).unwrap_or_else(|error| panic!("{}: {}", name, error));
+    std::fs::read_to_string(&output).expect("read output").lines().map(str::to_string).collect()
+}
+
+/// A column of numbers and text, as dirty data has it: numbers come first
+/// in numeric order, then text in text order, whichever way the rows came.
+#[test]
+fn sort_mixed_column() {
+    let mut values: Vec<String> = (0..200).map(|n| if n % 3 == 0 { format!("{}a", n) } else { n.to_string() }).collect();
+    values.extend(["N/A", "", " 7 ", "-1.5", "1e3"].map(str::to_string));
+    values.reverse();
+    let content = std::iter::once("price".to_string()).chain(values.iter().map(|value| format!("\"{}\"", value))).collect::<Vec<_>>().join("\n");
+    let input = write_input("mixed", &content, None);
+    let step = Step::Sort { input: "mixed".to_string(), by: vec!["price".to_string()], output: "sorted".to_string() };
+
+    let sorted = run(&step, &[input], "sort_mixed_column");
+    let fields: Vec<&str> = sorted[1..].iter().map(|line| line.trim_matches('"')).collect();
+    assert_eq!(fields.len(), values.len());
+    let split = fields.iter().position(|field| field.trim().parse::<f64>().is_err()).expect("text values");
+    let (numbers, text) = fields.split_at(split);
+    let parsed: Vec<f64> = numbers.iter().map(|field| field.trim().parse().expect("number")).collect();
+    assert!(parsed.windows(2).all(|pair| pair[0] <= pair[1]), "numbers out of order: {:?}", numbers);
+    assert!(text.iter().all(|field| field.trim().parse::<f64>().is_err()), "a number after text: {:?}", text);
+    assert!(text.windows(2).all(|pair| pair[0] <= pair[1]), "text out of order: {:?}", text);
+}
+
+/// Encrypted fields get a random nonce each, so equal names only match
+/// once the inputs decrypt them.
+#[test]
+fn join_on_encrypted_column() {
+    let cipher = Arc::new(ColumnCipher::new("customer_name", &format!("k1:{}", "ab".repeat(32))).expect("cipher"));
+    let encrypted = |name: &str| cipher.encrypt("customer_name", name);
+    let orders = format!("id,customer_name\n1,{}\n2,{}\n3,{}\n", encrypted("Ann"), encrypted("Bob"), encrypted("Ann"));
+    let managers = format!("customer_name,manager\n{},Zed\nBob,Yan\n", encrypted("Ann"));
+    let step = Step::Join {
+        left: "orders".to_string(),
+        right: "managers".to_string(),
+        on: "customer_name".to_string(),
+        kind: JoinKind::Inner,
+        output: "joined".to_string(),
+    };
+
+    let inputs = [write_input("orders", &orders, Some(cipher.clone())), write_input("managers", &managers, Some(cipher.clone()))];
+    let joined = run(&step, &inputs, "join_on_encrypted_column");
+    assert_eq!(joined, ["id,customer_name,manager", "1,Ann,Zed", "2,Bob,Yan", "3,Ann,Zed"]);
+
+    let stored = [write_input("orders_stored", &orders, None), write_input("managers_stored", &managers, None)];
+    assert_eq!(run(&step, &stored, "join_on_stored_column"), ["id,customer_name,manager"]);
+}